@@ -16,6 +16,30 @@ const CONFIG: Config = Config {
     clear_on_unsupported_version: true,
     mlock_headers: false,
     num_compaction_threads: 4,
+    background_split_threshold: None,
+    maintenance_thread_nice: None,
+    key_prefixes: vec![],
+    dedup_min_value_size: 1024,
+    strict_typed_values: false,
+    list_item_metadata: false,
+    list_item_tags: false,
+    intern_keys_longer_than: None,
+    yield_every: None,
+    list_recovery: None,
+    lazy_open: false,
+    preallocate_size: 0,
+    preallocate_increment: 0,
+    disk_full_policy: candystore::DiskFullPolicy::Fail,
+    export_signing_key: None,
+    collision_log_capacity: 0,
+    uncompressed_namespaces: Vec::new(),
+    shard_checksums: false,
+    soft_limits: candystore::SoftLimits {
+        total_bytes: None,
+        shard_fill_level: None,
+        list_span: None,
+    },
+    list_journal: false,
 };
 
 fn child_inserts() -> Result<()> {
@@ -16,13 +16,13 @@ fn main() -> Result<()> {
     let num_iters: usize = args[2].parse().expect("num_iters not a number");
     let tail_length: usize = args[3].parse().expect("tail_length not a number");
 
-    let db = Arc::new(CandyStore::open(
+    let db = CandyStore::open(
         "dbdir",
         Config {
             min_compaction_threashold: 1024 * 1024,
             ..Default::default()
         },
-    )?);
+    )?;
     db.clear()?;
 
     let mut handles = vec![];
@@ -209,7 +209,7 @@ fn test_lists(num_lists: u32, num_items_per_list: u32) -> Result<()> {
 
 fn test_concurrency_without_contention(num_threads: u32, num_keys: u32) -> Result<()> {
     for pre_split in [true, false] {
-        let db = Arc::new(CandyStore::open(
+        let db = CandyStore::open(
             "./dbdir",
             Config {
                 expected_number_of_keys: if pre_split {
@@ -219,7 +219,7 @@ fn test_concurrency_without_contention(num_threads: u32, num_keys: u32) -> Resul
                 },
                 ..Default::default()
             },
-        )?);
+        )?;
         db.clear()?;
 
         if pre_split {
@@ -306,7 +306,7 @@ fn do_inserts(
     thd: u32,
     num_keys: u32,
     insert_time_ns: &Arc<AtomicU64>,
-    db: &Arc<CandyStore>,
+    db: &CandyStore,
 ) -> Result<()> {
     let t0 = Instant::now();
     for i in 0..num_keys {
@@ -319,7 +319,7 @@ fn do_inserts(
     Ok(())
 }
 
-fn do_gets(num_keys: u32, get_time_ns: &Arc<AtomicU64>, db: &Arc<CandyStore>) -> Result<()> {
+fn do_gets(num_keys: u32, get_time_ns: &Arc<AtomicU64>, db: &CandyStore) -> Result<()> {
     let t0 = Instant::now();
     for i in 0..num_keys {
         let val = db.get(&i.to_le_bytes())?;
@@ -332,11 +332,7 @@ fn do_gets(num_keys: u32, get_time_ns: &Arc<AtomicU64>, db: &Arc<CandyStore>) ->
     Ok(())
 }
 
-fn do_removals(
-    num_keys: u32,
-    removal_time_ns: &Arc<AtomicU64>,
-    db: &Arc<CandyStore>,
-) -> Result<()> {
+fn do_removals(num_keys: u32, removal_time_ns: &Arc<AtomicU64>, db: &CandyStore) -> Result<()> {
     let t0 = Instant::now();
     for i in 0..num_keys {
         let val = db.remove(&i.to_le_bytes())?;
@@ -351,7 +347,7 @@ fn do_removals(
 
 fn test_concurrency_with_contention(num_threads: u32, num_keys: u32) -> Result<()> {
     for pre_split in [true, false] {
-        let db = Arc::new(CandyStore::open(
+        let db = CandyStore::open(
             "./dbdir",
             Config {
                 expected_number_of_keys: if pre_split {
@@ -361,7 +357,7 @@ fn test_concurrency_with_contention(num_threads: u32, num_keys: u32) -> Result<(
                 },
                 ..Default::default()
             },
-        )?);
+        )?;
         db.clear()?;
 
         if pre_split {
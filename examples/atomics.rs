@@ -1,5 +1,5 @@
 use core::str;
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
 use candystore::{CandyStore, Config, GetOrCreateStatus, Result};
 
@@ -14,7 +14,7 @@ use candystore::{CandyStore, Config, GetOrCreateStatus, Result};
 //   ...
 
 fn main() -> Result<()> {
-    let db = Arc::new(CandyStore::open("/tmp/candy-dir", Config::default())?);
+    let db = CandyStore::open("/tmp/candy-dir", Config::default())?;
 
     // clear the DB just in case we has something there before. in real-life scenarios you would probably
     // not clear the DB every time
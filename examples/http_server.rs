@@ -0,0 +1,21 @@
+use std::net::TcpListener;
+
+use candystore::{CandyStore, Config, HttpServerParams, Result};
+
+// run with `cargo run --example http_server --features server -- 127.0.0.1:8080 mytoken`, then e.g.,
+// `curl -H "Authorization: Bearer mytoken" -X PUT --data myval http://127.0.0.1:8080/kv/mykey`
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8080".into());
+    let db = CandyStore::open("/tmp/candy-http-dir", Config::default())?;
+
+    let listener = TcpListener::bind(&addr)?;
+    println!("listening on {addr}");
+    db.serve_http(
+        listener,
+        HttpServerParams {
+            auth_tokens: args.collect(),
+            ..Default::default()
+        },
+    )
+}
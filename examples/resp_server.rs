@@ -0,0 +1,16 @@
+use std::net::TcpListener;
+
+use candystore::{CandyStore, Config, RespServerParams, Result};
+
+// run with `cargo run --example resp_server --features server`, then use any redis client, e.g.,
+// `redis-cli -p 6380 set mykey myval`
+fn main() -> Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:6380".into());
+    let db = CandyStore::open("/tmp/candy-resp-dir", Config::default())?;
+
+    let listener = TcpListener::bind(&addr)?;
+    println!("listening on {addr}");
+    db.serve_resp(listener, RespServerParams::default())
+}
@@ -0,0 +1,137 @@
+use siphasher::sip128::{Hasher128, SipHasher24};
+use std::hash::Hasher;
+
+use crate::{hashing::PartedHash, store::BLOB_NAMESPACE, CandyError, CandyStore, Result};
+
+/// The identifier of a refcounted blob (see [CandyStore::put_blob]), derived from the blob's content. It can be
+/// stored anywhere (e.g., inside other values) using [Self::as_bytes] and [Self::from_bytes]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobId([u8; 16]);
+
+impl BlobId {
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+}
+
+// blobs are stored under their content hash, and hold the content followed by the refcount
+impl CandyStore {
+    fn make_blob_key(id: &BlobId) -> Vec<u8> {
+        let mut blob_key = Vec::with_capacity(id.0.len() + BLOB_NAMESPACE.len());
+        blob_key.extend_from_slice(&id.0);
+        blob_key.extend_from_slice(BLOB_NAMESPACE);
+        blob_key
+    }
+
+    fn parse_blob(blob_key: &[u8], mut blob: Vec<u8>) -> Result<(Vec<u8>, u64)> {
+        if blob.len() < size_of::<u64>() {
+            return Err(CandyError::Corruption(format!("bad blob {blob_key:?}")));
+        }
+        let refcount_offset = blob.len() - size_of::<u64>();
+        let refcount = u64::from_le_bytes(blob[refcount_offset..].try_into().unwrap());
+        blob.truncate(refcount_offset);
+        Ok((blob, refcount))
+    }
+
+    // modifies the refcount of an existing blob, removing it if the refcount drops to zero. returns the new
+    // refcount, or None if the blob does not exist
+    fn update_blob_refcount(&self, id: &BlobId, delta: i64) -> Result<Option<u64>> {
+        let blob_key = Self::make_blob_key(id);
+        let _guard = self.lock_list(PartedHash::new(&self.0.config.hash_seed, &blob_key));
+
+        let Some(blob) = self.get_raw(&blob_key)? else {
+            return Ok(None);
+        };
+        let (content, refcount) = Self::parse_blob(&blob_key, blob)?;
+        let refcount = refcount.saturating_add_signed(delta);
+        if refcount == 0 {
+            self.remove_raw(&blob_key)?;
+        } else {
+            self.patch_raw(&blob_key, content.len(), &refcount.to_le_bytes(), None)?;
+        }
+        Ok(Some(refcount))
+    }
+
+    /// adds a reference to the blob holding the given content, creating it if needed. returns None if a
+    /// different blob already exists under this content hash (a collision)
+    pub(crate) fn _put_blob(&self, content: &[u8]) -> Result<Option<BlobId>> {
+        let mut hasher = SipHasher24::new_with_key(&self.0.config.hash_seed);
+        hasher.write(content);
+        let id = BlobId(hasher.finish128().as_bytes());
+
+        let blob_key = Self::make_blob_key(&id);
+        let _guard = self.lock_list(PartedHash::new(&self.0.config.hash_seed, &blob_key));
+
+        match self.get_raw(&blob_key)? {
+            Some(blob) => {
+                let (existing, refcount) = Self::parse_blob(&blob_key, blob)?;
+                if existing != content {
+                    return Ok(None);
+                }
+                self.patch_raw(
+                    &blob_key,
+                    existing.len(),
+                    &(refcount + 1).to_le_bytes(),
+                    None,
+                )?;
+            }
+            None => {
+                let mut blob = Vec::with_capacity(content.len() + size_of::<u64>());
+                blob.extend_from_slice(content);
+                blob.extend_from_slice(&1u64.to_le_bytes());
+                self.set_raw(&blob_key, &blob)?;
+            }
+        }
+        Ok(Some(id))
+    }
+
+    /// Stores the given content as a refcounted blob, returning its ID. Blobs are content-addressed, so putting
+    /// the same content twice returns the same ID and simply adds a reference to the existing blob. Every
+    /// [Self::put_blob] or [Self::incref_blob] should be matched with a [Self::decref_blob], and the blob is
+    /// removed once its last reference is dropped.
+    ///
+    /// This is useful for building deduplication or object graphs on top of the store (see also
+    /// [Self::set_dedup], which uses the same blobs). Returns [CandyError::InvalidArgument] in the (highly
+    /// unlikely) case that a different blob with the same content hash exists
+    pub fn put_blob<B: AsRef<[u8]> + ?Sized>(&self, content: &B) -> Result<BlobId> {
+        let content = content.as_ref();
+        if content.len() > crate::MAX_VALUE_SIZE {
+            return Err(CandyError::ValueTooLong(content.len()));
+        }
+        self._put_blob(content)?.ok_or_else(|| {
+            CandyError::InvalidArgument("a different blob with the same content hash exists".into())
+        })
+    }
+
+    /// Gets the content of a blob, or None if it does not exist
+    pub fn get_blob(&self, id: &BlobId) -> Result<Option<Vec<u8>>> {
+        let blob_key = Self::make_blob_key(id);
+        match self.get_raw(&blob_key)? {
+            Some(blob) => Ok(Some(Self::parse_blob(&blob_key, blob)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of references to a blob, or None if it does not exist
+    pub fn blob_refcount(&self, id: &BlobId) -> Result<Option<u64>> {
+        let blob_key = Self::make_blob_key(id);
+        match self.get_raw(&blob_key)? {
+            Some(blob) => Ok(Some(Self::parse_blob(&blob_key, blob)?.1)),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds a reference to an existing blob, returning the new refcount, or None if the blob does not exist
+    pub fn incref_blob(&self, id: &BlobId) -> Result<Option<u64>> {
+        self.update_blob_refcount(id, 1)
+    }
+
+    /// Drops a reference to a blob, returning the new refcount (zero means the blob has been removed), or None
+    /// if the blob does not exist
+    pub fn decref_blob(&self, id: &BlobId) -> Result<Option<u64>> {
+        self.update_blob_refcount(id, -1)
+    }
+}
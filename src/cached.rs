@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Weak},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{BoxedKvIterator, KvStore, ListStore, Result, SetStatus};
+
+/// Controls when [CachedStore] writes modifications to the underlying store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// modifications are written to the underlying store right away (and update the cache)
+    WriteThrough,
+    /// modifications only update the cache, and are written to the underlying store in batches. Modifications of
+    /// the same key (or list item) are coalesced while pending, so a key that is set over and over is written
+    /// once per batch. Batches are flushed by [CachedStore::flush], once `max_pending` keys are pending, once
+    /// the oldest pending modification is older than `max_delay` (checked on every modification, and
+    /// periodically by [CachedStore::spawn_flusher]), when a modified entry is evicted, before operations that
+    /// read the underlying store directly (iterations, [ListStore::list_len] and [ListStore::discard_list]),
+    /// and when the cache is dropped.
+    ///
+    /// Note: modifications that were not flushed are lost if the process crashes, i.e., up to `max_delay` worth
+    /// of modifications (when a flusher is running)
+    WriteBehind {
+        max_pending: usize,
+        max_delay: Duration,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Kv(Vec<u8>),
+    ListItem(Vec<u8>, Vec<u8>),
+}
+
+struct CachedVal {
+    // `None` means the key is known not to exist
+    val: Option<Vec<u8>>,
+    // modified by a write-behind operation that was not flushed yet
+    dirty: bool,
+}
+
+struct Cache {
+    entries: HashMap<CacheKey, CachedVal>,
+    // the keys of `entries` in the order they were inserted, the oldest are evicted first
+    order: VecDeque<CacheKey>,
+    // the keys of the dirty entries, in the order they should be written out
+    pending: Vec<CacheKey>,
+    // when the oldest pending modification was made
+    pending_since: Option<Instant>,
+}
+
+/// A two-level cache: a bounded in-memory map in front of a store (usually a [crate::CandyStore]), which serves
+/// repeated reads of the same keys and list items from memory. Absent keys are cached as well. Modifications
+/// are written through or behind, according to the [WritePolicy].
+///
+/// All modifications must go through the cache, otherwise it would serve stale values. Removing a key or a list
+/// item updates the cache, and discarding a list invalidates all of its cached items. Operations are serialized
+/// by the cache's lock (including the accesses to the underlying store they perform), which keeps the cache
+/// consistent with the underlying store
+pub struct CachedStore<S: KvStore + ListStore> {
+    // shared (weakly) with the flusher thread, see spawn_flusher
+    inner: Arc<S>,
+    capacity: usize,
+    policy: WritePolicy,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<S: KvStore + ListStore> CachedStore<S> {
+    /// Creates a cache of up to `capacity` entries (keys and list items) in front of `inner`
+    pub fn new(inner: S, capacity: usize, policy: WritePolicy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            capacity: capacity.max(1),
+            policy,
+            cache: Arc::new(Mutex::new(Cache {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                pending: vec![],
+                pending_since: None,
+            })),
+        }
+    }
+
+    /// Returns the underlying store. Note that modifications made directly through it are not reflected by the
+    /// cache, and that pending write-behind modifications are not visible through it until flushed
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of cached entries
+    pub fn len(&self) -> usize {
+        self.cache.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of keys (and list items) with pending write-behind modifications
+    pub fn num_pending(&self) -> usize {
+        self.cache.lock().pending.len()
+    }
+
+    /// Writes the pending write-behind modifications to the underlying store. On failure, the modifications
+    /// that were not written remain pending
+    pub fn flush(&self) -> Result<()> {
+        self.flush_locked(&mut self.cache.lock())
+    }
+
+    /// Flushes the pending modifications and drops all cached entries, e.g., after the underlying store was
+    /// modified directly
+    pub fn invalidate_all(&self) -> Result<()> {
+        let mut cache = self.cache.lock();
+        self.flush_locked(&mut cache)?;
+        cache.entries.clear();
+        cache.order.clear();
+        Ok(())
+    }
+
+    fn flush_locked(&self, cache: &mut Cache) -> Result<()> {
+        flush_pending(&*self.inner, cache)
+    }
+
+    fn insert_locked(
+        &self,
+        cache: &mut Cache,
+        key: CacheKey,
+        val: Option<Vec<u8>>,
+        dirty: bool,
+    ) -> Result<()> {
+        let prev = cache.entries.insert(
+            key.clone(),
+            CachedVal {
+                val: val.clone(),
+                dirty,
+            },
+        );
+        if dirty && !prev.as_ref().is_some_and(|prev| prev.dirty) {
+            cache.pending.push(key.clone());
+            cache.pending_since.get_or_insert_with(Instant::now);
+        }
+        if prev.is_none() {
+            cache.order.push_back(key);
+        }
+
+        if let WritePolicy::WriteBehind {
+            max_pending,
+            max_delay,
+        } = self.policy
+        {
+            if cache.pending.len() >= max_pending
+                || cache
+                    .pending_since
+                    .is_some_and(|since| since.elapsed() >= max_delay)
+            {
+                self.flush_locked(cache)?;
+            }
+        }
+        while cache.entries.len() > self.capacity {
+            let Some(oldest) = cache.order.pop_front() else {
+                break;
+            };
+            if cache.entries.get(&oldest).is_some_and(|cv| cv.dirty) {
+                self.flush_locked(cache)?;
+            }
+            cache.entries.remove(&oldest);
+        }
+        Ok(())
+    }
+
+    fn get_locked(
+        &self,
+        cache: &mut Cache,
+        key: CacheKey,
+        fetch: impl FnOnce() -> Result<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(cv) = cache.entries.get(&key) {
+            return Ok(cv.val.clone());
+        }
+        let val = fetch()?;
+        self.insert_locked(cache, key, val.clone(), false)?;
+        Ok(val)
+    }
+
+    fn _get(
+        &self,
+        key: CacheKey,
+        fetch: impl FnOnce() -> Result<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.get_locked(&mut self.cache.lock(), key, fetch)
+    }
+
+    // sets (`Some`) or removes (`None`) the given key, returning its previous value
+    fn _modify(
+        &self,
+        key: CacheKey,
+        val: Option<&[u8]>,
+        fetch: impl FnOnce() -> Result<Option<Vec<u8>>>,
+        write: impl FnOnce() -> Result<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut cache = self.cache.lock();
+        let val = val.map(|v| v.to_owned());
+        match self.policy {
+            WritePolicy::WriteThrough => {
+                let prev = write()?;
+                self.insert_locked(&mut cache, key, val, false)?;
+                Ok(prev)
+            }
+            WritePolicy::WriteBehind { .. } => {
+                let prev = self.get_locked(&mut cache, key.clone(), fetch)?;
+                // modifications of the same key are coalesced, except that a list item that is removed and set
+                // again moves to the end of its list, so the removal must be written first
+                if prev.is_none()
+                    && val.is_some()
+                    && matches!(key, CacheKey::ListItem(..))
+                    && cache.entries.get(&key).is_some_and(|cv| cv.dirty)
+                {
+                    self.flush_locked(&mut cache)?;
+                }
+                self.insert_locked(&mut cache, key, val, true)?;
+                Ok(prev)
+            }
+        }
+    }
+}
+
+impl<S: KvStore + ListStore + 'static> CachedStore<S> {
+    /// Spawns a thread that flushes the pending write-behind modifications once they are older than the
+    /// policy's `max_delay`, so that they are flushed in time even if no further modifications are made. The
+    /// thread exits once the cache is dropped. Failed flushes are retried on the next round (use
+    /// [Self::flush] to handle the errors). Does nothing with [WritePolicy::WriteThrough]
+    pub fn spawn_flusher(&self) -> Option<JoinHandle<()>> {
+        let WritePolicy::WriteBehind { max_delay, .. } = self.policy else {
+            return None;
+        };
+        let interval = (max_delay / 2).max(Duration::from_millis(1));
+        let weak_inner: Weak<S> = Arc::downgrade(&self.inner);
+        let weak_cache: Weak<Mutex<Cache>> = Arc::downgrade(&self.cache);
+        Some(std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let (Some(inner), Some(cache)) = (weak_inner.upgrade(), weak_cache.upgrade()) else {
+                break;
+            };
+            let mut cache = cache.lock();
+            if cache
+                .pending_since
+                .is_some_and(|since| since.elapsed() >= max_delay)
+            {
+                _ = flush_pending(&*inner, &mut cache);
+            }
+        }))
+    }
+}
+
+fn flush_pending<S: KvStore + ListStore>(inner: &S, cache: &mut Cache) -> Result<()> {
+    let mut num_flushed = 0;
+    let mut res = Ok(());
+    for key in cache.pending.iter() {
+        let Some(cv) = cache.entries.get_mut(key) else {
+            continue;
+        };
+        let op_res = match (key, &cv.val) {
+            (CacheKey::Kv(k), Some(v)) => inner.set(k, v).map(|_| ()),
+            (CacheKey::Kv(k), None) => inner.remove(k).map(|_| ()),
+            (CacheKey::ListItem(lk, ik), Some(v)) => inner.set_in_list(lk, ik, v).map(|_| ()),
+            (CacheKey::ListItem(lk, ik), None) => inner.remove_from_list(lk, ik).map(|_| ()),
+        };
+        if let Err(e) = op_res {
+            res = Err(e);
+            break;
+        }
+        cv.dirty = false;
+        num_flushed += 1;
+    }
+    cache.pending.drain(..num_flushed);
+    if cache.pending.is_empty() {
+        cache.pending_since = None;
+    }
+    res
+}
+
+fn set_status(prev: Option<Vec<u8>>) -> SetStatus {
+    match prev {
+        Some(prev) => SetStatus::PrevValue(prev),
+        None => SetStatus::CreatedNew,
+    }
+}
+
+impl<S: KvStore + ListStore> KvStore for CachedStore<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self._get(CacheKey::Kv(key.to_owned()), || self.inner.get(key))
+    }
+    fn set(&self, key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        self._modify(
+            CacheKey::Kv(key.to_owned()),
+            Some(val),
+            || self.inner.get(key),
+            || {
+                Ok(match self.inner.set(key, val)? {
+                    SetStatus::PrevValue(prev) => Some(prev),
+                    SetStatus::CreatedNew => None,
+                })
+            },
+        )
+        .map(set_status)
+    }
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self._modify(
+            CacheKey::Kv(key.to_owned()),
+            None,
+            || self.inner.get(key),
+            || self.inner.remove(key),
+        )
+    }
+    fn iter(&self) -> BoxedKvIterator<'_> {
+        if let Err(e) = self.flush() {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        self.inner.iter()
+    }
+}
+
+impl<S: KvStore + ListStore> ListStore for CachedStore<S> {
+    fn get_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self._get(
+            CacheKey::ListItem(list_key.to_owned(), item_key.to_owned()),
+            || self.inner.get_from_list(list_key, item_key),
+        )
+    }
+    fn set_in_list(&self, list_key: &[u8], item_key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        self._modify(
+            CacheKey::ListItem(list_key.to_owned(), item_key.to_owned()),
+            Some(val),
+            || self.inner.get_from_list(list_key, item_key),
+            || {
+                Ok(match self.inner.set_in_list(list_key, item_key, val)? {
+                    SetStatus::PrevValue(prev) => Some(prev),
+                    SetStatus::CreatedNew => None,
+                })
+            },
+        )
+        .map(set_status)
+    }
+    fn remove_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self._modify(
+            CacheKey::ListItem(list_key.to_owned(), item_key.to_owned()),
+            None,
+            || self.inner.get_from_list(list_key, item_key),
+            || self.inner.remove_from_list(list_key, item_key),
+        )
+    }
+    fn iter_list(&self, list_key: &[u8]) -> BoxedKvIterator<'_> {
+        if let Err(e) = self.flush() {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        self.inner.iter_list(list_key)
+    }
+    fn list_len(&self, list_key: &[u8]) -> Result<usize> {
+        self.flush()?;
+        self.inner.list_len(list_key)
+    }
+    fn discard_list(&self, list_key: &[u8]) -> Result<bool> {
+        let mut cache = self.cache.lock();
+        self.flush_locked(&mut cache)?;
+        let existed = self.inner.discard_list(list_key)?;
+        let is_list_item =
+            |key: &CacheKey| matches!(key, CacheKey::ListItem(lk, _) if lk == list_key);
+        cache.entries.retain(|key, _| !is_list_item(key));
+        cache.order.retain(|key| !is_list_item(key));
+        Ok(existed)
+    }
+}
+
+impl<S: KvStore + ListStore> Drop for CachedStore<S> {
+    fn drop(&mut self) {
+        // errors can't be propagated from drop, use flush() explicitly to handle them
+        _ = self.flush();
+    }
+}
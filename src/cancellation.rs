@@ -0,0 +1,35 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{CandyError, Result};
+
+/// A token for aborting long-running operations (e.g., [crate::CandyStore::discard_list_cancellable] or
+/// [crate::CandyStore::export_sst]) from another thread. Clones of a token share its state, so keep one and
+/// pass a clone to the operation. Cancelled operations fail with [CandyError::Cancelled], and leave the
+/// structure they operate on consistent, though only partially processed
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the operations that use this token to stop. This cannot be undone
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(cancel: Option<&Self>) -> Result<()> {
+        match cancel {
+            Some(token) if token.is_cancelled() => Err(CandyError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    hash::Hasher,
+    io::{Read, Write},
+    path::Path,
+};
+
+use siphasher::sip::SipHasher13;
+
+use crate::{store::StoreInner, CandyError, CandyStore, Result};
+
+// must not start with "shard_", which marks shard files
+pub(crate) const CHECKSUMS_FILENAME: &str = "checksum_manifest";
+
+const MANIFEST_MAGIC: &[u8; 8] = b"CandyCK1";
+// shard files are checksummed in regions of this size
+const REGION_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The outcome of verifying the shard files against the checksums written when the store was last closed,
+/// see [crate::Config::shard_checksums] and [CandyStore::checksum_report]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChecksumReport {
+    /// false if the store was not closed cleanly (or not with `shard_checksums` set), in which case there was
+    /// nothing to verify against
+    pub manifest_found: bool,
+    pub num_files_verified: usize,
+    pub num_bytes_verified: u64,
+}
+
+// the length of a file and the checksums of the regions of its used part. the rest of a shard file is
+// preallocated space, which is not worth reading
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct FileChecksums {
+    len: u64,
+    used_len: u64,
+    regions: Vec<u64>,
+}
+
+impl FileChecksums {
+    pub(crate) fn of_file(path: &Path, used_len: u64) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = file.take(used_len);
+        let mut buf = vec![0u8; REGION_SIZE as usize];
+        let mut regions = vec![];
+        loop {
+            // fill the whole region, short reads may happen anywhere
+            let mut filled = 0;
+            while filled < buf.len() {
+                let n = reader.read(&mut buf[filled..])?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            if filled == 0 {
+                break;
+            }
+            let mut hasher = SipHasher13::new();
+            hasher.write(&buf[..filled]);
+            regions.push(hasher.finish());
+            if filled < buf.len() {
+                break;
+            }
+        }
+        Ok(Self {
+            len,
+            used_len,
+            regions,
+        })
+    }
+}
+
+fn read_u64(buf: &mut &[u8]) -> Result<u64> {
+    let Some((bytes, rest)) = buf.split_first_chunk::<8>() else {
+        return Err(CandyError::Corruption(
+            "truncated shard checksums manifest".into(),
+        ));
+    };
+    *buf = rest;
+    Ok(u64::from_le_bytes(*bytes))
+}
+
+fn manifest_checksum(data: &[u8]) -> u64 {
+    let mut hasher = SipHasher13::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+// writes the manifest of the given shard files (by file name) atomically
+pub(crate) fn write_manifest(
+    dir_path: &Path,
+    files: &BTreeMap<String, FileChecksums>,
+) -> Result<()> {
+    let mut buf = MANIFEST_MAGIC.to_vec();
+    buf.extend_from_slice(&(files.len() as u64).to_le_bytes());
+    for (name, checksums) in files {
+        buf.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(&checksums.len.to_le_bytes());
+        buf.extend_from_slice(&checksums.used_len.to_le_bytes());
+        buf.extend_from_slice(&(checksums.regions.len() as u64).to_le_bytes());
+        for region in checksums.regions.iter() {
+            buf.extend_from_slice(&region.to_le_bytes());
+        }
+    }
+    buf.extend_from_slice(&manifest_checksum(&buf).to_le_bytes());
+
+    let tmp_path = dir_path.join(format!("{CHECKSUMS_FILENAME}.tmp"));
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, dir_path.join(CHECKSUMS_FILENAME))?;
+    Ok(())
+}
+
+fn read_manifest(data: &[u8]) -> Result<BTreeMap<String, FileChecksums>> {
+    let corrupted = || CandyError::Corruption("bad shard checksums manifest".into());
+    if data.len() < MANIFEST_MAGIC.len() + 8 || !data.starts_with(MANIFEST_MAGIC) {
+        return Err(corrupted());
+    }
+    let (body, checksum) = data.split_at(data.len() - 8);
+    if manifest_checksum(body).to_le_bytes() != checksum {
+        return Err(corrupted());
+    }
+
+    let mut buf = &body[MANIFEST_MAGIC.len()..];
+    let mut files = BTreeMap::new();
+    for _ in 0..read_u64(&mut buf)? {
+        let name_len = read_u64(&mut buf)? as usize;
+        if name_len > buf.len() {
+            return Err(corrupted());
+        }
+        let (name, rest) = buf.split_at(name_len);
+        buf = rest;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| corrupted())?;
+        let len = read_u64(&mut buf)?;
+        let used_len = read_u64(&mut buf)?;
+        let mut regions = vec![];
+        for _ in 0..read_u64(&mut buf)? {
+            regions.push(read_u64(&mut buf)?);
+        }
+        files.insert(
+            name,
+            FileChecksums {
+                len,
+                used_len,
+                regions,
+            },
+        );
+    }
+    Ok(files)
+}
+
+// verifies the shard files against the manifest (if any), and removes it, since the files are about to change
+pub(crate) fn verify_and_remove_manifest(dir_path: &Path, verify: bool) -> Result<ChecksumReport> {
+    // left behind if we crashed while writing the manifest
+    _ = std::fs::remove_file(dir_path.join(format!("{CHECKSUMS_FILENAME}.tmp")));
+    let manifest_path = dir_path.join(CHECKSUMS_FILENAME);
+    let data = match std::fs::read(&manifest_path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ChecksumReport::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut report = ChecksumReport {
+        manifest_found: true,
+        ..Default::default()
+    };
+    if verify {
+        let mut expected = read_manifest(&data)?;
+        for res in std::fs::read_dir(dir_path)? {
+            let entry = res?;
+            let Some(filename) = entry.file_name().to_str().map(|s| s.to_owned()) else {
+                continue;
+            };
+            if !filename.starts_with("shard_") || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Some(checksums) = expected.remove(&filename) else {
+                return Err(CandyError::Corruption(format!(
+                    "{filename} was created after the store was closed"
+                )));
+            };
+            let actual = FileChecksums::of_file(&entry.path(), checksums.used_len)?;
+            if actual.len != checksums.len {
+                return Err(CandyError::Corruption(format!(
+                    "{filename} is {} bytes long rather than {}",
+                    actual.len, checksums.len
+                )));
+            }
+            if let Some(region) = actual
+                .regions
+                .iter()
+                .zip(checksums.regions.iter())
+                .position(|(a, e)| a != e)
+            {
+                return Err(CandyError::Corruption(format!(
+                    "{filename} was modified after the store was closed (at offset {})",
+                    region as u64 * REGION_SIZE
+                )));
+            }
+            report.num_files_verified += 1;
+            report.num_bytes_verified += actual.used_len;
+        }
+        if let Some(filename) = expected.keys().next() {
+            return Err(CandyError::Corruption(format!(
+                "{filename} was removed after the store was closed"
+            )));
+        }
+    }
+
+    std::fs::remove_file(&manifest_path)?;
+    Ok(report)
+}
+
+impl CandyStore {
+    /// Returns the outcome of verifying the shard files when the store was opened, or None if
+    /// [crate::Config::shard_checksums] was not set
+    pub fn checksum_report(&self) -> Option<ChecksumReport> {
+        self.0.checksum_report
+    }
+}
+
+impl StoreInner {
+    // called when the last handle is dropped, once nothing else is written to the shard files
+    pub(crate) fn write_checksum_manifest(&self) -> Result<()> {
+        if !self.config.shard_checksums {
+            return Ok(());
+        }
+        let files = self
+            .root
+            .call_on_all_shards(|sh| sh.with_exclusive_file(FileChecksums::of_file))?;
+        write_manifest(&self.config.dir_path.get(), &files.into_iter().collect())
+    }
+}
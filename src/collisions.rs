@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+
+use crate::CandyStore;
+
+/// Where a signature collision was encountered, see [CollisionRecord]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionKind {
+    /// while inserting (or updating) a key, counted by [crate::Stats::num_collisions] once the key is added
+    Insert,
+    /// while looking up a key, counted by [crate::Stats::num_lookup_collisions]
+    Lookup,
+    /// while resolving a list index to its item, counted by [crate::Stats::num_list_chain_collisions]
+    ListChain,
+}
+
+/// A signature collision: two different keys whose (32 bit) signatures are equal and that fall in the same row
+/// of the same shard, so telling them apart required reading and comparing the keys. See
+/// [crate::Config::collision_log_capacity]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollisionRecord {
+    pub kind: CollisionKind,
+    /// the shared signature
+    pub signature: u32,
+    /// the (internal) key that was looked for, including its namespace suffix. List chain lookups only know
+    /// the item's hash, so for them this is the suffix that all items of the list share
+    pub key: Vec<u8>,
+    /// the (internal) key that was found instead
+    pub other_key: Vec<u8>,
+}
+
+// the most recent collisions, oldest first
+#[derive(Debug, Default)]
+pub(crate) struct CollisionLog(Mutex<VecDeque<CollisionRecord>>);
+
+impl CollisionLog {
+    pub(crate) fn push(&self, capacity: usize, record: impl FnOnce() -> CollisionRecord) {
+        if capacity == 0 {
+            return;
+        }
+        let mut log = self.0.lock();
+        while log.len() >= capacity {
+            log.pop_front();
+        }
+        log.push_back(record());
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().clear();
+    }
+}
+
+impl CandyStore {
+    /// Returns the most recent signature collisions (oldest first), if [crate::Config::collision_log_capacity]
+    /// is set. The log is kept in memory and is emptied when the store's stats are cleared
+    pub fn collision_log(&self) -> Vec<CollisionRecord> {
+        self.0
+            .stats
+            .collision_log
+            .0
+            .lock()
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    fs::OpenOptions,
+    io::Read,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{CandyError, CandyStore, Result};
+
+pub(crate) const COMPRESSION_DICT_FILENAME: &str = "compression_dict";
+
+/// A zstd dictionary trained over a sample of the store's values (see [crate::CandyStore::train_compression_dict]).
+/// Values are compressed with it only when that actually saves space, and compressed entries are marked in the
+/// shard rows, so shards may freely mix compressed and uncompressed values
+pub(crate) struct CompressionDict {
+    #[cfg(feature = "zstd")]
+    encoder: zstd::dict::EncoderDictionary<'static>,
+    #[cfg(feature = "zstd")]
+    decoder: zstd::dict::DecoderDictionary<'static>,
+}
+
+impl std::fmt::Debug for CompressionDict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressionDict").finish_non_exhaustive()
+    }
+}
+
+impl CompressionDict {
+    // smaller values do not compress even with a dictionary, as the frame header alone is a few bytes
+    #[cfg(feature = "zstd")]
+    const MIN_VALUE_LEN: usize = 16;
+
+    pub(crate) fn load(dir_path: &Path) -> Result<Option<Self>> {
+        let dict = match OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(dir_path.join(COMPRESSION_DICT_FILENAME))
+            .and_then(|mut file| {
+                let mut dict = vec![];
+                file.read_to_end(&mut dict)?;
+                Ok(dict)
+            }) {
+            Ok(dict) => dict,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Self::new(&dict).map(Some)
+    }
+
+    #[cfg(feature = "zstd")]
+    pub(crate) fn new(dict: &[u8]) -> Result<Self> {
+        Ok(Self {
+            encoder: zstd::dict::EncoderDictionary::copy(dict, zstd::DEFAULT_COMPRESSION_LEVEL),
+            decoder: zstd::dict::DecoderDictionary::copy(dict),
+        })
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn new(_dict: &[u8]) -> Result<Self> {
+        Err(CandyError::InvalidArgument(
+            "the store uses a compression dictionary, but the zstd feature is disabled".into(),
+        ))
+    }
+
+    /// returns the compressed value, or None if compression gains nothing
+    #[cfg(feature = "zstd")]
+    pub(crate) fn compress(&self, val: &[u8]) -> Result<Option<Vec<u8>>> {
+        if val.len() < Self::MIN_VALUE_LEN {
+            return Ok(None);
+        }
+        let mut compressor = zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)?;
+        // the dictionary is implied by the store, no need to repeat its id in every value
+        compressor.set_parameter(zstd::zstd_safe::CParameter::DictIdFlag(false))?;
+        let compressed = compressor.compress(val)?;
+        Ok((compressed.len() < val.len()).then_some(compressed))
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn compress(&self, _val: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    #[cfg(feature = "zstd")]
+    pub(crate) fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>> {
+        let len = match zstd::zstd_safe::get_frame_content_size(compressed) {
+            Ok(Some(len)) if len <= crate::MAX_TOTAL_VALUE_SIZE as u64 => len as usize,
+            _ => {
+                return Err(CandyError::Corruption(
+                    "bad dictionary-compressed value".into(),
+                ))
+            }
+        };
+        let mut decompressor = zstd::bulk::Decompressor::with_prepared_dictionary(&self.decoder)?;
+        Ok(decompressor.decompress(compressed, len)?)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn decompress(&self, _compressed: &[u8]) -> Result<Vec<u8>> {
+        unreachable!("a dictionary cannot be loaded without the zstd feature")
+    }
+}
+
+/// Counters of value compression since the store was opened (or cleared), see [CandyStore::compression_stats].
+/// Values are only compressed once a dictionary was trained (see `CandyStore::train_compression_dict`), and
+/// values rewritten by compactions and splits are counted again
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompressionStats {
+    /// values that were stored compressed
+    pub num_compressed: u64,
+    /// values that compression was tried on, but that were stored as-is since it did not make them smaller
+    pub num_incompressible: u64,
+    /// values that were not compressed because of [crate::Config::uncompressed_namespaces]
+    pub num_skipped: u64,
+    /// bytes of the values that compression was tried on (compressed or not), before compression
+    pub input_bytes: u64,
+    /// bytes of these values as stored, i.e., compressed if that made them smaller
+    pub output_bytes: u64,
+    /// the time spent compressing (including values that turned out incompressible)
+    pub compression_time: Duration,
+    pub num_decompressed: u64,
+    pub decompression_time: Duration,
+}
+
+impl CompressionStats {
+    /// stored bytes per input byte of the values that compression was tried on, or 1 if there were none
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            1.0
+        } else {
+            self.output_bytes as f64 / self.input_bytes as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CompressionCounters {
+    num_compressed: AtomicU64,
+    num_incompressible: AtomicU64,
+    num_skipped: AtomicU64,
+    input_bytes: AtomicU64,
+    output_bytes: AtomicU64,
+    compression_nanos: AtomicU64,
+    num_decompressed: AtomicU64,
+    decompression_nanos: AtomicU64,
+}
+
+impl CompressionCounters {
+    pub(crate) fn report_compression(
+        &self,
+        input_len: usize,
+        compressed_len: Option<usize>,
+        elapsed: Duration,
+    ) {
+        match compressed_len {
+            Some(_) => self.num_compressed.fetch_add(1, Ordering::Relaxed),
+            None => self.num_incompressible.fetch_add(1, Ordering::Relaxed),
+        };
+        self.input_bytes
+            .fetch_add(input_len as u64, Ordering::Relaxed);
+        self.output_bytes.fetch_add(
+            compressed_len.unwrap_or(input_len) as u64,
+            Ordering::Relaxed,
+        );
+        self.compression_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report_skipped(&self) {
+        self.num_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report_decompression(&self, elapsed: Duration) {
+        self.num_decompressed.fetch_add(1, Ordering::Relaxed);
+        self.decompression_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear(&self) {
+        for counter in [
+            &self.num_compressed,
+            &self.num_incompressible,
+            &self.num_skipped,
+            &self.input_bytes,
+            &self.output_bytes,
+            &self.compression_nanos,
+            &self.num_decompressed,
+            &self.decompression_nanos,
+        ] {
+            counter.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+impl CandyStore {
+    /// Returns the counters of value compression since the store was opened (or cleared), e.g., to find out
+    /// whether compression pays off, and to disable it for namespaces where it does not (see
+    /// [crate::Config::uncompressed_namespaces])
+    pub fn compression_stats(&self) -> CompressionStats {
+        let c = &self.0.stats.compression;
+        CompressionStats {
+            num_compressed: c.num_compressed.load(Ordering::Relaxed),
+            num_incompressible: c.num_incompressible.load(Ordering::Relaxed),
+            num_skipped: c.num_skipped.load(Ordering::Relaxed),
+            input_bytes: c.input_bytes.load(Ordering::Relaxed),
+            output_bytes: c.output_bytes.load(Ordering::Relaxed),
+            compression_time: Duration::from_nanos(c.compression_nanos.load(Ordering::Relaxed)),
+            num_decompressed: c.num_decompressed.load(Ordering::Relaxed),
+            decompression_time: Duration::from_nanos(c.decompression_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl crate::CandyStore {
+    /// Trains a zstd dictionary over (up to) `max_samples` of the store's values, and from now on uses it to
+    /// compress newly-written values. Small values usually gain nothing from standalone compression, but they
+    /// tend to share a lot of structure with each other (think JSON documents or protobufs with the same
+    /// schema), which a dictionary captures. Values are only stored compressed if that actually makes them
+    /// smaller, and existing values are compressed as they get rewritten (e.g., by compaction).
+    ///
+    /// The dictionary is persisted in the store's directory and cannot be replaced once trained (values that
+    /// were compressed with it must remain readable), so this should be called once the store holds a
+    /// representative set of values. Returns false if the store already has a dictionary. Stores with a
+    /// dictionary can only be opened with the `zstd` feature enabled.
+    pub fn train_compression_dict(&self, max_samples: usize, max_dict_size: usize) -> Result<bool> {
+        if self.0.config.compression_dict.get().is_some() {
+            return Ok(false);
+        }
+
+        let mut samples = Vec::with_capacity(max_samples.min(1024));
+        for res in self.iter().take(max_samples) {
+            let (_, val) = res?;
+            samples.push(val);
+        }
+        let dict = zstd::dict::from_samples(&samples, max_dict_size).map_err(|e| {
+            CandyError::InvalidArgument(format!("failed to train a compression dictionary: {e}"))
+        })?;
+        let compression_dict = CompressionDict::new(&dict)?;
+
+        // write the dictionary under a temporary name and then hard-link it into place, which fails if another
+        // thread has beaten us to it
+        let dict_path = self.0.config.dir_path.join(COMPRESSION_DICT_FILENAME);
+        let tmp_path = self.0.config.dir_path.join(format!(
+            "{COMPRESSION_DICT_FILENAME}.{:016x}",
+            rand::random::<u64>()
+        ));
+        let res = (|| {
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)?;
+            std::io::Write::write_all(&mut &file, &dict)?;
+            file.sync_all()?;
+            std::fs::hard_link(&tmp_path, &dict_path)
+        })();
+        _ = std::fs::remove_file(&tmp_path);
+        match res {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(self.0.config.compression_dict.set(compression_dict).is_ok())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{store::DEDUP_NAMESPACE, BlobId, CandyError, CandyStore, Result, SetStatus};
+
+// dedup'ed keys map to a tag byte followed by either the value itself (for small values and hash collisions)
+// or the ID of the blob holding it
+const INLINE_TAG: u8 = 0;
+const BLOB_TAG: u8 = 1;
+
+impl CandyStore {
+    fn make_dedup_key(&self, mut key: Vec<u8>) -> Vec<u8> {
+        key.extend_from_slice(DEDUP_NAMESPACE);
+        key
+    }
+
+    fn parse_dedup_ptr(ptr: &[u8]) -> Result<std::result::Result<&[u8], BlobId>> {
+        match ptr.split_first() {
+            Some((&INLINE_TAG, val)) => Ok(Ok(val)),
+            Some((&BLOB_TAG, id)) if id.len() == size_of::<BlobId>() => {
+                Ok(Err(BlobId::from_bytes(id.try_into().unwrap())))
+            }
+            _ => Err(CandyError::Corruption(format!("bad dedup pointer {ptr:?}"))),
+        }
+    }
+
+    // resolves a pointer into the value. returns None if the blob has been removed under our feet, in which
+    // case the caller should re-read the pointer
+    fn resolve_dedup_ptr(&self, ptr: &[u8]) -> Result<Option<Vec<u8>>> {
+        match Self::parse_dedup_ptr(ptr)? {
+            Ok(val) => Ok(Some(val.to_owned())),
+            Err(id) => self.get_blob(&id),
+        }
+    }
+
+    // resolves a pointer that is no longer reachable by others, so its blob must exist (references are taken
+    // before pointing at a blob and released only after the pointer is gone)
+    fn resolve_owned_dedup_ptr(&self, ptr: &[u8]) -> Result<Vec<u8>> {
+        self.resolve_dedup_ptr(ptr)?
+            .ok_or_else(|| CandyError::Corruption(format!("dangling dedup pointer {ptr:?}")))
+    }
+
+    fn release_dedup_ptr(&self, ptr: &[u8]) -> Result<()> {
+        if let Err(id) = Self::parse_dedup_ptr(ptr)? {
+            self.decref_blob(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Sets a key in the deduplicated keyspace: values of at least [crate::Config::dedup_min_value_size] bytes
+    /// are stored once as refcounted blobs (see [Self::put_blob]), and keys merely point at them. This saves
+    /// a lot of space when many keys hold identical (large) values, at the cost of an extra lookup on reads and
+    /// extra writes on updates.
+    ///
+    /// Deduplicated keys live in a keyspace of their own, i.e., they are only accessible through the `*_dedup`
+    /// functions and do not show up in [Self::iter]. Note that a crash in the middle of an update may leak a
+    /// reference, so the blob will never be freed (this never affects correctness)
+    pub fn set_dedup<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B1,
+        val: &B2,
+    ) -> Result<SetStatus> {
+        self.owned_set_dedup(key.as_ref().to_owned(), val.as_ref())
+    }
+
+    /// Same as [Self::set_dedup], but the key passed owned to this function
+    pub fn owned_set_dedup(&self, key: Vec<u8>, val: &[u8]) -> Result<SetStatus> {
+        Self::ensure_sizes(&key, val)?;
+        let dedup_key = self.make_dedup_key(key);
+
+        // take a reference on the new blob before pointing at it, and only then release the previous one
+        let blob_id = if val.len() >= self.0.config.dedup_min_value_size.get() {
+            self._put_blob(val)?
+        } else {
+            None
+        };
+        let ptr = match blob_id {
+            Some(id) => [&[BLOB_TAG][..], id.as_bytes()].concat(),
+            None => [&[INLINE_TAG][..], val].concat(),
+        };
+
+        match self.set_raw(&dedup_key, &ptr)? {
+            SetStatus::CreatedNew => Ok(SetStatus::CreatedNew),
+            SetStatus::PrevValue(prev_ptr) => {
+                let prev_val = self.resolve_owned_dedup_ptr(&prev_ptr)?;
+                self.release_dedup_ptr(&prev_ptr)?;
+                Ok(SetStatus::PrevValue(prev_val))
+            }
+        }
+    }
+
+    /// Gets the value of a key from the deduplicated keyspace (see [Self::set_dedup])
+    pub fn get_dedup<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<Vec<u8>>> {
+        self.owned_get_dedup(key.as_ref().to_owned())
+    }
+
+    /// Same as [Self::get_dedup], but the key passed owned to this function
+    pub fn owned_get_dedup(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let dedup_key = self.make_dedup_key(key);
+        let mut ptr = self.get_raw(&dedup_key)?;
+        loop {
+            let Some(ref curr_ptr) = ptr else {
+                return Ok(None);
+            };
+            if let Some(val) = self.resolve_dedup_ptr(curr_ptr)? {
+                return Ok(Some(val));
+            }
+            // the key has been updated (and the blob released) concurrently
+            let new_ptr = self.get_raw(&dedup_key)?;
+            if new_ptr == ptr {
+                return Err(CandyError::Corruption(format!(
+                    "dangling dedup pointer {curr_ptr:?}"
+                )));
+            }
+            ptr = new_ptr;
+        }
+    }
+
+    /// Removes a key from the deduplicated keyspace (see [Self::set_dedup]), returning its value if it existed.
+    /// The underlying blob is removed once no keys point at it
+    pub fn remove_dedup<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<Vec<u8>>> {
+        self.owned_remove_dedup(key.as_ref().to_owned())
+    }
+
+    /// Same as [Self::remove_dedup], but the key passed owned to this function
+    pub fn owned_remove_dedup(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let dedup_key = self.make_dedup_key(key);
+        let Some(ptr) = self.remove_raw(&dedup_key)? else {
+            return Ok(None);
+        };
+        let val = self.resolve_owned_dedup_ptr(&ptr)?;
+        self.release_dedup_ptr(&ptr)?;
+        Ok(Some(val))
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{hashing::value_checksum, store::CandyStoreIterator, CandyStore, Result};
+
+/// Controls [CandyStore::diff]
+#[derive(Debug, Clone, Default)]
+pub struct DiffParams {
+    /// compare all entries of the stores, including the internal ones of lists, queues, typed stores, etc.
+    /// This is only meaningful if both stores use the same [crate::Config::hash_seed]. When unset, only the
+    /// keys of [CandyStore::set] and friends are compared
+    pub raw: bool,
+    /// report checksums of the values instead of the values themselves, which keeps the memory footprint
+    /// of the entries small when comparing large values
+    pub checksum_only: bool,
+}
+
+/// A value reported by [CandyStore::diff]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffValue {
+    Bytes(Vec<u8>),
+    /// a checksum of the value (see [DiffParams::checksum_only]), the same as [CandyStore::get_checksum]
+    /// returns
+    Checksum(u64),
+}
+
+impl DiffValue {
+    fn new(val: Vec<u8>, checksum_only: bool) -> Self {
+        if checksum_only {
+            Self::Checksum(value_checksum(&val))
+        } else {
+            Self::Bytes(val)
+        }
+    }
+}
+
+/// A key on which two stores differ, see [CandyStore::diff]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+    /// the key exists only in this store (the one `diff` was called on)
+    OnlyInThis { key: Vec<u8>, val: DiffValue },
+    /// the key exists only in the other store
+    OnlyInOther { key: Vec<u8>, val: DiffValue },
+    /// the key exists in both stores, with different values
+    Different {
+        key: Vec<u8>,
+        this: DiffValue,
+        other: DiffValue,
+    },
+}
+
+impl DiffEntry {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::OnlyInThis { key, .. }
+            | Self::OnlyInOther { key, .. }
+            | Self::Different { key, .. } => key,
+        }
+    }
+}
+
+impl CandyStore {
+    fn get_for_diff(&self, key: &[u8], raw: bool) -> Result<Option<Vec<u8>>> {
+        if raw {
+            self.get_raw(key)
+        } else {
+            self.get(key)
+        }
+    }
+
+    /// Compares this store with another one, streaming the keys that exist in only one of them or whose
+    /// values differ, e.g., for validating a migration or a replica. This is a full scan of both stores (first
+    /// this one, then the other), looking up every key in the opposite store, so it does not keep anything in
+    /// memory. The order of the entries is unspecified.
+    ///
+    /// Note: this is not a consistent snapshot, so keys modified concurrently may be reported (or missed)
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a CandyStore,
+        params: DiffParams,
+    ) -> impl Iterator<Item = Result<DiffEntry>> + 'a {
+        let DiffParams { raw, checksum_only } = params;
+
+        let changed = CandyStoreIterator::from_cookie(self, 0, raw, true).filter_map(move |res| {
+            let entry = (|| {
+                let (key, val) = res?;
+                Ok(match other.get_for_diff(&key, raw)? {
+                    None => Some(DiffEntry::OnlyInThis {
+                        key,
+                        val: DiffValue::new(val, checksum_only),
+                    }),
+                    Some(other_val) if other_val != val => Some(DiffEntry::Different {
+                        key,
+                        this: DiffValue::new(val, checksum_only),
+                        other: DiffValue::new(other_val, checksum_only),
+                    }),
+                    Some(_) => None,
+                })
+            })();
+            entry.transpose()
+        });
+
+        let added = CandyStoreIterator::from_cookie(other, 0, raw, true).filter_map(move |res| {
+            let entry = (|| {
+                let (key, val) = res?;
+                Ok(match self.get_for_diff(&key, raw)? {
+                    None => Some(DiffEntry::OnlyInOther {
+                        key,
+                        val: DiffValue::new(val, checksum_only),
+                    }),
+                    Some(_) => None,
+                })
+            })();
+            entry.transpose()
+        });
+
+        changed.chain(added)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{store::EPHEMERAL_NAMESPACE, CandyStore, Result};
+
+/// A guard returned by [CandyStore::ephemeral]. The key it refers to is removed from the store when the guard
+/// is dropped
+pub struct EphemeralGuard<'a> {
+    store: &'a CandyStore,
+    key: Vec<u8>,
+    full_key: Vec<u8>,
+}
+
+impl<'a> EphemeralGuard<'a> {
+    /// Returns the (user) key this guard refers to
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl<'a> Drop for EphemeralGuard<'a> {
+    fn drop(&mut self) {
+        // errors can't be propagated from drop. in the worst case, the key will be removed on the next open
+        _ = self.store.remove_raw(&self.full_key);
+        _ = self
+            .store
+            .owned_remove_from_list(EPHEMERAL_NAMESPACE.to_owned(), self.full_key.clone());
+    }
+}
+
+impl CandyStore {
+    /// Sets a "temporary" key, that lives as long as the returned [EphemeralGuard]. Once the guard is dropped,
+    /// the key is removed from the store. If the process dies while the guard is alive, the key will be removed
+    /// the next time the store is opened. This is useful for presence/heartbeat keys.
+    ///
+    /// The key is a regular key in all other respects, e.g., you can [Self::set] it to update its value.
+    pub fn ephemeral<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B1,
+        val: &B2,
+    ) -> Result<EphemeralGuard<'_>> {
+        self.owned_ephemeral(key.as_ref().to_owned(), val.as_ref())
+    }
+
+    /// Same as [Self::ephemeral] but takes an owned key
+    pub fn owned_ephemeral(&self, key: Vec<u8>, val: &[u8]) -> Result<EphemeralGuard<'_>> {
+        Self::ensure_sizes(&key, val)?;
+        let full_key = self.make_user_key_for_write(key.clone())?;
+
+        // the marker is written first, so a crash in between leaves (at most) a dangling marker
+        self.owned_set_in_list(
+            EPHEMERAL_NAMESPACE.to_owned(),
+            full_key.clone(),
+            vec![],
+            false,
+        )?;
+        self.set_raw(&full_key, val)?;
+
+        Ok(EphemeralGuard {
+            store: self,
+            key,
+            full_key,
+        })
+    }
+
+    /// removes all ephemeral keys left over from a previous session (called on open)
+    pub(crate) fn remove_leftover_ephemerals(&self) -> Result<()> {
+        for res in self.owned_iter_list(EPHEMERAL_NAMESPACE.to_owned()) {
+            let (full_key, _) = res?;
+            self.remove_raw(&full_key)?;
+        }
+        self.owned_discard_list(EPHEMERAL_NAMESPACE.to_owned())?;
+        Ok(())
+    }
+}
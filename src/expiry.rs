@@ -0,0 +1,405 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    hashing::PartedHash,
+    store::{ReplaceStatus, SetStatus, EXPIRY_NAMESPACE, LIST_NAMESPACE},
+    CandyError, CandyStore, Result,
+};
+
+// the entries of the expiry namespace, which are distinguished by their first byte. keys and list items have
+// separate deadlines and buckets, so that the keys indexed by the buckets of keys remain plain user keys
+const DEADLINE: u8 = b'd';
+const BUCKET: u8 = b'b';
+const ITEM_DEADLINE: u8 = b'i';
+const ITEM_BUCKET: u8 = b'l';
+const CURSOR: u8 = b'c';
+
+// keys are indexed by their expiry time, in buckets of this many milliseconds. sweeping visits every bucket
+// since the last sweep, so this trades the cost of sweeping after a long pause against the cost of visiting
+// buckets that expire only a few keys
+const EXPIRY_BUCKET_MS: u64 = 10_000;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn parse_ms(bytes: &[u8]) -> Result<u64> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| CandyError::Corruption("bad expiry time".into()))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ExpiryState {
+    // whether any TTL was ever set in the store, so that stores that don't use TTLs don't pay for looking up
+    // deadlines on every write
+    in_use: AtomicBool,
+}
+
+// what a deadline belongs to
+#[derive(Clone, Copy)]
+enum Expiring {
+    Key,
+    ListItem,
+}
+
+impl Expiring {
+    fn deadline_kind(self) -> u8 {
+        match self {
+            Self::Key => DEADLINE,
+            Self::ListItem => ITEM_DEADLINE,
+        }
+    }
+
+    fn bucket_kind(self) -> u8 {
+        match self {
+            Self::Key => BUCKET,
+            Self::ListItem => ITEM_BUCKET,
+        }
+    }
+}
+
+// identifies an item of a list within the expiry namespace
+fn make_item_id(list_key: &[u8], item_key: &[u8]) -> Vec<u8> {
+    [
+        &(list_key.len() as u32).to_le_bytes()[..],
+        list_key,
+        item_key,
+    ]
+    .concat()
+}
+
+fn parse_item_id(item_id: &[u8]) -> Result<(&[u8], &[u8])> {
+    let bad_id = || CandyError::Corruption("bad expiring list item".into());
+    let (len, rest) = item_id
+        .split_at_checked(size_of::<u32>())
+        .ok_or_else(bad_id)?;
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    rest.split_at_checked(len).ok_or_else(bad_id)
+}
+
+// returns the (user) key that an entry of the expiry namespace belongs to, given the entry's key without the
+// namespace byte
+pub(crate) fn expiry_owner(body: &[u8]) -> Option<&[u8]> {
+    match body.split_first()? {
+        (&DEADLINE, key) => Some(key),
+        _ => None,
+    }
+}
+
+impl CandyStore {
+    // the expiry time of a key (or list item)
+    fn make_deadline_key(expiring: Expiring, id: &[u8]) -> Vec<u8> {
+        [&[expiring.deadline_kind()], id, EXPIRY_NAMESPACE].concat()
+    }
+
+    // the (internal) list that indexes the keys (or list items) expiring within a bucket, mapping them to their
+    // expiry times
+    fn make_bucket_list_key(expiring: Expiring, bucket: u64) -> Vec<u8> {
+        [
+            &[expiring.bucket_kind()],
+            &bucket.to_le_bytes()[..],
+            EXPIRY_NAMESPACE,
+        ]
+        .concat()
+    }
+
+    // the first bucket that has not been swept yet
+    fn expiry_cursor_key() -> Vec<u8> {
+        [&[CURSOR], EXPIRY_NAMESPACE].concat()
+    }
+
+    pub(crate) fn load_expiry_state(&self) -> Result<()> {
+        // the cursor is created along with the first TTL
+        let in_use = self.get_raw(&Self::expiry_cursor_key())?.is_some();
+        self.0.expiry.in_use.store(in_use, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_deadline(&self, expiring: Expiring, id: &[u8], ttl: Duration) -> Result<()> {
+        self.0.expiry.in_use.store(true, Ordering::SeqCst);
+        let now = now_ms();
+        let deadline = now.saturating_add(ttl.as_millis() as u64);
+        // the cursor only ever points at or before the current bucket, so it never skips the new entry
+        self.get_or_create_raw(
+            &Self::expiry_cursor_key(),
+            (now / EXPIRY_BUCKET_MS).to_le_bytes().to_vec(),
+        )?;
+        // the index is written first, so a crash in between leaves (at most) a dangling index entry, which
+        // the sweeper drops
+        self.owned_set_in_list(
+            Self::make_bucket_list_key(expiring, deadline / EXPIRY_BUCKET_MS),
+            id.to_owned(),
+            deadline.to_le_bytes().to_vec(),
+            false,
+        )?;
+        self.set_raw(
+            &Self::make_deadline_key(expiring, id),
+            &deadline.to_le_bytes(),
+        )?;
+        Ok(())
+    }
+
+    // drops the TTL of a key that is being overwritten or removed, so that the sweeper does not remove the
+    // key's next value
+    pub(crate) fn clear_ttl(&self, key: &[u8]) -> Result<()> {
+        if self.0.expiry.in_use.load(Ordering::SeqCst) {
+            self.remove_raw(&Self::make_deadline_key(Expiring::Key, key))?;
+        }
+        Ok(())
+    }
+
+    // drops the TTL of a list item that is being removed, given the list's full key and the item's key
+    pub(crate) fn clear_list_item_ttl(&self, full_list_key: &[u8], item_key: &[u8]) -> Result<()> {
+        if self.0.expiry.in_use.load(Ordering::SeqCst) {
+            let list_key = &full_list_key[..full_list_key.len() - LIST_NAMESPACE.len()];
+            self.remove_raw(&Self::make_deadline_key(
+                Expiring::ListItem,
+                &make_item_id(list_key, item_key),
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn has_expired(&self, expiring: Expiring, id: &[u8]) -> Result<bool> {
+        if !self.0.expiry.in_use.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let Some(deadline) = self.get_raw(&Self::make_deadline_key(expiring, id))? else {
+            return Ok(false);
+        };
+        Ok(parse_ms(&deadline)? <= now_ms())
+    }
+
+    // removes the deadline only if it is still `deadline`, i.e., it was not replaced (or removed) meanwhile
+    fn remove_deadline_if(&self, expiring: Expiring, id: &[u8], deadline: &[u8]) -> Result<bool> {
+        let deadline_key = Self::make_deadline_key(expiring, id);
+        let ph = PartedHash::new(&self.0.config.hash_seed, &deadline_key);
+        Ok(matches!(
+            self.remove_if_with_hash(ph, &deadline_key, Some(deadline))?,
+            ReplaceStatus::PrevValue(_)
+        ))
+    }
+
+    // removes a key whose TTL has passed, given the deadline that has passed. the value is read before the
+    // deadline is removed, and the key is removed only if it still holds that value: setting the key drops its
+    // deadline before writing the new value, so a value that was read while the deadline was still in place is
+    // the one that expired, while a value written since then is left alone
+    fn purge_expired_key(&self, key: &[u8], deadline: &[u8]) -> Result<bool> {
+        let full_key = self.make_user_key(key.to_owned())?;
+        let ph = PartedHash::new(&self.0.config.hash_seed, &full_key);
+        let expired_val = self.get_with_hash(ph, &full_key)?;
+        if !self.remove_deadline_if(Expiring::Key, key, deadline)? {
+            return Ok(false);
+        }
+        let Some(expired_val) = expired_val else {
+            return Ok(false);
+        };
+        self.with_key_history(key, |_| {
+            Ok(matches!(
+                self.remove_if_with_hash(ph, &full_key, Some(&expired_val))?,
+                ReplaceStatus::PrevValue(_)
+            ))
+        })
+    }
+
+    // whether reads should treat the key as gone, as its TTL has passed but it was not purged yet
+    pub(crate) fn key_expired(&self, key: &[u8]) -> Result<bool> {
+        self.has_expired(Expiring::Key, key)
+    }
+
+    // purges the key if its TTL has passed, so that operations that act on its current value (e.g., replace or
+    // update) find it gone, rather than acting on the expired value. must not be called under the key's history
+    // lock, which purging takes
+    pub(crate) fn purge_if_expired(&self, key: &[u8]) -> Result<()> {
+        if !self.0.expiry.in_use.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let Some(deadline) = self.get_raw(&Self::make_deadline_key(Expiring::Key, key))? else {
+            return Ok(());
+        };
+        if parse_ms(&deadline)? <= now_ms() && self.purge_expired_key(key, &deadline)? {
+            self.0.expirations.notify(&[key.to_owned()]);
+        }
+        Ok(())
+    }
+
+    // same as key_expired, for list items
+    pub(crate) fn list_item_expired(&self, list_key: &[u8], item_key: &[u8]) -> Result<bool> {
+        self.has_expired(Expiring::ListItem, &make_item_id(list_key, item_key))
+    }
+
+    /// Same as [Self::set], but the key expires after `ttl`: from then on, reads (e.g., [Self::get],
+    /// [Self::contains] or [Self::value_len]) treat it as gone, operations that act on its current value (e.g.,
+    /// [Self::replace], [Self::update] or [Self::get_or_create]) remove it first, and so does the first
+    /// [Self::purge_expired] that runs (iterations keep returning it until it's removed).
+    ///
+    /// Like in Redis, the TTL is dropped when the key is removed or its value is replaced (e.g., with
+    /// [Self::set], [Self::replace] or [Self::update]), but not when it is patched (see [Self::patch]). Setting
+    /// a new TTL replaces the previous one
+    pub fn set_with_ttl<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B1,
+        val: &B2,
+        ttl: Duration,
+    ) -> Result<SetStatus> {
+        let key = key.as_ref();
+        // setting the value drops the previous TTL, so the new one is set afterwards
+        let status = self.set(key, val)?;
+        self.set_deadline(Expiring::Key, key, ttl)?;
+        Ok(status)
+    }
+
+    /// Same as [Self::set_in_list], but the item expires after `ttl`: from then on, [Self::get_from_list] treats
+    /// it as gone, and the first [Self::purge_expired] that runs removes it from the list. Unlike the TTLs of keys, the TTL stays in effect when the
+    /// item is updated, until it expires, is removed, or [Self::persist_in_list] is called, e.g., for LRU caches
+    /// built on lists (see [Self::set_in_list_promoting])
+    pub fn set_in_list_with_ttl<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+        ttl: Duration,
+    ) -> Result<SetStatus> {
+        let (list_key, item_key) = (list_key.as_ref(), item_key.as_ref());
+        Self::ensure_sizes(item_key, val.as_ref())?;
+        self.set_deadline(Expiring::ListItem, &make_item_id(list_key, item_key), ttl)?;
+        self.set_in_list(list_key, item_key, val)
+    }
+
+    /// Sets the TTL of an existing key (see [Self::set_with_ttl]), replacing its previous TTL, if any. Returns
+    /// false if the key does not exist
+    pub fn expire<B: AsRef<[u8]> + ?Sized>(&self, key: &B, ttl: Duration) -> Result<bool> {
+        let key = key.as_ref();
+        if !self.contains(key)? {
+            return Ok(false);
+        }
+        self.set_deadline(Expiring::Key, key, ttl)?;
+        Ok(true)
+    }
+
+    /// Removes the TTL of the given key, so that it never expires. Returns false if the key had no TTL
+    pub fn persist<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<bool> {
+        Ok(self
+            .remove_raw(&Self::make_deadline_key(Expiring::Key, key.as_ref()))?
+            .is_some())
+    }
+
+    /// Removes the TTL of the given list item (see [Self::set_in_list_with_ttl]), so that it never expires.
+    /// Returns false if the item had no TTL
+    pub fn persist_in_list<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+    ) -> Result<bool> {
+        let item_id = make_item_id(list_key.as_ref(), item_key.as_ref());
+        Ok(self
+            .remove_raw(&Self::make_deadline_key(Expiring::ListItem, &item_id))?
+            .is_some())
+    }
+
+    /// Returns the time left until the given key expires, or `None` if it has no TTL. Keys whose TTL has
+    /// passed but were not purged yet return zero
+    pub fn get_ttl<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<Duration>> {
+        let Some(deadline) = self.get_raw(&Self::make_deadline_key(Expiring::Key, key.as_ref()))?
+        else {
+            return Ok(None);
+        };
+        let left_ms = parse_ms(&deadline)?.saturating_sub(now_ms());
+        Ok(Some(Duration::from_millis(left_ms)))
+    }
+
+    /// Removes the keys and list items whose TTL has passed (see [Self::set_with_ttl] and
+    /// [Self::set_in_list_with_ttl]), returning the number of keys and items removed. The removed keys are
+    /// reported to [Self::subscribe_expirations].
+    ///
+    /// Keys are indexed by their expiry time, so this only visits the keys that expire between the previous
+    /// purge and now (rather than scanning the store), and its cost is proportional to their number (and to
+    /// the time since the previous purge, in 10 second steps). Call it periodically, or let
+    /// [Self::spawn_expiry_purger] do so. Concurrent purges are safe, but pointless
+    pub fn purge_expired(&self) -> Result<usize> {
+        let Some(cursor) = self.get_raw(&Self::expiry_cursor_key())? else {
+            return Ok(0);
+        };
+        let cursor = parse_ms(&cursor)?;
+        let now = now_ms();
+        let now_bucket = now / EXPIRY_BUCKET_MS;
+        let mut num_removed = 0;
+        let mut expired_keys = vec![];
+
+        for (bucket, expiring) in (cursor..=now_bucket)
+            .flat_map(|bucket| [(bucket, Expiring::Key), (bucket, Expiring::ListItem)])
+        {
+            let list_key = Self::make_bucket_list_key(expiring, bucket);
+            let mut swept = vec![];
+            for res in self.owned_iter_list(list_key.clone()) {
+                let (id, deadline_bytes) = res?;
+                if parse_ms(&deadline_bytes)? > now {
+                    continue;
+                }
+                match expiring {
+                    Expiring::Key => {
+                        if self.purge_expired_key(&id, &deadline_bytes)? {
+                            expired_keys.push(id.clone());
+                            num_removed += 1;
+                        }
+                    }
+                    Expiring::ListItem => {
+                        // the item is removed only if its TTL was not replaced (or removed) in the meantime
+                        if self.remove_deadline_if(expiring, &id, &deadline_bytes)? {
+                            let (list_key, item_key) = parse_item_id(&id)?;
+                            if self.remove_from_list(list_key, item_key)?.is_some() {
+                                num_removed += 1;
+                            }
+                        }
+                    }
+                }
+                swept.push(id);
+            }
+
+            // a bucket is done once the clock has moved past it, with a bucket of grace for writers that read
+            // the clock right before this sweep did
+            if bucket + 1 < now_bucket {
+                self.owned_discard_list(list_key)?;
+            } else {
+                for key in swept {
+                    self.owned_remove_from_list(list_key.clone(), key)?;
+                }
+            }
+        }
+
+        let new_cursor = now_bucket.saturating_sub(1).max(cursor);
+        if new_cursor != cursor {
+            self.set_raw(&Self::expiry_cursor_key(), &new_cursor.to_le_bytes())?;
+        }
+        if !expired_keys.is_empty() {
+            self.0.expirations.notify(&expired_keys);
+        }
+        Ok(num_removed)
+    }
+
+    /// Spawns a thread that calls [Self::purge_expired] every `interval`, until the store is closed (the thread
+    /// does not keep the store open). Failed purges are retried on the next round
+    pub fn spawn_expiry_purger(&self, interval: Duration) -> JoinHandle<()> {
+        let weak = self.downgrade();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(store) = Self::upgrade(&weak) else {
+                break;
+            };
+            _ = store.purge_expired();
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! Entry points for fuzzing the parsers of the on-disk structures, which are otherwise internal. Every
+//! function here must return an error (rather than panic) on malformed input, and the `Arbitrary` types can
+//! be used to generate well-formed inputs for structure-aware fuzzing, e.g.
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     _ = candystore::fuzzing::parse_list_header(data);
+//! });
+//! ```
+
+use std::path::Path;
+
+pub use arbitrary::{self, Arbitrary};
+use bytemuck::bytes_of;
+
+use crate::{
+    hashing::PartedHash,
+    lists::{ChainKey, List},
+    router::ShardRouter,
+    shard::{self, HEADER_SIZE, SHARD_FILE_MAGIC, SHARD_FILE_VERSION},
+    store::CHAIN_NAMESPACE,
+    CandyStore, Config, Result,
+};
+
+/// The size of a shard file's header, which must be followed by the data section
+pub const SHARD_HEADER_SIZE: u64 = HEADER_SIZE;
+
+/// The counters of a shard file's header, see [parse_shard_header]
+#[derive(Debug, Clone, PartialEq, Eq, Arbitrary)]
+pub struct ShardHeaderInfo {
+    pub wasted_bytes: u64,
+    pub write_offset: u64,
+    pub num_inserts: u64,
+    pub num_removals: u64,
+    pub compacted_up_to: u64,
+    pub key_prefixes_fingerprint: u64,
+}
+
+impl ShardHeaderInfo {
+    /// Encodes the beginning of a shard file with these counters (up to, but excluding, the rows)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = SHARD_FILE_MAGIC.to_vec();
+        for n in [
+            SHARD_FILE_VERSION,
+            self.wasted_bytes,
+            self.write_offset,
+            self.num_inserts,
+            self.num_removals,
+            self.compacted_up_to,
+            self.key_prefixes_fingerprint,
+        ] {
+            buf.extend_from_slice(&n.to_ne_bytes());
+        }
+        buf
+    }
+}
+
+/// Parses the beginning of a shard file of `file_size` bytes, applying the same checks as opening the store
+/// does (magic, version, size), as well as checking the counters' consistency
+pub fn parse_shard_header(bytes: &[u8], file_size: u64) -> Result<ShardHeaderInfo> {
+    let c = shard::parse_shard_header(bytes, file_size)?;
+    Ok(ShardHeaderInfo {
+        wasted_bytes: c.wasted_bytes,
+        write_offset: c.write_offset,
+        num_inserts: c.num_inserts,
+        num_removals: c.num_removals,
+        compacted_up_to: c.compacted_up_to,
+        key_prefixes_fingerprint: c.key_prefixes_fingerprint,
+    })
+}
+
+/// A used slot of a shard row, see [parse_shard_row]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowEntryInfo {
+    pub signature: u32,
+    pub key_len: usize,
+    pub val_len: usize,
+    /// the offset of the entry within the file's data section
+    pub offset: u64,
+    pub compressed: bool,
+}
+
+/// Parses a single row of a shard file's header, validating that the entries lie within the first
+/// `data_len` bytes of the data section
+pub fn parse_shard_row(bytes: &[u8], data_len: u64) -> Result<Vec<RowEntryInfo>> {
+    Ok(shard::parse_shard_row(bytes, data_len)?
+        .into_iter()
+        .map(|e| RowEntryInfo {
+            signature: e.signature,
+            key_len: e.klen,
+            val_len: e.vlen,
+            offset: e.offset,
+            compressed: e.compressed,
+        })
+        .collect())
+}
+
+/// A list's header, see [parse_list_header]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Arbitrary)]
+pub struct ListHeaderInfo {
+    pub head_idx: u64,
+    pub tail_idx: u64,
+    pub num_items: u64,
+}
+
+impl ListHeaderInfo {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bytes_of(&List {
+            head_idx: self.head_idx,
+            tail_idx: self.tail_idx,
+            num_items: self.num_items,
+        })
+        .to_vec()
+    }
+}
+
+/// Parses a list's header, as stored in the list's entry
+pub fn parse_list_header(bytes: &[u8]) -> Result<ListHeaderInfo> {
+    let list = List::parse(bytes)?;
+    Ok(ListHeaderInfo {
+        head_idx: list.head_idx,
+        tail_idx: list.tail_idx,
+        num_items: list.num_items,
+    })
+}
+
+/// A chain key (which maps a list's index to its item), see [parse_chain_key]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Arbitrary)]
+pub struct ChainKeyInfo {
+    pub list_hash: u64,
+    pub idx: u64,
+}
+
+impl ChainKeyInfo {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bytes_of(&ChainKey {
+            list_ph: bytemuck::cast(self.list_hash),
+            idx: self.idx,
+            namespace: CHAIN_NAMESPACE,
+        })
+        .to_vec()
+    }
+}
+
+/// Parses a chain key
+pub fn parse_chain_key(bytes: &[u8]) -> Result<ChainKeyInfo> {
+    let ck = ChainKey::parse(bytes)?;
+    Ok(ChainKeyInfo {
+        list_hash: bytemuck::cast::<PartedHash, u64>(ck.list_ph),
+        idx: ck.idx,
+    })
+}
+
+/// Writes `contents` as the store's only shard file in `dir` (which must be empty), and then opens the store
+/// and reads everything in it. This exercises the whole open/recovery path on arbitrary (e.g., partially
+/// written) files: it may return an error, but must not panic
+pub fn open_shard_file(dir: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(
+        dir.join(format!("shard_0000-{:04x}", ShardRouter::END_OF_SHARDS)),
+        contents,
+    )?;
+    let db = CandyStore::open(dir, Config::default())?;
+    for res in db.iter() {
+        res?;
+    }
+    Ok(())
+}
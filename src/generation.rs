@@ -0,0 +1,77 @@
+use std::{
+    fs::File,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+
+use crate::{store::InternalConfig, Result};
+
+// holds the generation up to which the previous runs of the store may have handed out generations
+const GENERATION_FILENAME: &str = "generation";
+
+// the number of generations that are handed out between writes of the file. the lease is renewed once half of it
+// is used, so that concurrent modifications do not run past it while it's written
+const LEASE_LEN: u64 = 1 << 24;
+
+/// Keeps [crate::CandyStore::generation] from going backwards across reopens (and crashes): the store only hands
+/// out generations below the one persisted in its directory, and resumes from it when it's opened
+#[derive(Debug, Default)]
+pub(crate) struct GenerationLease {
+    config: OnceLock<Arc<InternalConfig>>,
+    // the generation past which the lease is renewed
+    renew_at: AtomicU64,
+    renew_lock: Mutex<()>,
+}
+
+impl GenerationLease {
+    // returns the generation to start from, which is past any generation the store handed out before and past
+    // the current time (in microseconds), so that stores that were moved or recreated keep increasing as well
+    pub(crate) fn acquire(&self, config: &Arc<InternalConfig>) -> Result<u64> {
+        let persisted = match std::fs::read(config.dir_path.join(GENERATION_FILENAME)) {
+            Ok(bytes) => bytes.try_into().map_or(0, u64::from_le_bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let start = persisted.max(now);
+        _ = self.config.set(config.clone());
+        self.renew(start)?;
+        Ok(start)
+    }
+
+    // called whenever the generation is bumped
+    pub(crate) fn generation_bumped(&self, generation: u64) {
+        if generation < self.renew_at.load(Ordering::Relaxed) {
+            return;
+        }
+        let _guard = self.renew_lock.lock();
+        if generation >= self.renew_at.load(Ordering::Relaxed) {
+            // failing to write the file only matters if the store crashes before the lease is renewed, so
+            // this is retried with the next renewal instead of failing the modification
+            _ = self.renew(generation);
+        }
+    }
+
+    fn renew(&self, generation: u64) -> Result<()> {
+        self.renew_at
+            .store(generation + LEASE_LEN / 2, Ordering::Relaxed);
+        let Some(config) = self.config.get() else {
+            return Ok(());
+        };
+        let tmp_path = config.dir_path.join(format!("{GENERATION_FILENAME}.tmp"));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&(generation + LEASE_LEN).to_le_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, config.dir_path.join(GENERATION_FILENAME))?;
+        Ok(())
+    }
+}
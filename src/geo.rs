@@ -0,0 +1,216 @@
+use crate::{store::GEO_NAMESPACE, CandyError, CandyStore, Result};
+
+const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+// every point is indexed in a bucket list at each of these levels (bits per axis), from the coarsest to the
+// finest. at 16 bits, cells are ~300m tall, at 4 bits they are ~1250km tall
+const LEVEL_BITS: [u32; 4] = [4, 8, 12, 16];
+// queries use the finest level at which the search area is covered by at most this many cells
+const MAX_QUERY_CELLS: usize = 36;
+
+/// A point returned by [CandyStore::geo_query_radius]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoMatch {
+    pub key: Vec<u8>,
+    pub lat: f64,
+    pub lon: f64,
+    /// the distance from the query's center, in meters
+    pub distance: f64,
+}
+
+fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().min(1.0).asin()
+}
+
+fn lat_cell(lat: f64, bits: u32) -> u32 {
+    let n = 1u32 << bits;
+    (((lat + 90.0) / 180.0 * n as f64) as u32).min(n - 1)
+}
+
+fn lon_cell(lon: f64, bits: u32) -> u32 {
+    let n = 1u32 << bits;
+    (((lon + 180.0) / 360.0 * n as f64) as u32).min(n - 1)
+}
+
+fn encode_point(lat: f64, lon: f64) -> Vec<u8> {
+    [lat.to_le_bytes(), lon.to_le_bytes()].concat()
+}
+
+fn decode_point(buf: &[u8]) -> Result<(f64, f64)> {
+    if buf.len() != 2 * size_of::<f64>() {
+        return Err(CandyError::Corruption(format!("bad geo point {buf:?}")));
+    }
+    Ok((
+        f64::from_le_bytes(buf[..8].try_into().unwrap()),
+        f64::from_le_bytes(buf[8..].try_into().unwrap()),
+    ))
+}
+
+impl CandyStore {
+    fn make_geo_key(key: &[u8]) -> Vec<u8> {
+        let mut geo_key = Vec::with_capacity(key.len() + GEO_NAMESPACE.len());
+        geo_key.extend_from_slice(key);
+        geo_key.extend_from_slice(GEO_NAMESPACE);
+        geo_key
+    }
+
+    // the list key of a bucket is the level, the cell's coordinates, and the namespace
+    fn make_geo_bucket_key(level: usize, lat_cell: u32, lon_cell: u32) -> Vec<u8> {
+        let mut bucket_key = Vec::with_capacity(1 + 2 * size_of::<u32>() + GEO_NAMESPACE.len());
+        bucket_key.push(level as u8);
+        bucket_key.extend_from_slice(&lat_cell.to_le_bytes());
+        bucket_key.extend_from_slice(&lon_cell.to_le_bytes());
+        bucket_key.extend_from_slice(GEO_NAMESPACE);
+        bucket_key
+    }
+
+    fn geo_buckets(lat: f64, lon: f64) -> impl Iterator<Item = Vec<u8>> {
+        LEVEL_BITS.iter().enumerate().map(move |(level, &bits)| {
+            Self::make_geo_bucket_key(level, lat_cell(lat, bits), lon_cell(lon, bits))
+        })
+    }
+
+    /// Inserts (or moves) a point with the given key into the geospatial index. Points are indexed in buckets
+    /// of several sizes (lists keyed by the cell's coordinates), so that [Self::geo_query_radius] only scans
+    /// the buckets around the query's center.
+    ///
+    /// The geospatial index is a keyspace of its own, i.e., these keys are unrelated to the ones used by
+    /// [Self::set]. Returns the previous location of the point, if it existed
+    pub fn geo_insert<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        lat: f64,
+        lon: f64,
+    ) -> Result<Option<(f64, f64)>> {
+        let key = key.as_ref();
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(CandyError::InvalidArgument(format!(
+                "invalid coordinates ({lat}, {lon})"
+            )));
+        }
+        Self::ensure_sizes(key, &[])?;
+
+        // the point itself is written first, and queries ignore bucket entries that do not match it, so a
+        // crash in the middle leaves (at most) stale bucket entries behind
+        let point = encode_point(lat, lon);
+        let prev = match self.set_raw(&Self::make_geo_key(key), &point)? {
+            crate::SetStatus::PrevValue(prev) => Some(decode_point(&prev)?),
+            crate::SetStatus::CreatedNew => None,
+        };
+        for bucket_key in Self::geo_buckets(lat, lon) {
+            self.owned_set_in_list(bucket_key, key.to_owned(), point.clone(), false)?;
+        }
+        if let Some((prev_lat, prev_lon)) = prev {
+            for (bucket_key, prev_bucket_key) in
+                Self::geo_buckets(lat, lon).zip(Self::geo_buckets(prev_lat, prev_lon))
+            {
+                if bucket_key != prev_bucket_key {
+                    self.owned_remove_from_list(prev_bucket_key, key.to_owned())?;
+                }
+            }
+        }
+        Ok(prev)
+    }
+
+    /// Gets the location of a point in the geospatial index
+    pub fn geo_get<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<(f64, f64)>> {
+        match self.get_raw(&Self::make_geo_key(key.as_ref()))? {
+            Some(point) => Ok(Some(decode_point(&point)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes a point from the geospatial index, returning its location if it existed
+    pub fn geo_remove<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<(f64, f64)>> {
+        let key = key.as_ref();
+        let Some(point) = self.remove_raw(&Self::make_geo_key(key))? else {
+            return Ok(None);
+        };
+        let (lat, lon) = decode_point(&point)?;
+        for bucket_key in Self::geo_buckets(lat, lon) {
+            self.owned_remove_from_list(bucket_key, key.to_owned())?;
+        }
+        Ok(Some((lat, lon)))
+    }
+
+    // returns the level and the cells (lat, lon) that cover the bounding box of the given circle
+    fn geo_covering_cells(lat: f64, lon: f64, radius: f64) -> (usize, Vec<(u32, u32)>) {
+        let dlat = (radius / EARTH_RADIUS_METERS).to_degrees();
+        let (min_lat, max_lat) = ((lat - dlat).max(-90.0), (lat + dlat).min(90.0));
+        let max_cos = min_lat.abs().max(max_lat.abs()).to_radians().cos();
+        // the longitude span grows towards the poles, and covers everything if the circle contains a pole
+        let dlon = if max_lat >= 90.0 || min_lat <= -90.0 || dlat / max_cos >= 180.0 {
+            180.0
+        } else {
+            dlat / max_cos
+        };
+
+        let mut level = 0;
+        let mut cells = vec![];
+        for (i, &bits) in LEVEL_BITS.iter().enumerate() {
+            let n = 1u32 << bits;
+            let lat_cells = lat_cell(min_lat, bits)..=lat_cell(max_lat, bits);
+            let lon_cells: Vec<u32> = if 2.0 * dlon + 360.0 / n as f64 >= 360.0 {
+                (0..n).collect()
+            } else {
+                // longitudes may wrap around the antimeridian
+                let first = lon_cell((lon - dlon + 540.0) % 360.0 - 180.0, bits);
+                let last = lon_cell((lon + dlon + 540.0) % 360.0 - 180.0, bits);
+                let count = (last + n - first) % n + 1;
+                (0..count).map(|j| (first + j) % n).collect()
+            };
+            if i > 0 && lat_cells.clone().count() * lon_cells.len() > MAX_QUERY_CELLS {
+                break;
+            }
+            level = i;
+            cells = lat_cells
+                .flat_map(|la| lon_cells.iter().map(move |&lo| (la, lo)))
+                .collect();
+        }
+        (level, cells)
+    }
+
+    /// Returns all points within `radius` meters of the given center, sorted by their distance from it.
+    /// Only the buckets that overlap the search area are scanned, at the finest granularity that keeps their
+    /// number small
+    pub fn geo_query_radius(&self, lat: f64, lon: f64, radius: f64) -> Result<Vec<GeoMatch>> {
+        if !(-90.0..=90.0).contains(&lat)
+            || !(-180.0..=180.0).contains(&lon)
+            || radius.is_nan()
+            || radius < 0.0
+        {
+            return Err(CandyError::InvalidArgument(format!(
+                "invalid query ({lat}, {lon}, {radius})"
+            )));
+        }
+
+        let (level, cells) = Self::geo_covering_cells(lat, lon, radius);
+        let mut matches = vec![];
+        for (lat_cell, lon_cell) in cells {
+            for res in self.owned_iter_list(Self::make_geo_bucket_key(level, lat_cell, lon_cell)) {
+                let (key, point) = res?;
+                let (p_lat, p_lon) = decode_point(&point)?;
+                let distance = haversine_distance(lat, lon, p_lat, p_lon);
+                if distance > radius {
+                    continue;
+                }
+                // skip stale entries, left by a crash in the middle of moving the point
+                if self.get_raw(&Self::make_geo_key(&key))?.as_deref() != Some(&point[..]) {
+                    continue;
+                }
+                matches.push(GeoMatch {
+                    key,
+                    lat: p_lat,
+                    lon: p_lon,
+                    distance,
+                });
+            }
+        }
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        Ok(matches)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{store::GRAPH_NAMESPACE, CandyStore, Result};
+
+/// An undirected graph, stored as an adjacency list (a [CandyStore] list) per node. Every edge is recorded in
+/// the adjacency lists of both of its nodes, which [CandyGraph] keeps in sync.
+///
+/// Multiple graphs can live in the same store, as long as they have different names. Note that the two
+/// directions of an edge are updated one after the other, so a crash (or a concurrent operation on the same
+/// edge) may leave an edge recorded in just one direction. [Self::add_edge] and [Self::remove_edge] are
+/// idempotent, so simply repeating the operation fixes it
+#[derive(Clone)]
+pub struct CandyGraph {
+    store: CandyStore,
+    name: Vec<u8>,
+}
+
+impl CandyGraph {
+    /// Constructs a [CandyGraph] with the given name over an existing [CandyStore]
+    pub fn new<B: AsRef<[u8]> + ?Sized>(store: CandyStore, name: &B) -> Self {
+        Self {
+            store,
+            name: name.as_ref().to_owned(),
+        }
+    }
+
+    // the graph's name goes at the end (followed by its length), so adjacency lists of different graphs
+    // never collide
+    fn make_adjacency_key(&self, node: &[u8]) -> Vec<u8> {
+        let mut list_key = Vec::with_capacity(
+            node.len() + self.name.len() + size_of::<u32>() + GRAPH_NAMESPACE.len(),
+        );
+        list_key.extend_from_slice(node);
+        list_key.extend_from_slice(&self.name);
+        list_key.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        list_key.extend_from_slice(GRAPH_NAMESPACE);
+        list_key
+    }
+
+    /// Adds an (undirected) edge between `node1` and `node2`, returning true if the edge was newly added or
+    /// false if it already existed. Nodes exist implicitly as long as they have edges
+    pub fn add_edge<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        node1: &B1,
+        node2: &B2,
+    ) -> Result<bool> {
+        let (node1, node2) = (node1.as_ref(), node2.as_ref());
+        let created = self
+            .store
+            .owned_set_in_list(
+                self.make_adjacency_key(node1),
+                node2.to_owned(),
+                vec![],
+                false,
+            )?
+            .was_created();
+        if node1 != node2 {
+            self.store.owned_set_in_list(
+                self.make_adjacency_key(node2),
+                node1.to_owned(),
+                vec![],
+                false,
+            )?;
+        }
+        Ok(created)
+    }
+
+    /// Removes the edge between `node1` and `node2`, returning true if it existed
+    pub fn remove_edge<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        node1: &B1,
+        node2: &B2,
+    ) -> Result<bool> {
+        let (node1, node2) = (node1.as_ref(), node2.as_ref());
+        let removed = self
+            .store
+            .owned_remove_from_list(self.make_adjacency_key(node1), node2.to_owned())?
+            .is_some();
+        if node1 != node2 {
+            self.store
+                .owned_remove_from_list(self.make_adjacency_key(node2), node1.to_owned())?;
+        }
+        Ok(removed)
+    }
+
+    /// Tests whether there's an edge between `node1` and `node2`
+    pub fn has_edge<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        node1: &B1,
+        node2: &B2,
+    ) -> Result<bool> {
+        Ok(self
+            .store
+            .owned_get_from_list(
+                self.make_adjacency_key(node1.as_ref()),
+                node2.as_ref().to_owned(),
+            )?
+            .is_some())
+    }
+
+    /// Iterates over the neighbors of the given node (in the order in which the edges were added)
+    pub fn neighbors<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        node: &B,
+    ) -> impl Iterator<Item = Result<Vec<u8>>> + '_ {
+        self.store
+            .owned_iter_list(self.make_adjacency_key(node.as_ref()))
+            .map(|res| res.map(|(neighbor, _)| neighbor))
+    }
+
+    /// Returns the number of edges of the given node
+    pub fn degree<B: AsRef<[u8]> + ?Sized>(&self, node: &B) -> Result<usize> {
+        self.store
+            .owned_list_len(self.make_adjacency_key(node.as_ref()))
+    }
+
+    /// Removes all the edges of the given node, returning their number
+    pub fn remove_node<B: AsRef<[u8]> + ?Sized>(&self, node: &B) -> Result<usize> {
+        let node = node.as_ref();
+        let adjacency_key = self.make_adjacency_key(node);
+        let mut num_edges = 0;
+        for res in self.store.owned_iter_list(adjacency_key.clone()) {
+            let (neighbor, _) = res?;
+            if neighbor != node {
+                self.store
+                    .owned_remove_from_list(self.make_adjacency_key(&neighbor), node.to_owned())?;
+            }
+            num_edges += 1;
+        }
+        self.store.owned_discard_list(adjacency_key)?;
+        Ok(num_edges)
+    }
+}
@@ -1,4 +1,9 @@
-use siphasher::sip128::{Hash128, SipHasher24};
+use std::hash::Hasher;
+
+use siphasher::{
+    sip::SipHasher13,
+    sip128::{Hash128, SipHasher24},
+};
 
 use crate::shard::NUM_ROWS;
 
@@ -79,6 +84,18 @@ impl PartedHash {
     }
 }
 
+// the digest of a value, which (unlike PartedHash) does not depend on the store's seed, so it can be compared
+// across stores. The hasher may be fed the value in chunks
+pub(crate) fn value_checksum_hasher() -> SipHasher13 {
+    SipHasher13::new()
+}
+
+pub(crate) fn value_checksum(val: &[u8]) -> u64 {
+    let mut hasher = value_checksum_hasher();
+    hasher.write(val);
+    hasher.finish()
+}
+
 #[test]
 fn test_parted_hash() -> crate::Result<()> {
     use bytemuck::{bytes_of, from_bytes};
@@ -0,0 +1,89 @@
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{CandyStore, Result};
+
+/// The health of the store, see [CandyStore::health]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// true if nothing is wrong, i.e., `degraded_reasons` is empty
+    pub ok: bool,
+    /// human-readable descriptions of what is wrong, e.g., the store ran out of disk space and is read-only
+    pub degraded_reasons: Vec<String>,
+    /// the time of the last successful sync of a shard file to disk (see [CandyStore::flush]), or `None` if
+    /// nothing was synced since the store was opened
+    pub last_fsync: Option<SystemTime>,
+    /// the number of shards that are being compacted or are waiting for a background split
+    pub pending_maintenance: usize,
+    /// the disk space available to the store's directory, in bytes, or `None` if it could not be queried.
+    /// This is a hint, other processes may consume the space at any time
+    pub disk_free_hint: Option<u64>,
+}
+
+fn disk_free_bytes(dir_path: &Path) -> Option<u64> {
+    let path = CString::new(dir_path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+impl CandyStore {
+    /// Reports the health of the store, e.g., for liveness and readiness probes of orchestrators. This only
+    /// reads in-memory state, plus a `statvfs` of the store's directory, so it is cheap enough to be called
+    /// every second. It does wait for shard splits that are in progress (like all operations do), so a probe
+    /// with a short timeout fails while a split blocks the store.
+    ///
+    /// The store is reported as degraded if it is read-only because it ran out of disk space (see
+    /// [Self::is_degraded]), if a journaled transaction (of a write batch or of a list operation, see
+    /// [crate::Config::list_journal]) failed to commit or roll back, so it's left for the next open to recover,
+    /// or if the free disk space is smaller than a single shard, so that the next split or compaction may run
+    /// out of space
+    pub fn health(&self) -> Result<Health> {
+        let pending_maintenance = self
+            .0
+            .root
+            .call_on_all_shards(|sh| Ok(sh.has_pending_maintenance()))?
+            .into_iter()
+            .filter(|&pending| pending)
+            .count();
+        let disk_free_hint = disk_free_bytes(&self.0.config.dir_path.get());
+        let last_fsync = match self.0.stats.last_fsync_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        };
+
+        let mut degraded_reasons = vec![];
+        if self.is_degraded() {
+            degraded_reasons.push("ran out of disk space, the store is read-only".into());
+        }
+        let unfinished_txns = self.num_unfinished_txns();
+        if unfinished_txns > 0 {
+            degraded_reasons.push(format!(
+                "{unfinished_txns} journaled transactions failed to commit or roll back, they are recovered when the store is reopened"
+            ));
+        }
+        if let Some(free) = disk_free_hint {
+            if free < self.0.config.max_shard_size as u64 {
+                degraded_reasons.push(format!(
+                    "only {free} bytes of disk space are free, less than a shard ({})",
+                    self.0.config.max_shard_size
+                ));
+            }
+        }
+
+        Ok(Health {
+            ok: degraded_reasons.is_empty(),
+            degraded_reasons,
+            last_fsync,
+            pending_maintenance,
+            disk_free_hint,
+        })
+    }
+}
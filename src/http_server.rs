@@ -0,0 +1,440 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    server::{read_line, serve_connections},
+    CandyError, CandyStore, Result, MAX_VALUE_SIZE,
+};
+
+// the most headers a request may have
+const MAX_HEADERS: usize = 64;
+
+/// Controls [CandyStore::serve_http]. Debug-printing it redacts [Self::auth_tokens]
+#[derive(Clone)]
+pub struct HttpServerParams {
+    /// the bearer tokens that clients may authenticate with (`Authorization: Bearer <token>`). If empty,
+    /// requests are not authenticated
+    pub auth_tokens: Vec<String>,
+    /// the number of connections that are served at once, past which new connections are refused (with 503)
+    pub max_connections: usize,
+    /// the read and write timeout of connections: a connection that sends nothing (e.g., an idle keep-alive
+    /// connection) or does not read its responses for this long is closed. Must not be zero, `None` disables
+    /// the timeouts
+    pub io_timeout: Option<Duration>,
+    /// the most items that `GET /lists/<list>` returns at once (clients may ask for fewer with `?limit=`), so
+    /// that a large list is not built into a single response. Must not be zero
+    pub max_list_page: usize,
+}
+
+impl std::fmt::Debug for HttpServerParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpServerParams")
+            .field("auth_tokens", &vec!["<redacted>"; self.auth_tokens.len()])
+            .field("max_connections", &self.max_connections)
+            .field("io_timeout", &self.io_timeout)
+            .field("max_list_page", &self.max_list_page)
+            .finish()
+    }
+}
+
+impl Default for HttpServerParams {
+    fn default() -> Self {
+        Self {
+            auth_tokens: vec![],
+            max_connections: 256,
+            io_timeout: Some(Duration::from_secs(60)),
+            max_list_page: 1000,
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: vec![],
+        }
+    }
+
+    fn bytes(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/octet-stream",
+            body,
+        }
+    }
+
+    fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn text(status: u16, msg: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: msg.into().into_bytes(),
+        }
+    }
+
+    fn found_or_404(val: Option<Vec<u8>>) -> Self {
+        match val {
+            Some(val) => Self::bytes(val),
+            None => Self::new(404),
+        }
+    }
+
+    fn write_to(&self, w: &mut impl Write, keep_alive: bool) -> std::io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        write!(
+            w,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            if keep_alive { "keep-alive" } else { "close" }
+        )?;
+        w.write_all(&self.body)?;
+        w.flush()
+    }
+}
+
+fn bad_request(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+// reads a line of the request's head, which must be UTF-8
+fn read_head_line(r: &mut impl BufRead) -> std::io::Result<Option<String>> {
+    read_line(r)?
+        .map(|line| String::from_utf8(line).map_err(|_| bad_request("request is not UTF-8")))
+        .transpose()
+}
+
+// returns None when the connection is closed before a request begins. the lines of the head and the number of
+// headers are bounded, so that a client cannot make the server buffer arbitrarily much
+fn read_request(r: &mut impl BufRead) -> std::io::Result<Option<Request>> {
+    let Some(line) = read_head_line(r)? else {
+        return Ok(None);
+    };
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(bad_request("malformed request line"));
+    };
+    let mut req = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        headers: vec![],
+        body: vec![],
+    };
+
+    loop {
+        let Some(line) = read_head_line(r)? else {
+            return Err(bad_request("unexpected end of headers"));
+        };
+        if line.is_empty() {
+            break;
+        }
+        if req.headers.len() == MAX_HEADERS {
+            return Err(bad_request("too many headers"));
+        }
+        let Some((k, v)) = line.split_once(':') else {
+            return Err(bad_request("malformed header"));
+        };
+        req.headers.push((k.trim().to_owned(), v.trim().to_owned()));
+    }
+
+    let content_len = match req.header("content-length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| bad_request("bad content-length"))?,
+        None => 0,
+    };
+    // we do not want to buffer arbitrarily large bodies (values are bounded anyway)
+    if content_len > MAX_VALUE_SIZE {
+        return Err(bad_request("body too large"));
+    }
+    r.by_ref()
+        .take(content_len as u64)
+        .read_to_end(&mut req.body)?;
+    if req.body.len() != content_len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(req))
+}
+
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// compares the token in time that depends only on the lengths, so that timing does not reveal how much of it
+// matched
+fn token_matches(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// returns the value of the given query parameter, if it appears
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+// a page of the list's items, starting at the logical index `cursor`. the response carries the cursor of the
+// next page, or null after the last one
+fn list_page(store: &CandyStore, list_key: &[u8], cursor: u64, limit: usize) -> Result<Response> {
+    let mut json = String::from("{\"items\":[");
+    let mut next_cursor = None;
+    for (i, res) in store.iter_list_from_idx(list_key, cursor).enumerate() {
+        let (idx, k, v) = res?;
+        if i == limit {
+            next_cursor = Some(idx);
+            break;
+        }
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&format!(
+            "{{\"key\":\"{}\",\"value\":\"{}\"}}",
+            base64(&k),
+            base64(&v)
+        ));
+    }
+    match next_cursor {
+        Some(idx) => json.push_str(&format!("],\"next_cursor\":{idx}}}")),
+        None => json.push_str("],\"next_cursor\":null}"),
+    }
+    Ok(Response::json(json))
+}
+
+fn handle_request(
+    store: &CandyStore,
+    params: &HttpServerParams,
+    req: &Request,
+) -> Result<Response> {
+    let (path, query) = req.path.split_once('?').unwrap_or((&req.path, ""));
+    let segments = path
+        .trim_start_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Option<Vec<_>>>();
+    let Some(segments) = segments else {
+        return Ok(Response::text(400, "bad percent-encoding"));
+    };
+    let segments = segments.iter().map(|s| &s[..]).collect::<Vec<_>>();
+
+    let resp = match (req.method.as_str(), &segments[..]) {
+        ("GET", [b"kv", key]) => Response::found_or_404(store.get(key)?),
+        ("PUT", [b"kv", key]) => {
+            store.set(key, &req.body)?;
+            Response::new(204)
+        }
+        ("DELETE", [b"kv", key]) => Response::found_or_404(store.remove(key)?),
+        ("GET", [b"lists", list_key]) => {
+            let limit = match query_param(query, "limit").map(str::parse::<usize>) {
+                None => params.max_list_page,
+                Some(Ok(limit)) if limit > 0 => limit.min(params.max_list_page),
+                Some(_) => return Ok(Response::text(400, "bad limit")),
+            };
+            let cursor = match query_param(query, "cursor").map(str::parse::<u64>) {
+                None => 0,
+                Some(Ok(cursor)) => cursor,
+                Some(Err(_)) => return Ok(Response::text(400, "bad cursor")),
+            };
+            list_page(store, list_key, cursor, limit)?
+        }
+        ("DELETE", [b"lists", list_key]) => {
+            if store.discard_list(list_key)? {
+                Response::new(204)
+            } else {
+                Response::new(404)
+            }
+        }
+        ("GET", [b"lists", list_key, item_key]) => {
+            Response::found_or_404(store.get_from_list(list_key, item_key)?)
+        }
+        ("PUT", [b"lists", list_key, item_key]) => {
+            store.set_in_list(list_key, item_key, &req.body)?;
+            Response::new(204)
+        }
+        ("DELETE", [b"lists", list_key, item_key]) => {
+            Response::found_or_404(store.remove_from_list(list_key, item_key)?)
+        }
+        (_, [b"kv", _] | [b"lists", _] | [b"lists", _, _]) => Response::new(405),
+        _ => Response::new(404),
+    };
+    Ok(resp)
+}
+
+fn handle_connection(
+    store: &CandyStore,
+    params: &HttpServerParams,
+    stream: TcpStream,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let req = match read_request(&mut reader) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                return Response::text(400, e.to_string()).write_to(&mut writer, false);
+            }
+            Err(e) => return Err(e),
+        };
+        let keep_alive = !req
+            .header("connection")
+            .is_some_and(|c| c.eq_ignore_ascii_case("close"));
+
+        let authorized = params.auth_tokens.is_empty()
+            || req
+                .header("authorization")
+                .and_then(|auth| auth.strip_prefix("Bearer "))
+                .is_some_and(|token| {
+                    // all tokens are compared, so that timing does not reveal which one matched
+                    params
+                        .auth_tokens
+                        .iter()
+                        .fold(false, |found, t| found | token_matches(token, t))
+                });
+
+        let resp = if !authorized {
+            Response::new(401)
+        } else {
+            match handle_request(store, params, &req) {
+                Ok(resp) => resp,
+                Err(
+                    e @ (CandyError::KeyTooLong(_)
+                    | CandyError::ValueTooLong(_)
+                    | CandyError::InvalidArgument(_)),
+                ) => Response::text(400, e.to_string()),
+                Err(e) => Response::text(500, e.to_string()),
+            }
+        };
+        resp.write_to(&mut writer, keep_alive)?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+impl CandyStore {
+    /// Serves the store over a small HTTP API on the given listener, turning it into a lightweight network
+    /// key-value service. Each connection is handled by its own thread. The endpoints are:
+    /// * `GET`/`PUT`/`DELETE` `/kv/<key>` - get, set (the request body is the value) or remove a key
+    /// * `GET`/`PUT`/`DELETE` `/lists/<list>/<item>` - the same for list items
+    /// * `GET` `/lists/<list>` - a page of the list's items, as `{"items": [..], "next_cursor": ..}`, where
+    ///   items are `{"key": .., "value": ..}` objects (base64 encoded). Pages hold up to
+    ///   [HttpServerParams::max_list_page] items (`?limit=` asks for fewer), and the next page is fetched by
+    ///   passing `next_cursor` as `?cursor=`, until it is null. Like [CandyStore::iter_list_from_cursor],
+    ///   items that are moved to the tail (by compaction or promotion) may appear again, but none are skipped
+    /// * `DELETE` `/lists/<list>` - discards the list
+    ///
+    /// Keys in the path are percent-decoded, and values are returned as-is (`application/octet-stream`).
+    /// If [HttpServerParams::auth_tokens] is set, requests must carry one of the tokens, otherwise they fail
+    /// with 401. Note that there is no TLS, so tokens should only be used on trusted networks.
+    ///
+    /// The request line and headers are bounded in length and number, and bodies by [crate::MAX_VALUE_SIZE],
+    /// past which requests fail with 400. The number of connections and their timeouts are set by
+    /// [HttpServerParams].
+    ///
+    /// This function only returns if accepting connections fails
+    pub fn serve_http(&self, listener: TcpListener, params: HttpServerParams) -> Result<()> {
+        if params.max_list_page == 0 {
+            return Err(CandyError::InvalidArgument(
+                "max_list_page must not be zero".into(),
+            ));
+        }
+        let store = self.clone();
+        let (max_connections, io_timeout) = (params.max_connections, params.io_timeout);
+        let params = Arc::new(params);
+        serve_connections(
+            listener,
+            max_connections,
+            io_timeout,
+            |mut stream| {
+                _ = Response::new(503).write_to(&mut stream, false);
+            },
+            move |stream| {
+                _ = handle_connection(&store, &params, stream);
+            },
+        )
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+    store::{INTERNED_NAMESPACE, INTERN_TABLE_NAMESPACE, USER_NAMESPACE},
+    CandyError, CandyStore, Result,
+};
+
+// the entries of the interning table, which are distinguished by their first byte
+const KEY_TO_ID: u8 = b'k';
+const ID_TO_KEY: u8 = b'i';
+const NEXT_ID: u8 = b'n';
+
+// never allocated, so keys that were not interned yet map to an entry that does not exist
+const MISSING_ID: u64 = u64::MAX;
+
+/// Caches the ids of the interned keys that were looked up so far (see [crate::Config::intern_keys_longer_than])
+#[derive(Debug, Default)]
+pub(crate) struct KeyInterner {
+    ids: RwLock<HashMap<Vec<u8>, u64>>,
+    // serializes allocating ids, so a key is never assigned two ids
+    alloc_lock: Mutex<()>,
+}
+
+impl KeyInterner {
+    pub(crate) fn clear(&self) {
+        self.ids.write().clear();
+    }
+}
+
+fn table_key(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(body.len() + 2);
+    k.push(kind);
+    k.extend_from_slice(body);
+    k.extend_from_slice(INTERN_TABLE_NAMESPACE);
+    k
+}
+
+fn parse_id(bytes: &[u8]) -> Result<u64> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| CandyError::Corruption("bad interned key id".into()))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn interned_full_key(id: u64) -> Vec<u8> {
+    let mut k = id.to_le_bytes().to_vec();
+    k.extend_from_slice(INTERNED_NAMESPACE);
+    k
+}
+
+impl CandyStore {
+    fn should_intern(&self, key: &[u8]) -> bool {
+        self.0
+            .config
+            .intern_keys_longer_than
+            .is_some_and(|len| key.len() > len)
+    }
+
+    fn lookup_interned_id(&self, key: &[u8]) -> Result<Option<u64>> {
+        if let Some(id) = self.0.interner.ids.read().get(key) {
+            return Ok(Some(*id));
+        }
+        let Some(bytes) = self.get_raw(&table_key(KEY_TO_ID, key))? else {
+            return Ok(None);
+        };
+        let id = parse_id(&bytes)?;
+        self.0.interner.ids.write().insert(key.to_owned(), id);
+        Ok(Some(id))
+    }
+
+    fn intern_key(&self, key: &[u8]) -> Result<u64> {
+        if let Some(id) = self.lookup_interned_id(key)? {
+            return Ok(id);
+        }
+
+        let _guard = self.0.interner.alloc_lock.lock();
+        if let Some(id) = self.lookup_interned_id(key)? {
+            return Ok(id);
+        }
+        let next_id_key = table_key(NEXT_ID, &[]);
+        let id = match self.get_raw(&next_id_key)? {
+            Some(bytes) => parse_id(&bytes)?,
+            None => 0,
+        };
+        self.set_raw(&next_id_key, &(id + 1).to_le_bytes())?;
+        // the reverse mapping is written first, so a crash in between leaves (at most) an unused id
+        self.set_raw(&table_key(ID_TO_KEY, &id.to_le_bytes()), key)?;
+        self.set_raw(&table_key(KEY_TO_ID, key), &id.to_le_bytes())?;
+        self.0.interner.ids.write().insert(key.to_owned(), id);
+        Ok(id)
+    }
+
+    /// The full key of a user key, for operations that only access existing keys
+    pub(crate) fn make_user_key(&self, mut key: Vec<u8>) -> Result<Vec<u8>> {
+        if self.should_intern(&key) {
+            let id = self.lookup_interned_id(&key)?.unwrap_or(MISSING_ID);
+            return Ok(interned_full_key(id));
+        }
+        key.extend_from_slice(USER_NAMESPACE);
+        Ok(key)
+    }
+
+    /// Same as [Self::make_user_key], but interns the key if needed, for operations that may create it
+    pub(crate) fn make_user_key_for_write(&self, mut key: Vec<u8>) -> Result<Vec<u8>> {
+        if self.should_intern(&key) {
+            return Ok(interned_full_key(self.intern_key(&key)?));
+        }
+        key.extend_from_slice(USER_NAMESPACE);
+        Ok(key)
+    }
+
+    /// Returns the user key of an entry in the interned namespace (during iteration)
+    pub(crate) fn resolve_interned_key(&self, full_key: &[u8]) -> Result<Vec<u8>> {
+        let id = &full_key[..full_key.len() - INTERNED_NAMESPACE.len()];
+        self.get_raw(&table_key(ID_TO_KEY, id))?.ok_or_else(|| {
+            CandyError::Corruption(format!("interned key id {id:?} is missing its key"))
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{store::INVERTED_INDEX_NAMESPACE, CandyStore, Result};
+
+/// How the tokens of a query are combined, see [CandyInvertedIndex::query]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexQueryMode {
+    /// documents that contain all of the tokens
+    And,
+    /// documents that contain any of the tokens
+    Or,
+}
+
+const POSTINGS_KIND: u8 = 0;
+const DOCUMENT_KIND: u8 = 1;
+
+/// A lightweight inverted index (e.g., for looking up documents by tags or words), built on top of lists: every
+/// token has a posting list of the documents that contain it, and every document has a list of its tokens (so
+/// it can be re-indexed or removed). Queries are evaluated streamingly over the posting lists, without
+/// collecting them in memory.
+///
+/// Multiple indexes can live in the same store, as long as they have different names. Note that indexing a
+/// document updates several lists one after the other, so a crash in the middle may leave the document
+/// partially indexed. Indexing it again fixes that
+#[derive(Clone)]
+pub struct CandyInvertedIndex {
+    store: CandyStore,
+    name: Vec<u8>,
+}
+
+impl CandyInvertedIndex {
+    /// Constructs a [CandyInvertedIndex] with the given name over an existing [CandyStore]
+    pub fn new<B: AsRef<[u8]> + ?Sized>(store: CandyStore, name: &B) -> Self {
+        Self {
+            store,
+            name: name.as_ref().to_owned(),
+        }
+    }
+
+    fn make_list_key(&self, kind: u8, key: &[u8]) -> Vec<u8> {
+        let mut list_key = Vec::with_capacity(
+            key.len() + self.name.len() + size_of::<u32>() + 1 + INVERTED_INDEX_NAMESPACE.len(),
+        );
+        list_key.extend_from_slice(key);
+        list_key.extend_from_slice(&self.name);
+        list_key.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        list_key.push(kind);
+        list_key.extend_from_slice(INVERTED_INDEX_NAMESPACE);
+        list_key
+    }
+
+    /// Indexes (or re-indexes) the given document with the given tokens, replacing the tokens it was previously
+    /// indexed with. Duplicate tokens are ignored
+    pub fn index_document<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]>>(
+        &self,
+        doc_id: &B1,
+        tokens: &[B2],
+    ) -> Result<()> {
+        let doc_id = doc_id.as_ref();
+        let doc_key = self.make_list_key(DOCUMENT_KIND, doc_id);
+        let new_tokens: HashSet<&[u8]> = tokens.iter().map(|t| t.as_ref()).collect();
+
+        for res in self.store.owned_iter_list(doc_key.clone()) {
+            let (token, _) = res?;
+            if !new_tokens.contains(&token[..]) {
+                self.store.owned_remove_from_list(
+                    self.make_list_key(POSTINGS_KIND, &token),
+                    doc_id.to_owned(),
+                )?;
+                self.store.owned_remove_from_list(doc_key.clone(), token)?;
+            }
+        }
+
+        // the document's token list is updated first, so a partially-indexed document can always be removed
+        for token in tokens {
+            let token = token.as_ref();
+            self.store
+                .owned_set_in_list(doc_key.clone(), token.to_owned(), vec![], false)?;
+            self.store.owned_set_in_list(
+                self.make_list_key(POSTINGS_KIND, token),
+                doc_id.to_owned(),
+                vec![],
+                false,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Removes the given document from the index, returning true if it was indexed
+    pub fn remove_document<B: AsRef<[u8]> + ?Sized>(&self, doc_id: &B) -> Result<bool> {
+        let doc_id = doc_id.as_ref();
+        let doc_key = self.make_list_key(DOCUMENT_KIND, doc_id);
+        for res in self.store.owned_iter_list(doc_key.clone()) {
+            let (token, _) = res?;
+            self.store.owned_remove_from_list(
+                self.make_list_key(POSTINGS_KIND, &token),
+                doc_id.to_owned(),
+            )?;
+        }
+        self.store.owned_discard_list(doc_key)
+    }
+
+    /// Iterates over the tokens the given document is indexed with
+    pub fn document_tokens<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        doc_id: &B,
+    ) -> impl Iterator<Item = Result<Vec<u8>>> + '_ {
+        self.store
+            .owned_iter_list(self.make_list_key(DOCUMENT_KIND, doc_id.as_ref()))
+            .map(|res| res.map(|(token, _)| token))
+    }
+
+    /// Returns the number of documents that contain the given token
+    pub fn num_documents<B: AsRef<[u8]> + ?Sized>(&self, token: &B) -> Result<usize> {
+        self.store
+            .owned_list_len(self.make_list_key(POSTINGS_KIND, token.as_ref()))
+    }
+
+    /// Returns an iterator over the IDs of the documents that match the given tokens (each document is returned
+    /// once). With [IndexQueryMode::And], the shortest posting list is scanned and every document is looked up
+    /// in the rest, while with [IndexQueryMode::Or] the posting lists are scanned one after the other, skipping
+    /// documents that appear in previous lists. Either way, memory use does not depend on the number of results.
+    ///
+    /// An empty query matches no documents
+    pub fn query<B: AsRef<[u8]>>(
+        &self,
+        tokens: &[B],
+        mode: IndexQueryMode,
+    ) -> Result<Box<dyn Iterator<Item = Result<Vec<u8>>> + '_>> {
+        let mut seen = HashSet::new();
+        let mut postings = vec![];
+        for token in tokens {
+            let token = token.as_ref();
+            if seen.insert(token) {
+                postings.push(self.make_list_key(POSTINGS_KIND, token));
+            }
+        }
+
+        // checks whether the doc is in any (all) of the given posting lists
+        let store = &self.store;
+        let contained_in = move |doc_id: &[u8], postings: &[Vec<u8>], all: bool| -> Result<bool> {
+            for list_key in postings {
+                let found = store
+                    .owned_get_from_list(list_key.clone(), doc_id.to_owned())?
+                    .is_some();
+                if found != all {
+                    return Ok(found);
+                }
+            }
+            Ok(all)
+        };
+
+        match mode {
+            IndexQueryMode::And => {
+                let mut lens = Vec::with_capacity(postings.len());
+                for list_key in postings.iter() {
+                    lens.push(self.store.owned_list_len(list_key.clone())?);
+                }
+                let Some(shortest) = (0..postings.len()).min_by_key(|&i| lens[i]) else {
+                    return Ok(Box::new(std::iter::empty()));
+                };
+                let shortest = postings.swap_remove(shortest);
+                Ok(Box::new(self.store.owned_iter_list(shortest).filter_map(
+                    move |res| match res {
+                        Ok((doc_id, _)) => match contained_in(&doc_id, &postings, true) {
+                            Ok(true) => Some(Ok(doc_id)),
+                            Ok(false) => None,
+                            Err(e) => Some(Err(e)),
+                        },
+                        Err(e) => Some(Err(e)),
+                    },
+                )))
+            }
+            IndexQueryMode::Or => {
+                let postings = Arc::new(postings);
+                Ok(Box::new((0..postings.len()).flat_map(move |i| {
+                    let postings = postings.clone();
+                    self.store
+                        .owned_iter_list(postings[i].clone())
+                        .filter_map(move |res| match res {
+                            Ok((doc_id, _)) => match contained_in(&doc_id, &postings[..i], false) {
+                                Ok(false) => Some(Ok(doc_id)),
+                                Ok(true) => None,
+                                Err(e) => Some(Err(e)),
+                            },
+                            Err(e) => Some(Err(e)),
+                        })
+                })))
+            }
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fs::File,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use parking_lot::{ArcRwLockWriteGuard, Mutex, MutexGuard, RawRwLock, RwLock, RwLockReadGuard};
+
+use crate::{
+    hashing::PartedHash,
+    store::{INTERN_TABLE_NAMESPACE, JOURNAL_NAMESPACE},
+    CandyError, CandyStore, Result,
+};
+
+// the entries of the journal, which are distinguished by their first byte:
+// * the number of keyed lock slots whose transactions may need recovery (a single entry)
+const NUM_SLOTS: u8 = b'n';
+// * the transaction running under a keyed lock slot: its id and whether it was committed
+const TXN_HEADER: u8 = b'h';
+// * the full key that a transaction's record belongs to
+const RECORD_KEY: u8 = b'k';
+// * the value the key had before the transaction first wrote it (prefixed by whether it existed)
+const RECORD_VAL: u8 = b'v';
+
+// the slot of the transaction header of write batches, which are serialized by their own lock rather than by a
+// keyed lock
+const BATCH_SLOT: usize = usize::MAX;
+
+// a file that exists in the store's directory while the journal may hold transactions, so that stores that
+// never journal anything do not read from their shards on open
+const JOURNAL_MARKER_FILENAME: &str = "journal_in_use";
+
+// the number of locks that isolate write batches from other operations. keys are mapped to them by their hash, so
+// unrelated keys may share one
+const NUM_ISOLATION_LOCKS: usize = 1024;
+
+#[derive(Debug)]
+pub(crate) struct JournalState {
+    // serializes write batches, see CandyStore::write_batch
+    batch_lock: Mutex<()>,
+    // every access of a key holds the key's lock (shared) for the duration of the access, while a write batch
+    // holds the locks of the keys it writes (exclusively) until it's committed or rolled back
+    isolation_locks: Box<[Arc<RwLock<()>>]>,
+    // odd while a write batch is in flight. reads skip the isolation locks while it's even, and check that it did
+    // not change while they ran
+    batch_seq: AtomicU64,
+    marked: AtomicBool,
+    // the transactions that failed to commit or roll back, whose entries were left behind in the journal
+    unfinished_txns: AtomicU64,
+}
+
+impl Default for JournalState {
+    fn default() -> Self {
+        Self {
+            batch_lock: Mutex::new(()),
+            isolation_locks: (0..NUM_ISOLATION_LOCKS)
+                .map(|_| Arc::new(RwLock::new(())))
+                .collect(),
+            batch_seq: AtomicU64::new(0),
+            marked: AtomicBool::new(false),
+            unfinished_txns: AtomicU64::new(0),
+        }
+    }
+}
+
+// the transaction of the list operation running on this thread. list operations that lock further lists (e.g.,
+// moving items between lists) join the transaction of the outermost one
+struct ActiveTxn {
+    store_id: usize,
+    slot: usize,
+    txn_id: u64,
+    depth: usize,
+    header_written: bool,
+    journaled_keys: HashSet<Vec<u8>>,
+}
+
+// the locks held by the write batch running on this thread
+struct HeldBatchLocks {
+    store_id: usize,
+    list_slots: Vec<usize>,
+    isolation_locks: HashMap<usize, ArcRwLockWriteGuard<RawRwLock, ()>>,
+}
+
+thread_local! {
+    static ACTIVE_TXN: RefCell<Option<ActiveTxn>> = const { RefCell::new(None) };
+    static BATCH_LOCKS: RefCell<Option<HeldBatchLocks>> = const { RefCell::new(None) };
+    // set while accessing keys that are not isolated from write batches, see CandyStore::unisolated
+    static UNISOLATED: Cell<bool> = const { Cell::new(false) };
+}
+
+// keys that are accessed under internal locks, which write batches may need as well, are not isolated: the
+// journal itself and the table of interned keys
+fn is_isolated(full_key: &[u8]) -> bool {
+    !UNISOLATED.get()
+        && !full_key.ends_with(JOURNAL_NAMESPACE)
+        && !full_key.ends_with(INTERN_TABLE_NAMESPACE)
+}
+
+fn isolation_lock_index(ph: PartedHash) -> usize {
+    // the low bits of the signature select the keyed lock slots, so the high ones are used here
+    (ph.signature() >> 16) as usize % NUM_ISOLATION_LOCKS
+}
+
+fn header_key(slot: usize) -> Vec<u8> {
+    [
+        &[TXN_HEADER],
+        &(slot as u64).to_le_bytes()[..],
+        JOURNAL_NAMESPACE,
+    ]
+    .concat()
+}
+
+fn record_key(kind: u8, txn_id: u64, seq: u64) -> Vec<u8> {
+    [
+        &[kind],
+        &txn_id.to_le_bytes()[..],
+        &seq.to_le_bytes()[..],
+        JOURNAL_NAMESPACE,
+    ]
+    .concat()
+}
+
+fn header_val(txn_id: u64, committed: bool) -> Vec<u8> {
+    [&txn_id.to_le_bytes()[..], &[committed as u8]].concat()
+}
+
+/// The locks a write batch holds while it's applied: the lock that serializes batches, the locks of the lists
+/// it modifies (which its list operations do not take again) and the isolation locks of the keys it writes,
+/// which are taken as the keys are first written. They are all released when the guard is dropped, which must
+/// be after the batch's transaction ends
+pub(crate) struct BatchLockGuard<'a> {
+    _batch_guard: MutexGuard<'a, ()>,
+    _list_guards: Vec<MutexGuard<'a, ()>>,
+    batch_seq: &'a AtomicU64,
+}
+
+impl Drop for BatchLockGuard<'_> {
+    fn drop(&mut self) {
+        BATCH_LOCKS.with(|held| held.borrow_mut().take());
+        self.batch_seq.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The lock of a list (or queue), which journals the writes made while it is held when
+/// [crate::Config::list_journal] is set (and always for write batches). The transaction is committed when the
+/// guard is dropped, or rolled back if the thread is panicking. The lock itself is not held when the list's slot
+/// is already locked by the write batch running on this thread
+pub(crate) struct ListLockGuard<'a> {
+    guard: Option<MutexGuard<'a, ()>>,
+    store: &'a CandyStore,
+    journaled: bool,
+    rollback: bool,
+}
+
+impl<'a> ListLockGuard<'a> {
+    pub(crate) fn new(
+        store: &'a CandyStore,
+        guard: Option<MutexGuard<'a, ()>>,
+        slot: usize,
+    ) -> Self {
+        let journaled = store.0.config.list_journal && Self::begin_txn(store, slot);
+        Self {
+            guard,
+            store,
+            journaled,
+            rollback: false,
+        }
+    }
+
+    // the transaction of a write batch, whose writes are journaled regardless of the config. it runs under the
+    // batch's locks (see CandyStore::lock_batch), which are released after it ends
+    pub(crate) fn new_batch(store: &'a CandyStore, _locks: &BatchLockGuard<'a>) -> Self {
+        Self::begin_txn(store, BATCH_SLOT);
+        Self {
+            guard: None,
+            store,
+            journaled: true,
+            rollback: false,
+        }
+    }
+
+    // undoes the writes made under the lock, once it is released
+    pub(crate) fn roll_back(mut self) {
+        self.rollback = true;
+    }
+
+    // ends the transaction (committing it, unless it was rolled back) and returns the error that prevented it
+    // from finishing, which dropping the guard could only record
+    pub(crate) fn finish(mut self) -> Result<()> {
+        let journaled = std::mem::take(&mut self.journaled);
+        if journaled {
+            self.store.end_txn(self.rollback, false)?;
+        }
+        Ok(())
+    }
+
+    fn begin_txn(store: &CandyStore, slot: usize) -> bool {
+        ACTIVE_TXN.with(|active| {
+            let mut active = active.borrow_mut();
+            match &mut *active {
+                Some(txn) if txn.store_id == store.store_id() => {
+                    txn.depth += 1;
+                    true
+                }
+                // locking a list of another store within a transaction, which is left out of it
+                Some(_) => false,
+                None => {
+                    *active = Some(ActiveTxn {
+                        store_id: store.store_id(),
+                        slot,
+                        txn_id: rand::random(),
+                        depth: 1,
+                        header_written: false,
+                        journaled_keys: HashSet::new(),
+                    });
+                    true
+                }
+            }
+        })
+    }
+
+    // lets the threads waiting on the lock go first. the list is consistent at this point, so the outermost
+    // transaction is committed, and a new one begins
+    pub(crate) fn bump(&mut self) {
+        if self.journaled {
+            // the failure is recorded by end_txn, and the lock is bumped regardless
+            _ = self.store.end_txn(false, true);
+        }
+        if let Some(guard) = &mut self.guard {
+            MutexGuard::bump(guard);
+        }
+    }
+}
+
+impl Drop for ListLockGuard<'_> {
+    fn drop(&mut self) {
+        if self.journaled {
+            // the failure is recorded by end_txn
+            _ = self
+                .store
+                .end_txn(self.rollback || std::thread::panicking(), false);
+        }
+    }
+}
+
+impl CandyStore {
+    fn store_id(&self) -> usize {
+        Arc::as_ptr(&self.0.config) as usize
+    }
+
+    // serializes write batches and locks the given keyed lock slots (of the lists the batch modifies) for the
+    // whole batch. batches cannot run within another transaction, as they could not be rolled back on their own
+    pub(crate) fn lock_batch(&self, mut list_slots: Vec<usize>) -> Result<BatchLockGuard<'_>> {
+        if ACTIVE_TXN.with(|active| active.borrow().is_some()) {
+            return Err(CandyError::InvalidArgument(
+                "write batches cannot be applied from within list operations".into(),
+            ));
+        }
+        let batch_guard = self.0.journal.batch_lock.lock();
+        // slots are locked in order, like any batch would
+        list_slots.sort_unstable();
+        list_slots.dedup();
+        let list_guards = list_slots
+            .iter()
+            .map(|&slot| self.lock_list_slot(slot))
+            .collect();
+        BATCH_LOCKS.with(|held| {
+            *held.borrow_mut() = Some(HeldBatchLocks {
+                store_id: self.store_id(),
+                list_slots,
+                isolation_locks: HashMap::new(),
+            })
+        });
+        // batches are serialized, so this makes the sequence odd until the guard is dropped
+        self.0.journal.batch_seq.fetch_add(1, Ordering::SeqCst);
+        Ok(BatchLockGuard {
+            _batch_guard: batch_guard,
+            _list_guards: list_guards,
+            batch_seq: &self.0.journal.batch_seq,
+        })
+    }
+
+    // whether the given keyed lock slot is held by the write batch running on this thread
+    pub(crate) fn batch_holds_list_slot(&self, slot: usize) -> bool {
+        BATCH_LOCKS.with(|held| {
+            held.borrow().as_ref().is_some_and(|held| {
+                held.store_id == self.store_id() && held.list_slots.contains(&slot)
+            })
+        })
+    }
+
+    fn batch_holds_isolation_lock(&self, idx: usize) -> bool {
+        // the batch running on this thread keeps the sequence odd
+        if self.0.journal.batch_seq.load(Ordering::SeqCst) & 1 == 0 {
+            return false;
+        }
+        BATCH_LOCKS.with(|held| {
+            held.borrow().as_ref().is_some_and(|held| {
+                held.store_id == self.store_id() && held.isolation_locks.contains_key(&idx)
+            })
+        })
+    }
+
+    // locks the key (shared) for the duration of an access, so that it does not observe a write batch that is
+    // partly applied. the returned guard must be dropped before any other lock is taken
+    pub(crate) fn isolate(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+    ) -> Option<RwLockReadGuard<'_, ()>> {
+        if !is_isolated(full_key) {
+            return None;
+        }
+        let idx = isolation_lock_index(ph);
+        if self.batch_holds_isolation_lock(idx) {
+            return None;
+        }
+        Some(self.0.journal.isolation_locks[idx].read())
+    }
+
+    // same as isolate, but fails with DeadlineExceeded instead of waiting past the deadline
+    fn isolate_until(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        deadline: Instant,
+    ) -> Result<Option<RwLockReadGuard<'_, ()>>> {
+        if !is_isolated(full_key) {
+            return Ok(None);
+        }
+        let idx = isolation_lock_index(ph);
+        if self.batch_holds_isolation_lock(idx) {
+            return Ok(None);
+        }
+        match self.0.journal.isolation_locks[idx].try_read_until(deadline) {
+            Some(guard) => Ok(Some(guard)),
+            None => Err(CandyError::DeadlineExceeded),
+        }
+    }
+
+    // runs a read of the key, isolated from write batches. while no batch is in flight, the read takes no lock,
+    // and it is run again under the key's isolation lock if a batch began while it ran (and may have written the
+    // key). writes always take the lock, since a batch that begins must wait for them before journaling the key
+    pub(crate) fn isolated_read<T>(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        mut op: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        if let Some(res) = self.read_outside_batches(&mut op) {
+            return res;
+        }
+        let _isolation = self.isolate(ph, full_key);
+        op()
+    }
+
+    // same as isolated_read, but fails with DeadlineExceeded instead of waiting past the deadline
+    pub(crate) fn isolated_read_until<T>(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        deadline: Instant,
+        mut op: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        if let Some(res) = self.read_outside_batches(&mut op) {
+            return res;
+        }
+        let _isolation = self.isolate_until(ph, full_key, deadline)?;
+        op()
+    }
+
+    // runs `op` if no write batch is in flight, returning None if one was (or began while it ran)
+    fn read_outside_batches<T>(&self, op: &mut impl FnMut() -> T) -> Option<T> {
+        let batch_seq = &self.0.journal.batch_seq;
+        let seq = batch_seq.load(Ordering::SeqCst);
+        if seq & 1 != 0 {
+            return None;
+        }
+        let res = op();
+        (batch_seq.load(Ordering::SeqCst) == seq).then_some(res)
+    }
+
+    // runs `op` without isolating the keys it accesses from write batches. this is used for pinned list headers,
+    // which are accessed under their own locks and are not undone by batches anyway
+    pub(crate) fn unisolated<T>(&self, op: impl FnOnce() -> T) -> T {
+        let prev = UNISOLATED.replace(true);
+        let res = op();
+        UNISOLATED.set(prev);
+        res
+    }
+
+    // called by a write batch before its first write of a key, to hold the key's isolation lock until the batch
+    // ends
+    fn lock_for_batch(&self, full_key: &[u8]) {
+        if !is_isolated(full_key) {
+            return;
+        }
+        let idx = isolation_lock_index(PartedHash::new(&self.0.config.hash_seed, full_key));
+        let lock = &self.0.journal.isolation_locks[idx];
+        BATCH_LOCKS.with(|held| {
+            let mut held = held.borrow_mut();
+            let Some(held) = held
+                .as_mut()
+                .filter(|held| held.store_id == self.store_id())
+            else {
+                return;
+            };
+            // other threads hold the lock only while accessing a key, so this does not wait for long
+            held.isolation_locks
+                .entry(idx)
+                .or_insert_with(|| lock.write_arc());
+        });
+    }
+
+    // called before every write of a key, so that the key's current value is journaled if the write is the
+    // first one of the key in the running transaction (if any)
+    pub(crate) fn journal_before_write(&self, full_key: &[u8]) -> Result<()> {
+        if full_key.ends_with(JOURNAL_NAMESPACE) {
+            return Ok(());
+        }
+        let Some((slot, txn_id, seq, write_header)) = ACTIVE_TXN.with(|active| {
+            let mut active = active.borrow_mut();
+            let txn = active.as_mut()?;
+            if txn.store_id != self.store_id() || !txn.journaled_keys.insert(full_key.to_owned()) {
+                return None;
+            }
+            let write_header = !txn.header_written;
+            txn.header_written = true;
+            Some((
+                txn.slot,
+                txn.txn_id,
+                txn.journaled_keys.len() as u64 - 1,
+                write_header,
+            ))
+        }) else {
+            return Ok(());
+        };
+
+        if slot == BATCH_SLOT {
+            self.lock_for_batch(full_key);
+        }
+        if write_header {
+            self.mark_journal_in_use()?;
+            self.set_raw(&header_key(slot), &header_val(txn_id, false))?;
+        }
+        let prev = self.get_raw(full_key)?;
+        let mut val = vec![prev.is_some() as u8];
+        val.extend_from_slice(prev.as_deref().unwrap_or_default());
+        self.set_raw(&record_key(RECORD_VAL, txn_id, seq), &val)?;
+        // the key's record is written last, it marks the record as complete
+        self.set_raw(&record_key(RECORD_KEY, txn_id, seq), full_key)?;
+        Ok(())
+    }
+
+    fn mark_journal_in_use(&self) -> Result<()> {
+        if self.0.journal.marked.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        // the marker must be durable before the first transaction is
+        File::create(self.0.config.dir_path.join(JOURNAL_MARKER_FILENAME))?.sync_all()?;
+        File::open(self.0.config.dir_path.get())?.sync_all()?;
+        self.0.journal.marked.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // ends the running transaction, if this is its outermost lock. when `keep` is set, the lock remains held,
+    // and a new transaction begins under it
+    fn end_txn(&self, rollback: bool, keep: bool) -> Result<()> {
+        let Some((slot, txn_id, num_records, header_written)) = ACTIVE_TXN.with(|active| {
+            let mut active = active.borrow_mut();
+            let txn = active.as_mut()?;
+            if txn.depth > 1 {
+                if !keep {
+                    txn.depth -= 1;
+                }
+                return None;
+            }
+            let res = (
+                txn.slot,
+                txn.txn_id,
+                txn.journaled_keys.len() as u64,
+                txn.header_written,
+            );
+            if keep {
+                txn.txn_id = rand::random();
+                txn.header_written = false;
+                txn.journaled_keys.clear();
+            } else {
+                *active = None;
+            }
+            Some(res)
+        }) else {
+            return Ok(());
+        };
+
+        if !header_written {
+            return Ok(());
+        }
+        let res = if rollback {
+            self.rollback_txn(slot, txn_id, num_records)
+        } else {
+            self.commit_txn(slot, txn_id, num_records)
+        };
+        if res.is_err() {
+            // failing to finish the transaction leaves its entries behind, for the next open to recover. the
+            // store is reported as degraded until then, see CandyStore::health
+            self.0
+                .journal
+                .unfinished_txns
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+
+    // the number of transactions that failed to commit or roll back since the store was opened
+    pub(crate) fn num_unfinished_txns(&self) -> u64 {
+        self.0.journal.unfinished_txns.load(Ordering::Relaxed)
+    }
+
+    fn commit_txn(&self, slot: usize, txn_id: u64, num_records: u64) -> Result<()> {
+        // marking the transaction as committed is what makes it take effect. the records are then removed
+        // last-to-first, so a crash in between leaves a prefix of them, which the next open removes
+        self.set_raw(&header_key(slot), &header_val(txn_id, true))?;
+        self.remove_txn_records(slot, txn_id, num_records)
+    }
+
+    fn rollback_txn(&self, slot: usize, txn_id: u64, num_records: u64) -> Result<()> {
+        for seq in (0..num_records).rev() {
+            let Some(full_key) = self.get_raw(&record_key(RECORD_KEY, txn_id, seq))? else {
+                continue;
+            };
+            let Some(val) = self.get_raw(&record_key(RECORD_VAL, txn_id, seq))? else {
+                return Err(CandyError::Corruption(
+                    "journal record without a value".into(),
+                ));
+            };
+            match val.split_first() {
+                Some((1, prev)) => {
+                    self.set_raw(&full_key, prev)?;
+                }
+                Some((0, [])) => {
+                    self.remove_raw(&full_key)?;
+                }
+                _ => return Err(CandyError::Corruption("malformed journal record".into())),
+            }
+        }
+        self.remove_txn_records(slot, txn_id, num_records)
+    }
+
+    fn remove_txn_records(&self, slot: usize, txn_id: u64, num_records: u64) -> Result<()> {
+        // the value of a record whose key was not written yet may be left after the last record
+        self.remove_raw(&record_key(RECORD_VAL, txn_id, num_records))?;
+        for seq in (0..num_records).rev() {
+            self.remove_raw(&record_key(RECORD_KEY, txn_id, seq))?;
+            self.remove_raw(&record_key(RECORD_VAL, txn_id, seq))?;
+        }
+        self.remove_raw(&header_key(slot))?;
+        Ok(())
+    }
+
+    // called on open: rolls back the transactions that were torn by a crash, and finishes removing the
+    // entries of the committed ones
+    pub(crate) fn recover_journal(&self) -> Result<()> {
+        let marker_path = self.0.config.dir_path.join(JOURNAL_MARKER_FILENAME);
+        if !self.0.config.list_journal && !marker_path.exists() {
+            return Ok(());
+        }
+
+        let num_slots_key = [&[NUM_SLOTS], JOURNAL_NAMESPACE].concat();
+        let num_slots = match self.get_raw(&num_slots_key)? {
+            Some(v) => u64::from_le_bytes(
+                v.try_into()
+                    .map_err(|_| CandyError::Corruption("bad number of journal slots".into()))?,
+            ) as usize,
+            None => 0,
+        };
+
+        for slot in (0..num_slots).chain([BATCH_SLOT]) {
+            let Some(header) = self.get_raw(&header_key(slot))? else {
+                continue;
+            };
+            if header.len() != size_of::<u64>() + 1 {
+                return Err(CandyError::Corruption(
+                    "bad journal transaction header".into(),
+                ));
+            }
+            let txn_id = u64::from_le_bytes(header[..size_of::<u64>()].try_into().unwrap());
+            let mut num_records = 0;
+            while self
+                .get_raw(&record_key(RECORD_KEY, txn_id, num_records))?
+                .is_some()
+            {
+                num_records += 1;
+            }
+            if header[size_of::<u64>()] == 0 {
+                self.rollback_txn(slot, txn_id, num_records)?;
+            } else {
+                self.remove_txn_records(slot, txn_id, num_records)?;
+            }
+        }
+
+        if self.0.config.list_journal {
+            self.mark_journal_in_use()?;
+            self.set_raw(
+                &num_slots_key,
+                &(self.0.keyed_locks.len() as u64).to_le_bytes(),
+            )?;
+        } else {
+            // write batches mark the journal again when they are next applied
+            self.remove_raw(&num_slots_key)?;
+            std::fs::remove_file(marker_path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytemuck::{bytes_of, pod_read_unaligned, Pod, Zeroable};
+use parking_lot::RwLock;
+
+use crate::{
+    hashing::PartedHash, journal::ListLockGuard, store::KEY_HISTORY_NAMESPACE, CandyError,
+    CandyStore, Result,
+};
+
+// the entries of a key's history, which are distinguished by their first byte
+const HEADER: u8 = b'h';
+const VERSION: u8 = b'v';
+
+/// A past (or the current) value of a key whose history is kept, see [CandyStore::enable_key_history]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    /// the version number, which increases by one with every change of the key's value
+    pub version: u64,
+    /// wall-clock time of the change, in milliseconds since the epoch
+    pub timestamp_ms: u64,
+    /// the value the key was set to, or `None` if it was removed
+    pub value: Option<Vec<u8>>,
+}
+
+/// A point in a key's history, see [CandyStore::get_as_of]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// right after the given version was recorded (see [KeyVersion::version])
+    Version(u64),
+    /// the given wall-clock time, in milliseconds since the epoch (see [KeyVersion::timestamp_ms])
+    TimestampMs(u64),
+}
+
+// the range of versions the history of a key holds
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+#[repr(C)]
+struct KeyHistoryHeader {
+    head_version: u64, // inclusive
+    tail_version: u64, // exclusive
+}
+
+// the keys whose history is kept, mapped to the number of versions to keep. it is kept in memory so that keys
+// which are not tracked (the vast majority) pay nothing but a lookup
+#[derive(Default)]
+pub(crate) struct KeyHistories(RwLock<HashMap<Vec<u8>, usize>>);
+
+impl KeyHistories {
+    pub(crate) fn clear(&self) {
+        self.0.write().clear();
+    }
+
+    fn max_versions_of(&self, key: &[u8]) -> Option<usize> {
+        let histories = self.0.read();
+        if histories.is_empty() {
+            return None;
+        }
+        histories.get(key).copied()
+    }
+}
+
+// returns the (user) key that an entry of the history namespace belongs to, given the entry's key without
+// the namespace byte
+pub(crate) fn key_history_owner(body: &[u8]) -> Option<&[u8]> {
+    match *body.first()? {
+        HEADER => Some(&body[1..]),
+        VERSION if body.len() > size_of::<u64>() => Some(&body[1 + size_of::<u64>()..]),
+        _ => None,
+    }
+}
+
+impl CandyStore {
+    // the registry of tracked keys, mapping the keys to the number of versions to keep. it is only loaded on open
+    fn key_history_registry_key() -> Vec<u8> {
+        KEY_HISTORY_NAMESPACE.to_owned()
+    }
+
+    fn make_history_header_key(key: &[u8]) -> Vec<u8> {
+        [&[HEADER], key, KEY_HISTORY_NAMESPACE].concat()
+    }
+
+    fn make_history_version_key(key: &[u8], version: u64) -> Vec<u8> {
+        [
+            &[VERSION],
+            &version.to_le_bytes()[..],
+            key,
+            KEY_HISTORY_NAMESPACE,
+        ]
+        .concat()
+    }
+
+    // the history of a key is protected by a keyed lock, like lists are
+    // the slot of the lock that changes of the key take, if its history is kept (see Self::lock_batch)
+    pub(crate) fn key_history_lock_slot(&self, key: &[u8]) -> Option<usize> {
+        self.0.key_histories.max_versions_of(key)?;
+        let header_key = Self::make_history_header_key(key);
+        Some(self.keyed_lock_slot(PartedHash::new(&self.0.config.hash_seed, &header_key)))
+    }
+
+    fn lock_key_history(&self, key: &[u8]) -> ListLockGuard<'_> {
+        let header_key = Self::make_history_header_key(key);
+        self.lock_list(PartedHash::new(&self.0.config.hash_seed, &header_key))
+    }
+
+    fn get_history_header(&self, key: &[u8]) -> Result<KeyHistoryHeader> {
+        match self.get_raw(&Self::make_history_header_key(key))? {
+            Some(v) if v.len() == size_of::<KeyHistoryHeader>() => Ok(pod_read_unaligned(&v)),
+            Some(v) => Err(CandyError::Corruption(format!(
+                "key history header has wrong size {}",
+                v.len()
+            ))),
+            None => Ok(KeyHistoryHeader::default()),
+        }
+    }
+
+    fn get_key_version(&self, key: &[u8], version: u64) -> Result<Option<KeyVersion>> {
+        let Some(val) = self.get_raw(&Self::make_history_version_key(key, version))? else {
+            return Ok(None);
+        };
+        if val.len() < size_of::<u64>() + 1 {
+            return Err(CandyError::Corruption(format!(
+                "key version has wrong size {}",
+                val.len()
+            )));
+        }
+        Ok(Some(KeyVersion {
+            version,
+            timestamp_ms: u64::from_le_bytes(val[..size_of::<u64>()].try_into().unwrap()),
+            value: (val[size_of::<u64>()] != 0).then(|| val[size_of::<u64>() + 1..].to_vec()),
+        }))
+    }
+
+    pub(crate) fn load_key_histories(&self) -> Result<()> {
+        let mut histories = self.0.key_histories.0.write();
+        for res in self.owned_iter_list(Self::key_history_registry_key()) {
+            let (k, v) = res?;
+            let max_versions = v
+                .try_into()
+                .map_err(|_| CandyError::Corruption("bad key history limit".into()))?;
+            histories.insert(k, u64::from_le_bytes(max_versions) as usize);
+        }
+        Ok(())
+    }
+
+    // appends the key's current value to its history (unless it is the same as the last version), dropping the
+    // oldest versions beyond `max_versions`. the caller must hold the key's history lock
+    fn record_key_version(&self, key: &[u8], max_versions: usize) -> Result<()> {
+        let curr = self.get_raw(&self.make_user_key(key.to_owned())?)?;
+        let mut header = self.get_history_header(key)?;
+        let unchanged = header.tail_version > header.head_version
+            && self
+                .get_key_version(key, header.tail_version - 1)?
+                .is_some_and(|last| last.value == curr);
+        if !unchanged {
+            self.append_key_version(key, &mut header, curr)?;
+        }
+
+        // move the head first, so a crash leaves unreachable versions rather than missing ones
+        let old_head = header.head_version;
+        header.head_version = header
+            .tail_version
+            .saturating_sub(max_versions as u64)
+            .max(old_head);
+        if unchanged && header.head_version == old_head {
+            return Ok(());
+        }
+        self.set_raw(&Self::make_history_header_key(key), bytes_of(&header))?;
+        for version in old_head..header.head_version {
+            self.remove_raw(&Self::make_history_version_key(key, version))?;
+        }
+        Ok(())
+    }
+
+    fn append_key_version(
+        &self,
+        key: &[u8],
+        header: &mut KeyHistoryHeader,
+        curr: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let curr_len = curr.as_ref().map_or(0, |v| v.len());
+        let mut val = Vec::with_capacity(size_of::<u64>() + 1 + curr_len);
+        val.extend_from_slice(&timestamp_ms.to_le_bytes());
+        val.push(curr.is_some() as u8);
+        if let Some(curr) = curr {
+            val.extend_from_slice(&curr);
+        }
+        self.set_raw(
+            &Self::make_history_version_key(key, header.tail_version),
+            &val,
+        )?;
+        header.tail_version += 1;
+        Ok(())
+    }
+
+    // runs a write operation on a user key, recording the key's new value if its history is kept
+    pub(crate) fn with_key_history<K: AsRef<[u8]>, T>(
+        &self,
+        key: K,
+        op: impl FnOnce(K) -> Result<T>,
+    ) -> Result<T> {
+        let Some(max_versions) = self.0.key_histories.max_versions_of(key.as_ref()) else {
+            return op(key);
+        };
+        let key_copy = key.as_ref().to_owned();
+        let _guard = self.lock_key_history(&key_copy);
+        let res = op(key)?;
+        self.record_key_version(&key_copy, max_versions)?;
+        Ok(res)
+    }
+
+    /// Starts keeping the history of the given key: every change of its value made with [Self::set],
+    /// [Self::replace], [Self::remove], [Self::patch], [Self::get_or_create], [Self::update] or a
+    /// [crate::RawEntry] is recorded as a new version, of which the last `max_versions` are kept (see
+    /// [Self::get_history] and [Self::rollback]). The key's current value is recorded as the first version.
+    /// If the history is already kept, this changes the number of versions to keep.
+    ///
+    /// Keeping the history is persistent, it stays enabled across reopens until [Self::disable_key_history] is
+    /// called. Every change of the key costs a read and a few extra writes, while keys whose history is not
+    /// kept are not affected
+    pub fn enable_key_history<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        max_versions: usize,
+    ) -> Result<()> {
+        if max_versions == 0 {
+            return Err(CandyError::InvalidArgument(
+                "max_versions must be positive".into(),
+            ));
+        }
+        let key = key.as_ref();
+        // the registry is a list, so it must be updated without holding the key's history lock
+        self.owned_set_in_list(
+            Self::key_history_registry_key(),
+            key.to_owned(),
+            (max_versions as u64).to_le_bytes().to_vec(),
+            false,
+        )?;
+        let _guard = self.lock_key_history(key);
+        self.0
+            .key_histories
+            .0
+            .write()
+            .insert(key.to_owned(), max_versions);
+        self.record_key_version(key, max_versions)
+    }
+
+    /// Stops keeping the history of the given key and removes the recorded versions (the key itself is not
+    /// affected). Returns false if the history was not kept
+    pub fn disable_key_history<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<bool> {
+        let key = key.as_ref();
+        let was_kept = {
+            let _guard = self.lock_key_history(key);
+            let was_kept = self.0.key_histories.0.write().remove(key).is_some();
+            let header = self.get_history_header(key)?;
+            for version in header.head_version..header.tail_version {
+                self.remove_raw(&Self::make_history_version_key(key, version))?;
+            }
+            self.remove_raw(&Self::make_history_header_key(key))?;
+            was_kept
+        };
+        self.owned_remove_from_list(Self::key_history_registry_key(), key.to_owned())?;
+        Ok(was_kept)
+    }
+
+    /// Returns the recorded versions of the given key (see [Self::enable_key_history]), oldest first. The last
+    /// version is the key's current value
+    pub fn get_history<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Vec<KeyVersion>> {
+        let key = key.as_ref();
+        let _guard = self.lock_key_history(key);
+        let header = self.get_history_header(key)?;
+        let mut versions = Vec::with_capacity((header.tail_version - header.head_version) as usize);
+        for version in header.head_version..header.tail_version {
+            if let Some(v) = self.get_key_version(key, version)? {
+                versions.push(v);
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Returns the version of the given key that was in effect at the given point (see
+    /// [Self::enable_key_history]), e.g., to find out what a setting was at some past time. The value is
+    /// `None` if the key did not exist then.
+    ///
+    /// Returns `None` if the point is not covered by the kept versions: it precedes the oldest kept version
+    /// (or the history being enabled), or the key's history is not kept. A point past the last version
+    /// returns the key's current value
+    pub fn get_as_of<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        as_of: AsOf,
+    ) -> Result<Option<KeyVersion>> {
+        let key = key.as_ref();
+        let _guard = self.lock_key_history(key);
+        let header = self.get_history_header(key)?;
+        if header.tail_version == header.head_version {
+            return Ok(None);
+        }
+        match as_of {
+            AsOf::Version(version) => {
+                if version < header.head_version {
+                    return Ok(None);
+                }
+                self.get_key_version(key, version.min(header.tail_version - 1))
+            }
+            AsOf::TimestampMs(timestamp_ms) => {
+                // the latest version recorded no later than the given time. the history is usually short, so
+                // it is scanned backwards rather than searched
+                for version in (header.head_version..header.tail_version).rev() {
+                    if let Some(kv) = self.get_key_version(key, version)? {
+                        if kv.timestamp_ms <= timestamp_ms {
+                            return Ok(Some(kv));
+                        }
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Restores the value the given key had in `version` (see [Self::get_history]), removing the key if it
+    /// did not exist then. The restored value is recorded as a new version, so a rollback can be undone like
+    /// any other change. Returns false (without modifying the key) if the version is no longer kept
+    pub fn rollback<B: AsRef<[u8]> + ?Sized>(&self, key: &B, version: u64) -> Result<bool> {
+        let key = key.as_ref();
+        let Some(max_versions) = self.0.key_histories.max_versions_of(key) else {
+            return Ok(false);
+        };
+        let _guard = self.lock_key_history(key);
+        let header = self.get_history_header(key)?;
+        if !(header.head_version..header.tail_version).contains(&version) {
+            return Ok(false);
+        }
+        let Some(kv) = self.get_key_version(key, version)? else {
+            return Ok(false);
+        };
+        match kv.value {
+            Some(val) => {
+                self.set_raw(&self.make_user_key_for_write(key.to_owned())?, &val)?;
+            }
+            None => {
+                self.remove_raw(&self.make_user_key(key.to_owned())?)?;
+            }
+        }
+        self.record_key_version(key, max_versions)?;
+        Ok(true)
+    }
+}
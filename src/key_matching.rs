@@ -0,0 +1,224 @@
+#[cfg(feature = "regex")]
+use crate::CandyError;
+use crate::{shard::KVPair, store::CandyStoreIterator, CandyStore, Result};
+
+/// A pattern of user keys, see [CandyStore::iter_matching]
+#[derive(Debug, Clone)]
+pub enum KeyPattern {
+    /// A shell-style glob, matched against the whole key: `*` matches any sequence of bytes, `?` matches a
+    /// single byte, `[abc]` and `[a-z]` match a single byte of the set (`[!abc]` of its complement), and `\`
+    /// escapes the byte that follows it
+    Glob(Vec<u8>),
+    /// A regular expression, which matches keys that contain a match (use `^` and `$` to match whole keys)
+    #[cfg(feature = "regex")]
+    Regex(regex::bytes::Regex),
+}
+
+impl KeyPattern {
+    /// Constructs a [KeyPattern::Glob]
+    pub fn glob<B: AsRef<[u8]> + ?Sized>(pattern: &B) -> Self {
+        Self::Glob(pattern.as_ref().to_owned())
+    }
+
+    /// Constructs a [KeyPattern::Regex], failing with [CandyError::InvalidArgument] if the regex does not parse
+    #[cfg(feature = "regex")]
+    pub fn regex(pattern: &str) -> Result<Self> {
+        regex::bytes::Regex::new(pattern)
+            .map(Self::Regex)
+            .map_err(|e| CandyError::InvalidArgument(format!("bad regex: {e}")))
+    }
+
+    /// Returns true if the given key matches the pattern
+    pub fn is_match(&self, key: &[u8]) -> bool {
+        match self {
+            Self::Glob(pattern) => glob_match(pattern, key),
+            #[cfg(feature = "regex")]
+            Self::Regex(re) => re.is_match(key),
+        }
+    }
+
+    // the bytes that every matching key starts with
+    fn literal_prefix(&self) -> Vec<u8> {
+        match self {
+            Self::Glob(pattern) => {
+                let mut prefix = vec![];
+                let mut i = 0;
+                while i < pattern.len() {
+                    match pattern[i] {
+                        b'*' | b'?' | b'[' => break,
+                        b'\\' if i + 1 < pattern.len() => {
+                            prefix.push(pattern[i + 1]);
+                            i += 2;
+                        }
+                        b'\\' => break,
+                        c => {
+                            prefix.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                prefix
+            }
+            #[cfg(feature = "regex")]
+            Self::Regex(re) => {
+                // only anchored regexes made of plain characters up to their first metacharacter are narrowed,
+                // which is conservative but avoids parsing the regex
+                let pattern = re.as_str();
+                let Some(rest) = pattern.strip_prefix('^') else {
+                    return vec![];
+                };
+                if pattern.contains('|') {
+                    return vec![];
+                }
+                let mut prefix = String::new();
+                for c in rest.chars() {
+                    if c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '/' | ' ' | '@' | '#') {
+                        prefix.push(c);
+                        continue;
+                    }
+                    if matches!(c, '?' | '*' | '{') {
+                        // the last character is optional (or repeated)
+                        prefix.pop();
+                    }
+                    break;
+                }
+                prefix.into_bytes()
+            }
+        }
+    }
+}
+
+// matches the whole key, backtracking to the last `*` on a mismatch
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut backtrack = None;
+
+    while k < key.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p, k));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    k += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some((matched, len)) = match_class(&pattern[p..], key[k]) {
+                        if matched {
+                            p += len;
+                            k += 1;
+                            continue;
+                        }
+                    } else if key[k] == b'[' {
+                        // an unterminated class is a literal `[`
+                        p += 1;
+                        k += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == key[k] {
+                        p += 2;
+                        k += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == key[k] {
+                        p += 1;
+                        k += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        // mismatch: let the last `*` swallow one more byte
+        let Some((star_p, star_k)) = backtrack else {
+            return false;
+        };
+        backtrack = Some((star_p, star_k + 1));
+        p = star_p + 1;
+        k = star_k + 1;
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+// matches a byte against the class at the start of `pattern`, returning whether it matched and the length of
+// the class, or None if the class is unterminated
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &lo = pattern.get(i)?;
+        if lo == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&hi| hi != b']') {
+            let hi = pattern[i + 2];
+            matched |= lo <= c && c <= hi;
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+impl CandyStore {
+    /// Returns an iterator over the user keys (and their values) that match the given pattern, e.g., for admin
+    /// tooling. Keys are laid out by their hash, so the whole store is scanned, but only keys are read
+    /// while scanning: a key is first checked against the pattern's literal prefix (e.g., `user:` for
+    /// `user:*:name`), and the value is only read if the key matches
+    pub fn iter_matching<'a>(
+        &'a self,
+        pattern: &'a KeyPattern,
+    ) -> impl Iterator<Item = Result<KVPair>> + use<'a> {
+        let prefix = pattern.literal_prefix();
+        CandyStoreIterator::new(self, false, false).filter_map(move |res| {
+            let key = match res {
+                Ok((key, _)) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            if !key.starts_with(&prefix) || !pattern.is_match(&key) {
+                return None;
+            }
+            // the key may have been removed since it was scanned
+            match self.get(&key) {
+                Ok(Some(val)) => Some(Ok((key, val))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match(b"user:*", b"user:1"));
+    assert!(glob_match(b"user:*", b"user:"));
+    assert!(!glob_match(b"user:*", b"users"));
+    assert!(glob_match(b"*:name", b"user:1:name"));
+    assert!(glob_match(b"u?er:*:n*e", b"user:17:name"));
+    assert!(!glob_match(b"u?er", b"uer"));
+    assert!(glob_match(b"k[0-9][!a]", b"k7b"));
+    assert!(!glob_match(b"k[0-9][!a]", b"k7a"));
+    assert!(glob_match(b"a\\*b", b"a*b"));
+    assert!(!glob_match(b"a\\*b", b"axb"));
+    assert!(glob_match(b"a[b", b"a[b"));
+    assert!(glob_match(b"**a*", b"bab"));
+
+    assert_eq!(KeyPattern::glob("user:*:name").literal_prefix(), b"user:");
+    assert_eq!(KeyPattern::glob("a\\*b*").literal_prefix(), b"a*b");
+    assert_eq!(KeyPattern::glob("?x").literal_prefix(), b"");
+}
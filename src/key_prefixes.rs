@@ -0,0 +1,111 @@
+use siphasher::sip::SipHasher13;
+use std::hash::Hasher;
+
+use crate::{CandyError, Result};
+
+/// A dictionary of common key prefixes (see [crate::Config::key_prefixes]). Keys are stored in shard files as
+/// a single tag byte followed by the rest of the key: tag 0 means the key is stored as-is, and tag `i` means
+/// the key starts with the `i-1`th prefix, which is omitted
+#[derive(Debug)]
+pub(crate) struct KeyPrefixes {
+    prefixes: Vec<Vec<u8>>,
+    fingerprint: u64,
+}
+
+impl KeyPrefixes {
+    pub(crate) const MAX_PREFIXES: usize = u8::MAX as usize;
+
+    pub(crate) fn new(prefixes: &[Vec<u8>]) -> Result<Option<Self>> {
+        if prefixes.is_empty() {
+            return Ok(None);
+        }
+        if prefixes.len() > Self::MAX_PREFIXES {
+            return Err(CandyError::InvalidArgument(format!(
+                "too many key prefixes ({} > {})",
+                prefixes.len(),
+                Self::MAX_PREFIXES
+            )));
+        }
+        if prefixes.iter().any(|p| p.is_empty()) {
+            return Err(CandyError::InvalidArgument(
+                "key prefixes must not be empty".into(),
+            ));
+        }
+
+        let mut hasher = SipHasher13::new();
+        hasher.write_usize(prefixes.len());
+        for p in prefixes {
+            hasher.write_usize(p.len());
+            hasher.write(p);
+        }
+
+        Ok(Some(Self {
+            prefixes: prefixes.to_owned(),
+            // zero means "no prefixes" in the shard header
+            fingerprint: hasher.finish() | 1,
+        }))
+    }
+
+    pub(crate) fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub(crate) fn compress(&self, key: &[u8]) -> Vec<u8> {
+        let best = self
+            .prefixes
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| key.starts_with(p))
+            .max_by_key(|(_, p)| p.len());
+
+        match best {
+            Some((i, p)) => {
+                let mut stored = Vec::with_capacity(1 + key.len() - p.len());
+                stored.push(i as u8 + 1);
+                stored.extend_from_slice(&key[p.len()..]);
+                stored
+            }
+            None => {
+                let mut stored = Vec::with_capacity(1 + key.len());
+                stored.push(0);
+                stored.extend_from_slice(key);
+                stored
+            }
+        }
+    }
+
+    pub(crate) fn decompress(&self, stored: &[u8]) -> Result<Vec<u8>> {
+        let Some((&tag, rest)) = stored.split_first() else {
+            return Err(CandyError::Corruption("empty prefix-compressed key".into()));
+        };
+        if tag == 0 {
+            return Ok(rest.to_owned());
+        }
+        let Some(prefix) = self.prefixes.get(tag as usize - 1) else {
+            return Err(CandyError::Corruption(format!("bad key prefix tag {tag}")));
+        };
+        let mut key = Vec::with_capacity(prefix.len() + rest.len());
+        key.extend_from_slice(prefix);
+        key.extend_from_slice(rest);
+        Ok(key)
+    }
+}
+
+#[test]
+fn test_key_prefixes() -> Result<()> {
+    assert!(KeyPrefixes::new(&[])?.is_none());
+    assert!(KeyPrefixes::new(&[vec![]]).is_err());
+
+    let kp = KeyPrefixes::new(&[b"tenant/".to_vec(), b"tenant/acme/".to_vec()])?.unwrap();
+    assert_eq!(kp.compress(b"tenant/acme/user1"), b"\x02user1");
+    assert_eq!(kp.compress(b"tenant/other"), b"\x01other");
+    assert_eq!(kp.compress(b"foo"), b"\x00foo");
+    for key in [&b"tenant/acme/user1"[..], b"tenant/other", b"foo", b""] {
+        assert_eq!(kp.decompress(&kp.compress(key))?, key);
+    }
+    assert!(kp.decompress(b"\x03xxx").is_err());
+
+    let kp2 = KeyPrefixes::new(&[b"tenant/".to_vec()])?.unwrap();
+    assert_ne!(kp.fingerprint(), kp2.fingerprint());
+    Ok(())
+}
@@ -1,6 +1,6 @@
 //! A fast (*blazingly*, of course), persistent, in-process key-value store that relies on a novel sharding
-//! algorithm. Since Candy does not rely on log-structured merge (LSM) trees or B-Trees, no journal/WAL is needed
-//! and IOs go directly to file.
+//! algorithm. Since Candy does not rely on log-structured merge (LSM) trees or B-Trees, single-key operations
+//! need no journal/WAL and IOs go directly to file.
 //!
 //! The algorithm can be thought of as a "zero-overhead" extension to a hash table that's stored over files,
 //! as it's designed to minimizes disk IO operations. Most operations add an overhead of 1-2 microseconds
@@ -9,6 +9,12 @@
 //! The algorithm, for the most part, is crash-safe. That is, you can crash at any point and still be in a consistent
 //! state. You might lose the ongoing operation, but we consider this acceptable.
 //!
+//! Operations that write several keys are the exception. They use a journal, which records the previous value of
+//! every key before it's first written, so that the operations torn by a crash are rolled back the next time the
+//! store is opened. Write batches (see [CandyStore::write_batch]) are always journaled, and so are list and queue
+//! operations when [Config::list_journal] is set. Otherwise, a crash in the middle of a multi-key list operation
+//! (e.g., [CandyStore::compact_list_if_needed]) may leave the list torn, see [Config::list_recovery].
+//!
 //! Candy is designed to consume very little memory: entries are written directly to the shard-file, and only a
 //! table of ~380KB is kept `mmap`-ed (it is also file-backed, so can be evicted if needed). A shard-file can
 //! hold around 30K entries, and more shard-files are created as needed.
@@ -43,31 +49,168 @@
 //! }
 //! ```
 
+mod blobs;
+mod cached;
+mod cancellation;
+mod checksums;
+mod collisions;
+mod compression;
+mod dedup;
+mod diff;
+mod ephemeral;
+mod expiry;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod generation;
+mod geo;
+mod graph;
 mod hashing;
+mod health;
+#[cfg(feature = "server")]
+mod http_server;
+mod interning;
+mod inverted_index;
+mod journal;
+mod key_history;
+mod key_matching;
+mod key_prefixes;
+mod list_audit;
+mod list_recovery;
+mod list_transfer;
 mod lists;
+#[cfg(feature = "metrics")]
+mod lock_metrics;
+mod maintenance;
+mod namespaces;
+mod numeric_index;
+mod pinning;
+mod pop_tokens;
+mod presets;
+mod progress;
+#[cfg(feature = "metrics")]
+mod queue_metrics;
 mod queues;
+mod raw_entry;
+mod recording;
+#[cfg(feature = "redis_import")]
+mod redis_import;
+mod relocation;
+mod replay;
 mod router;
+#[cfg(feature = "server")]
+mod server;
+mod sessions;
 mod shard;
+mod sharded_queue;
+mod signing;
+mod soft_limits;
+mod sst;
 mod stats;
 mod store;
+mod traits;
+mod type_registry;
 mod typed;
+mod validation;
+mod write_batch;
 
+pub use blobs::BlobId;
+pub use cached::{CachedStore, WritePolicy};
+pub use cancellation::CancellationToken;
+pub use checksums::ChecksumReport;
+pub use collisions::{CollisionKind, CollisionRecord};
+pub use compression::CompressionStats;
+pub use diff::{DiffEntry, DiffParams, DiffValue};
+pub use ephemeral::EphemeralGuard;
+pub use geo::GeoMatch;
+pub use graph::CandyGraph;
 pub use hashing::HashSeed;
-pub use lists::{ListCompactionParams, ListIterator};
-pub use stats::Stats;
+pub use health::Health;
+#[cfg(feature = "server")]
+pub use http_server::HttpServerParams;
+#[cfg(feature = "server")]
+pub use server::RespServerParams;
+pub use inverted_index::{CandyInvertedIndex, IndexQueryMode};
+pub use key_history::{AsOf, KeyVersion};
+pub use key_matching::KeyPattern;
+pub use list_audit::{ListAuditOp, ListAuditRecord};
+pub use list_recovery::{ListRecoveryPolicy, ListRecoveryReport};
+#[cfg(feature = "metrics")]
+pub use lock_metrics::{ContendedLock, LockContention};
+#[cfg(feature = "metrics")]
+pub use queue_metrics::{QueueMetrics, QUEUE_RATE_WINDOW};
+pub use lists::{
+    CursorLag, DryRunReport, ListCompactionParams, ListFilteredIterator, ListIndexedIterator, ListItemMeta,
+    ListIterator,
+};
+pub use maintenance::MaintenanceObserver;
+pub use namespaces::{DroppedNamespace, Namespace, NamespaceStats};
+pub use presets::Profile;
+pub use progress::Progress;
+pub use raw_entry::RawEntry;
+pub use recording::{MemoryStore, RecordedOp, RecordingStore, StoreOp};
+#[cfg(feature = "redis_import")]
+pub use redis_import::{RedisImportParams, RedisImportStats};
+pub use replay::{read_workload, replay_workload, write_workload, ReplayParams, ReplayStats};
+pub use sharded_queue::{CandyShardedQueue, ShardingStrategy};
+pub use soft_limits::{SoftLimitObserver, SoftLimitWarning, SoftLimits};
+pub use sst::{ExportFilter, SstParams};
+pub use stats::{KeyedLockStats, Stats, WriteAmplification};
 pub use store::{CandyStore, GetOrCreateStatus, ReplaceStatus, SetStatus};
-pub use typed::{CandyTypedDeque, CandyTypedKey, CandyTypedList, CandyTypedStore};
+pub use traits::{BoxedKvIterator, KvStore, ListStore};
+pub use typed::{
+    CandyKeyPrefix, CandyTypedDeque, CandyTypedKey, CandyTypedList, CandyTypedStore, DedupWindow,
+};
+pub use validation::ConfigReport;
+pub use write_batch::WriteBatch;
 
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+};
 
 #[cfg(feature = "whitebox_testing")]
 pub use hashing::HASH_BITS_TO_KEEP;
+#[cfg(feature = "whitebox_testing")]
+pub use shard::WRITES_BEFORE_DISK_FULL;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// The errors returned by CandyStore. Match on the variants to handle specific failure kinds
+#[derive(Debug)]
 pub enum CandyError {
     KeyTooLong(usize),
     ValueTooLong(usize),
     EntryCannotFitInShard(usize, usize),
+    PatchOutOfBounds(usize, usize, usize),
+    PatchLengthMismatch(usize, usize),
+    /// an IO error while accessing the store's files
+    Io(std::io::Error),
+    /// a shard file (or an internal entry) is malformed
+    Corruption(String),
+    /// a shard file was created by an unsupported version of the library (see
+    /// [Config::clear_on_unsupported_version])
+    WrongVersion(PathBuf, u64),
+    /// the store's directory is locked by another process
+    Busy(String),
+    /// a typed key or value could not be deserialized
+    DecodeError(String),
+    /// a typed value was written by a wrapper with a different value type (see [Config::strict_typed_values])
+    TypeMismatch(String),
+    InvalidArgument(String),
+    /// the operation could not acquire the store's internal locks before its deadline, e.g., because the shard
+    /// is being split (see [CandyStore::get_with_deadline])
+    DeadlineExceeded,
+    /// the operation was aborted through its [CancellationToken]
+    Cancelled,
+    /// the store ran out of disk space and was switched to read-only mode, see [DiskFullPolicy::ReadOnly]
+    StoreFull,
+    /// a read-modify-write kept conflicting with concurrent modifications of the key, and gave up after the
+    /// given number of attempts (see [CandyStore::update])
+    TooManyRetries(usize),
+    /// an export's signature is missing or does not match its contents, see [Config::export_signing_key]
+    BadSignature(String),
+    /// an internal failure, e.g., a compaction thread terminated unexpectedly
+    Internal(String),
+    /// an error produced by user code (e.g., a callback passed to the store)
+    Other(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl Display for CandyError {
@@ -78,16 +221,94 @@ impl Display for CandyError {
             Self::EntryCannotFitInShard(sz, max) => {
                 write!(f, "entry too big ({sz}) for a single shard file ({max})")
             }
+            Self::PatchOutOfBounds(offset, len, vlen) => {
+                write!(
+                    f,
+                    "patch of {len} bytes at offset {offset} exceeds value length {vlen}"
+                )
+            }
+            Self::PatchLengthMismatch(expected, len) => {
+                write!(
+                    f,
+                    "expected bytes ({expected}) and patch ({len}) differ in length"
+                )
+            }
+            Self::Io(e) => write!(f, "IO error: {e}"),
+            Self::Corruption(msg) => write!(f, "corruption: {msg}"),
+            Self::WrongVersion(path, version) => {
+                write!(f, "{path:?} has unsupported version 0x{version:016x}")
+            }
+            Self::Busy(msg) => write!(f, "store is busy: {msg}"),
+            Self::DecodeError(msg) => write!(f, "decoding failed: {msg}"),
+            Self::TypeMismatch(msg) => write!(f, "type mismatch: {msg}"),
+            Self::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+            Self::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::StoreFull => write!(f, "store is full (out of disk space) and read-only"),
+            Self::TooManyRetries(attempts) => {
+                write!(f, "gave up after {attempts} conflicting attempts")
+            }
+            Self::BadSignature(msg) => write!(f, "bad signature: {msg}"),
+            Self::Internal(msg) => write!(f, "internal error: {msg}"),
+            Self::Other(e) => write!(f, "{e}"),
         }
     }
 }
 
-impl std::error::Error for CandyError {}
+impl std::error::Error for CandyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CandyError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<crossbeam_channel::RecvError> for CandyError {
+    fn from(e: crossbeam_channel::RecvError) -> Self {
+        Self::Internal(e.to_string())
+    }
+}
+
+impl<T> From<crossbeam_channel::SendError<T>> for CandyError {
+    fn from(e: crossbeam_channel::SendError<T>) -> Self {
+        Self::Internal(e.to_string())
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for CandyError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
+
+pub type Error = CandyError;
 
-pub type Result<T> = anyhow::Result<T>;
+pub type Result<T> = std::result::Result<T, CandyError>;
 
-/// The configuration options for CandyStore. Comes with sane defaults, feel free to use them
-#[derive(Debug, Clone)]
+/// What a store does when a write fails because the disk is full (ENOSPC), see [Config::disk_full_policy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiskFullPolicy {
+    /// fail the write with the IO error and keep going, so later writes are attempted (and may succeed once
+    /// space is freed)
+    #[default]
+    Fail,
+    /// switch the store to a read-only degraded mode: the write fails with [CandyError::StoreFull], as do all
+    /// subsequent modifications, while reads keep working. See [CandyStore::is_degraded]
+    ReadOnly,
+}
+
+/// The configuration options for CandyStore. Comes with sane defaults, feel free to use them. Debug-printing
+/// it redacts [Self::export_signing_key]
+#[derive(Clone)]
 pub struct Config {
     /// we don't want huge shards, because splitting would be expensive
     pub max_shard_size: u32,
@@ -97,7 +318,8 @@ pub struct Config {
     pub hash_seed: HashSeed,
     /// hint for creating number of shards accordingly)
     pub expected_number_of_keys: usize,
-    /// number of keyed locks for concurrent list ops
+    /// number of keyed locks for concurrent list ops (rounded up to a power of two, minimum 4). Lists and queues
+    /// are mapped to locks by their hash, see [CandyStore::keyed_lock_stats]
     pub max_concurrent_list_ops: u32,
     /// whether or not to truncate up shard files to their max size (spare files)
     pub truncate_up: bool,
@@ -105,14 +327,149 @@ pub struct Config {
     pub clear_on_unsupported_version: bool,
     /// whether or not to mlock the shard headers to RAM (POSIX only)
     pub mlock_headers: bool,
-    /// number of background compaction threads
+    /// number of background maintenance threads, which perform compactions and background splits
     pub num_compaction_threads: usize,
+    /// if set, shards are split in the background (by the maintenance threads) once their live data reaches
+    /// this fraction of `max_shard_size` (e.g., 0.8), so writers rarely have to split a full shard themselves.
+    /// Writers still split shards inline when they fill up before the background split is done
+    pub background_split_threshold: Option<f64>,
+    /// if set, the nice value of the maintenance threads (e.g., 10 to deprioritize them), on Linux. Unless the
+    /// process sets an explicit IO priority, Linux derives the threads' IO priority from it as well
+    pub maintenance_thread_nice: Option<i32>,
+    /// common key prefixes (e.g., tenant names) that are stored as a single byte in the shard files, which saves
+    /// space when keys share long prefixes (up to 255 prefixes, the longest matching one is used). This affects
+    /// all keys, including the internal keys of lists and queues, which end with the user's key.
+    ///
+    /// The prefixes must remain the same (same order) across opens of the store, since shards record which
+    /// prefixes they were written with. Existing shards are not rewritten when prefixes are first configured,
+    /// only new shards (created by splits and compactions) use them
+    pub key_prefixes: Vec<Vec<u8>>,
+    /// values of at least this size that are set with [CandyStore::set_dedup] are stored once per content,
+    /// smaller values are stored as-is
+    pub dedup_min_value_size: usize,
+    /// whether [CandyTypedStore] values are prefixed by a fingerprint of their type, which is verified on read,
+    /// so that two typed stores with the same key type but different value types return
+    /// [CandyError::TypeMismatch] instead of misinterpreting each other's values. The fingerprint is derived from
+    /// [std::any::type_name], so renaming or moving the value type is also reported as a mismatch.
+    ///
+    /// This changes the on-disk format of typed values, so it must be set when the store is created and never
+    /// changed afterwards (shards that have data record it, and fail to open otherwise)
+    pub strict_typed_values: bool,
+    /// whether list items keep metadata (their insertion time and a revision that is bumped on every update),
+    /// see [CandyStore::get_from_list_with_meta]. This adds 16 bytes to every list item, and changes their
+    /// on-disk format, so it must be set when the store is created and never changed afterwards (shards that
+    /// have data record it, and fail to open otherwise)
+    pub list_item_metadata: bool,
+    /// whether list items carry a small (u32) tag, see [CandyStore::set_in_list_tagged] and
+    /// [CandyStore::iter_list_by_tag]. This adds 4 bytes to every list item, and changes their on-disk format,
+    /// so it must be set when the store is created and never changed afterwards (shards that have data record
+    /// it, and fail to open otherwise)
+    pub list_item_tags: bool,
+    /// if set, keys of [CandyStore::set] and friends that are longer than this are interned: each such key is
+    /// stored once in an interning table, and its value is kept under a short (8 byte) id instead. This saves
+    /// space and hashing when a limited set of long keys is written over and over. Iteration maps the ids back
+    /// to the keys. The table only grows (removing a key keeps its id for when it is set again), so this is
+    /// not suitable for workloads with an unbounded number of distinct long keys.
+    ///
+    /// This changes where long keys are stored, so it must be set when the store is created and never
+    /// changed afterwards (shards that have data record it, and fail to open otherwise)
+    pub intern_keys_longer_than: Option<usize>,
+    /// if set, long loops that hold a list's keyed lock ([CandyStore::discard_list] and
+    /// [CandyStore::retain_in_list]) let the threads waiting on that lock go first every this many elements,
+    /// so discarding a huge list does not freeze every other list that maps to the same lock for seconds. The
+    /// list is left consistent whenever the lock is yielded, and may be modified by others in the meantime
+    /// (elements pushed meanwhile are not visited). Shard compactions already release their locks after every
+    /// row, while shard splits must hold the shard for their whole duration and never yield
+    pub yield_every: Option<usize>,
+    /// if set, the store is scanned on open for list operations that were torn by a crash (e.g., a chain
+    /// entry whose item was never written), which are handled according to the given policy. The outcome is
+    /// available through [CandyStore::list_recovery_report]. This reads every entry of the store, so it
+    /// makes opening large stores considerably slower
+    pub list_recovery: Option<ListRecoveryPolicy>,
+    /// if set, existing shard files are not opened (and mapped) when the store is opened, but on the first
+    /// access to each of them. This makes opening a store with many shards nearly instantaneous, which suits
+    /// short-lived processes (e.g., CLI tools) that only touch a few keys. Operations that visit all shards
+    /// (iteration, stats, flushing, etc.) open all of them, and a shard that fails to open (e.g., a corrupt
+    /// file) fails the operation that accessed it rather than the opening of the store
+    pub lazy_open: bool,
+    /// the number of bytes of each new shard file's data section to allocate on disk (`fallocate`) upfront
+    /// (capped at `max_shard_size`). Unlike [Self::truncate_up], which only sets the file's size (leaving a
+    /// sparse file), the space is actually reserved, which reduces fragmentation and makes running out of disk
+    /// space surface when the shard is created rather than in the middle of a write
+    pub preallocate_size: u64,
+    /// if nonzero, as a shard's data grows past the allocated part of its file, the file is allocated further
+    /// in increments of this many bytes (capped at `max_shard_size`). The allocation precedes the write, so a
+    /// full disk fails the operation before anything is written. See also [CandyStore::reserve]
+    pub preallocate_increment: u64,
+    /// what to do when a write runs out of disk space. Entries are only linked into the store once they were
+    /// written in full, so a failed write never leaves a half-written entry behind, but multi-step operations
+    /// (e.g., on lists) may be left incomplete
+    pub disk_full_policy: DiskFullPolicy,
+    /// if set, exports are signed with an HMAC-SHA256 under this key, and imports verify the signature before
+    /// writing anything, failing with [CandyError::BadSignature] if it is missing or does not match (e.g., the
+    /// artifact was tampered with or truncated). [CandyStore::export_list] appends the signature to the export,
+    /// while [CandyStore::export_sst] writes it to a `.sig` manifest next to the table (which must accompany
+    /// it). The key should be kept secret, and be the same on the exporting and importing stores
+    pub export_signing_key: Option<Vec<u8>>,
+    /// if nonzero, the last this many signature collisions (different keys whose 32 bit signatures are equal,
+    /// in the same row of a shard) are kept in memory, see [CandyStore::collision_log]. Collisions are always
+    /// counted (see [Stats::num_collisions] and friends). They never make the store return the wrong entry,
+    /// since the keys are always compared, but each costs an extra read. Note that the width of the signatures
+    /// is part of the shard file format, and cannot be configured
+    pub collision_log_capacity: usize,
+    /// values of entries in these namespaces are never compressed with the compression dictionary (see
+    /// `CandyStore::train_compression_dict`), e.g., [Namespace::User] if the values stored under it are already
+    /// compressed (like images), where trying to compress them only costs time. Entries written before the
+    /// namespace was listed keep their values as they were stored. See [CandyStore::compression_stats]
+    pub uncompressed_namespaces: Vec<Namespace>,
+    /// if set, checksums of the shard files are written when the store is closed (i.e., its last handle is
+    /// dropped), and verified when it is opened, failing with [CandyError::Corruption] if a shard file was
+    /// modified, truncated, added or removed in between. The outcome is available through
+    /// [CandyStore::checksum_report]. The used part of each file (its header and the data written so far) is
+    /// hashed sequentially, which is much faster than reading every entry, but still reads all of the data, on
+    /// close and on open. Nothing is verified after a crash, as the checksums are only written on a clean close
+    pub shard_checksums: bool,
+    /// thresholds below the hard limits (e.g., 80% of the disk space set aside for the store, or shards that
+    /// are about to split), past which the [SoftLimitObserver] registered with
+    /// [CandyStore::set_soft_limit_observer] is warned. All are unset by default
+    pub soft_limits: SoftLimits,
+    /// if set, the writes of every list and queue operation are journaled (the previous value of each key is
+    /// recorded before it is first written), and operations that were torn by a crash are rolled back when the
+    /// store is opened. This makes multi-key operations that are otherwise not crash-safe (e.g.,
+    /// [CandyStore::set_in_list_promoting], [CandyStore::compact_list_if_needed] and
+    /// [CandyStore::retain_in_list]) atomic with respect to crashes. Operations that yield their list's lock
+    /// (see [Self::yield_every]) are atomic between yields. Every key an operation writes costs a read and a
+    /// few extra writes, so this slows down list operations considerably. Pinned headers (see
+    /// [CandyStore::pin_list]) are kept in memory, and are not journaled
+    pub list_journal: bool,
     /// optionally delay modifying operations before for the given duration before flushing data to disk,
     /// to ensure reboot consistency
     #[cfg(feature = "flush_aggregation")]
     pub flush_aggregation_delay: Option<std::time::Duration>,
 }
 
+/// A partial configuration for [CandyStore::update_config], which changes the tunables of an open store. Fields
+/// that are set replace the store's current values, while the others are left as they are. The new values
+/// take effect on the next operation that consults them (e.g., `preallocate_size` applies to shard files
+/// created from then on)
+#[derive(Debug, Clone, Default)]
+pub struct ConfigUpdate {
+    /// see [Config::min_compaction_threashold]
+    pub min_compaction_threashold: Option<u32>,
+    /// see [Config::background_split_threshold]
+    pub background_split_threshold: Option<Option<f64>>,
+    /// see [Config::dedup_min_value_size]
+    pub dedup_min_value_size: Option<usize>,
+    /// see [Config::yield_every]
+    pub yield_every: Option<Option<usize>>,
+    /// see [Config::preallocate_size]
+    pub preallocate_size: Option<u64>,
+    /// see [Config::preallocate_increment]
+    pub preallocate_increment: Option<u64>,
+    /// see [Config::disk_full_policy]
+    pub disk_full_policy: Option<DiskFullPolicy>,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -125,12 +482,79 @@ impl Default for Config {
             clear_on_unsupported_version: false,
             mlock_headers: false,
             num_compaction_threads: 4,
+            background_split_threshold: None,
+            maintenance_thread_nice: None,
+            key_prefixes: vec![],
+            dedup_min_value_size: 1024,
+            strict_typed_values: false,
+            list_item_metadata: false,
+            list_item_tags: false,
+            intern_keys_longer_than: None,
+            yield_every: None,
+            list_recovery: None,
+            lazy_open: false,
+            preallocate_size: 0,
+            preallocate_increment: 0,
+            disk_full_policy: DiskFullPolicy::Fail,
+            export_signing_key: None,
+            collision_log_capacity: 0,
+            uncompressed_namespaces: vec![],
+            shard_checksums: false,
+            soft_limits: SoftLimits::default(),
+            list_journal: false,
             #[cfg(feature = "flush_aggregation")]
             flush_aggregation_delay: None,
         }
     }
 }
 
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("Config");
+        d.field("max_shard_size", &self.max_shard_size);
+        d.field("min_compaction_threashold", &self.min_compaction_threashold);
+        d.field("hash_seed", &self.hash_seed);
+        d.field("expected_number_of_keys", &self.expected_number_of_keys);
+        d.field("max_concurrent_list_ops", &self.max_concurrent_list_ops);
+        d.field("truncate_up", &self.truncate_up);
+        d.field(
+            "clear_on_unsupported_version",
+            &self.clear_on_unsupported_version,
+        );
+        d.field("mlock_headers", &self.mlock_headers);
+        d.field("num_compaction_threads", &self.num_compaction_threads);
+        d.field(
+            "background_split_threshold",
+            &self.background_split_threshold,
+        );
+        d.field("maintenance_thread_nice", &self.maintenance_thread_nice);
+        d.field("key_prefixes", &self.key_prefixes);
+        d.field("dedup_min_value_size", &self.dedup_min_value_size);
+        d.field("strict_typed_values", &self.strict_typed_values);
+        d.field("list_item_metadata", &self.list_item_metadata);
+        d.field("list_item_tags", &self.list_item_tags);
+        d.field("intern_keys_longer_than", &self.intern_keys_longer_than);
+        d.field("yield_every", &self.yield_every);
+        d.field("list_recovery", &self.list_recovery);
+        d.field("lazy_open", &self.lazy_open);
+        d.field("preallocate_size", &self.preallocate_size);
+        d.field("preallocate_increment", &self.preallocate_increment);
+        d.field("disk_full_policy", &self.disk_full_policy);
+        d.field(
+            "export_signing_key",
+            &self.export_signing_key.as_ref().map(|_| "<redacted>"),
+        );
+        d.field("collision_log_capacity", &self.collision_log_capacity);
+        d.field("uncompressed_namespaces", &self.uncompressed_namespaces);
+        d.field("shard_checksums", &self.shard_checksums);
+        d.field("soft_limits", &self.soft_limits);
+        d.field("list_journal", &self.list_journal);
+        #[cfg(feature = "flush_aggregation")]
+        d.field("flush_aggregation_delay", &self.flush_aggregation_delay);
+        d.finish()
+    }
+}
+
 pub(crate) const MAX_TOTAL_KEY_SIZE: usize = 0x3fff; // 14 bits
 pub(crate) const MAX_TOTAL_VALUE_SIZE: usize = 0xffff; // 16 bits
 pub(crate) const NAMESPACING_RESERVED_SIZE: usize = 0xff;
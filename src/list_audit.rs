@@ -0,0 +1,253 @@
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytemuck::{bytes_of, pod_read_unaligned, Pod, Zeroable};
+use parking_lot::RwLock;
+
+use crate::{hashing::PartedHash, store::LIST_AUDIT_NAMESPACE, CandyError, CandyStore, Result};
+
+/// A list operation recorded in a list's audit log, see [CandyStore::enable_list_audit]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListAuditOp {
+    /// a new item was pushed to the list (e.g., with [CandyStore::set_in_list])
+    Push,
+    /// the value of an existing item was updated
+    Update,
+    /// an item was popped from the head or tail
+    Pop,
+    /// an item was removed (with [CandyStore::remove_from_list] or [CandyStore::retain_in_list])
+    Remove,
+    /// the list was compacted, see [CandyStore::compact_list_if_needed]
+    Compact,
+    /// the list was discarded, see [CandyStore::discard_list]
+    Discard,
+    /// the list was reordered, see [CandyStore::sort_list_by]
+    Sort,
+}
+
+impl ListAuditOp {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Push => 1,
+            Self::Update => 2,
+            Self::Pop => 3,
+            Self::Remove => 4,
+            Self::Compact => 5,
+            Self::Discard => 6,
+            Self::Sort => 7,
+        }
+    }
+
+    fn from_byte(b: u8) -> Result<Self> {
+        Ok(match b {
+            1 => Self::Push,
+            2 => Self::Update,
+            3 => Self::Pop,
+            4 => Self::Remove,
+            5 => Self::Compact,
+            6 => Self::Discard,
+            7 => Self::Sort,
+            _ => return Err(CandyError::Corruption(format!("bad list audit op {b}"))),
+        })
+    }
+}
+
+/// A single entry of a list's audit log, see [CandyStore::list_audit_log]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListAuditRecord {
+    /// the record's sequence number, which increases by one with every record of the list
+    pub seq: u64,
+    /// wall-clock time of the operation, in milliseconds since the epoch
+    pub timestamp_ms: u64,
+    pub op: ListAuditOp,
+    /// the item the operation applied to (empty for [ListAuditOp::Compact], [ListAuditOp::Discard] and
+    /// [ListAuditOp::Sort])
+    pub item_key: Vec<u8>,
+}
+
+// the range of sequence numbers the audit log of a list holds
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+#[repr(C)]
+struct AuditLogHeader {
+    head_seq: u64, // inclusive
+    tail_seq: u64, // exclusive
+}
+
+// the hashes of the audited lists, kept in memory so that lists which are not audited (the vast majority) pay
+// nothing but a lookup
+#[derive(Default)]
+pub(crate) struct ListAudits(RwLock<HashSet<PartedHash>>);
+
+impl ListAudits {
+    pub(crate) fn clear(&self) {
+        self.0.write().clear();
+    }
+}
+
+impl CandyStore {
+    // the registry of audited lists, mapping list hashes to the (user) list keys. it is only loaded on open
+    fn list_audit_registry_key() -> Vec<u8> {
+        LIST_AUDIT_NAMESPACE.to_owned()
+    }
+
+    fn make_audit_header_key(list_ph: PartedHash) -> Vec<u8> {
+        [bytes_of(&list_ph), LIST_AUDIT_NAMESPACE].concat()
+    }
+
+    fn make_audit_record_key(list_ph: PartedHash, seq: u64) -> Vec<u8> {
+        [bytes_of(&list_ph), &seq.to_le_bytes(), LIST_AUDIT_NAMESPACE].concat()
+    }
+
+    fn get_audit_log_header(&self, list_ph: PartedHash) -> Result<AuditLogHeader> {
+        match self.get_raw(&Self::make_audit_header_key(list_ph))? {
+            Some(v) if v.len() == size_of::<AuditLogHeader>() => Ok(pod_read_unaligned(&v)),
+            Some(v) => Err(CandyError::Corruption(format!(
+                "list audit header has wrong size {}",
+                v.len()
+            ))),
+            None => Ok(AuditLogHeader::default()),
+        }
+    }
+
+    pub(crate) fn load_list_audits(&self) -> Result<()> {
+        let mut audited = self.0.list_audits.0.write();
+        for res in self.owned_iter_list(Self::list_audit_registry_key()) {
+            let (k, _) = res?;
+            if k.len() != size_of::<PartedHash>() {
+                return Err(CandyError::Corruption("bad audited list hash".into()));
+            }
+            audited.insert(pod_read_unaligned(&k));
+        }
+        Ok(())
+    }
+
+    // appends a record to the list's audit log, if the list is audited. the caller must hold the list's lock
+    pub(crate) fn audit_list_op(
+        &self,
+        list_ph: PartedHash,
+        op: ListAuditOp,
+        item_key: &[u8],
+    ) -> Result<()> {
+        if !self.0.list_audits.0.read().contains(&list_ph) {
+            return Ok(());
+        }
+        let mut header = self.get_audit_log_header(list_ph)?;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut val = Vec::with_capacity(size_of::<u64>() + 1 + item_key.len());
+        val.extend_from_slice(&timestamp_ms.to_le_bytes());
+        val.push(op.to_byte());
+        val.extend_from_slice(item_key);
+        self.set_raw(&Self::make_audit_record_key(list_ph, header.tail_seq), &val)?;
+        header.tail_seq += 1;
+        self.set_raw(&Self::make_audit_header_key(list_ph), bytes_of(&header))?;
+        Ok(())
+    }
+
+    /// Starts recording the operations on the given list (pushes, updates, pops, removals, compactions and
+    /// discards) in an append-only audit log, see [Self::list_audit_log]. Returns false if the list was already
+    /// audited. Auditing is persistent, it stays enabled across reopens until [Self::disable_list_audit] is
+    /// called.
+    ///
+    /// Every recorded operation costs two extra writes, and the log grows until it is trimmed with
+    /// [Self::trim_list_audit_log]. Lists that are not audited are not affected
+    pub fn enable_list_audit<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<bool> {
+        let list_key = list_key.as_ref();
+        let (list_ph, _) = self.make_list_key(list_key.to_owned());
+        // the registry is a list of its own, so it must be updated without holding the audited list's lock
+        self.owned_set_in_list(
+            Self::list_audit_registry_key(),
+            bytes_of(&list_ph).to_vec(),
+            list_key.to_owned(),
+            false,
+        )?;
+        let _guard = self.lock_list(list_ph);
+        Ok(self.0.list_audits.0.write().insert(list_ph))
+    }
+
+    /// Stops recording the operations on the given list and removes its audit log. Returns false if the list
+    /// was not audited
+    pub fn disable_list_audit<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<bool> {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        let was_audited = {
+            let _guard = self.lock_list(list_ph);
+            let was_audited = self.0.list_audits.0.write().remove(&list_ph);
+            let header = self.get_audit_log_header(list_ph)?;
+            for seq in header.head_seq..header.tail_seq {
+                self.remove_raw(&Self::make_audit_record_key(list_ph, seq))?;
+            }
+            self.remove_raw(&Self::make_audit_header_key(list_ph))?;
+            was_audited
+        };
+        self.owned_remove_from_list(Self::list_audit_registry_key(), bytes_of(&list_ph).to_vec())?;
+        Ok(was_audited)
+    }
+
+    /// Returns true if the operations on the given list are recorded, see [Self::enable_list_audit]
+    pub fn is_list_audited<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> bool {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        self.0.list_audits.0.read().contains(&list_ph)
+    }
+
+    /// Returns the audit log of the given list (see [Self::enable_list_audit]), oldest record first. The log
+    /// outlives the list itself, so it remains available after the list is emptied or discarded
+    pub fn list_audit_log<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+    ) -> Result<Vec<ListAuditRecord>> {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+        let header = self.get_audit_log_header(list_ph)?;
+        let mut records = Vec::with_capacity((header.tail_seq - header.head_seq) as usize);
+        for seq in header.head_seq..header.tail_seq {
+            let Some(val) = self.get_raw(&Self::make_audit_record_key(list_ph, seq))? else {
+                continue;
+            };
+            if val.len() < size_of::<u64>() + 1 {
+                return Err(CandyError::Corruption(format!(
+                    "list audit record has wrong size {}",
+                    val.len()
+                )));
+            }
+            records.push(ListAuditRecord {
+                seq,
+                timestamp_ms: u64::from_le_bytes(val[..size_of::<u64>()].try_into().unwrap()),
+                op: ListAuditOp::from_byte(val[size_of::<u64>()])?,
+                item_key: val[size_of::<u64>() + 1..].to_vec(),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Removes the oldest records of the given list's audit log, keeping the last `keep_last` ones. Returns the
+    /// number of records removed
+    pub fn trim_list_audit_log<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        keep_last: usize,
+    ) -> Result<usize> {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+        let mut header = self.get_audit_log_header(list_ph)?;
+        let new_head = header
+            .tail_seq
+            .saturating_sub(keep_last as u64)
+            .max(header.head_seq);
+        let num_trimmed = new_head - header.head_seq;
+        if num_trimmed == 0 {
+            return Ok(0);
+        }
+        // move the head first, so a crash leaves unreachable records rather than missing ones
+        let old_head = header.head_seq;
+        header.head_seq = new_head;
+        self.set_raw(&Self::make_audit_header_key(list_ph), bytes_of(&header))?;
+        for seq in old_head..new_head {
+            self.remove_raw(&Self::make_audit_record_key(list_ph, seq))?;
+        }
+        Ok(num_trimmed as usize)
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use bytemuck::{bytes_of, pod_read_unaligned};
+
+use crate::{
+    hashing::PartedHash,
+    lists::{ChainKey, List},
+    store::{CHAIN_NAMESPACE, ITEM_NAMESPACE, LIST_NAMESPACE},
+    CandyStore, Result,
+};
+
+/// What to do with list operations that were torn by a crash, see [crate::Config::list_recovery]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListRecoveryPolicy {
+    /// complete the torn operations: removals are finished by removing their items. The value of a torn
+    /// insertion was never written, so insertions are always rolled back
+    RollForward,
+    /// undo the torn operations: insertions are removed and the items of removals are linked back into their
+    /// lists. If the list itself was removed (its last item was being removed), the removal is completed
+    /// instead, since the list's key cannot be recovered
+    RollBack,
+    /// only count the torn operations (see [CandyStore::list_recovery_report]), leaving them as they are
+    ReportOnly,
+}
+
+/// The torn list operations found when the store was opened, see [crate::Config::list_recovery]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListRecoveryReport {
+    /// the policy that was applied
+    pub policy: ListRecoveryPolicy,
+    /// chain entries without an item, left by insertions that crashed before writing the item
+    pub torn_inserts: usize,
+    /// items without a chain entry, left by removals (or compactions) that crashed before removing the item
+    pub torn_removes: usize,
+}
+
+impl ListRecoveryReport {
+    /// Returns true if no torn operations were found
+    pub fn is_clean(&self) -> bool {
+        self.torn_inserts == 0 && self.torn_removes == 0
+    }
+}
+
+impl CandyStore {
+    // scans all entries of the store, so it only runs on open, before the store is shared with other threads
+    pub(crate) fn recover_torn_list_ops(
+        &self,
+        policy: ListRecoveryPolicy,
+    ) -> Result<ListRecoveryReport> {
+        let chain_key_len = size_of::<ChainKey>();
+        let mut headers = HashMap::new();
+        let mut torn_inserts = vec![];
+        let mut torn_removes = vec![];
+
+        for res in self.iter_raw() {
+            let (k, v) = res?;
+            match k.last() {
+                Some(&CHAIN_NAMESPACE) if k.len() == chain_key_len => {
+                    let ck: ChainKey = pod_read_unaligned(&k);
+                    if self
+                        .get_from_list_at_index(ck.list_ph, ck.idx, false)?
+                        .is_none()
+                    {
+                        torn_inserts.push(ck);
+                    }
+                }
+                Some(&ns) if ns == ITEM_NAMESPACE[0] => {
+                    if k.len() < Self::LIST_KEY_SUFFIX_LEN || v.len() < size_of::<u64>() {
+                        continue;
+                    }
+                    let list_ph: PartedHash = pod_read_unaligned(
+                        &k[k.len() - Self::LIST_KEY_SUFFIX_LEN..k.len() - ITEM_NAMESPACE.len()],
+                    );
+                    let idx =
+                        u64::from_le_bytes(v[v.len() - size_of::<u64>()..].try_into().unwrap());
+                    let item_ph = PartedHash::new(&self.0.config.hash_seed, &k);
+                    let chain = self.get_raw(bytes_of(&ChainKey {
+                        list_ph,
+                        idx,
+                        namespace: CHAIN_NAMESPACE,
+                    }))?;
+                    if chain.as_deref() != Some(bytes_of(&item_ph)) {
+                        // the chain entry may belong to another item, which must not be unlinked
+                        torn_removes.push((list_ph, idx, item_ph, k, chain.is_none()));
+                    }
+                }
+                Some(&ns) if ns == LIST_NAMESPACE[0] => {
+                    headers.insert(PartedHash::new(&self.0.config.hash_seed, &k), k);
+                }
+                _ => {}
+            }
+        }
+
+        let report = ListRecoveryReport {
+            policy,
+            torn_inserts: torn_inserts.len(),
+            torn_removes: torn_removes.len(),
+        };
+        if policy == ListRecoveryPolicy::ReportOnly {
+            return Ok(report);
+        }
+
+        // the lists whose headers need to be recounted, along with the indices that were linked back into them
+        let mut affected = HashMap::<PartedHash, Vec<u64>>::new();
+        for ck in torn_inserts {
+            self.remove_raw(bytes_of(&ck))?;
+            affected.entry(ck.list_ph).or_default();
+        }
+        for (list_ph, idx, item_ph, item_key, chain_missing) in torn_removes {
+            if policy == ListRecoveryPolicy::RollBack
+                && chain_missing
+                && headers.contains_key(&list_ph)
+            {
+                self.set_raw(
+                    bytes_of(&ChainKey {
+                        list_ph,
+                        idx,
+                        namespace: CHAIN_NAMESPACE,
+                    }),
+                    bytes_of(&item_ph),
+                )?;
+                affected.entry(list_ph).or_default().push(idx);
+            } else {
+                self.remove_raw(&item_key)?;
+                affected.entry(list_ph).or_default();
+            }
+        }
+
+        for (list_ph, relinked) in affected {
+            if let Some(header_key) = headers.get(&list_ph) {
+                self.recount_list_header(list_ph, header_key, &relinked)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // the header may or may not have been updated before the crash, so recount it from the chain entries
+    fn recount_list_header(
+        &self,
+        list_ph: PartedHash,
+        header_key: &[u8],
+        relinked: &[u64],
+    ) -> Result<()> {
+        let Some(list_bytes) = self.get_header(header_key)? else {
+            return Ok(());
+        };
+        let mut list = List::parse(&list_bytes)?;
+        for &idx in relinked {
+            list.head_idx = list.head_idx.min(idx);
+            list.tail_idx = list.tail_idx.max(idx + 1);
+        }
+
+        let mut present = (list.head_idx..list.tail_idx).filter_map(|idx| {
+            match self.get_raw(bytes_of(&ChainKey {
+                list_ph,
+                idx,
+                namespace: CHAIN_NAMESPACE,
+            })) {
+                Ok(Some(_)) => Some(Ok(idx)),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }
+        });
+        let Some(first) = present.next().transpose()? else {
+            self.remove_header(header_key)?;
+            return Ok(());
+        };
+        let mut last = first;
+        let mut num_items = 1;
+        for idx in present {
+            last = idx?;
+            num_items += 1;
+        }
+
+        list.head_idx = first;
+        list.tail_idx = last + 1;
+        list.num_items = num_items;
+        self.set_header(header_key, bytes_of(&list))?;
+        Ok(())
+    }
+}
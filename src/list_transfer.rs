@@ -0,0 +1,164 @@
+use std::io::{Read, Write};
+
+use crate::{
+    signing::{self, Signer, SIGNATURE_LEN},
+    CandyError, CandyStore, Result, MAX_KEY_SIZE, MAX_VALUE_SIZE,
+};
+
+const LIST_EXPORT_MAGIC: &[u8; 8] = b"CandyLT1";
+// same as above, but the export ends with its signature, see Config::export_signing_key
+const SIGNED_LIST_EXPORT_MAGIC: &[u8; 8] = b"CandyLS1";
+
+// marks the end of the items, in place of an item key length. it is followed by the number of items, so that
+// a truncated export is detected
+const END_OF_ITEMS: u32 = u32::MAX;
+
+// writes an export, signing it along the way if a signing key is configured
+struct ExportWriter<W> {
+    writer: W,
+    signer: Option<Signer>,
+}
+
+impl<W: Write> ExportWriter<W> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if let Some(signer) = &mut self.signer {
+            signer.update(buf);
+        }
+        self.writer.write_all(buf)?;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, buf: &[u8]) -> Result<()> {
+        self.write_all(&(buf.len() as u32).to_le_bytes())?;
+        self.write_all(buf)
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(signer) = self.signer {
+            self.writer.write_all(&signer.finalize())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn read_chunk(reader: &mut impl Read, len: u32, max_len: usize) -> Result<Vec<u8>> {
+    if len as usize > max_len {
+        return Err(CandyError::Corruption(format!(
+            "bad list export chunk length {len}"
+        )));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+impl CandyStore {
+    /// Writes the given list (its key, and its items' keys and values, from head to tail) to `writer`, from
+    /// which [Self::import_list] can recreate it in another store, e.g., to migrate a single list (or a queue
+    /// built on a list) without draining it through the application. Returns the number of items written.
+    /// If [crate::Config::export_signing_key] is set, the export ends with its signature.
+    ///
+    /// The list's bounds are captured when the export begins (see [Self::iter_list_snapshot]), so items
+    /// pushed concurrently are not exported. Item metadata and tags are not exported
+    pub fn export_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        writer: impl Write,
+    ) -> Result<usize> {
+        let list_key = list_key.as_ref();
+        let mut writer = ExportWriter {
+            writer,
+            signer: self.0.config.export_signing_key.as_deref().map(Signer::new),
+        };
+        writer.write_all(if writer.signer.is_some() {
+            SIGNED_LIST_EXPORT_MAGIC
+        } else {
+            LIST_EXPORT_MAGIC
+        })?;
+        writer.write_chunk(list_key)?;
+        let mut count = 0u64;
+        for res in self.iter_list_snapshot(list_key)? {
+            let (k, v) = res?;
+            writer.write_chunk(&k)?;
+            writer.write_chunk(&v)?;
+            count += 1;
+        }
+        writer.write_all(&END_OF_ITEMS.to_le_bytes())?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.finish()?;
+        Ok(count as usize)
+    }
+
+    /// Recreates a list written by [Self::export_list], returning its key and the number of items imported.
+    /// The items are pushed to the list's tail in their original order, so importing into a new (or empty)
+    /// list reproduces the exported one; items whose keys already exist in the list are overwritten in place.
+    ///
+    /// If [crate::Config::export_signing_key] is set, the export must be signed with the same key: it is read
+    /// into memory and verified before anything is imported, failing with [CandyError::BadSignature]
+    /// otherwise.
+    ///
+    /// Note: this is not atomic, failing midway (e.g., on a truncated export) leaves the items imported so far
+    /// in the list
+    pub fn import_list(&self, mut reader: impl Read) -> Result<(Vec<u8>, usize)> {
+        let Some(key) = &self.0.config.export_signing_key else {
+            let mut magic = [0u8; 8];
+            reader.read_exact(&mut magic)?;
+            // without a key, the signature of a signed export (which follows the items) is not checked
+            if &magic != LIST_EXPORT_MAGIC && &magic != SIGNED_LIST_EXPORT_MAGIC {
+                return Err(CandyError::Corruption("not a list export".into()));
+            }
+            return self.import_list_items(reader);
+        };
+
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        if !buf.starts_with(SIGNED_LIST_EXPORT_MAGIC) {
+            return Err(CandyError::BadSignature(
+                "the list export is not signed".into(),
+            ));
+        }
+        if buf.len() < SIGNED_LIST_EXPORT_MAGIC.len() + SIGNATURE_LEN {
+            return Err(CandyError::BadSignature(
+                "the list export is truncated".into(),
+            ));
+        }
+        let (body, signature) = buf.split_at(buf.len() - SIGNATURE_LEN);
+        signing::verify(key, body, signature)?;
+        self.import_list_items(&body[SIGNED_LIST_EXPORT_MAGIC.len()..])
+    }
+
+    fn import_list_items(&self, mut reader: impl Read) -> Result<(Vec<u8>, usize)> {
+        let len = read_u32(&mut reader)?;
+        let list_key = read_chunk(&mut reader, len, MAX_KEY_SIZE)?;
+
+        let mut count = 0u64;
+        loop {
+            let len = read_u32(&mut reader)?;
+            if len == END_OF_ITEMS {
+                break;
+            }
+            let k = read_chunk(&mut reader, len, MAX_KEY_SIZE)?;
+            let len = read_u32(&mut reader)?;
+            let v = read_chunk(&mut reader, len, MAX_VALUE_SIZE)?;
+            self.owned_set_in_list(list_key.clone(), k, v, false)?;
+            count += 1;
+        }
+
+        let mut expected = [0u8; 8];
+        reader.read_exact(&mut expected)?;
+        let expected = u64::from_le_bytes(expected);
+        if count != expected {
+            return Err(CandyError::Corruption(format!(
+                "list export holds {count} items rather than {expected}"
+            )));
+        }
+        Ok((list_key, count as usize))
+    }
+}
@@ -1,21 +1,63 @@
-use std::ops::Range;
+use std::{
+    ops::Range,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
+    cancellation::CancellationToken,
+    collisions::{CollisionKind, CollisionRecord},
     hashing::PartedHash,
+    journal::ListLockGuard,
+    list_audit::ListAuditOp,
+    progress::Progress,
     shard::{InsertMode, KVPair},
-    store::{CHAIN_NAMESPACE, ITEM_NAMESPACE, LIST_NAMESPACE},
-    CandyStore, GetOrCreateStatus, ReplaceStatus, Result, SetStatus,
+    stats::KeyedLockStats,
+    store::{CHAIN_NAMESPACE, ITEM_NAMESPACE, LIST_CURSOR_NAMESPACE, LIST_NAMESPACE},
+    CandyError, CandyStore, GetOrCreateStatus, ReplaceStatus, Result, SetStatus,
 };
 
-use bytemuck::{bytes_of, from_bytes, Pod, Zeroable};
-use parking_lot::MutexGuard;
+use bytemuck::{bytes_of, from_bytes, pod_read_unaligned, Pod, Zeroable};
+use parking_lot::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A slot in the store's pool of keyed locks, which serialize operations on lists and queues. Lists are mapped to
+/// slots by their hash, so unrelated lists may share a slot
+#[derive(Default)]
+pub(crate) struct KeyedLock {
+    mutex: Mutex<()>,
+    num_acquisitions: AtomicUsize,
+    num_contended: AtomicUsize,
+    num_waiters: AtomicUsize,
+}
+
+impl KeyedLock {
+    // `on_wait` is called with the time the wait started, if the lock was contended
+    fn lock(&self, on_wait: impl FnOnce(Instant)) -> MutexGuard<'_, ()> {
+        self.num_acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = self.mutex.try_lock() {
+            return guard;
+        }
+        self.num_contended.fetch_add(1, Ordering::Relaxed);
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+        let t0 = Instant::now();
+        let guard = self.mutex.lock();
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+        on_wait(t0);
+        guard
+    }
+
+    fn reset_counters(&self) {
+        self.num_acquisitions.store(0, Ordering::Relaxed);
+        self.num_contended.store(0, Ordering::Relaxed);
+    }
+}
 
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
-struct List {
-    head_idx: u64, // inclusive
-    tail_idx: u64, // exclusive
-    num_items: u64,
+pub(crate) struct List {
+    pub(crate) head_idx: u64, // inclusive
+    pub(crate) tail_idx: u64, // exclusive
+    pub(crate) num_items: u64,
 }
 
 impl std::fmt::Debug for List {
@@ -38,14 +80,47 @@ impl List {
     fn is_empty(&self) -> bool {
         self.head_idx == self.tail_idx
     }
+
+    /// Parses a list header as stored in the store, rejecting malformed ones (e.g., from a partially-written
+    /// file) instead of panicking later on
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != size_of::<Self>() {
+            return Err(CandyError::Corruption(format!(
+                "list header has wrong size {}",
+                bytes.len()
+            )));
+        }
+        let list: Self = pod_read_unaligned(bytes);
+        if list.head_idx > list.tail_idx || list.num_items > list.span_len() {
+            return Err(CandyError::Corruption(format!("malformed {list:?}")));
+        }
+        Ok(list)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 #[repr(C, packed)]
-struct ChainKey {
-    list_ph: PartedHash,
-    idx: u64,
-    namespace: u8,
+pub(crate) struct ChainKey {
+    pub(crate) list_ph: PartedHash,
+    pub(crate) idx: u64,
+    pub(crate) namespace: u8,
+}
+
+#[cfg(feature = "fuzzing")]
+impl ChainKey {
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != size_of::<Self>() {
+            return Err(CandyError::Corruption(format!(
+                "chain key has wrong size {}",
+                bytes.len()
+            )));
+        }
+        let ck: Self = pod_read_unaligned(bytes);
+        if ck.namespace != CHAIN_NAMESPACE || !{ ck.list_ph }.is_valid() {
+            return Err(CandyError::Corruption(format!("malformed {ck:?}")));
+        }
+        Ok(ck)
+    }
 }
 
 #[derive(Debug)]
@@ -63,27 +138,91 @@ impl Default for ListCompactionParams {
     }
 }
 
+/// The metadata of a list item, kept when [crate::Config::list_item_metadata] is set. See
+/// [CandyStore::get_from_list_with_meta]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListItemMeta {
+    /// when the item was inserted into the list (or promoted), in milliseconds since the unix epoch
+    pub inserted_at_ms: u64,
+    /// starts at zero and is bumped whenever the item's value is updated
+    pub revision: u64,
+}
+
+impl ListItemMeta {
+    fn from_suffix(suffix: &[u8]) -> Self {
+        Self {
+            inserted_at_ms: u64::from_le_bytes(suffix[0..8].try_into().unwrap()),
+            revision: u64::from_le_bytes(suffix[8..16].try_into().unwrap()),
+        }
+    }
+}
+
+/// What a destructive operation would affect, as reported by its `dry_run_` counterpart (e.g.,
+/// [CandyStore::dry_run_discard_list]) without mutating anything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// the number of items that would be removed (or rewritten, for compactions)
+    pub num_items: usize,
+    /// the number of bytes (keys and values, as stored) of these items
+    pub num_bytes: usize,
+}
+
+/// How far a consumer is behind the tail of a list, see [CandyStore::cursor_lag]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CursorLag {
+    /// the number of items that follow the consumer's cursor
+    pub num_items: usize,
+    /// the number of bytes (keys and values) of these items
+    pub num_bytes: usize,
+}
+
+impl DryRunReport {
+    fn add(&mut self, k: &[u8], v: &[u8]) {
+        self.num_items += 1;
+        self.num_bytes += k.len() + v.len();
+    }
+}
+
 pub struct ListIterator<'a> {
     store: &'a CandyStore,
     list_key: Vec<u8>,
     list_ph: PartedHash,
     range: Option<Range<u64>>,
+    // elements below this index are skipped (used to resume from a cursor)
+    min_idx: u64,
+    // only elements with this tag are yielded, see CandyStore::iter_list_by_tag
+    tag: Option<u32>,
     fwd: bool,
 }
 
-impl<'a> Iterator for ListIterator<'a> {
-    type Item = Result<KVPair>;
+impl<'a> ListIterator<'a> {
+    /// Turns this iterator into one that yields the logical index of each element along with its key and value.
+    /// See [CandyStore::iter_list_with_indices]
+    pub fn with_indices(self) -> ListIndexedIterator<'a> {
+        ListIndexedIterator(self)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_with_idx(&mut self) -> Option<Result<IndexedKVPair>> {
+        self.next_matching(&mut |_, _| true)
+    }
+
+    // the predicate is applied to the element as read, before it is truncated into the returned key and value
+    fn next_matching(
+        &mut self,
+        pred: &mut impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> Option<Result<IndexedKVPair>> {
         if self.range.is_none() {
             let _guard = self.store.lock_list(self.list_ph);
-            let list_bytes = match self.store.get_raw(&self.list_key) {
+            let list_bytes = match self.store.get_header(&self.list_key) {
                 Ok(Some(list_bytes)) => list_bytes,
                 Ok(None) => return None,
                 Err(e) => return Some(Err(e)),
             };
-            let list = *from_bytes::<List>(&list_bytes);
-            self.range = Some(list.head_idx..list.tail_idx);
+            let list = match List::parse(&list_bytes) {
+                Ok(list) => list,
+                Err(e) => return Some(Err(e)),
+            };
+            self.range = Some(list.head_idx.max(self.min_idx)..list.tail_idx);
         }
 
         loop {
@@ -96,9 +235,23 @@ impl<'a> Iterator for ListIterator<'a> {
                 return None;
             };
 
-            match self.store.get_from_list_at_index(self.list_ph, idx, true) {
+            match self.store.get_from_list_at_index(self.list_ph, idx, false) {
                 Err(e) => return Some(Err(e)),
-                Ok(Some((_, k, v))) => return Some(Ok((k, v))),
+                Ok(Some((_, mut k, mut v))) => {
+                    if self
+                        .tag
+                        .is_some_and(|tag| self.store.list_item_tag(&v) != tag)
+                    {
+                        continue;
+                    }
+                    let klen = k.len() - CandyStore::LIST_KEY_SUFFIX_LEN;
+                    let vlen = v.len() - self.store.list_item_suffix_len();
+                    if pred(&k[..klen], &v[..vlen]) {
+                        k.truncate(klen);
+                        v.truncate(vlen);
+                        return Some(Ok((idx as usize, k, v)));
+                    }
+                }
                 Ok(None) => {
                     // try next index
                 }
@@ -106,7 +259,7 @@ impl<'a> Iterator for ListIterator<'a> {
         }
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
+    fn range_size_hint(&self) -> (usize, Option<usize>) {
         if let Some(ref range) = self.range {
             range.size_hint()
         } else {
@@ -115,6 +268,58 @@ impl<'a> Iterator for ListIterator<'a> {
     }
 }
 
+impl<'a> Iterator for ListIterator<'a> {
+    type Item = Result<KVPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_idx().map(|res| res.map(|(_, k, v)| (k, v)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range_size_hint()
+    }
+}
+
+type IndexedKVPair = (usize, Vec<u8>, Vec<u8>);
+
+/// A list iterator that yields `(idx, key, value)` triplets, where `idx` is the logical index of the element in the
+/// list. Indices grow from head to tail, but are not contiguous (removed elements leave gaps), and they are
+/// reassigned when the list is compacted.
+pub struct ListIndexedIterator<'a>(ListIterator<'a>);
+
+impl<'a> Iterator for ListIndexedIterator<'a> {
+    type Item = Result<IndexedKVPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_with_idx()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.range_size_hint()
+    }
+}
+
+/// A list iterator that only yields the elements for which a predicate returns true, see
+/// [CandyStore::iter_list_filtered]
+pub struct ListFilteredIterator<'a, F> {
+    iter: ListIterator<'a>,
+    pred: F,
+}
+
+impl<'a, F: FnMut(&[u8], &[u8]) -> bool> Iterator for ListFilteredIterator<'a, F> {
+    type Item = Result<KVPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next_matching(&mut self.pred)
+            .map(|res| res.map(|(_, k, v)| (k, v)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.range_size_hint().1)
+    }
+}
+
 #[derive(Debug)]
 enum InsertToListStatus {
     Created(Vec<u8>),
@@ -127,19 +332,151 @@ enum InsertToListStatus {
 impl CandyStore {
     const FIRST_LIST_IDX: u64 = 0x8000_0000_0000_0000;
 
-    fn make_list_key(&self, mut list_key: Vec<u8>) -> (PartedHash, Vec<u8>) {
+    pub(crate) fn make_list_key(&self, mut list_key: Vec<u8>) -> (PartedHash, Vec<u8>) {
         list_key.extend_from_slice(LIST_NAMESPACE);
-        (PartedHash::new(&self.config.hash_seed, &list_key), list_key)
+        (
+            PartedHash::new(&self.0.config.hash_seed, &list_key),
+            list_key,
+        )
     }
 
-    fn make_item_key(&self, list_ph: PartedHash, mut item_key: Vec<u8>) -> (PartedHash, Vec<u8>) {
+    pub(crate) fn make_item_key(
+        &self,
+        list_ph: PartedHash,
+        mut item_key: Vec<u8>,
+    ) -> (PartedHash, Vec<u8>) {
         item_key.extend_from_slice(bytes_of(&list_ph));
         item_key.extend_from_slice(ITEM_NAMESPACE);
-        (PartedHash::new(&self.config.hash_seed, &item_key), item_key)
+        (
+            PartedHash::new(&self.0.config.hash_seed, &item_key),
+            item_key,
+        )
+    }
+
+    // list items are stored as the value followed by the metadata and the tag (if enabled) and the item's index
+    pub(crate) fn list_item_suffix_len(&self) -> usize {
+        let mut len = size_of::<u64>();
+        if self.0.config.list_item_metadata {
+            len += 2 * size_of::<u64>();
+        }
+        if self.0.config.list_item_tags {
+            len += size_of::<u32>();
+        }
+        len
+    }
+
+    fn push_new_item_meta(&self, val: &mut Vec<u8>, tag: Option<u32>) {
+        if self.0.config.list_item_metadata {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            val.extend_from_slice(&now_ms.to_le_bytes());
+            val.extend_from_slice(&0u64.to_le_bytes());
+        }
+        if self.0.config.list_item_tags {
+            val.extend_from_slice(&tag.unwrap_or(0).to_le_bytes());
+        }
+    }
+
+    // the tag sits right before the index, at the end of the (untruncated) item
+    fn list_item_tag_range(full_v: &[u8]) -> Range<usize> {
+        let end = full_v.len() - size_of::<u64>();
+        end - size_of::<u32>()..end
+    }
+
+    // returns the tag of an untruncated item, or 0 if tags are not enabled
+    fn list_item_tag(&self, full_v: &[u8]) -> u32 {
+        if !self.0.config.list_item_tags {
+            return 0;
+        }
+        u32::from_le_bytes(
+            full_v[Self::list_item_tag_range(full_v)]
+                .try_into()
+                .unwrap(),
+        )
     }
 
-    pub(crate) fn lock_list(&self, list_ph: PartedHash) -> MutexGuard<()> {
-        self.keyed_locks[(list_ph.signature() & self.keyed_locks_mask) as usize].lock()
+    pub(crate) fn keyed_lock_slot(&self, ph: PartedHash) -> usize {
+        (ph.signature() & self.0.keyed_locks_mask) as usize
+    }
+
+    pub(crate) fn lock_list(&self, list_ph: PartedHash) -> ListLockGuard<'_> {
+        let slot = self.keyed_lock_slot(list_ph);
+        if self.batch_holds_list_slot(slot) {
+            return ListLockGuard::new(self, None, slot);
+        }
+        let guard = self.0.keyed_locks[slot].lock(|_t0| {
+            #[cfg(feature = "metrics")]
+            self.0.stats.lock_waits.record(
+                crate::lock_metrics::ContendedLock::List(list_ph.as_u64()),
+                _t0,
+            );
+        });
+        ListLockGuard::new(self, Some(guard), slot)
+    }
+
+    // locks a slot for a write batch, see Self::lock_batch
+    pub(crate) fn lock_list_slot(&self, slot: usize) -> MutexGuard<'_, ()> {
+        self.0.keyed_locks[slot].lock(|_| {})
+    }
+
+    // called on every iteration of a long loop that holds a list's lock, returns true every
+    // `Config::yield_every` iterations, when the caller should leave the list consistent and call
+    // [Self::yield_list_lock]
+    fn should_yield(&self, iterations: &mut usize) -> bool {
+        let Some(yield_every) = self.0.config.yield_every.get() else {
+            return false;
+        };
+        *iterations += 1;
+        if *iterations < yield_every {
+            return false;
+        }
+        *iterations = 0;
+        true
+    }
+
+    // lets the threads waiting on the lock (if any) go first, and re-acquires it. Anything read under the lock
+    // must be read again afterwards
+    fn yield_list_lock(guard: &mut ListLockGuard) {
+        guard.bump();
+    }
+
+    /// Returns the slot (in the keyed locks pool) that operations on the given list lock. Two lists that map to
+    /// the same slot serialize each other's operations. The number of slots is controlled by
+    /// [crate::Config::max_concurrent_list_ops]
+    pub fn list_lock_slot<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> usize {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        self.keyed_lock_slot(list_ph)
+    }
+
+    /// Same as [Self::list_lock_slot], but for queues
+    pub fn queue_lock_slot<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> usize {
+        let (queue_ph, _) = self.make_queue_key(queue_key.as_ref());
+        self.keyed_lock_slot(queue_ph)
+    }
+
+    /// Returns the contention counters of each slot in the keyed locks pool, including the number of threads
+    /// currently waiting on it. Use [Self::list_lock_slot] to find which slot a list maps to
+    pub fn keyed_lock_stats(&self) -> Vec<KeyedLockStats> {
+        self.0
+            .keyed_locks
+            .iter()
+            .enumerate()
+            .map(|(slot, kl)| KeyedLockStats {
+                slot,
+                num_acquisitions: kl.num_acquisitions.load(Ordering::Relaxed),
+                num_contended: kl.num_contended.load(Ordering::Relaxed),
+                num_waiters: kl.num_waiters.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // the number of waiters is left as is, since it reflects threads that are waiting right now
+    pub(crate) fn reset_keyed_lock_stats(&self) {
+        for kl in self.0.keyed_locks.iter() {
+            kl.reset_counters();
+        }
     }
 
     fn _insert_to_list(
@@ -148,23 +485,24 @@ impl CandyStore {
         item_key: Vec<u8>,
         mut val: Vec<u8>,
         mode: InsertMode,
+        tag: Option<u32>,
     ) -> Result<InsertToListStatus> {
         let (list_ph, list_key) = self.make_list_key(list_key);
         let (item_ph, item_key) = self.make_item_key(list_ph, item_key);
 
         let _guard = self.lock_list(list_ph);
+        let suffix_len = self.list_item_suffix_len();
 
         // if the item already exists, it's already part of the list. just update it and preserve the index
         if let Some(mut existing_val) = self.get_raw(&item_key)? {
+            let suffix = existing_val.split_off(existing_val.len() - suffix_len);
             match mode {
                 InsertMode::GetOrCreate => {
-                    existing_val.truncate(existing_val.len() - size_of::<u64>());
                     return Ok(InsertToListStatus::ExistingValue(existing_val));
                 }
                 InsertMode::Replace(expected_val) => {
                     if let Some(expected_val) = expected_val {
-                        if expected_val != &existing_val[existing_val.len() - size_of::<u64>()..] {
-                            existing_val.truncate(existing_val.len() - size_of::<u64>());
+                        if expected_val != existing_val {
                             return Ok(InsertToListStatus::WrongValue(existing_val));
                         }
                     }
@@ -175,9 +513,22 @@ impl CandyStore {
                 }
             }
 
-            val.extend_from_slice(&existing_val[existing_val.len() - size_of::<u64>()..]);
+            let val_len = val.len();
+            val.extend_from_slice(&suffix);
+            if self.0.config.list_item_metadata {
+                let revision = ListItemMeta::from_suffix(&suffix).revision + 1;
+                val[val_len + 8..val_len + 16].copy_from_slice(&revision.to_le_bytes());
+            }
+            if let Some(tag) = tag {
+                let tag_range = Self::list_item_tag_range(&val);
+                val[tag_range].copy_from_slice(&tag.to_le_bytes());
+            }
             self.replace_raw(&item_key, &val, None)?;
-            existing_val.truncate(existing_val.len() - size_of::<u64>());
+            self.audit_list_op(
+                list_ph,
+                ListAuditOp::Update,
+                &item_key[..item_key.len() - Self::LIST_KEY_SUFFIX_LEN],
+            )?;
             return Ok(InsertToListStatus::Replaced(existing_val));
         }
 
@@ -187,7 +538,7 @@ impl CandyStore {
         }
 
         // get of create the list
-        let res = self.get_or_create_raw(
+        let res = self.get_or_create_header(
             &list_key,
             bytes_of(&List {
                 head_idx: Self::FIRST_LIST_IDX,
@@ -210,18 +561,20 @@ impl CandyStore {
                 )?;
 
                 // create item
+                self.push_new_item_meta(&mut val, tag);
                 val.extend_from_slice(bytes_of(&Self::FIRST_LIST_IDX));
                 self.set_raw(&item_key, &val)?;
             }
             crate::GetOrCreateStatus::ExistingValue(list_bytes) => {
-                let mut list = *from_bytes::<List>(&list_bytes);
+                let mut list = List::parse(&list_bytes)?;
 
                 let idx = list.tail_idx;
                 list.tail_idx += 1;
 
                 // update list
                 list.num_items += 1;
-                self.set_raw(&list_key, bytes_of(&list))?;
+                self.set_header(&list_key, bytes_of(&list))?;
+                self.check_list_span_soft_limit(&list_key, list.span_len() - 1, list.span_len());
 
                 // create chain
                 self.set_raw(
@@ -234,12 +587,18 @@ impl CandyStore {
                 )?;
 
                 // create item
+                self.push_new_item_meta(&mut val, tag);
                 val.extend_from_slice(bytes_of(&idx));
                 self.set_raw(&item_key, &val)?;
             }
         }
 
-        val.truncate(val.len() - size_of::<u64>());
+        self.audit_list_op(
+            list_ph,
+            ListAuditOp::Push,
+            &item_key[..item_key.len() - Self::LIST_KEY_SUFFIX_LEN],
+        )?;
+        val.truncate(val.len() - suffix_len);
         Ok(InsertToListStatus::Created(val))
     }
 
@@ -269,7 +628,7 @@ impl CandyStore {
     /// remove + insert operation. This can be usede to implement LRUs, where older elements are at the
     /// beginning and newer ones at the end.
     ///
-    /// Note: **not crash-safe**
+    /// Note: **not crash-safe**, unless [crate::Config::list_journal] is set
     pub fn set_in_list_promoting<
         B1: AsRef<[u8]> + ?Sized,
         B2: AsRef<[u8]> + ?Sized,
@@ -297,15 +656,72 @@ impl CandyStore {
         promote: bool,
     ) -> Result<SetStatus> {
         if promote {
-            self.owned_remove_from_list(list_key.clone(), item_key.clone())?;
+            // the item is pushed back right away, so it keeps its TTL
+            self._remove_from_list(list_key.clone(), item_key.clone(), true)?;
+        }
+        match self._insert_to_list(list_key, item_key, val, InsertMode::Set, None)? {
+            InsertToListStatus::Created(_v) => Ok(SetStatus::CreatedNew),
+            InsertToListStatus::Replaced(v) => Ok(SetStatus::PrevValue(v)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn ensure_list_item_tags(&self) -> Result<()> {
+        if !self.0.config.list_item_tags {
+            return Err(CandyError::InvalidArgument(
+                "list item tags are not enabled".into(),
+            ));
         }
-        match self._insert_to_list(list_key, item_key, val, InsertMode::Set)? {
+        Ok(())
+    }
+
+    /// Same as [Self::set_in_list], but also sets the item's tag: a small number that is kept next to the item's
+    /// index, e.g., the status of a job in a list of jobs. Updating an existing item replaces its tag, while
+    /// the other ways of inserting and updating items (e.g., [Self::set_in_list]) leave the tag of existing items
+    /// as it is, and give new items the tag 0. See [Self::iter_list_by_tag].
+    ///
+    /// Requires [crate::Config::list_item_tags], or returns [crate::CandyError::InvalidArgument]
+    pub fn set_in_list_tagged<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+        tag: u32,
+    ) -> Result<SetStatus> {
+        self.ensure_list_item_tags()?;
+        match self._insert_to_list(
+            list_key.as_ref().to_owned(),
+            item_key.as_ref().to_owned(),
+            val.as_ref().to_owned(),
+            InsertMode::Set,
+            Some(tag),
+        )? {
             InsertToListStatus::Created(_v) => Ok(SetStatus::CreatedNew),
             InsertToListStatus::Replaced(v) => Ok(SetStatus::PrevValue(v)),
             _ => unreachable!(),
         }
     }
 
+    /// Returns the tag of a list item (see [Self::set_in_list_tagged]), or `None` if it does not exist.
+    ///
+    /// Requires [crate::Config::list_item_tags], or returns [crate::CandyError::InvalidArgument]
+    pub fn get_list_item_tag<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+    ) -> Result<Option<u32>> {
+        self.ensure_list_item_tags()?;
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        let (_, item_key) = self.make_item_key(list_ph, item_key.as_ref().to_owned());
+        Ok(self
+            .get_raw(&item_key)?
+            .map(|full_v| self.list_item_tag(&full_v)))
+    }
+
     /// Like [Self::set_in_list], but will only replace (update) an existing item, i.e., it will never create the
     /// key
     pub fn replace_in_list<
@@ -335,7 +751,13 @@ impl CandyStore {
         val: Vec<u8>,
         expected_val: Option<&[u8]>,
     ) -> Result<ReplaceStatus> {
-        match self._insert_to_list(list_key, item_key, val, InsertMode::Replace(expected_val))? {
+        match self._insert_to_list(
+            list_key,
+            item_key,
+            val,
+            InsertMode::Replace(expected_val),
+            None,
+        )? {
             InsertToListStatus::DoesNotExist => Ok(ReplaceStatus::DoesNotExist),
             InsertToListStatus::Replaced(v) => Ok(ReplaceStatus::PrevValue(v)),
             InsertToListStatus::WrongValue(v) => Ok(ReplaceStatus::WrongValue(v)),
@@ -369,7 +791,13 @@ impl CandyStore {
         item_key: Vec<u8>,
         default_val: Vec<u8>,
     ) -> Result<GetOrCreateStatus> {
-        match self._insert_to_list(list_key, item_key, default_val, InsertMode::GetOrCreate)? {
+        match self._insert_to_list(
+            list_key,
+            item_key,
+            default_val,
+            InsertMode::GetOrCreate,
+            None,
+        )? {
             InsertToListStatus::ExistingValue(v) => Ok(GetOrCreateStatus::ExistingValue(v)),
             InsertToListStatus::Created(v) => Ok(GetOrCreateStatus::CreatedNew(v)),
             _ => unreachable!(),
@@ -393,15 +821,79 @@ impl CandyStore {
         list_key: Vec<u8>,
         item_key: Vec<u8>,
     ) -> Result<Option<Vec<u8>>> {
+        if self.list_item_expired(&list_key, &item_key)? {
+            return Ok(None);
+        }
         let (list_ph, _) = self.make_list_key(list_key);
         let (_, item_key) = self.make_item_key(list_ph, item_key);
         let Some(mut val) = self.get_raw(&item_key)? else {
             return Ok(None);
         };
-        val.truncate(val.len() - size_of::<u64>());
+        val.truncate(val.len() - self.list_item_suffix_len());
         Ok(Some(val))
     }
 
+    /// Same as [Self::get_from_list], but also returns the item's metadata: when it was inserted and how many
+    /// times it has been updated since. For instance, consumers of a list that is used as a queue can use it to
+    /// compute the queue's latency, or to detect re-deliveries.
+    ///
+    /// Requires [crate::Config::list_item_metadata], or returns [crate::CandyError::InvalidArgument]
+    pub fn get_from_list_with_meta<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+    ) -> Result<Option<(Vec<u8>, ListItemMeta)>> {
+        self.owned_get_from_list_with_meta(
+            list_key.as_ref().to_owned(),
+            item_key.as_ref().to_owned(),
+        )
+    }
+
+    /// Owned version of [Self::get_from_list_with_meta]
+    pub fn owned_get_from_list_with_meta(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+    ) -> Result<Option<(Vec<u8>, ListItemMeta)>> {
+        if !self.0.config.list_item_metadata {
+            return Err(CandyError::InvalidArgument(
+                "list item metadata is not enabled".into(),
+            ));
+        }
+        let (list_ph, _) = self.make_list_key(list_key);
+        let (_, item_key) = self.make_item_key(list_ph, item_key);
+        let Some(mut val) = self.get_raw(&item_key)? else {
+            return Ok(None);
+        };
+        let suffix = val.split_off(val.len() - self.list_item_suffix_len());
+        Ok(Some((val, ListItemMeta::from_suffix(&suffix))))
+    }
+
+    /// Returns the length of a list element's value, or `None` if it does not exist, without reading
+    /// the value itself.
+    ///
+    /// See also: [Self::value_len]
+    pub fn list_item_len<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+    ) -> Result<Option<usize>> {
+        self.owned_list_item_len(list_key.as_ref().to_owned(), item_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::list_item_len]
+    pub fn owned_list_item_len(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+    ) -> Result<Option<usize>> {
+        let (list_ph, _) = self.make_list_key(list_key);
+        let (_, item_key) = self.make_item_key(list_ph, item_key);
+        Ok(self
+            .get_value_len_raw(&item_key)?
+            .map(|len| len - self.list_item_suffix_len()))
+    }
+
     /// Removes a element from the list, identified by `list_key` and `item_key. The element can be
     /// at any position in the list, not just the head or the tail, but in this case, it will create a "hole".
     /// This means that iterations will go over the missing element's index every time, until the list is compacted.
@@ -420,13 +912,41 @@ impl CandyStore {
         &self,
         list_key: Vec<u8>,
         item_key: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        self._remove_from_list(list_key, item_key, false)
+    }
+
+    fn _remove_from_list(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        keep_ttl: bool,
     ) -> Result<Option<Vec<u8>>> {
         let (list_ph, list_key) = self.make_list_key(list_key);
         let (_, item_key) = self.make_item_key(list_ph, item_key);
 
         let _guard = self.lock_list(list_ph);
+        let res =
+            self.remove_from_list_locked(list_ph, &list_key, &item_key, ListAuditOp::Remove)?;
+        if res.is_some() && !keep_ttl {
+            self.clear_list_item_ttl(
+                &list_key,
+                &item_key[..item_key.len() - Self::LIST_KEY_SUFFIX_LEN],
+            )?;
+        }
+        Ok(res)
+    }
 
-        let Some(mut existing_val) = self.get_raw(&item_key)? else {
+    // removes the item (given by its full key) from the list, returning its value. the caller must hold the
+    // list's lock
+    pub(crate) fn remove_from_list_locked(
+        &self,
+        list_ph: PartedHash,
+        list_key: &[u8],
+        item_key: &[u8],
+        audit_op: ListAuditOp,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(mut existing_val) = self.get_raw(item_key)? else {
             return Ok(None);
         };
 
@@ -435,11 +955,11 @@ impl CandyStore {
                 .try_into()
                 .unwrap(),
         );
-        existing_val.truncate(existing_val.len() - size_of::<u64>());
+        existing_val.truncate(existing_val.len() - self.list_item_suffix_len());
 
         // update list, if the item was the head/tail
-        if let Some(list_bytes) = self.get_raw(&list_key)? {
-            let mut list = *from_bytes::<List>(&list_bytes);
+        if let Some(list_bytes) = self.get_header(list_key)? {
+            let mut list = List::parse(&list_bytes)?;
 
             list.num_items -= 1;
 
@@ -451,9 +971,9 @@ impl CandyStore {
                 }
             }
             if list.is_empty() {
-                self.remove_raw(&list_key)?;
+                self.remove_header(list_key)?;
             } else {
-                self.set_raw(&list_key, bytes_of(&list))?;
+                self.set_header(list_key, bytes_of(&list))?;
             }
         }
 
@@ -465,14 +985,19 @@ impl CandyStore {
         }))?;
 
         // remove item
-        self.remove_raw(&item_key)?;
+        self.remove_raw(item_key)?;
 
+        self.audit_list_op(
+            list_ph,
+            audit_op,
+            &item_key[..item_key.len() - Self::LIST_KEY_SUFFIX_LEN],
+        )?;
         Ok(Some(existing_val))
     }
 
-    const LIST_KEY_SUFFIX_LEN: usize = size_of::<PartedHash>() + ITEM_NAMESPACE.len();
+    pub(crate) const LIST_KEY_SUFFIX_LEN: usize = size_of::<PartedHash>() + ITEM_NAMESPACE.len();
 
-    fn get_from_list_at_index(
+    pub(crate) fn get_from_list_at_index(
         &self,
         list_ph: PartedHash,
         idx: u64,
@@ -493,9 +1018,26 @@ impl CandyStore {
         suffix[size_of::<PartedHash>()..].copy_from_slice(ITEM_NAMESPACE);
 
         for (mut k, mut v) in self.get_by_hash(item_ph)? {
-            if k.ends_with(&suffix) && v.ends_with(bytes_of(&idx)) {
+            if !k.ends_with(&suffix) {
+                // an entry of another list (or namespace) that shares the item's signature
+                self.0
+                    .stats
+                    .num_list_chain_collisions
+                    .fetch_add(1, Ordering::Relaxed);
+                self.0
+                    .stats
+                    .collision_log
+                    .push(self.0.config.collision_log_capacity, || CollisionRecord {
+                        kind: CollisionKind::ListChain,
+                        signature: item_ph.signature(),
+                        key: suffix.to_vec(),
+                        other_key: k.clone(),
+                    });
+                continue;
+            }
+            if v.ends_with(bytes_of(&idx)) {
                 if truncate {
-                    v.truncate(v.len() - size_of::<u64>());
+                    v.truncate(v.len() - self.list_item_suffix_len());
                     k.truncate(k.len() - suffix.len());
                 }
                 return Ok(Some((item_ph, k, v)));
@@ -512,19 +1054,43 @@ impl CandyStore {
     /// have a minimal holes-to-length ratio. The default values are expected to be okay for most use cases.
     /// Returns true if the list was compacted, false otherwise.
     ///
-    /// Note: **Not crash-safe**
+    /// Compaction re-indexes the elements from head to tail, so their relative order is preserved (see
+    /// [Self::iter_list]).
+    ///
+    /// Note: **Not crash-safe**, unless [crate::Config::list_journal] is set
     pub fn compact_list_if_needed<B: AsRef<[u8]> + ?Sized>(
         &self,
         list_key: &B,
         params: ListCompactionParams,
     ) -> Result<bool> {
-        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        self._compact_list_if_needed(list_key.as_ref(), params, None)
+    }
+
+    /// Same as [Self::compact_list_if_needed], but fails with [crate::CandyError::Cancelled] once `cancel` is
+    /// cancelled. The list remains consistent: the elements compacted up to that point are moved to the tail,
+    /// after the rest, which are kept where they are (so the order of the list is not preserved)
+    pub fn compact_list_if_needed_cancellable<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        params: ListCompactionParams,
+        cancel: &CancellationToken,
+    ) -> Result<bool> {
+        self._compact_list_if_needed(list_key.as_ref(), params, Some(cancel))
+    }
+
+    fn _compact_list_if_needed(
+        &self,
+        user_list_key: &[u8],
+        params: ListCompactionParams,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<bool> {
+        let (list_ph, list_key) = self.make_list_key(user_list_key.to_owned());
         let _guard = self.lock_list(list_ph);
 
-        let Some(list_bytes) = self.get_raw(&list_key)? else {
+        let Some(list_bytes) = self.get_header(&list_key)? else {
             return Ok(false);
         };
-        let list = *from_bytes::<List>(&list_bytes);
+        let list = List::parse(&list_bytes)?;
         if list.span_len() < params.min_length {
             return Ok(false);
         }
@@ -534,6 +1100,18 @@ impl CandyStore {
 
         let mut new_idx = list.tail_idx;
         for idx in list.head_idx..list.tail_idx {
+            if let Err(e) = CancellationToken::check(cancel) {
+                // the elements that were not moved yet remain in place, ahead of the moved ones
+                self.set_header(
+                    &list_key,
+                    bytes_of(&List {
+                        head_idx: idx,
+                        tail_idx: new_idx,
+                        num_items: list.num_items,
+                    }),
+                )?;
+                return Err(e);
+            }
             let Some((item_ph, full_k, mut full_v)) =
                 self.get_from_list_at_index(list_ph, idx, false)?
             else {
@@ -567,10 +1145,10 @@ impl CandyStore {
 
         if list.tail_idx == new_idx {
             // list is now empty
-            self.remove_raw(&list_key)?;
+            self.remove_header(&list_key)?;
         } else {
             // update list head and tail, set holes=0
-            self.set_raw(
+            self.set_header(
                 &list_key,
                 bytes_of(&List {
                     head_idx: list.tail_idx,
@@ -580,36 +1158,361 @@ impl CandyStore {
             )?;
         }
 
+        self.audit_list_op(list_ph, ListAuditOp::Compact, &[])?;
+        self.0
+            .stats
+            .maintenance_observer
+            .notify(|obs| obs.on_list_compaction(user_list_key, new_idx - list.tail_idx));
         Ok(true)
     }
 
-    /// Iterates over the elements of the list (identified by `list_key`) from the beginning (head)
-    /// to the end (tail). Note that if items are removed at random locations in the list, the iterator
-    /// will need to skip these holes. If you remove elements from the middle (not head/tail) of the list
-    /// frequently, and wish to use iteration, consider compacting the list every so often using
-    /// [Self::compact_list_if_needed]
-    pub fn iter_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> ListIterator {
-        self.owned_iter_list(list_key.as_ref().to_owned())
+    /// Reorders the items of the list by the given comparator of `(key, value)` pairs, so that iterating from
+    /// head to tail yields them in ascending order (the sort is stable). The items are rewritten to the tail,
+    /// like [Self::compact_list_if_needed] does, so this also removes the holes. Returns false if the list does
+    /// not exist.
+    ///
+    /// The whole operation holds the list's lock, so other operations on the list observe either the old
+    /// order or the new one. All items are read into memory, so this suits small to medium lists. The
+    /// comparator runs under the lock, and must not access the store.
+    ///
+    /// Note: **Not crash-safe**, unless [crate::Config::list_journal] is set
+    pub fn sort_list_by<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        mut cmp: impl FnMut(&KVPair, &KVPair) -> std::cmp::Ordering,
+    ) -> Result<bool> {
+        self.reorder_list(list_key.as_ref().to_owned(), |items| {
+            let mut order = (0..items.len()).collect::<Vec<_>>();
+            order.sort_by(|&a, &b| cmp(&items[a], &items[b]));
+            Ok(order)
+        })
     }
 
-    /// Owned version of [Self::iter_list]
-    pub fn owned_iter_list(&self, list_key: Vec<u8>) -> ListIterator {
+    // rewrites the items of the list in the order returned by `reorder`, which is given the items (from head
+    // to tail) and returns a permutation of their positions
+    pub(crate) fn reorder_list(
+        &self,
+        list_key: Vec<u8>,
+        reorder: impl FnOnce(&[KVPair]) -> Result<Vec<usize>>,
+    ) -> Result<bool> {
         let (list_ph, list_key) = self.make_list_key(list_key);
-        ListIterator {
-            store: &self,
-            list_key,
-            list_ph,
-            range: None,
-            fwd: true,
-        }
-    }
+        let _guard = self.lock_list(list_ph);
 
-    /// Same as [Self::iter_list] but iterates from the end (tail) to the beginning (head)
-    pub fn iter_list_backwards<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> ListIterator {
-        self.owned_iter_list_backwards(list_key.as_ref().to_owned())
-    }
+        let Some(list_bytes) = self.get_header(&list_key)? else {
+            return Ok(false);
+        };
+        let list = List::parse(&list_bytes)?;
 
-    /// Owned version of [Self::iter_list_backwards]
+        let mut items = vec![];
+        let mut entries = vec![];
+        for idx in list.head_idx..list.tail_idx {
+            let Some((item_ph, full_k, full_v)) =
+                self.get_from_list_at_index(list_ph, idx, false)?
+            else {
+                continue;
+            };
+            items.push((
+                full_k[..full_k.len() - Self::LIST_KEY_SUFFIX_LEN].to_vec(),
+                full_v[..full_v.len() - self.list_item_suffix_len()].to_vec(),
+            ));
+            entries.push((idx, item_ph, full_k, full_v));
+        }
+
+        let order = reorder(&items)?;
+        let mut seen = vec![false; entries.len()];
+        if order.len() != entries.len()
+            || order
+                .iter()
+                .any(|&i| i >= seen.len() || std::mem::replace(&mut seen[i], true))
+        {
+            return Err(CandyError::InvalidArgument(
+                "the new order is not a permutation of the list's items".into(),
+            ));
+        }
+
+        let mut new_idx = list.tail_idx;
+        for i in order {
+            let (idx, item_ph, ref full_k, ref mut full_v) = entries[i];
+
+            // create new chain
+            self.set_raw(
+                bytes_of(&ChainKey {
+                    idx: new_idx,
+                    list_ph,
+                    namespace: CHAIN_NAMESPACE,
+                }),
+                bytes_of(&item_ph),
+            )?;
+
+            // update item's index suffix
+            let offset = full_v.len() - size_of::<u64>();
+            full_v[offset..].copy_from_slice(bytes_of(&new_idx));
+            self.set_raw(full_k, full_v)?;
+
+            // remove old chain
+            self.remove_raw(bytes_of(&ChainKey {
+                idx,
+                list_ph,
+                namespace: CHAIN_NAMESPACE,
+            }))?;
+
+            new_idx += 1;
+        }
+
+        self.set_header(
+            &list_key,
+            bytes_of(&List {
+                head_idx: list.tail_idx,
+                tail_idx: new_idx,
+                num_items: new_idx - list.tail_idx,
+            }),
+        )?;
+        self.audit_list_op(list_ph, ListAuditOp::Sort, &[])?;
+        Ok(true)
+    }
+
+    /// Iterates over the elements of the list (identified by `list_key`) from the beginning (head)
+    /// to the end (tail). Note that if items are removed at random locations in the list, the iterator
+    /// will need to skip these holes. If you remove elements from the middle (not head/tail) of the list
+    /// frequently, and wish to use iteration, consider compacting the list every so often using
+    /// [Self::compact_list_if_needed]
+    ///
+    /// Elements are yielded in the order they were pushed to the tail, where promoted elements (see
+    /// [Self::set_in_list_promoting]) count as pushed when they were promoted. Compaction and
+    /// [Self::retain_in_list] preserve this order, while [Self::sort_list_by] replaces it, and so does cancelling
+    /// a compaction or retention midway (the elements visited by then are moved after the rest)
+    pub fn iter_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> ListIterator {
+        self.owned_iter_list(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::iter_list]
+    pub fn owned_iter_list(&self, list_key: Vec<u8>) -> ListIterator {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        ListIterator {
+            store: &self,
+            list_key,
+            list_ph,
+            range: None,
+            min_idx: 0,
+            tag: None,
+            fwd: true,
+        }
+    }
+
+    /// Same as [Self::iter_list], but only yields the elements for which `pred(key, value)` returns true. The
+    /// predicate is applied to each element as it is read, so the elements that do not match are skipped without
+    /// being copied into results, which is cheaper than filtering the output of [Self::iter_list] when most
+    /// elements are skipped
+    pub fn iter_list_filtered<B: AsRef<[u8]> + ?Sized, F: FnMut(&[u8], &[u8]) -> bool>(
+        &self,
+        list_key: &B,
+        pred: F,
+    ) -> ListFilteredIterator<'_, F> {
+        self.owned_iter_list_filtered(list_key.as_ref().to_owned(), pred)
+    }
+
+    /// Owned version of [Self::iter_list_filtered]
+    pub fn owned_iter_list_filtered<F: FnMut(&[u8], &[u8]) -> bool>(
+        &self,
+        list_key: Vec<u8>,
+        pred: F,
+    ) -> ListFilteredIterator<'_, F> {
+        ListFilteredIterator {
+            iter: self.owned_iter_list(list_key),
+            pred,
+        }
+    }
+
+    /// Same as [Self::iter_list], but only yields the elements with the given tag (see
+    /// [Self::set_in_list_tagged]). Tags are checked before the elements are copied into results, so skipping
+    /// the elements of other tags is cheap, but it still takes a lookup per element.
+    ///
+    /// Requires [crate::Config::list_item_tags], or returns [crate::CandyError::InvalidArgument]
+    pub fn iter_list_by_tag<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        tag: u32,
+    ) -> Result<ListIterator<'_>> {
+        self.ensure_list_item_tags()?;
+        let mut iter = self.owned_iter_list(list_key.as_ref().to_owned());
+        iter.tag = Some(tag);
+        Ok(iter)
+    }
+
+    /// Same as [Self::iter_list], but the list's bounds are captured (under the list's lock) when the iterator
+    /// is created, rather than on its first step. The iterator is therefore bounded by the list as it was at that
+    /// point: elements pushed (or promoted, compacted or retained, all of which move elements to the tail)
+    /// afterwards are not yielded, so iterating over a continuously-growing list always terminates. Elements
+    /// that are removed before the iterator reaches them are skipped, and values are read as they are reached
+    pub fn iter_list_snapshot<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+    ) -> Result<ListIterator<'_>> {
+        self.owned_iter_list_snapshot(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::iter_list_snapshot]
+    pub fn owned_iter_list_snapshot(&self, list_key: Vec<u8>) -> Result<ListIterator<'_>> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let range = {
+            let _guard = self.lock_list(list_ph);
+            match self.get_header(&list_key)? {
+                Some(list_bytes) => {
+                    let list = List::parse(&list_bytes)?;
+                    list.head_idx..list.tail_idx
+                }
+                None => 0..0,
+            }
+        };
+        Ok(ListIterator {
+            store: self,
+            list_key,
+            list_ph,
+            range: Some(range),
+            min_idx: 0,
+            tag: None,
+            fwd: true,
+        })
+    }
+
+    /// Same as [Self::iter_list], but also yields the logical index of each element, i.e., `(idx, key, value)`.
+    /// Use [ListIterator::with_indices] on [Self::iter_list_backwards] to iterate backwards with indices.
+    ///
+    /// Note: indices are stable as long as the list is not compacted (see [Self::compact_list_if_needed])
+    pub fn iter_list_with_indices<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+    ) -> ListIndexedIterator<'_> {
+        self.iter_list(list_key).with_indices()
+    }
+
+    /// Owned version of [Self::iter_list_with_indices]
+    pub fn owned_iter_list_with_indices(&self, list_key: Vec<u8>) -> ListIndexedIterator<'_> {
+        self.owned_iter_list(list_key).with_indices()
+    }
+
+    fn make_list_cursor_key(list_key: &[u8], name: &[u8]) -> Vec<u8> {
+        let mut cursor_key = Vec::with_capacity(list_key.len() + name.len() + size_of::<u16>() + 1);
+        cursor_key.extend_from_slice(list_key);
+        cursor_key.extend_from_slice(name);
+        // names are shorter than the maximal key size, which fits in 14 bits
+        cursor_key.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        cursor_key.extend_from_slice(LIST_CURSOR_NAMESPACE);
+        cursor_key
+    }
+
+    /// Saves a named cursor into the list (identified by `list_key`), marking that the consumer `name` has
+    /// processed all elements up to and including the logical index `idx`, as returned by
+    /// [Self::iter_list_with_indices] or [Self::iter_list_from_cursor]. The cursor is persisted in the store,
+    /// so consumers can resume from it after a restart using [Self::iter_list_from_cursor].
+    ///
+    /// Note: cursors are meant for append-only lists. Compaction and promotion move elements to the tail
+    /// (reassigning their indices), so an element may be yielded again after its cursor was saved, but it is
+    /// never skipped. Cursors are not removed with the list, see [Self::remove_list_cursor]
+    pub fn save_list_cursor<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        name: &B2,
+        idx: usize,
+    ) -> Result<()> {
+        let cursor_key = Self::make_list_cursor_key(list_key.as_ref(), name.as_ref());
+        self.set_raw(&cursor_key, &(idx as u64).to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the index saved by [Self::save_list_cursor] for the given list and consumer, if any
+    pub fn get_list_cursor<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        name: &B2,
+    ) -> Result<Option<usize>> {
+        let cursor_key = Self::make_list_cursor_key(list_key.as_ref(), name.as_ref());
+        let Some(bytes) = self.get_raw(&cursor_key)? else {
+            return Ok(None);
+        };
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| CandyError::Corruption("bad list cursor".into()))?;
+        Ok(Some(u64::from_le_bytes(bytes) as usize))
+    }
+
+    /// Removes a cursor saved by [Self::save_list_cursor], returning its index, if it existed
+    pub fn remove_list_cursor<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        name: &B2,
+    ) -> Result<Option<usize>> {
+        let cursor_key = Self::make_list_cursor_key(list_key.as_ref(), name.as_ref());
+        let Some(bytes) = self.remove_raw(&cursor_key)? else {
+            return Ok(None);
+        };
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| CandyError::Corruption("bad list cursor".into()))?;
+        Ok(Some(u64::from_le_bytes(bytes) as usize))
+    }
+
+    /// Iterates over the elements of the list (identified by `list_key`) that follow the cursor saved by
+    /// [Self::save_list_cursor] for the consumer `name`, yielding `(idx, key, value)` like
+    /// [Self::iter_list_with_indices]. The consumer is expected to save the indices it has processed, e.g.,
+    ///
+    /// ```ignore
+    /// for res in db.iter_list_from_cursor("events", "indexer")? {
+    ///     let (idx, k, v) = res?;
+    ///     process(k, v);
+    ///     db.save_list_cursor("events", "indexer", idx)?;
+    /// }
+    /// ```
+    ///
+    /// If no cursor was saved, iteration starts at the head of the list
+    pub fn iter_list_from_cursor<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        name: &B2,
+    ) -> Result<ListIndexedIterator<'_>> {
+        let min_idx = match self.get_list_cursor(list_key, name)? {
+            Some(idx) => idx as u64 + 1,
+            None => 0,
+        };
+        Ok(self.iter_list_from_idx(list_key.as_ref(), min_idx))
+    }
+
+    // iterates over the elements whose logical index is at least `min_idx`
+    pub(crate) fn iter_list_from_idx(
+        &self,
+        list_key: &[u8],
+        min_idx: u64,
+    ) -> ListIndexedIterator<'_> {
+        let mut iter = self.iter_list(list_key);
+        iter.min_idx = min_idx;
+        iter.with_indices()
+    }
+
+    /// Returns how far the consumer `name` is behind the tail of the list, i.e., the number of items (and their
+    /// bytes) that [Self::iter_list_from_cursor] would yield, so that the lag of every consumer of a pipeline
+    /// can be monitored from the store. If no cursor was saved, the whole list is counted.
+    ///
+    /// This walks the items that follow the cursor, so it takes time proportional to the lag
+    pub fn cursor_lag<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        name: &B2,
+    ) -> Result<CursorLag> {
+        let mut lag = CursorLag::default();
+        for res in self.iter_list_from_cursor(list_key, name)? {
+            let (_, k, v) = res?;
+            lag.num_items += 1;
+            lag.num_bytes += k.len() + v.len();
+        }
+        Ok(lag)
+    }
+
+    /// Same as [Self::iter_list] but iterates from the end (tail) to the beginning (head)
+    pub fn iter_list_backwards<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> ListIterator {
+        self.owned_iter_list_backwards(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::iter_list_backwards]
     pub fn owned_iter_list_backwards(&self, list_key: Vec<u8>) -> ListIterator {
         let (list_ph, list_key) = self.make_list_key(list_key);
         ListIterator {
@@ -617,6 +1520,8 @@ impl CandyStore {
             list_key,
             list_ph,
             range: None,
+            min_idx: 0,
+            tag: None,
             fwd: false,
         }
     }
@@ -629,27 +1534,105 @@ impl CandyStore {
 
     /// Owned version of [Self::discard_list]
     pub fn owned_discard_list(&self, list_key: Vec<u8>) -> Result<bool> {
+        self._discard_list(list_key, None, None)
+    }
+
+    /// Same as [Self::discard_list], but fails with [crate::CandyError::Cancelled] once `cancel` is cancelled.
+    /// The elements discarded up to that point are gone, and the rest of the list remains intact
+    pub fn discard_list_cancellable<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        cancel: &CancellationToken,
+    ) -> Result<bool> {
+        self._discard_list(list_key.as_ref().to_owned(), Some(cancel), None)
+    }
+
+    /// Same as [Self::discard_list], but reports its progress through `progress`, where the total is the
+    /// list's span (including holes)
+    pub fn discard_list_with_progress<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        progress: &Progress,
+    ) -> Result<bool> {
+        self._discard_list(list_key.as_ref().to_owned(), None, Some(progress))
+    }
+
+    fn _discard_list(
+        &self,
+        list_key: Vec<u8>,
+        cancel: Option<&CancellationToken>,
+        progress: Option<&Progress>,
+    ) -> Result<bool> {
         let (list_ph, list_key) = self.make_list_key(list_key);
-        let _guard = self.lock_list(list_ph);
+        let mut guard = self.lock_list(list_ph);
 
-        let Some(list_bytes) = self.get_raw(&list_key)? else {
+        let Some(list_bytes) = self.get_header(&list_key)? else {
             return Ok(false);
         };
-        let list = *from_bytes::<List>(&list_bytes);
-        for idx in list.head_idx..list.tail_idx {
-            let Some((_, full_key, _)) = self.get_from_list_at_index(list_ph, idx, false)? else {
+        let mut list = List::parse(&list_bytes)?;
+        let watch = self.watch_header(&list_key);
+        // elements pushed while yielding the lock are kept
+        let mut end_idx = list.tail_idx;
+        let mut idx = list.head_idx;
+        let start_idx = idx;
+        let mut iterations = 0;
+        let mut res = Ok(true);
+        while idx < end_idx {
+            if let Err(e) = CancellationToken::check(cancel) {
+                res = Err(e);
+                end_idx = idx;
+                break;
+            }
+            if self.should_yield(&mut iterations) {
+                // the elements discarded so far are no longer part of the list
+                list.head_idx = idx;
+                if list.num_items == 0 {
+                    break;
+                }
+                self.set_header(&list_key, bytes_of(&list))?;
+                Self::yield_list_lock(&mut guard);
+                // the list was discarded meanwhile, and may have been created again, in which case the new
+                // list's elements are not ours to discard
+                if watch.was_removed() {
+                    return Ok(true);
+                }
+                let Some(list_bytes) = self.get_header(&list_key)? else {
+                    return Ok(true);
+                };
+                list = List::parse(&list_bytes)?;
+                idx = idx.max(list.head_idx);
+                continue;
+            }
+
+            let curr_idx = idx;
+            idx += 1;
+            Progress::report(progress, idx - start_idx, end_idx - start_idx);
+            let Some((_, full_key, _)) = self.get_from_list_at_index(list_ph, curr_idx, false)?
+            else {
                 continue;
             };
             self.remove_raw(bytes_of(&ChainKey {
                 list_ph,
-                idx,
+                idx: curr_idx,
                 namespace: CHAIN_NAMESPACE,
             }))?;
             self.remove_raw(&full_key)?;
+            self.clear_list_item_ttl(
+                &list_key,
+                &full_key[..full_key.len() - Self::LIST_KEY_SUFFIX_LEN],
+            )?;
+            list.num_items = list.num_items.saturating_sub(1);
         }
-        self.remove_raw(&list_key)?;
 
-        Ok(true)
+        list.head_idx = end_idx.min(list.tail_idx);
+        if list.is_empty() || list.num_items == 0 {
+            self.remove_header(&list_key)?;
+        } else {
+            self.set_header(&list_key, bytes_of(&list))?;
+        }
+
+        self.audit_list_op(list_ph, ListAuditOp::Discard, &[])?;
+        res
     }
 
     /// Returns the first (head) element of the list
@@ -678,6 +1661,57 @@ impl CandyStore {
         Ok(None)
     }
 
+    fn _owned_peek_list_many(&self, list_key: Vec<u8>, n: usize, fwd: bool) -> Result<Vec<KVPair>> {
+        self._operate_on_list(list_key, vec![], |list_ph, _, list| {
+            let mut items = Vec::with_capacity(n.min(list.num_items as usize));
+            let range = list.head_idx..list.tail_idx;
+            let mut indices: Box<dyn Iterator<Item = u64>> = if fwd {
+                Box::new(range)
+            } else {
+                Box::new(range.rev())
+            };
+            while items.len() < n {
+                let Some(idx) = indices.next() else {
+                    break;
+                };
+                if let Some((_, k, v)) = self.get_from_list_at_index(list_ph, idx, true)? {
+                    items.push((k, v));
+                }
+            }
+            Ok(items)
+        })
+    }
+
+    /// Returns up to `n` elements from the head of the list (in order), without removing them. Unlike
+    /// iterating over the list, this takes the list's lock once, so the elements are a consistent snapshot
+    pub fn peek_list_head_many<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        n: usize,
+    ) -> Result<Vec<KVPair>> {
+        self.owned_peek_list_head_many(list_key.as_ref().to_owned(), n)
+    }
+
+    /// Owned version of [Self::peek_list_head_many]
+    pub fn owned_peek_list_head_many(&self, list_key: Vec<u8>, n: usize) -> Result<Vec<KVPair>> {
+        self._owned_peek_list_many(list_key, n, true /* fwd */)
+    }
+
+    /// Returns up to `n` elements from the tail of the list (starting with the last one), without removing
+    /// them. See [Self::peek_list_head_many]
+    pub fn peek_list_tail_many<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        n: usize,
+    ) -> Result<Vec<KVPair>> {
+        self.owned_peek_list_tail_many(list_key.as_ref().to_owned(), n)
+    }
+
+    /// Owned version of [Self::peek_list_tail_many]
+    pub fn owned_peek_list_tail_many(&self, list_key: Vec<u8>, n: usize) -> Result<Vec<KVPair>> {
+        self._owned_peek_list_many(list_key, n, false /* fwd */)
+    }
+
     /// Removes and returns the first (head) element of the list
     pub fn pop_list_head<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<Option<KVPair>> {
         self.owned_pop_list_head(list_key.as_ref().to_owned())
@@ -691,10 +1725,10 @@ impl CandyStore {
     ) -> Result<T> {
         let (list_ph, list_key) = self.make_list_key(list_key);
         let _guard = self.lock_list(list_ph);
-        let Some(list_bytes) = self.get_raw(&list_key)? else {
+        let Some(list_bytes) = self.get_header(&list_key)? else {
             return Ok(default);
         };
-        let list = *from_bytes::<List>(&list_bytes);
+        let list = List::parse(&list_bytes)?;
         func(list_ph, list_key, list)
     }
 
@@ -712,13 +1746,13 @@ impl CandyStore {
                 if fwd {
                     list.head_idx = idx + 1;
                 } else {
-                    list.tail_idx = idx - 1;
+                    list.tail_idx = idx;
                 }
                 list.num_items -= 1;
                 if list.is_empty() {
-                    self.remove_raw(&list_key)?;
+                    self.remove_header(&list_key)?;
                 } else {
-                    self.set_raw(&list_key, bytes_of(&list))?;
+                    self.set_header(&list_key, bytes_of(&list))?;
                 }
 
                 // remove chain
@@ -731,8 +1765,10 @@ impl CandyStore {
                 // remove item
                 self.remove_raw(&untrunc_k)?;
 
-                untrunc_v.truncate(untrunc_v.len() - size_of::<u64>());
+                untrunc_v.truncate(untrunc_v.len() - self.list_item_suffix_len());
                 untrunc_k.truncate(untrunc_k.len() - Self::LIST_KEY_SUFFIX_LEN);
+                self.clear_list_item_ttl(&list_key, &untrunc_k)?;
+                self.audit_list_op(list_ph, ListAuditOp::Pop, &untrunc_k)?;
                 Ok(Some((untrunc_k, untrunc_v)))
             };
 
@@ -776,20 +1812,44 @@ impl CandyStore {
     pub fn owned_list_len(&self, list_key: Vec<u8>) -> Result<usize> {
         let (_, list_key) = self.make_list_key(list_key);
 
-        let Some(list_bytes) = self.get_raw(&list_key)? else {
+        let Some(list_bytes) = self.get_header(&list_key)? else {
             return Ok(0);
         };
 
-        Ok(from_bytes::<List>(&list_bytes).num_items as usize)
+        Ok(List::parse(&list_bytes)?.num_items as usize)
+    }
+
+    /// Returns the list's tail index once the items pushed to the list before the call are durable: the items
+    /// whose indices (see [Self::iter_list_with_indices]) are below it survive a crash from then on, so a producer
+    /// can acknowledge them upstream. Pushes that are in progress are waited for, while the ones that begin
+    /// during the call may or may not be covered. This syncs all shards (like [Self::flush]), so it is best
+    /// called once per batch of pushes. Note that the indices of a list start over once it is discarded
+    pub fn fence_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<u64> {
+        self.owned_fence_list(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::fence_list]
+    pub fn owned_fence_list(&self, list_key: Vec<u8>) -> Result<u64> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let tail_idx = {
+            let _guard = self.lock_list(list_ph);
+            match self.get_header(&list_key)? {
+                Some(list_bytes) => List::parse(&list_bytes)?.tail_idx,
+                None => Self::FIRST_LIST_IDX,
+            }
+        };
+        self.flush()?;
+        Ok(tail_idx)
     }
 
     /// iterate over the given list and retain all elements for which the predicate returns `true`. In other
-    /// words, drop all other elements. This operation is not crash safe, and holds the list locked during the
-    /// whole iteration, so no other gets/sets/deletes can be done in by other threads on this list while
-    /// iterating over it. Beware of deadlocks.
+    /// words, drop all other elements. This operation is not crash safe (unless [crate::Config::list_journal]
+    /// is set), and holds the list locked during the whole iteration, so no other gets/sets/deletes can be done
+    /// in by other threads on this list while iterating over it. Beware of deadlocks.
     ///
     /// This operation will also compact the list, basically popping all elements and re-pushing the retained
-    /// ones at the end, so no holes will exist by the end.
+    /// ones at the end, so no holes will exist by the end. The relative order of the retained elements is
+    /// preserved.
     pub fn retain_in_list<B: AsRef<[u8]> + ?Sized>(
         &self,
         list_key: &B,
@@ -802,62 +1862,215 @@ impl CandyStore {
     pub fn owned_retain_in_list(
         &self,
         list_key: Vec<u8>,
-        mut func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+        func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
     ) -> Result<()> {
-        self._operate_on_list(list_key, (), |list_ph, list_key, mut list| {
-            let range = list.head_idx..list.tail_idx;
+        self._retain_in_list(list_key, None, None, func)
+    }
 
-            for idx in range {
-                list.head_idx = idx + 1;
-                let Some((item_ph, untrunc_k, mut untrunc_v)) =
-                    self.get_from_list_at_index(list_ph, idx, false)?
-                else {
-                    continue;
+    /// Same as [Self::retain_in_list], but fails with [crate::CandyError::Cancelled] once `cancel` is cancelled.
+    /// The list remains consistent: the elements visited up to that point have been retained (moving to the
+    /// tail, after the rest) or dropped, and the rest are kept where they are
+    pub fn retain_in_list_cancellable<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        cancel: &CancellationToken,
+        func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<()> {
+        self._retain_in_list(list_key.as_ref().to_owned(), Some(cancel), None, func)
+    }
+
+    /// Same as [Self::retain_in_list], but reports its progress through `progress`, where the total is the
+    /// list's span (including holes)
+    pub fn retain_in_list_with_progress<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        progress: &Progress,
+        func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<()> {
+        self._retain_in_list(list_key.as_ref().to_owned(), None, Some(progress), func)
+    }
+
+    fn _retain_in_list(
+        &self,
+        list_key: Vec<u8>,
+        cancel: Option<&CancellationToken>,
+        progress: Option<&Progress>,
+        mut func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<()> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let mut guard = self.lock_list(list_ph);
+        let Some(list_bytes) = self.get_header(&list_key)? else {
+            return Ok(());
+        };
+        let mut list = List::parse(&list_bytes)?;
+        let watch = self.watch_header(&list_key);
+        // retained elements are re-pushed past this index, and so are elements pushed while yielding the lock,
+        // neither of which should be visited
+        let mut end_idx = list.tail_idx;
+        let mut idx = list.head_idx;
+        let start_idx = idx;
+        let mut iterations = 0;
+        let mut res = Ok(());
+
+        while idx < end_idx {
+            if let Err(e) = CancellationToken::check(cancel) {
+                res = Err(e);
+                break;
+            }
+            if self.should_yield(&mut iterations) {
+                self.set_header(&list_key, bytes_of(&list))?;
+                Self::yield_list_lock(&mut guard);
+                // likewise, a list that was created again meanwhile is not ours to retain
+                if watch.was_removed() {
+                    return Ok(());
+                }
+                let Some(list_bytes) = self.get_header(&list_key)? else {
+                    return Ok(());
                 };
+                list = List::parse(&list_bytes)?;
+                idx = idx.max(list.head_idx);
+                // elements popped from the tail meanwhile free their indices for re-pushing
+                end_idx = end_idx.min(list.tail_idx);
+                continue;
+            }
 
-                untrunc_v.truncate(untrunc_v.len() - size_of::<u64>());
-                let mut v = untrunc_v;
-                let k = &untrunc_k[..untrunc_k.len() - Self::LIST_KEY_SUFFIX_LEN];
+            let curr_idx = idx;
+            idx += 1;
+            list.head_idx = idx;
+            Progress::report(progress, idx - start_idx, end_idx - start_idx);
+            let Some((item_ph, untrunc_k, mut untrunc_v)) =
+                self.get_from_list_at_index(list_ph, curr_idx, false)?
+            else {
+                continue;
+            };
 
-                // remove chain
-                self.remove_raw(bytes_of(&ChainKey {
-                    list_ph,
-                    idx,
-                    namespace: CHAIN_NAMESPACE,
-                }))?;
+            // keep the item's metadata (if any), the index is replaced below
+            let meta = untrunc_v.split_off(untrunc_v.len() - self.list_item_suffix_len());
+            let mut v = untrunc_v;
+            let k = &untrunc_k[..untrunc_k.len() - Self::LIST_KEY_SUFFIX_LEN];
 
-                if func(k, &v)? {
-                    let tail_idx = list.tail_idx;
-                    list.tail_idx += 1;
-
-                    // create chain
-                    self.set_raw(
-                        bytes_of(&ChainKey {
-                            list_ph,
-                            idx: tail_idx,
-                            namespace: CHAIN_NAMESPACE,
-                        }),
-                        bytes_of(&item_ph),
-                    )?;
-
-                    // create new item
-                    v.extend_from_slice(bytes_of(&tail_idx));
-                    self.set_raw(&untrunc_k, &v)?;
-                } else {
-                    // drop from list
-                    list.num_items -= 1;
+            // remove chain
+            self.remove_raw(bytes_of(&ChainKey {
+                list_ph,
+                idx: curr_idx,
+                namespace: CHAIN_NAMESPACE,
+            }))?;
 
-                    // remove item
-                    self.remove_raw(&untrunc_k)?;
-                }
-            }
-            // defer updating the list to the very end to save on IOs
-            if list.is_empty() {
-                self.remove_raw(&list_key)?;
+            if func(k, &v)? {
+                let tail_idx = list.tail_idx;
+                list.tail_idx += 1;
+
+                // create chain
+                self.set_raw(
+                    bytes_of(&ChainKey {
+                        list_ph,
+                        idx: tail_idx,
+                        namespace: CHAIN_NAMESPACE,
+                    }),
+                    bytes_of(&item_ph),
+                )?;
+
+                // create new item
+                v.extend_from_slice(&meta[..meta.len() - size_of::<u64>()]);
+                v.extend_from_slice(bytes_of(&tail_idx));
+                self.set_raw(&untrunc_k, &v)?;
             } else {
-                self.set_raw(&list_key, bytes_of(&list))?;
+                // drop from list
+                list.num_items -= 1;
+
+                // remove item
+                self.remove_raw(&untrunc_k)?;
+                self.clear_list_item_ttl(&list_key, k)?;
+                self.audit_list_op(list_ph, ListAuditOp::Remove, k)?;
             }
-            Ok(())
-        })
+        }
+        // defer updating the list to the very end to save on IOs
+        if list.is_empty() {
+            self.remove_header(&list_key)?;
+        } else {
+            self.set_header(&list_key, bytes_of(&list))?;
+        }
+        res
+    }
+
+    /// Reports what [Self::discard_list] would remove, without removing anything
+    pub fn dry_run_discard_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+    ) -> Result<DryRunReport> {
+        self.owned_dry_run_discard_list(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::dry_run_discard_list]
+    pub fn owned_dry_run_discard_list(&self, list_key: Vec<u8>) -> Result<DryRunReport> {
+        self.owned_dry_run_retain_in_list(list_key, |_, _| Ok(false))
+    }
+
+    /// Reports what [Self::retain_in_list] would drop (the elements for which the predicate returns `false`),
+    /// without mutating the list. The list is locked while iterating, same as with [Self::retain_in_list]
+    pub fn dry_run_retain_in_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<DryRunReport> {
+        self.owned_dry_run_retain_in_list(list_key.as_ref().to_owned(), func)
+    }
+
+    /// Owned version of [Self::dry_run_retain_in_list]
+    pub fn owned_dry_run_retain_in_list(
+        &self,
+        list_key: Vec<u8>,
+        mut func: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<DryRunReport> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let _guard = self.lock_list(list_ph);
+
+        let mut report = DryRunReport::default();
+        let Some(list_bytes) = self.get_header(&list_key)? else {
+            return Ok(report);
+        };
+        let list = List::parse(&list_bytes)?;
+        for idx in list.head_idx..list.tail_idx {
+            let Some((_, full_k, full_v)) = self.get_from_list_at_index(list_ph, idx, false)?
+            else {
+                continue;
+            };
+            let k = &full_k[..full_k.len() - Self::LIST_KEY_SUFFIX_LEN];
+            let v = &full_v[..full_v.len() - self.list_item_suffix_len()];
+            if !func(k, v)? {
+                report.add(&full_k, &full_v);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Reports what [Self::compact_list_if_needed] would rewrite given these `params`, without compacting.
+    /// Returns `None` if the list would not be compacted
+    pub fn dry_run_compact_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        params: ListCompactionParams,
+    ) -> Result<Option<DryRunReport>> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+
+        let Some(list_bytes) = self.get_header(&list_key)? else {
+            return Ok(None);
+        };
+        let list = List::parse(&list_bytes)?;
+        if list.span_len() < params.min_length {
+            return Ok(None);
+        }
+        if (list.holes() as f64) < (list.span_len() as f64) * params.min_holes_ratio {
+            return Ok(None);
+        }
+
+        let mut report = DryRunReport::default();
+        for idx in list.head_idx..list.tail_idx {
+            if let Some((_, full_k, full_v)) = self.get_from_list_at_index(list_ph, idx, false)? {
+                report.add(&full_k, &full_v);
+            }
+        }
+        Ok(Some(report))
     }
 }
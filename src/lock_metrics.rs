@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{hashing::PartedHash, CandyStore, Result};
+
+/// A lock that operations had to wait for, see [CandyStore::top_contended_locks]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContendedLock {
+    /// the keyed lock of a list or queue, identified by the hash of its key (see [CandyStore::list_lock_of] and
+    /// [CandyStore::queue_lock_of])
+    List(u64),
+    /// the lock of a row in a shard, which covers all the keys that map to that row (see
+    /// [CandyStore::row_lock_of]). The shard is identified by the first shard selector of its span, so the rows
+    /// of a shard get new identities once it is split or merged
+    Row { shard: u32, row: usize },
+}
+
+/// The time operations spent waiting for a single lock, see [CandyStore::top_contended_locks]. Only
+/// acquisitions that had to wait are counted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockContention {
+    pub lock: ContendedLock,
+    /// the number of acquisitions that had to wait
+    pub num_waits: usize,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+// bounds the memory taken by the tracking, the least contended locks are evicted first
+const MAX_TRACKED_LOCKS: usize = 4096;
+
+#[derive(Debug, Default)]
+pub(crate) struct LockWaits(Mutex<HashMap<ContendedLock, LockContention>>);
+
+impl LockWaits {
+    // called once a contended lock has been acquired, with the time the wait started
+    pub(crate) fn record(&self, lock: ContendedLock, since: Instant) {
+        let wait = since.elapsed();
+        let mut waits = self.0.lock();
+        if waits.len() >= MAX_TRACKED_LOCKS && !waits.contains_key(&lock) {
+            let coldest = waits.values().min_by_key(|c| c.total_wait).map(|c| c.lock);
+            if let Some(coldest) = coldest {
+                waits.remove(&coldest);
+            }
+        }
+        let contention = waits.entry(lock).or_insert_with(|| LockContention {
+            lock,
+            num_waits: 0,
+            total_wait: Duration::ZERO,
+            max_wait: Duration::ZERO,
+        });
+        contention.num_waits += 1;
+        contention.total_wait += wait;
+        contention.max_wait = contention.max_wait.max(wait);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    fn top(&self, n: usize) -> Vec<LockContention> {
+        let mut top = self.0.lock().values().cloned().collect::<Vec<_>>();
+        top.sort_unstable_by_key(|c| std::cmp::Reverse(c.total_wait));
+        top.truncate(n);
+        top
+    }
+}
+
+impl CandyStore {
+    /// Returns the (up to) `n` locks that operations spent the most time waiting for, since the store was opened
+    /// or [Self::clear]ed, most contended first. Use [Self::list_lock_of], [Self::queue_lock_of] and
+    /// [Self::row_lock_of] to check whether a given list, queue or key is behind a contended lock.
+    ///
+    /// Waits on the keyed locks of lists and queues and on the row locks of shards are tracked. Keep in mind that
+    /// a keyed lock may be shared by several lists (see [crate::Config::max_concurrent_list_ops]), and a row lock
+    /// covers many keys
+    pub fn top_contended_locks(&self, n: usize) -> Vec<LockContention> {
+        self.0.stats.lock_waits.top(n)
+    }
+
+    /// Returns the lock that operations on the given list take
+    pub fn list_lock_of<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> ContendedLock {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        ContendedLock::List(list_ph.as_u64())
+    }
+
+    /// Returns the lock that operations on the given queue take
+    pub fn queue_lock_of<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> ContendedLock {
+        let (queue_ph, _) = self.make_queue_key(queue_key.as_ref());
+        ContendedLock::List(queue_ph.as_u64())
+    }
+
+    /// Returns the row lock that operations on the given key currently take
+    pub fn row_lock_of<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<ContendedLock> {
+        let full_key = self.make_user_key(key.as_ref().to_owned())?;
+        let ph = PartedHash::new(&self.0.config.hash_seed, &full_key);
+        self.0.root.shared_op(ph.shard_selector(), |sh| {
+            Ok(ContendedLock::Row {
+                shard: sh.span.start,
+                row: ph.row_selector(),
+            })
+        })
+    }
+}
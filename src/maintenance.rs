@@ -0,0 +1,62 @@
+use std::{ops::Range, sync::Arc, time::Duration};
+
+use parking_lot::RwLock;
+
+use crate::CandyStore;
+
+/// Receives notifications about the store's internal maintenance work, e.g., so that applications can pause
+/// their own heavy jobs while the store is busy reorganizing. Register one using
+/// [CandyStore::set_maintenance_observer]. Shards are identified by their span (the range of hash bits they
+/// cover), and all methods have empty default implementations.
+///
+/// Notifications are delivered synchronously, from whichever thread performs the work (including the
+/// background compaction threads), possibly while internal locks are held. Implementations should therefore
+/// return quickly and must not access the store
+pub trait MaintenanceObserver: Send + Sync {
+    /// a shard is about to be split in two (operations on this shard block until the split ends)
+    fn on_split_start(&self, _span: Range<u32>) {}
+    /// a shard has been split, moving `bytes_moved` bytes into the two new shards
+    fn on_split_end(&self, _span: Range<u32>, _bytes_moved: u64, _duration: Duration) {}
+    /// a shard is about to be compacted in the background
+    fn on_compaction_start(&self, _span: Range<u32>) {}
+    /// a shard has been compacted from `prev_size` bytes into `new_size` bytes
+    fn on_compaction_end(
+        &self,
+        _span: Range<u32>,
+        _prev_size: u64,
+        _new_size: u64,
+        _duration: Duration,
+    ) {
+    }
+    /// a list has been compacted (see [CandyStore::compact_list_if_needed]), re-indexing `num_items` items
+    fn on_list_compaction(&self, _list_key: &[u8], _num_items: u64) {}
+}
+
+#[derive(Default)]
+pub(crate) struct MaintenanceObserverSlot(RwLock<Option<Arc<dyn MaintenanceObserver>>>);
+
+impl std::fmt::Debug for MaintenanceObserverSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MaintenanceObserverSlot")
+            .field(&self.0.read().is_some())
+            .finish()
+    }
+}
+
+impl MaintenanceObserverSlot {
+    pub(crate) fn notify(&self, func: impl FnOnce(&dyn MaintenanceObserver)) {
+        // clone the observer out, so a slow observer does not block replacing it
+        let observer = self.0.read().clone();
+        if let Some(observer) = observer {
+            func(&*observer);
+        }
+    }
+}
+
+impl CandyStore {
+    /// Registers an observer for the store's maintenance work (shard splits and compactions, list
+    /// compactions), replacing the previous one. Pass `None` to unregister it
+    pub fn set_maintenance_observer(&self, observer: Option<Arc<dyn MaintenanceObserver>>) {
+        *self.0.stats.maintenance_observer.0.write() = observer;
+    }
+}
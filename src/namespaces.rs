@@ -0,0 +1,305 @@
+use crate::{
+    router::ShardRouter,
+    shard::NUM_ROWS,
+    store::{
+        BLOB_NAMESPACE, CHAIN_NAMESPACE, DEDUP_NAMESPACE, EPHEMERAL_NAMESPACE, EXPIRY_NAMESPACE,
+        GEO_NAMESPACE, GRAPH_NAMESPACE, INTERNED_NAMESPACE, INTERN_TABLE_NAMESPACE,
+        INVERTED_INDEX_NAMESPACE, ITEM_NAMESPACE, JOURNAL_NAMESPACE, KEY_HISTORY_NAMESPACE,
+        LIST_AUDIT_NAMESPACE, LIST_CURSOR_NAMESPACE, LIST_NAMESPACE, NUMERIC_INDEX_NAMESPACE,
+        POP_TOKEN_NAMESPACE, QUEUE_ITEM_NAMESPACE, QUEUE_NAMESPACE, SCOPED_TYPED_NAMESPACE,
+        SESSIONS_NAMESPACE, TYPED_NAMESPACE, TYPE_REGISTRY_NAMESPACE, USER_NAMESPACE,
+    },
+    CandyStore, CandyTypedKey, Result,
+};
+use bytemuck::bytes_of;
+
+/// The internal namespaces that entries of the store belong to. Each API (plain keys, typed stores, lists,
+/// queues, etc.) keeps its entries in its own namespace, so they never collide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// keys set with [CandyStore::set] and friends
+    User,
+    /// keys of [crate::CandyTypedStore]s
+    Typed,
+    /// list headers (one per list)
+    List,
+    /// list items
+    ListItem,
+    /// list chain entries (one per list item, mapping its index to the item)
+    ListChain,
+    /// queue headers (one per queue)
+    Queue,
+    /// queue items
+    QueueItem,
+    /// ephemeral keys (see [CandyStore::ephemeral])
+    Ephemeral,
+    /// sessions (see [CandyStore::register_session])
+    Sessions,
+    /// deduplicated values (see [CandyStore::set_dedup])
+    Dedup,
+    /// blobs (see [CandyStore::put_blob])
+    Blob,
+    /// graph edges (see [crate::CandyGraph])
+    Graph,
+    /// inverted index postings (see [crate::CandyInvertedIndex])
+    InvertedIndex,
+    /// geo index entries (see [CandyStore::geo_insert])
+    Geo,
+    /// numeric index entries (see [CandyStore::index_numeric])
+    NumericIndex,
+    /// values of interned keys (see [crate::Config::intern_keys_longer_than])
+    Interned,
+    /// the interning table, which maps interned keys to their ids and back
+    InternTable,
+    /// named list cursors (see [CandyStore::save_list_cursor])
+    ListCursor,
+    /// the registry of typed-key `TYPE_ID`s (see [CandyStore::register_type])
+    TypeRegistry,
+    /// the audit logs of lists (see [CandyStore::enable_list_audit])
+    ListAudit,
+    /// the tokens of exactly-once pops (see [CandyStore::pop_list_head_idempotent])
+    PopToken,
+    /// the histories of keys (see [CandyStore::enable_key_history])
+    KeyHistory,
+    /// keys of [crate::CandyTypedStore]s that are scoped to a namespace (see [crate::CandyTypedStore::in_namespace])
+    ScopedTyped,
+    /// the expiry times of keys and list items (see [CandyStore::set_with_ttl])
+    Expiry,
+    /// the journal of list operations (see [crate::Config::list_journal])
+    Journal,
+}
+
+impl Namespace {
+    /// All namespaces
+    pub const ALL: [Namespace; 25] = [
+        Self::User,
+        Self::Typed,
+        Self::List,
+        Self::ListItem,
+        Self::ListChain,
+        Self::Queue,
+        Self::QueueItem,
+        Self::Ephemeral,
+        Self::Sessions,
+        Self::Dedup,
+        Self::Blob,
+        Self::Graph,
+        Self::InvertedIndex,
+        Self::Geo,
+        Self::NumericIndex,
+        Self::Interned,
+        Self::InternTable,
+        Self::ListCursor,
+        Self::TypeRegistry,
+        Self::ListAudit,
+        Self::PopToken,
+        Self::KeyHistory,
+        Self::ScopedTyped,
+        Self::Expiry,
+        Self::Journal,
+    ];
+
+    // the byte that keys of this namespace end with
+    pub(crate) const fn suffix(&self) -> u8 {
+        match self {
+            Self::User => USER_NAMESPACE[0],
+            Self::Typed => TYPED_NAMESPACE[0],
+            Self::List => LIST_NAMESPACE[0],
+            Self::ListItem => ITEM_NAMESPACE[0],
+            Self::ListChain => CHAIN_NAMESPACE,
+            Self::Queue => QUEUE_NAMESPACE[0],
+            Self::QueueItem => QUEUE_ITEM_NAMESPACE[0],
+            Self::Ephemeral => EPHEMERAL_NAMESPACE[0],
+            Self::Sessions => SESSIONS_NAMESPACE[0],
+            Self::Dedup => DEDUP_NAMESPACE[0],
+            Self::Blob => BLOB_NAMESPACE[0],
+            Self::Graph => GRAPH_NAMESPACE[0],
+            Self::InvertedIndex => INVERTED_INDEX_NAMESPACE[0],
+            Self::Geo => GEO_NAMESPACE[0],
+            Self::NumericIndex => NUMERIC_INDEX_NAMESPACE[0],
+            Self::Interned => INTERNED_NAMESPACE[0],
+            Self::InternTable => INTERN_TABLE_NAMESPACE[0],
+            Self::ListCursor => LIST_CURSOR_NAMESPACE[0],
+            Self::TypeRegistry => TYPE_REGISTRY_NAMESPACE[0],
+            Self::ListAudit => LIST_AUDIT_NAMESPACE[0],
+            Self::PopToken => POP_TOKEN_NAMESPACE[0],
+            Self::KeyHistory => KEY_HISTORY_NAMESPACE[0],
+            Self::ScopedTyped => SCOPED_TYPED_NAMESPACE[0],
+            Self::Expiry => EXPIRY_NAMESPACE[0],
+            Self::Journal => JOURNAL_NAMESPACE[0],
+        }
+    }
+
+    pub(crate) fn from_suffix(suffix: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|ns| ns.suffix() == suffix)
+    }
+}
+
+// every entry of the store ends with exactly one namespace byte, which is what keeps arbitrary binary user
+// keys from being confused with internal entries (e.g., a user key ending with `LIST_NAMESPACE` is still
+// followed by `USER_NAMESPACE`). This only holds as long as no two namespaces share a byte, so make adding a
+// namespace with a taken byte fail to compile
+const _: () = {
+    let mut i = 0;
+    while i < Namespace::ALL.len() {
+        let mut j = i + 1;
+        while j < Namespace::ALL.len() {
+            assert!(
+                Namespace::ALL[i].suffix() != Namespace::ALL[j].suffix(),
+                "namespaces must have distinct suffixes"
+            );
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+/// Usage statistics of a namespace (or a typed store), see [CandyStore::namespace_stats]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NamespaceStats {
+    /// the (estimated) number of entries
+    pub num_items: usize,
+    /// the (estimated) number of bytes the entries take up (keys and values, as stored)
+    pub num_bytes: usize,
+    /// the fraction of each shard's rows that were scanned, the counts above are extrapolated from them.
+    /// 1.0 means the counts are exact
+    pub sampled_fraction: f64,
+}
+
+impl NamespaceStats {
+    fn extrapolate(num_items: usize, num_bytes: usize, sampled_fraction: f64) -> Self {
+        Self {
+            num_items: (num_items as f64 / sampled_fraction).round() as usize,
+            num_bytes: (num_bytes as f64 / sampled_fraction).round() as usize,
+            sampled_fraction,
+        }
+    }
+}
+
+/// The outcome of [CandyStore::drop_namespace]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DroppedNamespace {
+    /// the number of entries removed
+    pub num_items: usize,
+    /// the number of shards that held only entries of the namespace, and were therefore replaced by empty ones
+    /// rather than having their entries removed one by one
+    pub num_shards_emptied: usize,
+}
+
+impl CandyStore {
+    // scans (a sample of) the rows of every shard, calling `func` on each entry's key and stored value length
+    fn scan_sampled_rows(
+        &self,
+        sample_ratio: f64,
+        mut func: impl FnMut(&[u8], usize),
+    ) -> Result<f64> {
+        let num_rows = ((NUM_ROWS as f64 * sample_ratio).ceil() as usize).clamp(1, NUM_ROWS);
+        let mut shard_selector = 0;
+        while shard_selector < ShardRouter::END_OF_SHARDS {
+            shard_selector = self.0.root.shared_op(shard_selector, |sh| {
+                for row_idx in 0..num_rows {
+                    sh.scan_row(row_idx, &mut func)?;
+                }
+                Ok(sh.span.end)
+            })?;
+        }
+        Ok(num_rows as f64 / NUM_ROWS as f64)
+    }
+
+    fn collect_namespace_stats(
+        &self,
+        sample_ratio: f64,
+        mut matches: impl FnMut(&[u8]) -> bool,
+    ) -> Result<NamespaceStats> {
+        let mut num_items = 0usize;
+        let mut num_bytes = 0usize;
+        let sampled_fraction = self.scan_sampled_rows(sample_ratio, |k, vlen| {
+            if matches(k) {
+                num_items += 1;
+                num_bytes += k.len() + vlen;
+            }
+        })?;
+        Ok(NamespaceStats::extrapolate(
+            num_items,
+            num_bytes,
+            sampled_fraction,
+        ))
+    }
+
+    /// Returns the number of entries in the given namespace and the bytes they take up. Since this requires
+    /// reading the keys, only a fraction (`sample_ratio`, between 0 and 1) of the rows of each shard is scanned,
+    /// and the numbers are extrapolated from them. Keys are spread uniformly across rows, so even small ratios
+    /// give good estimates for large namespaces; use 1.0 for exact numbers.
+    pub fn namespace_stats(&self, ns: Namespace, sample_ratio: f64) -> Result<NamespaceStats> {
+        let suffix = ns.suffix();
+        self.collect_namespace_stats(sample_ratio, |k| k.last() == Some(&suffix))
+    }
+
+    /// Same as [Self::namespace_stats], but for each of the namespaces, requiring only a single scan
+    pub fn all_namespace_stats(
+        &self,
+        sample_ratio: f64,
+    ) -> Result<Vec<(Namespace, NamespaceStats)>> {
+        let mut counts = [(0usize, 0usize); 256];
+        let sampled_fraction = self.scan_sampled_rows(sample_ratio, |k, vlen| {
+            if let Some(&suffix) = k.last() {
+                counts[suffix as usize].0 += 1;
+                counts[suffix as usize].1 += k.len() + vlen;
+            }
+        })?;
+        Ok(Namespace::ALL
+            .iter()
+            .map(|&ns| {
+                let (num_items, num_bytes) = counts[ns.suffix() as usize];
+                (
+                    ns,
+                    NamespaceStats::extrapolate(num_items, num_bytes, sampled_fraction),
+                )
+            })
+            .collect())
+    }
+
+    /// Same as [Self::namespace_stats], but counts only the entries of typed stores ([crate::CandyTypedStore])
+    /// whose key type is `K`. Note that stores that share the key type but differ in value type share the
+    /// same keyspace, so they are counted together
+    pub fn typed_stats<K: CandyTypedKey, V>(&self, sample_ratio: f64) -> Result<NamespaceStats> {
+        let mut suffix = bytes_of(&K::TYPE_ID).to_vec();
+        suffix.extend_from_slice(TYPED_NAMESPACE);
+        self.collect_namespace_stats(sample_ratio, |k| k.ends_with(&suffix))
+    }
+
+    /// Removes all entries of the given namespace, e.g., all plain keys ([Namespace::User]) or all typed-store
+    /// entries ([Namespace::Typed]). Shards that hold only entries of the namespace (which is common once a
+    /// namespace dominates the store) are replaced by empty ones, like [Self::clear] does, while the entries of
+    /// other shards are removed in place. Either way, each shard is scanned once while it is locked, which is much
+    /// faster than iterating over the entries and removing them.
+    ///
+    /// Note that entries of other namespaces that refer to the dropped ones are left as they are, e.g.,
+    /// dropping [Namespace::ListItem] leaves the lists' headers in place. In-memory state kept for the namespace
+    /// (pinned list headers, interned keys, list audits, key histories and queue metrics) is reset. Writes to the namespace
+    /// that run concurrently may or may not survive
+    pub fn drop_namespace(&self, ns: Namespace) -> Result<DroppedNamespace> {
+        let suffix = ns.suffix();
+        let mut dropped = DroppedNamespace::default();
+        let mut shard_selector = 0;
+        while shard_selector < ShardRouter::END_OF_SHARDS {
+            shard_selector = self.0.root.shared_op(shard_selector, |sh| {
+                let (num_items, emptied) = sh.drop_suffix(suffix)?;
+                dropped.num_items += num_items;
+                dropped.num_shards_emptied += emptied as usize;
+                Ok(sh.span.end)
+            })?;
+        }
+
+        match ns {
+            Namespace::List => self.reset_pinned_headers(),
+            Namespace::Interned | Namespace::InternTable => self.0.interner.clear(),
+            Namespace::ListAudit => self.0.list_audits.clear(),
+            Namespace::KeyHistory => self.0.key_histories.clear(),
+            #[cfg(feature = "metrics")]
+            Namespace::Queue | Namespace::QueueItem => self.0.stats.queue_activity.clear(),
+            _ => {}
+        }
+        Ok(dropped)
+    }
+}
@@ -0,0 +1,188 @@
+use std::ops::{Bound, RangeBounds};
+
+use crate::{store::NUMERIC_INDEX_NAMESPACE, CandyError, CandyStore, Result, SetStatus};
+
+const VALUE_KIND: u8 = 0;
+const BUCKET_KIND: u8 = 1;
+const DIRECTORY_KIND: u8 = 2;
+
+// maps floats to integers of the same order: positive numbers get their sign bit set, while negative ones
+// are inverted
+fn sortable_bits(value: f64) -> u64 {
+    let bits = value.to_bits();
+    if bits >> 63 == 0 {
+        bits | (1 << 63)
+    } else {
+        !bits
+    }
+}
+
+// the bucket is made of the sign, the exponent and the top 4 bits of the mantissa, so every power of two is
+// split into 16 buckets, regardless of the scale of the values
+fn bucket_of(value: f64) -> u16 {
+    (sortable_bits(value) >> 48) as u16
+}
+
+fn decode_value(buf: &[u8]) -> Result<f64> {
+    match buf.try_into() {
+        Ok(bytes) => Ok(f64::from_le_bytes(bytes)),
+        Err(_) => Err(CandyError::Corruption(format!(
+            "bad numeric index value {buf:?}"
+        ))),
+    }
+}
+
+impl CandyStore {
+    // all keys of an index end with the index name, its length, the kind of key, and the namespace
+    fn make_numeric_index_key(index: &[u8], kind: u8, key: &[u8]) -> Vec<u8> {
+        let mut full_key = Vec::with_capacity(
+            key.len() + index.len() + size_of::<u32>() + 1 + NUMERIC_INDEX_NAMESPACE.len(),
+        );
+        full_key.extend_from_slice(key);
+        full_key.extend_from_slice(index);
+        full_key.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        full_key.push(kind);
+        full_key.extend_from_slice(NUMERIC_INDEX_NAMESPACE);
+        full_key
+    }
+
+    /// Sets the numeric value of `key` in the given numeric index (e.g., the price of an item), so it can be
+    /// found by [Self::query_numeric_range]. This is meant to be called alongside the writes of the item itself.
+    /// Returns the previous value of the key in this index, if any.
+    ///
+    /// Values are kept in buckets (lists) by their magnitude, and a query only scans the buckets that overlap
+    /// its range. The index is a keyspace of its own, so removing the item does not remove it from the index,
+    /// see [Self::unindex_numeric]
+    pub fn index_numeric<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        index: &B1,
+        key: &B2,
+        value: f64,
+    ) -> Result<Option<f64>> {
+        let (index, key) = (index.as_ref(), key.as_ref());
+        if value.is_nan() {
+            return Err(CandyError::InvalidArgument(
+                "cannot index a NaN value".into(),
+            ));
+        }
+        Self::ensure_sizes(key, &[])?;
+        // normalize -0.0, so it goes into the same bucket as 0.0
+        let value = if value == 0.0 { 0.0 } else { value };
+
+        // the value itself is written first, and queries ignore bucket entries that do not match it, so a
+        // crash in the middle leaves (at most) stale bucket entries behind
+        let value_bytes = value.to_le_bytes();
+        let prev = match self.set_raw(
+            &Self::make_numeric_index_key(index, VALUE_KIND, key),
+            &value_bytes,
+        )? {
+            SetStatus::PrevValue(prev) => Some(decode_value(&prev)?),
+            SetStatus::CreatedNew => None,
+        };
+
+        let bucket = bucket_of(value);
+        self.owned_set_in_list(
+            Self::make_numeric_index_key(index, DIRECTORY_KIND, &[]),
+            bucket.to_be_bytes().to_vec(),
+            vec![],
+            false,
+        )?;
+        self.owned_set_in_list(
+            Self::make_numeric_index_key(index, BUCKET_KIND, &bucket.to_be_bytes()),
+            key.to_owned(),
+            value_bytes.to_vec(),
+            false,
+        )?;
+        if let Some(prev) = prev {
+            if bucket_of(prev) != bucket {
+                self.owned_remove_from_list(
+                    Self::make_numeric_index_key(
+                        index,
+                        BUCKET_KIND,
+                        &bucket_of(prev).to_be_bytes(),
+                    ),
+                    key.to_owned(),
+                )?;
+            }
+        }
+        Ok(prev)
+    }
+
+    /// Removes `key` from the given numeric index, returning its value if it was indexed
+    pub fn unindex_numeric<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        index: &B1,
+        key: &B2,
+    ) -> Result<Option<f64>> {
+        let (index, key) = (index.as_ref(), key.as_ref());
+        let Some(prev) = self.remove_raw(&Self::make_numeric_index_key(index, VALUE_KIND, key))?
+        else {
+            return Ok(None);
+        };
+        let prev = decode_value(&prev)?;
+        self.owned_remove_from_list(
+            Self::make_numeric_index_key(index, BUCKET_KIND, &bucket_of(prev).to_be_bytes()),
+            key.to_owned(),
+        )?;
+        Ok(Some(prev))
+    }
+
+    /// Returns the keys whose value (in the given numeric index) falls within `range`, along with their values,
+    /// sorted by value. Only the buckets overlapping the range are scanned
+    pub fn query_numeric_range<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        index: &B,
+        range: impl RangeBounds<f64>,
+    ) -> Result<Vec<(Vec<u8>, f64)>> {
+        let index = index.as_ref();
+        let min_bucket = match range.start_bound() {
+            Bound::Included(&v) | Bound::Excluded(&v) => bucket_of(v),
+            Bound::Unbounded => u16::MIN,
+        };
+        let max_bucket = match range.end_bound() {
+            Bound::Included(&v) | Bound::Excluded(&v) => bucket_of(v),
+            Bound::Unbounded => u16::MAX,
+        };
+
+        // the directory holds every bucket that was ever used by this index (buckets that become empty are not
+        // removed from it, as that would race with concurrent insertions)
+        let mut buckets = vec![];
+        for res in self.owned_iter_list(Self::make_numeric_index_key(index, DIRECTORY_KIND, &[])) {
+            let (bucket, _) = res?;
+            let bucket = u16::from_be_bytes(bucket.try_into().map_err(|bucket| {
+                CandyError::Corruption(format!("bad numeric index bucket {bucket:?}"))
+            })?);
+            if (min_bucket..=max_bucket).contains(&bucket) {
+                buckets.push(bucket);
+            }
+        }
+        buckets.sort();
+
+        let mut matches = vec![];
+        for bucket in buckets {
+            let first = matches.len();
+            for res in self.owned_iter_list(Self::make_numeric_index_key(
+                index,
+                BUCKET_KIND,
+                &bucket.to_be_bytes(),
+            )) {
+                let (key, value_bytes) = res?;
+                let value = decode_value(&value_bytes)?;
+                if !range.contains(&value) {
+                    continue;
+                }
+                // skip stale entries, left by a crash in the middle of updating the value
+                if self
+                    .get_raw(&Self::make_numeric_index_key(index, VALUE_KIND, &key))?
+                    .as_deref()
+                    != Some(&value_bytes[..])
+                {
+                    continue;
+                }
+                matches.push((key, value));
+            }
+            matches[first..].sort_by(|a, b| a.1.total_cmp(&b.1));
+        }
+        Ok(matches)
+    }
+}
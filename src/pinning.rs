@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bytemuck::{bytes_of, from_bytes_mut};
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+    hashing::PartedHash,
+    lists::{ChainKey, List},
+    queues::Queue,
+    shard::InsertMode,
+    store::{StoreInner, CHAIN_NAMESPACE},
+    CandyStore, GetOrCreateStatus, Result,
+};
+
+struct PinnedHeader {
+    // None means the list/queue does not exist (or has been removed)
+    header: Option<Vec<u8>>,
+    dirty: bool,
+    last_write_back: Instant,
+    max_staleness: Duration,
+}
+
+/// In-memory cache of list/queue headers that have been pinned, keyed by the full key of the header. The outer
+/// lock is only taken for writing when pinning/unpinning, while each header is protected by its own mutex
+/// (header operations also hold the list's keyed lock, so these mutexes are never contended in practice)
+#[derive(Default)]
+pub(crate) struct PinnedHeaders {
+    headers: RwLock<HashMap<Vec<u8>, Mutex<PinnedHeader>>>,
+    // the headers watched by HeaderWatch: the number of watches and the number of times the header was removed
+    watched: Mutex<HashMap<Vec<u8>, (usize, u64)>>,
+}
+
+/// Tells whether a header was removed since the watch began. Operations that yield a list's lock midway use it
+/// to tell whether the list they see after re-acquiring the lock is the one they started with, rather than a
+/// list that was removed and created again under the same key meanwhile
+pub(crate) struct HeaderWatch<'a> {
+    store: &'a CandyStore,
+    full_key: Vec<u8>,
+    num_removals: u64,
+}
+
+impl HeaderWatch<'_> {
+    pub(crate) fn was_removed(&self) -> bool {
+        self.store.0.pinned.watched.lock()[&self.full_key].1 != self.num_removals
+    }
+}
+
+impl Drop for HeaderWatch<'_> {
+    fn drop(&mut self) {
+        let mut watched = self.store.0.pinned.watched.lock();
+        let entry = watched.get_mut(&self.full_key).unwrap();
+        entry.0 -= 1;
+        if entry.0 == 0 {
+            watched.remove(&self.full_key);
+        }
+    }
+}
+
+impl StoreInner {
+    // called when the last handle is dropped, so nothing else can write anymore: the dirty headers are written
+    // to the shards directly, without going through the (per-thread) journal
+    pub(crate) fn write_back_pinned_headers_on_close(&mut self) -> Result<()> {
+        for (full_key, pinned) in self.pinned.headers.get_mut().iter_mut() {
+            let pinned = pinned.get_mut();
+            if !pinned.dirty {
+                continue;
+            }
+            let ph = PartedHash::new(&self.config.hash_seed, full_key);
+            match pinned.header {
+                Some(ref header) => {
+                    self.root.insert(ph, full_key, header, InsertMode::Set)?;
+                }
+                None => {
+                    self.root
+                        .shared_op(ph.shard_selector(), |sh| sh.remove(ph, full_key, None))?;
+                }
+            }
+            pinned.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl CandyStore {
+    fn write_back_header(&self, full_key: &[u8], pinned: &mut PinnedHeader) -> Result<()> {
+        if pinned.dirty {
+            self.unisolated(|| match pinned.header {
+                Some(ref header) => self.set_raw(full_key, header).map(|_| ()),
+                None => self.remove_raw(full_key).map(|_| ()),
+            })?;
+            pinned.dirty = false;
+        }
+        pinned.last_write_back = Instant::now();
+        Ok(())
+    }
+
+    pub(crate) fn get_header(&self, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        {
+            let headers = self.0.pinned.headers.read();
+            if let Some(pinned) = headers.get(full_key) {
+                return Ok(pinned.lock().header.clone());
+            }
+        }
+        self.get_raw(full_key)
+    }
+
+    pub(crate) fn set_header(&self, full_key: &[u8], header: &[u8]) -> Result<()> {
+        {
+            let headers = self.0.pinned.headers.read();
+            if let Some(pinned) = headers.get(full_key) {
+                let mut pinned = pinned.lock();
+                // creation is written through, so the list can be found (and repaired) after a crash
+                let created = pinned.header.is_none();
+                pinned.header = Some(header.to_owned());
+                pinned.dirty = true;
+                if created || pinned.last_write_back.elapsed() >= pinned.max_staleness {
+                    self.write_back_header(full_key, &mut pinned)?;
+                }
+                return Ok(());
+            }
+        }
+        self.set_raw(full_key, header)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_header(&self, full_key: &[u8]) -> Result<()> {
+        if let Some(entry) = self.0.pinned.watched.lock().get_mut(full_key) {
+            entry.1 += 1;
+        }
+        {
+            let headers = self.0.pinned.headers.read();
+            if let Some(pinned) = headers.get(full_key) {
+                let mut pinned = pinned.lock();
+                pinned.header = None;
+                pinned.dirty = true;
+                if pinned.last_write_back.elapsed() >= pinned.max_staleness {
+                    self.write_back_header(full_key, &mut pinned)?;
+                }
+                return Ok(());
+            }
+        }
+        self.remove_raw(full_key)?;
+        Ok(())
+    }
+
+    pub(crate) fn get_or_create_header(
+        &self,
+        full_key: &[u8],
+        default_header: Vec<u8>,
+    ) -> Result<GetOrCreateStatus> {
+        let is_pinned = self.0.pinned.headers.read().contains_key(full_key);
+        if !is_pinned {
+            return self.get_or_create_raw(full_key, default_header);
+        }
+        // the caller holds the list's lock, so get+set is atomic
+        if let Some(header) = self.get_header(full_key)? {
+            Ok(GetOrCreateStatus::ExistingValue(header))
+        } else {
+            self.set_header(full_key, &default_header)?;
+            Ok(GetOrCreateStatus::CreatedNew(default_header))
+        }
+    }
+
+    /// writes all dirty pinned headers back to the store
+    pub(crate) fn write_back_pinned_headers(&self) -> Result<()> {
+        let headers = self.0.pinned.headers.read();
+        for (full_key, pinned) in headers.iter() {
+            self.write_back_header(full_key, &mut pinned.lock())?;
+        }
+        Ok(())
+    }
+
+    // watches the header for removals, see HeaderWatch
+    pub(crate) fn watch_header(&self, full_key: &[u8]) -> HeaderWatch<'_> {
+        let mut watched = self.0.pinned.watched.lock();
+        let entry = watched.entry(full_key.to_owned()).or_default();
+        entry.0 += 1;
+        HeaderWatch {
+            store: self,
+            full_key: full_key.to_owned(),
+            num_removals: entry.1,
+        }
+    }
+
+    /// forgets the content of all pinned headers (used when the store is cleared), but keeps them pinned. clearing
+    /// removes the watched headers as well
+    pub(crate) fn reset_pinned_headers(&self) {
+        for entry in self.0.pinned.watched.lock().values_mut() {
+            entry.1 += 1;
+        }
+        let headers = self.0.pinned.headers.read();
+        for pinned in headers.values() {
+            let mut pinned = pinned.lock();
+            pinned.header = None;
+            pinned.dirty = false;
+        }
+    }
+
+    fn pin_header(
+        &self,
+        full_key: Vec<u8>,
+        max_staleness: Duration,
+        repair: impl FnOnce(&mut [u8]) -> Result<bool>,
+    ) -> Result<()> {
+        let mut headers = self.0.pinned.headers.write();
+        if let Some(pinned) = headers.get(&full_key) {
+            pinned.lock().max_staleness = max_staleness;
+            return Ok(());
+        }
+
+        let mut header = self.unisolated(|| self.get_raw(&full_key))?;
+        let mut dirty = false;
+        if let Some(ref mut header) = header {
+            dirty = repair(header)?;
+        }
+
+        let mut pinned = PinnedHeader {
+            header,
+            dirty,
+            last_write_back: Instant::now(),
+            max_staleness,
+        };
+        self.write_back_header(&full_key, &mut pinned)?;
+        headers.insert(full_key, Mutex::new(pinned));
+        Ok(())
+    }
+
+    fn unpin_header(&self, full_key: &[u8]) -> Result<bool> {
+        let mut headers = self.0.pinned.headers.write();
+        let Some(pinned) = headers.remove(full_key) else {
+            return Ok(false);
+        };
+        self.write_back_header(full_key, &mut pinned.lock())?;
+        Ok(true)
+    }
+
+    /// Pins the given list, so that its header (which is read and updated by every list operation) is cached in
+    /// memory. The header is written back to the store when it's been dirty for longer than `max_staleness`,
+    /// as well as on [Self::unpin_list], [Self::flush] and when the store is dropped. This saves a read and a
+    /// write per operation, which matters for very hot lists.
+    ///
+    /// Pinning is not persistent, and it trades crash-safety for speed: if the process crashes, the on-disk
+    /// header may be up to `max_staleness` behind. Elements pushed during that window are recovered the next
+    /// time the list is pinned (pinning repairs the header), so you should pin hot lists right after opening
+    /// the store. Pinning an already-pinned list only updates its `max_staleness`.
+    pub fn pin_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        max_staleness: Duration,
+    ) -> Result<()> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+        self.pin_header(list_key, max_staleness, |header| {
+            self.repair_list_header(list_ph, header)
+        })
+    }
+
+    /// Unpins the given list, writing its header back to the store. Returns false if the list was not pinned
+    pub fn unpin_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<bool> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+        self.unpin_header(&list_key)
+    }
+
+    /// Same as [Self::pin_list], but for queues
+    pub fn pin_queue<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        max_staleness: Duration,
+    ) -> Result<()> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+        self.pin_header(full_queue_key, max_staleness, |header| {
+            self.repair_queue_header(queue_key, header)
+        })
+    }
+
+    /// Same as [Self::unpin_list], but for queues
+    pub fn unpin_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<bool> {
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key.as_ref());
+        let _guard = self.lock_list(queue_ph);
+        self.unpin_header(&full_queue_key)
+    }
+
+    // elements are always pushed at the tail of lists, so a stale header can only miss elements past its tail
+    fn repair_list_header(&self, list_ph: PartedHash, header: &mut [u8]) -> Result<bool> {
+        let list = from_bytes_mut::<List>(header);
+        let mut repaired = false;
+        while self
+            .get_raw(bytes_of(&ChainKey {
+                list_ph,
+                idx: list.tail_idx,
+                namespace: CHAIN_NAMESPACE,
+            }))?
+            .is_some()
+        {
+            list.tail_idx += 1;
+            list.num_items += 1;
+            repaired = true;
+        }
+        Ok(repaired)
+    }
+
+    // queues can be pushed at both ends
+    fn repair_queue_header(&self, queue_key: &[u8], header: &mut [u8]) -> Result<bool> {
+        let queue = from_bytes_mut::<Queue>(header);
+        let mut repaired = false;
+        while self
+            .get_raw(&self.make_queue_item_key(queue_key, queue.tail_idx))?
+            .is_some()
+        {
+            queue.tail_idx += 1;
+            queue.num_items += 1;
+            repaired = true;
+        }
+        while self
+            .get_raw(&self.make_queue_item_key(queue_key, queue.head_idx - 1))?
+            .is_some()
+        {
+            queue.head_idx -= 1;
+            queue.num_items += 1;
+            repaired = true;
+        }
+        Ok(repaired)
+    }
+}
@@ -0,0 +1,112 @@
+use bytemuck::bytes_of;
+
+use crate::{
+    hashing::PartedHash, list_audit::ListAuditOp, lists::List, shard::KVPair,
+    store::POP_TOKEN_NAMESPACE, CandyError, CandyStore, Result,
+};
+
+// a pop token's record holds the popped item's index, followed by the length of its key, the key and the value
+const RECORD_HEADER_LEN: usize = size_of::<u64>() + size_of::<u32>();
+
+impl CandyStore {
+    fn make_pop_token_key(list_ph: PartedHash, token: &[u8]) -> Vec<u8> {
+        [bytes_of(&list_ph), token, POP_TOKEN_NAMESPACE].concat()
+    }
+
+    fn parse_pop_token_record(record: &[u8]) -> Result<(u64, KVPair)> {
+        let bad_record = || CandyError::Corruption(format!("bad pop token record {record:?}"));
+        if record.len() < RECORD_HEADER_LEN {
+            return Err(bad_record());
+        }
+        let idx = u64::from_le_bytes(record[..size_of::<u64>()].try_into().unwrap());
+        let key_len = u32::from_le_bytes(
+            record[size_of::<u64>()..RECORD_HEADER_LEN]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let rest = &record[RECORD_HEADER_LEN..];
+        if rest.len() < key_len {
+            return Err(bad_record());
+        }
+        Ok((idx, (rest[..key_len].to_vec(), rest[key_len..].to_vec())))
+    }
+
+    /// Same as [Self::pop_list_head], but exactly-once with respect to `consumer_token`: the popped item is
+    /// recorded under the token, so retrying the pop with the same token (e.g., after a timeout or a crash of
+    /// the consumer, which does not know whether its pop went through) returns the same item again, rather than
+    /// popping (and thus losing) the next one. This holds across crashes of the store too, as the token is
+    /// recorded before the item is removed.
+    ///
+    /// Once the item has been handled, release the token with [Self::release_pop_token], after which the token
+    /// pops a new item. Tokens are not released automatically, not even when the list is discarded
+    pub fn pop_list_head_idempotent<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        consumer_token: &B2,
+    ) -> Result<Option<KVPair>> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let token_key = Self::make_pop_token_key(list_ph, consumer_token.as_ref());
+        let _guard = self.lock_list(list_ph);
+
+        if let Some(record) = self.get_raw(&token_key)? {
+            let (idx, (k, v)) = Self::parse_pop_token_record(&record)?;
+            // the store may have crashed after recording the token but before removing the item. the item's
+            // index tells it apart from an item of the same key that was pushed after it was popped
+            let (_, item_key) = self.make_item_key(list_ph, k.clone());
+            if let Some(item_val) = self.get_raw(&item_key)? {
+                let item_idx = u64::from_le_bytes(
+                    item_val[item_val.len() - size_of::<u64>()..]
+                        .try_into()
+                        .unwrap(),
+                );
+                if item_idx == idx {
+                    self.remove_from_list_locked(list_ph, &list_key, &item_key, ListAuditOp::Pop)?;
+                    self.clear_list_item_ttl(&list_key, &k)?;
+                }
+            }
+            return Ok(Some((k, v)));
+        }
+
+        let Some(list_bytes) = self.get_header(&list_key)? else {
+            return Ok(None);
+        };
+        let list = List::parse(&list_bytes)?;
+        for idx in list.head_idx..list.tail_idx {
+            let Some((_, item_key, mut item_val)) =
+                self.get_from_list_at_index(list_ph, idx, false)?
+            else {
+                continue;
+            };
+            item_val.truncate(item_val.len() - self.list_item_suffix_len());
+            let k = &item_key[..item_key.len() - Self::LIST_KEY_SUFFIX_LEN];
+
+            let mut record = Vec::with_capacity(RECORD_HEADER_LEN + k.len() + item_val.len());
+            record.extend_from_slice(&idx.to_le_bytes());
+            record.extend_from_slice(&(k.len() as u32).to_le_bytes());
+            record.extend_from_slice(k);
+            record.extend_from_slice(&item_val);
+            self.set_raw(&token_key, &record)?;
+
+            self.remove_from_list_locked(list_ph, &list_key, &item_key, ListAuditOp::Pop)?;
+            self.clear_list_item_ttl(&list_key, k)?;
+            return Ok(Some((k.to_vec(), item_val)));
+        }
+        Ok(None)
+    }
+
+    /// Releases a token of [Self::pop_list_head_idempotent], returning the item that was popped with it, if
+    /// any. The next pop with this token pops a new item
+    pub fn release_pop_token<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        consumer_token: &B2,
+    ) -> Result<Option<KVPair>> {
+        let (list_ph, _) = self.make_list_key(list_key.as_ref().to_owned());
+        let token_key = Self::make_pop_token_key(list_ph, consumer_token.as_ref());
+        let _guard = self.lock_list(list_ph);
+        match self.remove_raw(&token_key)? {
+            Some(record) => Ok(Some(Self::parse_pop_token_record(&record)?.1)),
+            None => Ok(None),
+        }
+    }
+}
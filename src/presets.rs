@@ -0,0 +1,58 @@
+use crate::{Config, DiskFullPolicy, ListRecoveryPolicy};
+
+/// A deployment profile for [Config::preset]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// small devices with little RAM and disk: smaller shards (so a compaction or split rewrites less at a
+    /// time), a single deprioritized maintenance thread, few list locks, no sparse files, and shards that are
+    /// only opened when first accessed
+    EmbeddedLowMemory,
+    /// servers with many cores and plenty of disk: more maintenance threads and list locks, shards that are
+    /// split in the background before they fill up, and disk space that is allocated in large increments
+    ServerHighThroughput,
+    /// stores that mostly hold queues and lists that must survive crashes: torn list operations are rolled
+    /// back on open, shard files are checksummed across clean restarts, disk space is allocated before it
+    /// is written, and a full disk switches the store to read-only rather than leaving further operations
+    /// half done. Queues leave plenty of dead entries behind them, so shards are compacted sooner
+    DurableQueue,
+}
+
+impl Config {
+    /// Returns the default configuration, tuned for the given profile. The presets only cover tunables, so
+    /// format-affecting options (e.g., [Config::list_item_metadata]) are left at their defaults, and any
+    /// field can still be overridden, e.g., `Config { hash_seed, ..Config::preset(profile) }`.
+    ///
+    /// There are no cache or fsync knobs to tune: values are read straight from the mapped shard files, and
+    /// data reaches the disk when the OS writes it back or on [crate::CandyStore::flush] (see
+    /// [crate::CachedStore] for an in-memory cache in front of the store)
+    pub fn preset(profile: Profile) -> Self {
+        let default = Self::default();
+        match profile {
+            Profile::EmbeddedLowMemory => Self {
+                max_shard_size: 16 * 1024 * 1024,
+                min_compaction_threashold: 2 * 1024 * 1024,
+                max_concurrent_list_ops: 16,
+                truncate_up: false,
+                num_compaction_threads: 1,
+                maintenance_thread_nice: Some(10),
+                lazy_open: true,
+                ..default
+            },
+            Profile::ServerHighThroughput => Self {
+                max_concurrent_list_ops: 1024,
+                num_compaction_threads: 8,
+                background_split_threshold: Some(0.8),
+                preallocate_increment: 8 * 1024 * 1024,
+                ..default
+            },
+            Profile::DurableQueue => Self {
+                min_compaction_threashold: 4 * 1024 * 1024,
+                list_recovery: Some(ListRecoveryPolicy::RollBack),
+                preallocate_increment: 4 * 1024 * 1024,
+                disk_full_policy: DiskFullPolicy::ReadOnly,
+                shard_checksums: true,
+                ..default
+            },
+        }
+    }
+}
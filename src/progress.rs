@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+/// A progress callback for long-running operations (e.g., [crate::CandyStore::discard_list_with_progress] or
+/// [crate::CandyStore::export_sst]), so CLIs and UIs can show progress bars. It is called with `(done,
+/// total_estimate)` as the operation advances, from the thread performing it. The total is an estimate (e.g.,
+/// lists may contain holes), so `done` may end up short of it
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl Progress {
+    pub fn new(func: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(func))
+    }
+
+    pub(crate) fn report(progress: Option<&Self>, done: u64, total_estimate: u64) {
+        if let Some(progress) = progress {
+            (progress.0)(done, total_estimate);
+        }
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Progress")
+    }
+}
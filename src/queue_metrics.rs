@@ -0,0 +1,263 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{CandyStore, Result};
+
+/// Gauges of a single queue, see [CandyStore::queue_metrics]
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueMetrics {
+    /// the number of elements in the queue
+    pub len: usize,
+    /// the (approximate) time since the head element was pushed, or `None` if the queue is empty or its head
+    /// element was pushed before tracking began (e.g., before the store was opened)
+    pub oldest_item_age: Option<Duration>,
+    /// elements pushed per second, averaged over the last [QUEUE_RATE_WINDOW]
+    pub push_rate: f64,
+    /// elements popped per second, averaged over the last [QUEUE_RATE_WINDOW]
+    pub pop_rate: f64,
+}
+
+/// The sliding window over which [QueueMetrics::push_rate] and [QueueMetrics::pop_rate] are averaged
+pub const QUEUE_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// bounds the memory taken by the tracking, the least recently active queues are evicted first
+const MAX_TRACKED_QUEUES: usize = 1024;
+// bounds the push times kept per queue. once reached, every other one is dropped, halving their resolution
+const MAX_PUSH_MARKS: usize = 1024;
+// a push is marked only if the last mark is at least this old, so the age of the head element is
+// overestimated by less than this (as long as the marks were not thinned out)
+const MARK_RESOLUTION: Duration = Duration::from_secs(1);
+
+// counts events in one-second buckets, over the last QUEUE_RATE_WINDOW
+#[derive(Debug, Default)]
+struct RateWindow(VecDeque<(u64, usize)>);
+
+impl RateWindow {
+    fn add(&mut self, sec: u64, count: usize) {
+        match self.0.back_mut() {
+            Some((last_sec, last_count)) if *last_sec == sec => *last_count += count,
+            _ => self.0.push_back((sec, count)),
+        }
+        self.expire(sec);
+    }
+
+    fn expire(&mut self, sec: u64) {
+        while let Some(&(first_sec, _)) = self.0.front() {
+            if first_sec + QUEUE_RATE_WINDOW.as_secs() > sec {
+                break;
+            }
+            self.0.pop_front();
+        }
+    }
+
+    fn rate(&mut self, sec: u64, tracked_for: Duration) -> f64 {
+        self.expire(sec);
+        let total = self.0.iter().map(|(_, count)| count).sum::<usize>();
+        // a queue that has been tracked for less than a window is averaged over the time it was tracked
+        let secs = tracked_for.clamp(Duration::from_secs(1), QUEUE_RATE_WINDOW);
+        total as f64 / secs.as_secs_f64()
+    }
+}
+
+#[derive(Debug)]
+struct QueueTracker {
+    since: Instant,
+    last_active: Instant,
+    pushes: RateWindow,
+    pops: RateWindow,
+    // (element index, push time), ordered by index. an element was pushed no earlier than the mark with the
+    // largest index that is not larger than its own, assuming elements are pushed at the tail
+    marks: VecDeque<(u64, Instant)>,
+}
+
+impl QueueTracker {
+    fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            last_active: now,
+            pushes: RateWindow::default(),
+            pops: RateWindow::default(),
+            marks: VecDeque::new(),
+        }
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.duration_since(self.since).as_secs()
+    }
+
+    fn mark_tail(&mut self, idx: u64, now: Instant) {
+        if let Some(&(last_idx, last_time)) = self.marks.back() {
+            if last_idx >= idx || now.duration_since(last_time) < MARK_RESOLUTION {
+                return;
+            }
+        }
+        if self.marks.len() >= MAX_PUSH_MARKS {
+            let mut i = 0;
+            self.marks.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+        }
+        self.marks.push_back((idx, now));
+    }
+
+    fn mark_head(&mut self, idx: u64, now: Instant) {
+        if self
+            .marks
+            .front()
+            .is_some_and(|&(first_idx, _)| first_idx <= idx)
+        {
+            return;
+        }
+        if self.marks.len() >= MAX_PUSH_MARKS {
+            // the most recent marks are the least interesting ones for the age of the head element
+            self.marks.pop_back();
+        }
+        self.marks.push_front((idx, now));
+    }
+
+    // forgets the marks of elements that are no longer in the queue, keeping the one that covers the head
+    fn trim(&mut self, head_idx: u64, tail_idx: u64) {
+        while self.marks.back().is_some_and(|&(idx, _)| idx >= tail_idx) {
+            self.marks.pop_back();
+        }
+        while self.marks.len() >= 2 && self.marks[1].0 <= head_idx {
+            self.marks.pop_front();
+        }
+    }
+
+    fn head_push_time(&self, head_idx: u64) -> Option<Instant> {
+        self.marks
+            .iter()
+            .take_while(|&&(idx, _)| idx <= head_idx)
+            .last()
+            .map(|&(_, time)| time)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct QueueActivity(Mutex<HashMap<Vec<u8>, QueueTracker>>);
+
+impl QueueActivity {
+    fn with_tracker(&self, queue_key: &[u8], func: impl FnOnce(&mut QueueTracker, Instant)) {
+        let now = Instant::now();
+        let mut queues = self.0.lock();
+        if !queues.contains_key(queue_key) {
+            if queues.len() >= MAX_TRACKED_QUEUES {
+                let idlest = queues
+                    .iter()
+                    .min_by_key(|(_, t)| t.last_active)
+                    .map(|(k, _)| k.clone());
+                if let Some(idlest) = idlest {
+                    queues.remove(&idlest);
+                }
+            }
+            queues.insert(queue_key.to_owned(), QueueTracker::new(now));
+        }
+        let tracker = queues.get_mut(queue_key).unwrap();
+        tracker.last_active = now;
+        func(tracker, now);
+    }
+
+    // called (under the queue's lock) once `count` elements were pushed, starting at `first_idx`
+    pub(crate) fn record_push(
+        &self,
+        queue_key: &[u8],
+        first_idx: u64,
+        count: usize,
+        at_head: bool,
+    ) {
+        if count == 0 {
+            return;
+        }
+        self.with_tracker(queue_key, |tracker, now| {
+            let sec = tracker.second(now);
+            tracker.pushes.add(sec, count);
+            if at_head {
+                tracker.mark_head(first_idx, now);
+            } else {
+                tracker.mark_tail(first_idx, now);
+            }
+        });
+    }
+
+    // called (under the queue's lock) once `count` elements were popped, with the queue's remaining range
+    pub(crate) fn record_pop(&self, queue_key: &[u8], count: usize, head_idx: u64, tail_idx: u64) {
+        if count == 0 {
+            return;
+        }
+        self.with_tracker(queue_key, |tracker, now| {
+            let sec = tracker.second(now);
+            tracker.pops.add(sec, count);
+            tracker.trim(head_idx, tail_idx);
+        });
+    }
+
+    pub(crate) fn forget(&self, queue_key: &[u8]) {
+        self.0.lock().remove(queue_key);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    fn tracked_queues(&self) -> Vec<Vec<u8>> {
+        self.0.lock().keys().cloned().collect()
+    }
+}
+
+impl CandyStore {
+    /// Returns the gauges of the given queue: its length, the age of its head element and its push and pop
+    /// rates, e.g., to alert on a consumer that falls behind.
+    ///
+    /// Push and pop times are tracked in memory (for up to 1024 queues, the least recently active ones are
+    /// forgotten first), so elements pushed before the store was opened have no known age. The age assumes
+    /// elements are pushed at the tail and popped from the head (FIFO), and is accurate to about a second
+    pub fn queue_metrics<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<QueueMetrics> {
+        let queue_key = queue_key.as_ref();
+        let Some(queue) = self.fetch_queue(queue_key)? else {
+            return Ok(QueueMetrics {
+                len: 0,
+                oldest_item_age: None,
+                push_rate: 0.0,
+                pop_rate: 0.0,
+            });
+        };
+
+        let now = Instant::now();
+        let mut queues = self.0.stats.queue_activity.0.lock();
+        let Some(tracker) = queues.get_mut(queue_key) else {
+            return Ok(QueueMetrics {
+                len: queue.num_items as usize,
+                oldest_item_age: None,
+                push_rate: 0.0,
+                pop_rate: 0.0,
+            });
+        };
+        let sec = tracker.second(now);
+        let tracked_for = now.duration_since(tracker.since);
+        Ok(QueueMetrics {
+            len: queue.num_items as usize,
+            oldest_item_age: tracker
+                .head_push_time(queue.head_idx)
+                .map(|time| now.duration_since(time)),
+            push_rate: tracker.pushes.rate(sec, tracked_for),
+            pop_rate: tracker.pops.rate(sec, tracked_for),
+        })
+    }
+
+    /// Returns the gauges (see [Self::queue_metrics]) of all the queues that were pushed to or popped from
+    /// since the store was opened, in arbitrary order
+    pub fn all_queue_metrics(&self) -> Result<Vec<(Vec<u8>, QueueMetrics)>> {
+        let mut res = vec![];
+        for queue_key in self.0.stats.queue_activity.tracked_queues() {
+            let metrics = self.queue_metrics(&queue_key)?;
+            res.push((queue_key, metrics));
+        }
+        Ok(res)
+    }
+}
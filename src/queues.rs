@@ -3,17 +3,16 @@ use std::ops::Range;
 use crate::{
     hashing::PartedHash,
     store::{QUEUE_ITEM_NAMESPACE, QUEUE_NAMESPACE},
-    CandyStore,
+    CandyStore, ReplaceStatus, Result,
 };
-use anyhow::Result;
 use bytemuck::{bytes_of, checked::from_bytes_mut, from_bytes, Pod, Zeroable};
 
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
-struct Queue {
-    head_idx: u64, // inclusive
-    tail_idx: u64, // exclusive
-    num_items: u64,
+pub(crate) struct Queue {
+    pub(crate) head_idx: u64, // inclusive
+    pub(crate) tail_idx: u64, // exclusive
+    pub(crate) num_items: u64,
 }
 
 impl Queue {
@@ -96,15 +95,15 @@ impl<'a> Iterator for QueueIterator<'a> {
 impl CandyStore {
     const FIRST_QUEUE_IDX: u64 = 0x8000_0000_0000_0000;
 
-    fn make_queue_key(&self, queue_key: &[u8]) -> (PartedHash, Vec<u8>) {
+    pub(crate) fn make_queue_key(&self, queue_key: &[u8]) -> (PartedHash, Vec<u8>) {
         let mut full_queue_key = queue_key.to_owned();
         full_queue_key.extend_from_slice(QUEUE_NAMESPACE);
         (
-            PartedHash::new(&self.config.hash_seed, &queue_key),
+            PartedHash::new(&self.0.config.hash_seed, &queue_key),
             full_queue_key,
         )
     }
-    fn make_queue_item_key(&self, queue_key: &[u8], idx: u64) -> Vec<u8> {
+    pub(crate) fn make_queue_item_key(&self, queue_key: &[u8], idx: u64) -> Vec<u8> {
         let mut item_key = queue_key.to_owned();
         item_key.extend_from_slice(bytes_of(&idx));
         item_key.extend_from_slice(QUEUE_ITEM_NAMESPACE);
@@ -115,7 +114,7 @@ impl CandyStore {
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
 
-        let status = self.get_or_create_raw(
+        let status = self.get_or_create_header(
             &full_queue_key,
             bytes_of(&Queue {
                 head_idx: Self::FIRST_QUEUE_IDX,
@@ -141,12 +140,19 @@ impl CandyStore {
                     }
                 };
                 queue.num_items += 1;
-                self.set_raw(&full_queue_key, &queue_bytes)?;
+                self.set_header(&full_queue_key, &queue_bytes)?;
                 item_idx
             }
         };
 
         self.set_raw(&self.make_queue_item_key(queue_key, item_idx), val)?;
+        #[cfg(feature = "metrics")]
+        self.0.stats.queue_activity.record_push(
+            queue_key,
+            item_idx,
+            1,
+            matches!(pos, QueuePos::Head),
+        );
         Ok(item_idx as usize)
     }
 
@@ -168,50 +174,64 @@ impl CandyStore {
         self._push_to_queue(queue_key.as_ref(), val.as_ref(), QueuePos::Tail)
     }
 
-    fn _pop_queue(&self, queue_key: &[u8], pos: QueuePos) -> Result<Option<(usize, Vec<u8>)>> {
+    fn _pop_queue_many(
+        &self,
+        queue_key: &[u8],
+        pos: QueuePos,
+        max_items: usize,
+    ) -> Result<Vec<(usize, Vec<u8>)>> {
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
 
-        let Some(mut queue_bytes) = self.get_raw(&full_queue_key)? else {
-            return Ok(None);
+        let Some(mut queue_bytes) = self.get_header(&full_queue_key)? else {
+            return Ok(vec![]);
         };
         let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
-        let mut res = None;
+        let mut res = vec![];
 
         match pos {
             QueuePos::Head => {
-                while queue.head_idx < queue.tail_idx {
+                while res.len() < max_items && queue.head_idx < queue.tail_idx {
                     let idx = queue.head_idx;
                     queue.head_idx += 1;
                     if let Some(v) = self.remove_raw(&self.make_queue_item_key(queue_key, idx))? {
-                        res = Some((idx as usize, v));
+                        res.push((idx as usize, v));
                         queue.num_items -= 1;
-                        break;
                     }
                 }
             }
             QueuePos::Tail => {
-                while queue.tail_idx > queue.head_idx {
+                while res.len() < max_items && queue.tail_idx > queue.head_idx {
                     queue.tail_idx -= 1;
                     let idx = queue.tail_idx;
                     if let Some(v) = self.remove_raw(&self.make_queue_item_key(queue_key, idx))? {
-                        res = Some((idx as usize, v));
+                        res.push((idx as usize, v));
                         queue.num_items -= 1;
-                        break;
                     }
                 }
             }
         }
 
+        #[cfg(feature = "metrics")]
+        let remaining = (queue.head_idx, queue.tail_idx);
         if queue.is_empty() {
-            self.remove_raw(&full_queue_key)?;
+            self.remove_header(&full_queue_key)?;
         } else {
-            self.set_raw(&full_queue_key, &queue_bytes)?;
+            self.set_header(&full_queue_key, &queue_bytes)?;
         }
+        #[cfg(feature = "metrics")]
+        self.0
+            .stats
+            .queue_activity
+            .record_pop(queue_key, res.len(), remaining.0, remaining.1);
 
         Ok(res)
     }
 
+    fn _pop_queue(&self, queue_key: &[u8], pos: QueuePos) -> Result<Option<(usize, Vec<u8>)>> {
+        Ok(self._pop_queue_many(queue_key, pos, 1)?.pop())
+    }
+
     /// Removes and returns the head element and its index of the queue, or None if the queue is empty
     pub fn pop_queue_head_with_idx<B: AsRef<[u8]> + ?Sized>(
         &self,
@@ -246,6 +266,31 @@ impl CandyStore {
         Ok(self.pop_queue_tail_with_idx(queue_key)?.map(|iv| iv.1))
     }
 
+    /// Removes and returns up to `max_items` elements (along with their indices) from the head of the queue, in
+    /// head-to-tail order. The queue is locked and its header is updated only once, which makes this more efficient
+    /// than calling [Self::pop_queue_head_with_idx] in a loop. Returns an empty vector if the queue is empty.
+    ///
+    /// Note: this is not an atomic (crash-safe) operation: if your program crashes midway, some of the elements may
+    /// have been removed while the queue's header still accounts for them. They will be skipped over as holes.
+    /// Set [crate::Config::list_journal] to have such operations rolled back when the store is opened.
+    pub fn pop_queue_head_many<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        max_items: usize,
+    ) -> Result<Vec<(usize, Vec<u8>)>> {
+        self._pop_queue_many(queue_key.as_ref(), QueuePos::Head, max_items)
+    }
+
+    /// Same as [Self::pop_queue_head_many], but pops from the tail of the queue, returning the elements in
+    /// tail-to-head order
+    pub fn pop_queue_tail_many<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        max_items: usize,
+    ) -> Result<Vec<(usize, Vec<u8>)>> {
+        self._pop_queue_many(queue_key.as_ref(), QueuePos::Tail, max_items)
+    }
+
     /// Removes an element by index from the queue, returning the value it had or None if it did not exist (as well
     /// as if the queue itself does not exist).
     ///
@@ -266,7 +311,7 @@ impl CandyStore {
             return Ok(None);
         };
 
-        if let Some(mut queue_bytes) = self.get_raw(&full_queue_key)? {
+        if let Some(mut queue_bytes) = self.get_header(&full_queue_key)? {
             let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
             if queue.head_idx == idx {
                 queue.head_idx += 1;
@@ -276,22 +321,56 @@ impl CandyStore {
             }
             queue.num_items -= 1;
             if queue.is_empty() {
-                self.remove_raw(&full_queue_key)?;
+                self.remove_header(&full_queue_key)?;
             } else {
-                self.set_raw(&full_queue_key, &queue_bytes)?;
+                self.set_header(&full_queue_key, &queue_bytes)?;
             }
         }
 
         Ok(Some(val))
     }
 
+    /// Returns the value of the element at the given index (as returned by the push functions), or None if it
+    /// does not exist (either because it had been popped/removed, or the queue itself does not exist)
+    pub fn get_from_queue<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        idx: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        self.get_raw(&self.make_queue_item_key(queue_key.as_ref(), idx as u64))
+    }
+
+    /// Replaces the value of the element at the given index (as returned by the push functions), keeping its
+    /// position in the queue. Returns the previous value, or None if the element does not exist (in which case
+    /// nothing is written)
+    pub fn replace_in_queue<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B1,
+        idx: usize,
+        val: &B2,
+    ) -> Result<Option<Vec<u8>>> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, _) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        match self.replace_raw(
+            &self.make_queue_item_key(queue_key, idx as u64),
+            val.as_ref(),
+            None,
+        )? {
+            ReplaceStatus::PrevValue(v) => Ok(Some(v)),
+            ReplaceStatus::WrongValue(_) => unreachable!(),
+            ReplaceStatus::DoesNotExist => Ok(None),
+        }
+    }
+
     /// Discards the queue (dropping all elements in contains). Returns true if it had existed before, false otherwise
     pub fn discard_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<bool> {
         let queue_key = queue_key.as_ref();
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
 
-        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+        let Some(queue_bytes) = self.get_header(&full_queue_key)? else {
             return Ok(false);
         };
         let queue = from_bytes::<Queue>(&queue_bytes);
@@ -300,15 +379,17 @@ impl CandyStore {
             self.remove_raw(&self.make_queue_item_key(queue_key, i as u64))?;
         }
 
-        self.remove_raw(&full_queue_key)?;
+        self.remove_header(&full_queue_key)?;
+        #[cfg(feature = "metrics")]
+        self.0.stats.queue_activity.forget(queue_key);
         Ok(true)
     }
 
-    fn fetch_queue(&self, queue_key: &[u8]) -> Result<Option<Queue>> {
+    pub(crate) fn fetch_queue(&self, queue_key: &[u8]) -> Result<Option<Queue>> {
         let queue_key = queue_key.as_ref();
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
-        if let Some(queue_bytes) = self.get_raw(&full_queue_key)? {
+        if let Some(queue_bytes) = self.get_header(&full_queue_key)? {
             Ok(Some(*from_bytes::<Queue>(&queue_bytes)))
         } else {
             Ok(None)
@@ -320,7 +401,8 @@ impl CandyStore {
     /// [Self::push_to_queue_tail] in a loop
     ///
     /// Note: this is not an atomic (crash-safe) operation: if your program crashes while extending the queue, it
-    /// is possible that only some of the elements will have been appended.
+    /// is possible that only some of the elements will have been appended (unless [crate::Config::list_journal]
+    /// is set).
     ///
     /// Returns the indices of the elements added (a range)
     pub fn extend_queue<'a, B: AsRef<[u8]> + ?Sized>(
@@ -333,7 +415,7 @@ impl CandyStore {
         let _guard = self.lock_list(queue_ph);
 
         let mut queue_bytes = &mut self
-            .get_or_create_raw(
+            .get_or_create_header(
                 &full_queue_key,
                 bytes_of(&Queue {
                     head_idx: Self::FIRST_QUEUE_IDX,
@@ -357,7 +439,12 @@ impl CandyStore {
         }
 
         let indices = first_idx as usize..queue.tail_idx as usize;
-        self.set_raw(&full_queue_key, &queue_bytes)?;
+        self.set_header(&full_queue_key, &queue_bytes)?;
+        #[cfg(feature = "metrics")]
+        self.0
+            .stats
+            .queue_activity
+            .record_push(queue_key, first_idx, indices.len(), false);
 
         Ok(indices)
     }
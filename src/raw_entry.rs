@@ -0,0 +1,94 @@
+use crate::{hashing::PartedHash, CandyStore, ReplaceStatus, Result, SetStatus};
+
+/// A key whose hash has already been computed, see [CandyStore::raw_entry]. Operations on the entry reuse
+/// the hash instead of rehashing the key every time, which matters for long keys that are accessed several
+/// times in a row (e.g., get-then-set). The entry does not hold any lock, so it can be kept around and used
+/// any number of times, and it observes modifications made through the store as usual
+pub struct RawEntry<'a> {
+    store: &'a CandyStore,
+    ph: PartedHash,
+    key: Vec<u8>,
+    full_key: Vec<u8>,
+}
+
+impl<'a> RawEntry<'a> {
+    /// Returns the key of this entry
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Same as [CandyStore::get]
+    pub fn get(&self) -> Result<Option<Vec<u8>>> {
+        if self.store.key_expired(&self.key)? {
+            return Ok(None);
+        }
+        self.store.get_with_hash(self.ph, &self.full_key)
+    }
+
+    /// Same as [CandyStore::contains]
+    pub fn contains(&self) -> Result<bool> {
+        Ok(self.get()?.is_some())
+    }
+
+    /// Same as [CandyStore::set]
+    pub fn set<B: AsRef<[u8]> + ?Sized>(&self, val: &B) -> Result<SetStatus> {
+        let val = val.as_ref();
+        CandyStore::ensure_sizes(self.key(), val)?;
+        self.store.with_key_history(&self.key, |_| {
+            self.store.clear_ttl(&self.key)?;
+            self.store.set_with_hash(self.ph, &self.full_key, val)
+        })
+    }
+
+    /// Same as [CandyStore::replace]
+    pub fn replace<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        val: &B1,
+        expected_val: Option<&B2>,
+    ) -> Result<ReplaceStatus> {
+        let val = val.as_ref();
+        CandyStore::ensure_sizes(self.key(), val)?;
+        self.store.purge_if_expired(&self.key)?;
+        self.store.with_key_history(&self.key, |_| {
+            let status = self.store.replace_with_hash(
+                self.ph,
+                &self.full_key,
+                val,
+                expected_val.map(|ev| ev.as_ref()),
+            )?;
+            if let ReplaceStatus::PrevValue(_) = status {
+                self.store.clear_ttl(&self.key)?;
+            }
+            Ok(status)
+        })
+    }
+
+    /// Same as [CandyStore::remove]
+    pub fn remove(&self) -> Result<Option<Vec<u8>>> {
+        self.store.purge_if_expired(&self.key)?;
+        self.store.with_key_history(&self.key, |_| {
+            self.store.clear_ttl(&self.key)?;
+            self.store.remove_with_hash(self.ph, &self.full_key)
+        })
+    }
+}
+
+impl CandyStore {
+    /// Hashes the given key once and returns a [RawEntry] for operating on it repeatedly without rehashing.
+    /// The entry refers to the same key as [Self::get], [Self::set] and friends do. If the key is long enough
+    /// to be interned (see [crate::Config::intern_keys_longer_than]), it is interned here
+    pub fn raw_entry<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<RawEntry<'_>> {
+        self.owned_raw_entry(key.as_ref().to_owned())
+    }
+
+    /// Same as [Self::raw_entry], but the key passed owned to this function
+    pub fn owned_raw_entry(&self, key: Vec<u8>) -> Result<RawEntry<'_>> {
+        let full_key = self.make_user_key_for_write(key.clone())?;
+        Ok(RawEntry {
+            store: self,
+            ph: PartedHash::new(&self.0.config.hash_seed, &full_key),
+            key,
+            full_key,
+        })
+    }
+}
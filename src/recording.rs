@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use databuf::{Decode, Encode};
+use parking_lot::Mutex;
+
+use crate::{BoxedKvIterator, KvStore, ListStore, Result, SetStatus};
+
+#[derive(Default)]
+struct MemoryList {
+    next_idx: u64,
+    // item key -> index, and index -> (item key, value), to keep the insertion order
+    indices: HashMap<Vec<u8>, u64>,
+    items: BTreeMap<u64, (Vec<u8>, Vec<u8>)>,
+}
+
+/// An in-memory implementation of [KvStore] and [ListStore], for testing code written against these traits
+/// without touching the disk. Lists keep the insertion order, like [crate::CandyStore]'s lists
+#[derive(Default)]
+pub struct MemoryStore {
+    kvs: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    lists: Mutex<HashMap<Vec<u8>, MemoryList>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.kvs.lock().get(key).cloned())
+    }
+    fn set(&self, key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        Ok(
+            match self.kvs.lock().insert(key.to_owned(), val.to_owned()) {
+                Some(prev) => SetStatus::PrevValue(prev),
+                None => SetStatus::CreatedNew,
+            },
+        )
+    }
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.kvs.lock().remove(key))
+    }
+    fn iter(&self) -> BoxedKvIterator<'_> {
+        let kvs = self
+            .kvs
+            .lock()
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), v.clone())))
+            .collect::<Vec<_>>();
+        Box::new(kvs.into_iter())
+    }
+}
+
+impl ListStore for MemoryStore {
+    fn get_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let lists = self.lists.lock();
+        let Some(list) = lists.get(list_key) else {
+            return Ok(None);
+        };
+        Ok(list
+            .indices
+            .get(item_key)
+            .map(|idx| list.items[idx].1.clone()))
+    }
+    fn set_in_list(&self, list_key: &[u8], item_key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        let mut lists = self.lists.lock();
+        let list = lists.entry(list_key.to_owned()).or_default();
+        if let Some(idx) = list.indices.get(item_key) {
+            let item = list.items.get_mut(idx).unwrap();
+            return Ok(SetStatus::PrevValue(std::mem::replace(
+                &mut item.1,
+                val.to_owned(),
+            )));
+        }
+        let idx = list.next_idx;
+        list.next_idx += 1;
+        list.indices.insert(item_key.to_owned(), idx);
+        list.items
+            .insert(idx, (item_key.to_owned(), val.to_owned()));
+        Ok(SetStatus::CreatedNew)
+    }
+    fn remove_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut lists = self.lists.lock();
+        let Some(list) = lists.get_mut(list_key) else {
+            return Ok(None);
+        };
+        let Some(idx) = list.indices.remove(item_key) else {
+            return Ok(None);
+        };
+        let (_, val) = list.items.remove(&idx).unwrap();
+        if list.items.is_empty() {
+            lists.remove(list_key);
+        }
+        Ok(Some(val))
+    }
+    fn iter_list(&self, list_key: &[u8]) -> BoxedKvIterator<'_> {
+        let items = self
+            .lists
+            .lock()
+            .get(list_key)
+            .map(|list| {
+                list.items
+                    .values()
+                    .map(|kv| Ok(kv.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        Box::new(items.into_iter())
+    }
+    fn list_len(&self, list_key: &[u8]) -> Result<usize> {
+        Ok(self
+            .lists
+            .lock()
+            .get(list_key)
+            .map_or(0, |list| list.items.len()))
+    }
+    fn discard_list(&self, list_key: &[u8]) -> Result<bool> {
+        Ok(self.lists.lock().remove(list_key).is_some())
+    }
+}
+
+/// An operation recorded by [RecordingStore]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum StoreOp {
+    Get {
+        key: Vec<u8>,
+    },
+    Set {
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+    Iter,
+    GetFromList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+    },
+    SetInList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    RemoveFromList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+    },
+    IterList {
+        list_key: Vec<u8>,
+    },
+    ListLen {
+        list_key: Vec<u8>,
+    },
+    DiscardList {
+        list_key: Vec<u8>,
+    },
+}
+
+impl StoreOp {
+    /// Performs this operation on the given store, discarding its result (iterations are fully consumed)
+    pub fn apply<S: KvStore + ListStore + ?Sized>(&self, store: &S) -> Result<()> {
+        match self {
+            Self::Get { key } => _ = KvStore::get(store, key)?,
+            Self::Set { key, val } => _ = KvStore::set(store, key, val)?,
+            Self::Remove { key } => _ = KvStore::remove(store, key)?,
+            Self::Iter => {
+                for res in KvStore::iter(store) {
+                    res?;
+                }
+            }
+            Self::GetFromList { list_key, item_key } => {
+                _ = store.get_from_list(list_key, item_key)?
+            }
+            Self::SetInList {
+                list_key,
+                item_key,
+                val,
+            } => _ = store.set_in_list(list_key, item_key, val)?,
+            Self::RemoveFromList { list_key, item_key } => {
+                _ = store.remove_from_list(list_key, item_key)?
+            }
+            Self::IterList { list_key } => {
+                for res in store.iter_list(list_key) {
+                    res?;
+                }
+            }
+            Self::ListLen { list_key } => _ = store.list_len(list_key)?,
+            Self::DiscardList { list_key } => _ = store.discard_list(list_key)?,
+        }
+        Ok(())
+    }
+}
+
+/// An operation recorded by [RecordingStore], along with when it started and how long it took. For iterations,
+/// only creating the iterator is timed
+#[derive(Debug, Clone)]
+pub struct RecordedOp {
+    pub op: StoreOp,
+    /// when the operation started, relative to the creation of the [RecordingStore]
+    pub at: Duration,
+    pub duration: Duration,
+    /// whether the operation returned an error
+    pub failed: bool,
+}
+
+/// Wraps a store (e.g., a [crate::CandyStore] or a [MemoryStore]) and records every operation performed
+/// through the [KvStore] and [ListStore] traits, so tests can assert on the interactions of the code under
+/// test, or replay them against another store (see [Self::replay])
+pub struct RecordingStore<S> {
+    inner: S,
+    started: Instant,
+    ops: Mutex<Vec<RecordedOp>>,
+}
+
+impl<S> RecordingStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            started: Instant::now(),
+            ops: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns (a copy of) the operations recorded so far, in the order they were performed
+    pub fn recorded(&self) -> Vec<RecordedOp> {
+        self.ops.lock().clone()
+    }
+
+    /// Returns only the operations recorded so far (without timings)
+    pub fn ops(&self) -> Vec<StoreOp> {
+        self.ops.lock().iter().map(|r| r.op.clone()).collect()
+    }
+
+    /// Returns the operations recorded so far and clears the recording
+    pub fn take_recorded(&self) -> Vec<RecordedOp> {
+        std::mem::take(&mut *self.ops.lock())
+    }
+
+    /// Performs the operations recorded so far, in order, on the given store (which is not recorded).
+    /// Returns on the first error
+    pub fn replay<T: KvStore + ListStore + ?Sized>(&self, target: &T) -> Result<()> {
+        for op in self.ops() {
+            op.apply(target)?;
+        }
+        Ok(())
+    }
+
+    fn record<T>(&self, op: StoreOp, func: impl FnOnce() -> Result<T>) -> Result<T> {
+        let t0 = Instant::now();
+        let res = func();
+        self.ops.lock().push(RecordedOp {
+            op,
+            at: t0.duration_since(self.started),
+            duration: Instant::now().duration_since(t0),
+            failed: res.is_err(),
+        });
+        res
+    }
+}
+
+impl<S: KvStore> KvStore for RecordingStore<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record(
+            StoreOp::Get {
+                key: key.to_owned(),
+            },
+            || self.inner.get(key),
+        )
+    }
+    fn set(&self, key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        self.record(
+            StoreOp::Set {
+                key: key.to_owned(),
+                val: val.to_owned(),
+            },
+            || self.inner.set(key, val),
+        )
+    }
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record(
+            StoreOp::Remove {
+                key: key.to_owned(),
+            },
+            || self.inner.remove(key),
+        )
+    }
+    fn iter(&self) -> BoxedKvIterator<'_> {
+        self.record(StoreOp::Iter, || Ok(self.inner.iter()))
+            .unwrap()
+    }
+}
+
+impl<S: ListStore> ListStore for RecordingStore<S> {
+    fn get_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record(
+            StoreOp::GetFromList {
+                list_key: list_key.to_owned(),
+                item_key: item_key.to_owned(),
+            },
+            || self.inner.get_from_list(list_key, item_key),
+        )
+    }
+    fn set_in_list(&self, list_key: &[u8], item_key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        self.record(
+            StoreOp::SetInList {
+                list_key: list_key.to_owned(),
+                item_key: item_key.to_owned(),
+                val: val.to_owned(),
+            },
+            || self.inner.set_in_list(list_key, item_key, val),
+        )
+    }
+    fn remove_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record(
+            StoreOp::RemoveFromList {
+                list_key: list_key.to_owned(),
+                item_key: item_key.to_owned(),
+            },
+            || self.inner.remove_from_list(list_key, item_key),
+        )
+    }
+    fn iter_list(&self, list_key: &[u8]) -> BoxedKvIterator<'_> {
+        self.record(
+            StoreOp::IterList {
+                list_key: list_key.to_owned(),
+            },
+            || Ok(self.inner.iter_list(list_key)),
+        )
+        .unwrap()
+    }
+    fn list_len(&self, list_key: &[u8]) -> Result<usize> {
+        self.record(
+            StoreOp::ListLen {
+                list_key: list_key.to_owned(),
+            },
+            || self.inner.list_len(list_key),
+        )
+    }
+    fn discard_list(&self, list_key: &[u8]) -> Result<bool> {
+        self.record(
+            StoreOp::DiscardList {
+                list_key: list_key.to_owned(),
+            },
+            || self.inner.discard_list(list_key),
+        )
+    }
+}
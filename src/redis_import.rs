@@ -0,0 +1,549 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{CandyError, CandyStore, Result, MAX_VALUE_SIZE};
+
+/// Controls [CandyStore::import_redis_rdb]
+#[derive(Debug, Clone, Default)]
+pub struct RedisImportParams {
+    /// only import keys of this database (`SELECT`-ed index). `None` imports all databases into the same
+    /// keyspace
+    pub only_db: Option<u64>,
+    /// also import keys whose expiry time has already passed (expiry times are not kept either way)
+    pub include_expired: bool,
+}
+
+/// The number of keys imported by [CandyStore::import_redis_rdb], by type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RedisImportStats {
+    /// strings, imported as plain keys
+    pub num_strings: usize,
+    /// hashes, imported as lists (field → value)
+    pub num_hashes: usize,
+    /// lists, imported as queues
+    pub num_lists: usize,
+    /// sets, imported as lists of members with empty values
+    pub num_sets: usize,
+    /// keys that were skipped: expired, in other databases, or of unsupported types (sorted sets)
+    pub num_skipped: usize,
+}
+
+const RDB_OPCODE_SLOT_INFO: u8 = 0xf4;
+const RDB_OPCODE_FUNCTION2: u8 = 0xf5;
+const RDB_OPCODE_MODULE_AUX: u8 = 0xf7;
+const RDB_OPCODE_IDLE: u8 = 0xf8;
+const RDB_OPCODE_FREQ: u8 = 0xf9;
+const RDB_OPCODE_AUX: u8 = 0xfa;
+const RDB_OPCODE_RESIZEDB: u8 = 0xfb;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xfd;
+const RDB_OPCODE_SELECTDB: u8 = 0xfe;
+const RDB_OPCODE_EOF: u8 = 0xff;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+
+fn malformed(msg: impl std::fmt::Display) -> CandyError {
+    CandyError::InvalidArgument(format!("malformed RDB: {msg}"))
+}
+
+enum Length {
+    Len(u64),
+    // a string encoded as an integer or compressed (the low 6 bits of the length byte)
+    Special(u8),
+}
+
+enum RedisValue {
+    String(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    Hash(Vec<(Vec<u8>, Vec<u8>)>),
+    Unsupported,
+}
+
+struct RdbReader<R> {
+    reader: R,
+}
+
+impl<R: Read> RdbReader<R> {
+    // lengths come from the file, so nothing is allocated up front: strings longer than MAX_VALUE_SIZE could not
+    // be imported anyway, and a truncated file ends the read early
+    fn read_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        if len > MAX_VALUE_SIZE as u64 {
+            return Err(malformed(format!("string of {len} bytes is too long")));
+        }
+        let mut buf = vec![];
+        (&mut self.reader).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(malformed("truncated string"));
+        }
+        Ok(buf)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_length_encoding(&mut self) -> Result<Length> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(Length::Len((first & 0x3f) as u64)),
+            1 => Ok(Length::Len(
+                (((first & 0x3f) as u64) << 8) | self.read_u8()? as u64,
+            )),
+            2 => match first {
+                0x80 => Ok(Length::Len(u32::from_be_bytes(self.read_array()?) as u64)),
+                0x81 => Ok(Length::Len(u64::from_be_bytes(self.read_array()?))),
+                _ => Err(malformed(format!("bad length encoding 0x{first:02x}"))),
+            },
+            _ => Ok(Length::Special(first & 0x3f)),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Special(_) => Err(malformed("expected a length, found an encoded string")),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>> {
+        match self.read_length_encoding()? {
+            Length::Len(len) => self.read_bytes(len),
+            Length::Special(0) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Special(1) => Ok(i16::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            Length::Special(2) => Ok(i32::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            Length::Special(3) => {
+                let compressed_len = self.read_length()?;
+                let len = self.read_length()?;
+                if len > MAX_VALUE_SIZE as u64 {
+                    return Err(malformed(format!("string of {len} bytes is too long")));
+                }
+                let compressed = self.read_bytes(compressed_len)?;
+                lzf_decompress(&compressed, len as usize)
+            }
+            Length::Special(enc) => Err(malformed(format!("unknown string encoding {enc}"))),
+        }
+    }
+
+    // sorted set scores of RDB_TYPE_ZSET are stored as strings (with special lengths for nan and infinities)
+    fn skip_string_double(&mut self) -> Result<()> {
+        let len = self.read_u8()?;
+        if len < 253 {
+            self.read_bytes(len as u64)?;
+        }
+        Ok(())
+    }
+
+    fn read_strings(&mut self) -> Result<Vec<Vec<u8>>> {
+        let len = self.read_length()?;
+        (0..len).map(|_| self.read_string()).collect()
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<RedisValue> {
+        match value_type {
+            RDB_TYPE_STRING => Ok(RedisValue::String(self.read_string()?)),
+            RDB_TYPE_LIST => Ok(RedisValue::List(self.read_strings()?)),
+            RDB_TYPE_SET => Ok(RedisValue::Set(self.read_strings()?)),
+            RDB_TYPE_HASH => {
+                // the number of pairs comes from the file, so the vector grows as they are read
+                let mut pairs = vec![];
+                for _ in 0..self.read_length()? {
+                    pairs.push((self.read_string()?, self.read_string()?));
+                }
+                Ok(RedisValue::Hash(pairs))
+            }
+            RDB_TYPE_ZSET => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.skip_string_double()?;
+                }
+                Ok(RedisValue::Unsupported)
+            }
+            RDB_TYPE_ZSET_2 => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.read_array::<8>()?;
+                }
+                Ok(RedisValue::Unsupported)
+            }
+            RDB_TYPE_ZSET_ZIPLIST | RDB_TYPE_ZSET_LISTPACK => {
+                self.read_string()?;
+                Ok(RedisValue::Unsupported)
+            }
+            RDB_TYPE_LIST_ZIPLIST => Ok(RedisValue::List(parse_ziplist(&self.read_string()?)?)),
+            RDB_TYPE_SET_INTSET => Ok(RedisValue::Set(parse_intset(&self.read_string()?)?)),
+            RDB_TYPE_SET_LISTPACK => Ok(RedisValue::Set(parse_listpack(&self.read_string()?)?)),
+            RDB_TYPE_HASH_ZIPLIST => Ok(RedisValue::Hash(into_pairs(parse_ziplist(
+                &self.read_string()?,
+            )?)?)),
+            RDB_TYPE_HASH_LISTPACK => Ok(RedisValue::Hash(into_pairs(parse_listpack(
+                &self.read_string()?,
+            )?)?)),
+            RDB_TYPE_LIST_QUICKLIST => {
+                let mut items = vec![];
+                for _ in 0..self.read_length()? {
+                    items.extend(parse_ziplist(&self.read_string()?)?);
+                }
+                Ok(RedisValue::List(items))
+            }
+            RDB_TYPE_LIST_QUICKLIST_2 => {
+                let mut items = vec![];
+                for _ in 0..self.read_length()? {
+                    let container = self.read_length()?;
+                    let node = self.read_string()?;
+                    if container == QUICKLIST_NODE_CONTAINER_PLAIN {
+                        items.push(node);
+                    } else {
+                        items.extend(parse_listpack(&node)?);
+                    }
+                }
+                Ok(RedisValue::List(items))
+            }
+            _ => Err(malformed(format!(
+                "unsupported value type {value_type} (streams and modules cannot be imported)"
+            ))),
+        }
+    }
+}
+
+fn lzf_decompress(input: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected_len);
+    let mut ip = 0;
+    while ip < input.len() {
+        let ctrl = input[ip] as usize;
+        ip += 1;
+        if ctrl < 32 {
+            // literal run
+            let run = ctrl + 1;
+            let literal = input
+                .get(ip..ip + run)
+                .ok_or_else(|| malformed("truncated LZF literal"))?;
+            out.extend_from_slice(literal);
+            ip += run;
+        } else {
+            // back reference
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(ip).ok_or_else(|| malformed("truncated LZF"))? as usize;
+                ip += 1;
+            }
+            let low = *input.get(ip).ok_or_else(|| malformed("truncated LZF"))? as usize;
+            ip += 1;
+            let back = ((ctrl & 0x1f) << 8) + low + 1;
+            if back > out.len() {
+                return Err(malformed("LZF back reference out of bounds"));
+            }
+            let start = out.len() - back;
+            // the referenced range may overlap the bytes being written
+            for i in 0..len + 2 {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    if out.len() != expected_len {
+        return Err(malformed(format!(
+            "LZF decompressed to {} bytes, expected {expected_len}",
+            out.len()
+        )));
+    }
+    Ok(out)
+}
+
+fn take<'a>(buf: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let bytes = pos
+        .checked_add(len)
+        .and_then(|end| buf.get(*pos..end))
+        .ok_or_else(|| malformed("truncated ziplist/listpack/intset"))?;
+    *pos += len;
+    Ok(bytes)
+}
+
+fn parse_ziplist(buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+    // zlbytes: u32, zltail: u32, zllen: u16
+    let mut pos = 10;
+    let mut items = vec![];
+    loop {
+        let prevlen = take(buf, &mut pos, 1)?[0];
+        if prevlen == 0xff {
+            break;
+        }
+        if prevlen == 0xfe {
+            take(buf, &mut pos, 4)?;
+        }
+        let enc = take(buf, &mut pos, 1)?[0];
+        let item = match enc >> 6 {
+            0 => take(buf, &mut pos, (enc & 0x3f) as usize)?.to_vec(),
+            1 => {
+                let len = (((enc & 0x3f) as usize) << 8) | take(buf, &mut pos, 1)?[0] as usize;
+                take(buf, &mut pos, len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as usize;
+                take(buf, &mut pos, len)?.to_vec()
+            }
+            _ => {
+                let n: i64 = match enc {
+                    0xc0 => i16::from_le_bytes(take(buf, &mut pos, 2)?.try_into().unwrap()) as i64,
+                    0xd0 => i32::from_le_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as i64,
+                    0xe0 => i64::from_le_bytes(take(buf, &mut pos, 8)?.try_into().unwrap()),
+                    0xf0 => {
+                        let b = take(buf, &mut pos, 3)?;
+                        (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64
+                    }
+                    0xfe => take(buf, &mut pos, 1)?[0] as i8 as i64,
+                    0xf1..=0xfd => (enc & 0x0f) as i64 - 1,
+                    _ => return Err(malformed(format!("bad ziplist encoding 0x{enc:02x}"))),
+                };
+                n.to_string().into_bytes()
+            }
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn parse_listpack(buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+    // total bytes: u32, num elements: u16
+    let mut pos = 6;
+    let mut items = vec![];
+    loop {
+        let entry_start = pos;
+        let enc = take(buf, &mut pos, 1)?[0];
+        if enc == 0xff {
+            break;
+        }
+        let item = if enc & 0x80 == 0 {
+            (enc as i64).to_string().into_bytes()
+        } else if enc & 0xc0 == 0x80 {
+            take(buf, &mut pos, (enc & 0x3f) as usize)?.to_vec()
+        } else if enc & 0xe0 == 0xc0 {
+            let raw = (((enc & 0x1f) as i64) << 8) | take(buf, &mut pos, 1)?[0] as i64;
+            // sign-extend the 13 bit integer
+            ((raw << 51) >> 51).to_string().into_bytes()
+        } else if enc & 0xf0 == 0xe0 {
+            let len = (((enc & 0x0f) as usize) << 8) | take(buf, &mut pos, 1)?[0] as usize;
+            take(buf, &mut pos, len)?.to_vec()
+        } else {
+            let n: i64 = match enc {
+                0xf0 => {
+                    let len =
+                        u32::from_le_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as usize;
+                    items.push(take(buf, &mut pos, len)?.to_vec());
+                    skip_listpack_backlen(buf, &mut pos, entry_start)?;
+                    continue;
+                }
+                0xf1 => i16::from_le_bytes(take(buf, &mut pos, 2)?.try_into().unwrap()) as i64,
+                0xf2 => {
+                    let b = take(buf, &mut pos, 3)?;
+                    (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64
+                }
+                0xf3 => i32::from_le_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as i64,
+                0xf4 => i64::from_le_bytes(take(buf, &mut pos, 8)?.try_into().unwrap()),
+                _ => return Err(malformed(format!("bad listpack encoding 0x{enc:02x}"))),
+            };
+            n.to_string().into_bytes()
+        };
+        items.push(item);
+        skip_listpack_backlen(buf, &mut pos, entry_start)?;
+    }
+    Ok(items)
+}
+
+// every listpack entry ends with the length of its encoding and data, which takes 1-5 bytes
+fn skip_listpack_backlen(buf: &[u8], pos: &mut usize, entry_start: usize) -> Result<()> {
+    let entry_len = *pos - entry_start;
+    let backlen_size = match entry_len {
+        0..128 => 1,
+        128..16384 => 2,
+        16384..2097152 => 3,
+        2097152..268435456 => 4,
+        _ => 5,
+    };
+    take(buf, pos, backlen_size)?;
+    Ok(())
+}
+
+fn parse_intset(buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut pos = 0;
+    let width = u32::from_le_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(take(buf, &mut pos, 4)?.try_into().unwrap()) as usize;
+    (0..len)
+        .map(|_| {
+            let b = take(buf, &mut pos, width)?;
+            let n: i64 = match width {
+                2 => i16::from_le_bytes(b.try_into().unwrap()) as i64,
+                4 => i32::from_le_bytes(b.try_into().unwrap()) as i64,
+                8 => i64::from_le_bytes(b.try_into().unwrap()),
+                _ => return Err(malformed(format!("bad intset width {width}"))),
+            };
+            Ok(n.to_string().into_bytes())
+        })
+        .collect()
+}
+
+fn into_pairs(items: Vec<Vec<u8>>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !items.len().is_multiple_of(2) {
+        return Err(malformed("hash with an odd number of elements"));
+    }
+    let mut pairs = Vec::with_capacity(items.len() / 2);
+    let mut iter = items.into_iter();
+    while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+        pairs.push((k, v));
+    }
+    Ok(pairs)
+}
+
+impl CandyStore {
+    /// Imports a Redis RDB dump (as produced by `SAVE`/`BGSAVE`) into the store: strings become plain keys,
+    /// hashes become lists (field → value), lists become queues and sets become lists of members (with empty
+    /// values). Existing keys are overwritten, and list/queue elements are added to existing collections.
+    /// Expiry times are not kept, sorted sets are skipped, and streams or module types fail the import.
+    ///
+    /// Note: this is not atomic, failing midway leaves the keys imported so far in the store
+    pub fn import_redis_rdb(
+        &self,
+        reader: impl Read,
+        params: RedisImportParams,
+    ) -> Result<RedisImportStats> {
+        let mut rdb = RdbReader { reader };
+        let magic = rdb.read_array::<9>()?;
+        if &magic[..5] != b"REDIS" {
+            return Err(malformed("missing REDIS magic"));
+        }
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut stats = RedisImportStats::default();
+        let mut db = 0;
+        let mut expires_at_ms = None;
+
+        loop {
+            let opcode = rdb.read_u8()?;
+            match opcode {
+                RDB_OPCODE_EOF => break,
+                RDB_OPCODE_SELECTDB => db = rdb.read_length()?,
+                RDB_OPCODE_RESIZEDB => {
+                    rdb.read_length()?;
+                    rdb.read_length()?;
+                }
+                RDB_OPCODE_AUX => {
+                    rdb.read_string()?;
+                    rdb.read_string()?;
+                }
+                RDB_OPCODE_EXPIRETIME => {
+                    expires_at_ms = Some(u32::from_le_bytes(rdb.read_array()?) as u64 * 1000)
+                }
+                RDB_OPCODE_EXPIRETIME_MS => {
+                    expires_at_ms = Some(u64::from_le_bytes(rdb.read_array()?))
+                }
+                RDB_OPCODE_IDLE => {
+                    rdb.read_length()?;
+                }
+                RDB_OPCODE_FREQ => {
+                    rdb.read_u8()?;
+                }
+                RDB_OPCODE_SLOT_INFO => {
+                    // slot id, slot size and expires slot size
+                    for _ in 0..3 {
+                        rdb.read_length()?;
+                    }
+                }
+                RDB_OPCODE_FUNCTION2 => {
+                    rdb.read_string()?;
+                }
+                RDB_OPCODE_MODULE_AUX => {
+                    return Err(malformed("module data cannot be imported"));
+                }
+                value_type => {
+                    let key = rdb.read_string()?;
+                    let value = rdb.read_value(value_type)?;
+                    let expired = expires_at_ms.take().is_some_and(|exp| exp <= now_ms);
+                    if (expired && !params.include_expired)
+                        || params.only_db.is_some_and(|only_db| only_db != db)
+                    {
+                        stats.num_skipped += 1;
+                        continue;
+                    }
+                    self.import_redis_value(key, value, &mut stats)?;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Same as [Self::import_redis_rdb], but reads the dump from the given file
+    pub fn import_redis_rdb_file(
+        &self,
+        path: impl AsRef<Path>,
+        params: RedisImportParams,
+    ) -> Result<RedisImportStats> {
+        self.import_redis_rdb(BufReader::new(File::open(path)?), params)
+    }
+
+    fn import_redis_value(
+        &self,
+        key: Vec<u8>,
+        value: RedisValue,
+        stats: &mut RedisImportStats,
+    ) -> Result<()> {
+        match value {
+            RedisValue::String(val) => {
+                self.owned_set(key, &val)?;
+                stats.num_strings += 1;
+            }
+            RedisValue::Hash(pairs) => {
+                for (field, val) in pairs {
+                    self.owned_set_in_list(key.clone(), field, val, false)?;
+                }
+                stats.num_hashes += 1;
+            }
+            RedisValue::List(items) => {
+                self.extend_queue(&key, items.iter())?;
+                stats.num_lists += 1;
+            }
+            RedisValue::Set(members) => {
+                for member in members {
+                    self.owned_set_in_list(key.clone(), member, vec![], false)?;
+                }
+                stats.num_sets += 1;
+            }
+            RedisValue::Unsupported => stats.num_skipped += 1,
+        }
+        Ok(())
+    }
+}
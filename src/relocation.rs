@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{checksums::FileChecksums, store::DirLock, CandyError, CandyStore, Result};
+
+// makes the entries created (or renamed) in the directory durable
+fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+// copies the files of the store's directory (all but its lock file) into a new directory, verifying each copy
+// against its source
+fn copy_store_files(src: &Path, dst: &Path) -> Result<()> {
+    std::fs::create_dir(dst)?;
+    for res in std::fs::read_dir(src)? {
+        let entry = res?;
+        if !entry.file_type()?.is_file() || entry.file_name() == ".lock" {
+            continue;
+        }
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        std::fs::copy(&src_path, &dst_path)?;
+        File::open(&dst_path)?.sync_all()?;
+        let len = entry.metadata()?.len();
+        if FileChecksums::of_file(&src_path, len)? != FileChecksums::of_file(&dst_path, len)? {
+            return Err(CandyError::Corruption(format!(
+                "{dst_path:?} differs from {src_path:?} after copying"
+            )));
+        }
+    }
+    sync_dir(dst)
+}
+
+impl CandyStore {
+    /// Moves the store's directory to `new_path` (which must not exist), while the store remains open: all
+    /// handles keep working, and use the new directory from then on. Operations wait while the files are
+    /// moved, so this takes the store offline for the duration of the move.
+    ///
+    /// Within a volume, the directory is simply renamed, which is atomic. Across volumes, the files are copied
+    /// to a staging directory next to `new_path` (named `<new_path>.relocating`), verified against their
+    /// sources, and the staging directory is then renamed to `new_path`, after which the old directory is
+    /// removed. A crash during the copy leaves the old directory intact (and a staging directory behind, which
+    /// the next relocation removes), while a crash after the switch may leave two complete copies of the store.
+    ///
+    /// Stores opened with [Self::open_at] cannot be relocated
+    pub fn relocate(&self, new_path: impl AsRef<Path>) -> Result<()> {
+        self.relocate_impl(new_path.as_ref(), false)
+    }
+
+    fn relocate_impl(&self, new_path: &Path, force_copy: bool) -> Result<()> {
+        let mut dir_lock = self.0.dir_lock.lock();
+        if dir_lock.is_dirfd() {
+            return Err(CandyError::InvalidArgument(
+                "stores opened with open_at cannot be relocated".into(),
+            ));
+        }
+        if std::fs::symlink_metadata(new_path).is_ok() {
+            return Err(CandyError::InvalidArgument(format!(
+                "{new_path:?} already exists"
+            )));
+        }
+        let Some(name) = new_path.file_name() else {
+            return Err(CandyError::InvalidArgument(format!(
+                "{new_path:?} is not a valid directory name"
+            )));
+        };
+        let parent = match new_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        std::fs::create_dir_all(&parent)?;
+        let staging_path = parent.join(format!("{}.relocating", name.to_string_lossy()));
+
+        let old_path = self.0.config.dir_path.get();
+        let mut copied = false;
+        self.0.root.with_all_shards_frozen(&mut || {
+            let res = if force_copy {
+                Err(ErrorKind::CrossesDevices.into())
+            } else {
+                std::fs::rename(&old_path, new_path)
+            };
+            match res {
+                Ok(()) => {
+                    // the shard files keep their mappings, and the lock file moves along
+                    self.0.config.dir_path.set(new_path.to_owned());
+                    sync_dir(&parent)?;
+                    dir_lock.renamed(new_path)?;
+                    Ok(false)
+                }
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                    if std::fs::symlink_metadata(&staging_path).is_ok() {
+                        std::fs::remove_dir_all(&staging_path)?;
+                    }
+                    if let Err(e) = copy_store_files(&old_path, &staging_path) {
+                        _ = std::fs::remove_dir_all(&staging_path);
+                        return Err(e);
+                    }
+                    std::fs::rename(&staging_path, new_path)?;
+                    sync_dir(&parent)?;
+                    *dir_lock = DirLock::acquire(new_path, None)?;
+                    // the shards are closed before the nodes are released, and reopened from the copies
+                    self.0.config.dir_path.set(new_path.to_owned());
+                    copied = true;
+                    Ok(true)
+                }
+                Err(e) => Err(e.into()),
+            }
+        })?;
+
+        if copied {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_relocate_by_copying() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("candy-relocate-{}", std::process::id()));
+    _ = std::fs::remove_dir_all(&dir);
+    let (src, dst) = (dir.join("src"), dir.join("dst"));
+
+    let db = CandyStore::open(&src, crate::Config::default())?;
+    for i in 0..1000u32 {
+        db.set(&i.to_le_bytes(), &i.to_be_bytes())?;
+    }
+    db.push_to_queue_tail("q", "x")?;
+    let db2 = db.clone();
+
+    db.relocate_impl(&dst, true)?;
+    assert!(!src.exists());
+    assert!(dst.join(".lock").exists());
+    assert_eq!(db.get_shards_directory(), dst);
+    for i in 0..1000u32 {
+        assert_eq!(db2.get(&i.to_le_bytes())?, Some(i.to_be_bytes().to_vec()));
+    }
+    db2.set("after", "move")?;
+    assert_eq!(db.pop_queue_head("q")?, Some("x".into()));
+    drop(db);
+    drop(db2);
+
+    let db = CandyStore::open(&dst, crate::Config::default())?;
+    assert_eq!(db.get("after")?, Some("move".into()));
+    assert_eq!(
+        db.get(&7u32.to_le_bytes())?,
+        Some(7u32.to_be_bytes().to_vec())
+    );
+    drop(db);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+use databuf::{config::num::LE, Decode, Encode};
+
+use crate::{CandyError, KvStore, ListStore, RecordedOp, Result, StoreOp};
+
+const WORKLOAD_MAGIC: &[u8; 8] = b"CandyWL1";
+
+#[derive(Encode, Decode)]
+struct WorkloadRecord {
+    at_micros: u64,
+    duration_nanos: u64,
+    failed: bool,
+    op: StoreOp,
+}
+
+/// Writes recorded operations (see [crate::RecordingStore::recorded]) to a workload log, which can later be
+/// loaded with [read_workload] and replayed with [replay_workload], e.g., to reproduce a user's access
+/// pattern. Returns the number of bytes written
+pub fn write_workload(ops: &[RecordedOp], mut writer: impl Write) -> Result<usize> {
+    writer.write_all(WORKLOAD_MAGIC)?;
+    let mut written = WORKLOAD_MAGIC.len();
+    for rec in ops {
+        let buf = WorkloadRecord {
+            at_micros: rec.at.as_micros() as u64,
+            duration_nanos: rec.duration.as_nanos() as u64,
+            failed: rec.failed,
+            op: rec.op.clone(),
+        }
+        .to_bytes::<LE>();
+        writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+        writer.write_all(&buf)?;
+        written += 4 + buf.len();
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Reads a workload log written by [write_workload]
+pub fn read_workload(mut reader: impl Read) -> Result<Vec<RecordedOp>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != WORKLOAD_MAGIC {
+        return Err(CandyError::Corruption("not a workload log".into()));
+    }
+
+    let mut ops = vec![];
+    let mut buf = vec![];
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        buf.resize(u32::from_le_bytes(len) as usize, 0);
+        reader.read_exact(&mut buf)?;
+        let rec = WorkloadRecord::from_bytes::<LE>(&buf)
+            .map_err(|e| CandyError::Corruption(format!("bad workload record: {e}")))?;
+        ops.push(RecordedOp {
+            op: rec.op,
+            at: Duration::from_micros(rec.at_micros),
+            duration: Duration::from_nanos(rec.duration_nanos),
+            failed: rec.failed,
+        });
+    }
+    Ok(ops)
+}
+
+/// Controls [replay_workload]
+#[derive(Debug, Clone, Default)]
+pub struct ReplayParams {
+    /// the speed relative to the recording, e.g., `2.0` issues the operations twice as fast as they were
+    /// recorded. `None` replays the operations back to back, as fast as possible
+    pub speed: Option<f64>,
+}
+
+/// The outcome of [replay_workload]
+#[derive(Debug, Clone, Default)]
+pub struct ReplayStats {
+    pub num_ops: usize,
+    /// operations that returned an error (the replay carries on)
+    pub num_failed: usize,
+    /// the wall-clock time of the replay, including the pacing delays
+    pub elapsed: Duration,
+    /// the time spent in the operations themselves
+    pub total_op_duration: Duration,
+    pub max_op_duration: Duration,
+    /// the number of operations that were issued later than scheduled, because the target store could not
+    /// keep up with the requested speed
+    pub num_lagging: usize,
+}
+
+impl ReplayStats {
+    pub fn avg_op_duration(&self) -> Duration {
+        if self.num_ops == 0 {
+            Duration::ZERO
+        } else {
+            self.total_op_duration / self.num_ops as u32
+        }
+    }
+}
+
+/// Replays a recorded workload (see [read_workload] or [crate::RecordingStore::recorded]) against the given
+/// store, preserving the original timing between operations at the requested speed. This is meant for
+/// capacity testing and for reproducing performance regressions, so the target would usually be a fresh
+/// store. Errors returned by individual operations are counted rather than aborting the replay, since the
+/// recording may contain failed operations as well
+pub fn replay_workload<S: KvStore + ListStore + ?Sized>(
+    ops: &[RecordedOp],
+    target: &S,
+    params: &ReplayParams,
+) -> Result<ReplayStats> {
+    if params
+        .speed
+        .is_some_and(|speed| speed.is_nan() || speed <= 0.0)
+    {
+        return Err(CandyError::InvalidArgument(
+            "replay speed must be positive".into(),
+        ));
+    }
+
+    let mut stats = ReplayStats::default();
+    let t0 = Instant::now();
+    let first_at = ops.iter().map(|rec| rec.at).min().unwrap_or_default();
+    for rec in ops {
+        if let Some(speed) = params.speed {
+            let due = (rec.at - first_at).div_f64(speed);
+            let now = t0.elapsed();
+            if now < due {
+                std::thread::sleep(due - now);
+            } else if now > due + Duration::from_millis(1) {
+                stats.num_lagging += 1;
+            }
+        }
+
+        let t1 = Instant::now();
+        let res = rec.op.apply(target);
+        let dur = t1.elapsed();
+        stats.num_ops += 1;
+        stats.total_op_duration += dur;
+        stats.max_op_duration = stats.max_op_duration.max(dur);
+        if res.is_err() {
+            stats.num_failed += 1;
+        }
+    }
+    stats.elapsed = t0.elapsed();
+    Ok(stats)
+}
@@ -1,11 +1,10 @@
-use anyhow::ensure;
-use parking_lot::RwLock;
-use std::{ops::Range, sync::Arc};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{ops::Range, sync::Arc, time::Instant};
 
 use crate::shard::{CompactionThreadPool, InsertMode, InsertStatus, Shard};
 use crate::stats::InternalStats;
-use crate::Result;
 use crate::{hashing::PartedHash, store::InternalConfig};
+use crate::{CandyError, Result};
 
 fn consolidate_ranges(mut ranges: Vec<Range<u32>>) -> (Vec<Range<u32>>, Vec<Range<u32>>) {
     // we may encounter unfinished splits, where we have any combination of the bottom half, top half and
@@ -53,12 +52,15 @@ fn test_consolidate_ranges() {
 enum ShardNode {
     Leaf(Shard),
     Vertex(Arc<ShardRouter>, Arc<ShardRouter>),
+    // a shard whose file has not been opened yet, see Config::lazy_open
+    Unopened(Range<u32>),
 }
 
 impl ShardNode {
     fn span(&self) -> Range<u32> {
         match self {
             Self::Leaf(sh) => sh.span.clone(),
+            Self::Unopened(span) => span.clone(),
             Self::Vertex(bottom, top) => bottom.span.start..top.span.end,
         }
     }
@@ -85,9 +87,12 @@ impl ShardRouter {
     ) -> Result<Self> {
         let mut shards = Self::load(&config, &stats, &threadpool)?;
         if shards.is_empty() {
-            shards = Self::create_initial_shards(&config, &stats, &threadpool)?;
+            shards = Self::create_initial_shards(&config, &stats, &threadpool)?
+                .into_iter()
+                .map(ShardNode::Leaf)
+                .collect();
         }
-        let root = Self::treeify(shards, &stats, &threadpool);
+        let root = Self::treeify(shards, &config, &stats, &threadpool);
         Ok(Self {
             span: root.span(),
             config,
@@ -101,9 +106,9 @@ impl ShardRouter {
         config: &Arc<InternalConfig>,
         stats: &Arc<InternalStats>,
         threadpool: &Arc<CompactionThreadPool>,
-    ) -> Result<Vec<Shard>> {
+    ) -> Result<Vec<ShardNode>> {
         let mut found_shards = vec![];
-        for res in std::fs::read_dir(&config.dir_path)? {
+        for res in std::fs::read_dir(config.dir_path.get())? {
             let entry = res?;
             let filename = entry.file_name();
             let Some(filename) = filename.to_str() else {
@@ -118,6 +123,7 @@ impl ShardRouter {
             if filename.starts_with("bottom_")
                 || filename.starts_with("top_")
                 || filename.starts_with("merge_")
+                || filename.starts_with("drop_")
             {
                 std::fs::remove_file(entry.path())?;
                 continue;
@@ -133,10 +139,9 @@ impl ShardRouter {
             let start = u32::from_str_radix(start, 16).expect(filename);
             let end = u32::from_str_radix(end, 16).expect(filename);
 
-            ensure!(
-                start < end && end <= Self::END_OF_SHARDS,
-                "Bad span for {filename}"
-            );
+            if start >= end || end > Self::END_OF_SHARDS {
+                return Err(CandyError::Corruption(format!("bad span for {filename}")));
+            }
 
             found_shards.push(start..end);
         }
@@ -152,13 +157,17 @@ impl ShardRouter {
 
         let mut shards = vec![];
         for span in shards_to_keep {
-            shards.push(Shard::open(
-                span,
-                false,
-                config.clone(),
-                stats.clone(),
-                threadpool.clone(),
-            )?);
+            if config.lazy_open {
+                shards.push(ShardNode::Unopened(span));
+            } else {
+                shards.push(ShardNode::Leaf(Shard::open(
+                    span,
+                    false,
+                    config.clone(),
+                    stats.clone(),
+                    threadpool.clone(),
+                )?));
+            }
         }
 
         Ok(shards)
@@ -199,13 +208,10 @@ impl ShardRouter {
 
     fn from_shardnode(
         n: ShardNode,
+        config: Arc<InternalConfig>,
         stats: Arc<InternalStats>,
         threadpool: Arc<CompactionThreadPool>,
     ) -> Self {
-        let config = match n {
-            ShardNode::Leaf(ref sh) => sh.config.clone(),
-            ShardNode::Vertex(ref bottom, _) => bottom.config.clone(),
-        };
         Self {
             config,
             span: n.span(),
@@ -216,7 +222,8 @@ impl ShardRouter {
     }
 
     fn treeify(
-        shards: Vec<Shard>,
+        shards: Vec<ShardNode>,
+        config: &Arc<InternalConfig>,
         stats: &Arc<InternalStats>,
         threadpool: &Arc<CompactionThreadPool>,
     ) -> ShardNode {
@@ -229,14 +236,13 @@ impl ShardRouter {
         let mut unit: u32 = Self::END_OF_SHARDS;
         {
             let mut spans_debug: Vec<Range<u32>> = vec![];
-            for sh in shards {
+            for n in shards {
+                let span = n.span();
                 assert!(
-                    spans_debug.is_empty() || spans_debug.last().unwrap().start != sh.span.start,
-                    "two elements with the same start {spans_debug:?} {:?}",
-                    sh.span
+                    spans_debug.is_empty() || spans_debug.last().unwrap().start != span.start,
+                    "two elements with the same start {spans_debug:?} {span:?}",
                 );
-                spans_debug.push(sh.span.clone());
-                let n = ShardNode::Leaf(sh);
+                spans_debug.push(span);
                 if unit > n.len() {
                     unit = n.len();
                 }
@@ -261,8 +267,18 @@ impl ShardRouter {
                     nodes.insert(
                         i,
                         ShardNode::Vertex(
-                            Arc::new(Self::from_shardnode(n0, stats.clone(), threadpool.clone())),
-                            Arc::new(Self::from_shardnode(n1, stats.clone(), threadpool.clone())),
+                            Arc::new(Self::from_shardnode(
+                                n0,
+                                config.clone(),
+                                stats.clone(),
+                                threadpool.clone(),
+                            )),
+                            Arc::new(Self::from_shardnode(
+                                n1,
+                                config.clone(),
+                                stats.clone(),
+                                threadpool.clone(),
+                            )),
                         ),
                     );
                 } else {
@@ -277,12 +293,35 @@ impl ShardRouter {
         nodes.remove(0)
     }
 
+    // returns the node for reading, opening the shard first if it was not opened yet
+    fn read_node(&self) -> Result<RwLockReadGuard<'_, ShardNode>> {
+        let guard = self.node.read();
+        if !matches!(*guard, ShardNode::Unopened(_)) {
+            return Ok(guard);
+        }
+        drop(guard);
+
+        let mut guard = self.node.write();
+        // someone else may have opened it in the meantime
+        if let ShardNode::Unopened(span) = &*guard {
+            *guard = ShardNode::Leaf(Shard::open(
+                span.clone(),
+                false,
+                self.config.clone(),
+                self.stats.clone(),
+                self.threadpool.clone(),
+            )?);
+        }
+        Ok(RwLockWriteGuard::downgrade(guard))
+    }
+
     pub(crate) fn shared_op<T>(
         &self,
         shard_selector: u32,
         func: impl FnOnce(&Shard) -> Result<T>,
     ) -> Result<T> {
-        match &*self.node.read() {
+        match &*self.read_node()? {
+            ShardNode::Unopened(_) => unreachable!(),
             ShardNode::Leaf(sh) => func(sh),
             ShardNode::Vertex(bottom, top) => {
                 if shard_selector < bottom.span.end {
@@ -294,10 +333,38 @@ impl ShardRouter {
         }
     }
 
+    // same as shared_op, but fails with DeadlineExceeded instead of waiting past the deadline (e.g., for a
+    // split to finish)
+    pub(crate) fn shared_op_until<T>(
+        &self,
+        shard_selector: u32,
+        deadline: Instant,
+        func: impl FnOnce(&Shard) -> Result<T>,
+    ) -> Result<T> {
+        let Some(guard) = self.node.try_read_until(deadline) else {
+            return Err(CandyError::DeadlineExceeded);
+        };
+        match &*guard {
+            ShardNode::Unopened(_) => {
+                drop(guard);
+                drop(self.read_node()?);
+                self.shared_op_until(shard_selector, deadline, func)
+            }
+            ShardNode::Leaf(sh) => func(sh),
+            ShardNode::Vertex(bottom, top) => {
+                if shard_selector < bottom.span.end {
+                    bottom.shared_op_until(shard_selector, deadline, func)
+                } else {
+                    top.shared_op_until(shard_selector, deadline, func)
+                }
+            }
+        }
+    }
+
     pub(crate) fn clear(&self) -> Result<()> {
         let mut guard = self.node.write();
 
-        for res in std::fs::read_dir(&self.config.dir_path)? {
+        for res in std::fs::read_dir(self.config.dir_path.get())? {
             let entry = res?;
             let filename = entry.file_name();
             let Some(filename) = filename.to_str() else {
@@ -313,13 +380,19 @@ impl ShardRouter {
                 || filename.starts_with("compact_")
                 || filename.starts_with("bottom_")
                 || filename.starts_with("top_")
+                || filename.starts_with("drop_")
             {
                 std::fs::remove_file(entry.path())?;
             }
         }
 
         let shards = Self::create_initial_shards(&self.config, &self.stats, &self.threadpool)?;
-        *guard = Self::treeify(shards, &self.stats, &self.threadpool);
+        *guard = Self::treeify(
+            shards.into_iter().map(ShardNode::Leaf).collect(),
+            &self.config,
+            &self.stats,
+            &self.threadpool,
+        );
 
         Ok(())
     }
@@ -328,7 +401,8 @@ impl ShardRouter {
         &self,
         mut func: impl FnMut(&Shard) -> Result<T> + Copy,
     ) -> Result<Vec<T>> {
-        match &*self.node.read() {
+        match &*self.read_node()? {
+            ShardNode::Unopened(_) => unreachable!(),
             ShardNode::Leaf(sh) => Ok(vec![func(sh)?]),
             ShardNode::Vertex(bottom, top) => {
                 let mut v = bottom.call_on_all_shards(func)?;
@@ -338,16 +412,91 @@ impl ShardRouter {
         }
     }
 
-    pub(crate) fn insert(
+    // runs `func` while every node is held exclusively and no compaction is running, so nothing touches the
+    // shard files, e.g., to move them. if `func` returns true, the shards are closed before being released,
+    // and reopened (from the store's directory at that time) on their next access
+    pub(crate) fn with_all_shards_frozen(
         &self,
+        func: &mut dyn FnMut() -> Result<bool>,
+    ) -> Result<bool> {
+        let mut guard = self.node.write();
+        let close = match &*guard {
+            ShardNode::Unopened(_) => func()?,
+            ShardNode::Leaf(sh) => {
+                sh.wait_for_compaction()?;
+                func()?
+            }
+            ShardNode::Vertex(bottom, top) => {
+                bottom.with_all_shards_frozen(&mut || top.with_all_shards_frozen(func))?
+            }
+        };
+        if close && matches!(*guard, ShardNode::Leaf(_)) {
+            *guard = ShardNode::Unopened(guard.span());
+        }
+        Ok(close)
+    }
+
+    // splits the leaf shard held by this router into two (unless it was already split)
+    fn split_leaf(&self, guard: &mut RwLockWriteGuard<ShardNode>) -> Result<()> {
+        let ShardNode::Leaf(sh) = &**guard else {
+            // already split
+            return Ok(());
+        };
+
+        let (bottom, top) = sh.split()?;
+
+        **guard = ShardNode::Vertex(
+            Arc::new(ShardRouter {
+                span: bottom.span.clone(),
+                config: self.config.clone(),
+                node: RwLock::new(ShardNode::Leaf(bottom)),
+                stats: self.stats.clone(),
+                threadpool: self.threadpool.clone(),
+            }),
+            Arc::new(ShardRouter {
+                span: top.span.clone(),
+                config: self.config.clone(),
+                node: RwLock::new(ShardNode::Leaf(top)),
+                stats: self.stats.clone(),
+                threadpool: self.threadpool.clone(),
+            }),
+        );
+        Ok(())
+    }
+
+    // called by the maintenance threads, see Config::background_split_threshold
+    pub(crate) fn background_split(&self) -> Result<()> {
+        let mut guard = self.node.write();
+        let ShardNode::Leaf(sh) = &*guard else {
+            return Ok(());
+        };
+        // splitting waits for the shard's compaction, which may be queued behind us in the maintenance
+        // threads. compactions only begin under the node's read lock, so none can begin while we hold it
+        if sh.is_compacting() {
+            sh.reset_split_scheduled();
+            return Ok(());
+        }
+        self.split_leaf(&mut guard)
+    }
+
+    pub(crate) fn insert(
+        self: &Arc<Self>,
         ph: PartedHash,
         full_key: &[u8],
         val: &[u8],
         mode: InsertMode,
     ) -> Result<InsertStatus> {
         loop {
-            let res = match &*self.node.read() {
-                ShardNode::Leaf(sh) => sh.insert(ph, full_key, val, mode)?,
+            let res = match &*self.read_node()? {
+                ShardNode::Unopened(_) => unreachable!(),
+                ShardNode::Leaf(sh) => {
+                    let res = sh.insert(ph, full_key, val, mode)?;
+                    sh.check_soft_limit();
+                    if sh.should_split_in_background() {
+                        self.threadpool.submit_split(Arc::downgrade(self))?;
+                    }
+                    res
+                }
                 ShardNode::Vertex(bottom, top) => {
                     if ph.shard_selector() < bottom.span.end {
                         bottom.insert(ph, full_key, val, mode)?
@@ -359,31 +508,7 @@ impl ShardRouter {
 
             match res {
                 InsertStatus::SplitNeeded => {
-                    let mut guard = self.node.write();
-                    let ShardNode::Leaf(sh) = &*guard else {
-                        // already split
-                        continue;
-                    };
-
-                    let (bottom, top) = sh.split()?;
-
-                    *guard = ShardNode::Vertex(
-                        Arc::new(ShardRouter {
-                            span: bottom.span.clone(),
-                            config: self.config.clone(),
-                            node: RwLock::new(ShardNode::Leaf(bottom)),
-                            stats: self.stats.clone(),
-                            threadpool: self.threadpool.clone(),
-                        }),
-                        Arc::new(ShardRouter {
-                            span: top.span.clone(),
-                            config: self.config.clone(),
-                            node: RwLock::new(ShardNode::Leaf(top)),
-                            stats: self.stats.clone(),
-                            threadpool: self.threadpool.clone(),
-                        }),
-                    );
-
+                    self.split_leaf(&mut self.node.write())?;
                     // retry
                 }
                 _ => {
@@ -453,11 +578,17 @@ impl ShardRouter {
                     (None, None) => Ok(None),
                 }
             }
+            // only opened shards are merged
+            (ShardNode::Unopened(_), _) | (_, ShardNode::Unopened(_)) => Ok(None),
         }
     }
 
     pub(crate) fn merge_small_shards(&self, max_fill_level: f32) -> Result<bool> {
-        ensure!(max_fill_level > 0.0 && max_fill_level < 0.5);
+        if max_fill_level <= 0.0 || max_fill_level >= 0.5 {
+            return Err(CandyError::InvalidArgument(format!(
+                "max_fill_level={max_fill_level} must be in the range (0, 0.5)"
+            )));
+        }
         let max_fill = (Shard::EXPECTED_CAPACITY as f32 * max_fill_level) as usize;
 
         let mut num_items = 0usize;
@@ -479,7 +610,7 @@ impl ShardRouter {
             let mut guard = self.node.write();
 
             match &*guard {
-                ShardNode::Leaf(_) => None,
+                ShardNode::Leaf(_) | ShardNode::Unopened(_) => None,
                 ShardNode::Vertex(bottom, top) => {
                     self._merge(&bottom, &top, max_fill, &mut shards_to_remove)?
                 }
@@ -487,6 +618,7 @@ impl ShardRouter {
 
             *guard = Self::treeify(
                 Self::load(&self.config, &self.stats, &self.threadpool)?,
+                &self.config,
                 &self.stats,
                 &self.threadpool,
             );
@@ -0,0 +1,329 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{CandyError, CandyStore, Result, MAX_VALUE_SIZE};
+
+// the longest line that is read from a client, which fits a percent-encoded list key and item key (HTTP) or an
+// inline command that sets a key (RESP)
+pub(crate) const MAX_LINE_LEN: usize = 128 * 1024;
+// the most arguments a command may have, and the most bytes they may add up to
+const MAX_ARGS: usize = 64 * 1024;
+const MAX_COMMAND_LEN: usize = 16 * 1024 * 1024;
+
+/// Controls [CandyStore::serve_resp]
+#[derive(Debug, Clone)]
+pub struct RespServerParams {
+    /// the number of connections that are served at once, past which new connections are refused (with an
+    /// error reply)
+    pub max_connections: usize,
+    /// the read and write timeout of connections: a connection that sends nothing (e.g., an idle client) or
+    /// does not read its replies for this long is closed. Must not be zero, `None` disables the timeouts
+    pub io_timeout: Option<Duration>,
+}
+
+impl Default for RespServerParams {
+    fn default() -> Self {
+        Self {
+            max_connections: 256,
+            io_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(usize),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Vec<u8>>),
+}
+
+impl Reply {
+    fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Self::Simple(s) => write!(w, "+{s}\r\n"),
+            Self::Error(e) => write!(w, "-{}\r\n", e.replace(['\r', '\n'], " ")),
+            Self::Integer(n) => write!(w, ":{n}\r\n"),
+            Self::Bulk(None) => write!(w, "$-1\r\n"),
+            Self::Bulk(Some(b)) => {
+                write!(w, "${}\r\n", b.len())?;
+                w.write_all(b)?;
+                w.write_all(b"\r\n")
+            }
+            Self::Array(items) => {
+                write!(w, "*{}\r\n", items.len())?;
+                for item in items {
+                    write!(w, "${}\r\n", item.len())?;
+                    w.write_all(item)?;
+                    w.write_all(b"\r\n")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// reads a line (without its line break) of up to MAX_LINE_LEN bytes. returns None when the connection is closed
+// before the line begins
+pub(crate) fn read_line(r: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    if r.by_ref()
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)?
+        == 0
+    {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') && line.len() > MAX_LINE_LEN {
+        return Err(protocol_error("line too long"));
+    }
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn protocol_error(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+// releases a connection's slot when the connection's thread is done
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// accepts connections and handles each on its own thread, with the given read and write timeouts. connections
+// past `max_connections` are passed to `refuse` instead (on the accepting thread), and closed. only returns if
+// accepting connections fails
+pub(crate) fn serve_connections(
+    listener: TcpListener,
+    max_connections: usize,
+    io_timeout: Option<Duration>,
+    refuse: impl Fn(TcpStream),
+    handle: impl Fn(TcpStream) + Clone + Send + 'static,
+) -> Result<()> {
+    if io_timeout.is_some_and(|t| t.is_zero()) {
+        return Err(CandyError::InvalidArgument(
+            "io_timeout must not be zero".into(),
+        ));
+    }
+    let num_connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        // a connection without timeouts could hold its thread forever
+        if stream.set_read_timeout(io_timeout).is_err()
+            || stream.set_write_timeout(io_timeout).is_err()
+        {
+            continue;
+        }
+        if num_connections.fetch_add(1, Ordering::SeqCst) >= max_connections {
+            num_connections.fetch_sub(1, Ordering::SeqCst);
+            refuse(stream);
+            continue;
+        }
+        let slot = ConnectionSlot(num_connections.clone());
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            handle(stream);
+        });
+    }
+    Ok(())
+}
+
+fn parse_len(line: &[u8]) -> std::io::Result<i64> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+// reads a command, either as an array of bulk strings (what clients send) or as an inline command (what
+// telnet sends). returns None when the connection is closed
+fn read_command(r: &mut impl BufRead) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(r)? else {
+        return Ok(None);
+    };
+    let Some(count) = line.strip_prefix(b"*") else {
+        return Ok(Some(
+            line.split(|b| b.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| arg.to_vec())
+                .collect(),
+        ));
+    };
+
+    let count = parse_len(count)?;
+    if count > MAX_ARGS as i64 {
+        return Err(protocol_error("too many arguments"));
+    }
+    // the lengths come from the client, so nothing is allocated before the data arrives
+    let mut args = vec![];
+    let mut total_len = 0;
+    for _ in 0..count {
+        let line = read_line(r)?.ok_or_else(|| protocol_error("unexpected end of stream"))?;
+        let len = line
+            .strip_prefix(b"$")
+            .ok_or_else(|| protocol_error("expected a bulk string"))?;
+        let len =
+            usize::try_from(parse_len(len)?).map_err(|_| protocol_error("negative length"))?;
+        // no key or value is longer than this anyway
+        if len > MAX_VALUE_SIZE {
+            return Err(protocol_error("bulk string too long"));
+        }
+        total_len += len;
+        if total_len > MAX_COMMAND_LEN {
+            return Err(protocol_error("command too long"));
+        }
+        let mut arg = vec![];
+        r.by_ref().take(len as u64 + 2).read_to_end(&mut arg)?;
+        if arg.len() != len + 2 {
+            return Err(protocol_error("unexpected end of stream"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+fn wrong_args(cmd: &str) -> Reply {
+    Reply::Error(format!("ERR wrong number of arguments for '{cmd}' command"))
+}
+
+fn execute(store: &CandyStore, args: &[Vec<u8>]) -> Result<Reply> {
+    let cmd = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let args = &args[1..];
+    let reply = match (cmd.as_str(), args) {
+        ("ping", []) => Reply::Simple("PONG"),
+        ("ping", [msg]) => Reply::Bulk(Some(msg.clone())),
+        ("echo", [msg]) => Reply::Bulk(Some(msg.clone())),
+        // clients (e.g. redis-cli) query the supported commands when connecting
+        ("command", _) => Reply::Array(vec![]),
+        ("get", [k]) => Reply::Bulk(store.get(k)?),
+        ("set", [k, v]) => {
+            store.set(k, v)?;
+            Reply::Simple("OK")
+        }
+        ("del", keys) if !keys.is_empty() => {
+            let mut count = 0;
+            for k in keys {
+                if store.remove(k)?.is_some() {
+                    count += 1;
+                }
+            }
+            Reply::Integer(count)
+        }
+        ("lpush" | "rpush", [k, vals @ ..]) if !vals.is_empty() => {
+            for v in vals {
+                if cmd == "lpush" {
+                    store.push_to_queue_head(k, v)?;
+                } else {
+                    store.push_to_queue_tail(k, v)?;
+                }
+            }
+            Reply::Integer(store.queue_len(k)?)
+        }
+        ("lpop", [k]) => Reply::Bulk(store.pop_queue_head(k)?),
+        ("rpop", [k]) => Reply::Bulk(store.pop_queue_tail(k)?),
+        ("llen", [k]) => Reply::Integer(store.queue_len(k)?),
+        ("hset", [k, fields @ ..]) if !fields.is_empty() && fields.len().is_multiple_of(2) => {
+            let mut created = 0;
+            for pair in fields.chunks(2) {
+                if !store.set_in_list(k, &pair[0], &pair[1])?.was_replaced() {
+                    created += 1;
+                }
+            }
+            Reply::Integer(created)
+        }
+        ("hget", [k, f]) => Reply::Bulk(store.get_from_list(k, f)?),
+        ("hdel", [k, fields @ ..]) if !fields.is_empty() => {
+            let mut count = 0;
+            for f in fields {
+                if store.remove_from_list(k, f)?.is_some() {
+                    count += 1;
+                }
+            }
+            Reply::Integer(count)
+        }
+        ("hlen", [k]) => Reply::Integer(store.list_len(k)?),
+        (
+            "ping" | "echo" | "get" | "set" | "del" | "lpush" | "rpush" | "lpop" | "rpop" | "llen"
+            | "hset" | "hget" | "hdel" | "hlen",
+            _,
+        ) => wrong_args(&cmd),
+        _ => Reply::Error(format!("ERR unknown command '{cmd}'")),
+    };
+    Ok(reply)
+}
+
+fn handle_connection(store: &CandyStore, stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                Reply::Error(format!("ERR Protocol error: {e}")).write_to(&mut writer)?;
+                return writer.flush();
+            }
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+        if args[0].eq_ignore_ascii_case(b"quit") {
+            Reply::Simple("OK").write_to(&mut writer)?;
+            writer.flush()?;
+            break;
+        }
+        let reply = execute(store, &args).unwrap_or_else(|e| Reply::Error(format!("ERR {e}")));
+        reply.write_to(&mut writer)?;
+        // don't flush while pipelined commands are pending
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+impl CandyStore {
+    /// Serves the store over the Redis protocol (RESP) on the given listener, so that Redis clients can be
+    /// used for simple workloads. Each connection is handled by its own thread. The supported commands are
+    /// `PING`, `ECHO`, `GET`, `SET` (without options), `DEL`, `LPUSH`/`RPUSH`/`LPOP`/`RPOP`/`LLEN` (mapped to
+    /// queues), `HSET`/`HGET`/`HDEL`/`HLEN` (mapped to lists) and `QUIT`. Keys of different types do not
+    /// collide, e.g., `GET` on a hash returns nil rather than an error.
+    ///
+    /// Lines, commands and their arguments are bounded in length (arguments by [crate::MAX_VALUE_SIZE]), and
+    /// the number of connections and their timeouts are set by [RespServerParams]. Clients that break these
+    /// limits get an error reply and are disconnected.
+    ///
+    /// This function only returns if accepting connections fails
+    pub fn serve_resp(&self, listener: TcpListener, params: RespServerParams) -> Result<()> {
+        let store = self.clone();
+        serve_connections(
+            listener,
+            params.max_connections,
+            params.io_timeout,
+            |mut stream| {
+                _ = Reply::Error("ERR max number of clients reached".into()).write_to(&mut stream);
+            },
+            move |stream| {
+                // errors are reported to the client where possible, and otherwise close the connection
+                _ = handle_connection(&store, stream);
+            },
+        )
+    }
+}
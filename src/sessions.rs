@@ -0,0 +1,145 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytemuck::{bytes_of, from_bytes, Pod, Zeroable};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::{store::SESSIONS_NAMESPACE, CandyStore, ReplaceStatus, Result};
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct Session {
+    expires_at_ms: u64,
+    ttl_ms: u64,
+}
+
+impl Session {
+    fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at_ms <= now_ms
+    }
+}
+
+// the channels of [CandyStore::subscribe_expirations]
+#[derive(Default)]
+pub(crate) struct ExpirationSubscribers(Mutex<Vec<Sender<Vec<u8>>>>);
+
+impl ExpirationSubscribers {
+    pub(crate) fn notify(&self, ids: &[Vec<u8>]) {
+        let mut subscribers = self.0.lock();
+        // drop the subscribers whose receivers are gone
+        subscribers.retain(|tx| ids.iter().all(|id| tx.send(id.clone()).is_ok()));
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl CandyStore {
+    fn sessions_list_key() -> Vec<u8> {
+        SESSIONS_NAMESPACE.to_owned()
+    }
+
+    /// Registers (or re-registers) a session with the given ID and time-to-live. The session is considered live
+    /// until `ttl` elapses without a [Self::heartbeat]. Sessions are persistent, and expiration is based on the
+    /// wall-clock, so they survive restarts of the process.
+    ///
+    /// Expired sessions are garbage-collected lazily, by [Self::list_live_sessions]
+    pub fn register_session<B: AsRef<[u8]> + ?Sized>(&self, id: &B, ttl: Duration) -> Result<()> {
+        let ttl_ms = ttl.as_millis() as u64;
+        let session = Session {
+            expires_at_ms: now_ms() + ttl_ms,
+            ttl_ms,
+        };
+        self.owned_set_in_list(
+            Self::sessions_list_key(),
+            id.as_ref().to_owned(),
+            bytes_of(&session).to_owned(),
+            false,
+        )?;
+        Ok(())
+    }
+
+    /// Extends the given session by its time-to-live. Returns false if the session does not exist or has
+    /// already expired (in which case it must be registered again)
+    pub fn heartbeat<B: AsRef<[u8]> + ?Sized>(&self, id: &B) -> Result<bool> {
+        let id = id.as_ref();
+        let Some(session_bytes) =
+            self.owned_get_from_list(Self::sessions_list_key(), id.to_owned())?
+        else {
+            return Ok(false);
+        };
+        let mut session = *from_bytes::<Session>(&session_bytes);
+        let now = now_ms();
+        if session.is_expired(now) {
+            return Ok(false);
+        }
+        session.expires_at_ms = now + session.ttl_ms;
+
+        match self.owned_replace_in_list(
+            Self::sessions_list_key(),
+            id.to_owned(),
+            bytes_of(&session).to_owned(),
+            Some(&session_bytes),
+        )? {
+            ReplaceStatus::PrevValue(_) => Ok(true),
+            // someone else has heartbeated or re-registered the session concurrently
+            ReplaceStatus::WrongValue(_) => Ok(true),
+            ReplaceStatus::DoesNotExist => Ok(false),
+        }
+    }
+
+    /// Removes the given session, returning true if it had existed (even if expired), false otherwise
+    pub fn unregister_session<B: AsRef<[u8]> + ?Sized>(&self, id: &B) -> Result<bool> {
+        Ok(self
+            .owned_remove_from_list(Self::sessions_list_key(), id.as_ref().to_owned())?
+            .is_some())
+    }
+
+    /// Returns a channel that receives the IDs of expired sessions as they are removed from the store (see
+    /// [Self::list_live_sessions]), as well as the keys whose TTL has passed as they are removed by
+    /// [Self::purge_expired], so that dependent caches can react to expiry instead of polling. Each subscriber
+    /// receives every expired ID once; dropping the receiver unsubscribes. The receiver is a
+    /// [crossbeam_channel::Receiver], so it can be shared between threads and waited on with `select!`
+    pub fn subscribe_expirations(&self) -> Receiver<Vec<u8>> {
+        let (tx, rx) = unbounded();
+        self.0.expirations.0.lock().push(tx);
+        rx
+    }
+
+    /// Returns the IDs of all live (non-expired) sessions, in registration order. Expired sessions, if any are
+    /// found, are removed from the store, and reported to [Self::subscribe_expirations]
+    pub fn list_live_sessions(&self) -> Result<Vec<Vec<u8>>> {
+        let now = now_ms();
+        let mut live = vec![];
+        let mut found_expired = false;
+
+        for res in self.owned_iter_list(Self::sessions_list_key()) {
+            let (id, session_bytes) = res?;
+            if from_bytes::<Session>(&session_bytes).is_expired(now) {
+                found_expired = true;
+            } else {
+                live.push(id);
+            }
+        }
+
+        if found_expired {
+            // retain works under the list's lock, so sessions that were heartbeated in the meantime are kept
+            let mut expired = vec![];
+            self.owned_retain_in_list(Self::sessions_list_key(), |id, session_bytes| {
+                if from_bytes::<Session>(session_bytes).is_expired(now) {
+                    expired.push(id.to_owned());
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            })?;
+            self.0.expirations.notify(&expired);
+        }
+
+        Ok(live)
+    }
+}
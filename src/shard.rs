@@ -1,15 +1,18 @@
-use anyhow::bail;
 use bytemuck::{bytes_of_mut, Pod, Zeroable};
-use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::{
     fs::{File, OpenOptions},
+    hash::Hasher,
     io::Read,
     ops::Range,
-    os::{fd::AsRawFd, unix::fs::FileExt},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{FileExt, OpenOptionsExt},
+    },
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicU64, AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
     },
     thread::JoinHandle,
     time::Instant,
@@ -17,12 +20,16 @@ use std::{
 
 use memmap::{MmapMut, MmapOptions};
 
-use crate::Result;
 use crate::{
-    hashing::{PartedHash, INVALID_SIG},
-    stats::InternalStats,
+    collisions::{CollisionKind, CollisionRecord},
+    hashing::{value_checksum, value_checksum_hasher, PartedHash, INVALID_SIG},
+    key_prefixes::KeyPrefixes,
+    router::ShardRouter,
+    stats::{InternalStats, WriteKind},
     store::InternalConfig,
+    SoftLimitWarning, MAX_KEY_SIZE,
 };
+use crate::{CandyError, ReplaceStatus, Result};
 
 //
 // these numbers were chosen according to the simulation, as they allow for 90% utilization of the shard with
@@ -32,6 +39,11 @@ use crate::{
 //
 // other good combinations are 32/512, 32/1024, 64/256, 64/1024, 128/512, 256/256
 //
+// note that values are never stored inline in the rows, however small they are: the key and value are written
+// next to each other, so a lookup costs a single pread regardless of the value size, and the key must be read
+// anyway to rule out signature collisions. inlining would only pay off if both the key and the value fit in the
+// row, which would require growing `offsets_and_sizes` (and the mmap'ed header) for every entry
+//
 pub(crate) const NUM_ROWS: usize = 64;
 pub(crate) const ROW_WIDTH: usize = 512;
 
@@ -101,7 +113,7 @@ fn test_row_lookup() -> Result<()> {
 struct PageAligned<T>(T);
 
 pub(crate) const SHARD_FILE_MAGIC: [u8; 8] = *b"CandyStr";
-pub(crate) const SHARD_FILE_VERSION: u64 = 11;
+pub(crate) const SHARD_FILE_VERSION: u64 = 12;
 
 #[derive(Clone, Copy, Default, Debug, Pod, Zeroable)]
 #[repr(C)]
@@ -118,6 +130,10 @@ struct ShardHeader {
     num_inserts: AtomicU64,
     num_removals: AtomicU64,
     compacted_up_to: AtomicUsize,
+    // fingerprint of the key prefixes this shard was created with, or 0 if keys are stored uncompressed
+    key_prefixes_fingerprint: AtomicU64,
+    // the settings that change the format of the entries this shard was written with, see layout_flags
+    layout_flags: AtomicU64,
     rows: PageAligned<[ShardRow; NUM_ROWS]>,
 }
 
@@ -140,6 +156,13 @@ pub(crate) enum InsertMode<'a> {
     GetOrCreate,
 }
 
+#[derive(Debug)]
+pub(crate) enum PatchStatus {
+    Patched(Vec<u8>),
+    WrongValue(Vec<u8>),
+    KeyDoesNotExist,
+}
+
 enum TryReplaceStatus<'a> {
     KeyDoesNotExist(RwLockWriteGuard<'a, ()>, bool),
     KeyExistsNotReplaced(Vec<u8>),
@@ -148,17 +171,230 @@ enum TryReplaceStatus<'a> {
 
 pub(crate) type KVPair = (Vec<u8>, Vec<u8>);
 
+// the two top bits of the key length are never used by keys (see MAX_TOTAL_KEY_SIZE), so we use them to flag
+// entries whose value is compressed with the store's dictionary
+const KLEN_MASK: u64 = 0x3fff;
+const COMPRESSED_VAL_FLAG: u64 = 1 << 62;
+
+#[inline]
+fn is_compressed(offset_and_size: u64) -> bool {
+    offset_and_size & COMPRESSED_VAL_FLAG != 0
+}
+
+// the number of bytes the entry takes up in the file
+#[inline]
+fn stored_entry_size(offset_and_size: u64) -> u64 {
+    ((offset_and_size >> 48) & KLEN_MASK) + ((offset_and_size >> 32) & 0xffff)
+}
+
+// the settings that must not change once a store has data, as they change how entries are encoded (or where
+// they are kept), packed into a u64. the default settings are 0, as in the shards of older stores
+const LAYOUT_STRICT_TYPED_VALUES: u64 = 1 << 0;
+const LAYOUT_LIST_ITEM_METADATA: u64 = 1 << 1;
+const LAYOUT_LIST_ITEM_TAGS: u64 = 1 << 2;
+const LAYOUT_INTERNING: u64 = 1 << 3;
+const LAYOUT_INTERNING_SHIFT: u64 = 16;
+
+fn layout_flags(config: &InternalConfig) -> u64 {
+    let mut flags = 0;
+    if config.strict_typed_values {
+        flags |= LAYOUT_STRICT_TYPED_VALUES;
+    }
+    if config.list_item_metadata {
+        flags |= LAYOUT_LIST_ITEM_METADATA;
+    }
+    if config.list_item_tags {
+        flags |= LAYOUT_LIST_ITEM_TAGS;
+    }
+    // no key is longer than MAX_KEY_SIZE, so longer thresholds intern nothing
+    if let Some(len) = config
+        .intern_keys_longer_than
+        .filter(|&len| len < MAX_KEY_SIZE)
+    {
+        flags |= LAYOUT_INTERNING | ((len as u64) << LAYOUT_INTERNING_SHIFT);
+    }
+    flags
+}
+
+// the validation applied to existing shard files when they are opened: `header` is (a prefix of) the file's
+// beginning, which must start with a valid meta header
+pub(crate) fn check_shard_file_header(
+    filename: &Path,
+    header: &[u8],
+    file_size: u64,
+) -> Result<()> {
+    let mut meta_header = MetaHeader::default();
+    let sz = header.len().min(size_of::<MetaHeader>());
+    bytes_of_mut(&mut meta_header)[..sz].copy_from_slice(&header[..sz]);
+    if sz != size_of::<MetaHeader>() || meta_header.magic != SHARD_FILE_MAGIC {
+        return Err(CandyError::Corruption(format!(
+            "{filename:?} bad magic={:?} size={}",
+            meta_header.magic, file_size,
+        )));
+    }
+    if meta_header.version != SHARD_FILE_VERSION {
+        return Err(CandyError::WrongVersion(
+            filename.to_owned(),
+            meta_header.version,
+        ));
+    }
+    if file_size < HEADER_SIZE {
+        return Err(CandyError::Corruption(format!(
+            "{filename:?} corrupt shard file (size={file_size})"
+        )));
+    }
+    Ok(())
+}
+
+/// The decoded counters of a shard file's header
+#[cfg(feature = "fuzzing")]
+pub(crate) struct ShardHeaderCounters {
+    pub(crate) wasted_bytes: u64,
+    pub(crate) write_offset: u64,
+    pub(crate) num_inserts: u64,
+    pub(crate) num_removals: u64,
+    pub(crate) compacted_up_to: u64,
+    pub(crate) key_prefixes_fingerprint: u64,
+}
+
+// parses the beginning of a shard file (at least up to the end of the counters), validating it like `Shard::open` does,
+// as well as the consistency of the counters
+#[cfg(feature = "fuzzing")]
+pub(crate) fn parse_shard_header(bytes: &[u8], file_size: u64) -> Result<ShardHeaderCounters> {
+    check_shard_file_header(Path::new("<fuzz>"), bytes, file_size)?;
+    let counters_end = std::mem::offset_of!(ShardHeader, key_prefixes_fingerprint) + 8;
+    if bytes.len() < counters_end {
+        return Err(CandyError::Corruption("truncated shard header".into()));
+    }
+    let read_u64 =
+        |offset: usize| u64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    let counters = ShardHeaderCounters {
+        wasted_bytes: read_u64(std::mem::offset_of!(ShardHeader, wasted_bytes)),
+        write_offset: read_u64(std::mem::offset_of!(ShardHeader, write_offset)),
+        num_inserts: read_u64(std::mem::offset_of!(ShardHeader, num_inserts)),
+        num_removals: read_u64(std::mem::offset_of!(ShardHeader, num_removals)),
+        compacted_up_to: read_u64(std::mem::offset_of!(ShardHeader, compacted_up_to)),
+        key_prefixes_fingerprint: read_u64(std::mem::offset_of!(
+            ShardHeader,
+            key_prefixes_fingerprint
+        )),
+    };
+    if counters.write_offset > file_size - HEADER_SIZE
+        || counters.wasted_bytes > counters.write_offset
+        || counters.num_removals > counters.num_inserts
+    {
+        return Err(CandyError::Corruption(format!(
+            "inconsistent shard header (write_offset={} wasted_bytes={} inserts={} removals={})",
+            counters.write_offset,
+            counters.wasted_bytes,
+            counters.num_inserts,
+            counters.num_removals
+        )));
+    }
+    Ok(counters)
+}
+
+/// A decoded row entry (see [ShardRow::offsets_and_sizes])
+#[cfg(feature = "fuzzing")]
+pub(crate) struct RowEntry {
+    pub(crate) signature: u32,
+    pub(crate) klen: usize,
+    pub(crate) vlen: usize,
+    pub(crate) offset: u64,
+    pub(crate) compressed: bool,
+}
+
+// decodes the entries of a row, as laid out in the shard file's header, validating them against the length of
+// the file's data section
+#[cfg(feature = "fuzzing")]
+pub(crate) fn parse_shard_row(bytes: &[u8], data_len: u64) -> Result<Vec<RowEntry>> {
+    if bytes.len() != size_of::<ShardRow>() {
+        return Err(CandyError::Corruption(format!(
+            "row has wrong size {}",
+            bytes.len()
+        )));
+    }
+    let (sigs, offsets) = bytes.split_at(ROW_WIDTH * size_of::<u32>());
+    let mut entries = vec![];
+    for (sig, offset_and_size) in sigs.chunks_exact(4).zip(offsets.chunks_exact(8)) {
+        let sig = u32::from_ne_bytes(sig.try_into().unwrap());
+        if sig == INVALID_SIG {
+            continue;
+        }
+        let offset_and_size = u64::from_ne_bytes(offset_and_size.try_into().unwrap());
+        let entry = RowEntry {
+            signature: sig,
+            klen: ((offset_and_size >> 48) & KLEN_MASK) as usize,
+            vlen: ((offset_and_size >> 32) & 0xffff) as usize,
+            offset: (offset_and_size as u32) as u64,
+            compressed: is_compressed(offset_and_size),
+        };
+        if entry.klen == 0 || entry.offset + stored_entry_size(offset_and_size) > data_len {
+            return Err(CandyError::Corruption(format!(
+                "row entry out of bounds (offset={} klen={} vlen={} data_len={data_len})",
+                entry.offset, entry.klen, entry.vlen
+            )));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(feature = "whitebox_testing")]
+pub static WRITES_BEFORE_DISK_FULL: AtomicU64 = AtomicU64::new(u64::MAX); // how many entry writes succeed before all of them fail with ENOSPC (u64::MAX never fails) - for testing running out of disk space
+
+// fails the write with ENOSPC once the writes allowed by WRITES_BEFORE_DISK_FULL ran out
+fn check_disk_full() -> Result<()> {
+    #[cfg(feature = "whitebox_testing")]
+    if WRITES_BEFORE_DISK_FULL.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+        (n != 0 && n != u64::MAX).then(|| n - 1)
+    }) == Err(0)
+    {
+        return Err(std::io::Error::from_raw_os_error(libc::ENOSPC).into());
+    }
+    Ok(())
+}
+
+// the end of a patch of a value, which must lie within it. the offset comes from the caller, so it may be anything
+fn patch_end(offset: usize, len: usize, vlen: usize) -> Result<usize> {
+    match offset.checked_add(len) {
+        Some(end) if end <= vlen => Ok(end),
+        _ => Err(CandyError::PatchOutOfBounds(offset, len, vlen)),
+    }
+}
+
+// allocates the given range of the file's data section on disk, so writing to it cannot fail with ENOSPC
+fn allocate_data(file: &File, offset: u64, len: u64) -> Result<()> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let res = unsafe {
+            libc::posix_fallocate(file.as_raw_fd(), (HEADER_SIZE + offset) as i64, len as i64)
+        };
+        // posix_fallocate does not set errno, it returns the error
+        if res != 0 {
+            return Err(std::io::Error::from_raw_os_error(res).into());
+        }
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = (file, offset, len);
+    Ok(())
+}
+
 struct MmapFile {
     file: File,
     mmap: MmapMut,
+    key_prefixes: Option<Arc<KeyPrefixes>>,
+    config: Arc<InternalConfig>,
+    // the size of the prefix of the data section that is known to be allocated on disk
+    allocated: AtomicU64,
 }
 
 impl MmapFile {
-    fn new(file: File, mlock_headers: bool) -> Result<Self> {
+    fn new(file: File, config: &Arc<InternalConfig>) -> Result<Self> {
         let mmap = unsafe { MmapOptions::new().len(HEADER_SIZE as usize).map_mut(&file) }?;
 
         #[cfg(target_family = "unix")]
-        if mlock_headers {
+        if config.mlock_headers {
             unsafe { libc::mlock(mmap.as_ptr() as *const _, mmap.len()) };
         }
 
@@ -172,15 +408,74 @@ impl MmapFile {
         header.metadata.magic = SHARD_FILE_MAGIC;
         header.metadata.version = SHARD_FILE_VERSION;
 
-        Ok(Self { file, mmap })
+        // empty shards adopt the configured key prefixes, while shards that already contain data keep the
+        // format they were written with
+        let fingerprint = header.key_prefixes_fingerprint.load(Ordering::SeqCst);
+        let key_prefixes = if fingerprint == 0 {
+            if header.write_offset.load(Ordering::SeqCst) == 0 {
+                if let Some(ref kp) = config.key_prefixes {
+                    header
+                        .key_prefixes_fingerprint
+                        .store(kp.fingerprint(), Ordering::SeqCst);
+                }
+                config.key_prefixes.clone()
+            } else {
+                None
+            }
+        } else {
+            match config.key_prefixes {
+                Some(ref kp) if kp.fingerprint() == fingerprint => Some(kp.clone()),
+                _ => {
+                    return Err(CandyError::InvalidArgument(format!(
+                        "shard was created with different key prefixes (fingerprint={fingerprint:016x})"
+                    )))
+                }
+            }
+        };
+
+        // likewise for the layout of the entries, except that they cannot be told apart, so shards with data
+        // must have been written with the configured layout
+        let flags = layout_flags(config);
+        if header.write_offset.load(Ordering::SeqCst) == 0 {
+            header.layout_flags.store(flags, Ordering::SeqCst);
+        } else {
+            let shard_flags = header.layout_flags.load(Ordering::SeqCst);
+            if shard_flags != flags {
+                return Err(CandyError::InvalidArgument(format!(
+                    "shard was created with different settings of strict_typed_values, list_item_metadata, \
+                    list_item_tags or intern_keys_longer_than (layout={shard_flags:x}, configured={flags:x})"
+                )));
+            }
+        }
+
+        let allocated = AtomicU64::new(header.write_offset.load(Ordering::SeqCst));
+        Ok(Self {
+            file,
+            mmap,
+            key_prefixes,
+            config: config.clone(),
+            allocated,
+        })
+    }
+
+    // makes sure the first `len` bytes of the data section are allocated on disk
+    fn preallocate(&self, len: u64) -> Result<()> {
+        let allocated = self.allocated.load(Ordering::SeqCst);
+        if len <= allocated {
+            return Ok(());
+        }
+        allocate_data(&self.file, allocated, len - allocated)?;
+        self.allocated.fetch_max(len, Ordering::SeqCst);
+        Ok(())
     }
 
-    fn create(filename: impl AsRef<Path>, config: &InternalConfig) -> Result<Self> {
+    fn create(filename: impl AsRef<Path>, config: &Arc<InternalConfig>) -> Result<Self> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
             .open(filename)?;
         file.set_len(
             HEADER_SIZE
@@ -190,7 +485,14 @@ impl MmapFile {
                     0
                 },
         )?;
-        Self::new(file, config.mlock_headers)
+        let mmap_file = Self::new(file, config)?;
+        mmap_file.preallocate(
+            config
+                .preallocate_size
+                .get()
+                .min(config.max_shard_size as u64),
+        )?;
+        Ok(mmap_file)
     }
 
     #[inline(always)]
@@ -217,8 +519,7 @@ impl MmapFile {
         offset_and_size: u64,
         include_val: bool,
     ) -> Result<KVPair> {
-        let klen = (offset_and_size >> 48) as usize;
-        debug_assert_eq!(klen >> 14, 0, "attempting to read a special key");
+        let klen = ((offset_and_size >> 48) & KLEN_MASK) as usize;
         let vlen = if include_val {
             ((offset_and_size >> 32) & 0xffff) as usize
         } else {
@@ -231,13 +532,26 @@ impl MmapFile {
         stats.num_read_bytes.fetch_add(buf.len(), Ordering::Relaxed);
         stats.num_read_ops.fetch_add(1, Ordering::Relaxed);
 
-        if include_val {
-            let val = buf[klen..klen + vlen].to_owned();
-            buf.truncate(klen);
-
-            Ok((buf, val))
+        let val = if !include_val {
+            vec![]
+        } else if is_compressed(offset_and_size) {
+            let Some(dict) = self.config.compression_dict.get() else {
+                return Err(CandyError::Corruption(
+                    "value is compressed, but the store has no compression dictionary".into(),
+                ));
+            };
+            let t0 = Instant::now();
+            let val = dict.decompress(&buf[klen..klen + vlen])?;
+            stats.compression.report_decompression(t0.elapsed());
+            val
         } else {
-            Ok((buf, vec![]))
+            buf[klen..klen + vlen].to_owned()
+        };
+        buf.truncate(klen);
+
+        match self.key_prefixes {
+            Some(ref kp) => Ok((kp.decompress(&buf)?, val)),
+            None => Ok((buf, val)),
         }
     }
 
@@ -245,8 +559,133 @@ impl MmapFile {
         self._read_kv(stats, offset_and_size, true)
     }
 
+    // digests the value (see hashing::value_checksum) in chunks, without reading all of it into memory
+    fn val_checksum(&self, stats: &InternalStats, offset_and_size: u64) -> Result<u64> {
+        if is_compressed(offset_and_size) {
+            let (_, val) = self.read_kv(stats, offset_and_size)?;
+            return Ok(value_checksum(&val));
+        }
+
+        let klen = ((offset_and_size >> 48) & KLEN_MASK) as usize;
+        let vlen = ((offset_and_size >> 32) & 0xffff) as usize;
+        let offset = HEADER_SIZE + (offset_and_size as u32) as u64 + klen as u64;
+
+        let mut hasher = value_checksum_hasher();
+        let mut chunk = [0u8; 4096];
+        let mut pos = 0;
+        while pos < vlen {
+            let n = chunk.len().min(vlen - pos);
+            self.file
+                .read_exact_at(&mut chunk[..n], offset + pos as u64)?;
+            stats.num_read_bytes.fetch_add(n, Ordering::Relaxed);
+            stats.num_read_ops.fetch_add(1, Ordering::Relaxed);
+            hasher.write(&chunk[..n]);
+            pos += n;
+        }
+        Ok(hasher.finish())
+    }
+
+    // reads only the requested slice of the value, clamped to the value's actual length
+    fn read_val_range(
+        &self,
+        stats: &InternalStats,
+        offset_and_size: u64,
+        range: Range<usize>,
+    ) -> Result<Vec<u8>> {
+        if is_compressed(offset_and_size) {
+            let (_, val) = self.read_kv(stats, offset_and_size)?;
+            let end = range.end.min(val.len());
+            let start = range.start.min(end);
+            return Ok(val[start..end].to_owned());
+        }
+
+        let klen = ((offset_and_size >> 48) & KLEN_MASK) as usize;
+        let vlen = ((offset_and_size >> 32) & 0xffff) as usize;
+        let offset = (offset_and_size as u32) as u64;
+        let end = range.end.min(vlen);
+        let start = range.start.min(end);
+
+        let mut buf = vec![0u8; end - start];
+        if !buf.is_empty() {
+            self.file
+                .read_exact_at(&mut buf, HEADER_SIZE + offset + (klen + start) as u64)?;
+            stats.num_read_bytes.fetch_add(buf.len(), Ordering::Relaxed);
+            stats.num_read_ops.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(buf)
+    }
+
+    // overwrites part of an existing value. unlike everything else, this writes over existing data, so the
+    // caller must hold the row's write lock
+    fn write_val_at(
+        &self,
+        stats: &InternalStats,
+        offset_and_size: u64,
+        val_offset: usize,
+        buf: &[u8],
+    ) -> Result<()> {
+        debug_assert!(!is_compressed(offset_and_size));
+        let klen = ((offset_and_size >> 48) & KLEN_MASK) as usize;
+        let offset = (offset_and_size as u32) as u64;
+        check_disk_full()?;
+        self.file
+            .write_all_at(buf, HEADER_SIZE + offset + (klen + val_offset) as u64)?;
+        stats
+            .num_write_bytes
+            .fetch_add(buf.len(), Ordering::Relaxed);
+        stats.num_write_ops.fetch_add(1, Ordering::Relaxed);
+        stats.add_physical_write(buf.len(), WriteKind::Entry);
+        Ok(())
+    }
+
     // writing doesn't require holding any locks since we write with an offset
-    fn write_kv(&self, stats: &InternalStats, key: &[u8], val: &[u8]) -> Result<u64> {
+    fn write_kv(
+        &self,
+        stats: &InternalStats,
+        key: &[u8],
+        val: &[u8],
+        kind: WriteKind,
+    ) -> Result<u64> {
+        let compress_val = self
+            .config
+            .uncompressed_namespaces
+            .iter()
+            .all(|ns| key.last() != Some(&ns.suffix()));
+        let compressed_key;
+        let key = match self.key_prefixes {
+            Some(ref kp) => {
+                compressed_key = kp.compress(key);
+                &compressed_key[..]
+            }
+            None => key,
+        };
+        let mut flags = 0;
+        let compressed_val;
+        let val = match self.config.compression_dict.get() {
+            Some(_) if !compress_val => {
+                stats.compression.report_skipped();
+                val
+            }
+            Some(dict) => {
+                let t0 = Instant::now();
+                let res = dict.compress(val)?;
+                stats.compression.report_compression(
+                    val.len(),
+                    res.as_ref().map(|c| c.len()),
+                    t0.elapsed(),
+                );
+                match res {
+                    Some(compressed) => {
+                        compressed_val = compressed;
+                        flags |= COMPRESSED_VAL_FLAG;
+                        &compressed_val[..]
+                    }
+                    None => val,
+                }
+            }
+            None => val,
+        };
+        check_disk_full()?;
         let entry_size = key.len() + val.len();
         let mut buf = vec![0u8; entry_size];
         buf[..key.len()].copy_from_slice(key);
@@ -259,11 +698,25 @@ impl MmapFile {
             .write_offset
             .fetch_add(buf.len() as u64, Ordering::SeqCst) as u64;
 
+        // allocate ahead of the writes, so running out of disk space fails here rather than midway through
+        // writing the entry
+        let inc = self.config.preallocate_increment.get();
+        if inc > 0 {
+            let end = write_offset + buf.len() as u64;
+            if end > self.allocated.load(Ordering::Relaxed) {
+                self.preallocate(
+                    (end.div_ceil(inc) * inc)
+                        .min(self.config.max_shard_size as u64)
+                        .max(end),
+                )?;
+            }
+        }
+
         // now writing can be non-atomic (pwrite)
         self.file.write_all_at(&buf, HEADER_SIZE + write_offset)?;
-        stats.add_entry(entry_size);
+        stats.add_entry(entry_size, kind);
 
-        Ok(((key.len() as u64) << 48) | ((val.len() as u64) << 32) | write_offset)
+        Ok(((key.len() as u64) << 48) | ((val.len() as u64) << 32) | write_offset | flags)
     }
 }
 
@@ -280,6 +733,7 @@ impl TPHandle {
 }
 
 struct CompactionInfo {
+    span: Range<u32>,
     config: Arc<InternalConfig>,
     stats: Arc<InternalStats>,
     files: Arc<RwLock<(MmapFile, Option<MmapFile>)>>,
@@ -289,26 +743,58 @@ struct CompactionInfo {
     target_filename: PathBuf,
 }
 
+enum MaintenanceJob {
+    Compaction(CompactionInfo, crossbeam_channel::Sender<Result<()>>),
+    // splits are done by the router that holds the shard. it's held weakly, so a pending split does not keep
+    // the store alive
+    Split(Weak<ShardRouter>),
+}
+
+// lowers the priority of the calling thread. on linux, unless an IO priority was set explicitly, the thread's IO
+// priority is derived from its nice value as well
+#[cfg(target_os = "linux")]
+fn set_current_thread_nice(nice: i32) {
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, nice);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_nice(_nice: i32) {}
+
+/// The store's maintenance threads, which perform compactions and (see
+/// [crate::Config::background_split_threshold]) splits in the background
 pub(crate) struct CompactionThreadPool {
-    tx: crossbeam_channel::Sender<Option<(CompactionInfo, crossbeam_channel::Sender<Result<()>>)>>,
+    tx: crossbeam_channel::Sender<Option<MaintenanceJob>>,
     threads: Vec<JoinHandle<Result<()>>>,
 }
 
 impl CompactionThreadPool {
-    pub fn new(num_threads: usize) -> Self {
-        let (tx, rx) = crossbeam_channel::unbounded::<
-            Option<(CompactionInfo, crossbeam_channel::Sender<Result<()>>)>,
-        >();
+    pub fn new(num_threads: usize, nice: Option<i32>) -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded::<Option<MaintenanceJob>>();
         let mut threads = Vec::with_capacity(num_threads);
         for _ in 0..num_threads {
             let rx = rx.clone();
             let handle = std::thread::spawn(move || {
+                if let Some(nice) = nice {
+                    set_current_thread_nice(nice);
+                }
                 for elem in rx.iter() {
-                    let Some((info, handle_tx)) = elem else {
-                        break;
-                    };
-                    let res = Shard::background_compact(info);
-                    handle_tx.send(res)?;
+                    match elem {
+                        None => break,
+                        Some(MaintenanceJob::Compaction(info, handle_tx)) => {
+                            let res = Shard::background_compact(info);
+                            handle_tx.send(res)?;
+                        }
+                        Some(MaintenanceJob::Split(router)) => {
+                            if let Some(router) = router.upgrade() {
+                                // a failed split is not fatal: the shard is split inline once it fills up, and
+                                // the error surfaces there
+                                _ = router.background_split();
+                            }
+                        }
+                    }
                 }
                 Ok(())
             });
@@ -320,10 +806,15 @@ impl CompactionThreadPool {
 
     fn submit(&self, info: CompactionInfo) -> Result<TPHandle> {
         let (tx, rx) = crossbeam_channel::bounded(1);
-        self.tx.send(Some((info, tx)))?;
+        self.tx.send(Some(MaintenanceJob::Compaction(info, tx)))?;
         Ok(TPHandle { rx })
     }
 
+    pub(crate) fn submit_split(&self, router: Weak<ShardRouter>) -> Result<()> {
+        self.tx.send(Some(MaintenanceJob::Split(router)))?;
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn terminate(self) -> Result<()> {
         for _ in self.threads.iter() {
@@ -362,6 +853,10 @@ pub(crate) struct Shard {
     row_locks: Arc<[RwLock<()>; NUM_ROWS]>,
     threadpool: Arc<CompactionThreadPool>,
     compaction_handle: Arc<Mutex<Option<TPHandle>>>,
+    // set once a background split has been submitted for this shard
+    split_scheduled: AtomicBool,
+    // set while the live data is past the soft fill level, see SoftLimits::shard_fill_level
+    soft_limit_warned: AtomicBool,
     #[cfg(feature = "flush_aggregation")]
     sync_agg_mutex: parking_lot::Mutex<()>,
     #[cfg(feature = "flush_aggregation")]
@@ -381,40 +876,25 @@ impl Shard {
         let filename = config
             .dir_path
             .join(format!("shard_{:04x}-{:04x}", span.start, span.end));
+        // the store's files are never symlinks, and following one could write outside the store's directory
         let mut file = OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
             .truncate(truncate)
+            .custom_flags(libc::O_NOFOLLOW)
             .open(&filename)?;
 
         let mut file_size = file.metadata()?.len();
         if file_size != 0 {
-            let mut meta_header = MetaHeader::default();
-            let sz = file.read(bytes_of_mut(&mut meta_header))?;
-            if sz != size_of::<MetaHeader>()
-                || meta_header.magic != SHARD_FILE_MAGIC
-                || meta_header.version != SHARD_FILE_VERSION
-            {
+            let mut meta_header = [0u8; size_of::<MetaHeader>()];
+            let sz = file.read(&mut meta_header)?;
+            if let Err(e) = check_shard_file_header(&filename, &meta_header[..sz], file_size) {
                 if config.clear_on_unsupported_version {
                     file.set_len(0)?;
                     file_size = 0;
                 } else {
-                    bail!(
-                        "{filename:?} unsupported magic={:?} version=0x{:016x} size={}",
-                        meta_header.magic,
-                        meta_header.version,
-                        file_size,
-                    );
-                }
-            }
-
-            if file_size != 0 && file_size < HEADER_SIZE {
-                if config.clear_on_unsupported_version {
-                    file.set_len(0)?;
-                    file_size = 0;
-                } else {
-                    bail!("corrupt shard file (size={})", file_size);
+                    return Err(e);
                 }
             }
         }
@@ -435,7 +915,15 @@ impl Shard {
         }
         let row_locks: [RwLock<()>; NUM_ROWS] = row_locks.try_into().unwrap();
 
-        let mut mmap_file = MmapFile::new(file, config.mlock_headers)?;
+        let mut mmap_file = MmapFile::new(file, &config)?;
+        if file_size == 0 {
+            mmap_file.preallocate(
+                config
+                    .preallocate_size
+                    .get()
+                    .min(config.max_shard_size as u64),
+            )?;
+        }
 
         let compacted_filename = config
             .dir_path
@@ -446,9 +934,10 @@ impl Shard {
             if let Ok(compacted_file) = OpenOptions::new()
                 .read(true)
                 .write(true)
+                .custom_flags(libc::O_NOFOLLOW)
                 .open(&compacted_filename)
             {
-                let target = MmapFile::new(compacted_file, config.mlock_headers)?;
+                let target = MmapFile::new(compacted_file, &config)?;
                 Self::do_compaction(&row_locks, &mmap_file, &target, &stats, &config)?;
                 std::fs::rename(compacted_filename, filename)?;
                 mmap_file = target;
@@ -463,6 +952,8 @@ impl Shard {
             row_locks: Arc::new(row_locks),
             threadpool,
             compaction_handle: Arc::new(Mutex::new(None)),
+            split_scheduled: AtomicBool::new(false),
+            soft_limit_warned: AtomicBool::new(false),
             #[cfg(feature = "flush_aggregation")]
             sync_agg_mutex: parking_lot::Mutex::new(()),
             #[cfg(feature = "flush_aggregation")]
@@ -491,6 +982,8 @@ impl Shard {
             row_locks: Arc::new(row_locks),
             threadpool,
             compaction_handle: Arc::new(Mutex::new(None)),
+            split_scheduled: AtomicBool::new(false),
+            soft_limit_warned: AtomicBool::new(false),
             #[cfg(feature = "flush_aggregation")]
             sync_agg_mutex: parking_lot::Mutex::new(()),
             #[cfg(feature = "flush_aggregation")]
@@ -530,7 +1023,8 @@ impl Shard {
                 );
                 let ph = PartedHash::new(&config.hash_seed, &k);
                 assert_eq!(ph.row_selector(), row_idx);
-                target_row.offsets_and_sizes[target_col] = target.write_kv(&stats, &k, &v)?;
+                target_row.offsets_and_sizes[target_col] =
+                    target.write_kv(&stats, &k, &v, WriteKind::Compaction)?;
                 std::sync::atomic::fence(Ordering::SeqCst);
                 target_row.signatures[target_col] = ph.signature();
                 target.header().num_inserts.fetch_add(1, Ordering::Relaxed);
@@ -550,9 +1044,90 @@ impl Shard {
     pub(crate) fn flush(&self) -> Result<()> {
         //self.mmap.flush()? -- fdatasync should take care of that as well
         self.files.read().0.file.sync_data()?;
+        self.stats.report_fsync();
         Ok(())
     }
 
+    // allocates `bytes` more of the data section (past the current write offset) on disk, see
+    // CandyStore::reserve
+    pub(crate) fn reserve(&self, bytes: u64) -> Result<()> {
+        let files_guard = self.files.read();
+        let file = &files_guard.0;
+        let write_offset = file.header().write_offset.load(Ordering::SeqCst);
+        file.preallocate((write_offset + bytes).min(self.config.max_shard_size as u64))
+    }
+
+    // whether a compaction is running (or waiting for a thread)
+    pub(crate) fn is_compacting(&self) -> bool {
+        matches!(&*self.compaction_handle.lock(), Some(handle) if !handle.finished())
+    }
+
+    // returns true (once) if the live data in this shard crossed the background split threshold, in which case
+    // the caller should submit a background split
+    pub(crate) fn should_split_in_background(&self) -> bool {
+        let Some(threshold) = self.config.background_split_threshold.get() else {
+            return false;
+        };
+        if self.split_scheduled.load(Ordering::Relaxed) {
+            return false;
+        }
+        let files_guard = self.files.read();
+        if files_guard.1.is_some() {
+            // compacting
+            return false;
+        }
+        let header = files_guard.0.header();
+        let live_bytes = header.write_offset.load(Ordering::Relaxed)
+            - header.wasted_bytes.load(Ordering::Relaxed);
+        if (live_bytes as f64) < self.config.max_shard_size as f64 * threshold {
+            return false;
+        }
+        !self.split_scheduled.swap(true, Ordering::Relaxed)
+    }
+
+    // warns (once, until it drops below again) that the live data of this shard crossed the soft fill level
+    pub(crate) fn check_soft_limit(&self) {
+        let Some(level) = self.config.soft_limits.shard_fill_level else {
+            return;
+        };
+        if !self.stats.soft_limits.has_observer() {
+            return;
+        }
+        let live_bytes = {
+            let files_guard = self.files.read();
+            let header = files_guard.0.header();
+            header.write_offset.load(Ordering::Relaxed)
+                - header.wasted_bytes.load(Ordering::Relaxed)
+        };
+        let limit = (self.config.max_shard_size as f64 * level) as u64;
+        let over = live_bytes > limit;
+        if self.soft_limit_warned.swap(over, Ordering::Relaxed) != over && over {
+            self.stats
+                .soft_limits
+                .notify(SoftLimitWarning::ShardNearSplit {
+                    span: self.span.clone(),
+                    live_bytes,
+                    limit,
+                });
+        }
+    }
+
+    // the number of bytes written to the shard's file, without waiting for a compaction
+    pub(crate) fn used_bytes(&self) -> u64 {
+        let files_guard = self.files.read();
+        files_guard.0.header().write_offset.load(Ordering::Relaxed)
+    }
+
+    // whether the shard is being compacted or has a background split submitted
+    pub(crate) fn has_pending_maintenance(&self) -> bool {
+        self.is_compacting() || self.split_scheduled.load(Ordering::Relaxed)
+    }
+
+    // allows a new background split to be submitted, after the previous one was skipped
+    pub(crate) fn reset_split_scheduled(&self) {
+        self.split_scheduled.store(false, Ordering::Relaxed);
+    }
+
     pub(crate) fn split(&self) -> Result<(Shard, Shard)> {
         let mut handle_guard = self.compaction_handle.lock();
         if let Some(handle) = handle_guard.take() {
@@ -564,6 +1139,9 @@ impl Shard {
         let mid = (self.span.start + self.span.end) / 2;
 
         let t0 = Instant::now();
+        self.stats
+            .maintenance_observer
+            .notify(|obs| obs.on_split_start(self.span.clone()));
 
         let bottom_filename = self
             .config
@@ -602,7 +1180,8 @@ impl Shard {
                     "row={} col={} sig={}",
                     row_idx, *col, target_row.signatures[*col]
                 );
-                target_row.offsets_and_sizes[*col] = file.write_kv(&self.stats, &k, &v)?;
+                target_row.offsets_and_sizes[*col] =
+                    file.write_kv(&self.stats, &k, &v, WriteKind::Split)?;
                 std::sync::atomic::fence(Ordering::SeqCst);
                 target_row.signatures[*col] = ph.signature();
                 file.header().num_inserts.fetch_add(1, Ordering::Relaxed);
@@ -627,11 +1206,14 @@ impl Shard {
             self.span.start, self.span.end
         )))?;
 
-        self.stats.report_split(
-            t0,
+        let (bottom_size, top_size) = (
             bottom_file.header().write_offset.load(Ordering::Relaxed),
             top_file.header().write_offset.load(Ordering::Relaxed),
         );
+        self.stats.report_split(t0, bottom_size, top_size);
+        self.stats.maintenance_observer.notify(|obs| {
+            obs.on_split_end(self.span.clone(), bottom_size + top_size, t0.elapsed())
+        });
 
         let bottom = Self::new(
             self.span.start..mid,
@@ -696,7 +1278,9 @@ impl Shard {
                         row_idx, target_col, target_row.signatures[target_col]
                     );
                     target_row.offsets_and_sizes[target_col] =
-                        combined_files.0.write_kv(&combined.stats, &k, &v)?;
+                        combined_files
+                            .0
+                            .write_kv(&combined.stats, &k, &v, WriteKind::Split)?;
                     std::sync::atomic::fence(Ordering::SeqCst);
                     target_row.signatures[target_col] = ph.signature();
                     combined_files
@@ -731,13 +1315,123 @@ impl Shard {
         Ok(Some(combined))
     }
 
+    // calls `func` with the path of the shard's file and the length of its used part (the header and the data
+    // written so far), while no compaction is running and no row operation can modify the file. returns the
+    // file's name along with the result
+    pub(crate) fn with_exclusive_file<T>(
+        &self,
+        func: impl FnOnce(&Path, u64) -> Result<T>,
+    ) -> Result<(String, T)> {
+        self.wait_for_compaction()?;
+        let files_guard = self.files.write();
+        let used_len = HEADER_SIZE + files_guard.0.header().write_offset.load(Ordering::SeqCst);
+        let filename = format!("shard_{:04x}-{:04x}", self.span.start, self.span.end);
+        let res = func(&self.config.dir_path.join(&filename), used_len)?;
+        Ok((filename, res))
+    }
+
+    // removes every entry whose key ends with `suffix`, returning the number of entries removed and whether the
+    // shard consisted of such entries alone. in that case the shard's file is replaced by an empty one, rather
+    // than removing the entries one by one
+    pub(crate) fn drop_suffix(&self, suffix: u8) -> Result<(usize, bool)> {
+        let mut handle_guard = self.compaction_handle.lock();
+        if let Some(handle) = handle_guard.take() {
+            handle.wait()?;
+        }
+        // holding the files exclusively keeps out all row operations
+        let mut files_guard = self.files.write();
+
+        let mut matching = vec![];
+        let mut num_entries = 0;
+        for row_idx in 0..NUM_ROWS {
+            let row = files_guard.0.row(row_idx);
+            for (col, &sig) in row.signatures.iter().enumerate() {
+                if sig == INVALID_SIG {
+                    continue;
+                }
+                num_entries += 1;
+                let (k, _) =
+                    files_guard
+                        .0
+                        ._read_kv(&self.stats, row.offsets_and_sizes[col], false)?;
+                if k.last() == Some(&suffix) {
+                    matching.push((row_idx, col));
+                }
+            }
+        }
+        if matching.is_empty() {
+            return Ok((0, false));
+        }
+
+        if matching.len() == num_entries {
+            let tmp_filename = self.config.dir_path.join(format!(
+                "drop_{:04x}-{:04x}",
+                self.span.start, self.span.end
+            ));
+            let empty_file = MmapFile::create(&tmp_filename, &self.config)?;
+            std::fs::rename(
+                tmp_filename,
+                self.config.dir_path.join(format!(
+                    "shard_{:04x}-{:04x}",
+                    self.span.start, self.span.end
+                )),
+            )?;
+            files_guard.0 = empty_file;
+        } else {
+            let file = &files_guard.0;
+            for &(row_idx, col) in matching.iter() {
+                let row = file.row_mut(row_idx);
+                row.signatures[col] = INVALID_SIG;
+                file.header().num_removals.fetch_add(1, Ordering::Relaxed);
+                file.header().wasted_bytes.fetch_add(
+                    stored_entry_size(row.offsets_and_sizes[col]),
+                    Ordering::Relaxed,
+                );
+            }
+        }
+        self.stats.bump_generation();
+
+        Ok((matching.len(), matching.len() == num_entries))
+    }
+
+    fn lock_row_for_read(&self, row_idx: usize) -> RwLockReadGuard<'_, ()> {
+        let row_lock = &self.row_locks[row_idx];
+        row_lock.try_read().unwrap_or_else(|| {
+            let t0 = Instant::now();
+            let guard = row_lock.read();
+            self.report_row_wait(row_idx, t0);
+            guard
+        })
+    }
+
+    fn lock_row_for_write(&self, row_idx: usize) -> RwLockWriteGuard<'_, ()> {
+        let row_lock = &self.row_locks[row_idx];
+        row_lock.try_write().unwrap_or_else(|| {
+            let t0 = Instant::now();
+            let guard = row_lock.write();
+            self.report_row_wait(row_idx, t0);
+            guard
+        })
+    }
+
+    fn report_row_wait(&self, _row_idx: usize, _t0: Instant) {
+        #[cfg(feature = "metrics")]
+        self.stats.lock_waits.record(
+            crate::lock_metrics::ContendedLock::Row {
+                shard: self.span.start,
+                row: _row_idx,
+            },
+            _t0,
+        );
+    }
+
     fn operate_on_row<T>(
         &self,
         row_idx: usize,
         func: impl FnOnce(&MmapFile, &ShardRow) -> Result<T>,
     ) -> Result<T> {
         let files_guard = self.files.read();
-        let _row_guard = self.row_locks[row_idx].read();
+        let _row_guard = self.lock_row_for_read(row_idx);
         let file = if let Some(ref target) = files_guard.1 {
             if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) {
                 target
@@ -757,7 +1451,7 @@ impl Shard {
         func: impl FnOnce(&MmapFile, bool, RwLockWriteGuard<()>, &mut ShardRow) -> Result<T>,
     ) -> Result<T> {
         let files_guard = self.files.read();
-        let row_guard = self.row_locks[row_idx].write();
+        let row_guard = self.lock_row_for_write(row_idx);
         let file = if let Some(ref target) = files_guard.1 {
             if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) {
                 target
@@ -795,6 +1489,25 @@ impl Shard {
         })
     }
 
+    // calls `func` with the key and the stored (possibly compressed) value length of every entry in the row
+    pub(crate) fn scan_row(
+        &self,
+        row_idx: usize,
+        mut func: impl FnMut(&[u8], usize),
+    ) -> Result<()> {
+        self.operate_on_row(row_idx, |file, row| {
+            for (idx, sig) in row.signatures.iter().enumerate() {
+                if *sig == INVALID_SIG {
+                    continue;
+                }
+                let offset_and_size = row.offsets_and_sizes[idx];
+                let (k, _) = file._read_kv(&self.stats, offset_and_size, false)?;
+                func(&k, ((offset_and_size >> 32) & 0xffff) as usize);
+            }
+            Ok(())
+        })
+    }
+
     pub(crate) fn get_by_hash(&self, ph: PartedHash) -> Result<Vec<KVPair>> {
         self.operate_on_row(ph.row_selector(), |file, row| {
             let mut first_time = true;
@@ -818,16 +1531,144 @@ impl Shard {
         })
     }
 
+    fn log_collision(&self, kind: CollisionKind, ph: PartedHash, key: &[u8], other_key: Vec<u8>) {
+        self.stats
+            .collision_log
+            .push(self.config.collision_log_capacity, || CollisionRecord {
+                kind,
+                signature: ph.signature(),
+                key: key.to_vec(),
+                other_key,
+            });
+    }
+
+    fn get_in_row(
+        &self,
+        file: &MmapFile,
+        row: &ShardRow,
+        ph: PartedHash,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut start = 0;
+        while let Some(idx) = row.lookup(ph.signature(), &mut start) {
+            let (k, v) = file.read_kv(&self.stats, row.offsets_and_sizes[idx])?;
+            if key == k {
+                self.stats
+                    .num_positive_lookups
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(v));
+            }
+            self.stats
+                .num_lookup_collisions
+                .fetch_add(1, Ordering::Relaxed);
+            self.log_collision(CollisionKind::Lookup, ph, key, k);
+        }
+        self.stats
+            .num_negative_lookups
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(None)
+    }
+
     pub(crate) fn get(&self, ph: PartedHash, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.operate_on_row(ph.row_selector(), |file, row| {
+            self.get_in_row(file, row, ph, key)
+        })
+    }
+
+    pub(crate) fn get_until(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        deadline: Instant,
+    ) -> Result<Option<Vec<u8>>> {
+        let row_idx = ph.row_selector();
+        let Some(files_guard) = self.files.try_read_until(deadline) else {
+            return Err(CandyError::DeadlineExceeded);
+        };
+        let Some(_row_guard) = self.row_locks[row_idx].try_read_until(deadline) else {
+            return Err(CandyError::DeadlineExceeded);
+        };
+        let file = match files_guard.1 {
+            Some(ref target)
+                if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) =>
+            {
+                target
+            }
+            _ => &files_guard.0,
+        };
+        self.get_in_row(file, file.row(row_idx), ph, key)
+    }
+
+    pub(crate) fn get_value_len(&self, ph: PartedHash, key: &[u8]) -> Result<Option<usize>> {
         self.operate_on_row(ph.row_selector(), |file, row| {
             let mut start = 0;
             while let Some(idx) = row.lookup(ph.signature(), &mut start) {
-                let (k, v) = file.read_kv(&self.stats, row.offsets_and_sizes[idx])?;
+                let offset_and_size = row.offsets_and_sizes[idx];
+                if is_compressed(offset_and_size) {
+                    let (k, v) = file.read_kv(&self.stats, offset_and_size)?;
+                    if key == k {
+                        self.stats
+                            .num_positive_lookups
+                            .fetch_add(1, Ordering::Relaxed);
+                        return Ok(Some(v.len()));
+                    }
+                    continue;
+                }
+                let (k, _) = file._read_kv(&self.stats, offset_and_size, false)?;
+                if key == k {
+                    self.stats
+                        .num_positive_lookups
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Ok(Some(((offset_and_size >> 32) & 0xffff) as usize));
+                }
+            }
+            self.stats
+                .num_negative_lookups
+                .fetch_add(1, Ordering::Relaxed);
+            Ok(None)
+        })
+    }
+
+    pub(crate) fn get_checksum(&self, ph: PartedHash, key: &[u8]) -> Result<Option<u64>> {
+        self.operate_on_row(ph.row_selector(), |file, row| {
+            let mut start = 0;
+            while let Some(idx) = row.lookup(ph.signature(), &mut start) {
+                let (k, _) = file._read_kv(&self.stats, row.offsets_and_sizes[idx], false)?;
+                if key == k {
+                    self.stats
+                        .num_positive_lookups
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Ok(Some(
+                        file.val_checksum(&self.stats, row.offsets_and_sizes[idx])?,
+                    ));
+                }
+            }
+            self.stats
+                .num_negative_lookups
+                .fetch_add(1, Ordering::Relaxed);
+            Ok(None)
+        })
+    }
+
+    pub(crate) fn get_range(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        range: Range<usize>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.operate_on_row(ph.row_selector(), |file, row| {
+            let mut start = 0;
+            while let Some(idx) = row.lookup(ph.signature(), &mut start) {
+                let (k, _) = file._read_kv(&self.stats, row.offsets_and_sizes[idx], false)?;
                 if key == k {
                     self.stats
                         .num_positive_lookups
                         .fetch_add(1, Ordering::Relaxed);
-                    return Ok(Some(v));
+                    return Ok(Some(file.read_val_range(
+                        &self.stats,
+                        row.offsets_and_sizes[idx],
+                        range,
+                    )?));
                 }
             }
             self.stats
@@ -882,6 +1723,7 @@ impl Shard {
             let (k, existing_val) = file.read_kv(&self.stats, row.offsets_and_sizes[idx])?;
             if key != k {
                 had_collision = true;
+                self.log_collision(CollisionKind::Insert, ph, key, k);
                 continue;
             }
             match mode {
@@ -904,10 +1746,12 @@ impl Shard {
 
             // optimization
             if val != existing_val {
-                row.offsets_and_sizes[idx] = file.write_kv(&self.stats, key, val)?;
+                let prev_offset_and_size = row.offsets_and_sizes[idx];
+                row.offsets_and_sizes[idx] =
+                    file.write_kv(&self.stats, key, val, WriteKind::Entry)?;
                 file.header()
                     .wasted_bytes
-                    .fetch_add((k.len() + existing_val.len()) as u64, Ordering::Relaxed);
+                    .fetch_add(stored_entry_size(prev_offset_and_size), Ordering::Relaxed);
                 self.stats.num_updates.fetch_add(1, Ordering::Relaxed);
                 #[cfg(feature = "flush_aggregation")]
                 {
@@ -921,7 +1765,7 @@ impl Shard {
         Ok(TryReplaceStatus::KeyDoesNotExist(row_guard, had_collision))
     }
 
-    fn wait_for_compaction(&self) -> Result<()> {
+    pub(crate) fn wait_for_compaction(&self) -> Result<()> {
         let mut handle_guard = self.compaction_handle.lock();
         if let Some(handle) = handle_guard.take() {
             handle.wait()?;
@@ -970,6 +1814,7 @@ impl Shard {
         files_guard.1 = Some(target);
 
         let handle = self.threadpool.submit(CompactionInfo {
+            span: self.span.clone(),
             files: self.files.clone(),
             stats: self.stats.clone(),
             row_locks: self.row_locks.clone(),
@@ -988,6 +1833,10 @@ impl Shard {
         let src = &files_guard.0;
         let target = files_guard.1.as_ref().unwrap();
 
+        info.stats
+            .maintenance_observer
+            .notify(|obs| obs.on_compaction_start(info.span.clone()));
+
         Self::do_compaction(&info.row_locks, src, target, &info.stats, &info.config)?;
 
         std::fs::rename(&info.target_filename, &info.src_filename)?;
@@ -998,9 +1847,16 @@ impl Shard {
             target.header().write_offset.load(Ordering::Relaxed),
         );
 
+        let (prev_size, new_size) = (
+            src.header().write_offset.load(Ordering::Relaxed),
+            target.header().write_offset.load(Ordering::Relaxed),
+        );
         files_guard.with_upgraded(|files| {
             files.0 = files.1.take().unwrap();
         });
+        info.stats.maintenance_observer.notify(|obs| {
+            obs.on_compaction_end(info.span.clone(), prev_size, new_size, info.t0.elapsed())
+        });
         Ok(())
     }
 
@@ -1017,7 +1873,7 @@ impl Shard {
             self.operate_on_row_mut(ph.row_selector(), |file, is_compacting, row_guard, row| {
                 if !is_compacting {
                     if file.header().wasted_bytes.load(Ordering::Relaxed)
-                        >= self.config.min_compaction_threashold as u64
+                        >= self.config.min_compaction_threashold.get() as u64
                     {
                         should_compact = Some(file.header().write_offset.load(Ordering::Relaxed));
                     } else if file.header().write_offset.load(Ordering::Relaxed)
@@ -1038,7 +1894,8 @@ impl Shard {
                         // find an empty slot
                         let mut start = 0;
                         if let Some(idx) = row.lookup(INVALID_SIG, &mut start) {
-                            let new_off = file.write_kv(&self.stats, &full_key, val)?;
+                            let new_off =
+                                file.write_kv(&self.stats, &full_key, val, WriteKind::Entry)?;
 
                             // we don't want a reorder to happen here - first write the offset, then the signature
                             row.offsets_and_sizes[idx] = new_off;
@@ -1068,35 +1925,118 @@ impl Shard {
                 }
             })?;
 
+        if matches!(status, InsertStatus::Added | InsertStatus::Replaced(_)) {
+            self.stats.bump_generation();
+        }
         if let Some(min_write_offset) = should_compact {
             self.begin_compaction(min_write_offset)?;
         }
         Ok(status)
     }
 
-    pub(crate) fn remove(&self, ph: PartedHash, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.operate_on_row_mut(ph.row_selector(), |file, _, _guard, row| {
+    // removes the key, only if its value is `expected` (if given)
+    pub(crate) fn remove(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        expected: Option<&[u8]>,
+    ) -> Result<ReplaceStatus> {
+        let res = self.operate_on_row_mut(ph.row_selector(), |file, _, _guard, row| {
             let mut start = 0;
 
             while let Some(idx) = row.lookup(ph.signature(), &mut start) {
                 let (k, v) = file.read_kv(&self.stats, row.offsets_and_sizes[idx])?;
                 if key == k {
+                    if expected.is_some_and(|expected| expected != v) {
+                        return Ok(ReplaceStatus::WrongValue(v));
+                    }
                     row.signatures[idx] = INVALID_SIG;
                     // we managed to remove this key
                     file.header().num_removals.fetch_add(1, Ordering::Relaxed);
-                    file.header()
-                        .wasted_bytes
-                        .fetch_add((k.len() + v.len()) as u64, Ordering::Relaxed);
+                    file.header().wasted_bytes.fetch_add(
+                        stored_entry_size(row.offsets_and_sizes[idx]),
+                        Ordering::Relaxed,
+                    );
                     #[cfg(feature = "flush_aggregation")]
                     {
                         drop(_guard);
                         self.flush_aggregation()?;
                     }
-                    return Ok(Some(v));
+                    return Ok(ReplaceStatus::PrevValue(v));
                 }
             }
 
-            Ok(None)
+            Ok(ReplaceStatus::DoesNotExist)
+        })?;
+        if matches!(res, ReplaceStatus::PrevValue(_)) {
+            self.stats.bump_generation();
+        }
+        Ok(res)
+    }
+
+    pub(crate) fn patch(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        offset: usize,
+        patch: &[u8],
+        expected: Option<&[u8]>,
+    ) -> Result<PatchStatus> {
+        self.operate_on_row_mut(ph.row_selector(), |file, _, _guard, row| {
+            let mut start = 0;
+            while let Some(idx) = row.lookup(ph.signature(), &mut start) {
+                let offset_and_size = row.offsets_and_sizes[idx];
+                let (k, _) = file._read_kv(&self.stats, offset_and_size, false)?;
+                if key != k {
+                    continue;
+                }
+
+                if is_compressed(offset_and_size) {
+                    // compressed values cannot be patched in place, so we rewrite the whole entry
+                    let (_, mut val) = file.read_kv(&self.stats, offset_and_size)?;
+                    let end = patch_end(offset, patch.len(), val.len())?;
+                    let existing = val[offset..end].to_owned();
+                    if expected.is_some_and(|expected| expected != existing) {
+                        return Ok(PatchStatus::WrongValue(existing));
+                    }
+                    if existing != patch {
+                        val[offset..end].copy_from_slice(patch);
+                        row.offsets_and_sizes[idx] =
+                            file.write_kv(&self.stats, key, &val, WriteKind::Entry)?;
+                        file.header()
+                            .wasted_bytes
+                            .fetch_add(stored_entry_size(offset_and_size), Ordering::Relaxed);
+                        self.stats.num_updates.fetch_add(1, Ordering::Relaxed);
+                        self.stats.bump_generation();
+                        #[cfg(feature = "flush_aggregation")]
+                        {
+                            drop(_guard);
+                            self.flush_aggregation()?;
+                        }
+                    }
+                    return Ok(PatchStatus::Patched(existing));
+                }
+
+                let vlen = ((offset_and_size >> 32) & 0xffff) as usize;
+                let end = patch_end(offset, patch.len(), vlen)?;
+                let existing = file.read_val_range(&self.stats, offset_and_size, offset..end)?;
+                if expected.is_some_and(|expected| expected != existing) {
+                    return Ok(PatchStatus::WrongValue(existing));
+                }
+                if existing != patch {
+                    file.write_val_at(&self.stats, offset_and_size, offset, patch)?;
+                    self.stats.num_updates.fetch_add(1, Ordering::Relaxed);
+                    self.stats.bump_generation();
+                    #[cfg(feature = "flush_aggregation")]
+                    {
+                        drop(_guard);
+                        self.flush_aggregation()?;
+                    }
+                }
+                return Ok(PatchStatus::Patched(existing));
+            }
+
+            Ok(PatchStatus::KeyDoesNotExist)
         })
     }
 
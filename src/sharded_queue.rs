@@ -0,0 +1,133 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{CandyError, CandyStore, Result};
+
+/// How [CandyShardedQueue] picks the sub-queue that a pushed element goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardingStrategy {
+    /// pushes cycle through the sub-queues, spreading the load evenly regardless of the number of producers
+    #[default]
+    RoundRobin,
+    /// each producer thread always pushes to the same sub-queue (by the hash of its thread id), so the elements
+    /// of a single producer are popped in the order they were pushed
+    ProducerThread,
+}
+
+/// A logical FIFO queue that is spread across several sub-queues (regular [CandyStore] queues), for queues
+/// that are so hot that all producers serializing on a single queue's lock becomes the bottleneck. Pushes go
+/// to one sub-queue (see [ShardingStrategy]) and pops take turns over the sub-queues, so no sub-queue is
+/// starved.
+///
+/// The price is ordering: elements are FIFO within a sub-queue, but not across sub-queues. The number of
+/// sub-queues is not persisted, so a queue must always be opened with the same `num_shards` (elements of
+/// sub-queues beyond `num_shards` are not popped)
+pub struct CandyShardedQueue {
+    store: CandyStore,
+    name: Vec<u8>,
+    num_shards: usize,
+    strategy: ShardingStrategy,
+    next_push: AtomicUsize,
+    next_pop: AtomicUsize,
+}
+
+impl CandyShardedQueue {
+    /// Constructs a [CandyShardedQueue] with the given name and number of sub-queues over an existing
+    /// [CandyStore]
+    pub fn new<B: AsRef<[u8]> + ?Sized>(
+        store: CandyStore,
+        name: &B,
+        num_shards: usize,
+        strategy: ShardingStrategy,
+    ) -> Result<Self> {
+        if num_shards == 0 || num_shards > u16::MAX as usize {
+            return Err(CandyError::InvalidArgument(format!(
+                "num_shards must be between 1 and {}, got {num_shards}",
+                u16::MAX
+            )));
+        }
+        Ok(Self {
+            store,
+            name: name.as_ref().to_owned(),
+            num_shards,
+            strategy,
+            next_push: AtomicUsize::new(0),
+            next_pop: AtomicUsize::new(0),
+        })
+    }
+
+    /// The number of sub-queues
+    pub fn num_shards(&self) -> usize {
+        self.num_shards
+    }
+
+    // the name goes first, followed by the shard index and the name's length, so sub-queues of different
+    // sharded queues never collide
+    fn make_shard_key(&self, shard: usize) -> Vec<u8> {
+        let mut queue_key = Vec::with_capacity(self.name.len() + 2 * size_of::<u16>());
+        queue_key.extend_from_slice(&self.name);
+        queue_key.extend_from_slice(&(shard as u16).to_le_bytes());
+        queue_key.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        queue_key
+    }
+
+    fn push_shard(&self) -> usize {
+        match self.strategy {
+            ShardingStrategy::RoundRobin => {
+                self.next_push.fetch_add(1, Ordering::Relaxed) % self.num_shards
+            }
+            ShardingStrategy::ProducerThread => {
+                let mut hasher = DefaultHasher::new();
+                std::thread::current().id().hash(&mut hasher);
+                hasher.finish() as usize % self.num_shards
+            }
+        }
+    }
+
+    /// Pushes an element to the logical queue. Only the chosen sub-queue is locked
+    pub fn push<B: AsRef<[u8]> + ?Sized>(&self, val: &B) -> Result<()> {
+        self.store
+            .push_to_queue_tail(&self.make_shard_key(self.push_shard()), val)?;
+        Ok(())
+    }
+
+    /// Pops an element from the logical queue, or returns None if all sub-queues are empty. Every pop starts
+    /// at the sub-queue following the one the previous pop started at, so consumers drain the sub-queues
+    /// evenly
+    pub fn pop(&self) -> Result<Option<Vec<u8>>> {
+        let start = self.next_pop.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.num_shards {
+            let shard = (start + i) % self.num_shards;
+            if let Some(v) = self.store.pop_queue_head(&self.make_shard_key(shard))? {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the total number of elements in all sub-queues. This is not an atomic snapshot if elements are
+    /// pushed or popped concurrently
+    pub fn len(&self) -> Result<usize> {
+        let mut len = 0;
+        for shard in 0..self.num_shards {
+            len += self.store.queue_len(&self.make_shard_key(shard))?;
+        }
+        Ok(len)
+    }
+
+    /// Returns true if all sub-queues are empty
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Discards all sub-queues, returning true if any of them existed
+    pub fn discard(&self) -> Result<bool> {
+        let mut existed = false;
+        for shard in 0..self.num_shards {
+            existed |= self.store.discard_queue(&self.make_shard_key(shard))?;
+        }
+        Ok(existed)
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{CandyError, Result};
+
+pub(crate) const SIGNATURE_LEN: usize = 32;
+
+const MANIFEST_MAGIC: &[u8; 8] = b"CandySG1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// the key used to sign exports, which is never debug-printed
+pub(crate) struct SigningKey(Vec<u8>);
+
+impl SigningKey {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        Self(key.to_vec())
+    }
+}
+
+impl std::ops::Deref for SigningKey {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+fn new_mac(key: &[u8]) -> HmacSha256 {
+    // HMAC takes keys of any length
+    HmacSha256::new_from_slice(key).expect("HMAC accepts any key length")
+}
+
+// signs data that is written incrementally
+pub(crate) struct Signer(HmacSha256);
+
+impl Signer {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        Self(new_mac(key))
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub(crate) fn finalize(self) -> [u8; SIGNATURE_LEN] {
+        self.0.finalize().into_bytes().into()
+    }
+}
+
+pub(crate) fn verify(key: &[u8], data: &[u8], signature: &[u8]) -> Result<()> {
+    let mut mac = new_mac(key);
+    mac.update(data);
+    // compares in constant time
+    mac.verify_slice(signature)
+        .map_err(|_| CandyError::BadSignature("signature does not match the contents".into()))
+}
+
+// the manifest that accompanies a signed file, e.g., `table.sst.sig` for `table.sst`
+pub(crate) fn manifest_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+fn file_mac(key: &[u8], path: &Path) -> Result<(u64, HmacSha256)> {
+    let mut mac = new_mac(key);
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut len = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        mac.update(&buf[..n]);
+        len += n as u64;
+    }
+    // the length is signed as well, so a manifest can't be paired with a prefix of the file
+    mac.update(&len.to_le_bytes());
+    Ok((len, mac))
+}
+
+// writes the manifest of the given file: its length and signature
+pub(crate) fn write_manifest(key: &[u8], path: &Path) -> Result<()> {
+    let (len, mac) = file_mac(key, path)?;
+    let mut manifest = File::create(manifest_path(path))?;
+    manifest.write_all(MANIFEST_MAGIC)?;
+    manifest.write_all(&len.to_le_bytes())?;
+    manifest.write_all(&mac.finalize().into_bytes())?;
+    manifest.sync_data()?;
+    Ok(())
+}
+
+// checks the given file against its manifest, before anything is read from it
+pub(crate) fn verify_manifest(key: &[u8], path: &Path) -> Result<()> {
+    let manifest_path = manifest_path(path);
+    let manifest = match std::fs::read(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(CandyError::BadSignature(format!(
+                "{manifest_path:?} is missing"
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if manifest.len() != MANIFEST_MAGIC.len() + size_of::<u64>() + SIGNATURE_LEN
+        || &manifest[..MANIFEST_MAGIC.len()] != MANIFEST_MAGIC
+    {
+        return Err(CandyError::BadSignature(format!(
+            "{manifest_path:?} is malformed"
+        )));
+    }
+    let (len_bytes, signature) = manifest[MANIFEST_MAGIC.len()..].split_at(size_of::<u64>());
+    let expected_len = u64::from_le_bytes(len_bytes.try_into().unwrap());
+
+    let (len, mac) = file_mac(key, path)?;
+    if len != expected_len {
+        return Err(CandyError::BadSignature(format!(
+            "{path:?} is {len} bytes long rather than {expected_len}"
+        )));
+    }
+    mac.verify_slice(signature)
+        .map_err(|_| CandyError::BadSignature(format!("{path:?} does not match its signature")))
+}
@@ -0,0 +1,143 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::CandyStore;
+
+/// Thresholds below the store's hard limits, past which the [SoftLimitObserver] is warned, so that capacity
+/// issues are noticed before writes start failing. See [crate::Config::soft_limits]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoftLimits {
+    /// the total number of bytes written to the shard files (including data that was overwritten but not yet
+    /// compacted), e.g., 80% of the disk space set aside for the store. It is checked by writers, at most once
+    /// a second, which opens all shards of a [crate::Config::lazy_open] store
+    pub total_bytes: Option<u64>,
+    /// the fraction of `max_shard_size` (e.g., 0.8) that a shard's live data may reach before the shard is
+    /// reported as nearing its split
+    pub shard_fill_level: Option<f64>,
+    /// the span of a list, i.e., the distance between its head and tail (which includes the holes left by
+    /// removed items, see [CandyStore::compact_list_if_needed]), past which the list is reported
+    pub list_span: Option<u64>,
+}
+
+/// A soft limit that was crossed, see [SoftLimits]. Each warning is delivered once when the limit is crossed,
+/// rather than on every write past it
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoftLimitWarning {
+    /// the shard files hold `used_bytes`, more than the limit. Warned again once the total drops below the
+    /// limit (e.g., after compactions) and crosses it once more
+    TotalBytes { used_bytes: u64, limit: u64 },
+    /// the live data of the shard covering `span` reached `live_bytes`, more than the limit, so the shard will
+    /// have to be split soon
+    ShardNearSplit {
+        span: Range<u32>,
+        live_bytes: u64,
+        limit: u64,
+    },
+    /// the span of the given list grew past the limit
+    ListSpan {
+        list_key: Vec<u8>,
+        span: u64,
+        limit: u64,
+    },
+}
+
+/// Receives the warnings of [SoftLimits]. Register one using [CandyStore::set_soft_limit_observer].
+///
+/// Warnings are delivered synchronously, from the writing thread, possibly while internal locks (e.g., the
+/// list's lock) are held. Implementations should therefore return quickly and must not access the store
+pub trait SoftLimitObserver: Send + Sync {
+    fn on_soft_limit(&self, warning: SoftLimitWarning);
+}
+
+// the total size is summed over all shards, so it is not checked on every write
+const TOTAL_BYTES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub(crate) struct SoftLimitState {
+    observer: RwLock<Option<Arc<dyn SoftLimitObserver>>>,
+    last_total_check: Mutex<Option<Instant>>,
+    total_warned: AtomicBool,
+}
+
+impl std::fmt::Debug for SoftLimitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SoftLimitState")
+            .field("observer", &self.observer.read().is_some())
+            .field("total_warned", &self.total_warned)
+            .finish()
+    }
+}
+
+impl SoftLimitState {
+    pub(crate) fn has_observer(&self) -> bool {
+        self.observer.read().is_some()
+    }
+
+    pub(crate) fn notify(&self, warning: SoftLimitWarning) {
+        // clone the observer out, so a slow observer does not block replacing it
+        let observer = self.observer.read().clone();
+        if let Some(observer) = observer {
+            observer.on_soft_limit(warning);
+        }
+    }
+}
+
+impl CandyStore {
+    /// Registers the observer that receives the warnings of [crate::Config::soft_limits], replacing the
+    /// previous one. Pass `None` to unregister it. Soft limits are only checked while an observer is registered
+    pub fn set_soft_limit_observer(&self, observer: Option<Arc<dyn SoftLimitObserver>>) {
+        *self.0.stats.soft_limits.observer.write() = observer;
+    }
+
+    // called after every write, outside of the shards' locks
+    pub(crate) fn check_total_bytes_soft_limit(&self) {
+        let Some(limit) = self.0.config.soft_limits.total_bytes else {
+            return;
+        };
+        let state = &self.0.stats.soft_limits;
+        if !state.has_observer() {
+            return;
+        }
+        {
+            // another writer is already checking
+            let Some(mut last_check) = state.last_total_check.try_lock() else {
+                return;
+            };
+            if last_check.is_some_and(|t| t.elapsed() < TOTAL_BYTES_CHECK_INTERVAL) {
+                return;
+            }
+            *last_check = Some(Instant::now());
+        }
+
+        let Ok(sizes) = self.0.root.call_on_all_shards(|sh| Ok(sh.used_bytes())) else {
+            return;
+        };
+        let used_bytes = sizes.into_iter().sum::<u64>();
+        let over = used_bytes > limit;
+        if state.total_warned.swap(over, Ordering::Relaxed) != over && over {
+            state.notify(SoftLimitWarning::TotalBytes { used_bytes, limit });
+        }
+    }
+
+    // called under the list's lock, once the span of the list (given with its namespace) grew
+    pub(crate) fn check_list_span_soft_limit(&self, list_key: &[u8], prev_span: u64, span: u64) {
+        let Some(limit) = self.0.config.soft_limits.list_span else {
+            return;
+        };
+        if prev_span <= limit && span > limit {
+            self.0.stats.soft_limits.notify(SoftLimitWarning::ListSpan {
+                list_key: list_key[..list_key.len() - 1].to_owned(),
+                span,
+                limit,
+            });
+        }
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+use bytemuck::pod_read_unaligned;
+
+use crate::{
+    cancellation::CancellationToken, expiry::expiry_owner, hashing::PartedHash,
+    key_history::key_history_owner, progress::Progress, signing, store::CandyStoreIterator,
+    CandyError, CandyStore, Namespace, Result,
+};
+
+// the LevelDB table format (which RocksDB reads as its "legacy block-based table" format), see
+// https://github.com/google/leveldb/blob/main/doc/table_format.md
+const TABLE_MAGIC: u64 = 0xdb4775248b80fb57;
+const FOOTER_SIZE: usize = 48;
+const BLOCK_TRAILER_SIZE: usize = 5;
+const BLOCK_SIZE: usize = 4096;
+const RESTART_INTERVAL: usize = 16;
+const NO_COMPRESSION: u8 = 0;
+// keys in tables are "internal keys": the user key followed by (sequence << 8 | value type)
+const VALUE_TYPE_DELETION: u8 = 0;
+const VALUE_TYPE_VALUE: u8 = 1;
+const INTERNAL_KEY_SUFFIX_LEN: usize = 8;
+const MAX_SEQUENCE: u64 = (1 << 56) - 1;
+
+/// Controls [CandyStore::export_sst] and [CandyStore::import_sst]
+#[derive(Debug, Clone, Default)]
+pub struct SstParams {
+    /// export (or import) all entries of the store, including the internal ones of lists, queues, typed stores,
+    /// etc., as-is. Such tables can only be imported into a store with the same [crate::Config::hash_seed].
+    /// When unset, only the keys of [CandyStore::set] and friends are exported
+    pub raw: bool,
+    /// selects which entries are exported (ignored by imports)
+    pub filter: ExportFilter,
+    /// if set, the export (or import) fails with [CandyError::Cancelled] once the token is cancelled. A cancelled
+    /// export removes the partially-written table, while a cancelled import keeps the entries imported so far
+    pub cancel: Option<CancellationToken>,
+    /// if set, receives the progress of the export, i.e., the number of keys written out of the number of keys
+    /// collected (ignored by imports)
+    pub progress: Option<Progress>,
+}
+
+/// Selects the entries that [CandyStore::export_sst] exports, e.g., to back up only durable data and skip
+/// large transient queues. An entry is exported if it passes all the filters; empty `include_*` filters
+/// include everything.
+///
+/// Key prefixes apply to the key the entry belongs to: the user's key for plain keys, or the list's (queue's)
+/// key for all the entries of a list (queue). Keys of typed stores and typed lists are matched as encoded.
+/// Non-raw exports contain only the keys of [CandyStore::set] and friends (the [Namespace::User] namespace)
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    pub include_namespaces: Vec<Namespace>,
+    pub exclude_namespaces: Vec<Namespace>,
+    pub include_key_prefixes: Vec<Vec<u8>>,
+    pub exclude_key_prefixes: Vec<Vec<u8>>,
+    /// if not empty, the entries of lists and queues other than these are skipped (entries that do not
+    /// belong to a list or a queue are not affected)
+    pub include_lists: Vec<Vec<u8>>,
+    /// the entries of these lists and queues are skipped
+    pub exclude_lists: Vec<Vec<u8>>,
+}
+
+impl ExportFilter {
+    fn is_empty(&self) -> bool {
+        self.include_namespaces.is_empty()
+            && self.exclude_namespaces.is_empty()
+            && self.include_key_prefixes.is_empty()
+            && self.exclude_key_prefixes.is_empty()
+            && self.include_lists.is_empty()
+            && self.exclude_lists.is_empty()
+    }
+
+    fn allows_namespace(&self, ns: Namespace) -> bool {
+        (self.include_namespaces.is_empty() || self.include_namespaces.contains(&ns))
+            && !self.exclude_namespaces.contains(&ns)
+    }
+
+    // `owner` is the key the entry belongs to, if known
+    fn allows_owner(&self, owner: Option<&[u8]>, is_list: bool) -> bool {
+        if is_list {
+            if !self.include_lists.is_empty()
+                && !owner.is_some_and(|o| self.include_lists.iter().any(|l| l == o))
+            {
+                return false;
+            }
+            if owner.is_some_and(|o| self.exclude_lists.iter().any(|l| l == o)) {
+                return false;
+            }
+        }
+        match owner {
+            Some(o) => {
+                (self.include_key_prefixes.is_empty()
+                    || self.include_key_prefixes.iter().any(|p| o.starts_with(p)))
+                    && !self.exclude_key_prefixes.iter().any(|p| o.starts_with(p))
+            }
+            None => self.include_key_prefixes.is_empty(),
+        }
+    }
+}
+
+fn malformed(msg: impl std::fmt::Display) -> CandyError {
+    CandyError::InvalidArgument(format!("malformed SST: {msg}"))
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut j = 0;
+            while j < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x82f63b78
+                } else {
+                    crc >> 1
+                };
+                j += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    let mut crc = !0u32;
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    (crc.rotate_right(15)).wrapping_add(0xa282ead8)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn get_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *buf.get(*pos).ok_or_else(|| malformed("truncated varint"))?;
+        *pos += 1;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(malformed("varint too long"))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, self.offset);
+        put_varint(buf, self.size);
+    }
+
+    fn decode(buf: &[u8], pos: &mut usize) -> Result<Self> {
+        Ok(Self {
+            offset: get_varint(buf, pos)?,
+            size: get_varint(buf, pos)?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    fn add(&mut self, key: &[u8], val: &[u8], restart_interval: usize) {
+        let mut shared = 0;
+        if self.counter.is_multiple_of(restart_interval) {
+            self.restarts.push(self.buf.len() as u32);
+        } else {
+            shared = key
+                .iter()
+                .zip(&self.last_key)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        put_varint(&mut self.buf, shared as u64);
+        put_varint(&mut self.buf, (key.len() - shared) as u64);
+        put_varint(&mut self.buf, val.len() as u64);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(val);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.counter += 1;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.counter == 0
+    }
+
+    fn estimated_size(&self) -> usize {
+        self.buf.len() + (self.restarts.len() + 1) * 4
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buf);
+        let mut restarts = std::mem::take(&mut self.restarts);
+        if restarts.is_empty() {
+            restarts.push(0);
+        }
+        for r in &restarts {
+            block.extend_from_slice(&r.to_le_bytes());
+        }
+        block.extend_from_slice(&(restarts.len() as u32).to_le_bytes());
+        self.counter = 0;
+        block
+    }
+}
+
+struct TableWriter<W> {
+    writer: W,
+    offset: u64,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    sequence: u64,
+}
+
+impl<W: Write> TableWriter<W> {
+    fn write_block(&mut self, block: &[u8]) -> Result<BlockHandle> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: block.len() as u64,
+        };
+        let mut trailer = [NO_COMPRESSION, 0, 0, 0, 0];
+        let mut crc_input = Vec::with_capacity(block.len() + 1);
+        crc_input.extend_from_slice(block);
+        crc_input.push(NO_COMPRESSION);
+        trailer[1..].copy_from_slice(&masked_crc32c(&crc_input).to_le_bytes());
+        self.writer.write_all(block)?;
+        self.writer.write_all(&trailer)?;
+        self.offset += (block.len() + BLOCK_TRAILER_SIZE) as u64;
+        Ok(handle)
+    }
+
+    fn flush_data_block(&mut self) -> Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        // the index entry's key must be >= the block's last key, so we simply use the last key
+        let last_key = self.data_block.last_key.clone();
+        let block = self.data_block.finish();
+        let handle = self.write_block(&block)?;
+        let mut handle_buf = vec![];
+        handle.encode(&mut handle_buf);
+        self.index_block.add(&last_key, &handle_buf, 1);
+        Ok(())
+    }
+
+    fn add(&mut self, user_key: &[u8], val: &[u8]) -> Result<()> {
+        let mut key = Vec::with_capacity(user_key.len() + INTERNAL_KEY_SUFFIX_LEN);
+        key.extend_from_slice(user_key);
+        key.extend_from_slice(&(self.sequence << 8 | VALUE_TYPE_VALUE as u64).to_le_bytes());
+        self.data_block.add(&key, val, RESTART_INTERVAL);
+        if self.data_block.estimated_size() >= BLOCK_SIZE {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.flush_data_block()?;
+        let metaindex_block = BlockBuilder::default().finish();
+        let metaindex_handle = self.write_block(&metaindex_block)?;
+        let index_block = self.index_block.finish();
+        let index_handle = self.write_block(&index_block)?;
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        metaindex_handle.encode(&mut footer);
+        index_handle.encode(&mut footer);
+        footer.resize(FOOTER_SIZE - 8, 0);
+        footer.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+        self.writer.write_all(&footer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// the handle comes from the file, so it is checked against the file's length before anything is allocated
+fn read_block(file: &File, file_len: u64, handle: BlockHandle) -> Result<Vec<u8>> {
+    let fits = handle
+        .offset
+        .checked_add(handle.size)
+        .and_then(|end| end.checked_add(BLOCK_TRAILER_SIZE as u64))
+        .is_some_and(|end| end <= file_len);
+    if !fits {
+        return Err(malformed(format!(
+            "block of {} bytes at {} is past the end of the file",
+            handle.size, handle.offset
+        )));
+    }
+    let mut buf = vec![0u8; handle.size as usize + BLOCK_TRAILER_SIZE];
+    file.read_exact_at(&mut buf, handle.offset)?;
+    let trailer = &buf[handle.size as usize..];
+    if trailer[0] != NO_COMPRESSION {
+        return Err(malformed(format!(
+            "compressed blocks (type {}) are not supported",
+            trailer[0]
+        )));
+    }
+    let expected_crc = u32::from_le_bytes(trailer[1..].try_into().unwrap());
+    if masked_crc32c(&buf[..handle.size as usize + 1]) != expected_crc {
+        return Err(malformed(format!(
+            "checksum mismatch in block at {}",
+            handle.offset
+        )));
+    }
+    buf.truncate(handle.size as usize);
+    Ok(buf)
+}
+
+// iterates over the entries of a block, reconstructing the prefix-compressed keys
+fn for_each_in_block(block: &[u8], mut func: impl FnMut(&[u8], &[u8]) -> Result<()>) -> Result<()> {
+    if block.len() < 4 {
+        return Err(malformed("block too short"));
+    }
+    let num_restarts = u32::from_le_bytes(block[block.len() - 4..].try_into().unwrap()) as usize;
+    let entries_end = block
+        .len()
+        .checked_sub(4 + num_restarts * 4)
+        .ok_or_else(|| malformed("bad restart array"))?;
+
+    let mut pos = 0;
+    let mut key = vec![];
+    while pos < entries_end {
+        let shared = get_varint(block, &mut pos)? as usize;
+        let non_shared = get_varint(block, &mut pos)? as usize;
+        let val_len = get_varint(block, &mut pos)? as usize;
+        if shared > key.len() || pos + non_shared + val_len > entries_end {
+            return Err(malformed("bad block entry"));
+        }
+        key.truncate(shared);
+        key.extend_from_slice(&block[pos..pos + non_shared]);
+        pos += non_shared;
+        func(&key, &block[pos..pos + val_len])?;
+        pos += val_len;
+    }
+    Ok(())
+}
+
+// calls `func` with the user key, the internal key's suffix (sequence << 8 | value type) and the value of
+// every entry in the table
+fn for_each_in_table(
+    path: &Path,
+    mut func: impl FnMut(&[u8], u64, &[u8]) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    if file_len < FOOTER_SIZE as u64 {
+        return Err(malformed("file too short"));
+    }
+    let mut footer = [0u8; FOOTER_SIZE];
+    file.read_exact_at(&mut footer, file_len - FOOTER_SIZE as u64)?;
+    if u64::from_le_bytes(footer[FOOTER_SIZE - 8..].try_into().unwrap()) != TABLE_MAGIC {
+        return Err(malformed("bad magic number"));
+    }
+    let mut pos = 0;
+    let _metaindex_handle = BlockHandle::decode(&footer, &mut pos)?;
+    let index_handle = BlockHandle::decode(&footer, &mut pos)?;
+
+    let mut data_handles = vec![];
+    for_each_in_block(&read_block(&file, file_len, index_handle)?, |_, v| {
+        data_handles.push(BlockHandle::decode(v, &mut 0)?);
+        Ok(())
+    })?;
+
+    for handle in data_handles {
+        for_each_in_block(&read_block(&file, file_len, handle)?, |k, v| {
+            let Some(user_key_len) = k.len().checked_sub(INTERNAL_KEY_SUFFIX_LEN) else {
+                return Err(malformed("key too short"));
+            };
+            let (user_key, suffix) = k.split_at(user_key_len);
+            func(user_key, u64::from_le_bytes(suffix.try_into().unwrap()), v)
+        })?;
+    }
+    Ok(())
+}
+
+impl CandyStore {
+    fn filter_export_keys(&self, keys: Vec<Vec<u8>>, params: &SstParams) -> Result<Vec<Vec<u8>>> {
+        let filter = &params.filter;
+        if !params.raw {
+            if !filter.allows_namespace(Namespace::User) {
+                return Ok(vec![]);
+            }
+            return Ok(keys
+                .into_iter()
+                .filter(|k| filter.allows_owner(Some(k), false))
+                .collect());
+        }
+
+        // list items and chain entries refer to their list by its hash
+        let ph_len = size_of::<PartedHash>();
+        let lists = keys
+            .iter()
+            .filter(|k| Namespace::from_suffix(*k.last().unwrap()) == Some(Namespace::List))
+            .map(|k| {
+                let list_key = k[..k.len() - 1].to_vec();
+                (self.make_list_key(list_key.clone()).0, list_key)
+            })
+            .collect::<HashMap<_, _>>();
+        let list_of = |ph_bytes: &[u8]| {
+            lists
+                .get(&pod_read_unaligned::<PartedHash>(ph_bytes))
+                .cloned()
+        };
+
+        let mut filtered = vec![];
+        for key in keys {
+            let Some(ns) = Namespace::from_suffix(*key.last().unwrap()) else {
+                filtered.push(key);
+                continue;
+            };
+            if !filter.allows_namespace(ns) {
+                continue;
+            }
+            let body = &key[..key.len() - 1];
+            let (owner, is_list) = match ns {
+                Namespace::Interned => (Some(self.resolve_interned_key(&key)?), false),
+                Namespace::InternTable | Namespace::Journal => (None, false),
+                Namespace::List | Namespace::Queue => (Some(body.to_vec()), true),
+                Namespace::ListItem if body.len() >= ph_len => {
+                    (list_of(&body[body.len() - ph_len..]), true)
+                }
+                Namespace::ListChain | Namespace::ListAudit | Namespace::PopToken
+                    if body.len() >= ph_len =>
+                {
+                    (list_of(&body[..ph_len]), true)
+                }
+                Namespace::KeyHistory => (key_history_owner(body).map(|k| k.to_vec()), false),
+                Namespace::Expiry => (expiry_owner(body).map(|k| k.to_vec()), false),
+                Namespace::QueueItem if body.len() >= size_of::<u64>() => {
+                    (Some(body[..body.len() - size_of::<u64>()].to_vec()), true)
+                }
+                Namespace::ListCursor if body.len() >= size_of::<u16>() => {
+                    let name_len = u16::from_le_bytes(body[body.len() - 2..].try_into().unwrap());
+                    let list_key_len = body.len() - size_of::<u16>();
+                    (
+                        list_key_len
+                            .checked_sub(name_len as usize)
+                            .map(|len| body[..len].to_vec()),
+                        true,
+                    )
+                }
+                Namespace::ListItem
+                | Namespace::ListChain
+                | Namespace::QueueItem
+                | Namespace::ListCursor
+                | Namespace::ListAudit
+                | Namespace::PopToken => (None, true),
+                _ => (Some(body.to_vec()), false),
+            };
+            if filter.allows_owner(owner.as_deref(), is_list) {
+                filtered.push(key);
+            }
+        }
+        Ok(filtered)
+    }
+
+    /// Exports the store into a table file in the LevelDB table format (uncompressed), which RocksDB tooling
+    /// (e.g., `sst_dump`) can read as well. Keys are written sorted, and are all stamped with the store's
+    /// [generation](Self::generation) at the beginning of the export as their sequence number (see
+    /// [Self::sst_generation]). Returns the number of entries exported.
+    ///
+    /// Entries can be selected with [SstParams::filter], and the export can be aborted with [SstParams::cancel].
+    /// If [crate::Config::export_signing_key] is set, the table is signed, and the signature is written to a
+    /// manifest next to it (`<path>.sig`).
+    ///
+    /// Note: the keys are collected in memory in order to sort them, and the export is not a consistent
+    /// snapshot if the store is modified concurrently
+    pub fn export_sst(&self, path: impl AsRef<Path>, params: SstParams) -> Result<usize> {
+        let path = path.as_ref();
+        let res = self.export_sst_to(path, &params);
+        if matches!(res, Err(CandyError::Cancelled)) {
+            _ = std::fs::remove_file(path);
+        }
+        res
+    }
+
+    fn export_sst_to(&self, path: &Path, params: &SstParams) -> Result<usize> {
+        let cancel = params.cancel.as_ref();
+        let sequence = self.generation() & MAX_SEQUENCE;
+        let mut keys = CandyStoreIterator::from_cookie(self, 0, params.raw, false)
+            .map(|res| {
+                CancellationToken::check(cancel)?;
+                res.map(|(k, _)| k)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !params.filter.is_empty() {
+            keys = self.filter_export_keys(keys, params)?;
+        }
+        keys.sort();
+
+        let mut table = TableWriter {
+            writer: BufWriter::new(File::create(path)?),
+            offset: 0,
+            data_block: BlockBuilder::default(),
+            index_block: BlockBuilder::default(),
+            sequence,
+        };
+        let mut count = 0;
+        let total = keys.len() as u64;
+        for (i, key) in keys.into_iter().enumerate() {
+            CancellationToken::check(cancel)?;
+            Progress::report(params.progress.as_ref(), i as u64 + 1, total);
+            let val = if params.raw {
+                self.get_raw(&key)?
+            } else {
+                self.get(&key)?
+            };
+            // the key may have been removed since we collected it
+            if let Some(val) = val {
+                table.add(&key, &val)?;
+                count += 1;
+            }
+        }
+        table.finish()?;
+        if let Some(key) = &self.0.config.export_signing_key {
+            signing::write_manifest(key, path)?;
+        }
+        Ok(count)
+    }
+
+    /// Imports the entries of a table file in the LevelDB table format (e.g., as written by
+    /// [Self::export_sst]), overwriting existing keys. Only uncompressed tables are supported, and deletion
+    /// markers are ignored. Returns the number of entries imported.
+    ///
+    /// If [crate::Config::export_signing_key] is set, the table is first verified against its manifest (see
+    /// [Self::export_sst]), and nothing is imported if the manifest is missing or does not match.
+    ///
+    /// Note: this is not atomic, failing midway leaves the keys imported so far in the store
+    pub fn import_sst(&self, path: impl AsRef<Path>, params: SstParams) -> Result<usize> {
+        let path = path.as_ref();
+        if let Some(key) = &self.0.config.export_signing_key {
+            signing::verify_manifest(key, path)?;
+        }
+        let mut count = 0;
+        for_each_in_table(path, |user_key, suffix, v| {
+            CancellationToken::check(params.cancel.as_ref())?;
+            match suffix as u8 {
+                VALUE_TYPE_VALUE => {}
+                VALUE_TYPE_DELETION => return Ok(()),
+                t => return Err(malformed(format!("unsupported value type {t}"))),
+            }
+            if params.raw {
+                self.set_raw(user_key, v)?;
+            } else {
+                self.set(user_key, v)?;
+            }
+            count += 1;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    /// Returns the store generation that a table written by [Self::export_sst] was stamped with (the highest
+    /// sequence number in the table), or `None` if the table is empty. A store whose
+    /// [generation](Self::generation) is still the same has not been modified since the export began
+    pub fn sst_generation(path: impl AsRef<Path>) -> Result<Option<u64>> {
+        let mut generation = None;
+        for_each_in_table(path.as_ref(), |_, suffix, _| {
+            generation = generation.max(Some(suffix >> 8));
+            Ok(())
+        })?;
+        Ok(generation)
+    }
+}
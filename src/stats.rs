@@ -1,12 +1,16 @@
 use std::{
     fmt::Display,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::{Duration, Instant},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use parking_lot::Mutex;
 
-use crate::{router::ShardRouter, shard::HEADER_SIZE};
+use crate::{
+    collisions::CollisionLog, compression::CompressionCounters, generation::GenerationLease,
+    maintenance::MaintenanceObserverSlot, router::ShardRouter, shard::HEADER_SIZE,
+    soft_limits::SoftLimitState,
+};
 
 #[derive(Default, Debug, Clone)]
 pub struct Stats {
@@ -25,6 +29,11 @@ pub struct Stats {
     pub num_negative_lookups: usize,
     pub num_removals: usize,
     pub num_collisions: usize,
+    /// lookups that read a key with the same signature as the requested key (but a different key), see
+    /// [crate::CandyStore::collision_log]
+    pub num_lookup_collisions: usize,
+    /// same as `num_lookup_collisions`, but while resolving list indices to their items
+    pub num_list_chain_collisions: usize,
 
     pub num_read_ops: usize,
     pub num_read_bytes: usize,
@@ -67,14 +76,99 @@ impl Stats {
 impl Display for Stats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, 
-            "sh={} [sp={} com={}] [occ={} wst={}] [ins={} updt={} +lkup={} -lkup={} rem={} coll={}] R={}/{}b W={}/{}b",
+            "sh={} [sp={} com={}] [occ={} wst={}] [ins={} updt={} +lkup={} -lkup={} rem={} coll={}/{}/{}] R={}/{}b W={}/{}b",
             self.num_shards, self.num_splits, self.num_compactions, self.occupied_bytes, self.wasted_bytes, 
             self.num_inserts, self.num_updates, self.num_positive_lookups, self.num_negative_lookups, 
-            self.num_removals, self.num_collisions, self.num_read_ops, self.num_read_bytes, self.num_write_ops, 
+            self.num_removals, self.num_collisions, self.num_lookup_collisions, self.num_list_chain_collisions,
+            self.num_read_ops, self.num_read_bytes, self.num_write_ops, 
             self.num_write_bytes)
     }
 }
 
+/// Bytes written by the store over a time window, see [crate::CandyStore::write_amplification]. Only the data
+/// sections of the shard files are accounted for: the rows (index) are memory-mapped and written back by the
+/// kernel in whole pages
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct WriteAmplification {
+    /// the time window the counters cover
+    pub window: Duration,
+    /// bytes of the keys and values the store was asked to write (including the internal entries of lists,
+    /// queues, etc.), before compression
+    pub logical_bytes: u64,
+    /// bytes written for new or updated entries, as stored (after key prefixes and compression)
+    pub entry_bytes: u64,
+    /// bytes rewritten by shard splits and merges
+    pub split_bytes: u64,
+    /// bytes rewritten by compactions
+    pub compaction_bytes: u64,
+}
+
+impl WriteAmplification {
+    pub fn physical_bytes(&self) -> u64 {
+        self.entry_bytes + self.split_bytes + self.compaction_bytes
+    }
+    /// physical bytes per logical byte, or 0 if nothing was written
+    pub fn ratio(&self) -> f64 {
+        if self.logical_bytes == 0 {
+            0.0
+        } else {
+            self.physical_bytes() as f64 / self.logical_bytes as f64
+        }
+    }
+    /// Returns the counters of the window between `earlier` and `self`, both returned by
+    /// [crate::CandyStore::write_amplification] of the same store (and with no [crate::CandyStore::clear] in
+    /// between)
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            window: self.window.saturating_sub(earlier.window),
+            logical_bytes: self.logical_bytes.saturating_sub(earlier.logical_bytes),
+            entry_bytes: self.entry_bytes.saturating_sub(earlier.entry_bytes),
+            split_bytes: self.split_bytes.saturating_sub(earlier.split_bytes),
+            compaction_bytes: self.compaction_bytes.saturating_sub(earlier.compaction_bytes),
+        }
+    }
+}
+
+/// What a write to a shard file is for, to account for write amplification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteKind {
+    Entry,
+    Split,
+    Compaction,
+}
+
+#[derive(Debug)]
+pub(crate) struct WriteCounters {
+    since: Mutex<Instant>,
+    logical_bytes: AtomicU64,
+    entry_bytes: AtomicU64,
+    split_bytes: AtomicU64,
+    compaction_bytes: AtomicU64,
+}
+
+impl Default for WriteCounters {
+    fn default() -> Self {
+        Self {
+            since: Mutex::new(Instant::now()),
+            logical_bytes: Default::default(),
+            entry_bytes: Default::default(),
+            split_bytes: Default::default(),
+            compaction_bytes: Default::default(),
+        }
+    }
+}
+
+/// Contention counters of a single slot of the keyed locks pool (see [crate::CandyStore::keyed_lock_stats])
+#[derive(Default, Debug, Clone)]
+pub struct KeyedLockStats {
+    pub slot: usize,
+    pub num_acquisitions: usize,
+    /// number of acquisitions that had to wait for the lock
+    pub num_contended: usize,
+    /// number of threads waiting on the lock right now
+    pub num_waiters: usize,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct CyclicArr<T, const N: usize> {
     idx: usize,
@@ -135,6 +229,9 @@ fn test_cyclic_arr() {
 
 #[derive(Debug, Default)]
 pub struct InternalStats {
+    pub(crate) maintenance_observer: MaintenanceObserverSlot,
+    pub(crate) soft_limits: SoftLimitState,
+
     pub(crate) num_splits: AtomicUsize,
     pub(crate) num_compactions: AtomicUsize,
     pub(crate) last_compaction_stats: Mutex<CyclicArr<(Duration, u64, u64), 8>>,
@@ -144,6 +241,9 @@ pub struct InternalStats {
     pub(crate) num_positive_lookups: AtomicUsize,
     pub(crate) num_negative_lookups: AtomicUsize,
     pub(crate) num_collisions: AtomicUsize,
+    pub(crate) num_lookup_collisions: AtomicUsize,
+    pub(crate) num_list_chain_collisions: AtomicUsize,
+    pub(crate) collision_log: CollisionLog,
 
     pub(crate) num_read_ops: AtomicUsize,
     pub(crate) num_read_bytes: AtomicUsize,
@@ -155,10 +255,62 @@ pub struct InternalStats {
     pub(crate) entries_under_8k: AtomicUsize,
     pub(crate) entries_under_32k: AtomicUsize,
     pub(crate) entries_over_32k: AtomicUsize,
+
+    pub(crate) write_counters: WriteCounters,
+    pub(crate) compression: CompressionCounters,
+    #[cfg(feature = "metrics")]
+    pub(crate) lock_waits: crate::lock_metrics::LockWaits,
+    #[cfg(feature = "metrics")]
+    pub(crate) queue_activity: crate::queue_metrics::QueueActivity,
+
+    // see CandyStore::generation. not a statistic, so it is not reset by clear()
+    pub(crate) generation: AtomicU64,
+    pub(crate) generation_lease: GenerationLease,
+    // the time of the last successful fsync of a shard file (in milliseconds since the epoch, 0 if none), see
+    // CandyStore::health. not reset by clear() either
+    pub(crate) last_fsync_ms: AtomicU64,
 }
 
 impl InternalStats {
-    pub(crate) fn add_entry(&self, sz: usize) {
+    pub(crate) fn bump_generation(&self) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.generation_lease.generation_bumped(generation);
+    }
+
+    pub(crate) fn report_fsync(&self) {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.last_fsync_ms.fetch_max(now_ms, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_logical_write(&self, sz: usize) {
+        self.write_counters.logical_bytes.fetch_add(sz as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_physical_write(&self, sz: usize, kind: WriteKind) {
+        let counter = match kind {
+            WriteKind::Entry => &self.write_counters.entry_bytes,
+            WriteKind::Split => &self.write_counters.split_bytes,
+            WriteKind::Compaction => &self.write_counters.compaction_bytes,
+        };
+        counter.fetch_add(sz as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_amplification(&self) -> WriteAmplification {
+        let wc = &self.write_counters;
+        WriteAmplification {
+            window: wc.since.lock().elapsed(),
+            logical_bytes: wc.logical_bytes.load(Ordering::Relaxed),
+            entry_bytes: wc.entry_bytes.load(Ordering::Relaxed),
+            split_bytes: wc.split_bytes.load(Ordering::Relaxed),
+            compaction_bytes: wc.compaction_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn add_entry(&self, sz: usize, kind: WriteKind) {
+        self.add_physical_write(sz, kind);
         self.num_write_bytes.fetch_add(sz, Ordering::Relaxed);
         self.num_write_ops.fetch_add(1, Ordering::Relaxed);
         match sz {
@@ -198,6 +350,9 @@ impl InternalStats {
         self.num_positive_lookups.store(0, Ordering::SeqCst);
         self.num_negative_lookups.store(0, Ordering::SeqCst);
         self.num_collisions.store(0, Ordering::SeqCst);
+        self.num_lookup_collisions.store(0, Ordering::SeqCst);
+        self.num_list_chain_collisions.store(0, Ordering::SeqCst);
+        self.collision_log.clear();
 
         self.num_read_ops.store(0, Ordering::SeqCst);
         self.num_read_bytes.store(0, Ordering::SeqCst);
@@ -209,6 +364,17 @@ impl InternalStats {
         self.entries_under_8k.store(0, Ordering::SeqCst);
         self.entries_under_32k.store(0, Ordering::SeqCst);
         self.entries_over_32k.store(0, Ordering::SeqCst);
+
+        *self.write_counters.since.lock() = Instant::now();
+        self.write_counters.logical_bytes.store(0, Ordering::SeqCst);
+        self.write_counters.entry_bytes.store(0, Ordering::SeqCst);
+        self.write_counters.split_bytes.store(0, Ordering::SeqCst);
+        self.write_counters.compaction_bytes.store(0, Ordering::SeqCst);
+        self.compression.clear();
+        #[cfg(feature = "metrics")]
+        self.lock_waits.clear();
+        #[cfg(feature = "metrics")]
+        self.queue_activity.clear();
     }
 
     pub(crate) fn fill_stats(&self, stats: &mut Stats) {
@@ -230,6 +396,8 @@ impl InternalStats {
         stats.num_positive_lookups = self.num_positive_lookups.load(Ordering::Relaxed);
         stats.num_negative_lookups = self.num_negative_lookups.load(Ordering::Relaxed);
         stats.num_collisions = self.num_collisions.load(Ordering::Relaxed);
+        stats.num_lookup_collisions = self.num_lookup_collisions.load(Ordering::Relaxed);
+        stats.num_list_chain_collisions = self.num_list_chain_collisions.load(Ordering::Relaxed);
 
         stats.num_read_ops = self.num_read_ops.load(Ordering::Relaxed);
         stats.num_read_bytes = self.num_read_bytes.load(Ordering::Relaxed);
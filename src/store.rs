@@ -1,24 +1,47 @@
-use anyhow::{anyhow, bail, ensure};
 use bytemuck::{bytes_of, from_bytes};
 use fslock::LockFile;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use std::{
+    collections::BTreeSet,
+    fs::File,
+    ops::Range,
+    os::fd::{AsRawFd, OwnedFd},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::Instant,
 };
 
 use crate::{
+    checksums::{self, ChecksumReport},
+    compression::CompressionDict,
+    expiry::ExpiryState,
     hashing::{HashSeed, PartedHash},
+    interning::KeyInterner,
+    journal::JournalState,
+    key_history::KeyHistories,
+    key_prefixes::KeyPrefixes,
+    list_audit::ListAudits,
+    lists::KeyedLock,
+    pinning::PinnedHeaders,
     router::ShardRouter,
-    shard::{CompactionThreadPool, InsertMode, InsertStatus, KVPair},
-    Stats, MAX_KEY_SIZE, MAX_TOTAL_VALUE_SIZE,
+    sessions::ExpirationSubscribers,
+    shard::{CompactionThreadPool, InsertMode, InsertStatus, KVPair, PatchStatus},
+    signing::SigningKey,
+    Stats, WriteAmplification, MAX_KEY_SIZE, MAX_TOTAL_VALUE_SIZE,
 };
 use crate::{
     shard::{NUM_ROWS, ROW_WIDTH},
     stats::InternalStats,
+    validation::num_keyed_locks,
 };
 
-use crate::{CandyError, Config, Result, MAX_TOTAL_KEY_SIZE, MAX_VALUE_SIZE};
+use crate::{
+    CandyError, Config, ConfigReport, ConfigUpdate, DiskFullPolicy, DryRunReport,
+    ListRecoveryReport, Namespace, Result, SoftLimits, MAX_TOTAL_KEY_SIZE, MAX_VALUE_SIZE,
+};
 
 pub(crate) const USER_NAMESPACE: &[u8] = &[1];
 pub(crate) const TYPED_NAMESPACE: &[u8] = &[2];
@@ -27,12 +50,62 @@ pub(crate) const ITEM_NAMESPACE: &[u8] = &[4];
 pub(crate) const CHAIN_NAMESPACE: u8 = 5;
 pub(crate) const QUEUE_NAMESPACE: &[u8] = &[6];
 pub(crate) const QUEUE_ITEM_NAMESPACE: &[u8] = &[7];
+pub(crate) const EPHEMERAL_NAMESPACE: &[u8] = &[8];
+pub(crate) const SESSIONS_NAMESPACE: &[u8] = &[9];
+pub(crate) const DEDUP_NAMESPACE: &[u8] = &[10];
+pub(crate) const BLOB_NAMESPACE: &[u8] = &[11];
+pub(crate) const GRAPH_NAMESPACE: &[u8] = &[12];
+pub(crate) const INVERTED_INDEX_NAMESPACE: &[u8] = &[13];
+pub(crate) const GEO_NAMESPACE: &[u8] = &[14];
+pub(crate) const NUMERIC_INDEX_NAMESPACE: &[u8] = &[15];
+pub(crate) const INTERNED_NAMESPACE: &[u8] = &[16];
+pub(crate) const INTERN_TABLE_NAMESPACE: &[u8] = &[17];
+pub(crate) const LIST_CURSOR_NAMESPACE: &[u8] = &[18];
+pub(crate) const TYPE_REGISTRY_NAMESPACE: &[u8] = &[19];
+pub(crate) const LIST_AUDIT_NAMESPACE: &[u8] = &[20];
+pub(crate) const POP_TOKEN_NAMESPACE: &[u8] = &[21];
+pub(crate) const KEY_HISTORY_NAMESPACE: &[u8] = &[22];
+pub(crate) const SCOPED_TYPED_NAMESPACE: &[u8] = &[23];
+pub(crate) const EXPIRY_NAMESPACE: &[u8] = &[24];
+pub(crate) const JOURNAL_NAMESPACE: &[u8] = &[25];
+
+// a config value that can be changed while the store is open, see CandyStore::update_config
+#[derive(Debug)]
+pub(crate) struct Tunable<T>(RwLock<T>);
+
+impl<T: Copy> Tunable<T> {
+    fn new(val: T) -> Self {
+        Self(RwLock::new(val))
+    }
+    pub(crate) fn get(&self) -> T {
+        *self.0.read()
+    }
+    fn set(&self, val: T) {
+        *self.0.write() = val;
+    }
+}
+
+// the store's directory, which only changes when the store is relocated, see CandyStore::relocate
+#[derive(Debug)]
+pub(crate) struct DirPath(RwLock<PathBuf>);
+
+impl DirPath {
+    pub(crate) fn get(&self) -> PathBuf {
+        self.0.read().clone()
+    }
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.read().join(path)
+    }
+    pub(crate) fn set(&self, path: PathBuf) {
+        *self.0.write() = path;
+    }
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(crate) struct InternalConfig {
-    pub dir_path: PathBuf,
+    pub dir_path: DirPath,
     pub max_shard_size: u32,
-    pub min_compaction_threashold: u32,
+    pub min_compaction_threashold: Tunable<u32>,
     pub hash_seed: HashSeed,
     pub expected_number_of_keys: usize,
     pub max_concurrent_list_ops: u32,
@@ -40,6 +113,26 @@ pub(crate) struct InternalConfig {
     pub clear_on_unsupported_version: bool,
     pub mlock_headers: bool,
     pub num_compaction_threads: usize,
+    pub background_split_threshold: Tunable<Option<f64>>,
+    pub key_prefixes: Option<Arc<KeyPrefixes>>,
+    // set on open or when a dictionary is trained
+    pub compression_dict: OnceLock<CompressionDict>,
+    pub dedup_min_value_size: Tunable<usize>,
+    pub strict_typed_values: bool,
+    pub list_item_metadata: bool,
+    pub list_item_tags: bool,
+    pub intern_keys_longer_than: Option<usize>,
+    pub yield_every: Tunable<Option<usize>>,
+    pub lazy_open: bool,
+    pub preallocate_size: Tunable<u64>,
+    pub preallocate_increment: Tunable<u64>,
+    pub disk_full_policy: Tunable<DiskFullPolicy>,
+    pub export_signing_key: Option<SigningKey>,
+    pub collision_log_capacity: usize,
+    pub uncompressed_namespaces: Vec<Namespace>,
+    pub shard_checksums: bool,
+    pub soft_limits: SoftLimits,
+    pub list_journal: bool,
     #[cfg(feature = "flush_aggregation")]
     pub flush_aggregation_delay: Option<std::time::Duration>,
 }
@@ -98,15 +191,126 @@ impl GetOrCreateStatus {
         }
     }
 }
-/// The CandyStore object. Note that it's fully sync'ed, so can be shared between threads using `Arc`
-pub struct CandyStore {
-    pub(crate) root: ShardRouter,
+// the canonical paths of the stores currently open in this process. the lock file alone does not guard against
+// opening the same directory twice in-process on all platforms (e.g., flock is emulated with per-process locks
+// on NFS), and doing so corrupts the shards
+static OPEN_DIRS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+struct DirRegistration(PathBuf);
+
+impl Drop for DirRegistration {
+    fn drop(&mut self) {
+        OPEN_DIRS.lock().remove(&self.0);
+    }
+}
+
+// held by all handles of a store, so that the directory is released when the last one is dropped
+pub(crate) struct DirLock {
+    // the lock file is released before the registration, so that a store reopened right after the
+    // registration is removed would not find the lock file still held
+    _lockfile: LockFile,
+    _registration: DirRegistration,
+    // the directory handle passed to `CandyStore::open_at`, which the store's path refers to
+    _dirfd: Option<OwnedFd>,
+}
+
+impl DirLock {
+    pub(crate) fn acquire(dir_path: &Path, dirfd: Option<OwnedFd>) -> Result<Self> {
+        let canonical = dir_path.canonicalize()?;
+        if !OPEN_DIRS.lock().insert(canonical.clone()) {
+            return Err(CandyError::Busy(format!(
+                "{dir_path:?} is already open in this process (share the open store's handle instead)"
+            )));
+        }
+        let registration = DirRegistration(canonical);
+
+        let lockfilename = dir_path.join(".lock");
+        // taking the lock writes our pid into the file, which must not be redirected outside the directory
+        if std::fs::symlink_metadata(&lockfilename).is_ok_and(|md| md.is_symlink()) {
+            return Err(CandyError::InvalidArgument(format!(
+                "{lockfilename:?} is a symlink"
+            )));
+        }
+        let mut lockfile = LockFile::open(&lockfilename)?;
+        if !lockfile.try_lock_with_pid()? {
+            let (pid, comm, stat) = if let Ok(mut pid) = std::fs::read_to_string(&lockfilename) {
+                // this may fail on non-linux OSs, but we default to "?" anyway
+                pid = pid.trim().to_owned();
+                let exe: String = std::fs::read_link(format!("/proc/{pid}/exe"))
+                    .unwrap_or("?".into())
+                    .to_string_lossy()
+                    .to_string()
+                    .to_owned();
+
+                let stat: String = std::fs::read_link(format!("/proc/{pid}/stat"))
+                    .unwrap_or("?".into())
+                    .to_string_lossy()
+                    .to_string()
+                    .to_owned();
+
+                (pid, exe, stat)
+            } else {
+                ("?".into(), "?".into(), "?".into())
+            };
+
+            return Err(CandyError::Busy(format!(
+                "Lock file {lockfilename:?} is held by pid {:?} exe={:?} stat {:?}",
+                pid, comm, stat
+            )));
+        }
+
+        Ok(Self {
+            _lockfile: lockfile,
+            _registration: registration,
+            _dirfd: dirfd,
+        })
+    }
+
+    // whether the store was opened with `CandyStore::open_at`, whose directory is only known by its handle
+    pub(crate) fn is_dirfd(&self) -> bool {
+        self._dirfd.is_some()
+    }
+
+    // registers the directory under its new path once it was renamed. the lock file moves along with it
+    pub(crate) fn renamed(&mut self, new_path: &Path) -> Result<()> {
+        let canonical = new_path.canonicalize()?;
+        if !OPEN_DIRS.lock().insert(canonical.clone()) {
+            return Err(CandyError::Busy(format!(
+                "{new_path:?} is already open in this process"
+            )));
+        }
+        self._registration = DirRegistration(canonical);
+        Ok(())
+    }
+}
+
+/// The CandyStore object. Note that it's fully sync'ed, so can be shared between threads. It is also cheaply
+/// cloneable: clones are handles to the same underlying store (all state is kept behind an `Arc`), so there's
+/// no need to wrap it in an `Arc` yourself. The store is closed when the last handle is dropped.
+#[derive(Clone)]
+pub struct CandyStore(pub(crate) Arc<StoreInner>);
+
+// the state shared by all handles of a store, dropped (and closed) along with the last handle
+pub(crate) struct StoreInner {
+    pub(crate) root: Arc<ShardRouter>,
     pub(crate) config: Arc<InternalConfig>,
     // locks for complicated operations
     pub(crate) keyed_locks_mask: u32,
-    pub(crate) keyed_locks: Vec<Mutex<()>>,
-    _lockfile: LockFile,
-    stats: Arc<InternalStats>,
+    pub(crate) keyed_locks: Box<[KeyedLock]>,
+    // replaced when the store is relocated to another volume
+    pub(crate) dir_lock: Mutex<DirLock>,
+    pub(crate) stats: Arc<InternalStats>,
+    pub(crate) pinned: PinnedHeaders,
+    pub(crate) interner: KeyInterner,
+    pub(crate) expirations: ExpirationSubscribers,
+    list_recovery_report: OnceLock<ListRecoveryReport>,
+    pub(crate) checksum_report: Option<ChecksumReport>,
+    pub(crate) list_audits: ListAudits,
+    pub(crate) key_histories: KeyHistories,
+    pub(crate) journal: JournalState,
+    pub(crate) expiry: ExpiryState,
+    // set once a write ran out of disk space, see DiskFullPolicy::ReadOnly
+    degraded: AtomicBool,
     //threadpool: Arc<CompactionThreadPool>,
 }
 
@@ -123,7 +327,7 @@ pub struct CandyStoreIterator<'a> {
 }
 
 impl<'a> CandyStoreIterator<'a> {
-    fn new(store: &'a CandyStore, raw: bool, include_val: bool) -> Self {
+    pub(crate) fn new(store: &'a CandyStore, raw: bool, include_val: bool) -> Self {
         Self {
             store,
             shard_selector: 0,
@@ -160,7 +364,7 @@ impl<'a> Iterator for CandyStoreIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         while self.shard_selector < ShardRouter::END_OF_SHARDS {
-            let res = self.store.root.shared_op(self.shard_selector, |sh| {
+            let res = self.store.0.root.shared_op(self.shard_selector, |sh| {
                 while self.row_idx < NUM_ROWS {
                     let row_idx = self.row_idx;
                     let entry_idx = self.entry_idx;
@@ -175,10 +379,12 @@ impl<'a> Iterator for CandyStoreIterator<'a> {
                         continue;
                     };
                     if self.raw {
-                        return Ok((sh.span.start, Some((k, v))));
+                        return Ok((sh.span.start, Some((k, v, false))));
                     } else if k.ends_with(USER_NAMESPACE) {
                         k.truncate(k.len() - USER_NAMESPACE.len());
-                        return Ok((sh.span.start, Some((k, v))));
+                        return Ok((sh.span.start, Some((k, v, false))));
+                    } else if k.ends_with(INTERNED_NAMESPACE) {
+                        return Ok((sh.span.start, Some((k, v, true))));
                     }
                 }
 
@@ -190,10 +396,15 @@ impl<'a> Iterator for CandyStoreIterator<'a> {
             match res {
                 Ok((shard_selector, kv)) => {
                     self.shard_selector = shard_selector;
-                    if let Some(kv) = kv {
-                        return Some(Ok(kv));
+                    match kv {
+                        // resolved outside of the shard's lock, since the interning table may live in
+                        // another shard
+                        Some((k, v, true)) => {
+                            return Some(self.store.resolve_interned_key(&k).map(|k| (k, v)))
+                        }
+                        Some((k, v, false)) => return Some(Ok((k, v))),
+                        None => {} // continue
                     }
-                    // continue
                 }
                 Err(e) => return Some(Err(e)),
             }
@@ -207,124 +418,345 @@ impl CandyStore {
     /// Opens or creates a new CandyStore.
     /// * dir_path - the directory where shards will be kept
     /// * config - the configuration options for the store
+    ///
+    /// Fails with [CandyError::InvalidArgument] if the configuration has errors, see [Config::validate]
     pub fn open(dir_path: impl AsRef<Path>, config: Config) -> Result<Self> {
+        Self::open_with_report(dir_path, config).map(|(store, _)| store)
+    }
+
+    /// Same as [Self::open], but also returns the configuration's [ConfigReport], i.e., its warnings and the
+    /// effective values derived from it
+    pub fn open_with_report(
+        dir_path: impl AsRef<Path>,
+        config: Config,
+    ) -> Result<(Self, ConfigReport)> {
+        Self::open_impl(dir_path.as_ref(), None, config)
+    }
+
+    /// Opens or creates a store in an already-opened directory, e.g., one handed to a sandboxed process
+    /// (systemd's `DynamicUser`, seccomp filters, etc.) that cannot resolve the directory's path itself.
+    /// The store takes ownership of the handle and keeps it open for as long as the store is open.
+    ///
+    /// The store's files are accessed relative to the handle (through `/proc/self/fd`, which must be
+    /// mounted), so renaming or replacing the directory's path does not affect the store. The store never
+    /// follows symlinks when opening its files, so a symlink planted in the directory cannot redirect
+    /// writes outside of it (this holds for stores opened with [Self::open] as well)
+    pub fn open_at(dirfd: impl Into<OwnedFd>, config: Config) -> Result<Self> {
+        let dirfd = dirfd.into();
+        if !File::from(dirfd.try_clone()?).metadata()?.is_dir() {
+            return Err(CandyError::InvalidArgument(
+                "the handle passed to open_at is not a directory".into(),
+            ));
+        }
+        let dir_path = PathBuf::from(format!("/proc/self/fd/{}", dirfd.as_raw_fd()));
+        if !dir_path.is_dir() {
+            return Err(CandyError::InvalidArgument(
+                "open_at requires /proc to be mounted".into(),
+            ));
+        }
+        Self::open_impl(&dir_path, Some(dirfd), config).map(|(store, _)| store)
+    }
+
+    fn open_impl(
+        dir_path: &Path,
+        dirfd: Option<OwnedFd>,
+        config: Config,
+    ) -> Result<(Self, ConfigReport)> {
+        let report = config.validate();
+        if !report.is_ok() {
+            return Err(CandyError::InvalidArgument(report.errors.join("; ")));
+        }
+        let maintenance_thread_nice = config.maintenance_thread_nice;
+        let list_recovery = config.list_recovery;
         let config = Arc::new(InternalConfig {
-            dir_path: dir_path.as_ref().to_path_buf(),
+            dir_path: DirPath(RwLock::new(dir_path.to_path_buf())),
             expected_number_of_keys: config.expected_number_of_keys,
             hash_seed: config.hash_seed,
             max_concurrent_list_ops: config.max_concurrent_list_ops,
             max_shard_size: config.max_shard_size,
-            min_compaction_threashold: config.min_compaction_threashold,
+            min_compaction_threashold: Tunable::new(config.min_compaction_threashold),
             truncate_up: config.truncate_up,
             clear_on_unsupported_version: config.clear_on_unsupported_version,
             mlock_headers: config.mlock_headers,
             num_compaction_threads: config.num_compaction_threads,
+            background_split_threshold: Tunable::new(config.background_split_threshold),
+            key_prefixes: KeyPrefixes::new(&config.key_prefixes)?.map(Arc::new),
+            compression_dict: OnceLock::new(),
+            dedup_min_value_size: Tunable::new(config.dedup_min_value_size),
+            strict_typed_values: config.strict_typed_values,
+            list_item_metadata: config.list_item_metadata,
+            list_item_tags: config.list_item_tags,
+            intern_keys_longer_than: config.intern_keys_longer_than,
+            yield_every: Tunable::new(config.yield_every),
+            lazy_open: config.lazy_open,
+            preallocate_size: Tunable::new(config.preallocate_size),
+            preallocate_increment: Tunable::new(config.preallocate_increment),
+            disk_full_policy: Tunable::new(config.disk_full_policy),
+            export_signing_key: config.export_signing_key.as_deref().map(SigningKey::new),
+            collision_log_capacity: config.collision_log_capacity,
+            uncompressed_namespaces: config.uncompressed_namespaces.clone(),
+            shard_checksums: config.shard_checksums,
+            soft_limits: config.soft_limits,
+            list_journal: config.list_journal,
             #[cfg(feature = "flush_aggregation")]
             flush_aggregation_delay: config.flush_aggregation_delay,
         });
 
         std::fs::create_dir_all(dir_path)?;
-        let lockfilename = config.dir_path.join(".lock");
-        let mut lockfile = LockFile::open(&lockfilename)?;
-        if !lockfile.try_lock_with_pid()? {
-            let (pid, comm, stat) = if let Ok(mut pid) = std::fs::read_to_string(&lockfilename) {
-                // this may fail on non-linux OSs, but we default to "?" anyway
-                pid = pid.trim().to_owned();
-                let exe: String = std::fs::read_link(format!("/proc/{pid}/exe"))
-                    .unwrap_or("?".into())
-                    .to_string_lossy()
-                    .to_string()
-                    .to_owned();
+        let dir_lock = DirLock::acquire(&config.dir_path.get(), dirfd)?;
 
-                let stat: String = std::fs::read_link(format!("/proc/{pid}/stat"))
-                    .unwrap_or("?".into())
-                    .to_string_lossy()
-                    .to_string()
-                    .to_owned();
-
-                (pid, exe, stat)
-            } else {
-                ("?".into(), "?".into(), "?".into())
-            };
-
-            bail!(
-                "Lock file {lockfilename:?} is held by pid {:?} exe={:?} stat {:?}",
-                pid,
-                comm,
-                stat
-            );
+        if let Some(dict) = CompressionDict::load(&config.dir_path.get())? {
+            _ = config.compression_dict.set(dict);
         }
 
-        let mut num_keyed_locks = config.max_concurrent_list_ops.max(4);
-        if !num_keyed_locks.is_power_of_two() {
-            num_keyed_locks = 1 << (num_keyed_locks.ilog2() + 1);
-        }
+        // before any shard file is touched
+        let checksum_report =
+            checksums::verify_and_remove_manifest(&config.dir_path.get(), config.shard_checksums)?;
+        let checksum_report = config.shard_checksums.then_some(checksum_report);
+
+        let num_keyed_locks = num_keyed_locks(config.max_concurrent_list_ops);
 
         let mut keyed_locks = vec![];
         for _ in 0..num_keyed_locks {
-            keyed_locks.push(Mutex::new(()));
+            keyed_locks.push(KeyedLock::default());
         }
 
         let stats = Arc::new(InternalStats::default());
-        let threadpool = Arc::new(CompactionThreadPool::new(config.num_compaction_threads));
-        let root = ShardRouter::new(config.clone(), stats.clone(), threadpool.clone())?;
-
-        Ok(Self {
+        stats
+            .generation
+            .store(stats.generation_lease.acquire(&config)?, Ordering::SeqCst);
+        let threadpool = Arc::new(CompactionThreadPool::new(
+            config.num_compaction_threads,
+            maintenance_thread_nice,
+        ));
+        let root = Arc::new(ShardRouter::new(
+            config.clone(),
+            stats.clone(),
+            threadpool.clone(),
+        )?);
+
+        let store = Self(Arc::new(StoreInner {
             config,
             root,
             keyed_locks_mask: num_keyed_locks - 1,
-            keyed_locks,
-            _lockfile: lockfile,
+            keyed_locks: keyed_locks.into(),
+            dir_lock: Mutex::new(dir_lock),
             stats,
+            pinned: Default::default(),
+            interner: Default::default(),
+            expirations: Default::default(),
+            list_recovery_report: OnceLock::new(),
+            checksum_report,
+            list_audits: Default::default(),
+            key_histories: Default::default(),
+            journal: Default::default(),
+            expiry: Default::default(),
+            degraded: Default::default(),
             //threadpool,
-        })
+        }));
+
+        // torn list operations (and write batches) are rolled back before the lists are checked
+        store.recover_journal()?;
+        if let Some(policy) = list_recovery {
+            _ = store
+                .0
+                .list_recovery_report
+                .set(store.recover_torn_list_ops(policy)?);
+        }
+        store.load_list_audits()?;
+        store.load_key_histories()?;
+        store.load_expiry_state()?;
+        store.remove_leftover_ephemerals()?;
+
+        Ok((store, report))
+    }
+
+    /// Returns a new handle to this store. This is the same as `clone()`, and is provided for readability:
+    /// handles are cheap (a single reference-count increment) and can be moved freely to other threads
+    pub fn handle(&self) -> Self {
+        self.clone()
+    }
+
+    // a handle that does not keep the store open, for background threads
+    pub(crate) fn downgrade(&self) -> Weak<StoreInner> {
+        Arc::downgrade(&self.0)
+    }
+
+    pub(crate) fn upgrade(weak: &Weak<StoreInner>) -> Option<Self> {
+        weak.upgrade().map(Self)
+    }
+
+    /// Returns the store's generation, which is bumped by every modification (including those made by lists,
+    /// queues, etc.), so external caches can cheaply check whether anything has changed since they last looked:
+    /// if the generation is unchanged, so is the store's content. Note that the opposite does not hold, e.g.,
+    /// removing a key and setting it back to the same value bumps the generation twice.
+    ///
+    /// The generation keeps increasing across reopens, even after a crash: the store persists a bound on the
+    /// generations it hands out (rewriting it once every few million modifications), and resumes past it, or
+    /// from the current time in microseconds, if that's later
+    pub fn generation(&self) -> u64 {
+        self.0.stats.generation.load(Ordering::SeqCst)
+    }
+
+    /// Returns the torn list operations that were found (and handled) when the store was opened, or None if
+    /// [Config::list_recovery] was not set
+    pub fn list_recovery_report(&self) -> Option<ListRecoveryReport> {
+        self.0.list_recovery_report.get().copied()
     }
 
     /// returns the directory where shards are kept
-    pub fn get_shards_directory(&self) -> &Path {
-        &self.config.dir_path
+    pub fn get_shards_directory(&self) -> PathBuf {
+        self.0.config.dir_path.get()
+    }
+
+    /// Allocates disk space for about `bytes` more of data, failing early (with an IO error of kind
+    /// [std::io::ErrorKind::StorageFull]) if the volume does not have enough free space, e.g., before starting a
+    /// big import. Keys are spread uniformly across shards, so each shard allocates its share past its current
+    /// write offset (capped at `max_shard_size`). The space is not set aside for any particular write, it just
+    /// makes the upcoming writes not run out of space
+    pub fn reserve(&self, bytes: u64) -> Result<()> {
+        let num_shards = self.0.root.call_on_all_shards(|_| Ok(()))?.len() as u64;
+        let per_shard = bytes.div_ceil(num_shards);
+        self.0.root.call_on_all_shards(|sh| sh.reserve(per_shard))?;
+        Ok(())
     }
 
     /// Syncs all in-memory changes of all shards to disk. Concurrent changes are allowed while
     /// flushing, and may result in partially-sync'ed store. Use sparingly, as this is a costly operaton.
     pub fn flush(&self) -> Result<()> {
-        self.root.call_on_all_shards(|sh| sh.flush())?;
+        self.write_back_pinned_headers()?;
+        self.0.root.call_on_all_shards(|sh| sh.flush())?;
         Ok(())
     }
 
-    /// Clears the store (erasing all keys), and removing all shard files
+    /// Changes the given tunables of the open store, without reopening it. The new values are validated like
+    /// [Config::validate] does on open, and nothing is changed (and [CandyError::InvalidArgument] is returned)
+    /// if they have errors. Returns the validation's warnings
+    pub fn update_config(&self, update: ConfigUpdate) -> Result<Vec<String>> {
+        let c = &self.0.config;
+        // validate the tunables in the context of the settings that cannot change
+        let config = Config {
+            max_shard_size: c.max_shard_size,
+            min_compaction_threashold: update
+                .min_compaction_threashold
+                .unwrap_or(c.min_compaction_threashold.get()),
+            background_split_threshold: update
+                .background_split_threshold
+                .unwrap_or(c.background_split_threshold.get()),
+            dedup_min_value_size: update
+                .dedup_min_value_size
+                .unwrap_or(c.dedup_min_value_size.get()),
+            yield_every: update.yield_every.unwrap_or(c.yield_every.get()),
+            preallocate_size: update.preallocate_size.unwrap_or(c.preallocate_size.get()),
+            preallocate_increment: update
+                .preallocate_increment
+                .unwrap_or(c.preallocate_increment.get()),
+            disk_full_policy: update.disk_full_policy.unwrap_or(c.disk_full_policy.get()),
+            ..Default::default()
+        };
+        let report = config.validate();
+        if !report.is_ok() {
+            return Err(CandyError::InvalidArgument(report.errors.join("; ")));
+        }
+
+        c.min_compaction_threashold
+            .set(config.min_compaction_threashold);
+        c.background_split_threshold
+            .set(config.background_split_threshold);
+        c.dedup_min_value_size.set(config.dedup_min_value_size);
+        c.yield_every.set(config.yield_every);
+        c.preallocate_size.set(config.preallocate_size);
+        c.preallocate_increment.set(config.preallocate_increment);
+        c.disk_full_policy.set(config.disk_full_policy);
+        Ok(report.warnings)
+    }
+
+    /// Returns true if the store was switched to read-only mode because it ran out of disk space (see
+    /// [DiskFullPolicy::ReadOnly]), in which case all modifications fail with [CandyError::StoreFull]. The store
+    /// leaves this mode when it is reopened, or when it is cleared
+    pub fn is_degraded(&self) -> bool {
+        self.0.degraded.load(Ordering::SeqCst)
+    }
+
+    // runs a modification of the store's files, applying the disk-full policy
+    fn guard_write<T>(&self, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        if self.is_degraded() {
+            return Err(CandyError::StoreFull);
+        }
+        match op() {
+            Err(CandyError::Io(e))
+                if self.0.config.disk_full_policy.get() == DiskFullPolicy::ReadOnly
+                    && e.raw_os_error() == Some(libc::ENOSPC) =>
+            {
+                self.0.degraded.store(true, Ordering::SeqCst);
+                Err(CandyError::StoreFull)
+            }
+            res => res,
+        }
+    }
+
+    /// Clears the store (erasing all keys), and removing all shard files. Rather than removing the entries one by
+    /// one, the shard files are deleted and a fresh set of (empty) shards is created, and the in-memory state
+    /// (stats, keyed lock counters, caches of pinned headers, interned keys, etc.) is reset, so this takes time
+    /// proportional to the number of shards, regardless of the number of entries. This makes it suitable for
+    /// resetting test environments. Handles to the store remain valid, and observe the empty store
     pub fn clear(&self) -> Result<()> {
-        self.root.clear()?;
-        self.stats.clear();
+        self.0.root.clear()?;
+        self.0.degraded.store(false, Ordering::SeqCst);
+        self.0.stats.clear();
+        self.reset_keyed_lock_stats();
+        self.0.stats.bump_generation();
+        self.reset_pinned_headers();
+        self.0.interner.clear();
+        self.0.list_audits.clear();
+        self.0.key_histories.clear();
 
         Ok(())
     }
 
+    /// Reports what [Self::clear] would erase (all entries, including the internal ones used by lists, queues,
+    /// etc.), based on the store's stats, without erasing anything
+    pub fn dry_run_clear(&self) -> DryRunReport {
+        let stats = self.stats();
+        DryRunReport {
+            num_items: stats.num_entries(),
+            num_bytes: stats.data_bytes(),
+        }
+    }
+
     pub(crate) fn ensure_sizes(key: &[u8], val: &[u8]) -> Result<()> {
-        ensure!(key.len() <= MAX_KEY_SIZE, CandyError::KeyTooLong(key.len()));
-        ensure!(
-            val.len() <= MAX_VALUE_SIZE,
-            CandyError::ValueTooLong(val.len())
-        );
+        if key.len() > MAX_KEY_SIZE {
+            return Err(CandyError::KeyTooLong(key.len()));
+        }
+        if val.len() > MAX_VALUE_SIZE {
+            return Err(CandyError::ValueTooLong(val.len()));
+        }
 
         Ok(())
     }
 
-    pub(crate) fn make_user_key(&self, mut key: Vec<u8>) -> Vec<u8> {
-        key.extend_from_slice(USER_NAMESPACE);
-        key
-    }
-
     pub(crate) fn get_by_hash(&self, ph: PartedHash) -> Result<Vec<KVPair>> {
         debug_assert!(ph.is_valid());
-        self.root
-            .shared_op(ph.shard_selector(), |sh| sh.get_by_hash(ph))
+        self.isolated_read(ph, &[], || {
+            self.0
+                .root
+                .shared_op(ph.shard_selector(), |sh| sh.get_by_hash(ph))
+        })
     }
 
     pub(crate) fn get_raw(&self, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
-        self.root
-            .shared_op(ph.shard_selector(), |sh| sh.get(ph, &full_key))
+        self.get_with_hash(
+            PartedHash::new(&self.0.config.hash_seed, full_key),
+            full_key,
+        )
+    }
+
+    pub(crate) fn get_with_hash(&self, ph: PartedHash, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.isolated_read(ph, full_key, || {
+            self.0
+                .root
+                .shared_op(ph.shard_selector(), |sh| sh.get(ph, full_key))
+        })
     }
 
     /// Gets the value of a key from the store. If the key does not exist, `None` will be returned.
@@ -335,7 +767,125 @@ impl CandyStore {
 
     /// Same as [Self::get] but takes an owned key
     pub fn owned_get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        self.get_raw(&self.make_user_key(key))
+        if self.key_expired(&key)? {
+            return Ok(None);
+        }
+        self.get_raw(&self.make_user_key(key)?)
+    }
+
+    /// Same as [Self::get], but returns [CandyError::DeadlineExceeded] if the store's internal locks cannot be
+    /// acquired before `deadline`, e.g., while the key's shard is being split, instead of blocking until they are
+    /// released. Once the locks are acquired, the read itself is not interrupted
+    pub fn get_with_deadline<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        deadline: Instant,
+    ) -> Result<Option<Vec<u8>>> {
+        self.owned_get_with_deadline(key.as_ref().to_owned(), deadline)
+    }
+
+    /// Same as [Self::get_with_deadline] but takes an owned key
+    pub fn owned_get_with_deadline(
+        &self,
+        key: Vec<u8>,
+        deadline: Instant,
+    ) -> Result<Option<Vec<u8>>> {
+        if self.key_expired(&key)? {
+            return Ok(None);
+        }
+        let full_key = self.make_user_key(key)?;
+        let ph = PartedHash::new(&self.0.config.hash_seed, &full_key);
+        self.isolated_read_until(ph, &full_key, deadline, || {
+            self.0
+                .root
+                .shared_op_until(ph.shard_selector(), deadline, |sh| {
+                    sh.get_until(ph, &full_key, deadline)
+                })
+        })
+    }
+
+    pub(crate) fn get_value_len_raw(&self, full_key: &[u8]) -> Result<Option<usize>> {
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.isolated_read(ph, full_key, || {
+            self.0
+                .root
+                .shared_op(ph.shard_selector(), |sh| sh.get_value_len(ph, full_key))
+        })
+    }
+
+    /// Returns the length of the key's value, or `None` if the key does not exist. Only the key is read
+    /// from the shard file (to verify it), so this is cheap even for large values.
+    pub fn value_len<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<usize>> {
+        self.owned_value_len(key.as_ref().to_owned())
+    }
+
+    /// Same as [Self::value_len] but takes an owned key
+    pub fn owned_value_len(&self, key: Vec<u8>) -> Result<Option<usize>> {
+        if self.key_expired(&key)? {
+            return Ok(None);
+        }
+        self.get_value_len_raw(&self.make_user_key(key)?)
+    }
+
+    pub(crate) fn get_checksum_raw(&self, full_key: &[u8]) -> Result<Option<u64>> {
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.isolated_read(ph, full_key, || {
+            self.0
+                .root
+                .shared_op(ph.shard_selector(), |sh| sh.get_checksum(ph, full_key))
+        })
+    }
+
+    /// Returns a 64-bit digest of the key's value, or `None` if the key does not exist. The value is
+    /// digested within the shard, so it is not copied out. The digest depends only on the value's contents
+    /// (not on the store's hash seed or on compression), so it is stable across stores and reopens, which
+    /// makes it suitable for cheap comparisons or for generating HTTP ETags. It is not a cryptographic hash.
+    pub fn get_checksum<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<u64>> {
+        self.owned_get_checksum(key.as_ref().to_owned())
+    }
+
+    /// Same as [Self::get_checksum] but takes an owned key
+    pub fn owned_get_checksum(&self, key: Vec<u8>) -> Result<Option<u64>> {
+        if self.key_expired(&key)? {
+            return Ok(None);
+        }
+        self.get_checksum_raw(&self.make_user_key(key)?)
+    }
+
+    pub(crate) fn get_range_raw(
+        &self,
+        full_key: &[u8],
+        range: Range<usize>,
+    ) -> Result<Option<Vec<u8>>> {
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.isolated_read(ph, full_key, || {
+            self.0.root.shared_op(ph.shard_selector(), |sh| {
+                sh.get_range(ph, full_key, range.clone())
+            })
+        })
+    }
+
+    /// Gets only the given byte range of the value of a key, reading just that slice from the shard file
+    /// rather than materializing the whole value. The range is clamped to the value's length, so reading past
+    /// the end returns a shorter (possibly empty) buffer. If the key does not exist, `None` is returned.
+    pub fn get_value_range<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        range: Range<usize>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.owned_get_value_range(key.as_ref().to_owned(), range)
+    }
+
+    /// Same as [Self::get_value_range] but takes an owned key
+    pub fn owned_get_value_range(
+        &self,
+        key: Vec<u8>,
+        range: Range<usize>,
+    ) -> Result<Option<Vec<u8>>> {
+        if self.key_expired(&key)? {
+            return Ok(None);
+        }
+        self.get_range_raw(&self.make_user_key(key)?, range)
     }
 
     /// Checks whether the given key exists in the store
@@ -345,13 +895,45 @@ impl CandyStore {
 
     /// Same as [Self::contains] but takes an owned key
     pub fn owned_contains(&self, key: Vec<u8>) -> Result<bool> {
-        Ok(self.get_raw(&self.make_user_key(key))?.is_some())
+        if self.key_expired(&key)? {
+            return Ok(false);
+        }
+        Ok(self.get_raw(&self.make_user_key(key)?)?.is_some())
     }
 
     pub(crate) fn remove_raw(&self, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
-        self.root
-            .shared_op(ph.shard_selector(), |sh| sh.remove(ph, &full_key))
+        self.remove_with_hash(
+            PartedHash::new(&self.0.config.hash_seed, full_key),
+            full_key,
+        )
+    }
+
+    pub(crate) fn remove_with_hash(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        match self.remove_if_with_hash(ph, full_key, None)? {
+            ReplaceStatus::PrevValue(v) => Ok(Some(v)),
+            ReplaceStatus::DoesNotExist => Ok(None),
+            ReplaceStatus::WrongValue(_) => unreachable!(),
+        }
+    }
+
+    // removes the key only if its value is `expected` (if given)
+    pub(crate) fn remove_if_with_hash(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        expected: Option<&[u8]>,
+    ) -> Result<ReplaceStatus> {
+        self.journal_before_write(full_key)?;
+        let _isolation = self.isolate(ph, full_key);
+        self.guard_write(|| {
+            self.0
+                .root
+                .shared_op(ph.shard_selector(), |sh| sh.remove(ph, full_key, expected))
+        })
     }
 
     /// Removes a key-value pair from the store, returning `None` if the key did not exist,
@@ -362,7 +944,11 @@ impl CandyStore {
 
     /// Same as [Self::remove] but takes an owned key
     pub fn owned_remove(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        self.remove_raw(&self.make_user_key(key))
+        self.purge_if_expired(&key)?;
+        self.with_key_history(key, |key| {
+            self.clear_ttl(&key)?;
+            self.remove_raw(&self.make_user_key(key)?)
+        })
     }
 
     pub(crate) fn insert_internal(
@@ -371,29 +957,55 @@ impl CandyStore {
         val: &[u8],
         mode: InsertMode,
     ) -> Result<InsertStatus> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
-
-        ensure!(
-            full_key.len() <= MAX_TOTAL_KEY_SIZE,
-            CandyError::KeyTooLong(full_key.len())
-        );
-        ensure!(
-            val.len() <= MAX_TOTAL_VALUE_SIZE,
-            CandyError::ValueTooLong(val.len())
-        );
-
-        if full_key.len() + val.len() > self.config.max_shard_size as usize {
-            return Err(anyhow!(CandyError::EntryCannotFitInShard(
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.insert_with_hash(ph, full_key, val, mode)
+    }
+
+    pub(crate) fn insert_with_hash(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        val: &[u8],
+        mode: InsertMode,
+    ) -> Result<InsertStatus> {
+        if full_key.len() > MAX_TOTAL_KEY_SIZE {
+            return Err(CandyError::KeyTooLong(full_key.len()));
+        }
+        if val.len() > MAX_TOTAL_VALUE_SIZE {
+            return Err(CandyError::ValueTooLong(val.len()));
+        }
+
+        if full_key.len() + val.len() > self.0.config.max_shard_size as usize {
+            return Err(CandyError::EntryCannotFitInShard(
                 full_key.len() + val.len(),
-                self.config.max_shard_size as usize
-            )));
+                self.0.config.max_shard_size as usize,
+            ));
         }
 
-        self.root.insert(ph, full_key, val, mode)
+        self.journal_before_write(full_key)?;
+        let res = {
+            let _isolation = self.isolate(ph, full_key);
+            self.guard_write(|| {
+                self.0.stats.add_logical_write(full_key.len() + val.len());
+                self.0.root.insert(ph, full_key, val, mode)
+            })?
+        };
+        self.check_total_bytes_soft_limit();
+        Ok(res)
     }
 
     pub(crate) fn set_raw(&self, full_key: &[u8], val: &[u8]) -> Result<SetStatus> {
-        match self.insert_internal(full_key, val, InsertMode::Set)? {
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.set_with_hash(ph, full_key, val)
+    }
+
+    pub(crate) fn set_with_hash(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        val: &[u8],
+    ) -> Result<SetStatus> {
+        match self.insert_with_hash(ph, full_key, val, InsertMode::Set)? {
             InsertStatus::Added => Ok(SetStatus::CreatedNew),
             InsertStatus::Replaced(v) => Ok(SetStatus::PrevValue(v)),
             InsertStatus::AlreadyExists(v) => Ok(SetStatus::PrevValue(v)),
@@ -421,7 +1033,10 @@ impl CandyStore {
     /// Same as [Self::set], but the key passed owned to this function
     pub fn owned_set(&self, key: Vec<u8>, val: &[u8]) -> Result<SetStatus> {
         Self::ensure_sizes(&key, &val)?;
-        self.set_raw(&self.make_user_key(key), val)
+        self.with_key_history(key, |key| {
+            self.clear_ttl(&key)?;
+            self.set_raw(&self.make_user_key_for_write(key)?, val)
+        })
     }
 
     pub(crate) fn replace_raw(
@@ -430,7 +1045,18 @@ impl CandyStore {
         val: &[u8],
         expected_val: Option<&[u8]>,
     ) -> Result<ReplaceStatus> {
-        match self.insert_internal(full_key, val, InsertMode::Replace(expected_val))? {
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.replace_with_hash(ph, full_key, val, expected_val)
+    }
+
+    pub(crate) fn replace_with_hash(
+        &self,
+        ph: PartedHash,
+        full_key: &[u8],
+        val: &[u8],
+        expected_val: Option<&[u8]>,
+    ) -> Result<ReplaceStatus> {
+        match self.insert_with_hash(ph, full_key, val, InsertMode::Replace(expected_val))? {
             InsertStatus::Added => unreachable!(),
             InsertStatus::Replaced(v) => Ok(ReplaceStatus::PrevValue(v)),
             InsertStatus::AlreadyExists(v) => Ok(ReplaceStatus::WrongValue(v)),
@@ -465,7 +1091,85 @@ impl CandyStore {
         expected_val: Option<&[u8]>,
     ) -> Result<ReplaceStatus> {
         Self::ensure_sizes(&key, &val)?;
-        self.replace_raw(&self.make_user_key(key), val, expected_val)
+        self.purge_if_expired(&key)?;
+        self.with_key_history(key, |key| {
+            let status = self.replace_raw(&self.make_user_key(key.clone())?, val, expected_val)?;
+            if let ReplaceStatus::PrevValue(_) = status {
+                self.clear_ttl(&key)?;
+            }
+            Ok(status)
+        })
+    }
+
+    pub(crate) fn patch_raw(
+        &self,
+        full_key: &[u8],
+        offset: usize,
+        patch: &[u8],
+        expected_before: Option<&[u8]>,
+    ) -> Result<ReplaceStatus> {
+        if let Some(expected_before) = expected_before {
+            if expected_before.len() != patch.len() {
+                return Err(CandyError::PatchLengthMismatch(
+                    expected_before.len(),
+                    patch.len(),
+                ));
+            }
+        }
+        let ph = PartedHash::new(&self.0.config.hash_seed, full_key);
+        self.journal_before_write(full_key)?;
+        self.0.stats.add_logical_write(patch.len());
+        let _isolation = self.isolate(ph, full_key);
+        let status = self.guard_write(|| {
+            self.0.root.shared_op(ph.shard_selector(), |sh| {
+                sh.patch(ph, full_key, offset, patch, expected_before)
+            })
+        })?;
+        match status {
+            PatchStatus::Patched(v) => Ok(ReplaceStatus::PrevValue(v)),
+            PatchStatus::WrongValue(v) => Ok(ReplaceStatus::WrongValue(v)),
+            PatchStatus::KeyDoesNotExist => Ok(ReplaceStatus::DoesNotExist),
+        }
+    }
+
+    /// Overwrites `patch.len()` bytes of an existing value, starting at `offset`, without rewriting the
+    /// rest of the value. The value's length never changes: patching beyond its end fails with
+    /// [CandyError::PatchOutOfBounds]. If `expected_before` is given, it must be of the same length as `patch`
+    /// (or [CandyError::PatchLengthMismatch] is returned), and the patch is applied only if the bytes currently
+    /// in that range match it (compare-and-swap).
+    ///
+    /// Returns `PrevValue(bytes)` with the bytes that were overwritten, `WrongValue(bytes)` with the current
+    /// bytes if `expected_before` did not match, or `DoesNotExist` if the key does not exist.
+    ///
+    /// Note: unlike [Self::set], this writes over the existing data, so a crash in the middle may leave the
+    /// value partially patched (values compressed with a dictionary are rewritten as a whole, though)
+    pub fn patch<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B1,
+        offset: usize,
+        patch: &B2,
+        expected_before: Option<&B2>,
+    ) -> Result<ReplaceStatus> {
+        self.owned_patch(
+            key.as_ref().to_owned(),
+            offset,
+            patch.as_ref(),
+            expected_before.map(|eb| eb.as_ref()),
+        )
+    }
+
+    /// Same as [Self::patch], but the key passed owned to this function
+    pub fn owned_patch(
+        &self,
+        key: Vec<u8>,
+        offset: usize,
+        patch: &[u8],
+        expected_before: Option<&[u8]>,
+    ) -> Result<ReplaceStatus> {
+        self.purge_if_expired(&key)?;
+        self.with_key_history(key, |key| {
+            self.patch_raw(&self.make_user_key(key)?, offset, patch, expected_before)
+        })
     }
 
     pub(crate) fn get_or_create_raw(
@@ -503,7 +1207,84 @@ impl CandyStore {
         default_val: Vec<u8>,
     ) -> Result<GetOrCreateStatus> {
         Self::ensure_sizes(&key, &default_val)?;
-        self.get_or_create_raw(&self.make_user_key(key), default_val)
+        self.purge_if_expired(&key)?;
+        self.with_key_history(key, |key| {
+            let status =
+                self.get_or_create_raw(&self.make_user_key_for_write(key.clone())?, default_val)?;
+            if let GetOrCreateStatus::CreatedNew(_) = status {
+                // a leftover of a key that was removed before it expired
+                self.clear_ttl(&key)?;
+            }
+            Ok(status)
+        })
+    }
+
+    /// The number of attempts [Self::update] makes before giving up with [CandyError::TooManyRetries]
+    pub const MAX_UPDATE_ATTEMPTS: usize = 64;
+
+    /// Atomically updates the value of a key with `f`, which is given the current value (or None if the key
+    /// does not exist) and returns the new value (or None to remove the key). Returns the new value.
+    ///
+    /// This is a compare-and-swap loop: if the key is modified concurrently between reading it and writing the
+    /// new value, `f` is called again with the fresh value, so it must not have side effects. After
+    /// [Self::MAX_UPDATE_ATTEMPTS] conflicting attempts, [CandyError::TooManyRetries] is returned. Note that the
+    /// comparison is by value, so a concurrent modification that restores the value it read is not noticed
+    pub fn update<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+        f: impl FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.owned_update(key.as_ref().to_owned(), f)
+    }
+
+    /// Same as [Self::update], but the key passed owned to this function
+    pub fn owned_update(
+        &self,
+        key: Vec<u8>,
+        f: impl FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.purge_if_expired(&key)?;
+        self.with_key_history(key, |key| self.update_impl(key, f))
+    }
+
+    fn update_impl(
+        &self,
+        key: Vec<u8>,
+        mut f: impl FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        let full_key = self.make_user_key_for_write(key.clone())?;
+        let ph = PartedHash::new(&self.0.config.hash_seed, &full_key);
+
+        for _ in 0..Self::MAX_UPDATE_ATTEMPTS {
+            let curr = self.get_with_hash(ph, &full_key)?;
+            let curr_existed = curr.is_some();
+            let new = f(curr.as_deref());
+            if let Some(ref new) = new {
+                Self::ensure_sizes(&key, new)?;
+            }
+            let succeeded = match (curr, new.as_deref()) {
+                (None, None) => true,
+                (None, Some(new)) => matches!(
+                    self.insert_with_hash(ph, &full_key, new, InsertMode::GetOrCreate)?,
+                    InsertStatus::Added
+                ),
+                (Some(curr), Some(new)) => matches!(
+                    self.replace_with_hash(ph, &full_key, new, Some(&curr))?,
+                    ReplaceStatus::PrevValue(_)
+                ),
+                (Some(curr), None) => matches!(
+                    self.remove_if_with_hash(ph, &full_key, Some(&curr))?,
+                    ReplaceStatus::PrevValue(_)
+                ),
+            };
+            if succeeded {
+                if !matches!((curr_existed, &new), (false, None)) {
+                    self.clear_ttl(&key)?;
+                }
+                return Ok(new);
+            }
+        }
+        Err(CandyError::TooManyRetries(Self::MAX_UPDATE_ATTEMPTS))
     }
 
     /// Returns an iterator over the whole store (skipping lists or typed items)
@@ -541,10 +1322,10 @@ impl CandyStore {
 
     /// Returns useful stats about the store
     pub fn stats(&self) -> Stats {
-        let shard_stats = self.root.call_on_all_shards(|sh| sh.get_stats()).unwrap();
+        let shard_stats = self.0.root.call_on_all_shards(|sh| sh.get_stats()).unwrap();
 
         let mut stats = Stats::default();
-        self.stats.fill_stats(&mut stats);
+        self.0.stats.fill_stats(&mut stats);
 
         for stats2 in shard_stats {
             stats.num_shards += 1;
@@ -556,6 +1337,14 @@ impl CandyStore {
         stats
     }
 
+    /// Returns the bytes written since the store was opened (or cleared): the logical bytes that were asked to
+    /// be written, and the bytes actually written to the shard files, including the rewrites of splits and
+    /// compactions. Use [WriteAmplification::since] on two consecutive reports to get the write amplification
+    /// of a time window, e.g., for tuning `max_shard_size` and `min_compaction_threashold`
+    pub fn write_amplification(&self) -> WriteAmplification {
+        self.0.stats.write_amplification()
+    }
+
     /// Merges small shards (shards with a used capacity of less than `max_fill_level`), `max_fill_level` should
     /// be a number between 0 and 0.5, the reasonable choice is 0.25.
     ///
@@ -568,7 +1357,7 @@ impl CandyStore {
     ///
     /// Returns true if any shards were merged, false otherwise
     pub fn merge_small_shards(&self, max_fill_level: f32) -> Result<bool> {
-        self.root.merge_small_shards(max_fill_level)
+        self.0.root.merge_small_shards(max_fill_level)
     }
 
     /// Sets a big item, whose value is unlimited in size. Behind the scenes the value is split into chunks
@@ -615,6 +1404,14 @@ impl CandyStore {
     }
 }
 
+impl Drop for StoreInner {
+    fn drop(&mut self) {
+        // the last handle is gone, write back the pinned headers before the shards are closed
+        _ = self.write_back_pinned_headers_on_close();
+        _ = self.write_checksum_manifest();
+    }
+}
+
 // impl Drop for CandyStore {
 //     fn drop(&mut self) {
 //         _ = self.threadpool.terminate();
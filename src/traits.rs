@@ -0,0 +1,78 @@
+use crate::{CandyStore, Result, SetStatus};
+
+/// An iterator over key-value pairs, as returned by the traits' iteration methods
+pub type BoxedKvIterator<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// An object-safe abstraction over the store's key-value API, implemented by [CandyStore]. Application code
+/// written against `&dyn KvStore` can be tested against an in-memory implementation, or pointed at an
+/// alternative backend (e.g., during a migration)
+pub trait KvStore: Send + Sync {
+    /// See [CandyStore::get]
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// See [CandyStore::set]
+    fn set(&self, key: &[u8], val: &[u8]) -> Result<SetStatus>;
+    /// See [CandyStore::remove]
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// See [CandyStore::iter]. The order of iteration is unspecified
+    fn iter(&self) -> BoxedKvIterator<'_>;
+
+    /// See [CandyStore::contains]
+    fn contains(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+}
+
+/// An object-safe abstraction over the store's list API, implemented by [CandyStore]. See [KvStore]
+pub trait ListStore: Send + Sync {
+    /// See [CandyStore::get_from_list]
+    fn get_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// See [CandyStore::set_in_list]
+    fn set_in_list(&self, list_key: &[u8], item_key: &[u8], val: &[u8]) -> Result<SetStatus>;
+    /// See [CandyStore::remove_from_list]
+    fn remove_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// See [CandyStore::iter_list]. Items are yielded in insertion order
+    fn iter_list(&self, list_key: &[u8]) -> BoxedKvIterator<'_>;
+    /// See [CandyStore::list_len]
+    fn list_len(&self, list_key: &[u8]) -> Result<usize>;
+    /// See [CandyStore::discard_list]
+    fn discard_list(&self, list_key: &[u8]) -> Result<bool>;
+}
+
+impl KvStore for CandyStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        CandyStore::get(self, key)
+    }
+    fn set(&self, key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        CandyStore::set(self, key, val)
+    }
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        CandyStore::remove(self, key)
+    }
+    fn iter(&self) -> BoxedKvIterator<'_> {
+        Box::new(CandyStore::iter(self))
+    }
+    fn contains(&self, key: &[u8]) -> Result<bool> {
+        CandyStore::contains(self, key)
+    }
+}
+
+impl ListStore for CandyStore {
+    fn get_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        CandyStore::get_from_list(self, list_key, item_key)
+    }
+    fn set_in_list(&self, list_key: &[u8], item_key: &[u8], val: &[u8]) -> Result<SetStatus> {
+        CandyStore::set_in_list(self, list_key, item_key, val)
+    }
+    fn remove_from_list(&self, list_key: &[u8], item_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        CandyStore::remove_from_list(self, list_key, item_key)
+    }
+    fn iter_list(&self, list_key: &[u8]) -> BoxedKvIterator<'_> {
+        Box::new(CandyStore::iter_list(self, list_key))
+    }
+    fn list_len(&self, list_key: &[u8]) -> Result<usize> {
+        CandyStore::list_len(self, list_key)
+    }
+    fn discard_list(&self, list_key: &[u8]) -> Result<bool> {
+        CandyStore::discard_list(self, list_key)
+    }
+}
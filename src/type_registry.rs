@@ -0,0 +1,61 @@
+use crate::{
+    store::TYPE_REGISTRY_NAMESPACE, CandyError, CandyStore, CandyTypedKey, GetOrCreateStatus,
+    Result,
+};
+
+impl CandyStore {
+    fn type_registry_list_key() -> Vec<u8> {
+        TYPE_REGISTRY_NAMESPACE.to_owned()
+    }
+
+    /// Records the `TYPE_ID` of `K` along with its type name (as returned by [std::any::type_name]) in the
+    /// store, returning true if it was newly registered. Registering all key types on startup makes two types
+    /// that (accidentally) share a `TYPE_ID`, and would thus share a keyspace, fail fast with
+    /// [CandyError::TypeMismatch] instead of silently reading each other's entries.
+    ///
+    /// Note: renaming (or moving) a registered type also changes its name, so it is reported as a collision
+    /// too; use [Self::unregister_type] to drop the stale name
+    pub fn register_type<K: CandyTypedKey>(&self) -> Result<bool> {
+        let type_name = std::any::type_name::<K>();
+        match self.owned_get_or_create_in_list(
+            Self::type_registry_list_key(),
+            K::TYPE_ID.to_le_bytes().to_vec(),
+            type_name.as_bytes().to_vec(),
+        )? {
+            GetOrCreateStatus::CreatedNew(_) => Ok(true),
+            GetOrCreateStatus::ExistingValue(existing) if existing == type_name.as_bytes() => {
+                Ok(false)
+            }
+            GetOrCreateStatus::ExistingValue(existing) => Err(CandyError::TypeMismatch(format!(
+                "TYPE_ID 0x{:08x} of {type_name} is already registered to {}",
+                K::TYPE_ID,
+                String::from_utf8_lossy(&existing)
+            ))),
+        }
+    }
+
+    /// Removes the registration of the given `TYPE_ID` (see [Self::register_type]), returning the name it was
+    /// registered to, if any
+    pub fn unregister_type(&self, type_id: u32) -> Result<Option<String>> {
+        Ok(self
+            .owned_remove_from_list(
+                Self::type_registry_list_key(),
+                type_id.to_le_bytes().to_vec(),
+            )?
+            .map(|name| String::from_utf8_lossy(&name).into_owned()))
+    }
+
+    /// Returns the `(TYPE_ID, type name)` pairs registered by [Self::register_type], in registration order
+    pub fn list_registered_types(&self) -> Result<Vec<(u32, String)>> {
+        let mut types = vec![];
+        for res in self.owned_iter_list(Self::type_registry_list_key()) {
+            let (k, v) = res?;
+            let type_id = u32::from_le_bytes(
+                k.try_into()
+                    .map_err(|_| CandyError::Corruption("bad registered TYPE_ID".into()))?,
+            );
+            types.push((type_id, String::from_utf8_lossy(&v).into_owned()));
+        }
+        Ok(types)
+    }
+}
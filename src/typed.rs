@@ -1,10 +1,17 @@
-use anyhow::anyhow;
 use bytemuck::bytes_of;
-use std::{borrow::Borrow, marker::PhantomData, ops::Range, sync::Arc};
+use siphasher::sip::SipHasher13;
+use std::hash::Hasher;
+use std::{
+    borrow::Borrow,
+    marker::PhantomData,
+    mem::size_of,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    store::{ReplaceStatus, SetStatus, TYPED_NAMESPACE},
-    CandyStore, ListCompactionParams,
+    store::{GetOrCreateStatus, ReplaceStatus, SetStatus, SCOPED_TYPED_NAMESPACE, TYPED_NAMESPACE},
+    CandyError, CandyStore, DryRunReport, ListCompactionParams, ListItemMeta,
 };
 
 use crate::Result;
@@ -42,8 +49,77 @@ typed_builtin!(String, 15);
 typed_builtin!(Vec<u8>, 16);
 typed_builtin!(uuid::Bytes, 17);
 
+// combines the type IDs of a tuple's elements, so that (A, B) is different from both (B, A) and (A, C)
+const fn combine_type_ids(type_ids: &[u32]) -> u32 {
+    let mut h = 0x7c3a_91e5u32;
+    let mut i = 0;
+    while i < type_ids.len() {
+        h = (h ^ type_ids[i]).wrapping_mul(0x9e37_79b1).rotate_left(13);
+        i += 1;
+    }
+    h
+}
+
+/// Marks `P` as a key prefix of `Self`, i.e., the encoding of `Self` starts with the encoding of `P`. This holds
+/// for the leading elements of tuples, since [databuf] encodes tuples as the concatenation of their elements
+/// (and every element's encoding is self-delimiting). See [CandyTypedStore::iter_with_prefix]
+pub trait CandyKeyPrefix<P> {}
+
+macro_rules! typed_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: CandyTypedKey),+> CandyTypedKey for ($($t,)+) {
+            const TYPE_ID: u32 = combine_type_ids(&[$($t::TYPE_ID),+]);
+        }
+    };
+}
+
+typed_tuple!(A, B);
+typed_tuple!(A, B, C);
+typed_tuple!(A, B, C, D);
+
+macro_rules! key_prefix {
+    ($tuple:ty => $prefix:ty; $($t:ident),+) => {
+        impl<$($t),+> CandyKeyPrefix<$prefix> for $tuple {}
+    };
+}
+
+key_prefix!((A, B) => A; A, B);
+key_prefix!((A, B, C) => A; A, B, C);
+key_prefix!((A, B, C) => (A, B); A, B, C);
+key_prefix!((A, B, C, D) => A; A, B, C, D);
+key_prefix!((A, B, C, D) => (A, B); A, B, C, D);
+key_prefix!((A, B, C, D) => (A, B, C); A, B, C, D);
+
+/// Encodes `val` into a buffer that has room for `suffix_len` more bytes, so appending the namespace/type suffixes
+/// (done by the typed wrappers and by the store itself) does not reallocate. For `str` and `[u8]` (the common
+/// borrowed forms of `String` and `Vec<u8>` keys) the estimate is exact, making it a single allocation
+fn encode_with_room<Q: ?Sized + Encode>(val: &Q, suffix_len: usize) -> Vec<u8> {
+    // size_of_val is the length of the data for slices, and varint length prefixes take at most 9 bytes
+    let mut buf = Vec::with_capacity(std::mem::size_of_val(val) + 9 + suffix_len);
+    val.encode::<LE>(&mut buf).unwrap();
+    buf
+}
+
+// the namespace a wrapper is scoped to (see [CandyTypedStore::in_namespace]) is appended to the encoded key,
+// followed by its length, so that keys of different namespaces never collide with each other, nor with the
+// keys of unscoped wrappers (encodings of keys are self-delimiting, so one is never a prefix of another)
+fn push_namespace(kbytes: &mut Vec<u8>, ns: &[u8]) {
+    if !ns.is_empty() {
+        kbytes.extend_from_slice(ns);
+        kbytes.extend_from_slice(&(ns.len() as u16).to_le_bytes());
+    }
+}
+
+fn namespace_len(ns: &[u8]) -> usize {
+    if ns.is_empty() {
+        0
+    } else {
+        ns.len() + size_of::<u16>()
+    }
+}
+
 fn from_bytes<T: DecodeOwned>(bytes: &[u8]) -> Result<T> {
-    T::from_bytes::<LE>(bytes).map_err(|e| anyhow!(e))
+    T::from_bytes::<LE>(bytes).map_err(|e| CandyError::DecodeError(e.to_string()))
 }
 
 /// Typed stores are wrappers around an underlying [CandyStore], that serialize keys and values (using [databuf]).
@@ -56,8 +132,10 @@ fn from_bytes<T: DecodeOwned>(bytes: &[u8]) -> Result<T> {
 /// * All APIs take keys and values by-ref, because they will serialize them, so taking owned values doesn't
 ///   make sense
 /// * [CandyStore::iter] will skip typed items, since it's meaningless to interpret them without the wrapper
+/// * Wrappers can be scoped to a namespace (e.g., a tenant) with [Self::in_namespace]
 pub struct CandyTypedStore<K, V> {
-    store: Arc<CandyStore>,
+    store: CandyStore,
+    ns: Vec<u8>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -65,6 +143,7 @@ impl<K, V> Clone for CandyTypedStore<K, V> {
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),
+            ns: self.ns.clone(),
             _phantom: Default::default(),
         }
     }
@@ -76,29 +155,97 @@ where
     V: Encode + DecodeOwned,
 {
     /// Constructs a typed wrapper over a CandyStore
-    pub fn new(store: Arc<CandyStore>) -> Self {
+    pub fn new(store: CandyStore) -> Self {
         Self {
             store,
+            ns: vec![],
             _phantom: Default::default(),
         }
     }
 
-    fn make_key<Q: ?Sized + Encode>(key: &Q) -> Vec<u8>
+    /// Returns a wrapper over the same store that is scoped to the given namespace (e.g., a tenant ID): its
+    /// keys are kept apart from the keys of other namespaces (and of unscoped wrappers), so the same key can
+    /// be used in each of them, and [Self::iter] only returns the items of this namespace. The namespace is
+    /// part of every key, so it should be short. The empty namespace is the unscoped one
+    pub fn in_namespace<B: AsRef<[u8]> + ?Sized>(&self, ns: &B) -> Self {
+        Self {
+            store: self.store.clone(),
+            ns: ns.as_ref().to_owned(),
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Returns the namespace this wrapper is scoped to, see [Self::in_namespace]
+    pub fn namespace(&self) -> &[u8] {
+        &self.ns
+    }
+
+    // appends what follows the encoded key: the namespace (if scoped), the type ID and the namespace byte
+    fn push_key_suffix(&self, kbytes: &mut Vec<u8>) {
+        push_namespace(kbytes, &self.ns);
+        kbytes.extend_from_slice(bytes_of(&K::TYPE_ID));
+        kbytes.extend_from_slice(if self.ns.is_empty() {
+            TYPED_NAMESPACE
+        } else {
+            SCOPED_TYPED_NAMESPACE
+        });
+    }
+
+    fn make_key<Q: ?Sized + Encode>(&self, key: &Q) -> Vec<u8>
     where
         K: Borrow<Q>,
     {
-        let mut kbytes = key.to_bytes::<LE>();
-        kbytes.extend_from_slice(bytes_of(&K::TYPE_ID));
-        kbytes.extend_from_slice(TYPED_NAMESPACE);
+        let mut kbytes = encode_with_room(
+            key,
+            namespace_len(&self.ns) + size_of::<u32>() + TYPED_NAMESPACE.len(),
+        );
+        self.push_key_suffix(&mut kbytes);
         kbytes
     }
 
+    // a fingerprint of the value type, see [crate::Config::strict_typed_values]
+    fn value_type_id() -> u32 {
+        let mut hasher = SipHasher13::new();
+        hasher.write(std::any::type_name::<V>().as_bytes());
+        hasher.finish() as u32
+    }
+
+    fn encode_val<Q: ?Sized + Encode>(&self, val: &Q) -> Vec<u8> {
+        if !self.store.0.config.strict_typed_values {
+            return val.to_bytes::<LE>();
+        }
+        let mut vbytes = Self::value_type_id().to_le_bytes().to_vec();
+        val.encode::<LE>(&mut vbytes).unwrap();
+        vbytes
+    }
+
+    fn decode_val(&self, vbytes: &[u8]) -> Result<V> {
+        if !self.store.0.config.strict_typed_values {
+            return from_bytes::<V>(vbytes);
+        }
+        let expected = Self::value_type_id();
+        match vbytes.split_first_chunk::<4>() {
+            Some((header, vbytes)) if u32::from_le_bytes(*header) == expected => {
+                from_bytes::<V>(vbytes)
+            }
+            Some((header, _)) => Err(CandyError::TypeMismatch(format!(
+                "expected value type {} (0x{expected:08x}), found 0x{:08x}",
+                std::any::type_name::<V>(),
+                u32::from_le_bytes(*header)
+            ))),
+            None => Err(CandyError::TypeMismatch(format!(
+                "expected value type {}, found a value without a type header",
+                std::any::type_name::<V>()
+            ))),
+        }
+    }
+
     /// Same as [CandyStore::contains] but serializes the key
     pub fn contains<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
     where
         K: Borrow<Q>,
     {
-        Ok(self.store.get_raw(&Self::make_key(key))?.is_some())
+        Ok(self.store.get_raw(&self.make_key(key))?.is_some())
     }
 
     /// Same as [CandyStore::get] but serializes the key and deserializes the value
@@ -106,9 +253,9 @@ where
     where
         K: Borrow<Q>,
     {
-        let kbytes = Self::make_key(key);
+        let kbytes = self.make_key(key);
         if let Some(vbytes) = self.store.get_raw(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
+            Ok(Some(self.decode_val(&vbytes)?))
         } else {
             Ok(None)
         }
@@ -125,15 +272,15 @@ where
         K: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
-        let ebytes = expected_val.map(|ev| ev.to_bytes::<LE>()).unwrap_or(vec![]);
+        let kbytes = self.make_key(key);
+        let vbytes = self.encode_val(val);
+        let ebytes = expected_val.map(|ev| self.encode_val(ev)).unwrap_or(vec![]);
         match self
             .store
             .replace_raw(&kbytes, &vbytes, expected_val.map(|_| &*ebytes))?
         {
             ReplaceStatus::DoesNotExist => Ok(None),
-            ReplaceStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+            ReplaceStatus::PrevValue(v) => Ok(Some(self.decode_val(&v)?)),
             ReplaceStatus::WrongValue(_) => Ok(None),
         }
     }
@@ -148,11 +295,11 @@ where
         K: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
+        let kbytes = self.make_key(key);
+        let vbytes = self.encode_val(val);
         match self.store.set_raw(&kbytes, &vbytes)? {
             SetStatus::CreatedNew => Ok(None),
-            SetStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+            SetStatus::PrevValue(v) => Ok(Some(self.decode_val(&v)?)),
         }
     }
 
@@ -166,23 +313,36 @@ where
         K: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let kbytes = Self::make_key(key);
-        Ok(from_bytes::<V>(
+        let kbytes = self.make_key(key);
+        Ok(self.decode_val(
             &self
                 .store
-                .get_or_create_raw(&kbytes, default_val.to_bytes::<LE>())?
+                .get_or_create_raw(&kbytes, self.encode_val(default_val))?
                 .value(),
         )?)
     }
 
+    /// Same as [Self::get_or_create], but the default value is `V::default()`, which is only constructed if the
+    /// key does not exist. Creating the key is still atomic: if it is created concurrently, its value is returned
+    pub fn get_or_default<Q: ?Sized + Encode>(&self, key: &Q) -> Result<V>
+    where
+        K: Borrow<Q>,
+        V: Default,
+    {
+        if let Some(val) = self.get(key)? {
+            return Ok(val);
+        }
+        self.get_or_create(key, &V::default())
+    }
+
     /// Same as [CandyStore::remove] but serializes the key
     pub fn remove<Q: ?Sized + Encode>(&self, k: &Q) -> Result<Option<V>>
     where
         K: Borrow<Q>,
     {
-        let kbytes = Self::make_key(k);
+        let kbytes = self.make_key(k);
         if let Some(vbytes) = self.store.remove_raw(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
+            Ok(Some(self.decode_val(&vbytes)?))
         } else {
             Ok(None)
         }
@@ -193,9 +353,9 @@ where
     where
         K: Borrow<Q>,
     {
-        let kbytes = Self::make_key(key);
+        let kbytes = self.make_key(key);
         if let Some(vbytes) = self.store.get_big(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
+            Ok(Some(self.decode_val(&vbytes)?))
         } else {
             Ok(None)
         }
@@ -211,8 +371,8 @@ where
         K: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
+        let kbytes = self.make_key(key);
+        let vbytes = self.encode_val(val);
         self.store.set_big(&kbytes, &vbytes)
     }
 
@@ -221,15 +381,61 @@ where
     where
         K: Borrow<Q>,
     {
-        let kbytes = Self::make_key(k);
+        let kbytes = self.make_key(k);
         self.store.remove_big(&kbytes)
     }
+
+    fn iter_encoded_prefix(&self, prefix: Vec<u8>) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        let mut suffix = vec![];
+        self.push_key_suffix(&mut suffix);
+
+        self.store.iter_raw().filter_map(move |res| {
+            let (k, v) = match res {
+                Ok(kv) => kv,
+                Err(e) => return Some(Err(e)),
+            };
+            if k.len() < prefix.len() + suffix.len()
+                || !k.ends_with(&suffix)
+                || !k.starts_with(&prefix)
+            {
+                return None;
+            }
+            let key = match from_bytes::<K>(&k[..k.len() - suffix.len()]) {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            Some(self.decode_val(&v).map(|val| (key, val)))
+        })
+    }
+
+    /// Iterates over all the items of this typed store (i.e., with keys of type `K`, in this wrapper's namespace).
+    /// Note that this scans the whole underlying store
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        self.iter_encoded_prefix(vec![])
+    }
+
+    /// Iterates over the items whose (composite) key starts with the given prefix, e.g., for `K = (A, B)`,
+    /// all the `(a, _)` items. Like [Self::iter], this scans the whole underlying store, so if you need to
+    /// enumerate items by the first component often, consider keeping them in a [CandyTypedList] keyed by it
+    pub fn iter_with_prefix<P: Encode>(
+        &self,
+        prefix: &P,
+    ) -> impl Iterator<Item = Result<(K, V)>> + '_
+    where
+        K: CandyKeyPrefix<P>,
+    {
+        self.iter_encoded_prefix(prefix.to_bytes::<LE>())
+    }
 }
 
 /// A wrapper around [CandyStore] that exposes the list API in a typed manner. See [CandyTypedStore] for more
-/// info
+/// info.
+///
+/// List keys, item keys and values can be passed in their borrowed forms (e.g., `&str` for `String` or `&[u8]`
+/// for `Vec<u8>`), which encode identically and are serialized directly into the final key buffer.
 pub struct CandyTypedList<L, K, V> {
-    store: Arc<CandyStore>,
+    store: CandyStore,
+    ns: Vec<u8>,
     _phantom: PhantomData<(L, K, V)>,
 }
 
@@ -237,11 +443,26 @@ impl<L, K, V> Clone for CandyTypedList<L, K, V> {
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),
+            ns: self.ns.clone(),
             _phantom: Default::default(),
         }
     }
 }
 
+fn make_typed_list_key<L: CandyTypedKey + Borrow<Q>, Q: ?Sized + Encode>(
+    ns: &[u8],
+    list_key: &Q,
+) -> Vec<u8> {
+    // room for the namespace, the type ID, and the list (or queue) suffixes
+    let mut kbytes = encode_with_room(
+        list_key,
+        namespace_len(ns) + size_of::<u32>() + size_of::<u64>() + 1,
+    );
+    push_namespace(&mut kbytes, ns);
+    kbytes.extend_from_slice(bytes_of(&L::TYPE_ID));
+    kbytes
+}
+
 impl<L, K, V> CandyTypedList<L, K, V>
 where
     L: CandyTypedKey,
@@ -249,20 +470,34 @@ where
     V: Encode + DecodeOwned,
 {
     /// Constructs a [CandyTypedList] over an existing [CandyStore]
-    pub fn new(store: Arc<CandyStore>) -> Self {
+    pub fn new(store: CandyStore) -> Self {
         Self {
             store,
+            ns: vec![],
             _phantom: PhantomData,
         }
     }
 
-    fn make_list_key<Q: ?Sized + Encode>(list_key: &Q) -> Vec<u8>
+    /// Returns a wrapper over the same store whose lists are scoped to the given namespace, see
+    /// [CandyTypedStore::in_namespace]
+    pub fn in_namespace<B: AsRef<[u8]> + ?Sized>(&self, ns: &B) -> Self {
+        Self {
+            store: self.store.clone(),
+            ns: ns.as_ref().to_owned(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the namespace this wrapper is scoped to, see [Self::in_namespace]
+    pub fn namespace(&self) -> &[u8] {
+        &self.ns
+    }
+
+    fn make_list_key<Q: ?Sized + Encode>(&self, list_key: &Q) -> Vec<u8>
     where
         L: Borrow<Q>,
     {
-        let mut kbytes = list_key.to_bytes::<LE>();
-        kbytes.extend_from_slice(bytes_of(&L::TYPE_ID));
-        kbytes
+        make_typed_list_key::<L, Q>(&self.ns, list_key)
     }
 
     /// Tests if the given typed `item_key` exists in this list (identified by `list_key`)
@@ -275,8 +510,8 @@ where
         L: Borrow<Q1>,
         K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
         Ok(self
             .store
             .owned_get_from_list(list_key, item_key)?
@@ -293,8 +528,8 @@ where
         L: Borrow<Q1>,
         K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
         if let Some(vbytes) = self.store.owned_get_from_list(list_key, item_key)? {
             Ok(Some(from_bytes::<V>(&vbytes)?))
         } else {
@@ -302,6 +537,28 @@ where
         }
     }
 
+    /// Same as [CandyStore::get_from_list_with_meta], but `list_key` and `item_key` are typed
+    pub fn get_with_meta<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<Option<(V, ListItemMeta)>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
+        if let Some((vbytes, meta)) = self
+            .store
+            .owned_get_from_list_with_meta(list_key, item_key)?
+        {
+            Ok(Some((from_bytes::<V>(&vbytes)?, meta)))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn _set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
         &self,
         list_key: &Q1,
@@ -314,9 +571,9 @@ where
         K: Borrow<Q2>,
         V: Borrow<Q3>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let val = val.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
+        let val = encode_with_room(val, size_of::<u64>());
         match self
             .store
             .owned_set_in_list(list_key, item_key, val, promote)?
@@ -367,9 +624,9 @@ where
         L: Borrow<Q1>,
         K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let default_val = default_val.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
+        let default_val = encode_with_room(default_val, size_of::<u64>());
         let vbytes = self
             .store
             .owned_get_or_create_in_list(list_key, item_key, default_val)?
@@ -377,6 +634,25 @@ where
         from_bytes::<V>(&vbytes)
     }
 
+    /// Same as [Self::get_or_create], but the default value is `V::default()`, which is only constructed if the
+    /// item does not exist. Creating the item is still atomic: if it is created concurrently, its value is
+    /// returned
+    pub fn get_or_default<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<V>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Default,
+    {
+        if let Some(val) = self.get(list_key, item_key)? {
+            return Ok(val);
+        }
+        self.get_or_create(list_key, item_key, &V::default())
+    }
+
     /// Same as [CandyStore::replace_in_list], but `list_key`, `item_key` and `val` are typed
     pub fn replace<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
         &self,
@@ -390,9 +666,9 @@ where
         K: Borrow<Q2>,
         V: Borrow<Q3>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let val = val.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
+        let val = encode_with_room(val, size_of::<u64>());
         let ebytes = expected_val
             .map(|ev| ev.to_bytes::<LE>())
             .unwrap_or_default();
@@ -418,8 +694,8 @@ where
         L: Borrow<Q1>,
         K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
+        let list_key = self.make_list_key(list_key);
+        let item_key = encode_with_room(item_key, CandyStore::LIST_KEY_SUFFIX_LEN);
         if let Some(vbytes) = self.store.owned_remove_from_list(list_key, item_key)? {
             Ok(Some(from_bytes::<V>(&vbytes)?))
         } else {
@@ -435,7 +711,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         self.store.owned_iter_list(list_key).map(|res| match res {
             Err(e) => Err(e),
             Ok((k, v)) => {
@@ -454,7 +730,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         self.store
             .owned_iter_list_backwards(list_key)
             .map(|res| match res {
@@ -472,7 +748,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         self.store.owned_discard_list(list_key)
     }
 
@@ -485,16 +761,52 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         self.store.compact_list_if_needed(&list_key, params)
     }
 
+    /// Same as [CandyStore::sort_list_by], but `list_key`, and the keys and values given to `cmp`, are typed.
+    /// The items are decoded once, before sorting
+    pub fn sort_by<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        mut cmp: impl FnMut((&K, &V), (&K, &V)) -> std::cmp::Ordering,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = self.make_list_key(list_key);
+        self.store.reorder_list(list_key, |items| {
+            let decoded = items
+                .iter()
+                .map(|(k, v)| Ok((from_bytes::<K>(k)?, from_bytes::<V>(v)?)))
+                .collect::<Result<Vec<_>>>()?;
+            let mut order = (0..decoded.len()).collect::<Vec<_>>();
+            order.sort_by(|&a, &b| {
+                cmp(
+                    (&decoded[a].0, &decoded[a].1),
+                    (&decoded[b].0, &decoded[b].1),
+                )
+            });
+            Ok(order)
+        })
+    }
+
+    /// Same as [CandyStore::dry_run_discard_list], but `list_key` is typed
+    pub fn dry_run_discard<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<DryRunReport>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = self.make_list_key(list_key);
+        self.store.owned_dry_run_discard_list(list_key)
+    }
+
     /// Same as [CandyStore::pop_list_tail], but `list_key` is typed
     pub fn pop_tail<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         let Some((k, v)) = self.store.owned_pop_list_tail(list_key)? else {
             return Ok(None);
         };
@@ -506,7 +818,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         let Some((k, v)) = self.store.owned_pop_list_head(list_key)? else {
             return Ok(None);
         };
@@ -518,7 +830,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         let Some((k, v)) = self.store.owned_peek_list_tail(list_key)? else {
             return Ok(None);
         };
@@ -530,19 +842,45 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         let Some((k, v)) = self.store.owned_peek_list_head(list_key)? else {
             return Ok(None);
         };
         Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
     }
 
+    /// Same as [CandyStore::peek_list_head_many], but `list_key` is typed
+    pub fn peek_head_many<Q: ?Sized + Encode>(&self, list_key: &Q, n: usize) -> Result<Vec<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = self.make_list_key(list_key);
+        self.store
+            .owned_peek_list_head_many(list_key, n)?
+            .into_iter()
+            .map(|(k, v)| Ok((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+            .collect()
+    }
+
+    /// Same as [CandyStore::peek_list_tail_many], but `list_key` is typed
+    pub fn peek_tail_many<Q: ?Sized + Encode>(&self, list_key: &Q, n: usize) -> Result<Vec<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = self.make_list_key(list_key);
+        self.store
+            .owned_peek_list_tail_many(list_key, n)?
+            .into_iter()
+            .map(|(k, v)| Ok((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+            .collect()
+    }
+
     /// Same as [CandyStore::list_len], but `list_key` is typed
     pub fn len<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<usize>
     where
         L: Borrow<Q>,
     {
-        self.store.owned_list_len(Self::make_list_key(list_key))
+        self.store.owned_list_len(self.make_list_key(list_key))
     }
 
     /// Same as [CandyStore::retain_in_list], but `list_key` is typed
@@ -554,19 +892,50 @@ where
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let list_key = self.make_list_key(list_key);
         self.store.owned_retain_in_list(list_key, |k, v| {
             let tk = from_bytes::<K>(&k)?;
             let tv = from_bytes::<V>(&v)?;
             func(&tk, &tv)
         })
     }
+
+    /// Same as [CandyStore::dry_run_retain_in_list], but `list_key` is typed
+    pub fn dry_run_retain<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        mut func: impl FnMut(&K, &V) -> Result<bool>,
+    ) -> Result<DryRunReport>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = self.make_list_key(list_key);
+        self.store.owned_dry_run_retain_in_list(list_key, |k, v| {
+            let tk = from_bytes::<K>(k)?;
+            let tv = from_bytes::<V>(v)?;
+            func(&tk, &tv)
+        })
+    }
+}
+
+/// The window within which [CandyTypedDeque::push_unique] suppresses pushes of the same deduplication key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupWindow {
+    /// a key is suppressed for this long after it was last pushed (based on the wall-clock, so it survives
+    /// restarts)
+    Time(Duration),
+    /// a key is suppressed as long as it is one of the last this many distinct keys pushed
+    Count(usize),
 }
 
+// appended to the queue's key to form the key of the list that indexes its deduplication keys
+const DEDUP_INDEX_SUFFIX: &[u8] = b"\0dedup-index";
+
 /// A wrapper around [CandyStore] that exposes the queue API in a typed manner. See [CandyTypedStore] for more
 /// info
 pub struct CandyTypedDeque<L, V> {
-    store: Arc<CandyStore>,
+    store: CandyStore,
+    ns: Vec<u8>,
     _phantom: PhantomData<(L, V)>,
 }
 
@@ -574,6 +943,7 @@ impl<L, V> Clone for CandyTypedDeque<L, V> {
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),
+            ns: self.ns.clone(),
             _phantom: Default::default(),
         }
     }
@@ -584,13 +954,36 @@ where
     L: CandyTypedKey,
     V: Encode + DecodeOwned,
 {
-    pub fn new(store: Arc<CandyStore>) -> Self {
+    pub fn new(store: CandyStore) -> Self {
         Self {
             store,
+            ns: vec![],
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Returns a wrapper over the same store whose queues are scoped to the given namespace, see
+    /// [CandyTypedStore::in_namespace]
+    pub fn in_namespace<B: AsRef<[u8]> + ?Sized>(&self, ns: &B) -> Self {
+        Self {
+            store: self.store.clone(),
+            ns: ns.as_ref().to_owned(),
             _phantom: Default::default(),
         }
     }
 
+    /// Returns the namespace this wrapper is scoped to, see [Self::in_namespace]
+    pub fn namespace(&self) -> &[u8] {
+        &self.ns
+    }
+
+    fn make_queue_key<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Vec<u8>
+    where
+        L: Borrow<Q>,
+    {
+        make_typed_list_key::<L, Q>(&self.ns, queue_key)
+    }
+
     /// Pushes a value at the beginning (head) of the queue
     pub fn push_head<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
         &self,
@@ -601,7 +994,7 @@ where
         L: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let val = val.to_bytes::<LE>();
         self.store.push_to_queue_head(&queue_key, &val)?;
         Ok(())
@@ -617,18 +1010,123 @@ where
         L: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let val = val.to_bytes::<LE>();
         self.store.push_to_queue_tail(&queue_key, &val)?;
         Ok(())
     }
 
+    /// Pushes all the given values at the end (tail) of the queue, in order, returning the range of indices they
+    /// were assigned. The queue is locked and its header is written only once. See [CandyStore::extend_queue]
+    pub fn push_many_tail<Q1: ?Sized + Encode, Q2: Encode>(
+        &self,
+        queue_key: &Q1,
+        vals: &[Q2],
+    ) -> Result<Range<usize>>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        self.store
+            .extend_queue(&queue_key, vals.iter().map(|v| v.to_bytes::<LE>()))
+    }
+
+    /// Pushes a value at the end (tail) of the queue, unless a value with the same `dedup_key` was pushed (by this
+    /// function) within the given window, e.g., to suppress retries of the same webhook. Returns true if the
+    /// value was pushed, false if it was suppressed.
+    ///
+    /// The deduplication keys are kept in a side index (a list) per queue, which is pruned as keys fall out of
+    /// the window. Popping values does not affect the index. The index is updated before the value is pushed, so
+    /// a crash in between may suppress the value until its key falls out of the window
+    pub fn push_unique<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        dedup_key: &Q2,
+        val: &Q3,
+        window: DedupWindow,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q3>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        let mut index_key = queue_key.clone();
+        index_key.extend_from_slice(DEDUP_INDEX_SUFFIX);
+        let dedup_key = dedup_key.to_bytes::<LE>();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let now_bytes = now_ms.to_le_bytes();
+
+        let is_expired = |ts_bytes: &[u8]| match window {
+            DedupWindow::Time(dur) => {
+                let ts = u64::from_le_bytes(ts_bytes.try_into().unwrap_or_default());
+                now_ms.saturating_sub(ts) >= dur.as_millis() as u64
+            }
+            DedupWindow::Count(_) => false,
+        };
+
+        // keys are kept in the order they were pushed, so the expired ones are at the head
+        if let DedupWindow::Time(_) = window {
+            while let Some((k, ts_bytes)) = self.store.peek_list_head(&index_key)? {
+                if !is_expired(&ts_bytes) {
+                    break;
+                }
+                self.store.remove_from_list(&index_key, &k)?;
+            }
+        }
+
+        match self
+            .store
+            .get_or_create_in_list(&index_key, &dedup_key, &now_bytes)?
+        {
+            GetOrCreateStatus::CreatedNew(_) => {}
+            GetOrCreateStatus::ExistingValue(ts_bytes) => {
+                // the key may have expired after pruning. Only one of the concurrent pushers gets to refresh it
+                if !is_expired(&ts_bytes)
+                    || !self
+                        .store
+                        .replace_in_list(
+                            &index_key,
+                            &dedup_key,
+                            &now_bytes[..],
+                            Some(&ts_bytes[..]),
+                        )?
+                        .was_replaced()
+                {
+                    return Ok(false);
+                }
+                // move it to the tail, to keep the index ordered by time
+                self.store
+                    .set_in_list_promoting(&index_key, &dedup_key, &now_bytes)?;
+            }
+        }
+
+        if let DedupWindow::Count(count) = window {
+            while self.store.list_len(&index_key)? > count.max(1) {
+                self.store.pop_list_head(&index_key)?;
+            }
+        }
+
+        if let Err(e) = self
+            .store
+            .push_to_queue_tail(&queue_key, &val.to_bytes::<LE>())
+        {
+            // let the caller retry
+            _ = self.store.remove_from_list(&index_key, &dedup_key);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
     /// Pops a value from the beginning (head) of the queue
     pub fn pop_head_with_idx<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(usize, V)>>
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let Some((idx, v)) = self.store.pop_queue_head_with_idx(&queue_key)? else {
             return Ok(None);
         };
@@ -648,7 +1146,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let Some((idx, v)) = self.store.pop_queue_tail_with_idx(&queue_key)? else {
             return Ok(None);
         };
@@ -663,6 +1161,88 @@ where
         Ok(self.pop_tail_with_idx(queue_key)?.map(|iv| iv.1))
     }
 
+    /// Pops up to `max_items` values (and their indices) from the beginning (head) of the queue, in head-to-tail
+    /// order. See [CandyStore::pop_queue_head_many]
+    pub fn pop_many_head<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        max_items: usize,
+    ) -> Result<Vec<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        self.store
+            .pop_queue_head_many(&queue_key, max_items)?
+            .into_iter()
+            .map(|(idx, v)| Ok((idx, from_bytes::<V>(&v)?)))
+            .collect()
+    }
+
+    /// Pops up to `max_items` values (and their indices) from the end (tail) of the queue, in tail-to-head
+    /// order. See [CandyStore::pop_queue_tail_many]
+    pub fn pop_many_tail<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        max_items: usize,
+    ) -> Result<Vec<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        self.store
+            .pop_queue_tail_many(&queue_key, max_items)?
+            .into_iter()
+            .map(|(idx, v)| Ok((idx, from_bytes::<V>(&v)?)))
+            .collect()
+    }
+
+    /// Returns the value at the given index of the queue, or None if it does not exist. See
+    /// [CandyStore::get_from_queue]
+    pub fn get_by_idx<Q: ?Sized + Encode>(&self, queue_key: &Q, idx: usize) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        let Some(v) = self.store.get_from_queue(&queue_key, idx)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<V>(&v)?))
+    }
+
+    /// Removes the value at the given index of the queue, returning it, or None if it did not exist. See
+    /// [CandyStore::remove_from_queue]
+    pub fn remove_by_idx<Q: ?Sized + Encode>(&self, queue_key: &Q, idx: usize) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        let Some(v) = self.store.remove_from_queue(&queue_key, idx)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<V>(&v)?))
+    }
+
+    /// Replaces the value at the given index of the queue, returning the previous value, or None if it
+    /// did not exist. See [CandyStore::replace_in_queue]
+    pub fn replace_by_idx<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        idx: usize,
+        val: &Q2,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = self.make_queue_key(queue_key);
+        let val = val.to_bytes::<LE>();
+        let Some(v) = self.store.replace_in_queue(&queue_key, idx, &val)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<V>(&v)?))
+    }
+
     /// Peek at the value from the beginning (head) of the queue and its index
     pub fn peek_head_with_idx<Q: ?Sized + Encode>(
         &self,
@@ -671,7 +1251,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let Some((idx, v)) = self.store.peek_queue_head_with_idx(&queue_key)? else {
             return Ok(None);
         };
@@ -694,7 +1274,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         let Some((idx, v)) = self.store.peek_queue_tail_with_idx(&queue_key)? else {
             return Ok(None);
         };
@@ -717,7 +1297,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         self.store.iter_queue(&queue_key).map(|res| match res {
             Err(e) => Err(e),
             Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v).unwrap())),
@@ -732,7 +1312,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         self.store
             .iter_queue_backwards(&queue_key)
             .map(|res| match res {
@@ -745,7 +1325,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         self.store.queue_len(&queue_key)
     }
 
@@ -753,7 +1333,7 @@ where
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let queue_key = self.make_queue_key(queue_key);
         self.store.queue_range(&queue_key)
     }
 }
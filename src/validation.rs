@@ -0,0 +1,169 @@
+use crate::{
+    key_prefixes::KeyPrefixes, router::ShardRouter, Config, MAX_TOTAL_KEY_SIZE,
+    MAX_TOTAL_VALUE_SIZE, MAX_VALUE_SIZE,
+};
+
+/// The result of [Config::validate]: problems found in the configuration, along with the effective values
+/// that the store derives from it
+#[derive(Debug, Clone, Default)]
+pub struct ConfigReport {
+    /// settings that the store cannot work with. [crate::CandyStore::open] fails if there are any
+    pub errors: Vec<String>,
+    /// settings that are accepted, but are likely not what was intended
+    pub warnings: Vec<String>,
+    /// the number of keyed locks (`max_concurrent_list_ops`, rounded up to a power of two, minimum 4)
+    pub num_keyed_locks: usize,
+    /// the number of shards a new store starts with (derived from `expected_number_of_keys`)
+    pub initial_num_shards: usize,
+    /// the largest (user) value that can be stored in a single entry, i.e., with [crate::CandyStore::set]
+    pub max_value_size: usize,
+}
+
+impl ConfigReport {
+    /// Returns true if there are no errors (there may be warnings)
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub(crate) fn num_keyed_locks(max_concurrent_list_ops: u32) -> u32 {
+    let num_keyed_locks = max_concurrent_list_ops.max(4);
+    if num_keyed_locks.is_power_of_two() {
+        num_keyed_locks
+    } else {
+        1 << (num_keyed_locks.ilog2() + 1)
+    }
+}
+
+impl Config {
+    /// Checks the configuration for invalid or conflicting settings, and reports the effective values derived
+    /// from it. [crate::CandyStore::open] performs the same checks (failing on errors), and
+    /// [crate::CandyStore::open_with_report] returns the report
+    pub fn validate(&self) -> ConfigReport {
+        let mut report = ConfigReport {
+            num_keyed_locks: num_keyed_locks(self.max_concurrent_list_ops) as usize,
+            initial_num_shards: ShardRouter::calc_num_shards(self.expected_number_of_keys) as usize,
+            max_value_size: MAX_VALUE_SIZE,
+            ..Default::default()
+        };
+
+        // an entry (key and value, including the namespacing suffixes) must fit in a single shard
+        let max_entry_size = MAX_TOTAL_KEY_SIZE + MAX_TOTAL_VALUE_SIZE;
+        if (self.max_shard_size as usize) < max_entry_size {
+            report.max_value_size =
+                (self.max_shard_size as usize).saturating_sub(MAX_TOTAL_KEY_SIZE);
+            report.warnings.push(format!(
+                "max_shard_size ({}) is smaller than the largest entry ({max_entry_size}), large entries \
+                 will fail with EntryCannotFitInShard",
+                self.max_shard_size
+            ));
+        }
+        if self.min_compaction_threashold >= self.max_shard_size {
+            report.warnings.push(format!(
+                "min_compaction_threashold ({}) is not smaller than max_shard_size ({}), so shards will be \
+                 split rather than compacted",
+                self.min_compaction_threashold, self.max_shard_size
+            ));
+        } else if self.min_compaction_threashold == 0 {
+            report.warnings.push(
+                "min_compaction_threashold is zero, so every overwrite triggers a compaction"
+                    .into(),
+            );
+        }
+
+        if self.num_compaction_threads == 0 {
+            report.errors.push(
+                "num_compaction_threads must be at least 1, compactions would never finish".into(),
+            );
+        }
+        if let Some(threshold) = self.background_split_threshold {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                report.errors.push(format!(
+                    "background_split_threshold must be in (0, 1], got {threshold}"
+                ));
+            } else if threshold < 0.5 {
+                report.warnings.push(format!(
+                    "background_split_threshold ({threshold}) is low, shards will be split while mostly empty"
+                ));
+            }
+        }
+        if let Some(level) = self.soft_limits.shard_fill_level {
+            if !(level > 0.0 && level <= 1.0) {
+                report.errors.push(format!(
+                    "soft_limits.shard_fill_level must be in (0, 1], got {level}"
+                ));
+            }
+        }
+        if let Some(nice) = self.maintenance_thread_nice {
+            if !(-20..=19).contains(&nice) {
+                report.warnings.push(format!(
+                    "maintenance_thread_nice ({nice}) is out of range (-20..=19), and will be clamped by the OS"
+                ));
+            }
+        }
+        if self.yield_every == Some(0) {
+            report
+                .errors
+                .push("yield_every must be at least 1 (or None to never yield)".into());
+        }
+        if self.preallocate_size > self.max_shard_size as u64 {
+            report.warnings.push(format!(
+                "preallocate_size ({}) exceeds max_shard_size ({}), and will be capped",
+                self.preallocate_size, self.max_shard_size
+            ));
+        }
+        if self.preallocate_increment > self.max_shard_size as u64 {
+            report.warnings.push(format!(
+                "preallocate_increment ({}) exceeds max_shard_size ({}), so shards will be fully allocated \
+                 on their first write",
+                self.preallocate_increment, self.max_shard_size
+            ));
+        }
+        if self
+            .export_signing_key
+            .as_ref()
+            .is_some_and(|key| key.is_empty())
+        {
+            report
+                .errors
+                .push("export_signing_key must not be empty (or None to not sign exports)".into());
+        }
+        if self.max_concurrent_list_ops as usize != report.num_keyed_locks {
+            report.warnings.push(format!(
+                "max_concurrent_list_ops ({}) is rounded up to {}",
+                self.max_concurrent_list_ops, report.num_keyed_locks
+            ));
+        }
+
+        if let Err(e) = KeyPrefixes::new(&self.key_prefixes) {
+            report.errors.push(e.to_string());
+        }
+        for (i, prefix) in self.key_prefixes.iter().enumerate() {
+            if self.key_prefixes[..i].contains(prefix) {
+                report
+                    .warnings
+                    .push(format!("key prefix {prefix:?} appears more than once"));
+            }
+        }
+
+        if self.dedup_min_value_size > MAX_VALUE_SIZE {
+            report.warnings.push(format!(
+                "dedup_min_value_size ({}) exceeds the max value size ({MAX_VALUE_SIZE}), so values are never \
+                 deduplicated",
+                self.dedup_min_value_size
+            ));
+        }
+
+        // interned keys are stored as 8 byte ids
+        if let Some(len) = self.intern_keys_longer_than {
+            if len < 8 {
+                report.warnings.push(format!(
+                    "intern_keys_longer_than ({len}) is shorter than an interned key's id (8 bytes), so \
+                     interning short keys takes up more space"
+                ));
+            }
+        }
+
+        report
+    }
+}
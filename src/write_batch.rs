@@ -0,0 +1,148 @@
+use crate::{journal::ListLockGuard, CandyStore, Result};
+
+#[derive(Debug, Clone)]
+enum BatchOp {
+    Set {
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+    SetInList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        val: Vec<u8>,
+    },
+}
+
+/// A sequence of writes that [CandyStore::write_batch] applies atomically, e.g., updating a value along with
+/// its membership in a list. The operations are applied in the order they were added
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a [CandyStore::set] of the given key
+    pub fn set<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        key: &B1,
+        val: &B2,
+    ) -> &mut Self {
+        self.ops.push(BatchOp::Set {
+            key: key.as_ref().to_owned(),
+            val: val.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// Adds a [CandyStore::remove] of the given key
+    pub fn remove<B: AsRef<[u8]> + ?Sized>(&mut self, key: &B) -> &mut Self {
+        self.ops.push(BatchOp::Remove {
+            key: key.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// Adds a [CandyStore::set_in_list] of the given item
+    pub fn set_in_list<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &mut self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+    ) -> &mut Self {
+        self.ops.push(BatchOp::SetInList {
+            list_key: list_key.as_ref().to_owned(),
+            item_key: item_key.as_ref().to_owned(),
+            val: val.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// The number of operations in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl CandyStore {
+    /// Applies the operations of the batch, all or nothing: if one of them fails, the ones applied before it
+    /// are undone and the error is returned, and if the process crashes midway, they are undone when the store
+    /// is next opened. Likewise, if committing (or undoing) the batch fails, its journal entries are left for
+    /// the next open to recover, and the store is reported as degraded by [Self::health] until then; a failed
+    /// commit is returned as the batch's error. Like [crate::Config::list_journal], this journals the previous value of every key the
+    /// batch writes, so each write costs a read and a few extra writes.
+    ///
+    /// Batches are isolated from other operations: the lists a batch modifies are locked for the whole batch,
+    /// and so is every key it writes, from its first write until the batch is committed or rolled back.
+    /// Operations on them wait for the batch to end, so they observe it either entirely or not at all, and undoing
+    /// a batch never overwrites the writes of other threads. Batches are applied one at a time, and iterations
+    /// (e.g., [Self::iter]) are not isolated from them. Pinned list headers (see [Self::pin_list]) are kept in
+    /// memory and are not undone. Batches cannot be applied from within list operations (e.g., from a
+    /// [Self::retain_in_list] callback)
+    pub fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
+        // fail early on what can be checked before anything is written
+        for op in batch.ops.iter() {
+            if let BatchOp::Set { key, val } = op {
+                Self::ensure_sizes(key, val)?;
+            }
+        }
+
+        // the locks of the lists are taken upfront (in order), since taking them midway could deadlock with
+        // operations that wait for the keys the batch has written
+        let list_slots = batch
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                BatchOp::Set { key, .. } | BatchOp::Remove { key } => {
+                    self.key_history_lock_slot(key)
+                }
+                BatchOp::SetInList { list_key, .. } => Some(self.list_lock_slot(list_key)),
+            })
+            .collect();
+        let locks = self.lock_batch(list_slots)?;
+        let guard = ListLockGuard::new_batch(self, &locks);
+        let res = batch.ops.iter().try_for_each(|op| {
+            match op {
+                BatchOp::Set { key, val } => {
+                    self.set(key, val)?;
+                }
+                BatchOp::Remove { key } => {
+                    self.remove(key)?;
+                }
+                BatchOp::SetInList {
+                    list_key,
+                    item_key,
+                    val,
+                } => {
+                    self.set_in_list(list_key, item_key, val)?;
+                }
+            }
+            Ok(())
+        });
+        // the transaction ends (and is rolled back, if needed) before the locks are released. when the rollback
+        // fails, the batch's error is returned, and the failure is reported by CandyStore::health
+        let res = match res {
+            Ok(()) => guard.finish(),
+            Err(e) => {
+                guard.roll_back();
+                Err(e)
+            }
+        };
+        drop(locks);
+        res
+    }
+}
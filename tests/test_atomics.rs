@@ -1,6 +1,8 @@
 mod common;
 
-use candystore::{CandyStore, Config, GetOrCreateStatus, ReplaceStatus, Result, SetStatus};
+use candystore::{
+    CandyError, CandyStore, Config, GetOrCreateStatus, ReplaceStatus, Result, SetStatus,
+};
 
 use crate::common::run_in_tempdir;
 
@@ -38,3 +40,50 @@ fn test_atomics() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_patch() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        assert!(db.patch("aaa", 0, "xx", None)?.is_key_missing());
+
+        db.set("aaa", "0123456789")?;
+        assert_eq!(
+            db.patch("aaa", 2, "xy", None)?,
+            ReplaceStatus::PrevValue("23".into())
+        );
+        assert_eq!(db.get("aaa")?, Some("01xy456789".into()));
+
+        assert_eq!(
+            db.patch("aaa", 8, "zz", Some("00"))?,
+            ReplaceStatus::WrongValue("89".into())
+        );
+        assert_eq!(
+            db.patch("aaa", 8, "zz", Some("89"))?,
+            ReplaceStatus::PrevValue("89".into())
+        );
+        assert_eq!(db.get("aaa")?, Some("01xy4567zz".into()));
+
+        assert!(matches!(
+            db.patch("aaa", 9, "zz", None),
+            Err(CandyError::PatchOutOfBounds(9, 2, 10))
+        ));
+        // offsets so large that the end of the patch overflows are out of bounds as well
+        assert!(matches!(
+            db.patch("aaa", usize::MAX - 1, "zz", None),
+            Err(CandyError::PatchOutOfBounds(_, 2, 10))
+        ));
+        assert!(matches!(
+            db.patch("aaa", usize::MAX, "z", Some("z")),
+            Err(CandyError::PatchOutOfBounds(usize::MAX, 1, 10))
+        ));
+        assert!(matches!(
+            db.patch("aaa", 0, "zz", Some("z")),
+            Err(CandyError::PatchLengthMismatch(1, 2))
+        ));
+        assert_eq!(db.get("aaa")?, Some("01xy4567zz".into()));
+
+        Ok(())
+    })
+}
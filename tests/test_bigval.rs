@@ -1,7 +1,5 @@
 mod common;
 
-use std::sync::Arc;
-
 use candystore::{CandyStore, CandyTypedStore, Config, Result};
 
 use crate::common::run_in_tempdir;
@@ -9,7 +7,7 @@ use crate::common::run_in_tempdir;
 #[test]
 fn test_bigval() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
         assert_eq!(db.set_big(b"mykey", &vec![0x99; 1_000_000])?, false);
         assert_eq!(db.get_big(b"yourkey")?, None);
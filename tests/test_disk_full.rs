@@ -0,0 +1,153 @@
+#![cfg(feature = "whitebox_testing")]
+
+mod common;
+
+use std::sync::{atomic::Ordering, Mutex};
+
+use candystore::{
+    CandyError, CandyStore, Config, DiskFullPolicy, Result, WriteBatch, WRITES_BEFORE_DISK_FULL,
+};
+
+use crate::common::run_in_tempdir;
+
+fn is_enospc(e: &CandyError) -> bool {
+    matches!(e, CandyError::Io(e) if e.raw_os_error() == Some(libc::ENOSPC))
+}
+
+// the disk is "full" from the point the hook is set until it's reset, regardless of the store, so the tests that
+// set it do not run concurrently
+static DISK_FULL_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_disk_full() -> Result<()> {
+    let _lock = DISK_FULL_LOCK.lock().unwrap();
+    run_in_tempdir(|dir| {
+        // by default, writes that run out of space fail, and the store keeps accepting writes
+        let db = CandyStore::open(dir, Config::default())?;
+        db.set("a", "1")?;
+        WRITES_BEFORE_DISK_FULL.store(0, Ordering::SeqCst);
+        assert!(is_enospc(&db.set("b", "2").unwrap_err()));
+        assert!(is_enospc(&db.set("a", "3").unwrap_err()));
+        assert!(!db.is_degraded());
+        assert!(db.health()?.ok);
+        WRITES_BEFORE_DISK_FULL.store(u64::MAX, Ordering::SeqCst);
+        db.set("b", "2")?;
+        assert_eq!(db.get("a")?, Some("1".into()));
+        drop(db);
+
+        // with ReadOnly, the first write that runs out of space switches the store to read-only
+        let db = CandyStore::open(
+            dir,
+            Config {
+                disk_full_policy: DiskFullPolicy::ReadOnly,
+                ..Default::default()
+            },
+        )?;
+        WRITES_BEFORE_DISK_FULL.store(3, Ordering::SeqCst);
+        let mut num_written = 0;
+        let err = loop {
+            match db.set(&format!("key{num_written}"), "val") {
+                Ok(_) => num_written += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, CandyError::StoreFull), "{err}");
+        assert_eq!(num_written, 3);
+        assert!(db.is_degraded());
+
+        // freeing space does not leave the mode: all modifications keep failing, and reads keep working
+        WRITES_BEFORE_DISK_FULL.store(u64::MAX, Ordering::SeqCst);
+        assert!(matches!(db.set("c", "3"), Err(CandyError::StoreFull)));
+        assert!(matches!(db.remove("a"), Err(CandyError::StoreFull)));
+        assert!(matches!(
+            db.set_in_list("list", "x", "y"),
+            Err(CandyError::StoreFull)
+        ));
+        assert_eq!(db.get("a")?, Some("1".into()));
+        assert_eq!(db.get("key2")?, Some("val".into()));
+        assert_eq!(db.get("key3")?, None);
+        let health = db.health()?;
+        assert!(!health.ok);
+        assert!(
+            health
+                .degraded_reasons
+                .iter()
+                .any(|r| r.contains("read-only")),
+            "{:?}",
+            health.degraded_reasons
+        );
+        drop(db);
+
+        // reopening the store leaves the mode
+        let db = CandyStore::open(
+            dir,
+            Config {
+                disk_full_policy: DiskFullPolicy::ReadOnly,
+                ..Default::default()
+            },
+        )?;
+        assert!(!db.is_degraded());
+        db.set("c", "3")?;
+        db.remove("a")?;
+        assert_eq!(db.get("key2")?, Some("val".into()));
+
+        // and so does clearing it
+        WRITES_BEFORE_DISK_FULL.store(0, Ordering::SeqCst);
+        assert!(matches!(db.set("d", "4"), Err(CandyError::StoreFull)));
+        assert!(db.is_degraded());
+        WRITES_BEFORE_DISK_FULL.store(u64::MAX, Ordering::SeqCst);
+        db.clear()?;
+        assert!(!db.is_degraded());
+        db.set("d", "4")?;
+        assert_eq!(db.get("c")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_disk_full_write_batch() -> Result<()> {
+    let _lock = DISK_FULL_LOCK.lock().unwrap();
+    run_in_tempdir(|dir| {
+        CandyStore::open(dir, Config::default())?.set("a", "0")?;
+        let mut batch = WriteBatch::new();
+        batch.set("a", "1").set("b", "2");
+
+        // run out of space at every write of the batch in turn, until it goes through. once the batch wrote "a",
+        // restoring its previous value runs out of space as well (and at the last write, committing the batch
+        // does), leaving the transaction for the next open to roll back
+        let mut num_unfinished = 0;
+        for writes in 0.. {
+            assert!(writes < 100);
+            let db = CandyStore::open(dir, Config::default())?;
+            WRITES_BEFORE_DISK_FULL.store(writes, Ordering::SeqCst);
+            let res = db.write_batch(&batch);
+            WRITES_BEFORE_DISK_FULL.store(u64::MAX, Ordering::SeqCst);
+            let Err(e) = res else {
+                assert_eq!(db.get("a")?, Some("1".into()));
+                assert_eq!(db.get("b")?, Some("2".into()));
+                break;
+            };
+            assert!(is_enospc(&e), "{e}");
+            let health = db.health()?;
+            if health
+                .degraded_reasons
+                .iter()
+                .any(|r| r.contains("failed to commit or roll back"))
+            {
+                num_unfinished += 1;
+            } else {
+                assert!(health.ok, "{:?}", health.degraded_reasons);
+            }
+            drop(db);
+
+            let db = CandyStore::open(dir, Config::default())?;
+            assert!(db.health()?.ok);
+            assert_eq!(db.get("a")?, Some("0".into()));
+            assert_eq!(db.get("b")?, None);
+        }
+        assert!(num_unfinished > 1, "{num_unfinished}");
+
+        Ok(())
+    })
+}
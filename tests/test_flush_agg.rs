@@ -14,13 +14,13 @@ use crate::common::run_in_tempdir;
 #[test]
 fn test_flush_aggregation() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(
+        let db = CandyStore::open(
             dir,
             Config {
                 flush_aggregation_delay: Some(Duration::from_millis(1)),
                 ..Default::default()
             },
-        )?);
+        )?;
 
         let num_threads = 10;
         let barrier = Arc::new(Barrier::new(num_threads));
@@ -0,0 +1,157 @@
+#![cfg(feature = "fuzzing")]
+
+mod common;
+
+use candystore::{
+    fuzzing::{
+        arbitrary::{Arbitrary, Unstructured},
+        open_shard_file, parse_chain_key, parse_list_header, parse_shard_header, parse_shard_row,
+        ChainKeyInfo, ListHeaderInfo, ShardHeaderInfo, SHARD_HEADER_SIZE,
+    },
+    CandyError, Result,
+};
+use rand::RngCore;
+
+use crate::common::run_in_tempdir;
+
+#[test]
+fn test_fuzzing_parsers() -> Result<()> {
+    let list = ListHeaderInfo {
+        head_idx: 10,
+        tail_idx: 20,
+        num_items: 7,
+    };
+    assert_eq!(parse_list_header(&list.to_bytes())?, list);
+    assert!(matches!(
+        parse_list_header(&list.to_bytes()[1..]),
+        Err(CandyError::Corruption(_))
+    ));
+    for bad in [
+        ListHeaderInfo {
+            head_idx: 20,
+            tail_idx: 10,
+            num_items: 0,
+        },
+        ListHeaderInfo {
+            head_idx: 10,
+            tail_idx: 20,
+            num_items: 11,
+        },
+    ] {
+        assert!(matches!(
+            parse_list_header(&bad.to_bytes()),
+            Err(CandyError::Corruption(_))
+        ));
+    }
+
+    let ck = ChainKeyInfo {
+        list_hash: 0x1234_5678_9abc_def0,
+        idx: 17,
+    };
+    assert_eq!(parse_chain_key(&ck.to_bytes())?, ck);
+    let mut bad_ns = ck.to_bytes();
+    *bad_ns.last_mut().unwrap() = 99;
+    assert!(parse_chain_key(&bad_ns).is_err());
+    assert!(parse_chain_key(&[]).is_err());
+
+    let hdr = ShardHeaderInfo {
+        wasted_bytes: 100,
+        write_offset: 1000,
+        num_inserts: 20,
+        num_removals: 5,
+        compacted_up_to: 0,
+        key_prefixes_fingerprint: 0,
+    };
+    let file_size = SHARD_HEADER_SIZE + 1000;
+    assert_eq!(parse_shard_header(&hdr.to_bytes(), file_size)?, hdr);
+    assert!(matches!(
+        parse_shard_header(&hdr.to_bytes(), SHARD_HEADER_SIZE + 999),
+        Err(CandyError::Corruption(_))
+    ));
+    assert!(matches!(
+        parse_shard_header(&hdr.to_bytes(), 100),
+        Err(CandyError::Corruption(_))
+    ));
+    assert!(matches!(
+        parse_shard_header(b"NotCandy", file_size),
+        Err(CandyError::Corruption(_))
+    ));
+    let mut old_version = hdr.to_bytes();
+    old_version[8] ^= 0xff;
+    assert!(matches!(
+        parse_shard_header(&old_version, file_size),
+        Err(CandyError::WrongVersion(..))
+    ));
+    // version 11 files predate the key prefix dictionary and the layout flags
+    old_version[8..16].copy_from_slice(&11u64.to_ne_bytes());
+    assert!(matches!(
+        parse_shard_header(&old_version, file_size),
+        Err(CandyError::WrongVersion(_, 11))
+    ));
+
+    // a row is 512 signatures followed by 512 offsets_and_sizes
+    let mut row = vec![0u8; 512 * 12];
+    row[4..8].copy_from_slice(&77u32.to_ne_bytes());
+    let entry: u64 = (5 << 48) | (10 << 32) | 100;
+    row[2048 + 8..2048 + 16].copy_from_slice(&entry.to_ne_bytes());
+    let entries = parse_shard_row(&row, 115)?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].signature, 77);
+    assert_eq!(
+        (entries[0].key_len, entries[0].val_len, entries[0].offset),
+        (5, 10, 100)
+    );
+    assert!(matches!(
+        parse_shard_row(&row, 114),
+        Err(CandyError::Corruption(_))
+    ));
+    assert!(parse_shard_row(&row[1..], 115).is_err());
+
+    // arbitrary inputs must never panic
+    let mut data = vec![0u8; 8192];
+    for _ in 0..1000 {
+        rand::rng().fill_bytes(&mut data);
+        let mut u = Unstructured::new(&data);
+        let list = ListHeaderInfo::arbitrary(&mut u).unwrap();
+        _ = parse_list_header(&list.to_bytes());
+        let hdr = ShardHeaderInfo::arbitrary(&mut u).unwrap();
+        _ = parse_shard_header(&hdr.to_bytes(), u64::arbitrary(&mut u).unwrap());
+        _ = parse_shard_row(&data[..512 * 12], 1 << 20);
+        _ = parse_chain_key(&data[..17]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_fuzzing_open_shard_file() -> Result<()> {
+    run_in_tempdir(|dir| {
+        assert!(matches!(
+            open_shard_file(format!("{dir}/garbage"), b"garbage"),
+            Err(CandyError::Corruption(_))
+        ));
+
+        // a valid header followed by a truncated (partially written) body
+        let hdr = ShardHeaderInfo {
+            wasted_bytes: 0,
+            write_offset: 0,
+            num_inserts: 0,
+            num_removals: 0,
+            compacted_up_to: 0,
+            key_prefixes_fingerprint: 0,
+        };
+        let mut contents = hdr.to_bytes();
+        contents.resize(4096, 0);
+        assert!(matches!(
+            open_shard_file(format!("{dir}/truncated"), &contents),
+            Err(CandyError::Corruption(_))
+        ));
+
+        // a full header with random rows, pointing to entries that may be out of bounds
+        contents.resize(SHARD_HEADER_SIZE as usize + 1000, 0);
+        rand::rng().fill_bytes(&mut contents[4096..]);
+        _ = open_shard_file(format!("{dir}/random"), &contents);
+
+        Ok(())
+    })
+}
@@ -2,14 +2,20 @@
 
 mod common;
 
-use candystore::{CandyStore, Config, Result, HASH_BITS_TO_KEEP};
+use candystore::{CandyStore, CollisionKind, Config, Result, HASH_BITS_TO_KEEP};
 
 use crate::common::run_in_tempdir;
 
 #[test]
 fn test_list_collisions() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = CandyStore::open(dir, Config::default())?;
+        let db = CandyStore::open(
+            dir,
+            Config {
+                collision_log_capacity: 16,
+                ..Default::default()
+            },
+        )?;
 
         db.clear()?;
 
@@ -68,6 +74,20 @@ fn test_list_collisions() -> Result<()> {
         let expectd = (100..400).chain(600..900).collect::<Vec<_>>();
         assert_eq!(remaining, expectd);
 
+        // the collisions are counted and the last ones are logged
+        let stats = db.stats();
+        assert!(stats.num_collisions > 0);
+        assert!(stats.num_lookup_collisions > 0);
+        let log = db.collision_log();
+        assert_eq!(log.len(), 16);
+        for rec in log {
+            if rec.kind == CollisionKind::ListChain {
+                assert!(!rec.other_key.ends_with(&rec.key));
+            } else {
+                assert_ne!(rec.key, rec.other_key);
+            }
+        }
+
         db.discard_list("xxx")?;
         assert!(db.pop_list_head("xxx")?.is_none());
 
@@ -1,10 +1,15 @@
 mod common;
 
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::{
+    sync::{atomic::AtomicUsize, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use candystore::{
-    CandyStore, CandyTypedDeque, CandyTypedList, Config, GetOrCreateStatus, ListCompactionParams,
-    ReplaceStatus, Result, SetStatus,
+    CancellationToken, CandyError, CandyGraph, CandyInvertedIndex, CandyStore, CandyTypedDeque,
+    CandyTypedList, Config, CursorLag, DedupWindow, ExportFilter, GeoMatch, GetOrCreateStatus,
+    IndexQueryMode, ListAuditOp, ListCompactionParams, ListRecoveryPolicy, Namespace, Progress,
+    ReplaceStatus, Result, SetStatus, SstParams,
 };
 
 use crate::common::run_in_tempdir;
@@ -35,6 +40,8 @@ fn test_lists() -> Result<()> {
 
         assert_eq!(db.iter_list("texas").count(), 3);
         assert_eq!(db.list_len("texas")?, 3);
+        assert_eq!(db.list_item_len("texas", "austin")?, Some(7));
+        assert_eq!(db.list_item_len("texas", "el paso")?, None);
         assert_eq!(db.iter_list("arkansas").count(), 0);
         assert_eq!(db.list_len("arkansas")?, 0);
 
@@ -97,9 +104,9 @@ fn test_lists() -> Result<()> {
 #[test]
 fn test_typed_lists() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
-        let typed = CandyTypedList::<String, u64, u32>::new(db);
+        let typed = CandyTypedList::<String, u64, u32>::new(db.clone());
         typed.set("texas", &108, &2005)?;
         typed.set("texas", &555, &2006)?;
         typed.set("texas", &827, &2007)?;
@@ -119,6 +126,18 @@ fn test_typed_lists() -> Result<()> {
             .collect::<Vec<_>>();
         assert_eq!(items, vec![2005, 2009, 2008]);
 
+        let typed = CandyTypedList::<String, String, Vec<u8>>::new(db.clone());
+        typed.set("texas", "dallas", b"dal".as_slice())?;
+        assert_eq!(
+            typed.get(&"texas".to_owned(), &"dallas".to_owned())?,
+            Some(b"dal".to_vec())
+        );
+        assert_eq!(
+            typed.set(&"texas".to_owned(), "dallas", &b"big d".to_vec())?,
+            Some(b"dal".to_vec())
+        );
+        assert_eq!(typed.remove("texas", "dallas")?, Some(b"big d".to_vec()));
+
         Ok(())
     })
 }
@@ -126,7 +145,7 @@ fn test_typed_lists() -> Result<()> {
 #[test]
 fn test_lists_multithreading() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
         let removed = Arc::new(AtomicUsize::new(0));
         let created = Arc::new(AtomicUsize::new(0));
@@ -231,6 +250,61 @@ fn test_list_atomics() -> Result<()> {
 
         assert_eq!(db.get_from_list("xxx", "yyy")?, Some("4".into()));
 
+        assert_eq!(
+            db.replace_in_list("xxx", "yyy", "5", Some("3"))?,
+            ReplaceStatus::WrongValue("4".into())
+        );
+        assert_eq!(
+            db.replace_in_list("xxx", "yyy", "5", Some("4"))?,
+            ReplaceStatus::PrevValue("4".into())
+        );
+        assert_eq!(db.get_from_list("xxx", "yyy")?, Some("5".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_push_unique() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+
+        let window = DedupWindow::Time(Duration::from_millis(300));
+        assert!(queue.push_unique("hooks", "req1", &1, window)?);
+        assert!(queue.push_unique("hooks", "req2", &2, window)?);
+        assert!(!queue.push_unique("hooks", "req1", &3, window)?);
+        assert!(!queue.push_unique("hooks", "req2", &4, window)?);
+
+        // popping does not reset the window
+        assert_eq!(queue.pop_head("hooks")?, Some(1));
+        assert!(!queue.push_unique("hooks", "req1", &5, window)?);
+
+        std::thread::sleep(Duration::from_millis(400));
+        assert!(queue.push_unique("hooks", "req1", &6, window)?);
+        assert!(!queue.push_unique("hooks", "req1", &7, window)?);
+
+        let items = queue
+            .iter("hooks")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![2, 6]);
+
+        // count-based window: only the last two distinct keys are suppressed
+        let window = DedupWindow::Count(2);
+        assert!(queue.push_unique("jobs", "a", &1, window)?);
+        assert!(queue.push_unique("jobs", "b", &2, window)?);
+        assert!(!queue.push_unique("jobs", "a", &3, window)?);
+        assert!(queue.push_unique("jobs", "c", &4, window)?);
+        assert!(queue.push_unique("jobs", "a", &5, window)?);
+        assert!(!queue.push_unique("jobs", "c", &6, window)?);
+
+        let items = queue
+            .iter("jobs")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![1, 2, 4, 5]);
+
         Ok(())
     })
 }
@@ -238,7 +312,7 @@ fn test_list_atomics() -> Result<()> {
 #[test]
 fn test_typed_queue() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
         let queue = CandyTypedDeque::<String, u32>::new(db);
         assert_eq!(queue.pop_head("orders")?, None);
@@ -318,6 +392,38 @@ fn test_rev_iter() -> Result<()> {
     })
 }
 
+#[test]
+fn test_iter_with_indices() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.set_in_list("mylist", "item1", "xxx")?;
+        db.set_in_list("mylist", "item2", "xxx")?;
+        db.set_in_list("mylist", "item3", "xxx")?;
+        db.remove_from_list("mylist", "item2")?;
+
+        let items = db
+            .iter_list_with_indices("mylist")
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].1, b"item1");
+        assert_eq!(items[1].1, b"item3");
+        assert_eq!(items[1].0, items[0].0 + 2);
+
+        let items_rev = db
+            .iter_list_backwards("mylist")
+            .with_indices()
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(items_rev, items.iter().rev().cloned().collect::<Vec<_>>());
+
+        assert_eq!(db.iter_list_with_indices("nosuchlist").count(), 0);
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_promote() -> Result<()> {
     run_in_tempdir(|dir| {
@@ -359,7 +465,7 @@ fn test_promote() -> Result<()> {
 #[test]
 fn test_typed_promote() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
         let typed = CandyTypedList::<String, u32, String>::new(db);
 
         let items = || {
@@ -428,10 +534,76 @@ fn test_list_compaction() -> Result<()> {
     })
 }
 
+#[test]
+fn test_list_order() -> Result<()> {
+    run_in_tempdir(|dir| {
+        // retain yields the list's lock every few items, which must not affect the order either
+        let db = CandyStore::open(
+            dir,
+            Config {
+                yield_every: Some(7),
+                ..Default::default()
+            },
+        )?;
+        let keys = |db: &CandyStore| -> Result<Vec<u32>> {
+            db.iter_list("xxx")
+                .map(|res| Ok(u32::from_le_bytes(res?.0.try_into().unwrap())))
+                .collect()
+        };
+
+        // the expected order: keys are pushed, removed (leaving holes) and promoted to the tail
+        let mut expected = vec![];
+        for i in 0u32..1000 {
+            db.set_in_list("xxx", &i.to_le_bytes(), "yyy")?;
+            expected.push(i);
+        }
+        for i in (0u32..1000).step_by(3) {
+            db.remove_from_list("xxx", &i.to_le_bytes())?;
+            expected.retain(|&k| k != i);
+        }
+        for i in (1u32..1000).step_by(10) {
+            db.set_in_list_promoting("xxx", &i.to_le_bytes(), "zzz")?;
+            expected.retain(|&k| k != i);
+            expected.push(i);
+        }
+        // a promoted key that was not in the list is simply pushed
+        db.set_in_list_promoting("xxx", &2000u32.to_le_bytes(), "zzz")?;
+        expected.push(2000);
+        assert_eq!(keys(&db)?, expected);
+
+        assert!(db.compact_list_if_needed("xxx", ListCompactionParams::default())?);
+        assert_eq!(keys(&db)?, expected);
+
+        for i in (2u32..1000).step_by(4) {
+            db.remove_from_list("xxx", &i.to_le_bytes())?;
+            expected.retain(|&k| k != i);
+        }
+        db.retain_in_list("xxx", |k, _| {
+            Ok(u32::from_le_bytes(k.try_into().unwrap()) % 5 != 0)
+        })?;
+        expected.retain(|&k| k % 5 != 0);
+        assert_eq!(keys(&db)?, expected);
+
+        // promoting after compaction pushes past the re-indexed elements
+        db.set_in_list_promoting("xxx", &expected[0].to_le_bytes(), "zzz")?;
+        expected.rotate_left(1);
+        assert_eq!(keys(&db)?, expected);
+        expected.reverse();
+        assert_eq!(
+            db.iter_list_backwards("xxx")
+                .map(|res| Ok(u32::from_le_bytes(res?.0.try_into().unwrap())))
+                .collect::<Result<Vec<_>>>()?,
+            expected
+        );
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_list_retain() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
         {
             let mut dropped = 0;
@@ -514,3 +686,1356 @@ fn test_list_retain() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_keyed_lock_stats() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_concurrent_list_ops: 5,
+                ..Default::default()
+            },
+        )?;
+
+        let before = db.keyed_lock_stats();
+        assert_eq!(before.len(), 8);
+
+        let slot = db.list_lock_slot("mylist");
+        assert!(slot < 8);
+        db.set_in_list("mylist", "item1", "xxx")?;
+        db.set_in_list("mylist", "item2", "xxx")?;
+
+        let after = db.keyed_lock_stats();
+        assert_eq!(
+            after[slot].num_acquisitions,
+            before[slot].num_acquisitions + 2
+        );
+        assert_eq!(after[slot].num_waiters, 0);
+        assert_eq!(
+            after.iter().map(|s| s.num_acquisitions).sum::<usize>(),
+            before.iter().map(|s| s.num_acquisitions).sum::<usize>() + 2
+        );
+
+        db.clear()?;
+        assert!(db
+            .keyed_lock_stats()
+            .iter()
+            .all(|s| s.num_acquisitions == 0 && s.num_contended == 0));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_graph() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let friends = CandyGraph::new(db.clone(), "friends");
+        let enemies = CandyGraph::new(db.clone(), "enemies");
+
+        assert!(friends.add_edge("alice", "bob")?);
+        assert!(!friends.add_edge("bob", "alice")?);
+        assert!(friends.add_edge("alice", "carol")?);
+        assert!(friends.add_edge("dave", "dave")?);
+        assert!(enemies.add_edge("alice", "eve")?);
+
+        assert!(friends.has_edge("bob", "alice")?);
+        assert!(!friends.has_edge("alice", "eve")?);
+        assert!(enemies.has_edge("eve", "alice")?);
+
+        assert_eq!(friends.degree("alice")?, 2);
+        assert_eq!(friends.degree("bob")?, 1);
+        assert_eq!(friends.degree("dave")?, 1);
+        assert_eq!(enemies.degree("alice")?, 1);
+        assert_eq!(
+            friends.neighbors("alice").collect::<Result<Vec<_>>>()?,
+            vec![b"bob".to_vec(), b"carol".to_vec()]
+        );
+
+        assert!(friends.remove_edge("bob", "alice")?);
+        assert!(!friends.remove_edge("alice", "bob")?);
+        assert_eq!(friends.degree("alice")?, 1);
+        assert_eq!(friends.degree("bob")?, 0);
+
+        friends.add_edge("carol", "bob")?;
+        assert_eq!(friends.remove_node("carol")?, 2);
+        assert_eq!(friends.degree("alice")?, 0);
+        assert_eq!(friends.degree("bob")?, 0);
+        assert_eq!(friends.neighbors("carol").count(), 0);
+        assert_eq!(friends.remove_node("dave")?, 1);
+        assert_eq!(enemies.degree("alice")?, 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_inverted_index() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let index = CandyInvertedIndex::new(db.clone(), "tags");
+
+        index.index_document("doc1", &["red", "green", "blue"])?;
+        index.index_document("doc2", &["red", "green"])?;
+        index.index_document("doc3", &["red", "red"])?;
+        index.index_document("doc4", &["yellow"])?;
+
+        let query = |tokens: &[&str], mode| -> Result<Vec<String>> {
+            let mut docs = index
+                .query(tokens, mode)?
+                .map(|res| res.map(|doc| String::from_utf8(doc).unwrap()))
+                .collect::<Result<Vec<_>>>()?;
+            docs.sort();
+            Ok(docs)
+        };
+
+        assert_eq!(index.num_documents("red")?, 3);
+        assert_eq!(
+            query(&["red", "green"], IndexQueryMode::And)?,
+            ["doc1", "doc2"]
+        );
+        assert_eq!(
+            query(&["green", "red", "blue"], IndexQueryMode::And)?,
+            ["doc1"]
+        );
+        assert_eq!(
+            query(&["red", "purple"], IndexQueryMode::And)?,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            query(&["blue", "red", "yellow", "red"], IndexQueryMode::Or)?,
+            ["doc1", "doc2", "doc3", "doc4"]
+        );
+        assert_eq!(query(&[], IndexQueryMode::And)?, Vec::<String>::new());
+        assert_eq!(query(&[], IndexQueryMode::Or)?, Vec::<String>::new());
+
+        // re-indexing replaces the document's tokens
+        index.index_document("doc1", &["blue", "yellow"])?;
+        assert_eq!(query(&["red"], IndexQueryMode::Or)?, ["doc2", "doc3"]);
+        assert_eq!(query(&["yellow"], IndexQueryMode::Or)?, ["doc1", "doc4"]);
+        let mut tokens = index.document_tokens("doc1").collect::<Result<Vec<_>>>()?;
+        tokens.sort();
+        assert_eq!(tokens, [b"blue".to_vec(), b"yellow".to_vec()]);
+
+        assert!(index.remove_document("doc1")?);
+        assert!(!index.remove_document("doc1")?);
+        assert_eq!(query(&["blue", "yellow"], IndexQueryMode::Or)?, ["doc4"]);
+        assert_eq!(index.num_documents("blue")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_geo() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.geo_insert("eiffel", 48.8584, 2.2945)?;
+        db.geo_insert("louvre", 48.8606, 2.3376)?;
+        db.geo_insert("versailles", 48.8049, 2.1204)?;
+        db.geo_insert("london", 51.5074, -0.1278)?;
+        db.geo_insert("fiji", -17.7134, 178.0650)?;
+        db.geo_insert("samoa", -13.7590, -172.1046)?;
+        assert!(db.geo_insert("nowhere", 91.0, 0.0).is_err());
+
+        let keys = |matches: Vec<GeoMatch>| {
+            matches
+                .into_iter()
+                .map(|m| String::from_utf8(m.key).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 10.0)?),
+            ["eiffel"]
+        );
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 5_000.0)?),
+            ["eiffel", "louvre"]
+        );
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 20_000.0)?),
+            ["eiffel", "louvre", "versailles"]
+        );
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 500_000.0)?),
+            ["eiffel", "louvre", "versailles", "london"]
+        );
+        assert_eq!(db.geo_query_radius(0.0, 0.0, 30_000_000.0)?.len(), 6);
+
+        // across the antimeridian
+        assert_eq!(
+            keys(db.geo_query_radius(-15.0, 179.9, 1_500_000.0)?),
+            ["fiji", "samoa"]
+        );
+
+        // moving and removing points
+        assert_eq!(
+            db.geo_insert("louvre", 40.7794, -73.9632)?,
+            Some((48.8606, 2.3376))
+        );
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 20_000.0)?),
+            ["eiffel", "versailles"]
+        );
+        assert_eq!(db.geo_get("louvre")?, Some((40.7794, -73.9632)));
+        assert_eq!(db.geo_remove("versailles")?, Some((48.8049, 2.1204)));
+        assert_eq!(db.geo_remove("versailles")?, None);
+        assert_eq!(
+            keys(db.geo_query_radius(48.8584, 2.2945, 20_000.0)?),
+            ["eiffel"]
+        );
+        let m = &db.geo_query_radius(48.8584, 2.2945, 1_000_000.0)?[1];
+        assert_eq!(m.key, b"london");
+        assert!((m.distance - 340_000.0).abs() < 5_000.0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_numeric_index() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let prices = [
+            ("apple", 0.5),
+            ("bread", 2.25),
+            ("cheese", 7.0),
+            ("wine", 15.0),
+            ("caviar", 1200.0),
+            ("coupon", -3.0),
+            ("free sample", 0.0),
+        ];
+        for (item, price) in prices {
+            assert_eq!(db.index_numeric("price", item, price)?, None);
+        }
+        db.index_numeric("weight", "bread", 500.0)?;
+        assert!(db.index_numeric("price", "nan", f64::NAN).is_err());
+
+        let keys = |matches: Vec<(Vec<u8>, f64)>| {
+            matches
+                .into_iter()
+                .map(|(k, _)| String::from_utf8(k).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            keys(db.query_numeric_range("price", 1.0..20.0)?),
+            ["bread", "cheese", "wine"]
+        );
+        assert_eq!(
+            keys(db.query_numeric_range("price", 2.25..15.0)?),
+            ["bread", "cheese"]
+        );
+        assert_eq!(
+            keys(db.query_numeric_range("price", ..=0.5)?),
+            ["coupon", "free sample", "apple"]
+        );
+        assert_eq!(keys(db.query_numeric_range("price", 100.0..)?), ["caviar"]);
+        assert_eq!(db.query_numeric_range("price", ..)?.len(), 7);
+        assert_eq!(
+            db.query_numeric_range("weight", ..)?,
+            vec![(b"bread".to_vec(), 500.0)]
+        );
+
+        assert_eq!(db.index_numeric("price", "wine", 30.0)?, Some(15.0));
+        assert_eq!(
+            keys(db.query_numeric_range("price", 1.0..20.0)?),
+            ["bread", "cheese"]
+        );
+        assert_eq!(keys(db.query_numeric_range("price", 20.0..40.0)?), ["wine"]);
+        assert_eq!(db.unindex_numeric("price", "cheese")?, Some(7.0));
+        assert_eq!(db.unindex_numeric("price", "cheese")?, None);
+        assert_eq!(keys(db.query_numeric_range("price", 1.0..20.0)?), ["bread"]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_item_meta() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                list_item_metadata: true,
+                ..Default::default()
+            },
+        )?;
+
+        let t0 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        db.set_in_list("q", "a", "aaa")?;
+        db.set_in_list("q", "b", "bbb")?;
+        db.set_in_list("q", "c", "ccc")?;
+
+        let (val, meta) = db.get_from_list_with_meta("q", "a")?.unwrap();
+        assert_eq!(val, b"aaa");
+        assert_eq!(meta.revision, 0);
+        assert!(meta.inserted_at_ms >= t0);
+        assert_eq!(db.get_from_list("q", "a")?, Some(b"aaa".to_vec()));
+        assert_eq!(db.list_item_len("q", "a")?, Some(3));
+        assert_eq!(db.get_from_list_with_meta("q", "x")?, None);
+
+        // updates bump the revision but keep the insertion time
+        assert!(db.set_in_list("q", "a", "AAA")?.was_replaced());
+        assert!(db
+            .replace_in_list("q", "a", "aAa", Some("AAA"))?
+            .was_replaced());
+        assert!(!db
+            .replace_in_list("q", "a", "xxx", Some("AAA"))?
+            .was_replaced());
+        assert!(!db.get_or_create_in_list("q", "a", "yyy")?.was_created());
+        let (val, meta2) = db.get_from_list_with_meta("q", "a")?.unwrap();
+        assert_eq!(val, b"aAa");
+        assert_eq!(meta2.revision, 2);
+        assert_eq!(meta2.inserted_at_ms, meta.inserted_at_ms);
+
+        // metadata survives retention and compaction
+        db.retain_in_list("q", |k, _| Ok(k != b"b"))?;
+        assert_eq!(db.get_from_list_with_meta("q", "a")?.unwrap(), (val, meta2));
+        let items = db
+            .iter_list("q")
+            .map(|res| res.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(items, vec![b"aAa".to_vec(), b"ccc".to_vec()]);
+
+        assert_eq!(
+            db.pop_list_head("q")?,
+            Some((b"a".to_vec(), b"aAa".to_vec()))
+        );
+        assert_eq!(
+            db.pop_list_tail("q")?,
+            Some((b"c".to_vec(), b"ccc".to_vec()))
+        );
+        assert_eq!(db.pop_list_head("q")?, None);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        typed.set("tl", &7, "seven")?;
+        typed.set("tl", &7, "SEVEN")?;
+        let (val, meta) = typed.get_with_meta("tl", &7)?.unwrap();
+        assert_eq!(val, "SEVEN");
+        assert_eq!(meta.revision, 1);
+
+        Ok(())
+    })?;
+
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        db.set_in_list("q", "a", "aaa")?;
+        assert!(matches!(
+            db.get_from_list_with_meta("q", "a"),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_item_tags() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                list_item_metadata: true,
+                list_item_tags: true,
+                ..Default::default()
+            },
+        )?;
+
+        for i in 0u32..10 {
+            db.set_in_list_tagged("jobs", &format!("job{i}"), &format!("v{i}"), i % 3)?;
+        }
+        db.set_in_list("jobs", "untagged", "vvv")?;
+
+        let tagged_keys = |tag| {
+            db.iter_list_by_tag("jobs", tag)?
+                .map(|res| res.map(|(k, _)| String::from_utf8(k).unwrap()))
+                .collect::<Result<Vec<_>>>()
+        };
+        assert_eq!(tagged_keys(1)?, vec!["job1", "job4", "job7"]);
+        assert_eq!(
+            tagged_keys(0)?,
+            vec!["job0", "job3", "job6", "job9", "untagged"]
+        );
+        assert!(tagged_keys(5)?.is_empty());
+        assert_eq!(db.get_list_item_tag("jobs", "job2")?, Some(2));
+        assert_eq!(db.get_list_item_tag("jobs", "nope")?, None);
+
+        // plain updates keep the tag, tagged updates replace it
+        assert!(db.set_in_list("jobs", "job1", "V1")?.was_replaced());
+        assert_eq!(db.get_list_item_tag("jobs", "job1")?, Some(1));
+        assert!(db
+            .set_in_list_tagged("jobs", "job4", "V4", 2)?
+            .was_replaced());
+        assert_eq!(db.get_from_list("jobs", "job4")?, Some(b"V4".to_vec()));
+        assert_eq!(
+            db.get_from_list_with_meta("jobs", "job4")?
+                .unwrap()
+                .1
+                .revision,
+            1
+        );
+        assert_eq!(tagged_keys(1)?, vec!["job1", "job7"]);
+
+        // tags survive retention and compaction
+        db.retain_in_list("jobs", |k, _| Ok(k != b"job7"))?;
+        assert!(db.compact_list_if_needed(
+            "jobs",
+            ListCompactionParams {
+                min_length: 0,
+                min_holes_ratio: 0.0,
+            }
+        )?);
+        assert_eq!(tagged_keys(1)?, vec!["job1"]);
+        assert_eq!(tagged_keys(2)?, vec!["job2", "job4", "job5", "job8"]);
+        assert_eq!(
+            db.iter_list_by_tag("jobs", 1)?.next().unwrap()?,
+            (b"job1".to_vec(), b"V1".to_vec())
+        );
+        assert_eq!(db.iter_list("jobs").count(), 10);
+
+        Ok(())
+    })?;
+
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(matches!(
+            db.set_in_list_tagged("q", "a", "aaa", 1),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            db.iter_list_by_tag("q", 1),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_peek_many() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        assert!(db.peek_list_head_many("jobs", 5)?.is_empty());
+
+        for i in 0u32..30 {
+            db.set_in_list("jobs", &i.to_le_bytes(), &format!("job{i}"))?;
+        }
+        // create some holes
+        db.remove_from_list("jobs", &3u32.to_le_bytes())?;
+        db.remove_from_list("jobs", &27u32.to_le_bytes())?;
+        db.pop_list_head("jobs")?;
+
+        let head = db.peek_list_head_many("jobs", 5)?;
+        let head_keys = head
+            .iter()
+            .map(|(k, _)| u32::from_le_bytes(k[..].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(head_keys, [1, 2, 4, 5, 6]);
+        assert_eq!(head[0].1, b"job1");
+
+        let tail = db.peek_list_tail_many("jobs", 4)?;
+        let tail_keys = tail
+            .iter()
+            .map(|(k, _)| u32::from_le_bytes(k[..].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(tail_keys, [29, 28, 26, 25]);
+
+        // nothing was removed, and asking for more than there is returns everything
+        assert_eq!(db.list_len("jobs")?, 27);
+        assert_eq!(db.peek_list_head_many("jobs", 100)?.len(), 27);
+        assert_eq!(db.peek_list_tail_many("jobs", 0)?.len(), 0);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        for i in 0u32..10 {
+            typed.set("tjobs", &i, &format!("job{i}"))?;
+        }
+        assert_eq!(
+            typed.peek_head_many("tjobs", 2)?,
+            [(0, "job0".to_owned()), (1, "job1".to_owned())]
+        );
+        assert_eq!(
+            typed.peek_tail_many("tjobs", 2)?,
+            [(9, "job9".to_owned()), (8, "job8".to_owned())]
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_iter_list_snapshot() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        assert_eq!(db.iter_list_snapshot("empty")?.count(), 0);
+
+        for i in 0u32..10 {
+            db.set_in_list("xxx", &i.to_le_bytes(), "val")?;
+        }
+
+        // keep pushing while iterating: the snapshot only covers the original elements
+        let mut next = 10u32;
+        let mut seen = vec![];
+        for res in db.iter_list_snapshot("xxx")? {
+            let (k, _) = res?;
+            seen.push(u32::from_le_bytes(k.try_into().unwrap()));
+            db.set_in_list("xxx", &next.to_le_bytes(), "val")?;
+            next += 1;
+            if seen.len() == 3 {
+                db.remove_from_list("xxx", &5u32.to_le_bytes())?;
+                // promoting moves the element past the snapshot's end
+                db.set_in_list_promoting("xxx", &7u32.to_le_bytes(), "val")?;
+            }
+        }
+        assert_eq!(seen, [0, 1, 2, 3, 4, 6, 8, 9]);
+        assert_eq!(db.list_len("xxx")?, 9 + 8);
+
+        // the snapshot is taken when the iterator is created, not on its first step
+        let iter = db.iter_list_snapshot("xxx")?;
+        db.set_in_list("xxx", "late", "val")?;
+        assert_eq!(iter.count(), 17);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_dry_run() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        assert_eq!(db.dry_run_discard_list("nope")?.num_items, 0);
+
+        for i in 0u32..200 {
+            db.set_in_list("xs", &i.to_le_bytes(), &format!("x{i:03}"))?;
+        }
+
+        // odd items would be dropped
+        let report = db.dry_run_retain_in_list("xs", |k, _| {
+            Ok(u32::from_le_bytes(k.try_into().unwrap()) % 2 == 0)
+        })?;
+        assert_eq!(report.num_items, 100);
+        assert!(report.num_bytes > 100 * (4 + 4));
+        assert_eq!(db.list_len("xs")?, 200);
+
+        let report = db.dry_run_discard_list("xs")?;
+        assert_eq!(report.num_items, 200);
+        assert_eq!(db.list_len("xs")?, 200);
+
+        // no holes, so no compaction
+        assert_eq!(
+            db.dry_run_compact_list("xs", ListCompactionParams::default())?,
+            None
+        );
+        for i in 0u32..100 {
+            db.remove_from_list("xs", &(i * 2 + 1).to_le_bytes())?;
+        }
+        let report = db
+            .dry_run_compact_list("xs", ListCompactionParams::default())?
+            .unwrap();
+        assert_eq!(report.num_items, 100);
+        assert!(db.compact_list_if_needed("xs", ListCompactionParams::default())?);
+
+        let before = db.dry_run_clear();
+        assert!(before.num_items >= 100);
+        assert!(before.num_bytes > 0);
+        assert_eq!(db.list_len("xs")?, 100);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        for i in 0u32..10 {
+            typed.set("ys", &i, &format!("y{i}"))?;
+        }
+        let report = typed.dry_run_retain("ys", |k, _| Ok(*k < 7))?;
+        assert_eq!(report.num_items, 3);
+        assert_eq!(typed.dry_run_discard("ys")?.num_items, 10);
+        assert_eq!(typed.len("ys")?, 10);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fence_list() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.health()?.last_fsync, None);
+
+        for i in 0..10u32 {
+            db.set_in_list("events", &format!("ev{i}"), "payload")?;
+        }
+        db.remove_from_list("events", "ev9")?;
+        let fence = db.fence_list("events")?;
+        assert!(db.health()?.last_fsync.is_some());
+        let indices = db
+            .iter_list_with_indices("events")
+            .map(|res| res.map(|(idx, _, _)| idx as u64))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(indices.len(), 9);
+        // the fence is past the last item
+        assert_eq!(fence, indices[8] + 1);
+        assert_eq!(db.fence_list("events")?, fence);
+
+        // items pushed later are indexed past the fence
+        db.set_in_list("events", "ev10", "payload")?;
+        let (idx, _, _) = db.iter_list_with_indices("events").last().unwrap()?;
+        assert_eq!(idx as u64, fence);
+        assert_eq!(db.fence_list("events")?, fence + 1);
+
+        // a list that does not exist is fenced before its first item
+        let fence = db.fence_list("later")?;
+        db.set_in_list("later", "ev0", "payload")?;
+        let (idx, _, _) = db.iter_list_with_indices("later").next().unwrap()?;
+        assert_eq!(idx as u64, fence);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pop_list_tail() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        for i in 0..3 {
+            db.set_in_list("l", &format!("k{i}"), "v")?;
+        }
+
+        assert_eq!(db.pop_list_tail("l")?, Some(("k2".into(), "v".into())));
+        assert_eq!(db.list_len("l")?, 2);
+        assert_eq!(db.iter_list("l").count(), 2);
+        assert_eq!(db.pop_list_tail("l")?, Some(("k1".into(), "v".into())));
+        assert_eq!(db.pop_list_tail("l")?, Some(("k0".into(), "v".into())));
+        assert_eq!(db.pop_list_tail("l")?, None);
+        assert_eq!(db.list_len("l")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_yield_every() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                yield_every: Some(10),
+                ..Default::default()
+            },
+        )?;
+
+        for i in 0u32..2000 {
+            db.set_in_list("xxx", &i.to_le_bytes(), "yyy")?;
+        }
+
+        // keep pushing to the list while it's being retained
+        std::thread::scope(|s| -> Result<()> {
+            let pusher = s.spawn(|| -> Result<()> {
+                for i in 2000u32..2500 {
+                    db.set_in_list("xxx", &i.to_le_bytes(), "zzz")?;
+                }
+                Ok(())
+            });
+
+            let mut visited = 0;
+            db.retain_in_list("xxx", |k, v| {
+                let k = u32::from_le_bytes(k.try_into().unwrap());
+                assert!(k < 2000, "{k}");
+                assert_eq!(v, b"yyy");
+                visited += 1;
+                Ok(k % 2 == 0)
+            })?;
+            assert_eq!(visited, 2000);
+
+            pusher.join().unwrap()
+        })?;
+
+        assert_eq!(db.list_len("xxx")?, 1000 + 500);
+        for i in 0u32..2500 {
+            let exists = db.get_from_list("xxx", &i.to_le_bytes())?.is_some();
+            assert_eq!(exists, i >= 2000 || i % 2 == 0, "{i}");
+        }
+        let items = db.iter_list("xxx").collect::<Result<Vec<_>>>()?;
+        assert_eq!(items.len(), 1500);
+
+        // elements pushed while discarding are kept
+        std::thread::scope(|s| -> Result<()> {
+            let pusher = s.spawn(|| -> Result<()> {
+                for i in 3000u32..3100 {
+                    db.set_in_list("xxx", &i.to_le_bytes(), "zzz")?;
+                }
+                Ok(())
+            });
+            assert!(db.discard_list("xxx")?);
+            pusher.join().unwrap()
+        })?;
+
+        let remaining = db.list_len("xxx")?;
+        assert!(remaining <= 100);
+        let items = db.iter_list("xxx").collect::<Result<Vec<_>>>()?;
+        assert_eq!(items.len(), remaining);
+        for (k, _) in items {
+            assert!(u32::from_le_bytes(k.try_into().unwrap()) >= 3000);
+        }
+        for i in 0u32..2500 {
+            assert!(db.get_from_list("xxx", &i.to_le_bytes())?.is_none());
+        }
+
+        // a list that is discarded and created again while another discard (or retain) of it yields is a new
+        // list, whose elements are left alone
+        for retain in [false, true] {
+            for i in 0u32..5000 {
+                db.set_in_list("yyy", &i.to_le_bytes(), "old")?;
+            }
+            std::thread::scope(|s| -> Result<()> {
+                let yielder = s.spawn(|| -> Result<()> {
+                    if retain {
+                        db.retain_in_list("yyy", |_, v| {
+                            assert_eq!(v, b"old");
+                            Ok(false)
+                        })
+                    } else {
+                        db.discard_list("yyy").map(|_| ())
+                    }
+                });
+                while db.list_len("yyy")? == 5000 {
+                    std::thread::yield_now();
+                }
+                for round in 0u32..20 {
+                    db.discard_list("yyy")?;
+                    for i in 0u32..50 {
+                        db.set_in_list("yyy", &(round * 100 + i).to_le_bytes(), "new")?;
+                    }
+                    for i in 0u32..50 {
+                        let k = (round * 100 + i).to_le_bytes();
+                        assert_eq!(
+                            db.get_from_list("yyy", &k)?,
+                            Some("new".into()),
+                            "{round} {i}"
+                        );
+                    }
+                }
+                yielder.join().unwrap()
+            })?;
+            assert_eq!(db.list_len("yyy")?, 50);
+            assert!(db.discard_list("yyy")?);
+        }
+
+        assert!(matches!(
+            CandyStore::open(
+                dir,
+                Config {
+                    yield_every: Some(0),
+                    ..Default::default()
+                },
+            ),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_cancellation() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        for i in 0u32..100 {
+            db.set_in_list("xxx", &i.to_le_bytes(), "yyy")?;
+        }
+
+        // cancel the retain midway, from within the predicate
+        let cancel = CancellationToken::new();
+        let mut visited = 0;
+        let res = db.retain_in_list_cancellable("xxx", &cancel, |_, _| {
+            visited += 1;
+            if visited == 50 {
+                cancel.cancel();
+            }
+            Ok(visited % 2 == 0)
+        });
+        assert!(matches!(res, Err(CandyError::Cancelled)));
+        assert_eq!(visited, 50);
+        assert_eq!(db.list_len("xxx")?, 75);
+        let keys = db
+            .iter_list("xxx")
+            .map(|res| res.map(|(k, _)| u32::from_le_bytes(k.try_into().unwrap())))
+            .collect::<Result<Vec<_>>>()?;
+        // the unvisited elements come first, followed by the retained ones
+        assert_eq!(
+            keys,
+            (50..100).chain((1..50).step_by(2)).collect::<Vec<_>>()
+        );
+
+        // an already-cancelled token aborts right away
+        assert!(matches!(
+            db.discard_list_cancellable("xxx", &cancel),
+            Err(CandyError::Cancelled)
+        ));
+        assert!(matches!(
+            db.compact_list_if_needed_cancellable(
+                "xxx",
+                ListCompactionParams {
+                    min_length: 0,
+                    min_holes_ratio: 0.0
+                },
+                &cancel
+            ),
+            Err(CandyError::Cancelled)
+        ));
+        assert_eq!(db.list_len("xxx")?, 75);
+        assert_eq!(db.iter_list("xxx").count(), 75);
+
+        let path = format!("{dir}/export.sst");
+        assert!(matches!(
+            db.export_sst(
+                &path,
+                SstParams {
+                    raw: true,
+                    cancel: Some(cancel.clone()),
+                    ..Default::default()
+                }
+            ),
+            Err(CandyError::Cancelled)
+        ));
+        assert!(!std::path::Path::new(&path).exists());
+
+        let cancel = CancellationToken::new();
+        assert!(db.discard_list_cancellable("xxx", &cancel)?);
+        assert_eq!(db.list_len("xxx")?, 0);
+        assert_eq!(db.iter_list("xxx").count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_progress() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        for i in 0u32..100 {
+            db.set_in_list("xxx", &i.to_le_bytes(), "yyy")?;
+        }
+        for i in 0u32..10 {
+            db.remove_from_list("xxx", &(i * 10 + 5).to_le_bytes())?;
+        }
+
+        let reports = Arc::new(std::sync::Mutex::new(vec![]));
+        let progress = {
+            let reports = reports.clone();
+            Progress::new(move |done, total| reports.lock().unwrap().push((done, total)))
+        };
+
+        db.retain_in_list_with_progress("xxx", &progress, |k, _| {
+            Ok(u32::from_le_bytes(k.try_into().unwrap()) % 2 == 0)
+        })?;
+        assert_eq!(db.list_len("xxx")?, 50);
+        // the holes count towards the total
+        assert_eq!(
+            std::mem::take(&mut *reports.lock().unwrap()),
+            (1..=100).map(|i| (i, 100)).collect::<Vec<_>>()
+        );
+
+        let path = format!("{dir}/export.sst");
+        let num_exported = db.export_sst(
+            &path,
+            SstParams {
+                raw: true,
+                progress: Some(progress.clone()),
+                ..Default::default()
+            },
+        )?;
+        let reports2 = std::mem::take(&mut *reports.lock().unwrap());
+        assert_eq!(reports2.len(), num_exported);
+        assert_eq!(
+            reports2.last(),
+            Some(&(num_exported as u64, num_exported as u64))
+        );
+
+        assert!(db.discard_list_with_progress("xxx", &progress)?);
+        assert_eq!(
+            std::mem::take(&mut *reports.lock().unwrap()),
+            (1..=50).map(|i| (i, 50)).collect::<Vec<_>>()
+        );
+        assert_eq!(db.list_len("xxx")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_iter_list_filtered() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                list_item_metadata: true,
+                ..Default::default()
+            },
+        )?;
+
+        for i in 0u32..100 {
+            db.set_in_list("xxx", &i.to_le_bytes(), &(i * 2).to_le_bytes())?;
+        }
+        db.remove_from_list("xxx", &20u32.to_le_bytes())?;
+
+        let mut num_calls = 0;
+        let items = db
+            .iter_list_filtered("xxx", |k, v| {
+                num_calls += 1;
+                // the predicate sees the element's key and value only
+                assert_eq!(k.len(), 4);
+                assert_eq!(v.len(), 4);
+                u32::from_le_bytes(v.try_into().unwrap()) % 20 == 0
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(num_calls, 99);
+        assert_eq!(
+            items,
+            [0u32, 10, 30, 40, 50, 60, 70, 80, 90]
+                .iter()
+                .map(|i| (i.to_le_bytes().to_vec(), (i * 2).to_le_bytes().to_vec()))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(db.iter_list_filtered("yyy", |_, _| true).count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_cursors() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            for i in 0..10u32 {
+                db.set_in_list("events", &i.to_le_bytes(), "v")?;
+            }
+            assert_eq!(db.get_list_cursor("events", "indexer")?, None);
+
+            // no cursor: starts at the head
+            let mut seen = vec![];
+            for res in db.iter_list_from_cursor("events", "indexer")? {
+                let (idx, k, _) = res?;
+                seen.push(u32::from_le_bytes(k.try_into().unwrap()));
+                db.save_list_cursor("events", "indexer", idx)?;
+                if seen.len() == 4 {
+                    break;
+                }
+            }
+            assert_eq!(seen, vec![0, 1, 2, 3]);
+        }
+
+        // cursors persist across reopens and are independent of each other
+        let db = CandyStore::open(dir, Config::default())?;
+        for i in 10..12u32 {
+            db.set_in_list("events", &i.to_le_bytes(), "v")?;
+        }
+        let keys = db
+            .iter_list_from_cursor("events", "indexer")?
+            .map(|res| u32::from_le_bytes(res.unwrap().1.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (4..12).collect::<Vec<_>>());
+        assert_eq!(db.iter_list_from_cursor("events", "other")?.count(), 12);
+
+        // each item is a 4-byte key and a 1-byte value
+        let lag = db.cursor_lag("events", "indexer")?;
+        assert_eq!((lag.num_items, lag.num_bytes), (8, 8 * 5));
+        assert_eq!(db.cursor_lag("events", "other")?.num_items, 12);
+        assert_eq!(db.cursor_lag("other", "indexer")?, CursorLag::default());
+        assert_eq!(db.iter_list_from_cursor("other", "indexer")?.count(), 0);
+
+        let last_idx = db.iter_list_with_indices("events").last().unwrap()?.0;
+        db.save_list_cursor("events", "indexer", last_idx)?;
+        assert_eq!(db.iter_list_from_cursor("events", "indexer")?.count(), 0);
+        assert_eq!(db.cursor_lag("events", "indexer")?, CursorLag::default());
+        assert_eq!(db.get_list_cursor("events", "indexer")?, Some(last_idx));
+
+        assert_eq!(db.remove_list_cursor("events", "indexer")?, Some(last_idx));
+        assert_eq!(db.get_list_cursor("events", "indexer")?, None);
+        assert_eq!(db.iter_list_from_cursor("events", "indexer")?.count(), 12);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_recovery() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let src = CandyStore::open(format!("{dir}/src"), Config::default())?;
+        src.set_in_list("intact", "a", "1")?;
+        src.set_in_list("intact", "b", "2")?;
+        src.set_in_list("noitems", "x", "3")?;
+        src.set_in_list("noitems", "y", "4")?;
+        src.set_in_list("nochain", "p", "5")?;
+        src.set_in_list("nochain", "q", "6")?;
+
+        // raw exports that omit some of the lists' entries are imported to produce torn lists: the chain entries
+        // of "noitems" without their items (torn inserts) and the items of "nochain" without their chain
+        // entries (torn removes)
+        let exports = [
+            ("intact", vec![]),
+            ("noitems", vec![Namespace::ListItem]),
+            ("nochain", vec![Namespace::ListChain]),
+        ]
+        .into_iter()
+        .map(|(list, exclude_namespaces)| {
+            let path = format!("{dir}/{list}.sst");
+            src.export_sst(
+                &path,
+                SstParams {
+                    raw: true,
+                    filter: ExportFilter {
+                        exclude_namespaces,
+                        include_lists: vec![list.into()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )?;
+            Ok(path)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+        let open_torn = |name: &str, list_recovery| {
+            let path = format!("{dir}/{name}");
+            {
+                let db = CandyStore::open(&path, Config::default())?;
+                for sst in exports.iter() {
+                    db.import_sst(
+                        sst,
+                        SstParams {
+                            raw: true,
+                            ..Default::default()
+                        },
+                    )?;
+                }
+                assert_eq!(db.list_recovery_report(), None);
+            }
+            CandyStore::open(
+                &path,
+                Config {
+                    list_recovery,
+                    ..Default::default()
+                },
+            )
+        };
+        let items = |db: &CandyStore, list: &str| {
+            db.iter_list(list)
+                .map(|res| res.map(|(k, _)| String::from_utf8(k).unwrap()))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        let db = open_torn("report", Some(ListRecoveryPolicy::ReportOnly))?;
+        let report = db.list_recovery_report().unwrap();
+        assert_eq!((report.torn_inserts, report.torn_removes), (2, 2));
+        assert!(!report.is_clean());
+        assert_eq!(db.list_len("noitems")?, 2);
+        assert_eq!(items(&db, "noitems")?.len(), 0);
+        assert_eq!(items(&db, "nochain")?.len(), 0);
+        drop(db);
+
+        let db = open_torn("forward", Some(ListRecoveryPolicy::RollForward))?;
+        let report = db.list_recovery_report().unwrap();
+        assert_eq!((report.torn_inserts, report.torn_removes), (2, 2));
+        assert_eq!(items(&db, "intact")?, vec!["a", "b"]);
+        assert_eq!(db.list_len("noitems")?, 0);
+        assert_eq!(db.list_len("nochain")?, 0);
+        assert_eq!(db.get_from_list("nochain", "p")?, None);
+        drop(db);
+
+        let db = open_torn("back", Some(ListRecoveryPolicy::RollBack))?;
+        assert_eq!(items(&db, "intact")?, vec!["a", "b"]);
+        assert_eq!(db.list_len("noitems")?, 0);
+        assert_eq!(items(&db, "nochain")?, vec!["p", "q"]);
+        assert_eq!(db.list_len("nochain")?, 2);
+        assert_eq!(db.pop_list_tail("nochain")?, Some(("q".into(), "6".into())));
+        drop(db);
+
+        // once recovered, the store is clean
+        let db = CandyStore::open(
+            format!("{dir}/back"),
+            Config {
+                list_recovery: Some(ListRecoveryPolicy::ReportOnly),
+                ..Default::default()
+            },
+        )?;
+        assert!(db.list_recovery_report().unwrap().is_clean());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_audit() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.set_in_list("jobs", "early", "v")?;
+            assert!(!db.is_list_audited("jobs"));
+            assert!(db.enable_list_audit("jobs")?);
+            assert!(!db.enable_list_audit("jobs")?);
+            assert!(db.is_list_audited("jobs"));
+
+            db.set_in_list("jobs", "j1", "v")?;
+            db.set_in_list("jobs", "j2", "v")?;
+            db.set_in_list("jobs", "j1", "v2")?;
+            db.pop_list_head("jobs")?;
+            db.remove_from_list("jobs", "j2")?;
+            db.set_in_list("jobs", "j3", "v")?;
+            db.set_in_list("jobs", "j4", "v")?;
+            db.remove_from_list("jobs", "j3")?;
+            db.retain_in_list("jobs", |k, _| Ok(k != b"j4"))?;
+            // other lists are not audited
+            db.set_in_list("other", "x", "v")?;
+            assert!(db.list_audit_log("other")?.is_empty());
+        }
+
+        // auditing is persistent
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(db.is_list_audited("jobs"));
+        db.set_in_list("jobs", "j5", "v")?;
+        db.set_in_list("jobs", "j6", "v")?;
+        db.remove_from_list("jobs", "j5")?;
+        db.compact_list_if_needed(
+            "jobs",
+            ListCompactionParams {
+                min_length: 1,
+                min_holes_ratio: 0.0,
+            },
+        )?;
+        db.discard_list("jobs")?;
+
+        let log = db.list_audit_log("jobs")?;
+        let ops = log
+            .iter()
+            .map(|r| (r.op, String::from_utf8(r.item_key.clone()).unwrap()))
+            .collect::<Vec<_>>();
+        let expected = [
+            (ListAuditOp::Push, "j1"),
+            (ListAuditOp::Push, "j2"),
+            (ListAuditOp::Update, "j1"),
+            (ListAuditOp::Pop, "early"),
+            (ListAuditOp::Remove, "j2"),
+            (ListAuditOp::Push, "j3"),
+            (ListAuditOp::Push, "j4"),
+            (ListAuditOp::Remove, "j3"),
+            (ListAuditOp::Remove, "j4"),
+            (ListAuditOp::Push, "j5"),
+            (ListAuditOp::Push, "j6"),
+            (ListAuditOp::Remove, "j5"),
+            (ListAuditOp::Compact, ""),
+            (ListAuditOp::Discard, ""),
+        ]
+        .map(|(op, k)| (op, k.to_owned()));
+        assert_eq!(ops, expected);
+        assert!(log.iter().enumerate().all(|(i, r)| r.seq == i as u64));
+        assert!(log
+            .windows(2)
+            .all(|w| w[0].timestamp_ms <= w[1].timestamp_ms));
+        // audit entries are not part of the list
+        assert_eq!(db.list_len("jobs")?, 0);
+
+        assert_eq!(db.trim_list_audit_log("jobs", 4)?, expected.len() - 4);
+        assert_eq!(db.trim_list_audit_log("jobs", 4)?, 0);
+        let log = db.list_audit_log("jobs")?;
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[0].seq, expected.len() as u64 - 4);
+        assert_eq!(log[3].op, ListAuditOp::Discard);
+
+        assert!(db.disable_list_audit("jobs")?);
+        assert!(!db.disable_list_audit("jobs")?);
+        assert!(db.list_audit_log("jobs")?.is_empty());
+        db.set_in_list("jobs", "j7", "v")?;
+        assert!(db.list_audit_log("jobs")?.is_empty());
+        drop(db);
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(!db.is_list_audited("jobs"));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_idempotent_pop() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        for i in 0..5u32 {
+            db.set_in_list("jobs", &format!("job{i}"), &format!("payload{i}"))?;
+        }
+
+        let first = db.pop_list_head_idempotent("jobs", "worker-a")?;
+        assert_eq!(first, Some((b"job0".to_vec(), b"payload0".to_vec())));
+        // a retry returns the same item, without popping another one
+        assert_eq!(db.pop_list_head_idempotent("jobs", "worker-a")?, first);
+        assert_eq!(db.list_len("jobs")?, 4);
+
+        // other tokens pop other items
+        assert_eq!(
+            db.pop_list_head_idempotent("jobs", "worker-b")?,
+            Some((b"job1".to_vec(), b"payload1".to_vec()))
+        );
+        assert_eq!(db.list_len("jobs")?, 3);
+
+        // a re-pushed item of the same key is not the one that was popped, so retries leave it alone
+        db.set_in_list("jobs", "job0", "again")?;
+        assert_eq!(db.pop_list_head_idempotent("jobs", "worker-a")?, first);
+        assert_eq!(db.list_len("jobs")?, 4);
+
+        // releasing the token lets it pop the next item
+        assert_eq!(db.release_pop_token("jobs", "worker-a")?, first);
+        assert_eq!(db.release_pop_token("jobs", "worker-a")?, None);
+        assert_eq!(
+            db.pop_list_head_idempotent("jobs", "worker-a")?,
+            Some((b"job2".to_vec(), b"payload2".to_vec()))
+        );
+
+        // tokens survive reopening, along with the popped items
+        drop(db);
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(
+            db.pop_list_head_idempotent("jobs", "worker-a")?,
+            Some((b"job2".to_vec(), b"payload2".to_vec()))
+        );
+        let remaining = db
+            .iter_list("jobs")
+            .map(|res| res.map(|(k, _)| k))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            remaining,
+            vec![b"job3".to_vec(), b"job4".to_vec(), b"job0".to_vec()]
+        );
+
+        // popping an empty list records nothing
+        assert_eq!(db.pop_list_head_idempotent("empty", "worker-a")?, None);
+        assert_eq!(db.release_pop_token("empty", "worker-a")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_export_import() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let src = CandyStore::open(format!("{dir}/src"), Config::default())?;
+        let dst = CandyStore::open(format!("{dir}/dst"), Config::default())?;
+
+        for i in 0..100u32 {
+            src.set_in_list("jobs", &format!("job{i}"), &format!("payload{i}"))?;
+        }
+        // holes are skipped
+        src.remove_from_list("jobs", "job3")?;
+        src.pop_list_head("jobs")?;
+
+        let mut buf = vec![];
+        assert_eq!(src.export_list("jobs", &mut buf)?, 98);
+        assert_eq!(src.list_len("jobs")?, 98);
+
+        let (list_key, num_items) = dst.import_list(&buf[..])?;
+        assert_eq!(list_key, b"jobs");
+        assert_eq!(num_items, 98);
+        assert_eq!(
+            dst.iter_list("jobs").collect::<Result<Vec<_>>>()?,
+            src.iter_list("jobs").collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            dst.pop_list_head("jobs")?,
+            Some(("job1".into(), "payload1".into()))
+        );
+
+        // an empty list round-trips as well
+        let mut buf = vec![];
+        assert_eq!(src.export_list("nothing", &mut buf)?, 0);
+        assert_eq!(dst.import_list(&buf[..])?, (b"nothing".to_vec(), 0));
+
+        // truncated exports are detected
+        let mut buf = vec![];
+        src.export_list("jobs", &mut buf)?;
+        assert!(dst.import_list(&buf[..buf.len() - 4]).is_err());
+        assert!(matches!(
+            dst.import_list(&b"garbage!"[..]),
+            Err(CandyError::Corruption(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_journal() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            list_journal: true,
+            ..Default::default()
+        };
+        let db = CandyStore::open(format!("{dir}/db"), config.clone())?;
+        for i in 0..10u32 {
+            db.set_in_list("jobs", &format!("job{i}"), &format!("payload{i}"))?;
+        }
+        let items = |db: &CandyStore| {
+            db.iter_list("jobs")
+                .map(|res| res.map(|(k, _)| String::from_utf8(k).unwrap()))
+                .collect::<Result<Vec<_>>>()
+        };
+        let before = items(&db)?;
+
+        // an operation that panics halfway is rolled back
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.retain_in_list("jobs", |k, _| {
+                if k == b"job5" {
+                    panic!("boom");
+                }
+                Ok(k[3] % 2 == 0)
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(items(&db)?, before);
+        assert_eq!(db.list_len("jobs")?, 10);
+
+        // a raw export taken halfway through an operation captures a torn list, along with its journal, which
+        // is rolled back when the imported store is opened
+        let sst = format!("{dir}/torn.sst");
+        db.retain_in_list("jobs", |k, _| {
+            if k == b"job5" {
+                db.export_sst(
+                    &sst,
+                    SstParams {
+                        raw: true,
+                        ..Default::default()
+                    },
+                )?;
+            }
+            Ok(k[3] % 2 == 0)
+        })?;
+        assert_eq!(items(&db)?, vec!["job0", "job2", "job4", "job6", "job8"]);
+        // committed operations leave nothing but the journal's slot count behind
+        assert_eq!(db.namespace_stats(Namespace::Journal, 1.0)?.num_items, 1);
+
+        {
+            let torn = CandyStore::open(format!("{dir}/torn"), Config::default())?;
+            torn.import_sst(
+                &sst,
+                SstParams {
+                    raw: true,
+                    ..Default::default()
+                },
+            )?;
+            assert_ne!(items(&torn)?, before);
+        }
+        let torn = CandyStore::open(format!("{dir}/torn"), config)?;
+        assert_eq!(items(&torn)?, before);
+        assert_eq!(torn.list_len("jobs")?, 10);
+        assert_eq!(torn.namespace_stats(Namespace::Journal, 1.0)?.num_items, 1);
+
+        Ok(())
+    })
+}
@@ -1,6 +1,15 @@
 mod common;
 
-use candystore::{CandyStore, Config, Result};
+use std::{
+    os::unix::fs::MetadataExt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use candystore::{
+    CandyError, CandyStore, Config, ConfigUpdate, DiskFullPolicy, Result, SoftLimitObserver,
+    SoftLimitWarning, SoftLimits,
+};
 
 use crate::common::{run_in_tempdir, LONG_VAL};
 
@@ -70,3 +79,660 @@ fn test_loading() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_open_errors() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            assert!(matches!(
+                CandyStore::open(dir, Config::default()),
+                Err(CandyError::Busy(msg)) if msg.contains("already open in this process")
+            ));
+            // the same directory under a different path
+            assert!(matches!(
+                CandyStore::open(
+                    format!("{dir}/../{}", dir.rsplit('/').next().unwrap()),
+                    Config::default()
+                ),
+                Err(CandyError::Busy(_))
+            ));
+            // the directory stays registered as long as any handle is alive
+            let handle = db.clone();
+            drop(db);
+            assert!(matches!(
+                CandyStore::open(dir, Config::default()),
+                Err(CandyError::Busy(_))
+            ));
+            drop(handle);
+            let _db = CandyStore::open(dir, Config::default())?;
+        }
+
+        std::fs::write(
+            format!("{dir}/shard_0000-10000"),
+            "this is not a shard file",
+        )?;
+        assert!(matches!(
+            CandyStore::open(dir, Config::default()),
+            Err(CandyError::Corruption(_))
+        ));
+
+        let db = CandyStore::open(
+            dir,
+            Config {
+                clear_on_unsupported_version: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(db.iter().count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_key_prefixes() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let plain_dir = format!("{dir}/plain");
+        let compressed_dir = format!("{dir}/compressed");
+        let prefix = "tenant/some-long-organization-name/project-1234/";
+        let config = Config {
+            max_shard_size: 20 * 1024,
+            min_compaction_threashold: 10 * 1024,
+            ..Default::default()
+        };
+        let compressed_config = Config {
+            key_prefixes: vec![b"tenant/".to_vec(), prefix.as_bytes().to_vec()],
+            ..config.clone()
+        };
+
+        let occupied_plain = {
+            let db = CandyStore::open(&plain_dir, config.clone())?;
+            for i in 0..3000 {
+                db.set(&format!("{prefix}key{i}"), "val")?;
+            }
+            db.stats().occupied_bytes
+        };
+
+        {
+            let db = CandyStore::open(&compressed_dir, compressed_config.clone())?;
+            for i in 0..3000 {
+                db.set(&format!("{prefix}key{i}"), "val")?;
+            }
+            db.set("unprefixed", "val")?;
+            db.set_in_list(&format!("{prefix}list"), "item", "val")?;
+            assert!(db.stats().num_splits > 0);
+            assert!(db.stats().occupied_bytes < occupied_plain / 2);
+        }
+
+        {
+            let db = CandyStore::open(&compressed_dir, compressed_config.clone())?;
+            assert_eq!(db.iter().count(), 3001);
+            assert_eq!(db.get(&format!("{prefix}key777"))?, Some("val".into()));
+            assert_eq!(db.get("unprefixed")?, Some("val".into()));
+            assert_eq!(
+                db.get_from_list(&format!("{prefix}list"), "item")?,
+                Some("val".into())
+            );
+            for res in db.iter() {
+                let (k, _) = res?;
+                assert!(k.starts_with(prefix.as_bytes()) || k == b"unprefixed");
+            }
+        }
+
+        // shards record the prefixes they were written with
+        assert!(matches!(
+            CandyStore::open(&compressed_dir, config.clone()),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        // existing (uncompressed) shards remain readable once prefixes are configured
+        let db = CandyStore::open(&plain_dir, compressed_config)?;
+        assert_eq!(db.get(&format!("{prefix}key777"))?, Some("val".into()));
+        assert_eq!(db.iter().count(), 3000);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_layout_settings() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let layouts = [
+            Config {
+                list_item_metadata: true,
+                ..Default::default()
+            },
+            Config {
+                list_item_tags: true,
+                ..Default::default()
+            },
+            Config {
+                strict_typed_values: true,
+                ..Default::default()
+            },
+            Config {
+                intern_keys_longer_than: Some(16),
+                ..Default::default()
+            },
+        ];
+        for (i, config) in layouts.into_iter().enumerate() {
+            let dir = format!("{dir}/{i}");
+            {
+                let db = CandyStore::open(&dir, config.clone())?;
+                db.set_in_list("list", "item", "val")?;
+                db.set("a-key-longer-than-16-bytes", "val")?;
+            }
+
+            // shards record the settings that change the format of their entries, so opening them with other
+            // settings fails rather than misreading them
+            assert!(
+                matches!(
+                    CandyStore::open(&dir, Config::default()),
+                    Err(CandyError::InvalidArgument(_))
+                ),
+                "{i}"
+            );
+
+            let db = CandyStore::open(&dir, config)?;
+            assert_eq!(db.get_from_list("list", "item")?, Some("val".into()));
+            assert_eq!(db.get("a-key-longer-than-16-bytes")?, Some("val".into()));
+        }
+
+        // a store that is still empty adopts the new settings
+        let dir = format!("{dir}/empty");
+        drop(CandyStore::open(&dir, Config::default())?);
+        let db = CandyStore::open(
+            &dir,
+            Config {
+                list_item_metadata: true,
+                ..Default::default()
+            },
+        )?;
+        db.set_in_list("list", "item", "val")?;
+        assert!(db.get_from_list_with_meta("list", "item")?.is_some());
+
+        Ok(())
+    })
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compression_dict() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let make_val = |i: usize| {
+            format!(
+                r#"{{"id": {i}, "kind": "user", "status": "active", "email": "user{i}@example.com", "tags": ["a", "b"]}}"#
+            )
+        };
+
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            for i in 0..2000 {
+                db.set(&format!("key{i}"), &make_val(i))?;
+            }
+            db.set("short", "tiny")?;
+            let data_before = db.stats().data_bytes();
+
+            assert!(db.train_compression_dict(1000, 4096)?);
+            assert!(!db.train_compression_dict(1000, 4096)?);
+
+            // rewrite the values so they get compressed
+            for i in 0..2000 {
+                db.set(&format!("key{i}"), &make_val(i + 1))?;
+                db.set(&format!("key{i}"), &make_val(i))?;
+            }
+            assert!(db.stats().data_bytes() < data_before / 2);
+            let cs = db.compression_stats();
+            assert!(cs.num_compressed >= 4000, "{cs:?}");
+            assert!(cs.ratio() < 0.5, "{cs:?}");
+            assert_eq!(cs.num_skipped, 0);
+
+            assert_eq!(db.get("key17")?, Some(make_val(17).into_bytes()));
+            assert!(db.compression_stats().num_decompressed > cs.num_decompressed);
+            assert_eq!(db.get("short")?, Some("tiny".into()));
+            assert_eq!(db.value_len("key17")?, Some(make_val(17).len()));
+            assert_eq!(
+                db.get_value_range("key17", 1..5)?,
+                Some(make_val(17).as_bytes()[1..5].to_vec())
+            );
+            assert!(db.patch("key17", 2, "ID", Some("id"))?.was_replaced());
+            assert_eq!(
+                db.get("key17")?,
+                Some(make_val(17).replacen("id", "ID", 1).into_bytes())
+            );
+        }
+
+        let db = CandyStore::open(
+            dir,
+            Config {
+                uncompressed_namespaces: vec![candystore::Namespace::User],
+                ..Default::default()
+            },
+        )?;
+        assert!(!db.train_compression_dict(1000, 4096)?);
+        assert_eq!(db.iter().count(), 2001);
+        assert_eq!(db.get("key1999")?, Some(make_val(1999).into_bytes()));
+
+        // values of the namespace are no longer compressed, but compressed ones remain readable
+        db.set("key1999", &make_val(2000))?;
+        assert_eq!(db.get("key1999")?, Some(make_val(2000).into_bytes()));
+        let cs = db.compression_stats();
+        assert_eq!((cs.num_skipped, cs.num_compressed), (1, 0));
+        assert!(cs.num_decompressed > 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_open_at() -> Result<()> {
+    run_in_tempdir(|dir| {
+        std::fs::create_dir_all(dir)?;
+        {
+            let db = CandyStore::open_at(std::fs::File::open(dir)?, Config::default())?;
+            db.set("hello", "world")?;
+            // the directory is registered under its real path
+            assert!(matches!(
+                CandyStore::open(dir, Config::default()),
+                Err(CandyError::Busy(_))
+            ));
+        }
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.get("hello")?, Some("world".into()));
+        drop(db);
+
+        let file = format!("{dir}/.lock");
+        assert!(matches!(
+            CandyStore::open_at(std::fs::File::open(&file)?, Config::default()),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        // symlinks planted in the directory are not followed
+        let outside = format!("{dir}-outside");
+        std::fs::write(&outside, "precious")?;
+        let sandbox = format!("{dir}/sandbox");
+        std::fs::create_dir_all(&sandbox)?;
+        std::os::unix::fs::symlink(&outside, format!("{sandbox}/shard_0000-10000"))?;
+        assert!(CandyStore::open_at(std::fs::File::open(&sandbox)?, Config::default()).is_err());
+        std::fs::remove_file(format!("{sandbox}/shard_0000-10000"))?;
+        std::fs::remove_file(format!("{sandbox}/.lock"))?;
+        std::os::unix::fs::symlink(&outside, format!("{sandbox}/.lock"))?;
+        assert!(matches!(
+            CandyStore::open_at(std::fs::File::open(&sandbox)?, Config::default()),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&outside)?, "precious");
+        std::fs::remove_file(&outside)?;
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_lazy_open() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            expected_number_of_keys: 200_000, // start with several shards
+            ..Default::default()
+        };
+        let lazy_config = Config {
+            lazy_open: true,
+            ..config.clone()
+        };
+
+        {
+            let db = CandyStore::open(dir, config.clone())?;
+            for i in 0..1000 {
+                db.set(&format!("key{i}"), &format!("val{i}"))?;
+            }
+        }
+        {
+            let db = CandyStore::open(dir, lazy_config.clone())?;
+            assert_eq!(db.get("key7")?, Some("val7".into()));
+            db.set("key1000", "val1000")?;
+            assert_eq!(db.iter().count(), 1001);
+        }
+
+        // a corrupt shard only fails the operations that reach it
+        let mut shard_files = std::fs::read_dir(dir)?
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("shard_"))
+            .collect::<Vec<_>>();
+        assert!(shard_files.len() > 1);
+        shard_files.sort();
+        std::fs::write(
+            format!("{dir}/{}", shard_files[0]),
+            "this is not a shard file",
+        )?;
+        assert!(matches!(
+            CandyStore::open(dir, config.clone()),
+            Err(CandyError::Corruption(_))
+        ));
+
+        let db = CandyStore::open(dir, lazy_config)?;
+        let (mut found, mut failed) = (0, 0);
+        for i in 0..1001 {
+            match db.get(&format!("key{i}")) {
+                Ok(val) => {
+                    assert_eq!(val, Some(format!("val{i}").into()));
+                    found += 1;
+                }
+                Err(CandyError::Corruption(_)) => failed += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        assert!(found > 0 && failed > 0, "found={found} failed={failed}");
+        assert!(db.iter().any(|res| res.is_err()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_preallocation() -> Result<()> {
+    run_in_tempdir(|dir| {
+        // the bytes allocated on disk for the shard files, regardless of their (sparse) sizes
+        let allocated_bytes = || -> Result<u64> {
+            let mut total = 0;
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with("shard_") {
+                    total += entry.metadata()?.blocks() * 512;
+                }
+            }
+            Ok(total)
+        };
+
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 4 * 1024 * 1024,
+                preallocate_size: 1024 * 1024,
+                preallocate_increment: 256 * 1024,
+                disk_full_policy: DiskFullPolicy::ReadOnly,
+                ..Default::default()
+            },
+        )?;
+        let initial = allocated_bytes()?;
+        assert!(initial >= 1024 * 1024, "{initial}");
+
+        // writing past the preallocated part allocates more in increments
+        let val = vec![7u8; 1000];
+        for i in 0..1500 {
+            db.set(&format!("key{i}"), &val)?;
+        }
+        let after_writes = allocated_bytes()?;
+        assert!(after_writes > initial, "{after_writes} {initial}");
+        assert!(after_writes < initial + 2 * 1024 * 1024, "{after_writes}");
+
+        db.reserve(2 * 1024 * 1024)?;
+        let after_reserve = allocated_bytes()?;
+        assert!(
+            after_reserve >= after_writes + 1024 * 1024,
+            "{after_reserve}"
+        );
+        // reserving is capped at the shards' maximal size
+        db.reserve(u64::MAX / 2)?;
+        assert!(allocated_bytes()? <= 5 * 1024 * 1024);
+
+        for i in 0..1500 {
+            assert_eq!(db.get(&format!("key{i}"))?, Some(val.clone()));
+        }
+        // the store only degrades once a write runs out of disk space
+        assert!(!db.is_degraded());
+        db.remove("key0")?;
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_update_config() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                dedup_min_value_size: 1024 * 1024,
+                ..Default::default()
+            },
+        )?;
+        let val = vec![5u8; 2000];
+
+        // too small to be deduplicated, so the value is inlined in a single entry
+        db.set_dedup("k1", &val)?;
+        assert_eq!(db.iter_raw().count(), 1);
+
+        // from now on, the value is stored as a blob, which the key points at
+        let warnings = db.update_config(ConfigUpdate {
+            dedup_min_value_size: Some(1000),
+            ..Default::default()
+        })?;
+        assert!(warnings.is_empty(), "{warnings:?}");
+        db.set_dedup("k2", &val)?;
+        assert!(db.iter_raw().count() > 2);
+        assert_eq!(db.get_dedup("k1")?, Some(val.clone()));
+        assert_eq!(db.get_dedup("k2")?, Some(val.clone()));
+
+        // invalid updates are rejected as a whole
+        assert!(matches!(
+            db.update_config(ConfigUpdate {
+                dedup_min_value_size: Some(10),
+                yield_every: Some(Some(0)),
+                ..Default::default()
+            }),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        let num_entries = db.iter_raw().count();
+        db.set_dedup("k3", &vec![6u8; 100])?;
+        assert_eq!(db.iter_raw().count(), num_entries + 1);
+        assert_eq!(db.get_dedup("k3")?, Some(vec![6u8; 100]));
+
+        // warnings are returned, but the update is applied
+        let warnings = db.update_config(ConfigUpdate {
+            preallocate_increment: Some(u64::MAX),
+            ..Default::default()
+        })?;
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_shard_checksums() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            shard_checksums: true,
+            ..Default::default()
+        };
+
+        {
+            let db = CandyStore::open(dir, config.clone())?;
+            // nothing to verify on the first open
+            let report = db.checksum_report().unwrap();
+            assert!(!report.manifest_found);
+            for i in 0..1000 {
+                db.set(&format!("key{i}"), LONG_VAL)?;
+            }
+        }
+
+        {
+            let db = CandyStore::open(dir, config.clone())?;
+            let report = db.checksum_report().unwrap();
+            assert!(report.manifest_found);
+            assert!(report.num_files_verified > 0);
+            assert!(report.num_bytes_verified > 0);
+            assert_eq!(db.get("key17")?, Some(LONG_VAL.into()));
+            db.set("key17", "changed")?;
+        }
+
+        // the checksums are not verified unless asked for
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.checksum_report(), None);
+        drop(db);
+        let db = CandyStore::open(dir, config.clone())?;
+        assert!(!db.checksum_report().unwrap().manifest_found);
+        drop(db);
+
+        // modify an entry's value in its shard file, behind the store's back
+        let (shard_file, mut data, offset) = std::fs::read_dir(dir)?
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .starts_with("shard_")
+            })
+            .find_map(|path| {
+                let data = std::fs::read(&path).unwrap();
+                let offset = data.windows(7).position(|w| w == b"changed")?;
+                Some((path, data, offset))
+            })
+            .unwrap();
+        data[offset] = b'C';
+        std::fs::write(&shard_file, &data)?;
+
+        assert!(matches!(
+            CandyStore::open(dir, config.clone()),
+            Err(CandyError::Corruption(_))
+        ));
+        // and it keeps failing, until the file is restored
+        assert!(matches!(
+            CandyStore::open(dir, config.clone()),
+            Err(CandyError::Corruption(_))
+        ));
+        data[offset] = b'c';
+        std::fs::write(&shard_file, &data)?;
+        let db = CandyStore::open(dir, config)?;
+        assert_eq!(db.get("key17")?, Some("changed".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_relocate() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let (src, dst) = (format!("{dir}/src"), format!("{dir}/moved/dst"));
+        let config = Config {
+            max_shard_size: 20 * 1024,
+            min_compaction_threashold: 10 * 1024,
+            ..Default::default()
+        };
+        let db = CandyStore::open(&src, config.clone())?;
+        for i in 0..500 {
+            db.set(&format!("key{i}"), LONG_VAL)?;
+        }
+        let db2 = db.clone();
+
+        db.relocate(&dst)?;
+        assert!(!std::path::Path::new(&src).exists());
+        assert_eq!(db.get_shards_directory(), std::path::Path::new(&dst));
+        assert!(matches!(
+            db.relocate(&dst),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        // the directory is registered under its new path
+        assert!(matches!(
+            CandyStore::open(&dst, config.clone()),
+            Err(CandyError::Busy(_))
+        ));
+
+        // writes keep going to the new directory, including the new shards of splits
+        for i in 500..1000 {
+            db2.set(&format!("key{i}"), LONG_VAL)?;
+        }
+        assert_eq!(db.get("key7")?, Some(LONG_VAL.into()));
+        drop(db);
+        drop(db2);
+
+        let db = CandyStore::open(&dst, config)?;
+        for i in 0..1000 {
+            assert_eq!(db.get(&format!("key{i}"))?, Some(LONG_VAL.into()), "{i}");
+        }
+        assert!(!std::path::Path::new(&src).exists());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_soft_limits() -> Result<()> {
+    struct Collector(Mutex<Vec<SoftLimitWarning>>);
+    impl SoftLimitObserver for Collector {
+        fn on_soft_limit(&self, warning: SoftLimitWarning) {
+            self.0.lock().unwrap().push(warning);
+        }
+    }
+
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 64 * 1024,
+                min_compaction_threashold: 16 * 1024,
+                soft_limits: SoftLimits {
+                    total_bytes: Some(20 * 1024),
+                    shard_fill_level: Some(0.5),
+                    list_span: Some(10),
+                },
+                ..Default::default()
+            },
+        )?;
+        let collector = Arc::new(Collector(Mutex::new(vec![])));
+
+        // nothing is checked without an observer
+        for i in 0..20 {
+            db.set_in_list("mylist", &format!("item{i}"), "val")?;
+        }
+        db.set_soft_limit_observer(Some(collector.clone()));
+
+        for i in 0..400 {
+            db.set(&format!("key{i}"), LONG_VAL)?;
+        }
+        std::thread::sleep(Duration::from_millis(1100));
+        db.set("last", "key")?;
+        for i in 0..20 {
+            db.set_in_list("otherlist", &format!("item{i}"), "val")?;
+        }
+
+        let warnings = std::mem::take(&mut *collector.0.lock().unwrap());
+        assert_eq!(
+            warnings
+                .iter()
+                .filter(|w| matches!(w, SoftLimitWarning::TotalBytes { .. }))
+                .count(),
+            1,
+            "{warnings:?}"
+        );
+        assert!(warnings.iter().any(
+            |w| matches!(w, SoftLimitWarning::ShardNearSplit { limit, .. } if *limit == 32 * 1024)
+        ));
+        let lists = warnings
+            .iter()
+            .filter_map(|w| match w {
+                SoftLimitWarning::ListSpan { list_key, span, .. } => {
+                    Some((list_key.clone(), *span))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lists, vec![(b"otherlist".to_vec(), 11)]);
+
+        assert!(matches!(
+            CandyStore::open(
+                dir,
+                Config {
+                    soft_limits: SoftLimits {
+                        shard_fill_level: Some(1.5),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            ),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        Ok(())
+    })
+}
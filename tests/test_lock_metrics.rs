@@ -0,0 +1,103 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use std::time::Duration;
+
+use candystore::{CandyStore, Config, ContendedLock, Result};
+
+use crate::common::run_in_tempdir;
+
+#[test]
+fn test_lock_metrics() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(db.top_contended_locks(10).is_empty());
+
+        // many threads hammering a single queue (and a single key) must wait for each other
+        let mut handles = vec![];
+        for _ in 0..8 {
+            let db = db.clone();
+            handles.push(std::thread::spawn(move || -> Result<()> {
+                for i in 0..2000 {
+                    db.push_to_queue_tail("hotq", &format!("item{i}"))?;
+                    db.set("hotkey", &format!("val{i}"))?;
+                }
+                Ok(())
+            }));
+        }
+        for h in handles {
+            h.join().unwrap()?;
+        }
+
+        let top = db.top_contended_locks(usize::MAX);
+        assert!(!top.is_empty());
+        for pair in top.windows(2) {
+            assert!(pair[0].total_wait >= pair[1].total_wait);
+        }
+        for c in top.iter() {
+            assert!(c.num_waits > 0);
+            assert!(c.max_wait <= c.total_wait);
+        }
+
+        let queue_lock = db.queue_lock_of("hotq");
+        assert!(matches!(queue_lock, ContendedLock::List(_)));
+        assert_ne!(queue_lock, db.list_lock_of("hotq"));
+        assert!(top.iter().any(|c| c.lock == queue_lock), "{top:?}");
+        assert!(matches!(
+            db.row_lock_of("hotkey")?,
+            ContendedLock::Row { .. }
+        ));
+        assert_eq!(db.top_contended_locks(1).len(), 1);
+
+        db.clear()?;
+        assert!(db.top_contended_locks(10).is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_queue_metrics() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let m = db.queue_metrics("q")?;
+        assert_eq!(m.len, 0);
+        assert_eq!(m.oldest_item_age, None);
+
+        db.extend_queue("q", (0..10).map(|i| format!("old{i}")))?;
+        std::thread::sleep(Duration::from_millis(1200));
+        for i in 0..5 {
+            db.push_to_queue_tail("q", &format!("new{i}"))?;
+        }
+
+        let m = db.queue_metrics("q")?;
+        assert_eq!(m.len, 15);
+        assert!(m.oldest_item_age.unwrap() >= Duration::from_millis(1200));
+        assert!(m.push_rate > 0.0);
+        assert_eq!(m.pop_rate, 0.0);
+
+        // once the old elements are consumed, the head is one of the new ones
+        assert_eq!(db.pop_queue_head_many("q", 10)?.len(), 10);
+        let m = db.queue_metrics("q")?;
+        assert_eq!(m.len, 5);
+        assert!(m.oldest_item_age.unwrap() < Duration::from_millis(1200));
+        assert!(m.pop_rate > 0.0);
+
+        let all = db.all_queue_metrics()?;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].0, b"q");
+
+        // elements pushed before the store was opened have no known age
+        drop(db);
+        let db = CandyStore::open(dir, Config::default())?;
+        let m = db.queue_metrics("q")?;
+        assert_eq!(m.len, 5);
+        assert_eq!(m.oldest_item_age, None);
+
+        db.discard_queue("q")?;
+        assert!(db.all_queue_metrics()?.is_empty());
+
+        Ok(())
+    })
+}
@@ -1,8 +1,13 @@
 mod common;
 
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
-use candystore::{CandyStore, Config, Result, MAX_VALUE_SIZE};
+use candystore::{
+    read_workload, replay_workload, write_workload, AsOf, CachedStore, CandyError, CandyStore,
+    Config, DiffEntry, DiffParams, DiffValue, ExportFilter, KeyPattern, KeyVersion, KvStore,
+    ListStore, MemoryStore, Namespace, Profile, RecordingStore, ReplaceStatus, ReplayParams,
+    Result, SstParams, StoreOp, WriteBatch, WritePolicy, MAX_VALUE_SIZE,
+};
 
 use crate::common::{run_in_tempdir, LONG_VAL};
 
@@ -71,6 +76,15 @@ fn test_logic() -> Result<()> {
         assert_eq!(stats3.num_entries(), 0);
         assert_eq!(stats3.num_compactions, 0);
         assert_eq!(stats3.num_splits, 0);
+        // the split shards are gone, only the initial shards are recreated
+        assert_eq!(stats3.num_shards, 1);
+        let num_shard_files = std::fs::read_dir(dir)?
+            .filter(|e| {
+                e.as_ref()
+                    .is_ok_and(|e| e.file_name().to_string_lossy().starts_with("shard_"))
+            })
+            .count();
+        assert_eq!(num_shard_files, 1);
 
         for i in 0..1000 {
             db.set(&format!("unique key {i}"), LONG_VAL)?;
@@ -147,3 +161,1758 @@ fn test_histogram() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_user_keys_with_namespace_suffixes() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.set_in_list("mylist", "item", "list value")?;
+        db.push_to_queue_tail("myqueue", "queue value")?;
+
+        // user keys that end with (or consist of) every possible namespace byte must not shadow or be shadowed
+        // by internal entries
+        for b in 0..=255u8 {
+            db.set(&[b], &[b])?;
+            db.set(&[b"mylist".as_slice(), &[b]].concat(), "user value")?;
+        }
+        for b in 0..=255u8 {
+            assert_eq!(db.get(&[b])?, Some(vec![b]));
+            assert_eq!(
+                db.get(&[b"mylist".as_slice(), &[b]].concat())?,
+                Some("user value".into())
+            );
+        }
+        assert_eq!(
+            db.get_from_list("mylist", "item")?,
+            Some("list value".into())
+        );
+        assert_eq!(db.pop_queue_head("myqueue")?, Some("queue value".into()));
+        assert_eq!(db.iter().count(), 512);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_value_range() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.set("blob", "0123456789")?;
+        assert_eq!(db.get_value_range("blob", 2..5)?, Some("234".into()));
+        assert_eq!(
+            db.get_value_range("blob", 0..10)?,
+            Some("0123456789".into())
+        );
+        assert_eq!(db.get_value_range("blob", 8..100)?, Some("89".into()));
+        assert_eq!(db.get_value_range("blob", 20..30)?, Some(vec![]));
+        assert_eq!(db.get_value_range("nope", 0..3)?, None);
+        assert_eq!(db.value_len("blob")?, Some(10));
+        assert_eq!(db.value_len("nope")?, None);
+
+        db.set("blob", "abc")?;
+        assert_eq!(db.get_value_range("blob", 1..5)?, Some("bc".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ephemeral() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+
+            {
+                let guard = db.ephemeral("presence", "alive")?;
+                assert_eq!(guard.key(), b"presence");
+                assert_eq!(db.get("presence")?, Some("alive".into()));
+                db.set("presence", "still alive")?;
+                assert_eq!(db.get("presence")?, Some("still alive".into()));
+            }
+            assert_eq!(db.get("presence")?, None);
+            assert_eq!(db.iter().count(), 0);
+
+            // simulate a process dying while holding the guard
+            std::mem::forget(db.ephemeral("heartbeat", "123")?);
+            db.set("permanent", "yes")?;
+            assert_eq!(db.get("heartbeat")?, Some("123".into()));
+        }
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.get("heartbeat")?, None);
+        assert_eq!(db.get("permanent")?, Some("yes".into()));
+        assert_eq!(db.iter().count(), 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_sessions() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let expirations = db.subscribe_expirations();
+        drop(db.subscribe_expirations());
+
+        db.register_session("worker1", Duration::from_secs(60))?;
+        db.register_session("worker2", Duration::from_millis(500))?;
+        db.register_session("worker3", Duration::from_millis(500))?;
+        assert_eq!(
+            db.list_live_sessions()?,
+            vec![b"worker1", b"worker2", b"worker3"]
+        );
+
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(db.heartbeat("worker2")?);
+        std::thread::sleep(Duration::from_millis(300));
+
+        assert!(!db.heartbeat("worker3")?);
+        assert!(!db.heartbeat("worker4")?);
+        assert_eq!(db.list_live_sessions()?, vec![b"worker1", b"worker2"]);
+        assert_eq!(expirations.try_iter().collect::<Vec<_>>(), vec![b"worker3"]);
+
+        // expired sessions have been collected
+        assert!(!db.unregister_session("worker3")?);
+        assert!(db.unregister_session("worker1")?);
+        assert_eq!(db.list_live_sessions()?, vec![b"worker2"]);
+
+        // the receiver can be waited on from another thread
+        let waiter = std::thread::spawn({
+            let expirations = expirations.clone();
+            move || expirations.recv_timeout(Duration::from_secs(10))
+        });
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(db.list_live_sessions()?.is_empty());
+        assert_eq!(waiter.join().unwrap().ok(), Some(b"worker2".to_vec()));
+        assert!(expirations.is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_dedup() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            dedup_min_value_size: 100,
+            ..Default::default()
+        };
+        let payload = "x".repeat(10_000);
+        let other_payload = "y".repeat(10_000);
+
+        let db = CandyStore::open(dir, config)?;
+        let data_bytes = db.stats().data_bytes();
+
+        for i in 0..100 {
+            assert!(db.set_dedup(&format!("doc{i}"), &payload)?.was_created());
+        }
+        db.set_dedup("small", "tiny")?;
+        // one copy of the payload and 100 small pointers
+        assert!(db.stats().data_bytes() - data_bytes < 2 * payload.len());
+
+        assert_eq!(db.get_dedup("doc7")?, Some(payload.clone().into_bytes()));
+        assert_eq!(db.get_dedup("small")?, Some("tiny".into()));
+        assert_eq!(db.get_dedup("doc100")?, None);
+        assert_eq!(db.get("doc7")?, None);
+
+        assert_eq!(
+            db.set_dedup("doc7", &other_payload)?,
+            candystore::SetStatus::PrevValue(payload.clone().into_bytes())
+        );
+        assert_eq!(
+            db.get_dedup("doc7")?,
+            Some(other_payload.clone().into_bytes())
+        );
+        assert_eq!(db.get_dedup("doc8")?, Some(payload.clone().into_bytes()));
+
+        assert_eq!(db.remove_dedup("doc7")?, Some(other_payload.into_bytes()));
+        assert_eq!(db.remove_dedup("doc7")?, None);
+        assert_eq!(db.remove_dedup("small")?, Some("tiny".into()));
+        for i in 0..100 {
+            if i != 7 {
+                assert_eq!(
+                    db.remove_dedup(&format!("doc{i}"))?,
+                    Some(payload.clone().into_bytes())
+                );
+            }
+        }
+
+        // all blobs have been released
+        assert_eq!(db.stats().data_bytes(), data_bytes);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_blobs() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let id1 = db.put_blob("hello")?;
+        let id2 = db.put_blob("world")?;
+        assert_ne!(id1, id2);
+        assert_eq!(db.put_blob("hello")?, id1);
+        assert_eq!(db.blob_refcount(&id1)?, Some(2));
+        assert_eq!(db.blob_refcount(&id2)?, Some(1));
+        assert_eq!(db.get_blob(&id1)?, Some("hello".into()));
+
+        assert_eq!(db.incref_blob(&id2)?, Some(2));
+        assert_eq!(db.decref_blob(&id2)?, Some(1));
+        assert_eq!(db.decref_blob(&id2)?, Some(0));
+        assert_eq!(db.get_blob(&id2)?, None);
+        assert_eq!(db.decref_blob(&id2)?, None);
+        assert_eq!(db.incref_blob(&id2)?, None);
+
+        // blob IDs can be stored and restored
+        let stored = *id1.as_bytes();
+        db.set("ref", &stored)?;
+        let restored = candystore::BlobId::from_bytes(db.get("ref")?.unwrap().try_into().unwrap());
+        assert_eq!(db.get_blob(&restored)?, Some("hello".into()));
+
+        // blobs are shared with dedup'ed keys
+        let payload = "z".repeat(5000);
+        db.set_dedup("doc", &payload)?;
+        let id3 = db.put_blob(&payload)?;
+        assert_eq!(db.blob_refcount(&id3)?, Some(2));
+        db.remove_dedup("doc")?;
+        assert_eq!(db.blob_refcount(&id3)?, Some(1));
+        assert_eq!(db.get_blob(&id3)?, Some(payload.into_bytes()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_config_validation() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let report = Config::default().validate();
+        assert!(report.is_ok());
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(report.num_keyed_locks, 64);
+        assert_eq!(report.max_value_size, MAX_VALUE_SIZE);
+
+        let report = Config {
+            max_concurrent_list_ops: 10,
+            max_shard_size: 20 * 1024,
+            min_compaction_threashold: 10 * 1024,
+            ..Default::default()
+        }
+        .validate();
+        assert!(report.is_ok());
+        assert_eq!(report.num_keyed_locks, 16);
+        assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+        assert!(report.max_value_size < MAX_VALUE_SIZE);
+
+        let bad_config = Config {
+            num_compaction_threads: 0,
+            background_split_threshold: Some(1.5),
+            ..Default::default()
+        };
+        let report = bad_config.validate();
+        assert!(!report.is_ok());
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert!(matches!(
+            CandyStore::open(dir, bad_config),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        let (db, report) = CandyStore::open_with_report(
+            dir,
+            Config {
+                max_concurrent_list_ops: 5,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(report.num_keyed_locks, 8);
+        assert_eq!(report.warnings.len(), 1);
+        db.set("hello", "world")?;
+        assert_eq!(db.get("hello")?, Some("world".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_config_presets() -> Result<()> {
+    run_in_tempdir(|dir| {
+        for (i, profile) in [
+            Profile::EmbeddedLowMemory,
+            Profile::ServerHighThroughput,
+            Profile::DurableQueue,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let config = Config::preset(profile);
+            let report = config.validate();
+            assert!(report.is_ok(), "{profile:?}: {:?}", report.errors);
+            assert!(
+                report.warnings.is_empty(),
+                "{profile:?}: {:?}",
+                report.warnings
+            );
+
+            let db = CandyStore::open(format!("{dir}/{i}"), config)?;
+            db.set("hello", "world")?;
+            db.push_to_queue_tail("queue", "item")?;
+            assert_eq!(db.get("hello")?, Some("world".into()));
+            assert_eq!(db.pop_queue_head("queue")?, Some("item".into()));
+        }
+
+        assert!(Config::preset(Profile::DurableQueue).shard_checksums);
+        assert_eq!(
+            Config::preset(Profile::EmbeddedLowMemory).num_compaction_threads,
+            1
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_sst_export_import() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(format!("{dir}/src"), Config::default())?;
+        for i in 0..3000u32 {
+            db.set(
+                &format!("key{i:05}"),
+                &format!("val{i}").repeat(i as usize % 7),
+            )?;
+        }
+        db.set_in_list("mylist", "a", "1")?;
+        db.set_in_list("mylist", "b", "2")?;
+
+        let sst_path = format!("{dir}/export.sst");
+        assert_eq!(db.export_sst(&sst_path, SstParams::default())?, 3000);
+
+        let db2 = CandyStore::open(format!("{dir}/dst"), Config::default())?;
+        assert_eq!(db2.import_sst(&sst_path, SstParams::default())?, 3000);
+        for i in 0..3000u32 {
+            assert_eq!(
+                db2.get(&format!("key{i:05}"))?,
+                Some(format!("val{i}").repeat(i as usize % 7).into_bytes())
+            );
+        }
+        assert_eq!(db2.list_len("mylist")?, 0);
+
+        // raw exports include the lists' internal entries
+        let raw_path = format!("{dir}/raw.sst");
+        let num_raw = db.export_sst(
+            &raw_path,
+            SstParams {
+                raw: true,
+                ..Default::default()
+            },
+        )?;
+        assert!(num_raw > 3000);
+        let db3 = CandyStore::open(format!("{dir}/dst3"), Config::default())?;
+        assert_eq!(
+            db3.import_sst(
+                &raw_path,
+                SstParams {
+                    raw: true,
+                    ..Default::default()
+                }
+            )?,
+            num_raw
+        );
+        assert_eq!(db3.get("key00003")?, db.get("key00003")?);
+        assert_eq!(db3.list_len("mylist")?, 2);
+        assert_eq!(db3.get_from_list("mylist", "b")?, Some("2".into()));
+
+        // corrupting a data block is detected
+        let mut bytes = std::fs::read(&sst_path)?;
+        bytes[20] ^= 0xff;
+        std::fs::write(&sst_path, bytes)?;
+        assert!(matches!(
+            db2.import_sst(&sst_path, SstParams::default()),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        // a footer whose index block lies past the end of the file is rejected before allocating it
+        let mut footer = vec![0, 0, 0];
+        footer.extend_from_slice(&[0xff; 9]);
+        footer.push(0x01);
+        footer.resize(40, 0);
+        footer.extend_from_slice(&0xdb4775248b80fb57u64.to_le_bytes());
+        std::fs::write(&sst_path, footer)?;
+        assert!(matches!(
+            db2.import_sst(&sst_path, SstParams::default()),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_signed_exports() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let signed = |key: &[u8]| Config {
+            export_signing_key: Some(key.to_vec()),
+            ..Default::default()
+        };
+        let src = CandyStore::open(format!("{dir}/src"), signed(b"secret"))?;
+        for i in 0..100u32 {
+            src.set(&format!("key{i}"), &format!("val{i}"))?;
+            src.set_in_list("jobs", &format!("job{i}"), &format!("payload{i}"))?;
+        }
+
+        // tables are signed in a manifest next to them
+        let sst_path = format!("{dir}/export.sst");
+        src.export_sst(&sst_path, SstParams::default())?;
+        assert!(std::path::Path::new(&format!("{sst_path}.sig")).exists());
+        let dst = CandyStore::open(format!("{dir}/dst"), signed(b"secret"))?;
+        assert_eq!(dst.import_sst(&sst_path, SstParams::default())?, 100);
+
+        let other_key = CandyStore::open(format!("{dir}/other"), signed(b"other"))?;
+        assert!(matches!(
+            other_key.import_sst(&sst_path, SstParams::default()),
+            Err(CandyError::BadSignature(_))
+        ));
+        assert_eq!(other_key.iter().count(), 0);
+
+        // a truncated (or tampered) table is rejected before anything is imported
+        let bytes = std::fs::read(&sst_path)?;
+        std::fs::write(&sst_path, &bytes[..bytes.len() - 1])?;
+        let dst2 = CandyStore::open(format!("{dir}/dst2"), signed(b"secret"))?;
+        assert!(matches!(
+            dst2.import_sst(&sst_path, SstParams::default()),
+            Err(CandyError::BadSignature(_))
+        ));
+        std::fs::remove_file(format!("{sst_path}.sig"))?;
+        std::fs::write(&sst_path, &bytes)?;
+        assert!(matches!(
+            dst2.import_sst(&sst_path, SstParams::default()),
+            Err(CandyError::BadSignature(_))
+        ));
+        assert_eq!(dst2.iter().count(), 0);
+
+        // list exports carry their signature
+        let mut buf = vec![];
+        src.export_list("jobs", &mut buf)?;
+        assert_eq!(dst2.import_list(&buf[..])?, (b"jobs".to_vec(), 100));
+
+        let mut tampered = buf.clone();
+        tampered[20] ^= 0xff;
+        let dst3 = CandyStore::open(format!("{dir}/dst3"), signed(b"secret"))?;
+        assert!(matches!(
+            dst3.import_list(&tampered[..]),
+            Err(CandyError::BadSignature(_))
+        ));
+        assert!(matches!(
+            dst3.import_list(&buf[..buf.len() - 1]),
+            Err(CandyError::BadSignature(_))
+        ));
+        assert_eq!(dst3.list_len("jobs")?, 0);
+
+        // unsigned exports are rejected, while stores without a key ignore the signature
+        let unsigned = CandyStore::open(format!("{dir}/unsigned"), Config::default())?;
+        assert_eq!(unsigned.import_list(&buf[..])?.1, 100);
+        let mut unsigned_buf = vec![];
+        unsigned.export_list("jobs", &mut unsigned_buf)?;
+        assert!(matches!(
+            dst3.import_list(&unsigned_buf[..]),
+            Err(CandyError::BadSignature(_))
+        ));
+
+        assert!(CandyStore::open(format!("{dir}/empty"), signed(b"")).is_err());
+
+        // the key never shows up when debug-printing the config
+        let debug = format!("{:?}", signed(b"secret"));
+        assert!(
+            debug.contains("export_signing_key: Some(\"<redacted>\")"),
+            "{debug}"
+        );
+        assert!(
+            !debug.contains(&format!("{:?}", b"secret".to_vec())),
+            "{debug}"
+        );
+
+        Ok(())
+    })
+}
+
+// application code written against the traits
+fn count_users(kv: &dyn KvStore, lists: &dyn ListStore) -> Result<usize> {
+    kv.set(b"visits", b"1")?;
+    lists.set_in_list(b"users", b"alice", b"admin")?;
+    lists.set_in_list(b"users", b"bob", b"user")?;
+    lists.remove_from_list(b"users", b"alice")?;
+    lists.list_len(b"users")
+}
+
+#[test]
+fn test_store_traits() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        assert_eq!(count_users(&db, &db)?, 1);
+        assert_eq!(db.get("visits")?, Some("1".into()));
+
+        let kv: &dyn KvStore = &db;
+        assert!(kv.contains(b"visits")?);
+        assert!(kv.set(b"visits", b"2")?.was_replaced());
+        assert_eq!(kv.iter().count(), 1);
+        assert_eq!(kv.remove(b"visits")?, Some("2".into()));
+        assert!(!kv.contains(b"visits")?);
+
+        let lists: &dyn ListStore = &db;
+        let items = lists.iter_list(b"users").collect::<Result<Vec<_>>>()?;
+        assert_eq!(items, [("bob".into(), "user".into())]);
+        assert_eq!(lists.get_from_list(b"users", b"bob")?, Some("user".into()));
+        assert!(lists.discard_list(b"users")?);
+        assert_eq!(lists.list_len(b"users")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_recording_store() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let rec = RecordingStore::new(MemoryStore::new());
+        assert_eq!(count_users(&rec, &rec)?, 1);
+        assert_eq!(
+            rec.ops(),
+            [
+                StoreOp::Set {
+                    key: "visits".into(),
+                    val: "1".into()
+                },
+                StoreOp::SetInList {
+                    list_key: "users".into(),
+                    item_key: "alice".into(),
+                    val: "admin".into()
+                },
+                StoreOp::SetInList {
+                    list_key: "users".into(),
+                    item_key: "bob".into(),
+                    val: "user".into()
+                },
+                StoreOp::RemoveFromList {
+                    list_key: "users".into(),
+                    item_key: "alice".into()
+                },
+                StoreOp::ListLen {
+                    list_key: "users".into()
+                },
+            ]
+        );
+        assert!(rec.recorded().iter().all(|r| !r.failed));
+
+        // replaying against a real store yields the same state
+        let db = CandyStore::open(dir, Config::default())?;
+        rec.replay(&db)?;
+        assert_eq!(db.get("visits")?, Some("1".into()));
+        assert_eq!(db.list_len("users")?, 1);
+        assert_eq!(db.get_from_list("users", "bob")?, Some("user".into()));
+
+        assert_eq!(rec.take_recorded().len(), 5);
+        assert!(rec.ops().is_empty());
+        assert_eq!(rec.inner().list_len(b"users")?, 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_cached_store() -> Result<()> {
+    let cached = CachedStore::new(
+        RecordingStore::new(MemoryStore::new()),
+        4,
+        WritePolicy::WriteThrough,
+    );
+    assert_eq!(cached.get(b"a")?, None);
+    assert!(cached.set(b"a", b"1")?.was_created());
+    assert_eq!(cached.get(b"a")?, Some("1".into()));
+    assert_eq!(
+        cached.set(b"a", b"2")?,
+        candystore::SetStatus::PrevValue("1".into())
+    );
+    assert_eq!(cached.get(b"a")?, Some("2".into()));
+    // the first get was a miss, the rest were served from memory
+    assert_eq!(
+        cached.inner().ops(),
+        [
+            StoreOp::Get { key: "a".into() },
+            StoreOp::Set {
+                key: "a".into(),
+                val: "1".into()
+            },
+            StoreOp::Set {
+                key: "a".into(),
+                val: "2".into()
+            },
+        ]
+    );
+
+    cached.set_in_list(b"l", b"x", b"1")?;
+    cached.set_in_list(b"l", b"y", b"2")?;
+    assert_eq!(cached.get_from_list(b"l", b"x")?, Some("1".into()));
+    assert!(cached.discard_list(b"l")?);
+    assert_eq!(cached.get_from_list(b"l", b"x")?, None);
+    assert_eq!(cached.remove(b"a")?, Some("2".into()));
+    assert_eq!(cached.get(b"a")?, None);
+
+    // the cache is bounded
+    for i in 0..10u32 {
+        cached.set(&i.to_le_bytes(), b"v")?;
+    }
+    assert_eq!(cached.len(), 4);
+    cached.inner().take_recorded();
+    assert_eq!(cached.get(&0u32.to_le_bytes())?, Some("v".into()));
+    assert_eq!(cached.get(&9u32.to_le_bytes())?, Some("v".into()));
+    assert_eq!(cached.inner().ops().len(), 1);
+
+    // write-behind
+    let write_behind = WritePolicy::WriteBehind {
+        max_pending: 4,
+        max_delay: Duration::from_secs(3600),
+    };
+    let cached = CachedStore::new(MemoryStore::new(), 4, write_behind);
+    assert!(cached.set(b"a", b"1")?.was_created());
+    cached.set_in_list(b"l", b"x", b"1")?;
+    cached.set_in_list(b"l", b"y", b"2")?;
+    assert_eq!(cached.get(b"a")?, Some("1".into()));
+    assert_eq!(cached.inner().get(b"a")?, None);
+    assert_eq!(cached.inner().list_len(b"l")?, 0);
+
+    cached.flush()?;
+    assert_eq!(cached.inner().get(b"a")?, Some("1".into()));
+    assert_eq!(
+        cached.inner().iter_list(b"l").collect::<Result<Vec<_>>>()?,
+        vec![("x".into(), "1".into()), ("y".into(), "2".into())]
+    );
+
+    assert_eq!(cached.remove(b"a")?, Some("1".into()));
+    assert_eq!(cached.inner().get(b"a")?, Some("1".into()));
+    // list ops see the pending modifications
+    cached.remove_from_list(b"l", b"x")?;
+    assert_eq!(cached.list_len(b"l")?, 1);
+    assert_eq!(cached.inner().get(b"a")?, None);
+
+    // enough pending modifications are flushed
+    for i in 0..4u32 {
+        cached.set(&i.to_le_bytes(), b"v")?;
+    }
+    assert_eq!(cached.inner().get(&3u32.to_le_bytes())?, Some("v".into()));
+
+    // pending modifications are flushed on drop
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let cached = CachedStore::new(db.handle(), 4, write_behind);
+        cached.set(b"b", b"1")?;
+        assert_eq!(db.get("b")?, None);
+        drop(cached);
+        assert_eq!(db.get("b")?, Some("1".into()));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_behind_coalescing() -> Result<()> {
+    let cached = CachedStore::new(
+        RecordingStore::new(MemoryStore::new()),
+        100,
+        WritePolicy::WriteBehind {
+            max_pending: 100,
+            max_delay: Duration::from_secs(3600),
+        },
+    );
+
+    for i in 0..10u32 {
+        cached.set(b"counter", &i.to_le_bytes())?;
+    }
+    cached.set_in_list(b"l", b"x", b"1")?;
+    cached.set_in_list(b"l", b"y", b"2")?;
+    cached.set_in_list(b"l", b"x", b"3")?;
+    cached.set_in_list(b"l", b"z", b"4")?;
+    cached.remove_from_list(b"l", b"z")?;
+    assert_eq!(cached.num_pending(), 4);
+
+    cached.inner().take_recorded();
+    cached.flush()?;
+    assert_eq!(cached.num_pending(), 0);
+    assert_eq!(
+        cached.inner().ops(),
+        [
+            StoreOp::Set {
+                key: "counter".into(),
+                val: 9u32.to_le_bytes().into()
+            },
+            StoreOp::SetInList {
+                list_key: "l".into(),
+                item_key: "x".into(),
+                val: "3".into()
+            },
+            StoreOp::SetInList {
+                list_key: "l".into(),
+                item_key: "y".into(),
+                val: "2".into()
+            },
+            StoreOp::RemoveFromList {
+                list_key: "l".into(),
+                item_key: "z".into()
+            },
+        ]
+    );
+
+    // an item that is removed and set again moves to the end of the list
+    cached.remove_from_list(b"l", b"x")?;
+    cached.set_in_list(b"l", b"w", b"5")?;
+    cached.set_in_list(b"l", b"x", b"6")?;
+    assert_eq!(
+        cached.iter_list(b"l").collect::<Result<Vec<_>>>()?,
+        vec![
+            ("y".into(), "2".into()),
+            ("w".into(), "5".into()),
+            ("x".into(), "6".into())
+        ]
+    );
+
+    // stale modifications are flushed by the flusher
+    let cached = CachedStore::new(
+        MemoryStore::new(),
+        100,
+        WritePolicy::WriteBehind {
+            max_pending: 100,
+            max_delay: Duration::from_millis(50),
+        },
+    );
+    let flusher = cached.spawn_flusher().unwrap();
+    cached.set(b"a", b"1")?;
+    assert_eq!(cached.inner().get(b"a")?, None);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(cached.num_pending(), 0);
+    assert_eq!(cached.inner().get(b"a")?, Some("1".into()));
+    drop(cached);
+    flusher.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_workload_replay() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let rec = RecordingStore::new(MemoryStore::new());
+        for i in 0..100u32 {
+            rec.set(format!("key{i}").as_bytes(), &i.to_le_bytes())?;
+            if i % 10 == 0 {
+                rec.set_in_list(b"tens", &i.to_le_bytes(), b"")?;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        rec.remove(b"key0")?;
+
+        let mut log = vec![];
+        write_workload(&rec.recorded(), &mut log)?;
+        let ops = read_workload(&log[..])?;
+        assert_eq!(ops.len(), 111);
+        assert!(ops[110].at >= Duration::from_millis(100));
+        assert!(matches!(
+            read_workload(&b"garbage!"[..]),
+            Err(CandyError::Corruption(_))
+        ));
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(matches!(
+            replay_workload(&ops, &db, &ReplayParams { speed: Some(0.0) }),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        // replay at twice the recorded speed: the 100ms pause should take roughly 50ms
+        let stats = replay_workload(&ops, &db, &ReplayParams { speed: Some(2.0) })?;
+        assert_eq!(stats.num_ops, 111);
+        assert_eq!(stats.num_failed, 0);
+        assert!(stats.elapsed >= Duration::from_millis(50));
+        assert_eq!(db.get("key0")?, None);
+        assert_eq!(db.get("key99")?, Some(99u32.to_le_bytes().into()));
+        assert_eq!(db.list_len("tens")?, 10);
+
+        // as fast as possible
+        db.clear()?;
+        let stats = replay_workload(&ops, &db, &ReplayParams::default())?;
+        assert_eq!(stats.num_ops, 111);
+        assert!(stats.elapsed < Duration::from_millis(100));
+        assert_eq!(db.get("key1")?, Some(1u32.to_le_bytes().into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_generation() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let g0 = db.generation();
+        db.get("key1")?;
+        assert_eq!(db.generation(), g0);
+
+        db.set("key1", "aaaa")?;
+        let g1 = db.generation();
+        assert!(g1 > g0);
+        assert!(db.remove("missing")?.is_none());
+        assert_eq!(db.generation(), g1);
+
+        // a patch that doesn't change anything is not a modification
+        db.patch("key1", 1, "aa", None)?;
+        assert_eq!(db.generation(), g1);
+        db.patch("key1", 1, "bb", None)?;
+        let g2 = db.generation();
+        assert!(g2 > g1);
+
+        db.set_in_list("mylist", "a", "1")?;
+        let g3 = db.generation();
+        assert!(g3 > g2);
+
+        let sst_path = format!("{dir}/export.sst");
+        db.export_sst(&sst_path, SstParams::default())?;
+        assert_eq!(CandyStore::sst_generation(&sst_path)?, Some(g3));
+
+        db.remove("key1")?;
+        let g4 = db.generation();
+        assert!(g4 > g3);
+        db.clear()?;
+        let g5 = db.generation();
+        assert!(g5 > g4);
+
+        drop(db);
+        let db = CandyStore::open(dir, Config::default())?;
+        let g6 = db.generation();
+        assert!(g6 > g5);
+
+        // a store whose generation ran ahead of the clock (e.g., the clock was set back) resumes from the bound
+        // it persisted
+        drop(db);
+        let ahead = g6 + 1_000_000_000_000;
+        std::fs::write(format!("{dir}/generation"), ahead.to_le_bytes())?;
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(db.generation() >= ahead);
+        db.set("key1", "aaaa")?;
+        let g7 = db.generation();
+        drop(db);
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(db.generation() > g7);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_diff() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db1 = CandyStore::open(format!("{dir}/db1"), Config::default())?;
+        let db2 = CandyStore::open(format!("{dir}/db2"), Config::default())?;
+        for i in 0..1000u32 {
+            db1.set(&format!("key{i}"), &format!("val{i}"))?;
+            db2.set(&format!("key{i}"), &format!("val{i}"))?;
+        }
+        assert_eq!(db1.diff(&db2, DiffParams::default()).count(), 0);
+
+        db1.set("key7", "changed")?;
+        db1.set("only1", "a")?;
+        db2.remove("key8")?;
+        db2.set("only2", "b")?;
+        db2.set_in_list("mylist", "x", "y")?;
+
+        let mut entries = db1
+            .diff(&db2, DiffParams::default())
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by(|a, b| a.key().cmp(b.key()));
+        assert_eq!(
+            entries,
+            vec![
+                DiffEntry::Different {
+                    key: "key7".into(),
+                    this: DiffValue::Bytes("changed".into()),
+                    other: DiffValue::Bytes("val7".into()),
+                },
+                DiffEntry::OnlyInThis {
+                    key: "key8".into(),
+                    val: DiffValue::Bytes("val8".into()),
+                },
+                DiffEntry::OnlyInThis {
+                    key: "only1".into(),
+                    val: DiffValue::Bytes("a".into()),
+                },
+                DiffEntry::OnlyInOther {
+                    key: "only2".into(),
+                    val: DiffValue::Bytes("b".into()),
+                },
+            ]
+        );
+
+        let params = DiffParams {
+            checksum_only: true,
+            ..Default::default()
+        };
+        for entry in db1.diff(&db2, params) {
+            if let DiffEntry::Different { key, this, other } = entry? {
+                assert_eq!(key, b"key7");
+                assert!(matches!(this, DiffValue::Checksum(_)));
+                assert_ne!(this, other);
+            }
+        }
+
+        // raw diffs include the lists' internal entries
+        let raw = DiffParams {
+            raw: true,
+            ..Default::default()
+        };
+        assert!(db1.diff(&db2, raw).count() > entries.len());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_get_checksum() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db1 = CandyStore::open(format!("{dir}/db1"), Config::default())?;
+        let db2 = CandyStore::open(
+            format!("{dir}/db2"),
+            Config {
+                hash_seed: *b"zzzzyyyyxxxxwwww",
+                ..Default::default()
+            },
+        )?;
+
+        let large = LONG_VAL.repeat(300);
+        assert!(large.len() > 10_000);
+        db1.set("small", "hello")?;
+        db1.set("large", &large)?;
+        db1.set("empty", "")?;
+        db2.set("small", "hello")?;
+        db2.set("large", &large)?;
+
+        assert_eq!(db1.get_checksum("missing")?, None);
+        assert!(db1.get_checksum("empty")?.is_some());
+        assert_eq!(db1.get_checksum("small")?, db2.get_checksum("small")?);
+        assert_eq!(db1.get_checksum("large")?, db2.get_checksum("large")?);
+        assert_ne!(db1.get_checksum("small")?, db1.get_checksum("large")?);
+
+        // same as the checksums reported by diff
+        let small_checksum = db1.get_checksum("small")?.unwrap();
+        db2.set("small", "world")?;
+        let params = DiffParams {
+            checksum_only: true,
+            ..Default::default()
+        };
+        let entries = db1.diff(&db2, params).collect::<Result<Vec<_>>>()?;
+        assert!(entries.contains(&DiffEntry::Different {
+            key: "small".into(),
+            this: DiffValue::Checksum(small_checksum),
+            other: DiffValue::Checksum(db2.get_checksum("small")?.unwrap()),
+        }));
+
+        // stable across reopens
+        let large_checksum = db1.get_checksum("large")?;
+        drop(db1);
+        let db1 = CandyStore::open(format!("{dir}/db1"), Config::default())?;
+        assert_eq!(db1.get_checksum("large")?, large_checksum);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_raw_entry() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let long_key = "k".repeat(400);
+
+        let entry = db.raw_entry(&long_key)?;
+        assert_eq!(entry.key(), long_key.as_bytes());
+        assert_eq!(entry.get()?, None);
+        assert!(!entry.contains()?);
+        assert!(entry.set("v1")?.was_created());
+        assert_eq!(db.get(&long_key)?, Some("v1".into()));
+
+        // modifications through the store are visible to the entry, and vice versa
+        db.set(&long_key, "v2")?;
+        assert_eq!(entry.get()?, Some("v2".into()));
+        assert!(matches!(
+            entry.replace("v3", Some("xx")),
+            Ok(ReplaceStatus::WrongValue(_))
+        ));
+        assert!(matches!(
+            entry.replace("v3", Some("v2")),
+            Ok(ReplaceStatus::PrevValue(_))
+        ));
+        assert_eq!(db.get(&long_key)?, Some("v3".into()));
+        assert_eq!(entry.remove()?, Some("v3".into()));
+        assert!(!db.contains(&long_key)?);
+        assert!(matches!(
+            entry.replace::<_, [u8]>("v4", None),
+            Ok(ReplaceStatus::DoesNotExist)
+        ));
+
+        assert!(matches!(
+            db.raw_entry("too_large")?
+                .set(&vec![0u8; MAX_VALUE_SIZE + 1]),
+            Err(CandyError::ValueTooLong(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_key_interning() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            intern_keys_longer_than: Some(32),
+            ..Default::default()
+        };
+        let db = CandyStore::open(dir, config.clone())?;
+        let long_keys = (0..50)
+            .map(|i| format!("{}/{i}", "x".repeat(200)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(db.get(&long_keys[0])?, None);
+        assert!(!db.contains(&long_keys[0])?);
+        assert_eq!(db.remove(&long_keys[0])?, None);
+        for _ in 0..3 {
+            for (i, k) in long_keys.iter().enumerate() {
+                db.set(k, &format!("val{i}"))?;
+            }
+        }
+        db.set("short", "s")?;
+
+        for (i, k) in long_keys.iter().enumerate() {
+            assert_eq!(db.get(k)?, Some(format!("val{i}").into()));
+            assert_eq!(db.value_len(k)?, Some(format!("val{i}").len()));
+        }
+        // the long keys are stored once, in the interning table
+        let interned = db.namespace_stats(Namespace::Interned, 1.0)?;
+        assert_eq!(interned.num_items, 50);
+        assert!(interned.num_bytes < 50 * 100);
+        assert_eq!(db.namespace_stats(Namespace::User, 1.0)?.num_items, 1);
+
+        let mut keys = db.iter_keys().collect::<Result<HashSet<_>>>()?;
+        assert!(keys.remove(b"short".as_slice()));
+        assert_eq!(
+            keys,
+            long_keys.iter().map(|k| k.as_bytes().to_vec()).collect()
+        );
+
+        assert_eq!(db.remove(&long_keys[0])?, Some("val0".into()));
+        assert!(!db.contains(&long_keys[0])?);
+        assert!(db.replace(&long_keys[1], "new", None)?.was_replaced());
+        assert!(db.raw_entry(&long_keys[0])?.set("again")?.was_created());
+
+        // the ids survive reopening, and clearing removes them
+        drop(db);
+        let db = CandyStore::open(dir, config)?;
+        assert_eq!(db.get(&long_keys[0])?, Some("again".into()));
+        assert_eq!(db.get(&long_keys[1])?, Some("new".into()));
+        assert_eq!(db.iter().count(), 51);
+        db.clear()?;
+        assert_eq!(db.get(&long_keys[1])?, None);
+        db.set(&long_keys[1], "after clear")?;
+        assert_eq!(db.get(&long_keys[1])?, Some("after clear".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_export_filter() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(format!("{dir}/src"), Config::default())?;
+        for i in 0..100u32 {
+            db.set(&format!("durable/{i}"), "v")?;
+            db.set(&format!("cache/{i}"), "v")?;
+            db.push_to_queue_tail("jobs", &format!("job{i}"))?;
+        }
+        db.set_in_list("tenant1", "a", "1")?;
+        db.set_in_list("tenant1", "b", "2")?;
+        db.set_in_list("scratch", "x", "3")?;
+
+        let export = |name: &str, raw: bool, filter: ExportFilter| {
+            let path = format!("{dir}/{name}.sst");
+            db.export_sst(
+                &path,
+                SstParams {
+                    raw,
+                    filter,
+                    ..Default::default()
+                },
+            )?;
+            let dst = CandyStore::open(format!("{dir}/{name}"), Config::default())?;
+            dst.import_sst(
+                &path,
+                SstParams {
+                    raw,
+                    ..Default::default()
+                },
+            )?;
+            Ok::<_, CandyError>(dst)
+        };
+
+        // plain exports filter by key prefix
+        let dst = export(
+            "prefix",
+            false,
+            ExportFilter {
+                exclude_key_prefixes: vec!["cache/".into()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(dst.iter().count(), 100);
+        assert!(dst.contains("durable/7")?);
+        assert!(!dst.contains("cache/7")?);
+
+        // skipping the queue and a list, but keeping the other list as a whole
+        let dst = export(
+            "lists",
+            true,
+            ExportFilter {
+                exclude_namespaces: vec![Namespace::Queue, Namespace::QueueItem],
+                exclude_lists: vec!["scratch".into()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(dst.queue_len("jobs")?, 0);
+        assert_eq!(dst.list_len("tenant1")?, 2);
+        assert_eq!(dst.get_from_list("tenant1", "b")?, Some("2".into()));
+        assert_eq!(dst.list_len("scratch")?, 0);
+        assert_eq!(dst.iter().count(), 200);
+
+        // prefixes apply to the lists' and queues' keys in raw exports
+        let dst = export(
+            "raw_prefix",
+            true,
+            ExportFilter {
+                include_key_prefixes: vec!["tenant".into(), "jobs".into()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(dst.iter().count(), 0);
+        assert_eq!(dst.list_len("tenant1")?, 2);
+        assert_eq!(dst.queue_len("jobs")?, 100);
+        assert_eq!(dst.list_len("scratch")?, 0);
+
+        let dst = export(
+            "include_lists",
+            true,
+            ExportFilter {
+                include_namespaces: vec![
+                    Namespace::List,
+                    Namespace::ListItem,
+                    Namespace::ListChain,
+                ],
+                include_lists: vec!["scratch".into()],
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(dst.list_len("tenant1")?, 0);
+        assert_eq!(
+            dst.iter_list("scratch").collect::<Result<Vec<_>>>()?,
+            vec![("x".into(), "3".into())]
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_amplification() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 20 * 1024,
+                min_compaction_threashold: 10 * 1024,
+                ..Default::default()
+            },
+        )?;
+        let before = db.write_amplification();
+        assert_eq!(before.ratio(), 0.0);
+
+        let mut logical = 0;
+        for i in 0..6000u32 {
+            let key = format!("key{}", i % 2000);
+            let val = format!("value{i}");
+            logical += key.len() + 1 + val.len();
+            db.set(&key, &val)?;
+        }
+        let after = db.write_amplification();
+        let window = after.since(&before);
+        assert_eq!(window.logical_bytes, logical as u64);
+        assert!(window.entry_bytes >= window.logical_bytes);
+        assert!(window.split_bytes > 0);
+        assert!(window.compaction_bytes > 0);
+        assert!(window.ratio() > 1.0);
+        assert!(window.window <= after.window);
+
+        // patches are counted as the patched bytes
+        db.patch("key1", 0, "VALUE", None)?;
+        let patched = db.write_amplification().since(&after);
+        assert_eq!(patched.logical_bytes, 5);
+        assert_eq!(patched.entry_bytes, 5);
+
+        db.clear()?;
+        assert_eq!(db.write_amplification().physical_bytes(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_update() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let incr = |v: Option<&[u8]>| {
+            let n = v.map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()));
+            Some((n + 1).to_le_bytes().to_vec())
+        };
+
+        // concurrent increments are never lost
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| -> Result<()> {
+                    for _ in 0..500 {
+                        db.update("counter", incr)?;
+                    }
+                    Ok(())
+                });
+            }
+        });
+        assert_eq!(db.get("counter")?, Some(4000u64.to_le_bytes().to_vec()));
+
+        // returning None removes the key, or leaves it missing
+        assert_eq!(db.update("counter", |_| None)?, None);
+        assert_eq!(db.get("counter")?, None);
+        assert_eq!(db.update("counter", |_| None)?, None);
+        assert_eq!(db.get("counter")?, None);
+        assert_eq!(
+            db.update("flag", |v| {
+                assert_eq!(v, None);
+                Some(b"on".to_vec())
+            })?,
+            Some(b"on".to_vec())
+        );
+
+        // a key that keeps changing under the update makes it give up
+        let mut attempts = 0;
+        let res = db.update("flag", |_| {
+            attempts += 1;
+            db.set("flag", &format!("changed{attempts}")).unwrap();
+            Some(b"off".to_vec())
+        });
+        assert!(matches!(res, Err(CandyError::TooManyRetries(_))));
+        assert_eq!(attempts, CandyStore::MAX_UPDATE_ATTEMPTS);
+        assert_ne!(db.get("flag")?, Some(b"off".to_vec()));
+
+        assert!(matches!(
+            db.update("flag", |_| Some(vec![0; MAX_VALUE_SIZE + 1])),
+            Err(CandyError::ValueTooLong(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_key_history() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let values = |versions: &[KeyVersion]| {
+            versions
+                .iter()
+                .map(|v| {
+                    v.value
+                        .as_deref()
+                        .map(|v| String::from_utf8(v.to_vec()).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.set("cfg", "v1")?;
+            db.set("other", "x")?;
+            db.enable_key_history("cfg", 4)?;
+
+            db.set("cfg", "v2")?;
+            db.set("cfg", "v2")?; // unchanged, not recorded
+            db.replace("cfg", "v3", Some("v2"))?;
+            db.replace("cfg", "nope", Some("v2"))?;
+            db.set("other", "y")?;
+
+            let history = db.get_history("cfg")?;
+            assert_eq!(
+                values(&history),
+                vec![Some("v1".into()), Some("v2".into()), Some("v3".into())]
+            );
+            assert_eq!(
+                history.iter().map(|v| v.version).collect::<Vec<_>>(),
+                vec![0, 1, 2]
+            );
+            assert!(db.get_history("other")?.is_empty());
+
+            db.remove("cfg")?;
+            db.get_or_create("cfg", "v4")?;
+            db.raw_entry("cfg")?.set("v5")?;
+            // only the last 4 versions are kept
+            let history = db.get_history("cfg")?;
+            assert_eq!(
+                values(&history),
+                vec![
+                    Some("v3".into()),
+                    None,
+                    Some("v4".into()),
+                    Some("v5".into())
+                ]
+            );
+            assert_eq!(history[0].version, 2);
+        }
+
+        let db = CandyStore::open(dir, Config::default())?;
+        // rollbacks restore old values (or removals), and are recorded themselves
+        assert!(db.rollback("cfg", 2)?);
+        assert_eq!(db.get("cfg")?, Some(b"v3".to_vec()));
+        assert!(db.rollback("cfg", 3)?);
+        assert_eq!(db.get("cfg")?, None);
+        assert!(!db.rollback("cfg", 1)?);
+        assert!(!db.rollback("other", 0)?);
+        let history = db.get_history("cfg")?;
+        assert_eq!(history.last().unwrap().version, 7);
+        assert_eq!(
+            values(&history),
+            vec![
+                Some("v4".into()),
+                Some("v5".into()),
+                Some("v3".into()),
+                None
+            ]
+        );
+
+        // reads as of a past version or time, within the kept versions
+        let as_of = |as_of| {
+            db.get_as_of("cfg", as_of)
+                .map(|kv| kv.map(|kv| (kv.version, kv.value)))
+        };
+        assert_eq!(as_of(AsOf::Version(5))?, Some((5, Some(b"v5".to_vec()))));
+        assert_eq!(as_of(AsOf::Version(100))?, Some((7, None)));
+        assert_eq!(as_of(AsOf::Version(3))?, None);
+        assert_eq!(as_of(AsOf::TimestampMs(0))?, None);
+        assert_eq!(as_of(AsOf::TimestampMs(u64::MAX))?, Some((7, None)));
+        let kv = db
+            .get_as_of("cfg", AsOf::TimestampMs(history[1].timestamp_ms))?
+            .unwrap();
+        assert!(kv.version >= 5 && kv.timestamp_ms == history[1].timestamp_ms);
+        assert_eq!(db.get_as_of("other", AsOf::TimestampMs(u64::MAX))?, None);
+
+        // shrinking the limit trims the history right away
+        db.enable_key_history("cfg", 2)?;
+        assert_eq!(
+            values(&db.get_history("cfg")?),
+            vec![Some("v3".into()), None]
+        );
+
+        assert!(db.disable_key_history("cfg")?);
+        assert!(!db.disable_key_history("cfg")?);
+        assert!(db.get_history("cfg")?.is_empty());
+        db.set("cfg", "v6")?;
+        assert!(db.get_history("cfg")?.is_empty());
+        assert!(db.namespace_stats(Namespace::KeyHistory, 1.0)?.num_items == 0);
+
+        assert!(matches!(
+            db.enable_key_history("cfg", 0),
+            Err(CandyError::InvalidArgument(_))
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ttl() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let hour = Duration::from_secs(3600);
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            assert_eq!(db.purge_expired()?, 0);
+
+            db.set_with_ttl("a", "1", Duration::ZERO)?;
+            db.set_with_ttl("b", "2", hour)?;
+            db.set_with_ttl("c", "3", Duration::ZERO)?;
+            assert!(db.persist("c")?);
+            assert!(!db.persist("c")?);
+            db.set("d", "4")?;
+            // a new TTL replaces the previous one
+            db.set_with_ttl("e", "5", Duration::ZERO)?;
+            db.set_with_ttl("e", "5", hour)?;
+
+            assert!(db.get_ttl("b")?.unwrap() > Duration::from_secs(3500));
+            assert_eq!(db.get_ttl("a")?, Some(Duration::ZERO));
+            assert_eq!(db.get_ttl("d")?, None);
+            // expired keys are gone for reads even before they are purged
+            assert_eq!(db.get("a")?, None);
+            assert!(!db.contains("a")?);
+            assert_eq!(db.iter_keys().count(), 5);
+
+            assert_eq!(db.purge_expired()?, 1);
+            assert_eq!(db.get("a")?, None);
+            assert_eq!(db.get_ttl("a")?, None);
+            for k in ["b", "c", "d", "e"] {
+                assert!(db.contains(k)?, "{k}");
+            }
+            assert_eq!(db.purge_expired()?, 0);
+
+            assert!(db.expire("d", Duration::ZERO)?);
+            assert!(!db.expire("nope", Duration::ZERO)?);
+            assert_eq!(db.purge_expired()?, 1);
+            assert_eq!(db.get("d")?, None);
+        }
+
+        // TTLs are persistent
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(db.get_ttl("b")?.unwrap() > Duration::from_secs(3500));
+        assert!(db.expire("b", Duration::ZERO)?);
+        assert_eq!(db.purge_expired()?, 1);
+        assert_eq!(db.get("b")?, None);
+        assert_eq!(db.get("e")?, Some(b"5".to_vec()));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ttl_dropped_on_overwrite() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let hour = Duration::from_secs(3600);
+        let db = CandyStore::open(dir, Config::default())?;
+        let expired = db.subscribe_expirations();
+
+        // a removed key that is set again does not inherit the old TTL
+        db.set_with_ttl("k", "v1", hour)?;
+        db.remove("k")?;
+        db.set("k", "v2")?;
+        assert_eq!(db.get_ttl("k")?, None);
+
+        // neither does an overwritten (or replaced) one
+        db.set_with_ttl("s", "v1", hour)?;
+        db.set("s", "v2")?;
+        db.set_with_ttl("r", "v1", hour)?;
+        db.replace("r", "v2", None)?;
+        db.set_with_ttl("u", "1", hour)?;
+        db.update("u", |_| Some(b"2".to_vec()))?;
+        for k in ["s", "r", "u"] {
+            assert_eq!(db.get_ttl(k)?, None, "{k}");
+        }
+
+        // patching keeps the TTL
+        db.set_with_ttl("p", "v1", hour)?;
+        db.patch("p", 1, "2", None)?;
+        assert!(db.get_ttl("p")?.unwrap() > Duration::from_secs(3500));
+        assert_eq!(db.get("p")?, Some(b"v2".to_vec()));
+        db.expire("p", Duration::ZERO)?;
+
+        assert_eq!(db.purge_expired()?, 1);
+        for k in ["k", "s", "r"] {
+            assert_eq!(db.get(k)?, Some(b"v2".to_vec()), "{k}");
+        }
+        assert_eq!(db.get("u")?, Some(b"2".to_vec()));
+        assert_eq!(db.get("p")?, None);
+
+        // reaped keys are reported to the expiration subscribers
+        assert_eq!(expired.try_recv().ok(), Some(b"p".to_vec()));
+        assert!(expired.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ttl_expired_before_purge() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let expired = db.subscribe_expirations();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+
+        // reads treat a key whose TTL has passed as gone, even before it's purged
+        db.set_with_ttl("k", "v", Duration::from_millis(10))?;
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(db.get("k")?, None);
+        assert_eq!(db.value_len("k")?, None);
+        assert_eq!(db.get_value_range("k", 0..1)?, None);
+        assert_eq!(db.get_checksum("k")?, None);
+        assert_eq!(db.get_with_deadline("k", deadline)?, None);
+        assert_eq!(db.raw_entry("k")?.get()?, None);
+        assert!(expired.is_empty());
+
+        // and so do the operations that act on its value, which purge it first
+        assert!(db.get_or_create("k", "new")?.was_created());
+        assert_eq!(db.get_ttl("k")?, None);
+        assert_eq!(db.get("k")?, Some("new".into()));
+
+        db.set_with_ttl("r", "v", Duration::ZERO)?;
+        assert_eq!(db.replace("r", "new", None)?, ReplaceStatus::DoesNotExist);
+        db.set_with_ttl("p", "v", Duration::ZERO)?;
+        assert_eq!(db.patch("p", 0, "x", None)?, ReplaceStatus::DoesNotExist);
+        db.set_with_ttl("u", "v", Duration::ZERO)?;
+        let mut seen = vec![];
+        db.update("u", |curr| {
+            seen.push(curr.map(|c| c.to_vec()));
+            Some(b"new".to_vec())
+        })?;
+        assert_eq!(seen, vec![None]);
+        db.set_with_ttl("d", "v", Duration::ZERO)?;
+        assert_eq!(db.remove("d")?, None);
+        db.set_with_ttl("e", "v", Duration::ZERO)?;
+        assert_eq!(db.raw_entry("e")?.remove()?, None);
+        // they are gone from the store, not just hidden
+        let mut keys = db.iter_keys().collect::<Result<Vec<_>>>()?;
+        keys.sort();
+        assert_eq!(keys, vec![b"k".to_vec(), b"u".to_vec()]);
+
+        // the purged keys are reported, and are not purged again
+        assert_eq!(
+            expired.try_iter().collect::<Vec<_>>(),
+            vec![
+                b"k".to_vec(),
+                b"r".to_vec(),
+                b"p".to_vec(),
+                b"u".to_vec(),
+                b"d".to_vec(),
+                b"e".to_vec()
+            ]
+        );
+        assert_eq!(db.purge_expired()?, 0);
+        assert_eq!(db.get("u")?, Some("new".into()));
+        assert_eq!(db.get_ttl("u")?, None);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_item_ttl() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let hour = Duration::from_secs(3600);
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.set_in_list_with_ttl("lru", "a", "1", Duration::ZERO)?;
+        db.set_in_list_with_ttl("lru", "b", "2", hour)?;
+        db.set_in_list_with_ttl("lru", "c", "3", Duration::ZERO)?;
+        assert!(db.persist_in_list("lru", "c")?);
+        assert!(!db.persist_in_list("lru", "c")?);
+        // the TTL belongs to the item, so it survives updates
+        db.set_in_list_promoting("lru", "a", "11")?;
+        // items are told apart from keys with the same name
+        db.set_with_ttl("a", "x", hour)?;
+        db.set_in_list("other", "a", "y")?;
+        assert_eq!(db.get_from_list("lru", "a")?, None);
+        assert_eq!(db.list_len("lru")?, 3);
+
+        assert_eq!(db.purge_expired()?, 1);
+        assert_eq!(db.get_from_list("lru", "a")?, None);
+        assert_eq!(db.list_len("lru")?, 2);
+        assert_eq!(db.get("a")?, Some(b"x".to_vec()));
+        assert_eq!(db.get_from_list("other", "a")?, Some(b"y".to_vec()));
+        assert_eq!(db.purge_expired()?, 0);
+
+        // removed items (and discarded lists) do not pass their TTLs on to items that are set again
+        db.set_in_list_with_ttl("lru", "d", "4", Duration::ZERO)?;
+        db.remove_from_list("lru", "d")?;
+        db.set_in_list("lru", "d", "44")?;
+        db.set_in_list_with_ttl("tmp", "e", "5", Duration::ZERO)?;
+        db.discard_list("tmp")?;
+        db.set_in_list("tmp", "e", "55")?;
+        assert_eq!(db.purge_expired()?, 0);
+        assert_eq!(db.get_from_list("lru", "d")?, Some(b"44".to_vec()));
+        assert_eq!(db.get_from_list("tmp", "e")?, Some(b"55".to_vec()));
+        drop(db);
+
+        // TTLs are enforced on reads after reopening as well, and purged in the background
+        let db = CandyStore::open(dir, Config::default())?;
+        db.set_in_list_with_ttl("lru", "f", "6", Duration::ZERO)?;
+        assert_eq!(db.get_from_list("lru", "f")?, None);
+        assert_eq!(db.get_from_list("lru", "b")?, Some(b"2".to_vec()));
+        assert_eq!(db.list_len("lru")?, 4);
+        let purger = db.spawn_expiry_purger(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(db.list_len("lru")?, 3);
+        // the purger does not keep the store open
+        drop(db);
+        purger.join().unwrap();
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.list_len("lru")?, 3);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_health() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 64 * 1024,
+                ..Default::default()
+            },
+        )?;
+        let health = db.health()?;
+        assert!(health.ok, "{health:?}");
+        assert!(health.degraded_reasons.is_empty());
+        assert_eq!(health.last_fsync, None);
+        assert!(health.disk_free_hint.is_some());
+
+        for i in 0..1000u32 {
+            db.set(&format!("key{i}"), LONG_VAL)?;
+        }
+        let before = std::time::SystemTime::now() - Duration::from_secs(1);
+        db.flush()?;
+        let health = db.health()?;
+        assert!(health.ok);
+        assert!(health.last_fsync.is_some_and(|t| t >= before));
+        assert!(health.pending_maintenance <= db.stats().num_shards);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_iter_matching() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        for i in 0..100 {
+            db.set(&format!("user:{i}:name"), &format!("name{i}"))?;
+            db.set(&format!("user:{i}:email"), &format!("email{i}"))?;
+            db.set(&format!("order:{i}"), "x")?;
+        }
+        db.set_in_list("user:1:name", "item", "not a user key")?;
+
+        let mut names = db
+            .iter_matching(&KeyPattern::glob("user:*:name"))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        assert_eq!(names.len(), 100);
+        assert!(names.contains(&(b"user:7:name".to_vec(), b"name7".to_vec())));
+
+        let matched = db.iter_matching(&KeyPattern::glob("user:[1-3]:*")).count();
+        assert_eq!(matched, 6);
+        assert_eq!(db.iter_matching(&KeyPattern::glob("order:?")).count(), 10);
+        assert_eq!(db.iter_matching(&KeyPattern::glob("nothing*")).count(), 0);
+
+        #[cfg(feature = "regex")]
+        {
+            let pattern = KeyPattern::regex(r"^user:\d+:email$")?;
+            assert_eq!(db.iter_matching(&pattern).count(), 100);
+            assert!(KeyPattern::regex("(").is_err());
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_batch() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        db.set("job1", "pending")?;
+        db.set_in_list("pending", "job1", "")?;
+
+        let mut batch = WriteBatch::new();
+        batch
+            .set("job1", "done")
+            .remove("stale")
+            .set_in_list("done", "job1", "");
+        assert_eq!(batch.len(), 3);
+        db.write_batch(&batch)?;
+        assert_eq!(db.get("job1")?, Some("done".into()));
+        assert_eq!(db.get_from_list("done", "job1")?, Some("".into()));
+
+        // a failing operation (an item too long for the list) undoes the ones applied before it
+        let mut batch = WriteBatch::new();
+        batch
+            .set("job2", "pending")
+            .set_in_list("pending", "job2", "")
+            .remove("job1")
+            .set_in_list("pending", "job3", &vec![7u8; 0x10000]);
+        assert!(db.write_batch(&batch).is_err());
+        assert_eq!(db.get("job2")?, None);
+        assert_eq!(db.get("job1")?, Some("done".into()));
+        assert_eq!(db.get_from_list("pending", "job2")?, None);
+        assert_eq!(db.list_len("pending")?, 1);
+        assert_eq!(
+            db.iter_list("pending").collect::<Result<Vec<_>>>()?,
+            vec![("job1".into(), "".into())]
+        );
+        assert_eq!(db.namespace_stats(Namespace::Journal, 1.0)?.num_items, 0);
+
+        // oversized values of plain sets are rejected before anything is written
+        let mut batch = WriteBatch::new();
+        batch
+            .set("job4", "x")
+            .set("job5", &vec![7u8; MAX_VALUE_SIZE + 1]);
+        assert!(matches!(
+            db.write_batch(&batch),
+            Err(CandyError::ValueTooLong(_))
+        ));
+        assert_eq!(db.get("job4")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_batch_crash() -> Result<()> {
+    const NUM_KEYS: usize = 50_000;
+
+    // the child process (this test, rerun with the store's directory) crashes while applying a batch, once the
+    // journal holds the records of hundreds of the keys it wrote
+    if let Ok(dir) = std::env::var("CANDY_BATCH_CRASH_DIR") {
+        let db = CandyStore::open(dir, Config::default())?;
+        let mut batch = WriteBatch::new();
+        for i in 0..NUM_KEYS {
+            batch.set(&format!("key{i}"), "new");
+        }
+        let db2 = db.clone();
+        std::thread::spawn(move || loop {
+            if db2
+                .namespace_stats(Namespace::Journal, 1.0)
+                .unwrap()
+                .num_items
+                > 1000
+            {
+                std::process::abort();
+            }
+        });
+        db.write_batch(&batch)?;
+        std::process::abort();
+    }
+
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            for i in 0..NUM_KEYS {
+                db.set(&format!("key{i}"), "old")?;
+            }
+        }
+
+        let status = std::process::Command::new(std::env::current_exe()?)
+            .args(["--exact", "test_write_batch_crash", "--test-threads=1"])
+            .env("CANDY_BATCH_CRASH_DIR", dir)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()?;
+        assert!(!status.success());
+
+        // the torn batch is rolled back when the store is opened (unless it was committed just before the
+        // crash, in which case it's kept as a whole)
+        let db = CandyStore::open(dir, Config::default())?;
+        let num_old = (0..NUM_KEYS)
+            .map(|i| db.get(&format!("key{i}")))
+            .filter(|res| matches!(res, Ok(Some(v)) if v == b"old"))
+            .count();
+        assert!(num_old == 0 || num_old == NUM_KEYS, "{num_old}");
+        assert_eq!(db.namespace_stats(Namespace::Journal, 1.0)?.num_items, 0);
+
+        Ok(())
+    })
+}
@@ -1,8 +1,14 @@
 mod common;
 
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use candystore::{CandyStore, Config, Result};
+use candystore::{CandyStore, Config, Result, WriteBatch};
 use rand::random;
 
 use crate::common::run_in_tempdir;
@@ -11,14 +17,14 @@ use crate::common::run_in_tempdir;
 fn test_multithreaded() -> Result<()> {
     run_in_tempdir(|dir| {
         for attempt in 0..10 {
-            let db = Arc::new(CandyStore::open(
+            let db = CandyStore::open(
                 dir,
                 Config {
                     max_shard_size: 20 * 1024,
                     min_compaction_threashold: 10 * 1024,
                     ..Default::default()
                 },
-            )?);
+            )?;
 
             const NUM_ITEMS: usize = 10_000;
             let succ_gets = Arc::new(AtomicUsize::new(0));
@@ -73,3 +79,149 @@ fn test_multithreaded() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_handles() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let mut handles = vec![];
+        for thd in 0..4 {
+            let db = db.handle();
+            handles.push(std::thread::spawn(move || {
+                for i in 0..100 {
+                    db.set(&format!("key{thd}-{i}"), &format!("val{thd}-{i}"))
+                        .unwrap();
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(db.iter().count(), 400);
+
+        // the store stays open (and locked) as long as any handle is alive
+        let db2 = db.clone();
+        drop(db);
+        assert_eq!(db2.get("key3-99")?, Some("val3-99".into()));
+        assert!(CandyStore::open(dir, Config::default()).is_err());
+        drop(db2);
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.iter().count(), 400);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_write_batch_isolation() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        const NUM_BATCHES: u64 = 2000;
+        let read =
+            |val: Option<Vec<u8>>| val.map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()));
+
+        let readers = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    loop {
+                        // every batch writes "a", the list item and "b" in this order, so a batch that is observed
+                        // through an earlier one is observed through the later ones as well
+                        let a = read(db.get("a")?);
+                        let item = read(db.get_from_list("l", "item")?);
+                        let b = read(db.get("b")?);
+                        assert!(a <= item && item <= b, "{a} {item} {b}");
+                        // the batches that fail are never observed
+                        assert!(b <= NUM_BATCHES, "{b}");
+                        if a == NUM_BATCHES {
+                            return Ok(());
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..=NUM_BATCHES {
+            let val = i.to_le_bytes();
+            let mut batch = WriteBatch::new();
+            batch
+                .set("a", &val)
+                .set_in_list("l", "item", &val)
+                .set("b", &val);
+            db.write_batch(&batch)?;
+
+            // fails on its last operation (an item too long for the list), after writing the keys
+            let mut batch = WriteBatch::new();
+            batch
+                .set("a", &u64::MAX.to_le_bytes())
+                .set("b", &u64::MAX.to_le_bytes())
+                .set_in_list("l", "too long", &vec![7u8; 0x10000]);
+            assert!(db.write_batch(&batch).is_err());
+        }
+        for thd in readers {
+            thd.join().unwrap()?;
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_purge_expired_while_setting() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let done = AtomicBool::new(false);
+        let num_purges = AtomicUsize::new(0);
+        let mut lost = vec![];
+
+        std::thread::scope(|s| -> Result<()> {
+            let purgers = (0..4)
+                .map(|_| {
+                    s.spawn(|| -> Result<()> {
+                        while !done.load(Ordering::SeqCst) {
+                            db.purge_expired()?;
+                            num_purges.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // keys that expired are set again while they are being purged: a key that was set again is never
+            // removed by the purge of its previous value
+            for round in 0u32..20 {
+                let fresh = format!("fresh{round}");
+                for i in 0u32..500 {
+                    db.set_with_ttl(&format!("key{i}"), "old", Duration::ZERO)?;
+                }
+                for i in 0u32..500 {
+                    db.set(&format!("key{i}"), &fresh)?;
+                }
+                // let the purges that began during the round end
+                let purges = num_purges.load(Ordering::SeqCst);
+                while num_purges.load(Ordering::SeqCst) < purges + 8 {
+                    std::thread::yield_now();
+                }
+                // checked once the purgers are stopped, so that failing does not leave them running
+                for i in 0u32..500 {
+                    let key = format!("key{i}");
+                    if db.get(&key)? != Some(fresh.clone().into()) || db.get_ttl(&key)?.is_some() {
+                        lost.push((round, i));
+                    }
+                }
+            }
+
+            done.store(true, Ordering::SeqCst);
+            for purger in purgers {
+                purger.join().unwrap()?;
+            }
+            Ok(())
+        })?;
+        assert!(lost.is_empty(), "{lost:?}");
+        assert_eq!(db.purge_expired()?, 0);
+
+        Ok(())
+    })
+}
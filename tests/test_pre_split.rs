@@ -1,6 +1,14 @@
 mod common;
 
-use candystore::{CandyError, CandyStore, Config, Result};
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use candystore::{
+    CandyError, CandyStore, Config, ListCompactionParams, MaintenanceObserver, Result,
+};
 
 use crate::common::run_in_tempdir;
 
@@ -114,10 +122,7 @@ fn test_too_large() -> Result<()> {
         )?;
 
         assert!(matches!(
-            db.set("yyy", &vec![7u8; 1000])
-                .unwrap_err()
-                .downcast::<CandyError>()
-                .unwrap(),
+            db.set("yyy", &vec![7u8; 1000]).unwrap_err(),
             CandyError::EntryCannotFitInShard(_, _)
         ));
 
@@ -179,3 +184,234 @@ fn test_compaction_stats() -> Result<()> {
         Ok(())
     })
 }
+
+#[derive(Default)]
+struct RecordingObserver {
+    events: Mutex<Vec<String>>,
+}
+
+impl MaintenanceObserver for RecordingObserver {
+    fn on_split_start(&self, span: Range<u32>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("split_start {span:?}"));
+    }
+    fn on_split_end(&self, span: Range<u32>, bytes_moved: u64, _duration: Duration) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("split_end {span:?} {bytes_moved}"));
+    }
+    fn on_compaction_start(&self, span: Range<u32>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("compaction_start {span:?}"));
+    }
+    fn on_compaction_end(&self, span: Range<u32>, prev_size: u64, new_size: u64, _: Duration) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("compaction_end {span:?} {prev_size} {new_size}"));
+    }
+    fn on_list_compaction(&self, list_key: &[u8], num_items: u64) {
+        self.events.lock().unwrap().push(format!(
+            "list_compaction {} {num_items}",
+            String::from_utf8_lossy(list_key)
+        ));
+    }
+}
+
+#[test]
+fn test_maintenance_observer() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 1000,
+                min_compaction_threashold: 900,
+                ..Default::default()
+            },
+        )?;
+        let observer = Arc::new(RecordingObserver::default());
+        db.set_maintenance_observer(Some(observer.clone()));
+
+        for i in 0..10 {
+            db.set("aaa", &format!("{i:0>96}"))?;
+        }
+        assert!(observer.events.lock().unwrap().is_empty());
+
+        // compaction (which runs in the background, stats() waits for it)
+        db.set("bbb", "x")?;
+        assert_eq!(db.stats().num_compactions, 1);
+        assert_eq!(
+            std::mem::take(&mut *observer.events.lock().unwrap()),
+            [
+                "compaction_start 0..65536".to_owned(),
+                "compaction_end 0..65536 1005 105".to_owned()
+            ]
+        );
+
+        // split
+        db.set("yyy", &vec![7u8; 700])?;
+        db.set("zzz", &vec![7u8; 700])?;
+        assert_eq!(
+            std::mem::take(&mut *observer.events.lock().unwrap()),
+            [
+                "split_start 0..65536".to_owned(),
+                "split_end 0..65536 809".to_owned()
+            ]
+        );
+
+        db.clear()?;
+        for i in 0u32..100 {
+            db.set_in_list("mylist", &i.to_le_bytes(), "val")?;
+        }
+        for i in 10u32..90 {
+            db.remove_from_list("mylist", &i.to_le_bytes())?;
+        }
+        assert!(db.compact_list_if_needed(
+            "mylist",
+            ListCompactionParams {
+                min_length: 10,
+                min_holes_ratio: 0.5
+            }
+        )?);
+        assert!(observer
+            .events
+            .lock()
+            .unwrap()
+            .contains(&"list_compaction mylist 20".to_owned()));
+
+        db.set_maintenance_observer(None);
+        db.clear()?;
+        Ok(())
+    })
+}
+
+// blocks splits until released, so that other threads can observe the store mid-split
+struct SlowSplitObserver {
+    started: Mutex<std::sync::mpsc::Sender<()>>,
+    release: Mutex<std::sync::mpsc::Receiver<()>>,
+}
+
+impl MaintenanceObserver for SlowSplitObserver {
+    fn on_split_start(&self, _span: Range<u32>) {
+        self.started.lock().unwrap().send(()).unwrap();
+        self.release.lock().unwrap().recv().unwrap();
+    }
+}
+
+#[test]
+fn test_get_with_deadline() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 1000,
+                min_compaction_threashold: 1000,
+                ..Default::default()
+            },
+        )?;
+
+        db.set("yyy", &vec![7u8; 700])?;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert_eq!(db.get_with_deadline("yyy", deadline)?, Some(vec![7u8; 700]));
+        assert_eq!(db.get_with_deadline("nope", deadline)?, None);
+
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel();
+        db.set_maintenance_observer(Some(Arc::new(SlowSplitObserver {
+            started: Mutex::new(started_tx),
+            release: Mutex::new(release_rx),
+        })));
+
+        let db2 = db.clone();
+        let handle = std::thread::spawn(move || db2.set("zzz", &vec![7u8; 700]));
+        started_rx.recv().unwrap();
+
+        // the shard is being split, so the deadline passes
+        let t0 = Instant::now();
+        assert!(matches!(
+            db.get_with_deadline("yyy", t0 + Duration::from_millis(50)),
+            Err(CandyError::DeadlineExceeded)
+        ));
+        assert!(t0.elapsed() < Duration::from_secs(5));
+
+        release_tx.send(()).unwrap();
+        handle.join().unwrap()?;
+        assert_eq!(
+            db.get_with_deadline("zzz", Instant::now() + Duration::from_secs(10))?,
+            Some(vec![7u8; 700])
+        );
+        assert_eq!(db.stats().num_splits, 1);
+
+        Ok(())
+    })
+}
+
+struct SplitThreadObserver {
+    split_threads: Mutex<Vec<std::thread::ThreadId>>,
+}
+
+impl MaintenanceObserver for SplitThreadObserver {
+    fn on_split_end(&self, _span: Range<u32>, _bytes_moved: u64, _duration: Duration) {
+        self.split_threads
+            .lock()
+            .unwrap()
+            .push(std::thread::current().id());
+    }
+}
+
+#[test]
+fn test_background_split() -> Result<()> {
+    run_in_tempdir(|dir| {
+        assert!(matches!(
+            CandyStore::open(
+                dir,
+                Config {
+                    background_split_threshold: Some(1.5),
+                    ..Default::default()
+                },
+            ),
+            Err(CandyError::InvalidArgument(_))
+        ));
+
+        let db = CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 1000,
+                min_compaction_threashold: 1000,
+                background_split_threshold: Some(0.5),
+                maintenance_thread_nice: Some(5),
+                ..Default::default()
+            },
+        )?;
+        let observer = Arc::new(SplitThreadObserver {
+            split_threads: Mutex::new(vec![]),
+        });
+        db.set_maintenance_observer(Some(observer.clone()));
+
+        db.set("aaa", &vec![7u8; 400])?;
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(db.stats().num_splits, 0);
+
+        // crossing the threshold schedules a split, without splitting inline
+        db.set("bbb", &vec![7u8; 200])?;
+        let t0 = Instant::now();
+        while db.stats().num_splits == 0 {
+            assert!(t0.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(db.stats().num_shards, 2);
+        let split_threads = observer.split_threads.lock().unwrap().clone();
+        assert_eq!(split_threads.len(), 1);
+        assert_ne!(split_threads[0], std::thread::current().id());
+
+        assert_eq!(db.get("aaa")?, Some(vec![7u8; 400]));
+        assert_eq!(db.get("bbb")?, Some(vec![7u8; 200]));
+
+        Ok(())
+    })
+}
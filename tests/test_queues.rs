@@ -1,6 +1,10 @@
 mod common;
 
-use candystore::{CandyStore, Config, Result};
+use std::{sync::Arc, time::Duration};
+
+use candystore::{
+    CandyShardedQueue, CandyStore, CandyTypedDeque, Config, Result, ShardingStrategy,
+};
 
 use crate::common::run_in_tempdir;
 
@@ -107,3 +111,210 @@ fn test_queues() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_queue_batches() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        db.extend_queue("work", (1u32..10).map(|i| i.to_le_bytes()))?;
+        db.remove_from_queue("work", db.queue_range("work")?.start + 1)?;
+
+        let items = db.pop_queue_head_many("work", 3)?;
+        assert_eq!(
+            items
+                .iter()
+                .map(|(_, v)| u32::from_le_bytes(v.clone().try_into().unwrap()))
+                .collect::<Vec<_>>(),
+            [1, 3, 4]
+        );
+        let items = db.pop_queue_tail_many("work", 2)?;
+        assert_eq!(
+            items
+                .iter()
+                .map(|(_, v)| u32::from_le_bytes(v.clone().try_into().unwrap()))
+                .collect::<Vec<_>>(),
+            [9, 8]
+        );
+        assert_eq!(db.queue_len("work")?, 3);
+        assert_eq!(db.pop_queue_head_many("work", 100)?.len(), 3);
+        assert_eq!(db.queue_len("work")?, 0);
+        assert!(db.pop_queue_head_many("work", 100)?.is_empty());
+
+        let typed = CandyTypedDeque::<String, u32>::new(db.clone());
+        let range = typed.push_many_tail("jobs", &[10u32, 20, 30, 40])?;
+        assert_eq!(range.len(), 4);
+        assert_eq!(typed.len("jobs")?, 4);
+
+        let items = typed.pop_many_head("jobs", 3)?;
+        assert_eq!(
+            items,
+            range
+                .clone()
+                .take(3)
+                .zip([10u32, 20, 30])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(typed.pop_many_tail("jobs", 3)?, [(range.end - 1, 40)]);
+        assert_eq!(typed.len("jobs")?, 0);
+
+        let range = typed.push_many_tail("jobs", &[1u32, 2, 3])?;
+        assert_eq!(typed.get_by_idx("jobs", range.start + 1)?, Some(2));
+        assert_eq!(typed.replace_by_idx("jobs", range.start + 1, &22)?, Some(2));
+        assert_eq!(typed.get_by_idx("jobs", range.start + 1)?, Some(22));
+        assert_eq!(typed.remove_by_idx("jobs", range.start)?, Some(1));
+        assert_eq!(typed.get_by_idx("jobs", range.start)?, None);
+        assert_eq!(typed.replace_by_idx("jobs", range.start, &11)?, None);
+        assert_eq!(typed.get_by_idx("jobs", range.start)?, None);
+        assert_eq!(typed.pop_many_head("jobs", 10)?.len(), 2);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pinned() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.pin_queue("work", Duration::from_secs(1000))?;
+            db.pin_list("mylist", Duration::from_secs(1000))?;
+
+            for i in 0u32..100 {
+                db.push_to_queue_tail("work", &i.to_le_bytes())?;
+                db.set_in_list("mylist", &i.to_le_bytes(), "xxx")?;
+            }
+            for _ in 0..10 {
+                db.pop_queue_head("work")?;
+                db.pop_list_head("mylist")?;
+            }
+            assert_eq!(db.queue_len("work")?, 90);
+            assert_eq!(db.list_len("mylist")?, 90);
+            assert_eq!(db.iter_queue("work").count(), 90);
+            assert_eq!(db.iter_list("mylist").count(), 90);
+
+            // a handle going away does not write back the headers, but the last one does
+            let db2 = db.clone();
+            drop(db2);
+            assert!(db.unpin_list("mylist")?);
+            assert!(!db.unpin_list("mylist")?);
+        }
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.queue_len("work")?, 90);
+        assert_eq!(db.list_len("mylist")?, 90);
+        assert_eq!(
+            db.peek_queue_head("work")?,
+            Some(10u32.to_le_bytes().to_vec())
+        );
+
+        // pinned queues that get emptied are removed on write-back
+        db.pin_queue("work", Duration::ZERO)?;
+        assert_eq!(db.pop_queue_head_many("work", 1000)?.len(), 90);
+        assert_eq!(db.queue_len("work")?, 0);
+        db.push_to_queue_head("work", "first")?;
+        assert_eq!(db.pop_queue_tail("work")?, Some("first".into()));
+        db.flush()?;
+        assert!(db.unpin_queue("work")?);
+        assert_eq!(db.queue_len("work")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pinned_concurrent_drop() -> Result<()> {
+    run_in_tempdir(|dir| {
+        for round in 1u32..=5 {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.pin_queue("work", Duration::from_secs(1000))?;
+            db.push_to_queue_tail("work", &round.to_le_bytes())?;
+
+            // whichever handle goes away last writes back the header, even if they all go away at once
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(4));
+            let handles = (0..4)
+                .map(|_| {
+                    let db = db.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        drop(db);
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(db);
+            for h in handles {
+                h.join().unwrap();
+            }
+
+            let db = CandyStore::open(dir, Config::default())?;
+            assert_eq!(db.queue_len("work")?, round as usize);
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_sharded_queue() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(CandyShardedQueue::new(db.clone(), "q", 0, ShardingStrategy::RoundRobin).is_err());
+
+        let queue = CandyShardedQueue::new(db.clone(), "q", 4, ShardingStrategy::RoundRobin)?;
+        assert_eq!(queue.pop()?, None);
+        for i in 0u32..8 {
+            queue.push(&i.to_le_bytes())?;
+        }
+        assert_eq!(queue.len()?, 8);
+        let mut popped = vec![];
+        while let Some(v) = queue.pop()? {
+            popped.push(u32::from_le_bytes(v.try_into().unwrap()));
+        }
+        popped.sort();
+        assert_eq!(popped, (0..8).collect::<Vec<_>>());
+        assert!(queue.is_empty()?);
+
+        // sub-queues of different sharded queues and plain queues do not collide
+        db.push_to_queue_tail("q", "plain")?;
+        let other = CandyShardedQueue::new(db.clone(), "q2", 4, ShardingStrategy::RoundRobin)?;
+        other.push("x")?;
+        assert_eq!(queue.pop()?, None);
+        assert_eq!(other.len()?, 1);
+        assert!(other.discard()?);
+        assert!(other.is_empty()?);
+
+        // concurrent producers, each keeping its order under ProducerThread
+        let queue = Arc::new(CandyShardedQueue::new(
+            db.clone(),
+            "hot",
+            4,
+            ShardingStrategy::ProducerThread,
+        )?);
+        let handles = (0u32..4)
+            .map(|t| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0u32..500 {
+                        queue.push(&[t.to_le_bytes(), i.to_le_bytes()].concat())?;
+                    }
+                    Result::<()>::Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap()?;
+        }
+        assert_eq!(queue.len()?, 2000);
+
+        let mut next_per_producer = [0u32; 4];
+        while let Some(v) = queue.pop()? {
+            let t = u32::from_le_bytes(v[..4].try_into().unwrap()) as usize;
+            let i = u32::from_le_bytes(v[4..].try_into().unwrap());
+            assert_eq!(i, next_per_producer[t]);
+            next_per_producer[t] += 1;
+        }
+        assert_eq!(next_per_producer, [500; 4]);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,236 @@
+#![cfg(feature = "redis_import")]
+
+mod common;
+
+use candystore::{CandyStore, Config, RedisImportParams, RedisImportStats, Result};
+
+use crate::common::run_in_tempdir;
+
+fn rdb_string(out: &mut Vec<u8>, s: &[u8]) {
+    assert!(s.len() < 64);
+    out.push(s.len() as u8);
+    out.extend_from_slice(s);
+}
+
+fn listpack(items: &[&[u8]]) -> Vec<u8> {
+    let mut lp = vec![0u8; 6];
+    for item in items {
+        match std::str::from_utf8(item)
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+        {
+            // 7 bit unsigned int
+            Some(n) if n < 128 => {
+                lp.push(n);
+                lp.push(1);
+            }
+            _ => {
+                lp.push(0x80 | item.len() as u8);
+                lp.extend_from_slice(item);
+                lp.push(1 + item.len() as u8);
+            }
+        }
+    }
+    lp.push(0xff);
+    let total_len = lp.len() as u32;
+    lp[0..4].copy_from_slice(&total_len.to_le_bytes());
+    lp[4..6].copy_from_slice(&(items.len() as u16).to_le_bytes());
+    lp
+}
+
+fn make_rdb() -> Vec<u8> {
+    let mut rdb = b"REDIS0011".to_vec();
+    // aux field
+    rdb.push(0xfa);
+    rdb_string(&mut rdb, b"redis-ver");
+    rdb_string(&mut rdb, b"7.2.0");
+    // select db 0, resize db
+    rdb.extend_from_slice(&[0xfe, 0, 0xfb, 8, 1]);
+
+    // plain string
+    rdb.push(0);
+    rdb_string(&mut rdb, b"greeting");
+    rdb_string(&mut rdb, b"hello");
+
+    // int-encoded string
+    rdb.push(0);
+    rdb_string(&mut rdb, b"counter");
+    rdb.extend_from_slice(&[0xc1, 0x39, 0x30]); // 12345 as int16
+
+    // LZF-compressed string: literal "abc", then a back reference copying 6 bytes from 3 bytes back
+    rdb.push(0);
+    rdb_string(&mut rdb, b"compressed");
+    rdb.extend_from_slice(&[0xc3, 6, 9, 2, b'a', b'b', b'c', 0x80, 2]);
+
+    // list
+    rdb.push(1);
+    rdb_string(&mut rdb, b"tasks");
+    rdb.push(3);
+    for item in [b"t1", b"t2", b"t3"] {
+        rdb_string(&mut rdb, item);
+    }
+
+    // set
+    rdb.push(2);
+    rdb_string(&mut rdb, b"colors");
+    rdb.push(2);
+    rdb_string(&mut rdb, b"red");
+    rdb_string(&mut rdb, b"blue");
+
+    // hash
+    rdb.push(4);
+    rdb_string(&mut rdb, b"user:1");
+    rdb.push(2);
+    rdb_string(&mut rdb, b"name");
+    rdb_string(&mut rdb, b"alice");
+    rdb_string(&mut rdb, b"age");
+    rdb_string(&mut rdb, b"30");
+
+    // intset of int16s
+    rdb.push(11);
+    rdb_string(&mut rdb, b"primes");
+    let mut intset = vec![];
+    intset.extend_from_slice(&2u32.to_le_bytes());
+    intset.extend_from_slice(&3u32.to_le_bytes());
+    for n in [2i16, 3, 5] {
+        intset.extend_from_slice(&n.to_le_bytes());
+    }
+    rdb_string(&mut rdb, &intset);
+
+    // listpack-encoded hash
+    rdb.push(16);
+    rdb_string(&mut rdb, b"user:2");
+    rdb_string(&mut rdb, &listpack(&[b"name", b"bob", b"age", b"41"]));
+
+    // quicklist with a single packed node
+    rdb.push(18);
+    rdb_string(&mut rdb, b"events");
+    rdb.extend_from_slice(&[1, 2]);
+    rdb_string(&mut rdb, &listpack(&[b"e1", b"7", b"e3"]));
+
+    // an expired string
+    rdb.push(0xfc);
+    rdb.extend_from_slice(&1000u64.to_le_bytes());
+    rdb.push(0);
+    rdb_string(&mut rdb, b"expired");
+    rdb_string(&mut rdb, b"old");
+
+    // a sorted set, which is skipped
+    rdb.push(5);
+    rdb_string(&mut rdb, b"leaderboard");
+    rdb.push(1);
+    rdb_string(&mut rdb, b"carol");
+    rdb.extend_from_slice(&1.5f64.to_le_bytes());
+
+    // a string in db 1
+    rdb.extend_from_slice(&[0xfe, 1]);
+    rdb.push(0);
+    rdb_string(&mut rdb, b"other-db");
+    rdb_string(&mut rdb, b"x");
+
+    rdb.push(0xff);
+    rdb.extend_from_slice(&[0u8; 8]);
+    rdb
+}
+
+#[test]
+fn test_redis_import() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let rdb = make_rdb();
+
+        let stats = db.import_redis_rdb(
+            &rdb[..],
+            RedisImportParams {
+                only_db: Some(0),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            stats,
+            RedisImportStats {
+                num_strings: 3,
+                num_hashes: 2,
+                num_lists: 2,
+                num_sets: 2,
+                num_skipped: 3,
+            }
+        );
+
+        assert_eq!(db.get("greeting")?, Some("hello".into()));
+        assert_eq!(db.get("counter")?, Some("12345".into()));
+        assert_eq!(db.get("compressed")?, Some("abcabcabc".into()));
+        assert_eq!(db.get("expired")?, None);
+        assert_eq!(db.get("other-db")?, None);
+
+        let tasks = db
+            .iter_queue("tasks")
+            .map(|res| res.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(tasks, [b"t1", b"t2", b"t3"]);
+        let events = db
+            .iter_queue("events")
+            .map(|res| res.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(events, [&b"e1"[..], b"7", b"e3"]);
+
+        assert_eq!(db.get_from_list("user:1", "name")?, Some("alice".into()));
+        assert_eq!(db.get_from_list("user:1", "age")?, Some("30".into()));
+        assert_eq!(db.get_from_list("user:2", "name")?, Some("bob".into()));
+        assert_eq!(db.get_from_list("user:2", "age")?, Some("41".into()));
+
+        assert_eq!(db.get_from_list("colors", "red")?, Some(vec![]));
+        assert_eq!(db.list_len("colors")?, 2);
+        assert_eq!(db.get_from_list("primes", "5")?, Some(vec![]));
+        assert_eq!(db.list_len("primes")?, 3);
+
+        // all databases, including expired keys
+        let stats = db.import_redis_rdb(
+            &rdb[..],
+            RedisImportParams {
+                only_db: None,
+                include_expired: true,
+            },
+        )?;
+        assert_eq!(stats.num_strings, 5);
+        assert_eq!(stats.num_skipped, 1);
+        assert_eq!(db.get("other-db")?, Some("x".into()));
+        assert_eq!(db.get("expired")?, Some("old".into()));
+
+        assert!(db
+            .import_redis_rdb(&b"NOTREDIS0"[..], RedisImportParams::default())
+            .is_err());
+        assert!(db
+            .import_redis_rdb(&rdb[..rdb.len() / 2], RedisImportParams::default())
+            .is_err());
+
+        // lengths taken from the file are checked before anything is allocated
+        let mut huge_hash = b"REDIS0011".to_vec();
+        huge_hash.push(4);
+        rdb_string(&mut huge_hash, b"k");
+        huge_hash.push(0x81);
+        huge_hash.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut huge_string = b"REDIS0011".to_vec();
+        huge_string.push(0);
+        rdb_string(&mut huge_string, b"k");
+        huge_string.push(0x81);
+        huge_string.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut huge_lzf = b"REDIS0011".to_vec();
+        huge_lzf.push(0);
+        rdb_string(&mut huge_lzf, b"k");
+        huge_lzf.extend_from_slice(&[0xc3, 0x01, 0x81]);
+        huge_lzf.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut truncated = b"REDIS0011".to_vec();
+        truncated.push(0);
+        rdb_string(&mut truncated, b"k");
+        truncated.extend_from_slice(&[0x80, 0, 0, 0x10, 0]);
+        truncated.extend_from_slice(b"short");
+        for rdb in [huge_hash, huge_string, huge_lzf, truncated] {
+            assert!(db
+                .import_redis_rdb(&rdb[..], RedisImportParams::default())
+                .is_err());
+        }
+
+        Ok(())
+    })
+}
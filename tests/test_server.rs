@@ -0,0 +1,336 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use candystore::{CandyStore, Config, HttpServerParams, RespServerParams, Result};
+
+use crate::common::run_in_tempdir;
+
+fn command(stream: &mut TcpStream, args: &[&str]) -> Result<()> {
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+    }
+    stream.write_all(buf.as_bytes())?;
+    Ok(())
+}
+
+fn reply(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if let Some(len) = line.strip_prefix('$') {
+        let len: i64 = len.trim_end().parse().unwrap();
+        if len >= 0 {
+            let mut data = vec![0u8; len as usize + 2];
+            reader.read_exact(&mut data)?;
+            line.push_str(&String::from_utf8(data).unwrap());
+        }
+    }
+    Ok(line)
+}
+
+#[test]
+fn test_resp_server() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server_db = db.clone();
+        std::thread::spawn(move || server_db.serve_resp(listener, RespServerParams::default()));
+
+        let mut stream = TcpStream::connect(addr)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut roundtrip = |args: &[&str]| -> Result<String> {
+            command(&mut stream, args)?;
+            reply(&mut reader)
+        };
+
+        assert_eq!(roundtrip(&["PING"])?, "+PONG\r\n");
+        assert_eq!(roundtrip(&["SET", "k1", "hello"])?, "+OK\r\n");
+        assert_eq!(roundtrip(&["GET", "k1"])?, "$5\r\nhello\r\n");
+        assert_eq!(roundtrip(&["get", "nope"])?, "$-1\r\n");
+        assert_eq!(roundtrip(&["DEL", "k1", "nope"])?, ":1\r\n");
+        assert_eq!(db.get("k1")?, None);
+
+        assert_eq!(roundtrip(&["RPUSH", "q", "a", "b"])?, ":2\r\n");
+        assert_eq!(roundtrip(&["LPUSH", "q", "z"])?, ":3\r\n");
+        assert_eq!(roundtrip(&["RPOP", "q"])?, "$1\r\nb\r\n");
+        assert_eq!(roundtrip(&["LPOP", "q"])?, "$1\r\nz\r\n");
+        assert_eq!(db.queue_len("q")?, 1);
+
+        assert_eq!(roundtrip(&["HSET", "h", "f1", "v1", "f2", "v2"])?, ":2\r\n");
+        assert_eq!(roundtrip(&["HSET", "h", "f1", "v3"])?, ":0\r\n");
+        assert_eq!(roundtrip(&["HGET", "h", "f1"])?, "$2\r\nv3\r\n");
+        assert_eq!(db.get_from_list("h", "f2")?, Some("v2".into()));
+
+        assert!(roundtrip(&["SET", "k1"])?.starts_with("-ERR wrong number"));
+        assert!(roundtrip(&["FLUSHALL"])?.starts_with("-ERR unknown command"));
+
+        // inline commands work too
+        stream.write_all(b"PING\r\n")?;
+        assert_eq!(reply(&mut reader)?, "+PONG\r\n");
+
+        command(&mut stream, &["QUIT"])?;
+        assert_eq!(reply(&mut reader)?, "+OK\r\n");
+        assert_eq!(reply(&mut reader)?, "");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_resp_server_limits() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let serve = |params: RespServerParams| -> Result<std::net::SocketAddr> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let addr = listener.local_addr()?;
+            let db = db.clone();
+            std::thread::spawn(move || db.serve_resp(listener, params));
+            Ok(addr)
+        };
+        let connect = |addr| -> Result<(TcpStream, BufReader<TcpStream>)> {
+            let stream = TcpStream::connect(addr)?;
+            let reader = BufReader::new(stream.try_clone()?);
+            Ok((stream, reader))
+        };
+
+        // lengths are checked before anything is allocated for them
+        let addr = serve(RespServerParams::default())?;
+        let (mut stream, mut reader) = connect(addr)?;
+        stream.write_all(b"*1\r\n$99999999999\r\n")?;
+        assert!(reply(&mut reader)?.starts_with("-ERR Protocol error: bulk string too long"));
+        assert_eq!(reply(&mut reader)?, "");
+
+        let (mut stream, mut reader) = connect(addr)?;
+        stream.write_all(b"*99999999999\r\n")?;
+        assert!(reply(&mut reader)?.starts_with("-ERR Protocol error: too many arguments"));
+
+        let (mut stream, mut reader) = connect(addr)?;
+        stream.write_all(&vec![b'a'; 128 * 1024 + 1])?;
+        assert!(reply(&mut reader)?.starts_with("-ERR Protocol error: line too long"));
+
+        // only one connection is served at a time
+        let addr = serve(RespServerParams {
+            max_connections: 1,
+            io_timeout: Some(Duration::from_millis(500)),
+        })?;
+        let (mut stream, mut reader) = connect(addr)?;
+        command(&mut stream, &["PING"])?;
+        assert_eq!(reply(&mut reader)?, "+PONG\r\n");
+        let (_, mut reader2) = connect(addr)?;
+        assert_eq!(
+            reply(&mut reader2)?,
+            "-ERR max number of clients reached\r\n"
+        );
+        assert_eq!(reply(&mut reader2)?, "");
+
+        // idle connections are closed, which frees their slot
+        assert_eq!(reply(&mut reader)?, "");
+        std::thread::sleep(Duration::from_millis(100));
+        let (mut stream, mut reader) = connect(addr)?;
+        command(&mut stream, &["PING"])?;
+        assert_eq!(reply(&mut reader)?, "+PONG\r\n");
+
+        Ok(())
+    })
+}
+
+fn http(addr: std::net::SocketAddr, req: &str) -> Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(req.as_bytes())?;
+    let mut resp = vec![];
+    stream.read_to_end(&mut resp)?;
+    let header_end = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let status_line = String::from_utf8(resp[..header_end].to_vec()).unwrap();
+    let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+    Ok((status, resp[header_end + 4..].to_vec()))
+}
+
+#[test]
+fn test_http_server() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server_db = db.clone();
+        std::thread::spawn(move || {
+            server_db.serve_http(
+                listener,
+                HttpServerParams {
+                    auth_tokens: vec!["s3cr3t".into()],
+                    ..Default::default()
+                },
+            )
+        });
+
+        let auth = "Authorization: Bearer s3cr3t\r\nConnection: close\r\n";
+
+        let (status, _) = http(addr, "GET /kv/k1 HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        assert_eq!(status, 401);
+        let (status, _) = http(
+            addr,
+            "GET /kv/k1 HTTP/1.1\r\nAuthorization: Bearer nope\r\nConnection: close\r\n\r\n",
+        )?;
+        assert_eq!(status, 401);
+
+        let (status, _) = http(addr, &format!("GET /kv/k1 HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!(status, 404);
+        let (status, _) = http(
+            addr,
+            &format!("PUT /kv/k1 HTTP/1.1\r\n{auth}Content-Length: 5\r\n\r\nhello"),
+        )?;
+        assert_eq!(status, 204);
+        assert_eq!(db.get("k1")?, Some("hello".into()));
+        let (status, body) = http(addr, &format!("GET /kv/k1 HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!((status, body), (200, b"hello".to_vec()));
+
+        // percent-encoded keys
+        db.set("a key/with slash", "v")?;
+        let (status, body) = http(
+            addr,
+            &format!("DELETE /kv/a%20key%2Fwith%20slash HTTP/1.1\r\n{auth}\r\n"),
+        )?;
+        assert_eq!((status, body), (200, b"v".to_vec()));
+        assert_eq!(db.get("a key/with slash")?, None);
+
+        let (status, _) = http(
+            addr,
+            &format!("PUT /lists/l/x HTTP/1.1\r\n{auth}Content-Length: 2\r\n\r\nxy"),
+        )?;
+        assert_eq!(status, 204);
+        db.set_in_list("l", "y", "zz")?;
+        let (status, body) = http(addr, &format!("GET /lists/l/x HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!((status, body), (200, b"xy".to_vec()));
+        let (status, body) = http(addr, &format!("GET /lists/l HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!(status, 200);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            r#"{"items":[{"key":"eA==","value":"eHk="},{"key":"eQ==","value":"eno="}],"next_cursor":null}"#
+        );
+        let (status, _) = http(addr, &format!("DELETE /lists/l HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!(status, 204);
+        assert_eq!(db.list_len("l")?, 0);
+
+        let (status, _) = http(addr, &format!("POST /kv/k1 HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!(status, 405);
+        let (status, _) = http(addr, &format!("GET /nothing HTTP/1.1\r\n{auth}\r\n"))?;
+        assert_eq!(status, 404);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_http_server_limits() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let serve = |params: HttpServerParams| -> Result<std::net::SocketAddr> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let addr = listener.local_addr()?;
+            let db = db.clone();
+            std::thread::spawn(move || db.serve_http(listener, params));
+            Ok(addr)
+        };
+
+        // the requests end right where the limits are exceeded, so the server reads all of them
+        let addr = serve(HttpServerParams::default())?;
+        let (status, body) = http(addr, &format!("GET /kv/{}", "a".repeat(128 * 1024 - 7)))?;
+        assert_eq!((status, body), (400, b"line too long".to_vec()));
+        let (status, body) = http(
+            addr,
+            &format!("GET /kv/k HTTP/1.1\r\n{}", "X-Header: x\r\n".repeat(65)),
+        )?;
+        assert_eq!((status, body), (400, b"too many headers".to_vec()));
+
+        // only one connection is served at a time
+        let addr = serve(HttpServerParams {
+            max_connections: 1,
+            io_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        })?;
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(b"PUT /kv/k HTTP/1.1\r\nContent-Length: 1\r\n\r\nv")?;
+        let mut buf = [0u8; 12];
+        stream.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"HTTP/1.1 204");
+        // the refused connection is answered right away, without reading a request
+        let mut resp = vec![];
+        TcpStream::connect(addr)?.read_to_end(&mut resp)?;
+        assert!(resp.starts_with(b"HTTP/1.1 503"));
+
+        // idle connections are closed, which frees their slot
+        stream.read_to_end(&mut vec![])?;
+        std::thread::sleep(Duration::from_millis(100));
+        let (status, body) = http(addr, "GET /kv/k HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        assert_eq!((status, body), (200, b"v".to_vec()));
+
+        // lists are returned in pages, which are followed by their cursors
+        for i in 0..5 {
+            db.set_in_list("l", &format!("k{i}"), "v")?;
+        }
+        let addr = serve(HttpServerParams {
+            max_list_page: 2,
+            ..Default::default()
+        })?;
+        let get_page = |query: &str| -> Result<(usize, String)> {
+            let (status, body) = http(
+                addr,
+                &format!("GET /lists/l{query} HTTP/1.1\r\nConnection: close\r\n\r\n"),
+            )?;
+            assert_eq!(status, 200);
+            let body = String::from_utf8(body).unwrap();
+            let next_cursor = body.rsplit_once("\"next_cursor\":").unwrap().1;
+            Ok((
+                body.matches("\"key\"").count(),
+                next_cursor.trim_end_matches('}').to_owned(),
+            ))
+        };
+        let mut pages = vec![];
+        let mut query = String::new();
+        loop {
+            let (num_items, next_cursor) = get_page(&query)?;
+            pages.push(num_items);
+            if next_cursor == "null" {
+                break;
+            }
+            query = format!("?cursor={next_cursor}");
+        }
+        assert_eq!(pages, [2, 2, 1]);
+        assert_eq!(get_page("?limit=1")?.0, 1);
+        assert_eq!(get_page("?limit=100")?.0, 2);
+        let (num_items, next_cursor) = get_page(&format!("?limit=1&{}", &query[1..]))?;
+        assert_eq!((num_items, next_cursor.as_str()), (1, "null"));
+        for query in ["?limit=0", "?limit=x", "?cursor=-1"] {
+            let (status, _) = http(
+                addr,
+                &format!("GET /lists/l{query} HTTP/1.1\r\nConnection: close\r\n\r\n"),
+            )?;
+            assert_eq!(status, 400, "{query}");
+        }
+        assert!(db
+            .serve_http(
+                TcpListener::bind("127.0.0.1:0")?,
+                HttpServerParams {
+                    max_list_page: 0,
+                    ..Default::default()
+                }
+            )
+            .is_err());
+
+        // the tokens never show up when debug-printing the params
+        let params = HttpServerParams {
+            auth_tokens: vec!["s3cr3t".into()],
+            ..Default::default()
+        };
+        assert!(!format!("{params:?}").contains("s3cr3t"));
+
+        Ok(())
+    })
+}
@@ -1,8 +1,9 @@
 mod common;
 
-use std::sync::Arc;
-
-use candystore::{CandyStore, CandyTypedKey, CandyTypedStore, Config, Result};
+use candystore::{
+    CandyError, CandyStore, CandyTypedDeque, CandyTypedKey, CandyTypedList, CandyTypedStore,
+    Config, Namespace, Result,
+};
 
 use crate::common::run_in_tempdir;
 
@@ -29,7 +30,7 @@ struct MyVal {
 #[test]
 fn test_typed() -> Result<()> {
     run_in_tempdir(|dir| {
-        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let db = CandyStore::open(dir, Config::default())?;
 
         let typed = CandyTypedStore::<MyKey, MyVal>::new(db.clone());
         typed.set(
@@ -111,3 +112,382 @@ fn test_typed() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_composite_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let orders = CandyTypedStore::<(String, u32), String>::new(db.clone());
+        let tagged = CandyTypedStore::<(String, u32, bool), u64>::new(db.clone());
+        let by_id = CandyTypedStore::<u32, String>::new(db.clone());
+
+        for i in 0..5 {
+            orders.set(&("alice".to_string(), i), &format!("alice order {i}"))?;
+        }
+        for i in 0..3 {
+            orders.set(&("al".to_string(), i), &format!("al order {i}"))?;
+            by_id.set(&i, &format!("id {i}"))?;
+        }
+        tagged.set(&("alice".to_string(), 7, true), &77)?;
+        tagged.set(&("alice".to_string(), 7, false), &78)?;
+        tagged.set(&("alice".to_string(), 8, true), &88)?;
+        db.set("alice", "plain")?;
+
+        // a tuple's type ID is derived from its elements
+        assert_ne!(<(String, u32)>::TYPE_ID, <(u32, String)>::TYPE_ID);
+
+        let mut alice = orders
+            .iter_with_prefix(&"alice".to_string())
+            .collect::<Result<Vec<_>>>()?;
+        alice.sort();
+        assert_eq!(alice.len(), 5);
+        for (i, ((name, id), val)) in alice.into_iter().enumerate() {
+            assert_eq!(name, "alice");
+            assert_eq!(id, i as u32);
+            assert_eq!(val, format!("alice order {i}"));
+        }
+
+        // "al" is not a prefix of "alice" in the encoded form
+        assert_eq!(orders.iter_with_prefix(&"al".to_string()).count(), 3);
+        assert_eq!(orders.iter_with_prefix(&"bob".to_string()).count(), 0);
+        assert_eq!(orders.iter().count(), 8);
+
+        assert_eq!(tagged.iter_with_prefix(&"alice".to_string()).count(), 3);
+        let mut alice7 = tagged
+            .iter_with_prefix(&("alice".to_string(), 7u32))
+            .map(|res| res.map(|(_, v)| v))
+            .collect::<Result<Vec<_>>>()?;
+        alice7.sort();
+        assert_eq!(alice7, [77, 78]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_strict_typed_values() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                strict_typed_values: true,
+                ..Default::default()
+            },
+        )?;
+
+        let names = CandyTypedStore::<u32, String>::new(db.clone());
+        let counts = CandyTypedStore::<u32, u64>::new(db.clone());
+
+        assert_eq!(names.set(&1, "hello")?, None);
+        assert_eq!(names.get(&1)?, Some("hello".to_owned()));
+        assert_eq!(names.set(&1, "world")?, Some("hello".to_owned()));
+        assert_eq!(
+            names.replace(&1, "again", Some("world"))?,
+            Some("world".to_owned())
+        );
+        assert_eq!(names.replace(&1, "nope", Some("world"))?, None);
+        assert_eq!(names.get_or_create(&2, "new")?, "new");
+        assert_eq!(names.iter().count(), 2);
+
+        // the same key type with a different value type is detected, rather than decoded as garbage
+        assert!(matches!(counts.get(&1), Err(CandyError::TypeMismatch(_))));
+        assert!(matches!(
+            counts.set(&2, &7),
+            Err(CandyError::TypeMismatch(_))
+        ));
+        assert_eq!(counts.get(&2)?, Some(7));
+        assert!(matches!(names.get(&2), Err(CandyError::TypeMismatch(_))));
+
+        assert_eq!(names.remove(&1)?, Some("again".to_owned()));
+        assert_eq!(names.get(&1)?, None);
+
+        assert!(!names.set_big(&3, "big")?);
+        assert_eq!(names.get_big(&3)?, Some("big".to_owned()));
+        assert!(matches!(
+            counts.get_big(&3),
+            Err(CandyError::TypeMismatch(_))
+        ));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_namespace_stats() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        for i in 0..1000u32 {
+            db.set(&format!("user{i}"), "0123456789")?;
+        }
+        for i in 0..50u32 {
+            db.set_in_list("xs", &format!("item{i}"), "v")?;
+        }
+        let typed1 = CandyTypedStore::<u32, String>::new(db.clone());
+        for i in 0..300u32 {
+            typed1.set(&i, &"hello".to_string())?;
+        }
+        let typed2 = CandyTypedStore::<u64, u64>::new(db.clone());
+        for i in 0..200u64 {
+            typed2.set(&i, &i)?;
+        }
+
+        let user = db.namespace_stats(Namespace::User, 1.0)?;
+        assert_eq!(user.num_items, 1000);
+        assert_eq!(user.sampled_fraction, 1.0);
+        // key + namespace byte, and the value
+        assert!(user.num_bytes >= 1000 * (5 + 1 + 10));
+
+        assert_eq!(db.namespace_stats(Namespace::List, 1.0)?.num_items, 1);
+        assert_eq!(db.namespace_stats(Namespace::ListItem, 1.0)?.num_items, 50);
+        assert_eq!(db.namespace_stats(Namespace::ListChain, 1.0)?.num_items, 50);
+        assert_eq!(db.namespace_stats(Namespace::Typed, 1.0)?.num_items, 500);
+        assert_eq!(db.namespace_stats(Namespace::Queue, 1.0)?.num_items, 0);
+
+        assert_eq!(db.typed_stats::<u32, String>(1.0)?.num_items, 300);
+        assert_eq!(db.typed_stats::<u64, u64>(1.0)?.num_items, 200);
+        assert_eq!(db.typed_stats::<u16, u16>(1.0)?.num_items, 0);
+
+        let all = db.all_namespace_stats(1.0)?;
+        assert_eq!(all.len(), Namespace::ALL.len());
+        assert_eq!(
+            all.iter().map(|(_, s)| s.num_items).sum::<usize>(),
+            db.stats().num_entries()
+        );
+
+        // sampling extrapolates from a fraction of the rows
+        let sampled = db.namespace_stats(Namespace::User, 0.5)?;
+        assert_eq!(sampled.sampled_fraction, 0.5);
+        assert!(
+            sampled.num_items > 700 && sampled.num_items < 1300,
+            "{sampled:?}"
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_drop_namespace() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+
+            for i in 0..1000u32 {
+                db.set(&format!("user{i}"), "0123456789")?;
+            }
+            for i in 0..50u32 {
+                db.set_in_list("xs", &format!("item{i}"), "v")?;
+            }
+            let typed = CandyTypedStore::<u32, String>::new(db.clone());
+            for i in 0..300u32 {
+                typed.set(&i, &"hello".to_string())?;
+            }
+
+            // the typed entries are mixed with others, so they are removed in place
+            let dropped = db.drop_namespace(Namespace::Typed)?;
+            assert_eq!(dropped.num_items, 300);
+            assert_eq!(dropped.num_shards_emptied, 0);
+            assert_eq!(typed.get(&7)?, None);
+            assert_eq!(db.namespace_stats(Namespace::Typed, 1.0)?.num_items, 0);
+            assert_eq!(db.get("user7")?, Some("0123456789".into()));
+            assert_eq!(db.iter_list("xs").count(), 50);
+
+            assert_eq!(db.drop_namespace(Namespace::Typed)?.num_items, 0);
+
+            // once only plain keys are left, every shard is replaced by an empty one
+            db.discard_list("xs")?;
+            let dropped = db.drop_namespace(Namespace::User)?;
+            assert_eq!(dropped.num_items, 1000);
+            assert_eq!(dropped.num_shards_emptied, db.stats().num_shards);
+            assert_eq!(db.get("user7")?, None);
+            assert_eq!(db.stats().num_entries(), 0);
+
+            db.set("user7", "again")?;
+        }
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.get("user7")?, Some("again".into()));
+        assert_eq!(db.iter().count(), 1);
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, Encode, Decode)]
+struct OtherKey(u32);
+
+impl CandyTypedKey for OtherKey {
+    // collides with MyKey on purpose
+    const TYPE_ID: u32 = 0x3476a551;
+}
+
+#[test]
+fn test_type_registry() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            assert_eq!(db.list_registered_types()?, vec![]);
+            assert!(db.register_type::<MyKey>()?);
+            assert!(!db.register_type::<MyKey>()?);
+            assert!(db.register_type::<u64>()?);
+        }
+
+        // the registry is persistent, so collisions are caught across restarts
+        let db = CandyStore::open(dir, Config::default())?;
+        assert!(matches!(
+            db.register_type::<OtherKey>(),
+            Err(CandyError::TypeMismatch(_))
+        ));
+
+        let types = db.list_registered_types()?;
+        assert_eq!(types.len(), 2);
+        assert_eq!(types[0].0, MyKey::TYPE_ID);
+        assert!(types[0].1.ends_with("MyKey"));
+        assert_eq!(types[1], (u64::TYPE_ID, "u64".to_string()));
+
+        assert_eq!(
+            db.unregister_type(MyKey::TYPE_ID)?.as_deref(),
+            Some(types[0].1.as_str())
+        );
+        assert!(db.register_type::<OtherKey>()?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_namespaces() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let users = CandyTypedStore::<String, u32>::new(db.clone());
+        let acme = users.in_namespace("acme");
+        let globex = users.in_namespace("globex");
+        // a namespace that is a suffix of another must not collide with it
+        let ex = users.in_namespace("ex");
+        assert_eq!(acme.namespace(), b"acme");
+        assert!(users.namespace().is_empty());
+
+        users.set("alice", &1)?;
+        acme.set("alice", &2)?;
+        globex.set("alice", &3)?;
+        globex.set("bob", &4)?;
+        ex.set("glob", &5)?;
+
+        assert_eq!(users.get("alice")?, Some(1));
+        assert_eq!(acme.get("alice")?, Some(2));
+        assert_eq!(globex.get("alice")?, Some(3));
+        assert_eq!(acme.get("bob")?, None);
+        assert_eq!(ex.get("bob")?, None);
+
+        let mut items = globex.iter().collect::<Result<Vec<_>>>()?;
+        items.sort();
+        assert_eq!(items, vec![("alice".into(), 3), ("bob".into(), 4)]);
+        assert_eq!(
+            users.iter().collect::<Result<Vec<_>>>()?,
+            vec![("alice".into(), 1)]
+        );
+        assert_eq!(acme.remove("alice")?, Some(2));
+        assert_eq!(users.get("alice")?, Some(1));
+        assert_eq!(
+            db.namespace_stats(Namespace::ScopedTyped, 1.0)?.num_items,
+            3
+        );
+
+        let lists = CandyTypedList::<String, u32, String>::new(db.clone());
+        lists.set("l", &1, "plain")?;
+        lists.in_namespace("acme").set("l", &1, "acme")?;
+        assert_eq!(lists.get("l", &1)?, Some("plain".into()));
+        assert_eq!(
+            lists.in_namespace("acme").get("l", &1)?,
+            Some("acme".into())
+        );
+        assert_eq!(lists.in_namespace("globex").len("l")?, 0);
+
+        let queues = CandyTypedDeque::<String, u32>::new(db.clone());
+        queues.push_tail("q", &1)?;
+        queues.in_namespace("acme").push_tail("q", &2)?;
+        assert_eq!(queues.in_namespace("acme").pop_head("q")?, Some(2));
+        assert_eq!(queues.in_namespace("acme").pop_head("q")?, None);
+        assert_eq!(queues.pop_head("q")?, Some(1));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_sort() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+        let scores = CandyTypedList::<String, String, u32>::new(db.clone());
+
+        for (name, score) in [
+            ("dave", 7),
+            ("alice", 3),
+            ("carol", 9),
+            ("bob", 3),
+            ("eve", 1),
+        ] {
+            scores.set("scores", name, &score)?;
+        }
+        scores.remove("scores", "eve")?;
+
+        // by value, stable for equal values
+        assert!(scores.sort_by("scores", |(_, a), (_, b)| a.cmp(b))?);
+        let sorted = scores.iter("scores").collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            sorted,
+            vec![
+                ("alice".to_string(), 3),
+                ("bob".to_string(), 3),
+                ("dave".to_string(), 7),
+                ("carol".to_string(), 9),
+            ]
+        );
+        assert_eq!(scores.len("scores")?, 4);
+        assert_eq!(scores.get("scores", "carol")?, Some(9));
+
+        // the sorted list keeps working as a list
+        scores.set("scores", "frank", &0)?;
+        assert_eq!(scores.pop_head("scores")?, Some(("alice".to_string(), 3)));
+        assert_eq!(scores.pop_tail("scores")?, Some(("frank".to_string(), 0)));
+
+        // by key, descending, through the untyped API
+        for name in ["b", "d", "a", "c"] {
+            db.set_in_list("names", name, "")?;
+        }
+        assert!(db.sort_list_by("names", |(a, _), (b, _)| b.cmp(a))?);
+        let keys = db
+            .iter_list("names")
+            .map(|res| res.map(|(k, _)| String::from_utf8(k).unwrap()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(keys, vec!["d", "c", "b", "a"]);
+
+        assert!(!scores.sort_by("nothing", |(_, a), (_, b)| a.cmp(b))?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_get_or_default() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(dir, Config::default())?;
+
+        let counts = CandyTypedStore::<String, u64>::new(db.clone());
+        assert_eq!(counts.get_or_default("a")?, 0);
+        assert_eq!(counts.get("a")?, Some(0));
+        counts.set("a", &5)?;
+        assert_eq!(counts.get_or_default("a")?, 5);
+
+        let tags = CandyTypedList::<String, u32, Vec<String>>::new(db.clone());
+        assert_eq!(tags.get_or_default("l", &1)?, Vec::<String>::new());
+        assert_eq!(tags.len("l")?, 1);
+        tags.set("l", &1, &vec!["x".to_owned()])?;
+        assert_eq!(tags.get_or_default("l", &1)?, vec!["x".to_owned()]);
+        assert_eq!(tags.len("l")?, 1);
+
+        Ok(())
+    })
+}